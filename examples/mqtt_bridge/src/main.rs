@@ -0,0 +1,283 @@
+//! Bridges LIFX devices on the local network to an MQTT broker
+//!
+//! For every device that is discovered, this publishes its state (as produced by
+//! `DeviceState::to_json`) as a retained message on `lifx/<serial>/state`, and listens for
+//! commands on:
+//!
+//! * `lifx/<serial>/set/power`: `on` or `off`
+//! * `lifx/<serial>/set/color`: a color string like `red`, `#ff8800`, or `3500K 50%`
+//!
+//! The broker is configured with the `MQTT_HOST` and `MQTT_PORT` environment variables (defaults
+//! to localhost:1883).  Set `LIFX_BIND_ADDR` to one of this host's IP addresses to only talk to
+//! LIFX devices through that interface, which is useful on hosts (and in containers) with more than
+//! one network.
+
+use lifx::{Client, ClientOptions, DeviceState, DiscoveredDevice, ReliableSender};
+use lifx_core::{Message, PowerLevel, HSBK};
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const PREFIX: &str = "lifx";
+
+/// LIFX recommends sending no more than 20 messages per second to a single device
+const MIN_COMMAND_INTERVAL: Duration = Duration::from_millis(50);
+
+/// How many times to try sending a command before giving up
+const COMMAND_ATTEMPTS: usize = 3;
+
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(30);
+const REFRESH_INTERVAL: Duration = Duration::from_secs(5);
+
+struct Device {
+    info: DiscoveredDevice,
+    state: DeviceState,
+    /// The last published state, so we only publish when something changed
+    published: Option<String>,
+    /// Held while sending a command, to rate limit commands to this device
+    last_command: Arc<tokio::sync::Mutex<Instant>>,
+}
+
+type Devices = Arc<Mutex<HashMap<String, Device>>>;
+
+fn device_info(devices: &Devices, serial: &str) -> Option<(DiscoveredDevice, DeviceState)> {
+    let devices = devices.lock().unwrap();
+    devices.get(serial).map(|d| (d.info, d.state.clone()))
+}
+
+/// Queries a device's state, and publishes it if it has changed
+async fn refresh(client: &Client, mqtt: &AsyncClient, devices: &Devices, serial: &str) {
+    let (info, state) = match device_info(devices, serial) {
+        Some(d) => d,
+        None => return,
+    };
+
+    let mut queries = vec![Message::LightGet];
+    if state.version.is_none() {
+        queries.push(Message::GetVersion);
+    }
+    if state.firmware.is_none() {
+        queries.push(Message::GetHostFirmware);
+    }
+    if state.group.is_none() {
+        queries.push(Message::GetGroup);
+    }
+    if state.location.is_none() {
+        queries.push(Message::GetLocation);
+    }
+    let mut replies = Vec::new();
+    for query in queries {
+        match client.request(info.addr, info.target, query).await {
+            Ok(reply) => replies.push(reply),
+            Err(e) => eprintln!("Failed to refresh {}: {}", serial, e),
+        }
+    }
+    if state.product_info().is_some_and(|p| p.multizone()) {
+        if let Ok(reply) = client
+            .request(info.addr, info.target, Message::GetExtendedColorZone)
+            .await
+        {
+            replies.push(reply);
+        }
+    }
+
+    let json = {
+        let mut devices = devices.lock().unwrap();
+        let device = match devices.get_mut(serial) {
+            Some(d) => d,
+            None => return,
+        };
+        for reply in &replies {
+            device.state.update(reply);
+        }
+        let json = device.state.to_json().to_string();
+        if device.published.as_ref() == Some(&json) {
+            return;
+        }
+        device.published = Some(json.clone());
+        json
+    };
+
+    let topic = format!("{}/{}/state", PREFIX, serial);
+    if let Err(e) = mqtt.publish(topic, QoS::AtLeastOnce, true, json).await {
+        eprintln!("Failed to publish state for {}: {}", serial, e);
+    }
+}
+
+/// Periodically discovers new devices, and subscribes to their command topics
+async fn discovery_task(client: Client, mqtt: AsyncClient, devices: Devices) {
+    loop {
+        match client.discover(Duration::from_secs(2)).await {
+            Ok(found) => {
+                for info in found {
+                    let state = DeviceState::new(info.target);
+                    let serial = state.serial();
+                    let is_new = {
+                        let mut devices = devices.lock().unwrap();
+                        match devices.get_mut(&serial) {
+                            Some(device) => {
+                                // the device may have moved to a new address
+                                device.info = info;
+                                false
+                            }
+                            None => {
+                                devices.insert(
+                                    serial.clone(),
+                                    Device {
+                                        info,
+                                        state,
+                                        published: None,
+                                        last_command: Arc::new(tokio::sync::Mutex::new(
+                                            Instant::now(),
+                                        )),
+                                    },
+                                );
+                                true
+                            }
+                        }
+                    };
+                    if is_new {
+                        println!("Found {} at {}", serial, info.addr);
+                        let topic = format!("{}/{}/set/#", PREFIX, serial);
+                        if let Err(e) = mqtt.subscribe(topic, QoS::AtLeastOnce).await {
+                            eprintln!("Failed to subscribe: {}", e);
+                        }
+                    }
+                }
+            }
+            Err(e) => eprintln!("Discovery failed: {}", e),
+        }
+        tokio::time::sleep(DISCOVERY_INTERVAL).await;
+    }
+}
+
+/// Periodically refreshes the state of every known device
+async fn refresh_task(client: Client, mqtt: AsyncClient, devices: Devices) {
+    loop {
+        let serials: Vec<String> = devices.lock().unwrap().keys().cloned().collect();
+        for serial in serials {
+            refresh(&client, &mqtt, &devices, &serial).await;
+        }
+        tokio::time::sleep(REFRESH_INTERVAL).await;
+    }
+}
+
+/// Turns an MQTT command into a LIFX message
+fn parse_command(command: &str, payload: &str, state: &DeviceState) -> Result<Message, String> {
+    match command {
+        "power" => match payload.trim() {
+            "on" => Ok(Message::set_power(PowerLevel::Enabled, None)),
+            "off" => Ok(Message::set_power(PowerLevel::Standby, None)),
+            other => Err(format!("unknown power state {:?}", other)),
+        },
+        "color" => {
+            let base = state.color.unwrap_or(HSBK {
+                hue: 0,
+                saturation: 0,
+                brightness: 65535,
+                kelvin: 3500,
+            });
+            let color = HSBK::from_str_with_base(payload, base).map_err(|e| e.to_string())?;
+            Ok(Message::LightSetColor {
+                reserved: 0,
+                color,
+                duration: 0,
+            })
+        }
+        other => Err(format!("unknown command {:?}", other)),
+    }
+}
+
+/// Sends a command to a device, retrying if it isn't acknowledged
+async fn handle_command(
+    client: Client,
+    mqtt: AsyncClient,
+    devices: Devices,
+    serial: String,
+    command: String,
+    payload: String,
+) {
+    let (info, state, last_command) = {
+        let devices = devices.lock().unwrap();
+        match devices.get(&serial) {
+            Some(d) => (d.info, d.state.clone(), d.last_command.clone()),
+            None => return,
+        }
+    };
+    let msg = match parse_command(&command, &payload, &state) {
+        Ok(msg) => msg,
+        Err(e) => {
+            eprintln!("Bad command for {}: {}", serial, e);
+            return;
+        }
+    };
+
+    {
+        let mut last_command = last_command.lock().await;
+        let wait = MIN_COMMAND_INTERVAL.saturating_sub(last_command.elapsed());
+        tokio::time::sleep(wait).await;
+
+        let sender = ReliableSender::new(client.clone(), COMMAND_ATTEMPTS);
+        if let Err(e) = sender.send_acked(info.addr, info.target, msg).await {
+            eprintln!("Giving up on {} {} for {}: {}", command, payload, serial, e);
+        }
+        *last_command = Instant::now();
+    }
+
+    refresh(&client, &mqtt, &devices, &serial).await;
+}
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let host = std::env::var("MQTT_HOST").unwrap_or_else(|_| "localhost".to_owned());
+    let port = match std::env::var("MQTT_PORT") {
+        Ok(port) => port.parse()?,
+        Err(_) => 1883,
+    };
+
+    let mut options = MqttOptions::new("lifx-bridge", host, port);
+    options.set_keep_alive(Duration::from_secs(30));
+    let (mqtt, mut eventloop) = AsyncClient::new(options, 64);
+
+    // on a host with several networks, this picks the one the lights are on
+    let mut client_options = ClientOptions::default();
+    if let Ok(addr) = std::env::var("LIFX_BIND_ADDR") {
+        client_options.bind_addr = SocketAddr::new(addr.parse()?, 0);
+    }
+    let client = Client::with_options(client_options).await?;
+    let devices = Devices::default();
+
+    tokio::spawn(discovery_task(
+        client.clone(),
+        mqtt.clone(),
+        devices.clone(),
+    ));
+    tokio::spawn(refresh_task(client.clone(), mqtt.clone(), devices.clone()));
+
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Incoming(Packet::Publish(publish))) => {
+                // topics look like lifx/<serial>/set/<command>
+                let parts: Vec<&str> = publish.topic.split('/').collect();
+                if let [PREFIX, serial, "set", command] = parts[..] {
+                    let payload = String::from_utf8_lossy(&publish.payload).into_owned();
+                    tokio::spawn(handle_command(
+                        client.clone(),
+                        mqtt.clone(),
+                        devices.clone(),
+                        serial.to_owned(),
+                        command.to_owned(),
+                        payload,
+                    ));
+                }
+            }
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("MQTT connection error: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
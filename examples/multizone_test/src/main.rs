@@ -1,4 +1,5 @@
-use lifx_core::{ApplicationRequest, BuildOptions, Message, RawMessage, HSBK};
+use lifx_core::zones::plan_color_zones;
+use lifx_core::{ApplicationRequest, BuildOptions, Message, RawMessage, SourceId, ZoneRange, HSBK};
 use std::net::{SocketAddr, UdpSocket};
 use std::thread::sleep;
 use std::time::Duration;
@@ -26,7 +27,7 @@ fn main() {
 
     let opts = BuildOptions {
         target: Some(0x0000562B29D573D0),
-        source: 12345678,
+        source: SourceId::new(12345678).unwrap(),
         ..Default::default()
     };
 
@@ -38,92 +39,40 @@ fn main() {
         },
     )
     .unwrap();
-    sock.send_to(&raw.pack().unwrap(), &target).unwrap();
+    sock.send_to(&raw.pack().unwrap(), target).unwrap();
 
     let raw = RawMessage::build(&opts, msg).unwrap();
-    sock.send_to(&raw.pack().unwrap(), &target).unwrap();
-
-    let duration = 50;
+    sock.send_to(&raw.pack().unwrap(), target).unwrap();
+
+    let duration = Duration::from_millis(50);
+    let on = HSBK {
+        hue: 0,
+        brightness: 65535,
+        kelvin: 3000,
+        saturation: 65535,
+    };
+    let off = HSBK {
+        brightness: 0,
+        ..on
+    };
 
+    // bounce a single lit zone back and forth along the strip
+    let steps: Vec<u8> = (0..16).chain((0..16).rev()).collect();
+    let mut prev = None;
     loop {
-        for idx in 0..16 {
-            // turn on this zone, and turn off the previous one (if applicable)
-            let msg = Message::SetColorZones {
-                start_index: idx,
-                end_index: idx,
-                color: HSBK {
-                    hue: 0,
-                    brightness: 65535,
-                    kelvin: 3000,
-                    saturation: 65535,
-                },
-                duration,
-                apply: ApplicationRequest::Apply,
-            };
-
-            let raw = RawMessage::build(&opts, msg).unwrap();
-            sock.send_to(&raw.pack().unwrap(), &target).unwrap();
-
-            if idx > 0 {
-                let msg = Message::SetColorZones {
-                    start_index: idx - 1,
-                    end_index: idx - 1,
-                    color: HSBK {
-                        hue: 0,
-                        brightness: 0,
-                        kelvin: 3000,
-                        saturation: 65535,
-                    },
-                    duration,
-                    apply: ApplicationRequest::Apply,
-                };
-
-                let raw = RawMessage::build(&opts, msg).unwrap();
-                sock.send_to(&raw.pack().unwrap(), &target).unwrap();
+        for &idx in &steps {
+            // turn on this zone and turn off the previous one, in a single visible change
+            let mut changes = vec![(ZoneRange::single(idx), on)];
+            if let Some(prev) = prev.filter(|&prev| prev != idx) {
+                changes.insert(0, (ZoneRange::single(prev), off));
             }
-
-            sleep(Duration::from_millis(duration as u64));
-        }
-
-        for idx in 0..16 {
-            let idx = 15 - idx;
-
-            // turn on this zone, and turn off the previous one (if applicable)
-            let msg = Message::SetColorZones {
-                start_index: idx,
-                end_index: idx,
-                color: HSBK {
-                    hue: 0,
-                    brightness: 65535,
-                    kelvin: 3000,
-                    saturation: 65535,
-                },
-                duration,
-                apply: ApplicationRequest::Apply,
-            };
-
-            let raw = RawMessage::build(&opts, msg).unwrap();
-            sock.send_to(&raw.pack().unwrap(), &target).unwrap();
-
-            if idx < 15 {
-                let msg = Message::SetColorZones {
-                    start_index: idx + 1,
-                    end_index: idx + 1,
-                    color: HSBK {
-                        hue: 0,
-                        brightness: 0,
-                        kelvin: 3000,
-                        saturation: 65535,
-                    },
-                    duration,
-                    apply: ApplicationRequest::Apply,
-                };
-
+            for msg in plan_color_zones(&changes, duration) {
                 let raw = RawMessage::build(&opts, msg).unwrap();
-                sock.send_to(&raw.pack().unwrap(), &target).unwrap();
+                sock.send_to(&raw.pack().unwrap(), target).unwrap();
             }
+            prev = Some(idx);
 
-            sleep(Duration::from_millis(duration as u64));
+            sleep(duration);
         }
     }
 }
@@ -1,4 +1,5 @@
-use lifx_core::{BuildOptions, Message, RawMessage, Waveform, HSBK};
+use lifx_core::waveform::BeatSync;
+use lifx_core::{BuildOptions, Message, RawMessage, SourceId, Waveform, HSBK};
 use std::net::{SocketAddr, UdpSocket};
 use std::time::Instant;
 
@@ -15,7 +16,7 @@ fn main() {
         ack_required: false,
         res_required: false,
         sequence: 0,
-        source: 12345678,
+        source: SourceId::new(12345678).unwrap(),
     };
 
     let starting_color = HSBK {
@@ -40,35 +41,20 @@ fn main() {
 
     let raw = RawMessage::build(&opts, msg).unwrap();
     let bytes = raw.pack().unwrap();
-    sock.send_to(&bytes, &target).unwrap();
+    sock.send_to(&bytes, target).unwrap();
 
     let stdin = std::io::stdin();
     let mut s = String::new();
 
-    println!("When ready, tap the [enter] key with the beat of a song.");
-    stdin.read_line(&mut s).unwrap();
-    stdin.read_line(&mut s).unwrap();
-    let start = Instant::now();
-    let mut count = 0;
-    for _ in 0..10 {
+    println!("Tap the [enter] key with the beat of a song.  The bulb will follow along.");
+    let mut sync = BeatSync::new(color, Waveform::Saw).with_skew_ratio(20000);
+    loop {
         stdin.read_line(&mut s).unwrap();
-        count += 1;
-        let d = start.elapsed();
-        println!("{:?}", d / count);
+        if let Some(msg) = sync.beat(Instant::now()) {
+            println!("Resyncing, period is {:?}", sync.period().unwrap());
+            let raw = RawMessage::build(&opts, msg).unwrap();
+            let bytes = raw.pack().unwrap();
+            sock.send_to(&bytes, target).unwrap();
+        }
     }
-    let period = start.elapsed() / count;
-
-    let msg = Message::SetWaveform {
-        reserved: 0,
-        transient: true,
-        color,
-        period: period.as_millis() as u32,
-        cycles: 50.0,
-        skew_ratio: 20000,
-        waveform: Waveform::Saw,
-    };
-
-    let raw = RawMessage::build(&opts, msg).unwrap();
-    let bytes = raw.pack().unwrap();
-    sock.send_to(&bytes, &target).unwrap();
 }
@@ -0,0 +1,168 @@
+/*
+ * C bindings for lifx-core.
+ *
+ * Typical usage:
+ *
+ *     LifxRawMessage *raw = NULL;
+ *     if (lifx_raw_message_unpack(buf, len, &raw) == LIFX_STATUS_OK) {
+ *         LifxMessage msg;
+ *         if (lifx_message_from_raw(raw, &msg) == LIFX_STATUS_OK && msg.typ == 107) {
+ *             printf("%s\n", msg.payload.light_state.label);
+ *         }
+ *         lifx_raw_message_free(raw);
+ *     }
+ *
+ * See lifx-core-capi/src/lib.rs for full documentation of each function.
+ */
+
+#ifndef LIFX_CORE_H
+#define LIFX_CORE_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* The UDP port that LIFX devices listen on */
+#define LIFX_PORT 56700
+
+/* The protocol number in every frame header */
+#define LIFX_PROTOCOL_NUMBER 1024
+
+typedef enum LifxStatus {
+    LIFX_STATUS_OK = 0,
+    LIFX_STATUS_NULL_POINTER = 1,
+    LIFX_STATUS_BUFFER_TOO_SMALL = 2,
+    LIFX_STATUS_PROTOCOL_ERROR = 3,
+    LIFX_STATUS_UNKNOWN_MESSAGE_TYPE = 4,
+    LIFX_STATUS_UNSUPPORTED = 5,
+} LifxStatus;
+
+/* Opaque; always free with lifx_raw_message_free */
+typedef struct LifxRawMessage LifxRawMessage;
+
+typedef struct LifxBuildOptions {
+    uint64_t target;
+    bool has_target;
+    bool ack_required;
+    bool res_required;
+    uint8_t sequence;
+    uint32_t source; /* must be nonzero */
+} LifxBuildOptions;
+
+typedef struct LifxHeader {
+    uint16_t size;
+    uint32_t source;
+    uint64_t target;
+    bool ack_required;
+    bool res_required;
+    uint8_t sequence;
+    uint16_t typ;
+} LifxHeader;
+
+typedef struct LifxHSBK {
+    uint16_t hue;
+    uint16_t saturation;
+    uint16_t brightness;
+    uint16_t kelvin;
+} LifxHSBK;
+
+/* StateService (3) */
+typedef struct LifxStateService {
+    uint8_t service;
+    uint32_t port;
+} LifxStateService;
+
+/* StateHostFirmware (15) */
+typedef struct LifxStateHostFirmware {
+    uint64_t build;
+    uint16_t version_minor;
+    uint16_t version_major;
+} LifxStateHostFirmware;
+
+/* SetPower (21), StatePower (22), LightStatePower (118) */
+typedef struct LifxPower {
+    uint16_t level;
+} LifxPower;
+
+/* SetLabel (24), StateLabel (25) */
+typedef struct LifxLabel {
+    char label[32];
+} LifxLabel;
+
+/* StateVersion (33) */
+typedef struct LifxStateVersion {
+    uint32_t vendor;
+    uint32_t product;
+} LifxStateVersion;
+
+/* Acknowledgement (45) */
+typedef struct LifxAcknowledgement {
+    uint8_t seq;
+} LifxAcknowledgement;
+
+/* LightSetColor (102) */
+typedef struct LifxLightSetColor {
+    LifxHSBK color;
+    uint32_t duration;
+} LifxLightSetColor;
+
+/* LightState (107) */
+typedef struct LifxLightState {
+    LifxHSBK color;
+    uint16_t power;
+    char label[32];
+} LifxLightState;
+
+/* LightSetPower (117) */
+typedef struct LifxLightSetPower {
+    uint16_t level;
+    uint32_t duration;
+} LifxLightSetPower;
+
+/*
+ * Get messages (GetService 2, GetHostFirmware 14, GetPower 20, GetLabel 23, GetVersion 32,
+ * LightGet 101, LightGetPower 116) and SetReboot 38 have no payload.
+ */
+typedef union LifxPayload {
+    LifxStateService state_service;
+    LifxStateHostFirmware state_host_firmware;
+    LifxPower power;
+    LifxLabel label;
+    LifxStateVersion state_version;
+    LifxAcknowledgement acknowledgement;
+    LifxLightSetColor light_set_color;
+    LifxLightState light_state;
+    LifxLightSetPower light_set_power;
+} LifxPayload;
+
+typedef struct LifxMessage {
+    uint16_t typ;
+    LifxPayload payload;
+} LifxMessage;
+
+LifxStatus lifx_raw_message_build(const LifxBuildOptions *options, const LifxMessage *msg,
+                                  LifxRawMessage **out);
+
+LifxStatus lifx_raw_message_unpack(const uint8_t *data, size_t len, LifxRawMessage **out);
+
+LifxStatus lifx_raw_message_pack(const LifxRawMessage *raw, uint8_t *out, size_t out_len,
+                                 size_t *written);
+
+LifxStatus lifx_raw_message_header(const LifxRawMessage *raw, LifxHeader *out);
+
+LifxStatus lifx_raw_message_payload(const LifxRawMessage *raw, uint8_t *out, size_t out_len,
+                                    size_t *written);
+
+LifxStatus lifx_message_from_raw(const LifxRawMessage *raw, LifxMessage *out);
+
+void lifx_raw_message_free(LifxRawMessage *raw);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* LIFX_CORE_H */
@@ -0,0 +1,748 @@
+//! C bindings for `lifx-core`
+//!
+//! This exposes the same build/pack/unpack/decode flow as the Rust API, so that C and C++
+//! programs can reuse this implementation of the LIFX LAN protocol.  The matching header is in
+//! `include/lifx_core.h`.
+//!
+//! A [RawMessage] is passed across the boundary as an opaque `LifxRawMessage` pointer, which
+//! must be freed with [lifx_raw_message_free].  Decoded messages use [LifxMessage], a tagged union
+//! keyed by the LIFX message type number.  Only the most commonly used messages have a union
+//! representation (see [LifxPayload]); decoding anything else returns
+//! [LifxStatus::Unsupported], but the raw payload is still available through
+//! [lifx_raw_message_payload].
+//!
+//! All functions return a [LifxStatus], and never panic across the FFI boundary.
+
+use lifx_core::{
+    BuildOptions, LifxString, Message, PowerLevel, RawMessage, Service, SourceId, HSBK,
+};
+use std::convert::TryFrom;
+use std::ffi::CStr;
+use std::os::raw::c_char;
+use std::ptr;
+
+/// The result of every function in this library
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LifxStatus {
+    Ok = 0,
+    /// A required pointer argument was null
+    NullPointer = 1,
+    /// The output buffer was too small; the required size has been written to `written`
+    BufferTooSmall = 2,
+    /// The data couldn't be parsed as a LIFX message
+    ProtocolError = 3,
+    /// The message type isn't known to lifx-core
+    UnknownMessageType = 4,
+    /// The message type is known, but has no [LifxMessage] representation
+    Unsupported = 5,
+}
+
+impl From<lifx_core::Error> for LifxStatus {
+    fn from(e: lifx_core::Error) -> LifxStatus {
+        match e {
+            lifx_core::Error::UnknownMessageType(_) => LifxStatus::UnknownMessageType,
+            _ => LifxStatus::ProtocolError,
+        }
+    }
+}
+
+/// C version of [BuildOptions]
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LifxBuildOptions {
+    /// The device to address; ignored unless `has_target` is set
+    pub target: u64,
+    pub has_target: bool,
+    pub ack_required: bool,
+    pub res_required: bool,
+    pub sequence: u8,
+    /// Must be nonzero, otherwise building fails with [LifxStatus::ProtocolError]
+    pub source: u32,
+}
+
+/// The header fields of a [RawMessage] that callers usually care about
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LifxHeader {
+    pub size: u16,
+    pub source: u32,
+    pub target: u64,
+    pub ack_required: bool,
+    pub res_required: bool,
+    pub sequence: u8,
+    /// The message type number
+    pub typ: u16,
+}
+
+/// C version of [HSBK]
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LifxHSBK {
+    pub hue: u16,
+    pub saturation: u16,
+    pub brightness: u16,
+    pub kelvin: u16,
+}
+
+impl From<HSBK> for LifxHSBK {
+    fn from(c: HSBK) -> LifxHSBK {
+        LifxHSBK {
+            hue: c.hue,
+            saturation: c.saturation,
+            brightness: c.brightness,
+            kelvin: c.kelvin,
+        }
+    }
+}
+
+impl From<LifxHSBK> for HSBK {
+    fn from(c: LifxHSBK) -> HSBK {
+        HSBK {
+            hue: c.hue,
+            saturation: c.saturation,
+            brightness: c.brightness,
+            kelvin: c.kelvin,
+        }
+    }
+}
+
+/// Payload of StateService (3)
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LifxStateService {
+    pub service: u8,
+    pub port: u32,
+}
+
+/// Payload of StateHostFirmware (15)
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LifxStateHostFirmware {
+    pub build: u64,
+    pub version_minor: u16,
+    pub version_major: u16,
+}
+
+/// Payload of SetPower (21), StatePower (22), and LightStatePower (118)
+///
+/// For SetPower, any nonzero level turns the device on.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LifxPower {
+    pub level: u16,
+}
+
+/// Payload of SetLabel (24) and StateLabel (25): a nul-terminated string
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LifxLabel {
+    pub label: [c_char; 32],
+}
+
+/// Payload of StateVersion (33)
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LifxStateVersion {
+    pub vendor: u32,
+    pub product: u32,
+}
+
+/// Payload of Acknowledgement (45)
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LifxAcknowledgement {
+    pub seq: u8,
+}
+
+/// Payload of LightSetColor (102)
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LifxLightSetColor {
+    pub color: LifxHSBK,
+    /// Transition time, in milliseconds
+    pub duration: u32,
+}
+
+/// Payload of LightState (107)
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LifxLightState {
+    pub color: LifxHSBK,
+    pub power: u16,
+    pub label: [c_char; 32],
+}
+
+/// Payload of LightSetPower (117)
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LifxLightSetPower {
+    pub level: u16,
+    /// Transition time, in milliseconds
+    pub duration: u32,
+}
+
+/// The payload of a [LifxMessage].  Which field is valid depends on [LifxMessage::typ].
+///
+/// Get messages (GetService, GetHostFirmware, GetPower, GetLabel, GetVersion, LightGet,
+/// LightGetPower) and SetReboot have no payload.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub union LifxPayload {
+    pub state_service: LifxStateService,
+    pub state_host_firmware: LifxStateHostFirmware,
+    pub power: LifxPower,
+    pub label: LifxLabel,
+    pub state_version: LifxStateVersion,
+    pub acknowledgement: LifxAcknowledgement,
+    pub light_set_color: LifxLightSetColor,
+    pub light_state: LifxLightState,
+    pub light_set_power: LifxLightSetPower,
+}
+
+/// A decoded message, tagged by its message type number
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct LifxMessage {
+    pub typ: u16,
+    pub payload: LifxPayload,
+}
+
+fn label_to_c(label: &LifxString) -> [c_char; 32] {
+    let mut out = [0; 32];
+    for (o, b) in out.iter_mut().zip(label.cstr().to_bytes().iter().take(31)) {
+        *o = *b as c_char;
+    }
+    out
+}
+
+fn label_from_c(label: &[c_char; 32]) -> LifxString {
+    let bytes: Vec<u8> = label.iter().map(|c| *c as u8).collect();
+    let end = bytes.iter().position(|b| *b == 0).unwrap_or(31);
+    let cstr = CStr::from_bytes_with_nul(&bytes[..=end])
+        .ok()
+        .map(|c| c.to_owned())
+        .unwrap_or_else(|| {
+            // no terminator in the first 32 bytes, so truncate to 31 and add one
+            let mut v = bytes[..31].to_vec();
+            v.push(0);
+            CStr::from_bytes_with_nul(&v).unwrap().to_owned()
+        });
+    LifxString::new(&cstr)
+}
+
+impl LifxMessage {
+    fn empty(typ: u16) -> LifxMessage {
+        LifxMessage {
+            typ,
+            payload: LifxPayload {
+                power: LifxPower { level: 0 },
+            },
+        }
+    }
+
+    fn with(typ: u16, payload: LifxPayload) -> LifxMessage {
+        LifxMessage { typ, payload }
+    }
+}
+
+impl TryFrom<&Message> for LifxMessage {
+    type Error = LifxStatus;
+
+    fn try_from(msg: &Message) -> Result<LifxMessage, LifxStatus> {
+        let typ = msg.get_num();
+        Ok(match msg {
+            Message::GetService
+            | Message::GetHostFirmware
+            | Message::GetPower
+            | Message::GetLabel
+            | Message::GetVersion
+            | Message::SetReboot
+            | Message::LightGet
+            | Message::LightGetPower => LifxMessage::empty(typ),
+            Message::StateService { service, port } => LifxMessage::with(
+                typ,
+                LifxPayload {
+                    state_service: LifxStateService {
+                        service: u8::from(*service),
+                        port: *port,
+                    },
+                },
+            ),
+            Message::StateHostFirmware {
+                build,
+                version_minor,
+                version_major,
+                ..
+            } => LifxMessage::with(
+                typ,
+                LifxPayload {
+                    state_host_firmware: LifxStateHostFirmware {
+                        build: *build,
+                        version_minor: *version_minor,
+                        version_major: *version_major,
+                    },
+                },
+            ),
+            Message::SetPower { level } => LifxMessage::with(
+                typ,
+                LifxPayload {
+                    power: LifxPower {
+                        level: *level as u16,
+                    },
+                },
+            ),
+            Message::StatePower { level } | Message::LightStatePower { level } => {
+                LifxMessage::with(
+                    typ,
+                    LifxPayload {
+                        power: LifxPower { level: *level },
+                    },
+                )
+            }
+            Message::SetLabel { label } | Message::StateLabel { label } => LifxMessage::with(
+                typ,
+                LifxPayload {
+                    label: LifxLabel {
+                        label: label_to_c(label),
+                    },
+                },
+            ),
+            Message::StateVersion {
+                vendor, product, ..
+            } => LifxMessage::with(
+                typ,
+                LifxPayload {
+                    state_version: LifxStateVersion {
+                        vendor: *vendor,
+                        product: *product,
+                    },
+                },
+            ),
+            Message::Acknowledgement { seq } => LifxMessage::with(
+                typ,
+                LifxPayload {
+                    acknowledgement: LifxAcknowledgement { seq: *seq },
+                },
+            ),
+            Message::LightSetColor {
+                color, duration, ..
+            } => LifxMessage::with(
+                typ,
+                LifxPayload {
+                    light_set_color: LifxLightSetColor {
+                        color: (*color).into(),
+                        duration: *duration,
+                    },
+                },
+            ),
+            Message::LightState {
+                color,
+                power,
+                label,
+                ..
+            } => LifxMessage::with(
+                typ,
+                LifxPayload {
+                    light_state: LifxLightState {
+                        color: (*color).into(),
+                        power: *power,
+                        label: label_to_c(label),
+                    },
+                },
+            ),
+            Message::LightSetPower { level, duration } => LifxMessage::with(
+                typ,
+                LifxPayload {
+                    light_set_power: LifxLightSetPower {
+                        level: *level,
+                        duration: *duration,
+                    },
+                },
+            ),
+            _ => return Err(LifxStatus::Unsupported),
+        })
+    }
+}
+
+impl TryFrom<&LifxMessage> for Message {
+    type Error = LifxStatus;
+
+    fn try_from(msg: &LifxMessage) -> Result<Message, LifxStatus> {
+        // Safety (for all the union reads below): the caller promises that the payload field
+        // matching `typ` is the one that was written
+        let p = &msg.payload;
+        unsafe {
+            Ok(match msg.typ {
+                2 => Message::GetService,
+                3 => Message::StateService {
+                    service: Service::from(p.state_service.service),
+                    port: p.state_service.port,
+                },
+                14 => Message::GetHostFirmware,
+                15 => Message::StateHostFirmware {
+                    build: p.state_host_firmware.build,
+                    reserved: 0,
+                    version_minor: p.state_host_firmware.version_minor,
+                    version_major: p.state_host_firmware.version_major,
+                },
+                20 => Message::GetPower,
+                21 => Message::SetPower {
+                    level: if p.power.level == 0 {
+                        PowerLevel::Standby
+                    } else {
+                        PowerLevel::Enabled
+                    },
+                },
+                22 => Message::StatePower {
+                    level: p.power.level,
+                },
+                23 => Message::GetLabel,
+                24 => Message::SetLabel {
+                    label: label_from_c(&p.label.label),
+                },
+                25 => Message::StateLabel {
+                    label: label_from_c(&p.label.label),
+                },
+                32 => Message::GetVersion,
+                38 => Message::SetReboot,
+                33 => Message::StateVersion {
+                    vendor: p.state_version.vendor,
+                    product: p.state_version.product,
+                    reserved: 0,
+                },
+                45 => Message::Acknowledgement {
+                    seq: p.acknowledgement.seq,
+                },
+                101 => Message::LightGet,
+                102 => Message::LightSetColor {
+                    reserved: 0,
+                    color: p.light_set_color.color.into(),
+                    duration: p.light_set_color.duration,
+                },
+                107 => Message::LightState {
+                    color: p.light_state.color.into(),
+                    reserved: 0,
+                    power: p.light_state.power,
+                    label: label_from_c(&p.light_state.label),
+                    reserved2: 0,
+                },
+                116 => Message::LightGetPower,
+                117 => Message::LightSetPower {
+                    level: p.light_set_power.level,
+                    duration: p.light_set_power.duration,
+                },
+                118 => Message::LightStatePower {
+                    level: p.power.level,
+                },
+                _ => return Err(LifxStatus::Unsupported),
+            })
+        }
+    }
+}
+
+/// Copies `data` into a caller-provided buffer, reporting the required size through `written`
+unsafe fn copy_out(data: &[u8], out: *mut u8, out_len: usize, written: *mut usize) -> LifxStatus {
+    if !written.is_null() {
+        *written = data.len();
+    }
+    if data.len() > out_len {
+        return LifxStatus::BufferTooSmall;
+    }
+    if !data.is_empty() {
+        if out.is_null() {
+            return LifxStatus::NullPointer;
+        }
+        ptr::copy_nonoverlapping(data.as_ptr(), out, data.len());
+    }
+    LifxStatus::Ok
+}
+
+/// Builds a new raw message from a decoded message, see [RawMessage::build]
+///
+/// On success, `*out` is set to a new message, which must be freed with [lifx_raw_message_free].
+///
+/// # Safety
+///
+/// `options` and `msg` must point to valid structs, with the payload field matching `msg->typ`
+/// initialized.  `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn lifx_raw_message_build(
+    options: *const LifxBuildOptions,
+    msg: *const LifxMessage,
+    out: *mut *mut RawMessage,
+) -> LifxStatus {
+    if options.is_null() || msg.is_null() || out.is_null() {
+        return LifxStatus::NullPointer;
+    }
+    let options = &*options;
+    let opts = BuildOptions {
+        target: if options.has_target {
+            Some(options.target)
+        } else {
+            None
+        },
+        ack_required: options.ack_required,
+        res_required: options.res_required,
+        sequence: options.sequence,
+        source: match SourceId::try_from(options.source) {
+            Ok(source) => source,
+            Err(e) => return e.into(),
+        },
+    };
+    let msg = match Message::try_from(&*msg) {
+        Ok(msg) => msg,
+        Err(e) => return e,
+    };
+    match RawMessage::build(&opts, msg) {
+        Ok(raw) => {
+            *out = Box::into_raw(Box::new(raw));
+            LifxStatus::Ok
+        }
+        Err(e) => e.into(),
+    }
+}
+
+/// Parses a datagram into a raw message, see [RawMessage::unpack]
+///
+/// On success, `*out` is set to a new message, which must be freed with [lifx_raw_message_free].
+///
+/// # Safety
+///
+/// `data` must be valid for reads of `len` bytes, and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn lifx_raw_message_unpack(
+    data: *const u8,
+    len: usize,
+    out: *mut *mut RawMessage,
+) -> LifxStatus {
+    if data.is_null() || out.is_null() {
+        return LifxStatus::NullPointer;
+    }
+    match RawMessage::unpack(std::slice::from_raw_parts(data, len)) {
+        Ok(raw) => {
+            *out = Box::into_raw(Box::new(raw));
+            LifxStatus::Ok
+        }
+        Err(e) => e.into(),
+    }
+}
+
+/// Packs a raw message into bytes that can be sent over the network, see [RawMessage::pack]
+///
+/// The number of bytes needed is always written to `written` (if it's not null), so you can call
+/// this with a zero-length buffer to find out how big the buffer needs to be.
+///
+/// # Safety
+///
+/// `raw` must come from this library, `out` must be valid for writes of `out_len` bytes, and
+/// `written` must be null or valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn lifx_raw_message_pack(
+    raw: *const RawMessage,
+    out: *mut u8,
+    out_len: usize,
+    written: *mut usize,
+) -> LifxStatus {
+    if raw.is_null() {
+        return LifxStatus::NullPointer;
+    }
+    match (*raw).pack() {
+        Ok(bytes) => copy_out(&bytes, out, out_len, written),
+        Err(e) => e.into(),
+    }
+}
+
+/// Reads the header fields of a raw message
+///
+/// # Safety
+///
+/// `raw` must come from this library, and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn lifx_raw_message_header(
+    raw: *const RawMessage,
+    out: *mut LifxHeader,
+) -> LifxStatus {
+    if raw.is_null() || out.is_null() {
+        return LifxStatus::NullPointer;
+    }
+    let raw = &*raw;
+    *out = LifxHeader {
+        size: raw.frame.size(),
+        source: raw.frame.source(),
+        target: raw.frame_addr.target,
+        ack_required: raw.frame_addr.ack_required,
+        res_required: raw.frame_addr.res_required,
+        sequence: raw.frame_addr.sequence,
+        typ: raw.protocol_header.typ,
+    };
+    LifxStatus::Ok
+}
+
+/// Copies out the undecoded payload of a raw message
+///
+/// This works the same way as [lifx_raw_message_pack].
+///
+/// # Safety
+///
+/// See [lifx_raw_message_pack].
+#[no_mangle]
+pub unsafe extern "C" fn lifx_raw_message_payload(
+    raw: *const RawMessage,
+    out: *mut u8,
+    out_len: usize,
+    written: *mut usize,
+) -> LifxStatus {
+    if raw.is_null() {
+        return LifxStatus::NullPointer;
+    }
+    copy_out(&(*raw).payload, out, out_len, written)
+}
+
+/// Decodes the payload of a raw message, see [Message::from_raw]
+///
+/// # Safety
+///
+/// `raw` must come from this library, and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn lifx_message_from_raw(
+    raw: *const RawMessage,
+    out: *mut LifxMessage,
+) -> LifxStatus {
+    if raw.is_null() || out.is_null() {
+        return LifxStatus::NullPointer;
+    }
+    let msg = match Message::from_raw(&*raw) {
+        Ok(msg) => msg,
+        Err(e) => return e.into(),
+    };
+    match LifxMessage::try_from(&msg) {
+        Ok(m) => {
+            *out = m;
+            LifxStatus::Ok
+        }
+        Err(e) => e,
+    }
+}
+
+/// Frees a raw message created by this library.  Passing null is allowed, and does nothing.
+///
+/// # Safety
+///
+/// `raw` must be null, or come from this library and not have already been freed.
+#[no_mangle]
+pub unsafe extern "C" fn lifx_raw_message_free(raw: *mut RawMessage) {
+    if !raw.is_null() {
+        drop(Box::from_raw(raw));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CString;
+
+    #[test]
+    fn test_roundtrip() {
+        let opts = LifxBuildOptions {
+            target: 0x1234,
+            has_target: true,
+            ack_required: false,
+            res_required: true,
+            sequence: 7,
+            source: 42,
+        };
+        let msg = LifxMessage::try_from(&Message::LightState {
+            color: HSBK {
+                hue: 1,
+                saturation: 2,
+                brightness: 3,
+                kelvin: 3500,
+            },
+            reserved: 0,
+            power: 65535,
+            label: LifxString::new(&CString::new("Kitchen").unwrap()),
+            reserved2: 0,
+        })
+        .unwrap();
+
+        unsafe {
+            let mut raw = ptr::null_mut();
+            assert_eq!(
+                lifx_raw_message_build(&opts, &msg, &mut raw),
+                LifxStatus::Ok
+            );
+
+            let mut needed = 0;
+            assert_eq!(
+                lifx_raw_message_pack(raw, ptr::null_mut(), 0, &mut needed),
+                LifxStatus::BufferTooSmall
+            );
+            let mut buf = vec![0u8; needed];
+            assert_eq!(
+                lifx_raw_message_pack(raw, buf.as_mut_ptr(), buf.len(), &mut needed),
+                LifxStatus::Ok
+            );
+            lifx_raw_message_free(raw);
+
+            let zero_source = LifxBuildOptions { source: 0, ..opts };
+            let mut raw = ptr::null_mut();
+            assert_eq!(
+                lifx_raw_message_build(&zero_source, &msg, &mut raw),
+                LifxStatus::ProtocolError
+            );
+            assert!(raw.is_null());
+
+            let mut raw = ptr::null_mut();
+            assert_eq!(
+                lifx_raw_message_unpack(buf.as_ptr(), buf.len(), &mut raw),
+                LifxStatus::Ok
+            );
+            let mut header = LifxHeader::default();
+            assert_eq!(lifx_raw_message_header(raw, &mut header), LifxStatus::Ok);
+            assert_eq!(header.target, 0x1234);
+            assert_eq!(header.sequence, 7);
+            assert_eq!(header.typ, 107);
+
+            let mut decoded = LifxMessage::empty(0);
+            assert_eq!(lifx_message_from_raw(raw, &mut decoded), LifxStatus::Ok);
+            lifx_raw_message_free(raw);
+
+            assert_eq!(decoded.typ, 107);
+            let state = decoded.payload.light_state;
+            assert_eq!(state.color.kelvin, 3500);
+            assert_eq!(state.power, 65535);
+            assert_eq!(
+                CStr::from_ptr(state.label.as_ptr()).to_str().unwrap(),
+                "Kitchen"
+            );
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        unsafe {
+            let mut raw = ptr::null_mut();
+            let junk = [0u8; 4];
+            assert_eq!(
+                lifx_raw_message_unpack(junk.as_ptr(), junk.len(), &mut raw),
+                LifxStatus::ProtocolError
+            );
+            assert!(raw.is_null());
+
+            let msg = LifxMessage::empty(501);
+            let opts = LifxBuildOptions {
+                target: 0,
+                has_target: false,
+                ack_required: false,
+                res_required: false,
+                sequence: 0,
+                source: 1,
+            };
+            assert_eq!(
+                lifx_raw_message_build(&opts, &msg, &mut raw),
+                LifxStatus::Unsupported
+            );
+        }
+    }
+}
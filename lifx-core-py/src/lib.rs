@@ -0,0 +1,388 @@
+//! Python bindings for `lifx-core`
+//!
+//! This exposes `Message`, `RawMessage`, `HSBK`, `get_product_info`, and the `LIFX_PORT` and
+//! `PROTOCOL_NUMBER` constants to Python, so that projects using pure-Python LIFX libraries (like
+//! `lifxlan`) can adopt this codec incrementally.
+//!
+//! The bindings are only compiled with the `python` feature, and are meant to be built with
+//! [maturin](https://www.maturin.rs/):
+//!
+//! ```text
+//! cd lifx-core-py
+//! maturin develop
+//! ```
+//!
+//! ```python
+//! import lifx_core
+//!
+//! msg = lifx_core.Message.light_set_color(lifx_core.HSBK.parse("red 50%"), duration_ms=1000)
+//! raw = lifx_core.RawMessage.build(msg, target=0xd073d5001337, ack_required=True, source=1234)
+//! sock.sendto(raw.pack(), (ip, lifx_core.LIFX_PORT))
+//!
+//! reply = lifx_core.RawMessage.unpack(data).message()
+//! print(reply.name, reply.to_dict())
+//! ```
+
+#[cfg(feature = "python")]
+mod bindings {
+    use lifx_core::{BuildOptions, LifxString, PowerLevel, SourceId, TemperatureRange};
+    use pyo3::exceptions::PyValueError;
+    use pyo3::prelude::*;
+    use pyo3::types::{PyBytes, PyDict};
+    use std::convert::TryFrom;
+    use std::ffi::CString;
+    use std::time::Duration;
+
+    fn to_py_err(e: lifx_core::Error) -> PyErr {
+        PyValueError::new_err(e.to_string())
+    }
+
+    fn lifx_string(s: &str) -> PyResult<LifxString> {
+        let s = CString::new(s).map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(LifxString::new(&s))
+    }
+
+    /// A color, with every component between 0 and 65535 (except kelvin, which is in degrees)
+    #[pyclass(name = "HSBK", eq, from_py_object)]
+    #[derive(Clone, PartialEq)]
+    pub struct Hsbk {
+        #[pyo3(get, set)]
+        hue: u16,
+        #[pyo3(get, set)]
+        saturation: u16,
+        #[pyo3(get, set)]
+        brightness: u16,
+        #[pyo3(get, set)]
+        kelvin: u16,
+    }
+
+    impl From<lifx_core::HSBK> for Hsbk {
+        fn from(c: lifx_core::HSBK) -> Hsbk {
+            Hsbk {
+                hue: c.hue,
+                saturation: c.saturation,
+                brightness: c.brightness,
+                kelvin: c.kelvin,
+            }
+        }
+    }
+
+    impl From<&Hsbk> for lifx_core::HSBK {
+        fn from(c: &Hsbk) -> lifx_core::HSBK {
+            lifx_core::HSBK {
+                hue: c.hue,
+                saturation: c.saturation,
+                brightness: c.brightness,
+                kelvin: c.kelvin,
+            }
+        }
+    }
+
+    #[pymethods]
+    impl Hsbk {
+        #[new]
+        fn new(hue: u16, saturation: u16, brightness: u16, kelvin: u16) -> Hsbk {
+            Hsbk {
+                hue,
+                saturation,
+                brightness,
+                kelvin,
+            }
+        }
+
+        /// Parses a color string like `"red"`, `"#ff8800"`, or `"3500K 50%"`
+        #[staticmethod]
+        fn parse(s: &str) -> PyResult<Hsbk> {
+            s.parse::<lifx_core::HSBK>()
+                .map(Hsbk::from)
+                .map_err(to_py_err)
+        }
+
+        fn __repr__(&self) -> String {
+            format!(
+                "HSBK(hue={}, saturation={}, brightness={}, kelvin={})",
+                self.hue, self.saturation, self.brightness, self.kelvin
+            )
+        }
+    }
+
+    /// A decoded LIFX message
+    #[pyclass(name = "Message", skip_from_py_object)]
+    #[derive(Clone)]
+    pub struct Message(lifx_core::Message);
+
+    #[pymethods]
+    impl Message {
+        /// The name of this message type, like `"LightState"`
+        #[getter]
+        fn name(&self) -> String {
+            format!("{:?}", self.0)
+                .chars()
+                .take_while(|c| c.is_alphanumeric())
+                .collect()
+        }
+
+        /// The message type number
+        #[getter]
+        fn typ(&self) -> u16 {
+            self.0.get_num()
+        }
+
+        /// The name that the LIFX LAN docs give to this message type, like `"GetColor"`
+        #[getter]
+        fn type_name(&self) -> &'static str {
+            self.0.type_name()
+        }
+
+        /// A link to the section of the LIFX LAN docs that describes this message type
+        #[getter]
+        fn doc_url(&self) -> String {
+            self.0.doc_url()
+        }
+
+        /// The fields of this message, for the most commonly received replies
+        ///
+        /// For other messages, this only contains `name` and `typ`.
+        fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+            use lifx_core::Message as M;
+
+            let d = PyDict::new(py);
+            d.set_item("name", self.name())?;
+            d.set_item("typ", self.typ())?;
+            match &self.0 {
+                M::StateService { service, port } => {
+                    d.set_item("service", u8::from(*service))?;
+                    d.set_item("port", port)?;
+                }
+                M::StateHostFirmware {
+                    build,
+                    version_minor,
+                    version_major,
+                    ..
+                } => {
+                    d.set_item("build", build)?;
+                    d.set_item("version_major", version_major)?;
+                    d.set_item("version_minor", version_minor)?;
+                }
+                M::StatePower { level } | M::LightStatePower { level } => {
+                    d.set_item("level", level)?;
+                }
+                M::StateLabel { label } => d.set_item("label", label.to_string())?,
+                M::StateVersion {
+                    vendor, product, ..
+                } => {
+                    d.set_item("vendor", vendor)?;
+                    d.set_item("product", product)?;
+                }
+                M::Acknowledgement { seq } => d.set_item("seq", seq)?,
+                M::LightState {
+                    color,
+                    power,
+                    label,
+                    ..
+                } => {
+                    d.set_item("color", Hsbk::from(*color))?;
+                    d.set_item("power", power)?;
+                    d.set_item("label", label.to_string())?;
+                }
+                _ => (),
+            }
+            Ok(d)
+        }
+
+        #[staticmethod]
+        fn get_service() -> Message {
+            Message(lifx_core::Message::GetService)
+        }
+
+        #[staticmethod]
+        fn get_label() -> Message {
+            Message(lifx_core::Message::GetLabel)
+        }
+
+        #[staticmethod]
+        fn get_version() -> Message {
+            Message(lifx_core::Message::GetVersion)
+        }
+
+        #[staticmethod]
+        fn get_power() -> Message {
+            Message(lifx_core::Message::GetPower)
+        }
+
+        #[staticmethod]
+        fn light_get() -> Message {
+            Message(lifx_core::Message::LightGet)
+        }
+
+        #[staticmethod]
+        fn set_label(label: &str) -> PyResult<Message> {
+            Ok(Message(lifx_core::Message::SetLabel {
+                label: lifx_string(label)?,
+            }))
+        }
+
+        /// Turns a device on or off, optionally fading over `duration_ms` milliseconds
+        #[staticmethod]
+        #[pyo3(signature = (on, duration_ms=None))]
+        fn set_power(on: bool, duration_ms: Option<u64>) -> Message {
+            let level = if on {
+                PowerLevel::Enabled
+            } else {
+                PowerLevel::Standby
+            };
+            Message(lifx_core::Message::set_power(
+                level,
+                duration_ms.map(Duration::from_millis),
+            ))
+        }
+
+        #[staticmethod]
+        #[pyo3(signature = (color, duration_ms=0))]
+        fn light_set_color(color: PyRef<'_, Hsbk>, duration_ms: u32) -> Message {
+            Message(lifx_core::Message::LightSetColor {
+                reserved: 0,
+                color: (&*color).into(),
+                duration: duration_ms,
+            })
+        }
+
+        fn __repr__(&self) -> String {
+            format!("{:?}", self.0)
+        }
+
+        fn __eq__(&self, other: PyRef<'_, Message>) -> bool {
+            self.0 == other.0
+        }
+    }
+
+    /// A message, along with its frame header
+    #[pyclass(name = "RawMessage")]
+    pub struct RawMessage(lifx_core::RawMessage);
+
+    #[pymethods]
+    impl RawMessage {
+        /// Builds a message to send
+        ///
+        /// `source` must be nonzero; if it's not given, a random source is used that's shared by
+        /// every message built in this process.
+        #[staticmethod]
+        #[pyo3(signature = (message, target=None, ack_required=false, res_required=false, sequence=0, source=None))]
+        fn build(
+            message: PyRef<'_, Message>,
+            target: Option<u64>,
+            ack_required: bool,
+            res_required: bool,
+            sequence: u8,
+            source: Option<u32>,
+        ) -> PyResult<RawMessage> {
+            let source = match source {
+                Some(source) => SourceId::try_from(source).map_err(to_py_err)?,
+                None => SourceId::default(),
+            };
+            let options = BuildOptions {
+                target,
+                ack_required,
+                res_required,
+                sequence,
+                source,
+            };
+            lifx_core::RawMessage::build(&options, message.0.clone())
+                .map(RawMessage)
+                .map_err(to_py_err)
+        }
+
+        #[staticmethod]
+        fn unpack(data: &[u8]) -> PyResult<RawMessage> {
+            lifx_core::RawMessage::unpack(data)
+                .map(RawMessage)
+                .map_err(to_py_err)
+        }
+
+        fn pack<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+            let bytes = self.0.pack().map_err(to_py_err)?;
+            Ok(PyBytes::new(py, &bytes))
+        }
+
+        /// Decodes the payload of this message
+        fn message(&self) -> PyResult<Message> {
+            lifx_core::Message::from_raw(&self.0)
+                .map(Message)
+                .map_err(to_py_err)
+        }
+
+        #[getter]
+        fn target(&self) -> u64 {
+            self.0.frame_addr.target
+        }
+
+        #[getter]
+        fn source(&self) -> u32 {
+            self.0.frame.source()
+        }
+
+        #[getter]
+        fn sequence(&self) -> u8 {
+            self.0.frame_addr.sequence
+        }
+
+        #[getter]
+        fn typ(&self) -> u16 {
+            self.0.protocol_header.typ
+        }
+
+        #[getter]
+        fn payload<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+            PyBytes::new(py, &self.0.payload)
+        }
+
+        fn __repr__(&self) -> String {
+            format!("{:?}", self.0)
+        }
+    }
+
+    /// Looks up a product by its vendor and product ID, returning a dict of its capabilities
+    #[pyfunction]
+    fn get_product_info(
+        py: Python<'_>,
+        vendor: u32,
+        product: u32,
+    ) -> PyResult<Option<Bound<'_, PyDict>>> {
+        let info = match lifx_core::get_product_info(vendor, product) {
+            Some(info) => info,
+            None => return Ok(None),
+        };
+        let d = PyDict::new(py);
+        d.set_item("name", info.name)?;
+        d.set_item("color", info.color())?;
+        d.set_item("infrared", info.infrared())?;
+        d.set_item("multizone", info.multizone())?;
+        d.set_item("chain", info.chain())?;
+        d.set_item("hev", info.hev())?;
+        d.set_item("matrix", info.matrix())?;
+        d.set_item("relays", info.relays())?;
+        d.set_item("buttons", info.buttons())?;
+        d.set_item("extended_multizone", info.extended_multizone())?;
+        let (min_kelvin, max_kelvin) = match info.temperature_range {
+            TemperatureRange::Variable { min, max } => (Some(min), Some(max)),
+            TemperatureRange::Fixed(k) => (Some(k), Some(k)),
+            TemperatureRange::None => (None, None),
+        };
+        d.set_item("min_kelvin", min_kelvin)?;
+        d.set_item("max_kelvin", max_kelvin)?;
+        d.set_item("max_watts", info.max_watts())?;
+        Ok(Some(d))
+    }
+
+    #[pymodule]
+    #[pyo3(name = "lifx_core")]
+    fn lifx_core_module(m: &Bound<'_, PyModule>) -> PyResult<()> {
+        m.add_class::<Hsbk>()?;
+        m.add_class::<Message>()?;
+        m.add_class::<RawMessage>()?;
+        m.add_function(wrap_pyfunction!(get_product_info, m)?)?;
+        m.add("LIFX_PORT", lifx_core::LIFX_PORT)?;
+        m.add("PROTOCOL_NUMBER", lifx_core::PROTOCOL_NUMBER)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,362 @@
+//! Monochrome bitmaps and a tiny font, for drawing text and icons on matrix lights
+//!
+//! Each tile in a Tile chain is an 8x8 grid of zones, whose colors are sent a row at a time, from
+//! the top left.  A [Bitmap] is a grid of pixels that are either on or off, which can be drawn
+//! into another bitmap with [Bitmap::blit], cut down to the size of a tile with
+//! [Bitmap::window], and turned into zone colors with [Bitmap::to_colors].
+//!
+//! [Bitmap::text] renders a string in a 5x7 font, and [Bitmap::scroll] slides a bitmap across a
+//! display one column at a time, which is enough for a scrolling banner:
+//!
+//! ```
+//! use lifx_core::bitmap::Bitmap;
+//! use lifx_core::HSBK;
+//!
+//! let text = Bitmap::text("Hello!");
+//! let on = HSBK::new(120.0, 100.0, 100.0, 3500);
+//! let off = HSBK::new(0.0, 0.0, 0.0, 3500);
+//! for frame in text.scroll(8, 8) {
+//!     let colors = frame.to_colors(on, off);
+//!     assert_eq!(colors.len(), 64);
+//!     // ... send the colors to the tile, and wait a little
+//! }
+//! ```
+//!
+//! For a chain of tiles side by side, take a window of the same frame at every tile's offset.
+
+use crate::HSBK;
+
+/// The width of a character in [Bitmap::text], not counting the column between characters
+pub const FONT_WIDTH: usize = 5;
+
+/// The height of a character in [Bitmap::text]
+pub const FONT_HEIGHT: usize = 7;
+
+/// The first character in [FONT]
+const FONT_FIRST: char = ' ';
+
+/// Printable ASCII, from space to `~`, in columns from the left.  The lowest bit of each column
+/// is the top pixel.
+const FONT: [[u8; FONT_WIDTH]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], // ' '
+    [0x00, 0x00, 0x5f, 0x00, 0x00], // !
+    [0x00, 0x07, 0x00, 0x07, 0x00], // "
+    [0x14, 0x7f, 0x14, 0x7f, 0x14], // #
+    [0x24, 0x2a, 0x7f, 0x2a, 0x12], // $
+    [0x23, 0x13, 0x08, 0x64, 0x62], // %
+    [0x36, 0x49, 0x55, 0x22, 0x50], // &
+    [0x00, 0x05, 0x03, 0x00, 0x00], // '
+    [0x00, 0x1c, 0x22, 0x41, 0x00], // (
+    [0x00, 0x41, 0x22, 0x1c, 0x00], // )
+    [0x14, 0x08, 0x3e, 0x08, 0x14], // *
+    [0x08, 0x08, 0x3e, 0x08, 0x08], // +
+    [0x00, 0x50, 0x30, 0x00, 0x00], // ,
+    [0x08, 0x08, 0x08, 0x08, 0x08], // -
+    [0x00, 0x60, 0x60, 0x00, 0x00], // .
+    [0x20, 0x10, 0x08, 0x04, 0x02], // /
+    [0x3e, 0x51, 0x49, 0x45, 0x3e], // 0
+    [0x00, 0x42, 0x7f, 0x40, 0x00], // 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], // 2
+    [0x21, 0x41, 0x45, 0x4b, 0x31], // 3
+    [0x18, 0x14, 0x12, 0x7f, 0x10], // 4
+    [0x27, 0x45, 0x45, 0x45, 0x39], // 5
+    [0x3c, 0x4a, 0x49, 0x49, 0x30], // 6
+    [0x01, 0x71, 0x09, 0x05, 0x03], // 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], // 8
+    [0x06, 0x49, 0x49, 0x29, 0x1e], // 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], // :
+    [0x00, 0x56, 0x36, 0x00, 0x00], // ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], // <
+    [0x14, 0x14, 0x14, 0x14, 0x14], // =
+    [0x00, 0x41, 0x22, 0x14, 0x08], // >
+    [0x02, 0x01, 0x51, 0x09, 0x06], // ?
+    [0x32, 0x49, 0x79, 0x41, 0x3e], // @
+    [0x7e, 0x11, 0x11, 0x11, 0x7e], // A
+    [0x7f, 0x49, 0x49, 0x49, 0x36], // B
+    [0x3e, 0x41, 0x41, 0x41, 0x22], // C
+    [0x7f, 0x41, 0x41, 0x22, 0x1c], // D
+    [0x7f, 0x49, 0x49, 0x49, 0x41], // E
+    [0x7f, 0x09, 0x09, 0x09, 0x01], // F
+    [0x3e, 0x41, 0x49, 0x49, 0x7a], // G
+    [0x7f, 0x08, 0x08, 0x08, 0x7f], // H
+    [0x00, 0x41, 0x7f, 0x41, 0x00], // I
+    [0x20, 0x40, 0x41, 0x3f, 0x01], // J
+    [0x7f, 0x08, 0x14, 0x22, 0x41], // K
+    [0x7f, 0x40, 0x40, 0x40, 0x40], // L
+    [0x7f, 0x02, 0x0c, 0x02, 0x7f], // M
+    [0x7f, 0x04, 0x08, 0x10, 0x7f], // N
+    [0x3e, 0x41, 0x41, 0x41, 0x3e], // O
+    [0x7f, 0x09, 0x09, 0x09, 0x06], // P
+    [0x3e, 0x41, 0x51, 0x21, 0x5e], // Q
+    [0x7f, 0x09, 0x19, 0x29, 0x46], // R
+    [0x46, 0x49, 0x49, 0x49, 0x31], // S
+    [0x01, 0x01, 0x7f, 0x01, 0x01], // T
+    [0x3f, 0x40, 0x40, 0x40, 0x3f], // U
+    [0x1f, 0x20, 0x40, 0x20, 0x1f], // V
+    [0x3f, 0x40, 0x38, 0x40, 0x3f], // W
+    [0x63, 0x14, 0x08, 0x14, 0x63], // X
+    [0x07, 0x08, 0x70, 0x08, 0x07], // Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], // Z
+    [0x00, 0x7f, 0x41, 0x41, 0x00], // [
+    [0x02, 0x04, 0x08, 0x10, 0x20], // \
+    [0x00, 0x41, 0x41, 0x7f, 0x00], // ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], // ^
+    [0x40, 0x40, 0x40, 0x40, 0x40], // _
+    [0x00, 0x01, 0x02, 0x04, 0x00], // `
+    [0x20, 0x54, 0x54, 0x54, 0x78], // a
+    [0x7f, 0x48, 0x44, 0x44, 0x38], // b
+    [0x38, 0x44, 0x44, 0x44, 0x20], // c
+    [0x38, 0x44, 0x44, 0x48, 0x7f], // d
+    [0x38, 0x54, 0x54, 0x54, 0x18], // e
+    [0x08, 0x7e, 0x09, 0x01, 0x02], // f
+    [0x0c, 0x52, 0x52, 0x52, 0x3e], // g
+    [0x7f, 0x08, 0x04, 0x04, 0x78], // h
+    [0x00, 0x44, 0x7d, 0x40, 0x00], // i
+    [0x20, 0x40, 0x44, 0x3d, 0x00], // j
+    [0x7f, 0x10, 0x28, 0x44, 0x00], // k
+    [0x00, 0x41, 0x7f, 0x40, 0x00], // l
+    [0x7c, 0x04, 0x18, 0x04, 0x78], // m
+    [0x7c, 0x08, 0x04, 0x04, 0x78], // n
+    [0x38, 0x44, 0x44, 0x44, 0x38], // o
+    [0x7c, 0x14, 0x14, 0x14, 0x08], // p
+    [0x08, 0x14, 0x14, 0x18, 0x7c], // q
+    [0x7c, 0x08, 0x04, 0x04, 0x08], // r
+    [0x48, 0x54, 0x54, 0x54, 0x20], // s
+    [0x04, 0x3f, 0x44, 0x40, 0x20], // t
+    [0x3c, 0x40, 0x40, 0x20, 0x7c], // u
+    [0x1c, 0x20, 0x40, 0x20, 0x1c], // v
+    [0x3c, 0x40, 0x30, 0x40, 0x3c], // w
+    [0x44, 0x28, 0x10, 0x28, 0x44], // x
+    [0x0c, 0x50, 0x50, 0x50, 0x3c], // y
+    [0x44, 0x64, 0x54, 0x4c, 0x44], // z
+    [0x00, 0x08, 0x36, 0x41, 0x00], // {
+    [0x00, 0x00, 0x7f, 0x00, 0x00], // |
+    [0x00, 0x41, 0x36, 0x08, 0x00], // }
+    [0x10, 0x08, 0x08, 0x10, 0x08], // ~
+];
+
+/// The columns of a character, or of `?` if it isn't in the font
+fn glyph(c: char) -> &'static [u8; FONT_WIDTH] {
+    let index = (c as usize).wrapping_sub(FONT_FIRST as usize);
+    FONT.get(index)
+        .unwrap_or(&FONT[('?' as usize) - (FONT_FIRST as usize)])
+}
+
+/// A grid of pixels that are each either on or off
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Bitmap {
+    width: usize,
+    height: usize,
+    /// One row after another, from the top left
+    pixels: Vec<bool>,
+}
+
+impl Bitmap {
+    /// A bitmap with every pixel off
+    pub fn new(width: usize, height: usize) -> Bitmap {
+        Bitmap {
+            width,
+            height,
+            pixels: vec![false; width * height],
+        }
+    }
+
+    /// Draws a bitmap from rows of text, where `#` is on and anything else is off
+    ///
+    /// This is meant for icons written out in the source.  The bitmap is as wide as the longest
+    /// row.
+    ///
+    /// ```
+    /// use lifx_core::bitmap::Bitmap;
+    ///
+    /// let arrow = Bitmap::from_rows(&["..#..", ".###.", "#####"]);
+    /// assert_eq!((arrow.width(), arrow.height()), (5, 3));
+    /// assert!(arrow.get(2, 0) && !arrow.get(1, 0));
+    /// ```
+    pub fn from_rows(rows: &[&str]) -> Bitmap {
+        let width = rows.iter().map(|row| row.chars().count()).max();
+        let mut bitmap = Bitmap::new(width.unwrap_or(0), rows.len());
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                bitmap.set(x, y, c == '#');
+            }
+        }
+        bitmap
+    }
+
+    /// Renders a line of text in the built-in 5x7 font
+    ///
+    /// The bitmap is [FONT_HEIGHT] pixels high, with an empty column between characters.
+    /// Characters outside of printable ASCII are drawn as `?`.
+    pub fn text(text: &str) -> Bitmap {
+        let chars = text.chars().count();
+        let width = (chars * (FONT_WIDTH + 1)).saturating_sub(1);
+        let mut bitmap = Bitmap::new(width, FONT_HEIGHT);
+        for (i, c) in text.chars().enumerate() {
+            for (dx, column) in glyph(c).iter().enumerate() {
+                for y in 0..FONT_HEIGHT {
+                    bitmap.set(i * (FONT_WIDTH + 1) + dx, y, column & (1 << y) != 0);
+                }
+            }
+        }
+        bitmap
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    /// Whether a pixel is on (pixels outside of the bitmap are off)
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        x < self.width && y < self.height && self.pixels[y * self.width + x]
+    }
+
+    /// Turns a pixel on or off
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pixel is outside of the bitmap.
+    pub fn set(&mut self, x: usize, y: usize, on: bool) {
+        assert!(
+            x < self.width && y < self.height,
+            "pixel ({}, {}) is outside of a {}x{} bitmap",
+            x,
+            y,
+            self.width,
+            self.height
+        );
+        self.pixels[y * self.width + x] = on;
+    }
+
+    /// Draws another bitmap on top of this one, with its top left corner at `(x, y)`
+    ///
+    /// Only the pixels that are on in `src` are drawn, so what's underneath shows through.  The
+    /// position can be negative, or past the edge, and anything that doesn't fit is left out.
+    pub fn blit(&mut self, src: &Bitmap, x: isize, y: isize) {
+        for sy in 0..src.height {
+            for sx in 0..src.width {
+                if !src.get(sx, sy) {
+                    continue;
+                }
+                let (dx, dy) = (x + sx as isize, y + sy as isize);
+                if (0..self.width as isize).contains(&dx) && (0..self.height as isize).contains(&dy)
+                {
+                    self.set(dx as usize, dy as usize, true);
+                }
+            }
+        }
+    }
+
+    /// The part of this bitmap in a `width` by `height` window with its top left corner at
+    /// `(x, y)`
+    ///
+    /// Any of the window that's outside of this bitmap is off.
+    pub fn window(&self, x: isize, y: isize, width: usize, height: usize) -> Bitmap {
+        let mut window = Bitmap::new(width, height);
+        window.blit(self, -x, -y);
+        window
+    }
+
+    /// The frames of this bitmap scrolling right to left across a `width` by `height` display
+    ///
+    /// The first frame has the bitmap's first column at the right edge of the display, and the
+    /// last has its last column at the left edge, so it starts and finishes off the screen.  The
+    /// bitmap is vertically centered (or as near as it can be).
+    pub fn scroll(&self, width: usize, height: usize) -> impl Iterator<Item = Bitmap> + '_ {
+        let y = (self.height as isize - height as isize) / 2;
+        let start = -(width as isize) + 1;
+        (start..self.width as isize).map(move |x| self.window(x, y, width, height))
+    }
+
+    /// The color of every pixel, one row after another from the top left, which is the order that
+    /// matrix lights take their zone colors in
+    pub fn to_colors(&self, on: HSBK, off: HSBK) -> Vec<HSBK> {
+        self.pixels
+            .iter()
+            .map(|&pixel| if pixel { on } else { off })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(bitmap: &Bitmap) -> Vec<String> {
+        (0..bitmap.height())
+            .map(|y| {
+                (0..bitmap.width())
+                    .map(|x| if bitmap.get(x, y) { '#' } else { '.' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_text() {
+        let text = Bitmap::text("Hi!");
+        assert_eq!(
+            rows(&text),
+            vec![
+                "#...#...#.....#..",
+                "#...#.........#..",
+                "#...#..##.....#..",
+                "#####...#.....#..",
+                "#...#...#.....#..",
+                "#...#...#........",
+                "#...#..###....#..",
+            ]
+        );
+        assert_eq!(Bitmap::text("").width(), 0);
+        // anything that isn't printable ASCII is a question mark
+        assert_eq!(Bitmap::text("é\n"), Bitmap::text("??"));
+    }
+
+    #[test]
+    fn test_blit() {
+        let square = Bitmap::from_rows(&["##", "##"]);
+        let mut canvas = Bitmap::new(3, 3);
+        canvas.set(0, 2, true);
+        canvas.blit(&square, 2, -1);
+        canvas.blit(&Bitmap::new(3, 3), 0, 0);
+        assert_eq!(rows(&canvas), vec!["..#", "...", "#.."]);
+        canvas.blit(&square, 5, 5);
+        assert_eq!(rows(&canvas), vec!["..#", "...", "#.."]);
+
+        assert_eq!(rows(&canvas.window(1, -1, 2, 2)), vec!["..", ".#"]);
+        assert_eq!(Bitmap::from_rows(&["#", "..#"]).width(), 3);
+    }
+
+    #[test]
+    fn test_scroll() {
+        let dot = Bitmap::from_rows(&["#"]);
+        let frames: Vec<_> = dot.scroll(3, 3).map(|frame| rows(&frame)).collect();
+        assert_eq!(
+            frames,
+            vec![
+                vec!["...", "..#", "..."],
+                vec!["...", ".#.", "..."],
+                vec!["...", "#..", "..."],
+            ]
+        );
+
+        // text on a tile, with a row to spare at the bottom
+        let text = Bitmap::text("Hi");
+        let frames: Vec<_> = text.scroll(8, 8).collect();
+        assert_eq!(frames.len(), 8 + text.width() - 1);
+        assert_eq!(frames[7], text.window(0, 0, 8, 8));
+        assert!(!frames[7].get(0, 7));
+    }
+
+    #[test]
+    fn test_to_colors() {
+        let on = HSBK::new(0.0, 100.0, 100.0, 3500);
+        let off = HSBK::new(0.0, 0.0, 0.0, 3500);
+        let bitmap = Bitmap::from_rows(&["#.", ".#", "##"]);
+        assert_eq!(bitmap.to_colors(on, off), vec![on, off, off, on, on, on]);
+    }
+}
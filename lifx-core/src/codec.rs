@@ -0,0 +1,329 @@
+//! Reading and writing the fields of message payloads
+//!
+//! Payload fields are packed one after another, little-endian, with no padding.  A
+//! [PayloadWriter] appends fields to a buffer, and a [PayloadReader] reads them back from a slice,
+//! keeping track of the offset so that decode errors can say where they happened.  Each kind of
+//! field has its own method, so the method names in a message's encode and decode code read like
+//! the field list in the LIFX docs.
+
+use crate::{
+    EchoPayload, LastHevCycleResult, LifxIdent, LifxString, MultiZoneEffectType, PowerLevel,
+    TileEffectType, Waveform, HSBK,
+};
+use std::convert::TryInto;
+use std::ffi::CString;
+use std::io;
+use std::num::NonZeroU8;
+
+/// Appends payload fields to a buffer
+pub(crate) struct PayloadWriter<'a> {
+    buf: &'a mut Vec<u8>,
+}
+
+impl<'a> PayloadWriter<'a> {
+    pub(crate) fn new(buf: &'a mut Vec<u8>) -> PayloadWriter<'a> {
+        PayloadWriter { buf }
+    }
+
+    pub(crate) fn put_u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    pub(crate) fn put_bool(&mut self, v: bool) {
+        self.put_u8(v as u8);
+    }
+
+    pub(crate) fn put_u16(&mut self, v: u16) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub(crate) fn put_i16(&mut self, v: i16) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub(crate) fn put_u32(&mut self, v: u32) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    pub(crate) fn put_u64(&mut self, v: u64) {
+        self.buf.extend_from_slice(&v.to_le_bytes());
+    }
+
+    /// Takes anything that converts to `f32`, since float fields are wrapped in a
+    /// `ComparableFloat` when fuzzing
+    pub(crate) fn put_f32(&mut self, v: impl Into<f32>) {
+        self.buf.extend_from_slice(&v.into().to_le_bytes());
+    }
+
+    pub(crate) fn put_bytes(&mut self, v: &[u8]) {
+        self.buf.extend_from_slice(v);
+    }
+
+    pub(crate) fn put_u32s(&mut self, v: &[u32]) {
+        for &x in v {
+            self.put_u32(x);
+        }
+    }
+
+    pub(crate) fn put_hsbk(&mut self, v: HSBK) {
+        self.put_u16(v.hue);
+        self.put_u16(v.saturation);
+        self.put_u16(v.brightness);
+        self.put_u16(v.kelvin);
+    }
+
+    pub(crate) fn put_hsbks(&mut self, v: &[HSBK]) {
+        for &color in v {
+            self.put_hsbk(color);
+        }
+    }
+
+    /// Writes a 32 byte string field, padded with nulls
+    pub(crate) fn put_string(&mut self, v: &LifxString) {
+        let mut bytes = [0; 32];
+        let s = v.0.to_bytes();
+        let len = s.len().min(32);
+        bytes[..len].copy_from_slice(&s[..len]);
+        self.put_bytes(&bytes);
+    }
+
+    pub(crate) fn put_ident(&mut self, v: &LifxIdent) {
+        self.put_bytes(&v.0);
+    }
+
+    pub(crate) fn put_echo(&mut self, v: &EchoPayload) {
+        self.put_bytes(&v.0);
+    }
+}
+
+/// Reads payload fields from a slice, in order
+pub(crate) struct PayloadReader<'a> {
+    buf: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> PayloadReader<'a> {
+    pub(crate) fn new(buf: &'a [u8]) -> PayloadReader<'a> {
+        PayloadReader { buf, offset: 0 }
+    }
+
+    /// How many bytes have been read so far
+    pub(crate) fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Reads a field of any type that can be decoded
+    ///
+    /// This is for the `unpack!` macro, which only knows each field's type; code that's written
+    /// by hand should use the method for the field's type.
+    pub(crate) fn get<T: Decode>(&mut self) -> io::Result<T> {
+        T::decode(self)
+    }
+
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        let end = self.offset + N;
+        let bytes = self
+            .buf
+            .get(self.offset..end)
+            .ok_or_else(|| io::Error::from(io::ErrorKind::UnexpectedEof))?;
+        self.offset = end;
+        Ok(bytes.try_into().unwrap())
+    }
+
+    pub(crate) fn get_u8(&mut self) -> io::Result<u8> {
+        Ok(self.take::<1>()?[0])
+    }
+
+    pub(crate) fn get_bool(&mut self) -> io::Result<bool> {
+        Ok(self.get_u8()? > 0)
+    }
+
+    pub(crate) fn get_u16(&mut self) -> io::Result<u16> {
+        self.take().map(u16::from_le_bytes)
+    }
+
+    pub(crate) fn get_i16(&mut self) -> io::Result<i16> {
+        self.take().map(i16::from_le_bytes)
+    }
+
+    pub(crate) fn get_u32(&mut self) -> io::Result<u32> {
+        self.take().map(u32::from_le_bytes)
+    }
+
+    pub(crate) fn get_u64(&mut self) -> io::Result<u64> {
+        self.take().map(u64::from_le_bytes)
+    }
+
+    pub(crate) fn get_f32(&mut self) -> io::Result<f32> {
+        self.take().map(f32::from_le_bytes)
+    }
+
+    pub(crate) fn get_bytes<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        self.take()
+    }
+
+    pub(crate) fn get_u32s<const N: usize>(&mut self) -> io::Result<[u32; N]> {
+        let mut data = [0; N];
+        for x in &mut data {
+            *x = self.get_u32()?;
+        }
+        Ok(data)
+    }
+
+    pub(crate) fn get_hsbk(&mut self) -> io::Result<HSBK> {
+        Ok(HSBK {
+            hue: self.get_u16()?,
+            saturation: self.get_u16()?,
+            brightness: self.get_u16()?,
+            kelvin: self.get_u16()?,
+        })
+    }
+
+    pub(crate) fn get_hsbks<const N: usize>(&mut self) -> io::Result<[HSBK; N]> {
+        let mut data = [HSBK {
+            hue: 0,
+            saturation: 0,
+            brightness: 0,
+            kelvin: 0,
+        }; N];
+        for x in &mut data {
+            *x = self.get_hsbk()?;
+        }
+        Ok(data)
+    }
+
+    /// Reads a 32 byte string field, dropping any nulls
+    pub(crate) fn get_string(&mut self) -> io::Result<LifxString> {
+        let bytes = self.get_bytes::<32>()?;
+        // the last byte is always treated as the null terminator
+        let bytes: Vec<_> = bytes[..31]
+            .iter()
+            .copied()
+            .filter_map(NonZeroU8::new)
+            .collect();
+        Ok(LifxString(CString::from(bytes)))
+    }
+
+    pub(crate) fn get_ident(&mut self) -> io::Result<LifxIdent> {
+        self.get_bytes().map(LifxIdent)
+    }
+
+    pub(crate) fn get_echo(&mut self) -> io::Result<EchoPayload> {
+        self.get_bytes().map(EchoPayload)
+    }
+
+    pub(crate) fn get_power(&mut self) -> io::Result<PowerLevel> {
+        Ok(match self.get_u16()? {
+            0 => PowerLevel::Standby,
+            _ => PowerLevel::Enabled,
+        })
+    }
+}
+
+/// A field type that a [PayloadReader] can read
+pub(crate) trait Decode: Sized {
+    fn decode(r: &mut PayloadReader<'_>) -> io::Result<Self>;
+}
+
+macro_rules! decode_with {
+    { $( $t:ty => $m:ident ),* $(,)? } => {
+        $(
+            impl Decode for $t {
+                fn decode(r: &mut PayloadReader<'_>) -> io::Result<$t> {
+                    r.$m()
+                }
+            }
+        )*
+    }
+}
+
+decode_with! {
+    u8 => get_u8,
+    bool => get_bool,
+    u16 => get_u16,
+    i16 => get_i16,
+    u32 => get_u32,
+    u64 => get_u64,
+    f32 => get_f32,
+    [u8; 32] => get_bytes,
+    [u32; 8] => get_u32s,
+    HSBK => get_hsbk,
+    LifxString => get_string,
+    LifxIdent => get_ident,
+    EchoPayload => get_echo,
+    PowerLevel => get_power,
+}
+
+/// Enums whose unknown values are kept in an `Unknown(u8)` variant
+macro_rules! decode_from_u8 {
+    { $( $t:ty ),* } => {
+        $(
+            impl Decode for $t {
+                fn decode(r: &mut PayloadReader<'_>) -> io::Result<$t> {
+                    r.get_u8().map(<$t>::from)
+                }
+            }
+        )*
+    }
+}
+
+decode_from_u8! { Waveform, LastHevCycleResult, MultiZoneEffectType, TileEffectType }
+
+impl<const N: usize> Decode for [HSBK; N] {
+    fn decode(r: &mut PayloadReader<'_>) -> io::Result<[HSBK; N]> {
+        r.get_hsbks()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let mut buf = Vec::new();
+        let mut w = PayloadWriter::new(&mut buf);
+        w.put_u8(7);
+        w.put_u16(0x1234);
+        w.put_i16(-2);
+        w.put_u32(0xdeadbeef);
+        w.put_u64(u64::MAX - 1);
+        w.put_f32(1.5);
+        w.put_bool(true);
+        w.put_hsbk(HSBK::new(120.0, 50.0, 25.0, 3500));
+        w.put_string(&LifxString::new(&CString::new("Kitchen").unwrap()));
+        assert_eq!(buf.len(), 1 + 2 + 2 + 4 + 8 + 4 + 1 + 8 + 32);
+        assert_eq!(&buf[1..3], &[0x34, 0x12]);
+
+        let mut r = PayloadReader::new(&buf);
+        assert_eq!(r.get_u8().unwrap(), 7);
+        assert_eq!(r.get_u16().unwrap(), 0x1234);
+        assert_eq!(r.get_i16().unwrap(), -2);
+        assert_eq!(r.get_u32().unwrap(), 0xdeadbeef);
+        assert_eq!(r.get_u64().unwrap(), u64::MAX - 1);
+        assert_eq!(r.get_f32().unwrap(), 1.5);
+        assert!(r.get_bool().unwrap());
+        assert_eq!(r.get_hsbk().unwrap(), HSBK::new(120.0, 50.0, 25.0, 3500));
+        assert_eq!(r.offset(), 30);
+        assert_eq!(r.get_string().unwrap().to_string(), "Kitchen");
+
+        // running out doesn't move the offset
+        let err = r.get_u16().unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        assert_eq!(r.offset(), buf.len());
+    }
+
+    #[test]
+    fn test_enums() {
+        let buf = [9, 255, 0, 0, 0, 2];
+        let mut r = PayloadReader::new(&buf);
+        assert_eq!(r.get::<Waveform>().unwrap(), Waveform::Unknown(9));
+        assert_eq!(
+            r.get::<LastHevCycleResult>().unwrap(),
+            LastHevCycleResult::None
+        );
+        assert_eq!(r.get_power().unwrap(), PowerLevel::Standby);
+        assert_eq!(r.get_u8().unwrap(), 0);
+        assert_eq!(r.get::<TileEffectType>().unwrap(), TileEffectType::Morph);
+    }
+}
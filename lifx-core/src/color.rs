@@ -0,0 +1,667 @@
+//! Parsing of human-friendly color strings into [HSBK] values
+//!
+//! The accepted syntax is a superset of the color strings used by the LIFX HTTP API
+//! (<https://api.developer.lifx.com/docs/colors>).  A color string is made up of one or more
+//! whitespace-separated components, which are applied in order:
+//!
+//! * A color name: `white`, `red`, `orange`, `yellow`, `cyan`, `green`, `blue`, `purple`, `pink`
+//! * `hue:[0-360]`, `saturation:[0.0-1.0]`, `brightness:[0.0-1.0]`, `kelvin:[1500-9000]`
+//! * `rgb:[0-255],[0-255],[0-255]` or `#RRGGBB`
+//! * `hsb(210, 50%, 80%)`, where saturation and brightness can be given either as a percentage or
+//!   as a fraction between 0 and 1
+//! * `3500K`, which selects a white with the given color temperature
+//! * `80%`, which sets the brightness
+//!
+//! For example, `"red brightness:0.5"`, `"#ff8800"`, and `"3500K 80%"` are all valid.
+//!
+//! [NormalizedHSBK] is the same color in floating point units (hue in degrees, saturation and
+//! brightness between 0 and 1), as used by the HTTP API and most color pickers.
+//!
+//! # Perceptual brightness
+//!
+//! Devices scale their light output linearly with [HSBK::brightness], but the eye doesn't see it
+//! that way: going from 1% to 2% looks like a much bigger step than going from 50% to 51%.
+//! [BrightnessCurve] maps positions on a slider (or steps in a fade) to brightness values that look
+//! evenly spaced.
+
+use crate::{Error, HSBK};
+use std::str::FromStr;
+
+/// The base color that [HSBK::from_str] applies color strings to: a full brightness neutral white.
+const DEFAULT_BASE: HSBK = HSBK {
+    hue: 0,
+    saturation: 0,
+    brightness: 65535,
+    kelvin: 3500,
+};
+
+const KELVIN_MIN: u16 = 1500;
+const KELVIN_MAX: u16 = 9000;
+
+/// Named colors, with their hue in degrees (these all have full saturation, except white)
+const NAMED_COLORS: &[(&str, f32)] = &[
+    ("red", 0.0),
+    ("orange", 36.0),
+    ("yellow", 60.0),
+    ("green", 120.0),
+    ("cyan", 180.0),
+    ("blue", 250.0),
+    ("purple", 280.0),
+    ("pink", 325.0),
+];
+
+fn invalid(s: &str, reason: &str) -> Error {
+    Error::InvalidColor(format!("{}: {}", s, reason))
+}
+
+fn degrees_to_hue(deg: f32) -> u16 {
+    (deg / 360.0 * 65535.0).round() as u16
+}
+
+fn fraction_to_u16(f: f32) -> u16 {
+    (f * 65535.0).round() as u16
+}
+
+fn parse_num(s: &str, token: &str) -> Result<f32, Error> {
+    s.trim()
+        .parse::<f32>()
+        .ok()
+        .filter(|f| f.is_finite())
+        .ok_or_else(|| invalid(token, "not a number"))
+}
+
+fn parse_degrees(s: &str, token: &str) -> Result<u16, Error> {
+    let deg = parse_num(s, token)?;
+    if !(0.0..=360.0).contains(&deg) {
+        return Err(invalid(token, "hue must be between 0 and 360"));
+    }
+    Ok(degrees_to_hue(deg))
+}
+
+/// Parses a fraction between 0 and 1
+fn parse_fraction(s: &str, token: &str) -> Result<u16, Error> {
+    let f = parse_num(s, token)?;
+    if !(0.0..=1.0).contains(&f) {
+        return Err(invalid(token, "value must be between 0.0 and 1.0"));
+    }
+    Ok(fraction_to_u16(f))
+}
+
+/// Parses either a percentage (`"50%"`) or a fraction between 0 and 1 (`"0.5"`)
+fn parse_percent_or_fraction(s: &str, token: &str) -> Result<u16, Error> {
+    let s = s.trim();
+    match s.strip_suffix('%') {
+        Some(pct) => {
+            let f = parse_num(pct, token)?;
+            if !(0.0..=100.0).contains(&f) {
+                return Err(invalid(token, "percentage must be between 0 and 100"));
+            }
+            Ok(fraction_to_u16(f / 100.0))
+        }
+        None => parse_fraction(s, token),
+    }
+}
+
+fn parse_kelvin(s: &str, token: &str) -> Result<u16, Error> {
+    let k: u16 = s
+        .trim()
+        .parse()
+        .map_err(|_| invalid(token, "not a valid kelvin value"))?;
+    if !(KELVIN_MIN..=KELVIN_MAX).contains(&k) {
+        return Err(invalid(token, "kelvin must be between 1500 and 9000"));
+    }
+    Ok(k)
+}
+
+/// Converts RGB (each 0-255) into hue, saturation, and brightness, leaving kelvin unchanged
+fn apply_rgb(color: &mut HSBK, r: u8, g: u8, b: u8) {
+    let r = r as f32 / 255.0;
+    let g = g as f32 / 255.0;
+    let b = b as f32 / 255.0;
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let delta = max - min;
+
+    let hue = if delta == 0.0 {
+        0.0
+    } else if max == r {
+        60.0 * (((g - b) / delta) % 6.0)
+    } else if max == g {
+        60.0 * ((b - r) / delta + 2.0)
+    } else {
+        60.0 * ((r - g) / delta + 4.0)
+    };
+    let hue = if hue < 0.0 { hue + 360.0 } else { hue };
+
+    color.hue = degrees_to_hue(hue);
+    color.saturation = if max == 0.0 {
+        0
+    } else {
+        fraction_to_u16(delta / max)
+    };
+    color.brightness = fraction_to_u16(max);
+}
+
+fn apply_token(color: &mut HSBK, token: &str) -> Result<(), Error> {
+    let lower = token.to_ascii_lowercase();
+
+    if lower == "white" {
+        color.saturation = 0;
+        return Ok(());
+    }
+    if let Some((_, deg)) = NAMED_COLORS.iter().find(|(name, _)| *name == lower) {
+        color.hue = degrees_to_hue(*deg);
+        color.saturation = 65535;
+        return Ok(());
+    }
+
+    if let Some((key, val)) = lower.split_once(':') {
+        match key {
+            "hue" => color.hue = parse_degrees(val, token)?,
+            "saturation" => color.saturation = parse_fraction(val, token)?,
+            "brightness" => color.brightness = parse_fraction(val, token)?,
+            "kelvin" => color.kelvin = parse_kelvin(val, token)?,
+            "rgb" => {
+                let parts: Vec<u8> = val
+                    .split(',')
+                    .map(|p| p.trim().parse::<u8>())
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| invalid(token, "rgb components must be between 0 and 255"))?;
+                match parts[..] {
+                    [r, g, b] => apply_rgb(color, r, g, b),
+                    _ => return Err(invalid(token, "expected three rgb components")),
+                }
+            }
+            _ => return Err(invalid(token, "unknown color component")),
+        }
+        return Ok(());
+    }
+
+    if let Some(hex) = lower.strip_prefix('#') {
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid(token, "expected a hex color like #ff8800"));
+        }
+        let v = u32::from_str_radix(hex, 16).map_err(|_| invalid(token, "bad hex color"))?;
+        apply_rgb(color, (v >> 16) as u8, (v >> 8) as u8, v as u8);
+        return Ok(());
+    }
+
+    if let Some(args) = lower
+        .strip_prefix("hsb(")
+        .and_then(|rest| rest.strip_suffix(')'))
+    {
+        match args.split(',').collect::<Vec<_>>()[..] {
+            [h, s, b] => {
+                color.hue = parse_degrees(h, token)?;
+                color.saturation = parse_percent_or_fraction(s, token)?;
+                color.brightness = parse_percent_or_fraction(b, token)?;
+            }
+            _ => return Err(invalid(token, "expected hsb(hue, saturation, brightness)")),
+        }
+        return Ok(());
+    }
+
+    if let Some(k) = lower.strip_suffix('k') {
+        color.kelvin = parse_kelvin(k, token)?;
+        color.saturation = 0;
+        return Ok(());
+    }
+
+    if lower.ends_with('%') {
+        color.brightness = parse_percent_or_fraction(&lower, token)?;
+        return Ok(());
+    }
+
+    Err(invalid(token, "unknown color component"))
+}
+
+impl HSBK {
+    /// Parses a color string (see the [color](crate::color) module docs), applying it on top of
+    /// the given base color.
+    ///
+    /// Any component not mentioned in the color string keeps its value from `base`, which mirrors
+    /// how the LIFX HTTP API leaves unspecified components unchanged.
+    pub fn from_str_with_base(s: &str, base: HSBK) -> Result<HSBK, Error> {
+        // hsb(...) may contain spaces between its arguments, so drop any whitespace inside parens
+        let mut depth = 0;
+        let mut normalized = String::with_capacity(s.len());
+        for c in s.chars() {
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 0 => return Err(invalid(s, "unmatched `)`")),
+                ')' => depth -= 1,
+                _ => (),
+            }
+            if depth == 0 || !c.is_whitespace() {
+                normalized.push(c);
+            }
+        }
+        let mut tokens = normalized.split_whitespace().peekable();
+        if tokens.peek().is_none() {
+            return Err(Error::InvalidColor("empty color string".to_owned()));
+        }
+        let mut color = base;
+        for token in tokens {
+            apply_token(&mut color, token)?;
+        }
+        Ok(color)
+    }
+}
+
+/// A color in the units used by the LIFX HTTP API and most UIs
+///
+/// Converting an [HSBK] to this and back always gives the original [HSBK], so it's safe to use
+/// this as the editable representation of a device's color.
+///
+/// ```
+/// use lifx_core::color::NormalizedHSBK;
+/// use lifx_core::HSBK;
+///
+/// let color: HSBK = "hsb(210, 50%, 80%)".parse().unwrap();
+/// let normalized = color.to_normalized();
+/// assert!((normalized.hue - 210.0).abs() < 0.01);
+/// assert!((normalized.saturation - 0.5).abs() < 0.0001);
+/// assert_eq!(HSBK::from_normalized(normalized), color);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct NormalizedHSBK {
+    /// Degrees, from 0 to 360
+    pub hue: f32,
+    /// From 0 to 1
+    pub saturation: f32,
+    /// From 0 to 1
+    pub brightness: f32,
+    /// Degrees kelvin, exactly as in [HSBK::kelvin]
+    pub kelvin: u16,
+}
+
+/// Scales a u16 to a fraction of `max`
+///
+/// This is done in f64 and rounded to the nearest f32, so that [from_scaled] always gets back the
+/// same u16.
+fn to_scaled(v: u16, max: f64) -> f32 {
+    (v as f64 / 65535.0 * max) as f32
+}
+
+fn from_scaled(v: f32, max: f64) -> u16 {
+    (v as f64 / max * 65535.0).round().clamp(0.0, 65535.0) as u16
+}
+
+impl HSBK {
+    /// Converts to hue in degrees, and saturation and brightness between 0 and 1
+    pub fn to_normalized(&self) -> NormalizedHSBK {
+        NormalizedHSBK {
+            hue: to_scaled(self.hue, 360.0),
+            saturation: to_scaled(self.saturation, 1.0),
+            brightness: to_scaled(self.brightness, 1.0),
+            kelvin: self.kelvin,
+        }
+    }
+
+    /// Converts from hue in degrees, and saturation and brightness between 0 and 1
+    ///
+    /// Hues outside of 0-360 wrap around, saturation and brightness are clamped to 0-1, and NaNs
+    /// become zero.
+    pub fn from_normalized(color: NormalizedHSBK) -> HSBK {
+        let hue = match color.hue {
+            h if (0.0..=360.0).contains(&h) => h,
+            h if h.is_finite() => h.rem_euclid(360.0),
+            _ => 0.0,
+        };
+        HSBK {
+            hue: from_scaled(hue, 360.0),
+            saturation: from_scaled(color.saturation, 1.0),
+            brightness: from_scaled(color.brightness, 1.0),
+            kelvin: color.kelvin,
+        }
+    }
+}
+
+impl HSBK {
+    /// Makes a color from hue in degrees, saturation and brightness as percentages, and kelvin
+    ///
+    /// Hues outside of 0-360 wrap around, saturation and brightness are clamped to 0-100, kelvin is
+    /// clamped to 1500-9000, and NaNs become zero.  Use [HSBK::try_new] to reject values that are
+    /// out of range instead.
+    ///
+    /// ```
+    /// use lifx_core::HSBK;
+    ///
+    /// let color = HSBK::new(120.0, 100.0, 50.0, 3500);
+    /// assert_eq!(color.hue, 21845);
+    /// assert_eq!(color.saturation, 65535);
+    /// assert_eq!(color.brightness, 32768);
+    /// assert_eq!(color.hue_degrees(), 120.0);
+    /// assert_eq!(HSBK::new(-240.0, 150.0, 50.0, 1000), HSBK::new(120.0, 100.0, 50.0, 1500));
+    /// ```
+    pub fn new(hue_degrees: f32, saturation_pct: f32, brightness_pct: f32, kelvin: u16) -> HSBK {
+        HSBK::from_normalized(NormalizedHSBK {
+            hue: hue_degrees,
+            saturation: saturation_pct / 100.0,
+            brightness: brightness_pct / 100.0,
+            kelvin: kelvin.clamp(KELVIN_MIN, KELVIN_MAX),
+        })
+    }
+
+    /// Like [HSBK::new], but returns [Error::InvalidColor] if any value is out of range (or NaN)
+    pub fn try_new(
+        hue_degrees: f32,
+        saturation_pct: f32,
+        brightness_pct: f32,
+        kelvin: u16,
+    ) -> Result<HSBK, Error> {
+        let check = |name, value: f32, max| {
+            if (0.0..=max).contains(&value) {
+                Ok(())
+            } else {
+                Err(Error::InvalidColor(format!(
+                    "{} must be between 0 and {}, not {}",
+                    name, max, value
+                )))
+            }
+        };
+        check("hue", hue_degrees, 360.0)?;
+        check("saturation", saturation_pct, 100.0)?;
+        check("brightness", brightness_pct, 100.0)?;
+        if !(KELVIN_MIN..=KELVIN_MAX).contains(&kelvin) {
+            return Err(Error::InvalidColor(format!(
+                "kelvin must be between {} and {}, not {}",
+                KELVIN_MIN, KELVIN_MAX, kelvin
+            )));
+        }
+        Ok(HSBK::new(
+            hue_degrees,
+            saturation_pct,
+            brightness_pct,
+            kelvin,
+        ))
+    }
+
+    /// The hue in degrees, from 0 to 360
+    pub fn hue_degrees(&self) -> f32 {
+        to_scaled(self.hue, 360.0)
+    }
+
+    /// The saturation as a percentage, from 0 to 100
+    pub fn saturation_pct(&self) -> f32 {
+        to_scaled(self.saturation, 100.0)
+    }
+
+    /// The brightness as a percentage, from 0 to 100
+    pub fn brightness_pct(&self) -> f32 {
+        to_scaled(self.brightness, 100.0)
+    }
+}
+
+impl From<HSBK> for NormalizedHSBK {
+    fn from(color: HSBK) -> NormalizedHSBK {
+        color.to_normalized()
+    }
+}
+
+impl From<NormalizedHSBK> for HSBK {
+    fn from(color: NormalizedHSBK) -> HSBK {
+        HSBK::from_normalized(color)
+    }
+}
+
+/// How positions on a brightness slider map to [HSBK::brightness]
+///
+/// ```
+/// use lifx_core::color::BrightnessCurve;
+///
+/// let curve = BrightnessCurve::default();
+/// // half way along the slider is well under half of the device's light output
+/// assert!(curve.to_brightness(0.5) < 65535 / 4);
+/// assert_eq!(curve.to_brightness(1.0), 65535);
+/// assert!((curve.from_brightness(curve.to_brightness(0.3)) - 0.3).abs() < 0.001);
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Default)]
+pub enum BrightnessCurve {
+    /// Positions map directly to brightness, like the raw protocol
+    Linear,
+    /// Brightness is the position raised to this power (2.2 is a common choice)
+    Gamma(f32),
+    /// The CIE 1976 lightness curve (L*), which is designed so that equal steps look equal
+    #[default]
+    CieLightness,
+}
+
+impl BrightnessCurve {
+    /// Converts a position between 0 and 1 to a brightness
+    ///
+    /// Positions outside of 0-1 are clamped, and NaN is treated as zero.
+    pub fn to_brightness(&self, position: f32) -> u16 {
+        let p = if position.is_nan() {
+            0.0
+        } else {
+            (position as f64).clamp(0.0, 1.0)
+        };
+        let linear = match *self {
+            BrightnessCurve::Linear => p,
+            BrightnessCurve::Gamma(gamma) => p.powf(gamma as f64),
+            BrightnessCurve::CieLightness => {
+                let l = p * 100.0;
+                if l > 8.0 {
+                    ((l + 16.0) / 116.0).powi(3)
+                } else {
+                    l / CIE_KAPPA
+                }
+            }
+        };
+        (linear * 65535.0).round().clamp(0.0, 65535.0) as u16
+    }
+
+    /// Converts a brightness to a position between 0 and 1 (the inverse of
+    /// [to_brightness](BrightnessCurve::to_brightness))
+    pub fn from_brightness(&self, brightness: u16) -> f32 {
+        let linear = brightness as f64 / 65535.0;
+        let p = match *self {
+            BrightnessCurve::Linear => linear,
+            BrightnessCurve::Gamma(gamma) => linear.powf(1.0 / gamma as f64),
+            BrightnessCurve::CieLightness => {
+                if linear > CIE_EPSILON {
+                    (116.0 * linear.cbrt() - 16.0) / 100.0
+                } else {
+                    linear * CIE_KAPPA / 100.0
+                }
+            }
+        };
+        p.clamp(0.0, 1.0) as f32
+    }
+}
+
+/// Constants from the CIE L* definition (as exact fractions, so that both halves of the curve meet)
+const CIE_EPSILON: f64 = 216.0 / 24389.0;
+const CIE_KAPPA: f64 = 24389.0 / 27.0;
+
+/// Parses a color string, applied on top of a full brightness neutral (3500K) white.
+///
+/// See the [color](crate::color) module docs for the accepted syntax.
+impl FromStr for HSBK {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<HSBK, Error> {
+        HSBK::from_str_with_base(s, DEFAULT_BASE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_named() {
+        let red: HSBK = "red".parse().unwrap();
+        assert_eq!(red.hue, 0);
+        assert_eq!(red.saturation, 65535);
+        assert_eq!(red.brightness, 65535);
+
+        let white: HSBK = "White brightness:0.5".parse().unwrap();
+        assert_eq!(white.saturation, 0);
+        assert_eq!(white.brightness, 32768);
+    }
+
+    #[test]
+    fn test_formats() {
+        let c: HSBK = "#ff8800".parse().unwrap();
+        assert_eq!(c.saturation, 65535);
+        assert_eq!(c.brightness, 65535);
+        assert_eq!(c.hue, degrees_to_hue(32.0));
+
+        assert_eq!("rgb:255,136,0".parse::<HSBK>().unwrap(), c);
+
+        let c: HSBK = "hsb(210, 50%, 80%)".parse().unwrap();
+        assert_eq!(c.hue, degrees_to_hue(210.0));
+        assert_eq!(c.saturation, 32768);
+        assert_eq!(c.brightness, 52428);
+        assert_eq!(c.kelvin, 3500);
+
+        let c: HSBK = "2700K 80%".parse().unwrap();
+        assert_eq!(c.kelvin, 2700);
+        assert_eq!(c.saturation, 0);
+        assert_eq!(c.brightness, 52428);
+
+        let c: HSBK = "hue:120 saturation:1.0 kelvin:5000".parse().unwrap();
+        assert_eq!(c.hue, degrees_to_hue(120.0));
+        assert_eq!(c.kelvin, 5000);
+    }
+
+    #[test]
+    fn test_base() {
+        let base = HSBK {
+            hue: 100,
+            saturation: 200,
+            brightness: 300,
+            kelvin: 4000,
+        };
+        let c = HSBK::from_str_with_base("brightness:1", base).unwrap();
+        assert_eq!(
+            c,
+            HSBK {
+                brightness: 65535,
+                ..base
+            }
+        );
+    }
+
+    #[test]
+    fn test_normalized() {
+        // every value survives a roundtrip
+        for v in 0..=u16::MAX {
+            let c = HSBK {
+                hue: v,
+                saturation: v,
+                brightness: v,
+                kelvin: 3500,
+            };
+            assert_eq!(HSBK::from_normalized(c.to_normalized()), c);
+        }
+
+        let c = HSBK {
+            hue: 65535,
+            saturation: 65535,
+            brightness: 0,
+            kelvin: 9000,
+        };
+        let n = c.to_normalized();
+        assert_eq!(n.hue, 360.0);
+        assert_eq!(n.saturation, 1.0);
+        assert_eq!(n.brightness, 0.0);
+        assert_eq!(n.kelvin, 9000);
+
+        let c = HSBK::from_normalized(NormalizedHSBK {
+            hue: -90.0,
+            saturation: 2.0,
+            brightness: f32::NAN,
+            kelvin: 2500,
+        });
+        assert_eq!(c.hue, degrees_to_hue(270.0));
+        assert_eq!(c.saturation, 65535);
+        assert_eq!(c.brightness, 0);
+    }
+
+    #[test]
+    fn test_humane_units() {
+        // every value survives a roundtrip
+        for v in 0..=u16::MAX {
+            let c = HSBK {
+                hue: v,
+                saturation: v,
+                brightness: v,
+                kelvin: 3500,
+            };
+            let humane = (c.hue_degrees(), c.saturation_pct(), c.brightness_pct());
+            assert_eq!(HSBK::new(humane.0, humane.1, humane.2, 3500), c);
+        }
+
+        assert_eq!(HSBK::new(0.0, 0.0, 0.0, 3500).hue, 0);
+        assert_eq!(HSBK::new(360.0, 100.0, 100.0, 3500).hue, 65535);
+        assert_eq!(HSBK::new(90.0, 0.0, 0.0, 3500).hue, 16384);
+        assert_eq!(HSBK::new(1.0, 0.0, 0.0, 3500).hue, 182);
+        let c = HSBK::new(f32::NAN, f32::INFINITY, -5.0, 10000);
+        assert_eq!((c.hue, c.saturation, c.brightness), (0, 65535, 0));
+        assert_eq!(c.kelvin, 9000);
+
+        assert_eq!(
+            HSBK::try_new(90.0, 50.0, 25.0, 2700).unwrap(),
+            HSBK::new(90.0, 50.0, 25.0, 2700)
+        );
+        assert!(HSBK::try_new(361.0, 50.0, 25.0, 2700).is_err());
+        assert!(HSBK::try_new(90.0, f32::NAN, 25.0, 2700).is_err());
+        assert!(HSBK::try_new(90.0, 50.0, 100.5, 2700).is_err());
+        assert!(HSBK::try_new(90.0, 50.0, 25.0, 1000).is_err());
+    }
+
+    #[test]
+    fn test_brightness_curves() {
+        for curve in &[
+            BrightnessCurve::Linear,
+            BrightnessCurve::Gamma(2.2),
+            BrightnessCurve::CieLightness,
+        ] {
+            assert_eq!(curve.to_brightness(0.0), 0);
+            assert_eq!(curve.to_brightness(1.0), 65535);
+            assert_eq!(curve.to_brightness(-1.0), 0);
+            assert_eq!(curve.to_brightness(f32::NAN), 0);
+            assert_eq!(curve.from_brightness(0), 0.0);
+            assert_eq!(curve.from_brightness(65535), 1.0);
+
+            // monotonic, and the inverse really is the inverse
+            let mut last = 0;
+            for step in 0..=100 {
+                let p = step as f32 / 100.0;
+                let b = curve.to_brightness(p);
+                assert!(b >= last, "{:?} at {}", curve, p);
+                last = b;
+                assert!((curve.from_brightness(b) - p).abs() < 0.01, "{:?}", curve);
+            }
+        }
+
+        assert_eq!(BrightnessCurve::Linear.to_brightness(0.5), 32768);
+        // L* = 50 is about 18% of the light output
+        let mid = BrightnessCurve::CieLightness.to_brightness(0.5);
+        assert!((11900..=12200).contains(&mid), "{}", mid);
+    }
+
+    #[test]
+    fn test_invalid() {
+        for s in &[
+            "",
+            "mauve",
+            "#ff88",
+            "hue:400",
+            "saturation:1.5",
+            "kelvin:100",
+            "rgb:1,2",
+            "rgb:1,2,300",
+            "hsb(1,2)",
+            ")(",
+            "red) (",
+            "120%",
+            "brightness:nan",
+        ] {
+            assert!(s.parse::<HSBK>().is_err(), "{:?} should not parse", s);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+//! Which LAN protocol messages this crate supports
+//!
+//! [ProtocolCoverage::new] checks every message type in the LIFX LAN protocol docs against this
+//! crate, so tools (and people keeping track of what's missing) don't have to compare the docs
+//! with [Message] by hand.  `cargo xtask coverage` prints the same report as a markdown table.
+//!
+//! ```
+//! use lifx_core::coverage::ProtocolCoverage;
+//!
+//! let coverage = ProtocolCoverage::new();
+//! let set_power = coverage.get(21).unwrap();
+//! assert_eq!(set_power.name, "SetPower");
+//! assert!(set_power.decode && set_power.encode);
+//!
+//! for gap in coverage.gaps() {
+//!     println!("{} ({}) isn't supported yet", gap.name, gap.type_num);
+//! }
+//! ```
+
+use crate::{expected_payload_len, BuildOptions, Error, Message, RawMessage};
+use std::fmt;
+
+/// Every message type in the LAN protocol docs, with its section of the docs and its name
+const DOCUMENTED: &[(u16, &str, &str)] = &[
+    (2, "Discovery", "GetService"),
+    (3, "Discovery", "StateService"),
+    (12, "Device", "GetHostInfo"),
+    (13, "Device", "StateHostInfo"),
+    (14, "Device", "GetHostFirmware"),
+    (15, "Device", "StateHostFirmware"),
+    (16, "Device", "GetWifiInfo"),
+    (17, "Device", "StateWifiInfo"),
+    (18, "Device", "GetWifiFirmware"),
+    (19, "Device", "StateWifiFirmware"),
+    (20, "Device", "GetPower"),
+    (21, "Device", "SetPower"),
+    (22, "Device", "StatePower"),
+    (23, "Device", "GetLabel"),
+    (24, "Device", "SetLabel"),
+    (25, "Device", "StateLabel"),
+    (32, "Device", "GetVersion"),
+    (33, "Device", "StateVersion"),
+    (34, "Device", "GetInfo"),
+    (35, "Device", "StateInfo"),
+    (38, "Device", "SetReboot"),
+    (45, "Device", "Acknowledgement"),
+    (48, "Device", "GetLocation"),
+    (49, "Device", "SetLocation"),
+    (50, "Device", "StateLocation"),
+    (51, "Device", "GetGroup"),
+    (52, "Device", "SetGroup"),
+    (53, "Device", "StateGroup"),
+    (58, "Device", "EchoRequest"),
+    (59, "Device", "EchoResponse"),
+    (223, "Device", "StateUnhandled"),
+    (101, "Light", "GetColor"),
+    (102, "Light", "SetColor"),
+    (103, "Light", "SetWaveform"),
+    (107, "Light", "LightState"),
+    (116, "Light", "GetLightPower"),
+    (117, "Light", "SetLightPower"),
+    (118, "Light", "StateLightPower"),
+    (119, "Light", "SetWaveformOptional"),
+    (120, "Light", "GetInfrared"),
+    (121, "Light", "StateInfrared"),
+    (122, "Light", "SetInfrared"),
+    (142, "Light", "GetHevCycle"),
+    (143, "Light", "SetHevCycle"),
+    (144, "Light", "StateHevCycle"),
+    (145, "Light", "GetHevCycleConfiguration"),
+    (146, "Light", "SetHevCycleConfiguration"),
+    (147, "Light", "StateHevCycleConfiguration"),
+    (148, "Light", "GetLastHevCycleResult"),
+    (149, "Light", "StateLastHevCycleResult"),
+    (501, "MultiZone", "SetColorZones"),
+    (502, "MultiZone", "GetColorZones"),
+    (503, "MultiZone", "StateZone"),
+    (506, "MultiZone", "StateMultiZone"),
+    (507, "MultiZone", "GetMultiZoneEffect"),
+    (508, "MultiZone", "SetMultiZoneEffect"),
+    (509, "MultiZone", "StateMultiZoneEffect"),
+    (510, "MultiZone", "SetExtendedColorZones"),
+    (511, "MultiZone", "GetExtendedColorZones"),
+    (512, "MultiZone", "StateExtendedColorZones"),
+    (701, "Tile", "GetDeviceChain"),
+    (702, "Tile", "StateDeviceChain"),
+    (703, "Tile", "SetUserPosition"),
+    (707, "Tile", "Get64"),
+    (711, "Tile", "State64"),
+    (715, "Tile", "Set64"),
+    (718, "Tile", "GetTileEffect"),
+    (719, "Tile", "SetTileEffect"),
+    (720, "Tile", "StateTileEffect"),
+    (816, "Relay", "GetRPower"),
+    (817, "Relay", "SetRPower"),
+    (818, "Relay", "StateRPower"),
+];
+
+/// How well this crate supports one message type
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageCoverage {
+    pub type_num: u16,
+    /// The message's name in the LAN protocol docs
+    ///
+    /// This is occasionally different from the name of the [Message] variant (the docs call
+    /// [Message::LightGet] `GetColor`, for instance).
+    pub name: &'static str,
+    /// The section of the docs that the message is in, like `"Light"` or `"MultiZone"`
+    pub section: &'static str,
+    /// Whether [Message::from_raw] can decode the message
+    pub decode: bool,
+    /// Whether the message can be built with [RawMessage::build]
+    pub encode: bool,
+}
+
+impl MessageCoverage {
+    fn check(type_num: u16, section: &'static str, name: &'static str) -> MessageCoverage {
+        let mut coverage = MessageCoverage {
+            type_num,
+            name,
+            section,
+            decode: false,
+            encode: false,
+        };
+        let Some(len) = expected_payload_len(type_num) else {
+            return coverage;
+        };
+        // a payload of all zeroes or all ones is a valid value for every supported message (zero
+        // isn't a valid service, for instance, but one is)
+        let opts = BuildOptions::default();
+        for fill in [0, 1] {
+            let Ok(raw) = RawMessage::with_payload(&opts, type_num, vec![fill; len.size()]) else {
+                return coverage;
+            };
+            match Message::from_raw(&raw) {
+                Ok(msg) => {
+                    coverage.decode = true;
+                    coverage.encode = RawMessage::build(&opts, msg).is_ok_and(|built| {
+                        built.protocol_header.typ == type_num && built.payload == raw.payload
+                    });
+                    return coverage;
+                }
+                Err(Error::UnknownMessageType(_)) => return coverage,
+                // the decoder knows the type, but didn't like this payload
+                Err(_) => coverage.decode = true,
+            }
+        }
+        coverage
+    }
+
+    /// Whether the message is fully supported
+    pub fn is_supported(&self) -> bool {
+        self.decode && self.encode
+    }
+}
+
+/// Which of the documented LAN protocol messages this crate supports
+///
+/// This displays as a markdown table, with a row for each message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProtocolCoverage {
+    messages: Vec<MessageCoverage>,
+}
+
+impl ProtocolCoverage {
+    /// Checks every documented message type against this crate
+    pub fn new() -> ProtocolCoverage {
+        ProtocolCoverage {
+            messages: DOCUMENTED
+                .iter()
+                .map(|&(type_num, section, name)| MessageCoverage::check(type_num, section, name))
+                .collect(),
+        }
+    }
+
+    /// Every documented message, in the order of the docs
+    pub fn messages(&self) -> &[MessageCoverage] {
+        &self.messages
+    }
+
+    /// The coverage of one message type, if it's documented
+    pub fn get(&self, type_num: u16) -> Option<&MessageCoverage> {
+        self.messages.iter().find(|m| m.type_num == type_num)
+    }
+
+    /// The documented messages that aren't fully supported
+    pub fn gaps(&self) -> impl Iterator<Item = &MessageCoverage> {
+        self.messages.iter().filter(|m| !m.is_supported())
+    }
+
+    /// How many of the documented messages are fully supported
+    pub fn supported_count(&self) -> usize {
+        self.messages.iter().filter(|m| m.is_supported()).count()
+    }
+}
+
+impl Default for ProtocolCoverage {
+    fn default() -> Self {
+        ProtocolCoverage::new()
+    }
+}
+
+impl fmt::Display for ProtocolCoverage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mark = |b| if b { "yes" } else { "no" };
+        writeln!(f, "| Type | Section | Message | Decode | Encode |")?;
+        writeln!(f, "|-----:|---------|---------|:------:|:------:|")?;
+        for m in &self.messages {
+            writeln!(
+                f,
+                "| {} | {} | {} | {} | {} |",
+                m.type_num,
+                m.section,
+                m.name,
+                mark(m.decode),
+                mark(m.encode)
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{} of {} documented messages are supported",
+            self.supported_count(),
+            self.messages.len()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_coverage() {
+        let coverage = ProtocolCoverage::new();
+        // everything this crate knows about is fully supported, in both directions
+        for m in coverage.messages() {
+            assert_eq!(
+                m.is_supported(),
+                expected_payload_len(m.type_num).is_some(),
+                "{:?}",
+                m
+            );
+        }
+        // and everything it knows about is documented
+        for type_num in 0..=u16::MAX {
+            if expected_payload_len(type_num).is_some() {
+                assert!(coverage.get(type_num).is_some(), "{}", type_num);
+            }
+        }
+        let gaps: Vec<_> = coverage.gaps().map(|m| m.type_num).collect();
+        assert_eq!(gaps, vec![223, 701, 702, 703, 707, 711, 715]);
+        assert!(coverage.get(9999).is_none());
+
+        let table = coverage.to_string();
+        assert!(table.contains("| 21 | Device | SetPower | yes | yes |"));
+        assert!(table.contains("| 707 | Tile | Get64 | no | no |"));
+        assert!(table.ends_with("65 of 72 documented messages are supported\n"));
+    }
+}
@@ -0,0 +1,281 @@
+//! Taking apart a datagram that isn't (quite) a valid LIFX message
+//!
+//! [RawMessage::unpack] and [Message::from_raw] either succeed or fail, which is right for a
+//! client, but not much help when working out what a misbehaving device (or a corrupted capture)
+//! actually sent.  [diagnose] is the lenient version: it reads as much of a datagram as it can,
+//! lists everything that's wrong with it, and still makes a best-effort attempt at decoding the
+//! payload.  It's meant for packet dissectors, capture tools, and debugging, not for normal use.
+//!
+//! ```
+//! use lifx_core::diagnose::{diagnose, Problem};
+//! use lifx_core::{BuildOptions, Message, RawMessage};
+//!
+//! let msg = Message::SetPower { level: lifx_core::PowerLevel::Enabled };
+//! let mut bytes = RawMessage::build(&BuildOptions::default(), msg.clone()).unwrap().pack().unwrap();
+//! // some junk on the end, which the size field doesn't count
+//! bytes.extend([0xff; 3]);
+//!
+//! let report = diagnose(&bytes);
+//! assert_eq!(report.problems, vec![Problem::SizeMismatch { declared: 38, actual: 41 }]);
+//! assert_eq!(report.message, Some(msg));
+//! ```
+
+use crate::{
+    expected_payload_len, Frame, FrameAddress, Message, PayloadLen, ProtocolHeader, RawMessage,
+    PROTOCOL_NUMBER,
+};
+use std::fmt;
+use thiserror::Error;
+
+/// Something wrong with a datagram, found by [diagnose]
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The datagram ended before the end of a header section, so that section (and everything
+    /// after it) is missing
+    #[error("the datagram ends at byte {len}, in the middle of the {section}")]
+    Truncated { section: &'static str, len: usize },
+    /// The frame header has values that no LIFX message has, so this probably isn't LIFX at all
+    #[error("not a LIFX frame (origin {origin}, addressable {addressable}, protocol {protocol})")]
+    NotLifx {
+        origin: u8,
+        addressable: bool,
+        protocol: u16,
+    },
+    /// The size in the frame header isn't the length of the datagram
+    ///
+    /// If it's smaller, the payload is taken to end where the header says.  If it's larger, the
+    /// payload is whatever is there.
+    #[error("the frame header says {declared} bytes, but the datagram has {actual}")]
+    SizeMismatch { declared: usize, actual: usize },
+    /// The message type isn't one this library knows about
+    #[error("unknown message type {0}")]
+    UnknownType(u16),
+    /// The payload isn't the size its message type calls for (see [expected_payload_len])
+    #[error("the payload has {actual} bytes, but should have {expected}")]
+    PayloadSize { expected: PayloadLen, actual: usize },
+    /// The payload couldn't be decoded, even after any other problems were worked around
+    #[error("the payload couldn't be decoded: {0}")]
+    Undecodable(String),
+}
+
+/// Everything [diagnose] could work out about a datagram
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodeReport {
+    /// The length of the datagram
+    pub len: usize,
+    /// The frame header, if the datagram was long enough for one
+    pub frame: Option<Frame>,
+    /// The frame address header, if the datagram was long enough for one
+    pub frame_addr: Option<FrameAddress>,
+    /// The protocol header, if the datagram was long enough for one
+    pub protocol_header: Option<ProtocolHeader>,
+    /// The payload, as decoded despite any problems
+    ///
+    /// This is `None` if the headers were incomplete, the type is unknown, or the payload is too
+    /// short or has values that don't make sense.
+    pub message: Option<Message>,
+    /// Everything that's wrong with the datagram, in the order it was found
+    pub problems: Vec<Problem>,
+}
+
+impl DecodeReport {
+    /// Whether the datagram is a perfectly good message
+    ///
+    /// This is stricter than [RawMessage::unpack] and [Message::from_raw], which ignore anything
+    /// after the size given in the frame header.
+    pub fn is_clean(&self) -> bool {
+        self.problems.is_empty() && self.message.is_some()
+    }
+}
+
+/// Describes a report over several lines, one for each header section, then the problems and the
+/// decoded message
+impl fmt::Display for DecodeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "{} bytes", self.len)?;
+        fn section<T: fmt::Debug>(
+            f: &mut fmt::Formatter<'_>,
+            name: &str,
+            value: &Option<T>,
+        ) -> fmt::Result {
+            match value {
+                Some(value) => writeln!(f, "{}: {:?}", name, value),
+                None => writeln!(f, "{}: missing", name),
+            }
+        }
+        section(f, "frame", &self.frame)?;
+        section(f, "frame address", &self.frame_addr)?;
+        section(f, "protocol header", &self.protocol_header)?;
+        for problem in &self.problems {
+            writeln!(f, "problem: {}", problem)?;
+        }
+        section(f, "message", &self.message)
+    }
+}
+
+/// Reads as much of a datagram as possible, without stopping at the first problem
+///
+/// See the [module docs](self) for an example.
+pub fn diagnose(buf: &[u8]) -> DecodeReport {
+    let mut report = DecodeReport {
+        len: buf.len(),
+        frame: None,
+        frame_addr: None,
+        protocol_header: None,
+        message: None,
+        problems: Vec::new(),
+    };
+
+    let mut start = 0;
+    let truncated = |section, problems: &mut Vec<Problem>| {
+        problems.push(Problem::Truncated {
+            section,
+            len: buf.len(),
+        })
+    };
+    let Ok(frame) = Frame::unpack_unchecked(buf) else {
+        truncated("frame header", &mut report.problems);
+        return report;
+    };
+    report.frame = Some(frame);
+    if frame.origin() >= 4 || !frame.addressable() || frame.protocol() != PROTOCOL_NUMBER {
+        report.problems.push(Problem::NotLifx {
+            origin: frame.origin(),
+            addressable: frame.addressable(),
+            protocol: frame.protocol(),
+        });
+    }
+    start += Frame::packed_size();
+    let Ok(frame_addr) = FrameAddress::unpack(&buf[start..]) else {
+        truncated("frame address", &mut report.problems);
+        return report;
+    };
+    report.frame_addr = Some(frame_addr);
+    start += FrameAddress::packed_size();
+    let Ok(protocol_header) = ProtocolHeader::unpack(&buf[start..]) else {
+        truncated("protocol header", &mut report.problems);
+        return report;
+    };
+    report.protocol_header = Some(protocol_header);
+    start += ProtocolHeader::packed_size();
+
+    let declared = frame.size() as usize;
+    if declared != buf.len() {
+        report.problems.push(Problem::SizeMismatch {
+            declared,
+            actual: buf.len(),
+        });
+    }
+    let mut payload = &buf[start..declared.clamp(start, buf.len())];
+
+    let typ = protocol_header.typ;
+    let Some(expected) = expected_payload_len(typ) else {
+        report.problems.push(Problem::UnknownType(typ));
+        return report;
+    };
+    if !expected.accepts(payload.len()) {
+        report.problems.push(Problem::PayloadSize {
+            expected,
+            actual: payload.len(),
+        });
+        if payload.len() < expected.size() {
+            return report;
+        }
+        // the fields are all there, so ignore whatever is after them
+        payload = &payload[..expected.size()];
+    }
+
+    let raw = RawMessage {
+        frame,
+        frame_addr,
+        protocol_header,
+        payload: payload.to_vec(),
+    };
+    match Message::from_raw(&raw) {
+        Ok(msg) => report.message = Some(msg),
+        Err(e) => report.problems.push(Problem::Undecodable(e.to_string())),
+    }
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BuildOptions, HEADER_SIZE};
+
+    fn packed(msg: Message) -> Vec<u8> {
+        RawMessage::build(&BuildOptions::default(), msg)
+            .unwrap()
+            .pack()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_clean() {
+        let bytes = packed(Message::GetLabel);
+        let report = diagnose(&bytes);
+        assert!(report.is_clean());
+        assert_eq!(report.message, Some(Message::GetLabel));
+        assert!(report.to_string().ends_with("\nmessage: GetLabel\n"));
+    }
+
+    #[test]
+    fn test_problems() {
+        // the headers are cut off
+        let bytes = packed(Message::GetLabel);
+        let report = diagnose(&bytes[..20]);
+        assert!(report.frame.is_some());
+        assert!(report.frame_addr.is_none());
+        assert_eq!(
+            report.problems,
+            vec![Problem::Truncated {
+                section: "frame address",
+                len: 20
+            }]
+        );
+        assert!(!report.is_clean());
+
+        // a payload that's too long for its type still decodes
+        let mut bytes = packed(Message::EchoRequest {
+            payload: crate::EchoPayload([7; 64]),
+        });
+        bytes[32..34].copy_from_slice(&117u16.to_le_bytes()); // LightSetPower
+        let report = diagnose(&bytes);
+        assert_eq!(
+            report.problems,
+            vec![Problem::PayloadSize {
+                expected: PayloadLen::Exact(6),
+                actual: 64
+            }]
+        );
+        assert_eq!(
+            report.message,
+            Some(Message::LightSetPower {
+                level: 0x0707,
+                duration: 0x07070707
+            })
+        );
+
+        // garbage everywhere
+        let mut bytes = vec![0xff; HEADER_SIZE + 1];
+        bytes[0..2].copy_from_slice(&200u16.to_le_bytes());
+        let report = diagnose(&bytes);
+        assert_eq!(
+            report.problems,
+            vec![
+                Problem::NotLifx {
+                    origin: 3,
+                    addressable: true,
+                    protocol: 4095
+                },
+                Problem::SizeMismatch {
+                    declared: 200,
+                    actual: HEADER_SIZE + 1
+                },
+                Problem::UnknownType(0xffff),
+            ]
+        );
+        assert!(report.protocol_header.is_some());
+        assert_eq!(report.message, None);
+    }
+}
@@ -0,0 +1,374 @@
+//! Effects that devices run by themselves, across the different kinds of device
+//!
+//! Every light can run a [Message::SetWaveform], multizone lights (the Z and Beam) also have a
+//! "move" effect, and matrix lights (the Tile and Candle) have "morph" and "flame" effects.  Each
+//! of these is started with a different message.  [Effect] describes any of them, and
+//! [start_effect] builds the right message for a device, after checking that the device supports
+//! it (see [supported_effects]).
+//!
+//! This doesn't do any I/O, so the caller sends the messages.
+//!
+//! ```
+//! use lifx_core::effects::{start_effect, supported_effects, Effect, EffectKind};
+//! use lifx_core::{Message, ProductInfo};
+//! use std::time::Duration;
+//!
+//! let version = Message::StateVersion { vendor: 1, product: 55, reserved: 0 };
+//! let tile = ProductInfo::from_state_version(&version).unwrap();
+//! assert!(supported_effects(tile).contains(&EffectKind::Flame));
+//!
+//! let msg = start_effect(tile, &Effect::Flame { speed: Duration::from_secs(4) }).unwrap();
+//! assert!(matches!(msg, Message::SetTileEffect { speed: 4000, .. }));
+//! assert!(start_effect(tile, &Effect::Move { speed: Duration::from_secs(4), direction: Default::default() }).is_err());
+//! ```
+
+use crate::{Error, Message, MultiZoneEffectType, ProductInfo, TileEffectType, Waveform, HSBK};
+use std::convert::TryFrom;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::Duration;
+
+/// The most colors a [Effect::Morph] palette can have
+pub const MAX_PALETTE: usize = 16;
+
+/// Which way a [Effect::Move] goes along the strip
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum MoveDirection {
+    #[default]
+    Right = 0,
+    Left = 1,
+}
+
+/// The different kinds of [Effect], without their settings
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EffectKind {
+    Waveform,
+    Move,
+    Morph,
+    Flame,
+}
+
+/// An effect that a device runs by itself
+#[derive(Debug, Clone, PartialEq)]
+pub enum Effect {
+    /// Repeatedly changes to `color` and back, as a [Message::SetWaveform]
+    ///
+    /// Every light supports this.
+    Waveform {
+        color: HSBK,
+        waveform: Waveform,
+        /// How long each cycle takes
+        period: Duration,
+        cycles: f32,
+        skew_ratio: i16,
+        /// Whether to go back to the original color when the waveform finishes
+        transient: bool,
+    },
+    /// Moves the current zone colors along a multizone light
+    Move {
+        /// How long it takes for the colors to move the length of the strip
+        speed: Duration,
+        direction: MoveDirection,
+    },
+    /// Slowly blends between the colors in a palette, on a matrix light
+    Morph {
+        /// How long each cycle takes
+        speed: Duration,
+        /// Up to [MAX_PALETTE] colors, or none to use the device's default palette
+        palette: Vec<HSBK>,
+    },
+    /// A flickering fire, on a matrix light
+    Flame {
+        /// How long each cycle takes
+        speed: Duration,
+    },
+}
+
+impl Effect {
+    pub fn kind(&self) -> EffectKind {
+        match self {
+            Effect::Waveform { .. } => EffectKind::Waveform,
+            Effect::Move { .. } => EffectKind::Move,
+            Effect::Morph { .. } => EffectKind::Morph,
+            Effect::Flame { .. } => EffectKind::Flame,
+        }
+    }
+
+    /// Reads the firmware effect that a device reports it's running
+    ///
+    /// This understands [Message::StateMultiZoneEffect] and [Message::StateTileEffect] (and the
+    /// matching `Set` messages), and returns `None` for any other message, or if no effect is
+    /// running.  Devices don't report waveforms.
+    pub fn from_message(msg: &Message) -> Option<Effect> {
+        match msg {
+            Message::SetMultiZoneEffect {
+                typ: MultiZoneEffectType::Move,
+                speed,
+                parameters,
+                ..
+            }
+            | Message::StateMultiZoneEffect {
+                typ: MultiZoneEffectType::Move,
+                speed,
+                parameters,
+                ..
+            } => Some(Effect::Move {
+                speed: Duration::from_millis(*speed as u64),
+                direction: match parameters[2] {
+                    1 => MoveDirection::Left,
+                    _ => MoveDirection::Right,
+                },
+            }),
+            Message::SetTileEffect {
+                typ,
+                speed,
+                palette_count,
+                palette,
+                ..
+            }
+            | Message::StateTileEffect {
+                typ,
+                speed,
+                palette_count,
+                palette,
+                ..
+            } => {
+                let speed = Duration::from_millis(*speed as u64);
+                match typ {
+                    TileEffectType::Morph => Some(Effect::Morph {
+                        speed,
+                        palette: palette[..(*palette_count as usize).min(MAX_PALETTE)].to_vec(),
+                    }),
+                    TileEffectType::Flame => Some(Effect::Flame { speed }),
+                    _ => None,
+                }
+            }
+            _ => None,
+        }
+    }
+}
+
+/// The kinds of effect that a product can run
+pub fn supported_effects(info: &ProductInfo) -> Vec<EffectKind> {
+    let mut kinds = Vec::new();
+    if info.is_light() {
+        kinds.push(EffectKind::Waveform);
+    }
+    if info.multizone() {
+        kinds.push(EffectKind::Move);
+    }
+    if info.matrix() {
+        kinds.extend_from_slice(&[EffectKind::Morph, EffectKind::Flame]);
+    }
+    kinds
+}
+
+/// Each firmware effect gets a new ID, so that devices can tell them apart
+fn next_instance_id() -> u32 {
+    static NEXT: AtomicU32 = AtomicU32::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+fn millis(duration: Duration) -> u32 {
+    u32::try_from(duration.as_millis()).unwrap_or(u32::MAX)
+}
+
+/// Builds the message that starts an effect on a product
+///
+/// Returns an error if the product doesn't support the effect (see [supported_effects]), or if a
+/// [Effect::Morph] palette has more than [MAX_PALETTE] colors.  Firmware effects run until they're
+/// stopped with [stop_effect].
+#[allow(clippy::useless_conversion)]
+pub fn start_effect(info: &ProductInfo, effect: &Effect) -> Result<Message, Error> {
+    if !supported_effects(info).contains(&effect.kind()) {
+        return Err(Error::ProtocolError(format!(
+            "{} doesn't support the {:?} effect",
+            info.name,
+            effect.kind()
+        )));
+    }
+    Ok(match effect {
+        Effect::Waveform {
+            color,
+            waveform,
+            period,
+            cycles,
+            skew_ratio,
+            transient,
+        } => Message::SetWaveform {
+            reserved: 0,
+            transient: *transient,
+            color: *color,
+            period: millis(*period),
+            cycles: (*cycles).into(),
+            skew_ratio: *skew_ratio,
+            waveform: *waveform,
+        },
+        Effect::Move { speed, direction } => {
+            let mut parameters = [0; 8];
+            parameters[2] = *direction as u32;
+            multizone_effect(MultiZoneEffectType::Move, millis(*speed), parameters)
+        }
+        Effect::Morph { speed, palette } => {
+            if palette.len() > MAX_PALETTE {
+                return Err(Error::ProtocolError(format!(
+                    "a palette can have at most {} colors, not {}",
+                    MAX_PALETTE,
+                    palette.len()
+                )));
+            }
+            tile_effect(TileEffectType::Morph, millis(*speed), palette)
+        }
+        Effect::Flame { speed } => tile_effect(TileEffectType::Flame, millis(*speed), &[]),
+    })
+}
+
+/// Builds the messages that stop any firmware effect running on a product
+///
+/// This is one message for each kind of firmware effect the product has, so it's empty for
+/// products that can only run waveforms.  Waveforms can't be stopped early, but finish after their
+/// cycles, or when the light's color is next set.
+pub fn stop_effect(info: &ProductInfo) -> Vec<Message> {
+    let mut messages = Vec::new();
+    if info.multizone() {
+        messages.push(multizone_effect(MultiZoneEffectType::Off, 0, [0; 8]));
+    }
+    if info.matrix() {
+        messages.push(tile_effect(TileEffectType::Off, 0, &[]));
+    }
+    messages
+}
+
+fn multizone_effect(typ: MultiZoneEffectType, speed: u32, parameters: [u32; 8]) -> Message {
+    Message::SetMultiZoneEffect {
+        instance_id: next_instance_id(),
+        typ,
+        reserved: 0,
+        speed,
+        duration: 0,
+        reserved7: 0,
+        reserved8: 0,
+        parameters,
+    }
+}
+
+fn tile_effect(typ: TileEffectType, speed: u32, colors: &[HSBK]) -> Message {
+    let mut palette = Box::new(
+        [HSBK {
+            hue: 0,
+            saturation: 0,
+            brightness: 0,
+            kelvin: 0,
+        }; MAX_PALETTE],
+    );
+    palette[..colors.len()].copy_from_slice(colors);
+    Message::SetTileEffect {
+        reserved8: 0,
+        reserved9: 0,
+        instance_id: next_instance_id(),
+        typ,
+        speed,
+        duration: 0,
+        reserved6: 0,
+        reserved7: 0,
+        parameters: [0; 32],
+        palette_count: colors.len() as u8,
+        palette,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{get_product_info, BuildOptions, RawMessage};
+
+    const COLOR: HSBK = HSBK {
+        hue: 0,
+        saturation: 65535,
+        brightness: 65535,
+        kelvin: 3500,
+    };
+
+    #[test]
+    fn test_supported_effects() {
+        let bulb = get_product_info(1, 27).unwrap();
+        let strip = get_product_info(1, 32).unwrap();
+        let tile = get_product_info(1, 55).unwrap();
+        let switch = get_product_info(1, 70).unwrap();
+        assert_eq!(supported_effects(bulb), vec![EffectKind::Waveform]);
+        assert_eq!(
+            supported_effects(strip),
+            vec![EffectKind::Waveform, EffectKind::Move]
+        );
+        assert_eq!(
+            supported_effects(tile),
+            vec![EffectKind::Waveform, EffectKind::Morph, EffectKind::Flame]
+        );
+        assert!(supported_effects(switch).is_empty());
+
+        assert!(stop_effect(bulb).is_empty());
+        assert_eq!(stop_effect(strip).len(), 1);
+        assert_eq!(stop_effect(tile).len(), 1);
+    }
+
+    #[test]
+    fn test_start_effect() {
+        let strip = get_product_info(1, 32).unwrap();
+        let tile = get_product_info(1, 55).unwrap();
+
+        let waveform = Effect::Waveform {
+            color: COLOR,
+            waveform: Waveform::Sine,
+            period: Duration::from_secs(1),
+            cycles: 3.0,
+            skew_ratio: 0,
+            transient: true,
+        };
+        assert!(matches!(
+            start_effect(strip, &waveform).unwrap(),
+            Message::SetWaveform { period: 1000, .. }
+        ));
+
+        // firmware effects survive a trip through the protocol
+        let effects = [
+            (
+                strip,
+                Effect::Move {
+                    speed: Duration::from_secs(3),
+                    direction: MoveDirection::Left,
+                },
+            ),
+            (
+                tile,
+                Effect::Morph {
+                    speed: Duration::from_secs(5),
+                    palette: vec![COLOR; 3],
+                },
+            ),
+            (
+                tile,
+                Effect::Flame {
+                    speed: Duration::from_secs(4),
+                },
+            ),
+        ];
+        for (info, effect) in &effects {
+            let msg = start_effect(info, effect).unwrap();
+            let raw = RawMessage::build(&BuildOptions::default(), msg).unwrap();
+            let msg =
+                Message::from_raw(&RawMessage::unpack(&raw.pack().unwrap()).unwrap()).unwrap();
+            assert_eq!(Effect::from_message(&msg).as_ref(), Some(effect));
+        }
+        for msg in stop_effect(tile).iter().chain(&stop_effect(strip)) {
+            assert_eq!(Effect::from_message(msg), None);
+        }
+
+        // effects for other kinds of device, and oversized palettes, are refused
+        assert!(start_effect(strip, &effects[2].1).is_err());
+        assert!(start_effect(tile, &effects[0].1).is_err());
+        let morph = Effect::Morph {
+            speed: Duration::from_secs(5),
+            palette: vec![COLOR; MAX_PALETTE + 1],
+        };
+        assert!(start_effect(tile, &morph).is_err());
+    }
+}
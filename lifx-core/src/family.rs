@@ -0,0 +1,175 @@
+//! Checked views of [Message], one per message family
+//!
+//! LIFX messages are grouped into families by the range their type numbers fall in:
+//! [DeviceMessage], [LightMessage], [MultiZoneMessage], [TileMessage], and [RelayMessage].  Each
+//! of these is just a [Message] that's been checked to be in the family's range.  The fields are
+//! still read by matching on [DeviceMessage::message], and packing and unpacking is done by
+//! [Message] itself.  Code that only deals with one family can take that type instead of a
+//! [Message], and let the type system rule out everything else.
+//!
+//! Every view implements [Payload], so it can be sent with [RawMessage::build_payload].
+//!
+//! ```
+//! use lifx_core::family::LightMessage;
+//! use lifx_core::payload::Payload;
+//! use lifx_core::{BuildOptions, Message, RawMessage};
+//! use std::convert::TryFrom;
+//!
+//! let light = LightMessage::try_from(Message::LightGet).unwrap();
+//! assert_eq!(light.type_num(), 101);
+//! assert!(LightMessage::try_from(Message::GetLabel).is_err());
+//!
+//! let raw = RawMessage::build_payload(&BuildOptions::default(), &light).unwrap();
+//! assert_eq!(Message::from_raw(&raw).unwrap(), Message::LightGet);
+//! ```
+
+use crate::payload::Payload;
+use crate::{Error, Message, RawMessage};
+use std::convert::TryFrom;
+use std::ops::RangeInclusive;
+
+/// Defines a view of a family of messages, as a [Message] whose type number is known to be in a
+/// range
+macro_rules! family {
+    ($(#[$doc:meta])* $name:ident, $types:expr) => {
+        $(#[$doc])*
+        #[derive(Debug, Clone, PartialEq)]
+        pub struct $name(Message);
+
+        impl $name {
+            /// The message type numbers in this family
+            pub const TYPES: RangeInclusive<u16> = $types;
+
+            /// The message
+            pub fn message(&self) -> &Message {
+                &self.0
+            }
+
+            pub fn into_message(self) -> Message {
+                self.0
+            }
+        }
+
+        impl TryFrom<Message> for $name {
+            type Error = Error;
+
+            /// Returns [Error::ProtocolError] if the message isn't in this family
+            fn try_from(msg: Message) -> Result<$name, Error> {
+                if !Self::TYPES.contains(&msg.get_num()) {
+                    return Err(Error::ProtocolError(format!(
+                        "{:?} isn't a {}",
+                        msg,
+                        stringify!($name)
+                    )));
+                }
+                Ok($name(msg))
+            }
+        }
+
+        impl From<$name> for Message {
+            fn from(view: $name) -> Message {
+                view.0
+            }
+        }
+
+        impl Payload for $name {
+            fn type_num(&self) -> u16 {
+                self.0.get_num()
+            }
+
+            fn pack(&self, buf: &mut Vec<u8>) -> Result<(), Error> {
+                self.0.pack(buf)
+            }
+
+            /// Returns [Error::ProtocolError] if the message isn't in this family
+            fn unpack(raw: &RawMessage) -> Result<$name, Error> {
+                $name::try_from(Message::from_raw(raw)?)
+            }
+        }
+    };
+}
+
+family!(
+    /// Messages that every device understands, like [Message::GetService] and
+    /// [Message::SetLabel] (types 1 to 99)
+    DeviceMessage,
+    1..=99
+);
+
+family!(
+    /// Messages for lights, like [Message::LightSetColor] (types 100 to 199)
+    LightMessage,
+    100..=199
+);
+
+family!(
+    /// Messages for multizone lights, like [Message::SetColorZones] (types 500 to 599)
+    MultiZoneMessage,
+    500..=599
+);
+
+family!(
+    /// Messages for matrix lights, like [Message::SetTileEffect] (types 700 to 799)
+    TileMessage,
+    700..=799
+);
+
+family!(
+    /// Messages for devices with relays, like [Message::RelaySetPower] (types 800 to 899)
+    RelayMessage,
+    800..=899
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{BuildOptions, PowerLevel};
+
+    #[test]
+    fn test_families() {
+        let messages = [
+            Message::SetPower {
+                level: PowerLevel::Enabled,
+            },
+            Message::LightGetPower,
+            Message::GetExtendedColorZone,
+            Message::GetTileEffect {
+                reserved6: 0,
+                reserved7: 0,
+            },
+            Message::RelayGetPower { relay_index: 1 },
+        ];
+        let families = [
+            DeviceMessage::TYPES,
+            LightMessage::TYPES,
+            MultiZoneMessage::TYPES,
+            TileMessage::TYPES,
+            RelayMessage::TYPES,
+        ];
+        for (msg, types) in messages.iter().zip(&families) {
+            assert_eq!(
+                families
+                    .iter()
+                    .filter(|t| t.contains(&msg.get_num()))
+                    .collect::<Vec<_>>(),
+                vec![types]
+            );
+        }
+
+        // a family view packs exactly like its message
+        let relay = RelayMessage::try_from(messages[4].clone()).unwrap();
+        let opts = BuildOptions::default();
+        let raw = RawMessage::build_payload(&opts, &relay).unwrap();
+        assert_eq!(raw, RawMessage::build(&opts, messages[4].clone()).unwrap());
+        assert_eq!(RelayMessage::unpack(&raw).unwrap(), relay);
+        assert!(LightMessage::unpack(&raw).is_err());
+        assert_eq!(Message::from(relay.clone()), messages[4]);
+
+        // payloads of different types can be mixed
+        let payloads: Vec<Box<dyn Payload>> = vec![Box::new(Message::LightGet), Box::new(relay)];
+        let types: Vec<_> = payloads.iter().map(|p| p.type_num()).collect();
+        assert_eq!(types, vec![101, 816]);
+        let raw = RawMessage::build_payload(&opts, &*payloads[1]).unwrap();
+        assert_eq!(Message::from_raw(&raw).unwrap(), messages[4]);
+    }
+}
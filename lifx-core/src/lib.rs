@@ -8,32 +8,70 @@
 //!
 //! # Discovery
 //!
-//! To discover lights on your LAN, send a [Message::GetService] message as a UDP broadcast to port 56700.
+//! To discover lights on your LAN, send a [Message::GetService] message as a UDP broadcast to port
+//! [LIFX_PORT] (see [default_broadcast_addr]).
 //! When a device is discovered, the [Service] types and IP port are provided.  To get additional
 //! info about each device, send additional Get messages directly to each device (by setting the
 //! [FrameAddress::target] field to the bulbs target ID, and then send a UDP packet to the IP address
 //! associated with the device).
 //!
+//! # WebAssembly
+//! This crate doesn't touch the network or the filesystem, and builds for `wasm32-unknown-unknown`,
+//! so the same codec can be used by browser-based tools.  The only thing missing on that target is
+//! [Message::set_group] and [Message::set_location], which need the current time.
+//!
 //! # Reserved fields
 //! When *constructing* packets, you must always set every reserved field to zero.  However, it's
 //! possible to receive packets with these fields set to non-zero values.  Be conservative in what
 //! you send, and liberal in what you accept.
 //!
+//! # Fields whose meaning changed
+//! Some fields had a meaning in earlier versions of the protocol docs, and are now reserved.
+//! Current firmware leaves them zero, but older firmware may still fill them in.
+//!
+//! * If the message is still documented, the field is named `reservedN` (like
+//!   [Message::StateWifiInfo]'s old byte counters), and its doc comment says what it used to mean.
+//! * If the message itself has been dropped from the docs (like [Message::StateHostInfo]), the
+//!   field keeps its old name but is `#[deprecated]`, so using it gives a warning.
+//! * Where a field is still meaningful but needs interpreting differently depending on the device,
+//!   there's an accessor method that explains how, like [Message::wifi_signal_dbm].
+//!
 //! # Unknown values
 //! It's common to see packets for LIFX bulbs that don't match the documented protocol.  These are
 //! suspected to be internal messages that are used by official LIFX apps, but that aren't documented.
 
-use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use codec::{PayloadReader, PayloadWriter};
 use std::cmp::PartialEq;
 use std::convert::{TryFrom, TryInto};
 use std::ffi::{CStr, CString};
 use std::io;
-use std::io::Cursor;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use thiserror::Error;
 
+pub mod bitmap;
+mod codec;
+pub mod color;
+pub mod coverage;
+pub mod diagnose;
+pub mod effects;
+pub mod family;
+pub mod maintenance;
+pub mod palette;
+pub mod payload;
+pub mod products;
+pub mod registry;
+pub mod source;
+pub mod waveform;
+pub mod zones;
+
+pub use payload::Payload;
+pub use source::SourceId;
+pub use zones::ZoneRange;
+
 #[cfg(fuzzing)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy)]
 pub struct ComparableFloat(f32);
 #[cfg(fuzzing)]
 impl PartialEq for ComparableFloat {
@@ -51,9 +89,350 @@ impl From<f32> for ComparableFloat {
         ComparableFloat(f)
     }
 }
+#[cfg(fuzzing)]
+impl From<ComparableFloat> for f32 {
+    fn from(f: ComparableFloat) -> Self {
+        f.0
+    }
+}
+
+/// The UDP port that LIFX devices listen on
+///
+/// Devices report the port to use in [Message::StateService], and in practice it's always this one.
+pub const LIFX_PORT: u16 = 56700;
+
+/// The protocol number in every [Frame]
+pub const PROTOCOL_NUMBER: u16 = 1024;
+
+/// The address to broadcast discovery messages to: the IPv4 limited broadcast address, on
+/// [LIFX_PORT]
+///
+/// Routers don't forward this address, so it only reaches the local subnet.
+pub const fn default_broadcast_addr() -> SocketAddr {
+    SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::BROADCAST, LIFX_PORT))
+}
+
+/// The largest datagram that this library will build
+///
+/// LIFX devices don't document a maximum message size, but anything larger than this won't fit
+/// into a single Ethernet frame (1500 bytes, less 20 bytes of IPv4 header and 8 bytes of UDP
+/// header), and would need to be fragmented.  Devices are not known to handle fragmented packets.
+/// The largest message currently defined, [Message::SetExtendedColorZones], is 700 bytes.
+///
+/// When receiving, use a buffer at least one byte larger than this; a datagram that fills the
+/// whole buffer was probably truncated, and shouldn't be unpacked.
+pub const MAX_DATAGRAM_SIZE: usize = 1472;
+
+/// The size of the header at the start of every message, in bytes
+///
+/// This is a [Frame], followed by a [FrameAddress] and a [ProtocolHeader].  The message's payload
+/// comes straight after it.
+pub const HEADER_SIZE: usize =
+    Frame::PACKED_SIZE + FrameAddress::PACKED_SIZE + ProtocolHeader::PACKED_SIZE;
+
+// The sizes of the fixed-size types that appear in payloads, and of the payloads built from them,
+// checked against the layouts in the protocol docs.  A mistake in any of these fails the build.
+const _: () = {
+    const HSBK_SIZE: usize = 8;
+    const STRING_SIZE: usize = 32;
+    const IDENT_SIZE: usize = 16;
+    const EXTENDED_ZONES: usize = 82;
+    const TILE_PALETTE: usize = 16;
+
+    assert!(HEADER_SIZE == 36);
+    assert!(std::mem::size_of::<EchoPayload>() == 64);
+    assert!(std::mem::size_of::<LifxIdent>() == IDENT_SIZE);
+
+    // SetLocation / StateLocation / SetGroup / StateGroup: ident, label, updated_at
+    assert!(IDENT_SIZE + STRING_SIZE + 8 == 56);
+    // LightSetColor: reserved, color, duration
+    assert!(1 + HSBK_SIZE + 4 == 13);
+    // SetWaveform: reserved, transient, color, period, cycles, skew_ratio, waveform
+    const WAVEFORM_SIZE: usize = 1 + 1 + HSBK_SIZE + 4 + 4 + 2 + 1;
+    assert!(WAVEFORM_SIZE == 21);
+    // SetWaveformOptional: SetWaveform plus four set_* flags
+    assert!(WAVEFORM_SIZE + 4 == 25);
+    // LightState: color, reserved, power, label, reserved
+    assert!(HSBK_SIZE + 2 + 2 + STRING_SIZE + 8 == 52);
+    // SetColorZones: start_index, end_index, color, duration, apply
+    assert!(1 + 1 + HSBK_SIZE + 4 + 1 == 15);
+    // StateZone: count, index, color
+    assert!(1 + 1 + HSBK_SIZE == 10);
+    // StateMultiZone: count, index, 8 colors
+    assert!(1 + 1 + 8 * HSBK_SIZE == 66);
+    // SetExtendedColorZones: duration, apply, zone_index, colors_count, colors
+    assert!(4 + 1 + 2 + 1 + EXTENDED_ZONES * HSBK_SIZE == 664);
+    // StateExtendedColorZones: zones_count, zone_index, colors_count, colors
+    assert!(2 + 2 + 1 + EXTENDED_ZONES * HSBK_SIZE == 661);
+    // SetTileEffect: 2 reserved, instance_id, typ, speed, duration, 2 reserved, parameters,
+    // palette_count, palette
+    assert!(2 + 4 + 1 + 4 + 8 + 4 + 4 + 32 + 1 + TILE_PALETTE * HSBK_SIZE == 188);
+    // StateTileEffect: the same, with only one reserved byte at the start
+    assert!(1 + 4 + 1 + 4 + 8 + 4 + 4 + 32 + 1 + TILE_PALETTE * HSBK_SIZE == 187);
+
+    // the largest message still fits in a single datagram
+    assert!(HEADER_SIZE + 664 <= MAX_DATAGRAM_SIZE);
+};
+
+/// The payload size that a message type calls for
+///
+/// See [expected_payload_len].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PayloadLen {
+    /// The payload must be exactly this many bytes
+    Exact(usize),
+    /// The payload must be at least this many bytes, and anything after that is ignored
+    AtLeast(usize),
+}
+
+impl PayloadLen {
+    /// The size of the payload when this library packs a message of this type
+    pub const fn size(self) -> usize {
+        match self {
+            PayloadLen::Exact(size) | PayloadLen::AtLeast(size) => size,
+        }
+    }
+
+    /// Whether a payload of this many bytes is acceptable
+    pub const fn accepts(self, len: usize) -> bool {
+        match self {
+            PayloadLen::Exact(size) => len == size,
+            PayloadLen::AtLeast(size) => len >= size,
+        }
+    }
+}
+
+impl std::fmt::Display for PayloadLen {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PayloadLen::Exact(size) => write!(f, "exactly {} bytes", size),
+            PayloadLen::AtLeast(size) => write!(f, "at least {} bytes", size),
+        }
+    }
+}
+
+/// The payload size that a message type calls for, or `None` if the type is unknown
+///
+/// Messages that devices send in reply may have extra fields added to the end by newer firmware,
+/// so those only need to be at least as long as the documented layout.  Everything else has to be
+/// exactly the documented size.  [Message::from_raw] checks this before decoding anything, and
+/// returns [Error::PayloadSizeMismatch] if it doesn't match.
+///
+/// ```
+/// # use lifx_core::{expected_payload_len, PayloadLen};
+/// assert_eq!(expected_payload_len(21), Some(PayloadLen::Exact(2))); // SetPower
+/// assert_eq!(expected_payload_len(22), Some(PayloadLen::AtLeast(2))); // StatePower
+/// assert_eq!(expected_payload_len(9999), None);
+/// ```
+pub const fn expected_payload_len(typ: u16) -> Option<PayloadLen> {
+    use PayloadLen::*;
+    Some(match typ {
+        2 => Exact(0),       // GetService
+        3 => AtLeast(5),     // StateService
+        12 => Exact(0),      // GetHostInfo
+        13 => AtLeast(14),   // StateHostInfo
+        14 => Exact(0),      // GetHostFirmware
+        15 => AtLeast(20),   // StateHostFirmware
+        16 => Exact(0),      // GetWifiInfo
+        17 => AtLeast(14),   // StateWifiInfo
+        18 => Exact(0),      // GetWifiFirmware
+        19 => AtLeast(20),   // StateWifiFirmware
+        20 => Exact(0),      // GetPower
+        21 => Exact(2),      // SetPower
+        22 => AtLeast(2),    // StatePower
+        23 => Exact(0),      // GetLabel
+        24 => Exact(32),     // SetLabel
+        25 => AtLeast(32),   // StateLabel
+        32 => Exact(0),      // GetVersion
+        33 => AtLeast(12),   // StateVersion
+        34 => Exact(0),      // GetInfo
+        35 => AtLeast(24),   // StateInfo
+        38 => Exact(0),      // SetReboot
+        45 => AtLeast(0),    // Acknowledgement
+        48 => Exact(0),      // GetLocation
+        49 => Exact(56),     // SetLocation
+        50 => AtLeast(56),   // StateLocation
+        51 => Exact(0),      // GetGroup
+        52 => Exact(56),     // SetGroup
+        53 => AtLeast(56),   // StateGroup
+        58 => Exact(64),     // EchoRequest
+        59 => AtLeast(64),   // EchoResponse
+        101 => Exact(0),     // LightGet
+        102 => Exact(13),    // LightSetColor
+        103 => Exact(21),    // SetWaveform
+        107 => AtLeast(52),  // LightState
+        116 => Exact(0),     // LightGetPower
+        117 => Exact(6),     // LightSetPower
+        118 => AtLeast(2),   // LightStatePower
+        119 => Exact(25),    // SetWaveformOptional
+        120 => Exact(0),     // LightGetInfrared
+        121 => AtLeast(2),   // LightStateInfrared
+        122 => Exact(2),     // LightSetInfrared
+        142 => Exact(0),     // LightGetHevCycle
+        143 => Exact(5),     // LightSetHevCycle
+        144 => AtLeast(9),   // LightStateHevCycle
+        145 => Exact(0),     // LightGetHevCycleConfiguration
+        146 => Exact(5),     // LightSetHevCycleConfiguration
+        147 => AtLeast(5),   // LightStateHevCycleConfiguration
+        148 => Exact(0),     // LightGetLastHevCycleResult
+        149 => AtLeast(1),   // LightStateLastHevCycleResult
+        501 => Exact(15),    // SetColorZones
+        502 => Exact(2),     // GetColorZones
+        503 => AtLeast(10),  // StateZone
+        506 => AtLeast(66),  // StateMultiZone
+        507 => Exact(0),     // GetMultiZoneEffect
+        508 => Exact(59),    // SetMultiZoneEffect
+        509 => AtLeast(59),  // StateMultiZoneEffect
+        510 => Exact(664),   // SetExtendedColorZones
+        511 => Exact(0),     // GetExtendedColorZone
+        512 => AtLeast(661), // StateExtendedColorZones
+        718 => Exact(2),     // GetTileEffect
+        719 => Exact(188),   // SetTileEffect
+        720 => AtLeast(187), // StateTileEffect
+        816 => Exact(1),     // RelayGetPower
+        817 => Exact(3),     // RelaySetPower
+        818 => AtLeast(3),   // RelayStatePower
+        _ => return None,
+    })
+}
+
+/// The name that the LIFX LAN docs give to a message type number, or `None` if it isn't a type
+/// this crate knows
+///
+/// These are the names from the docs, which aren't always the same as the names of the [Message]
+/// variants: type 101 is [Message::LightGet] here, but "GetColor" in the docs.
+///
+/// ```
+/// # use lifx_core::message_type_name;
+/// assert_eq!(message_type_name(101), Some("GetColor"));
+/// assert_eq!(message_type_name(9999), None);
+/// ```
+pub const fn message_type_name(typ: u16) -> Option<&'static str> {
+    Some(match typ {
+        2 => "GetService",
+        3 => "StateService",
+        12 => "GetHostInfo",
+        13 => "StateHostInfo",
+        14 => "GetHostFirmware",
+        15 => "StateHostFirmware",
+        16 => "GetWifiInfo",
+        17 => "StateWifiInfo",
+        18 => "GetWifiFirmware",
+        19 => "StateWifiFirmware",
+        20 => "GetPower",
+        21 => "SetPower",
+        22 => "StatePower",
+        23 => "GetLabel",
+        24 => "SetLabel",
+        25 => "StateLabel",
+        32 => "GetVersion",
+        33 => "StateVersion",
+        34 => "GetInfo",
+        35 => "StateInfo",
+        38 => "SetReboot",
+        45 => "Acknowledgement",
+        48 => "GetLocation",
+        49 => "SetLocation",
+        50 => "StateLocation",
+        51 => "GetGroup",
+        52 => "SetGroup",
+        53 => "StateGroup",
+        58 => "EchoRequest",
+        59 => "EchoResponse",
+        101 => "GetColor",
+        102 => "SetColor",
+        103 => "SetWaveform",
+        107 => "LightState",
+        116 => "GetLightPower",
+        117 => "SetLightPower",
+        118 => "StateLightPower",
+        119 => "SetWaveformOptional",
+        120 => "GetInfrared",
+        121 => "StateInfrared",
+        122 => "SetInfrared",
+        142 => "GetHevCycle",
+        143 => "SetHevCycle",
+        144 => "StateHevCycle",
+        145 => "GetHevCycleConfiguration",
+        146 => "SetHevCycleConfiguration",
+        147 => "StateHevCycleConfiguration",
+        148 => "GetLastHevCycleResult",
+        149 => "StateLastHevCycleResult",
+        501 => "SetColorZones",
+        502 => "GetColorZones",
+        503 => "StateZone",
+        506 => "StateMultiZone",
+        507 => "GetMultiZoneEffect",
+        508 => "SetMultiZoneEffect",
+        509 => "StateMultiZoneEffect",
+        510 => "SetExtendedColorZones",
+        511 => "GetExtendedColorZones",
+        512 => "StateExtendedColorZones",
+        718 => "GetTileEffect",
+        719 => "SetTileEffect",
+        720 => "StateTileEffect",
+        816 => "GetRPower",
+        817 => "SetRPower",
+        818 => "StateRPower",
+        _ => return None,
+    })
+}
+
+/// Whether a message type number is one that devices send (a `State` message, an
+/// [Acknowledgement](Message::Acknowledgement), or an [EchoResponse](Message::EchoResponse)),
+/// rather than one that controllers send
+///
+/// ```
+/// # use lifx_core::is_device_message;
+/// assert!(is_device_message(22)); // StatePower
+/// assert!(!is_device_message(21)); // SetPower
+/// ```
+pub fn is_device_message(typ: u16) -> bool {
+    match message_type_name(typ) {
+        Some(name) => {
+            name.starts_with("State")
+                || matches!(name, "LightState" | "Acknowledgement" | "EchoResponse")
+        }
+        None => false,
+    }
+}
+
+/// A link to the section of the LIFX LAN docs that describes a message type number, or `None` if
+/// it isn't a type this crate knows
+///
+/// Some old messages (like GetHostInfo) have since been dropped from the docs, so their links only
+/// lead to the page where they would be.
+///
+/// ```
+/// # use lifx_core::message_doc_url;
+/// assert_eq!(
+///     message_doc_url(17).as_deref(),
+///     Some("https://lan.developer.lifx.com/docs/information-messages#statewifiinfo---packet-17")
+/// );
+/// ```
+pub fn message_doc_url(typ: u16) -> Option<String> {
+    let name = message_type_name(typ)?;
+    // the docs have a page each for queries, changes, and replies
+    let page = if name.starts_with("Get") || name == "EchoRequest" {
+        "querying-the-device-for-data"
+    } else if name.starts_with("Set") {
+        "changing-a-device"
+    } else {
+        "information-messages"
+    };
+    Some(format!(
+        "https://lan.developer.lifx.com/docs/{}#{}---packet-{}",
+        page,
+        name.to_ascii_lowercase(),
+        typ
+    ))
+}
 
 /// Various message encoding/decoding errors
+///
+/// More variants may be added in future releases, so matches on this need a wildcard arm.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// This error means we were unable to parse a raw message because its type is unknown.
     ///
@@ -64,11 +443,55 @@ pub enum Error {
     /// This error means one of the message fields contains an invalid or unsupported value.
     #[error("protocol error: `{0}`")]
     ProtocolError(String),
+    /// This error means a color string couldn't be parsed.
+    ///
+    /// See the [color] module for the supported syntax.
+    #[error("invalid color: `{0}`")]
+    InvalidColor(String),
+    /// This error means a message is too large to be sent in a single UDP datagram.
+    ///
+    /// The value is the size of the packed message, which is more than [MAX_DATAGRAM_SIZE].
+    #[error("message of {0} bytes is too large to send in one datagram")]
+    MessageTooLarge(usize),
+    /// This error means a message's payload couldn't be decoded.
+    ///
+    /// Payloads are checked against [expected_payload_len] first, so this shouldn't normally
+    /// happen.  See [DecodeError] for where decoding stopped.
+    #[error(transparent)]
+    Decode(#[from] DecodeError),
+    /// This error means a message's payload isn't the size its type calls for.
+    ///
+    /// See [expected_payload_len].
+    #[error("message type {message_type} has a {actual}-byte payload, but should have {expected}")]
+    PayloadSizeMismatch {
+        message_type: u16,
+        expected: PayloadLen,
+        actual: usize,
+    },
 
     #[error("i/o error")]
     Io(#[from] io::Error),
 }
 
+/// Where decoding a message's payload failed
+///
+/// This is returned (as [Error::Decode]) by [Message::from_raw], and is mostly useful for working
+/// out what a device sent when it doesn't match the documented layout.
+#[derive(Error, Debug)]
+#[error("couldn't decode `{field}` of message type {message_type} at payload offset {offset}")]
+pub struct DecodeError {
+    /// The type of the message, from its [ProtocolHeader]
+    pub message_type: u16,
+    /// The name of the field that couldn't be read
+    pub field: &'static str,
+    /// The offset of the field from the start of the payload
+    ///
+    /// Add [HEADER_SIZE] to get the offset from the start of the datagram.
+    pub offset: usize,
+    #[source]
+    pub source: io::Error,
+}
+
 impl From<std::convert::Infallible> for Error {
     fn from(_: std::convert::Infallible) -> Self {
         unreachable!()
@@ -90,40 +513,70 @@ impl TryFrom<u8> for ApplicationRequest {
     }
 }
 
-impl TryFrom<u8> for Waveform {
-    type Error = Error;
-    fn try_from(val: u8) -> Result<Waveform, Error> {
-        match val {
-            0 => Ok(Waveform::Saw),
-            1 => Ok(Waveform::Sine),
-            2 => Ok(Waveform::HalfSign),
-            3 => Ok(Waveform::Triangle),
-            4 => Ok(Waveform::Pulse),
-            x => Err(Error::ProtocolError(format!(
-                "Unknown waveform value {}",
-                x
-            ))),
+/// Conversions to and from the wire value of an enum with an `Unknown(u8)` variant
+macro_rules! wire_enum {
+    ($t:ident { $( $v:ident = $n:literal ),* $(,)? }) => {
+        impl From<u8> for $t {
+            fn from(val: u8) -> $t {
+                match val {
+                    $( $n => $t::$v, )*
+                    x => $t::Unknown(x),
+                }
+            }
         }
-    }
-}
 
-impl TryFrom<u8> for Service {
-    type Error = Error;
-    fn try_from(val: u8) -> Result<Service, Error> {
-        match val {
-            x if x == Service::UDP as u8 => Ok(Service::UDP),
-            x if x == Service::Reserved1 as u8 => Ok(Service::Reserved1),
-            x if x == Service::Reserved2 as u8 => Ok(Service::Reserved2),
-            x if x == Service::Reserved3 as u8 => Ok(Service::Reserved3),
-            x if x == Service::Reserved4 as u8 => Ok(Service::Reserved4),
-            val => Err(Error::ProtocolError(format!(
-                "Unknown service value {}",
-                val
-            ))),
+        impl From<$t> for u8 {
+            fn from(val: $t) -> u8 {
+                match val {
+                    $( $t::$v => $n, )*
+                    $t::Unknown(x) => x,
+                }
+            }
         }
-    }
+    };
 }
 
+wire_enum!(Service {
+    UDP = 1,
+    Reserved1 = 2,
+    Reserved2 = 3,
+    Reserved3 = 4,
+    Reserved4 = 5,
+});
+
+wire_enum!(Waveform {
+    Saw = 0,
+    Sine = 1,
+    HalfSign = 2,
+    Triangle = 3,
+    Pulse = 4,
+});
+
+wire_enum!(LastHevCycleResult {
+    Success = 0,
+    Busy = 1,
+    InterruptedByReset = 2,
+    InterruptedByHomekit = 3,
+    InterruptedByLan = 4,
+    InterruptedByCloud = 5,
+    None = 255,
+});
+
+wire_enum!(MultiZoneEffectType {
+    Off = 0,
+    Move = 1,
+    Reserved1 = 2,
+    Reserved2 = 3,
+});
+
+wire_enum!(TileEffectType {
+    Off = 0,
+    Reserved1 = 1,
+    Morph = 2,
+    Flame = 3,
+    Reserved2 = 4,
+});
+
 impl TryFrom<u16> for PowerLevel {
     type Error = Error;
     fn try_from(val: u16) -> Result<PowerLevel, Error> {
@@ -135,20 +588,119 @@ impl TryFrom<u16> for PowerLevel {
     }
 }
 
+/// The 64-byte payload carried by [Message::EchoRequest] and [Message::EchoResponse]
+///
+/// Shorter payloads can be constructed from a byte slice, in which case the remaining bytes are
+/// zero-filled:
+///
+/// ```
+/// # use lifx_core::EchoPayload;
+/// let payload = EchoPayload::from(&b"ping"[..]);
+/// assert_eq!(&payload[..4], b"ping");
+/// assert_eq!(payload, b"ping"[..]);
+/// ```
 #[derive(Copy, Clone, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct EchoPayload(pub [u8; 64]);
 
+impl From<&[u8]> for EchoPayload {
+    /// Copies up to 64 bytes from the given slice, zero-padding the rest.
+    ///
+    /// Any bytes past the first 64 are ignored.
+    fn from(b: &[u8]) -> Self {
+        let mut val = [0; 64];
+        let len = b.len().min(64);
+        val[..len].copy_from_slice(&b[..len]);
+        EchoPayload(val)
+    }
+}
+
+impl From<[u8; 64]> for EchoPayload {
+    fn from(b: [u8; 64]) -> Self {
+        EchoPayload(b)
+    }
+}
+
+impl std::ops::Deref for EchoPayload {
+    type Target = [u8];
+    fn deref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+impl AsRef<[u8]> for EchoPayload {
+    fn as_ref(&self) -> &[u8] {
+        &self.0
+    }
+}
+
+/// Compares against a (possibly shorter) byte slice, treating any missing trailing bytes as zero.
+///
+/// This matches the zero-padding done by `EchoPayload::from(&[u8])`.
+impl std::cmp::PartialEq<[u8]> for EchoPayload {
+    fn eq(&self, other: &[u8]) -> bool {
+        other.len() <= 64 && *self == EchoPayload::from(other)
+    }
+}
+
 impl std::fmt::Debug for EchoPayload {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
-        write!(f, "<EchoPayload>")
+        write!(f, "EchoPayload({})", self)
+    }
+}
+
+/// Formats the payload as a lowercase hex string
+impl std::fmt::Display for EchoPayload {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        for b in &self.0 {
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
     }
 }
 
+/// A 16-byte identifier, used for groups and locations
+///
+/// The LIFX docs describe these as UUIDs, and they are displayed using the canonical hyphenated
+/// UUID format.  With the `uuid` feature enabled, these can be converted to and from `uuid::Uuid`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct LifxIdent(pub [u8; 16]);
 
+impl std::fmt::Display for LifxIdent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        for (idx, b) in self.0.iter().enumerate() {
+            if idx == 4 || idx == 6 || idx == 8 || idx == 10 {
+                write!(f, "-")?;
+            }
+            write!(f, "{:02x}", b)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl LifxIdent {
+    /// Interprets this identifier as a UUID
+    pub fn as_uuid(&self) -> uuid::Uuid {
+        uuid::Uuid::from_bytes(self.0)
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl From<uuid::Uuid> for LifxIdent {
+    fn from(u: uuid::Uuid) -> Self {
+        LifxIdent(*u.as_bytes())
+    }
+}
+
+#[cfg(feature = "uuid")]
+impl From<LifxIdent> for uuid::Uuid {
+    fn from(ident: LifxIdent) -> Self {
+        ident.as_uuid()
+    }
+}
+
 /// Lifx strings are fixed-length (32-bytes maximum)
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub struct LifxString(CString);
@@ -171,6 +723,35 @@ impl LifxString {
     pub fn cstr(&self) -> &CStr {
         &self.0
     }
+
+    /// Truncates to the 31 bytes that fit in a message, dropping any partial UTF-8 character
+    /// left at the end (see [Message::normalize])
+    fn canonicalize(&mut self) {
+        let bytes = self.0.as_bytes();
+        let mut len = bytes.len().min(31);
+        if let Err(e) = std::str::from_utf8(&bytes[..len]) {
+            // an incomplete character at the end, rather than invalid bytes in the middle
+            if e.error_len().is_none() {
+                len = e.valid_up_to();
+            }
+        }
+        if len < bytes.len() {
+            // a prefix of a CString has no nul bytes in it
+            self.0 = CString::new(&bytes[..len]).unwrap();
+        }
+    }
+}
+
+/// Zeroes the colors after the first `count`
+fn clear_unused(colors: &mut [HSBK], count: u8) {
+    for color in colors.iter_mut().skip(count as usize) {
+        *color = HSBK {
+            hue: 0,
+            saturation: 0,
+            brightness: 0,
+            kelvin: 0,
+        };
+    }
 }
 
 impl std::fmt::Display for LifxString {
@@ -203,449 +784,137 @@ impl<'a> arbitrary::Arbitrary<'a> for LifxString {
     }
 }
 
-trait LittleEndianWriter<T>: WriteBytesExt {
-    fn write_val(&mut self, v: T) -> Result<(), io::Error>;
-}
+macro_rules! unpack {
+    ($msg:ident, $typ:ident, $( $n:ident: $t:ty ),*) => {
+        {
+        let mut r = PayloadReader::new(&$msg.payload);
+        $(
+            let offset = r.offset();
+            let $n: $t = r.get().map_err(|source| DecodeError {
+                message_type: $msg.protocol_header.typ,
+                field: stringify!($n),
+                offset,
+                source,
+            })?;
+        )*
 
-macro_rules! derive_writer {
-{ $( $m:ident: $t:ty ),*} => {
-    $(
-        impl<T: WriteBytesExt> LittleEndianWriter<$t> for T {
-            fn write_val(&mut self, v: $t) -> Result<(), io::Error> {
-                self . $m ::<LittleEndian>(v)
-            }
+            Message::$typ {
+            $(
+                    $n: $n.try_into()?,
+            )*
         }
-    )*
-
-}
-}
-
-derive_writer! { write_u32: u32, write_u16: u16, write_i16: i16, write_u64: u64, write_f32: f32 }
-
-#[cfg(fuzzing)]
-impl<T: WriteBytesExt> LittleEndianWriter<ComparableFloat> for T {
-    fn write_val(&mut self, v: ComparableFloat) -> Result<(), io::Error> {
-        self.write_f32::<LittleEndian>(v.0)
-    }
-}
-
-impl<T: WriteBytesExt> LittleEndianWriter<u8> for T {
-    fn write_val(&mut self, v: u8) -> Result<(), io::Error> {
-        self.write_u8(v)
-    }
+        }
+    };
 }
 
-impl<T: WriteBytesExt> LittleEndianWriter<bool> for T {
-    fn write_val(&mut self, v: bool) -> Result<(), io::Error> {
-        self.write_u8(if v { 1 } else { 0 })
-    }
-}
+//trace_macros!(true);
+//message_types! {
+//    /// GetService - 2
+//    ///
+//    /// Sent by a client to acquire responses from all devices on the local network.
+//    GetService(2, ),
+//    /// StateService - 3
+//    ///
+//    /// Response to GetService message.  Provides the device Service and Port.  If the Service
+//    /// is temporarily unavailable, then the port value will be zero
+//    StateService(3, {
+//        service: Service,
+//        port: u32
+//    })
+//}
+//trace_macros!(false);
 
-impl<T> LittleEndianWriter<LifxString> for T
-where
-    T: WriteBytesExt,
-{
-    fn write_val(&mut self, v: LifxString) -> Result<(), io::Error> {
-        let b = v.0.to_bytes();
-        for idx in 0..32 {
-            if idx >= b.len() {
-                self.write_u8(0)?;
-            } else {
-                self.write_u8(b[idx])?;
-            }
-        }
-        Ok(())
-    }
+/// What services are exposed by the device.
+///
+/// LIFX only documents the UDP service, though bulbs may support other undocumented services.
+/// Those are decoded as [Service::Unknown], which keeps the raw value.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
+pub enum Service {
+    UDP,
+    Reserved1,
+    Reserved2,
+    Reserved3,
+    Reserved4,
+    /// A value without a variant of its own, kept so that it can be sent on unchanged
+    Unknown(u8),
 }
 
-impl<T> LittleEndianWriter<LifxIdent> for T
-where
-    T: WriteBytesExt,
-{
-    fn write_val(&mut self, v: LifxIdent) -> Result<(), io::Error> {
-        for idx in 0..16 {
-            self.write_u8(v.0[idx])?;
-        }
-        Ok(())
-    }
+#[repr(u16)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum PowerLevel {
+    Standby = 0,
+    Enabled = 65535,
 }
 
-impl<T> LittleEndianWriter<EchoPayload> for T
-where
-    T: WriteBytesExt,
-{
-    fn write_val(&mut self, v: EchoPayload) -> Result<(), io::Error> {
-        for idx in 0..64 {
-            self.write_u8(v.0[idx])?;
-        }
-        Ok(())
-    }
+/// Controls how/when multizone devices apply color changes
+///
+/// See also [Message::SetColorZones].
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub enum ApplicationRequest {
+    /// Don't apply the requested changes until a message with Apply or ApplyOnly is sent
+    NoApply = 0,
+    /// Apply the changes immediately and apply any pending changes
+    Apply = 1,
+    /// Ignore the requested changes in this message and only apply pending changes
+    ApplyOnly = 2,
 }
 
-impl<T> LittleEndianWriter<HSBK> for T
-where
-    T: WriteBytesExt,
-{
-    fn write_val(&mut self, v: HSBK) -> Result<(), io::Error> {
-        self.write_val(v.hue)?;
-        self.write_val(v.saturation)?;
-        self.write_val(v.brightness)?;
-        self.write_val(v.kelvin)?;
-        Ok(())
-    }
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
+pub enum Waveform {
+    Saw,
+    Sine,
+    HalfSign,
+    Triangle,
+    Pulse,
+    /// A value without a variant of its own, kept so that it can be sent on unchanged
+    Unknown(u8),
 }
 
-impl<T> LittleEndianWriter<PowerLevel> for T
-where
-    T: WriteBytesExt,
-{
-    fn write_val(&mut self, v: PowerLevel) -> Result<(), io::Error> {
-        self.write_u16::<LittleEndian>(v as u16)
-    }
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
+pub enum LastHevCycleResult {
+    Success,
+    Busy,
+    InterruptedByReset,
+    InterruptedByHomekit,
+    InterruptedByLan,
+    InterruptedByCloud,
+    None,
+    /// A value without a variant of its own, kept so that it can be sent on unchanged
+    Unknown(u8),
 }
 
-impl<T> LittleEndianWriter<ApplicationRequest> for T
-where
-    T: WriteBytesExt,
-{
-    fn write_val(&mut self, v: ApplicationRequest) -> Result<(), io::Error> {
-        self.write_u8(v as u8)
-    }
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
+pub enum MultiZoneEffectType {
+    Off,
+    Move,
+    Reserved1,
+    Reserved2,
+    /// A value without a variant of its own, kept so that it can be sent on unchanged
+    Unknown(u8),
 }
 
-impl<T> LittleEndianWriter<Waveform> for T
-where
-    T: WriteBytesExt,
-{
-    fn write_val(&mut self, v: Waveform) -> Result<(), io::Error> {
-        self.write_u8(v as u8)
-    }
-}
-
-impl<T> LittleEndianWriter<LastHevCycleResult> for T
-where
-    T: WriteBytesExt,
-{
-    fn write_val(&mut self, v: LastHevCycleResult) -> Result<(), io::Error> {
-        self.write_u8(v as u8)
-    }
-}
-
-impl<T> LittleEndianWriter<MultiZoneEffectType> for T
-where
-    T: WriteBytesExt,
-{
-    fn write_val(&mut self, v: MultiZoneEffectType) -> Result<(), io::Error> {
-        self.write_u8(v as u8)
-    }
-}
-
-impl<T> LittleEndianWriter<&Box<[HSBK; 82]>> for T
-where
-    T: WriteBytesExt,
-{
-    fn write_val(&mut self, v: &Box<[HSBK; 82]>) -> Result<(), io::Error> {
-        for elem in &**v {
-            self.write_val(*elem)?;
-        }
-        Ok(())
-    }
-}
-
-impl<T> LittleEndianWriter<&[u8; 32]> for T
-where
-    T: WriteBytesExt,
-{
-    fn write_val(&mut self, v: &[u8; 32]) -> Result<(), io::Error> {
-        self.write_all(v)
-    }
-}
-
-impl<T> LittleEndianWriter<&[u32; 8]> for T
-where
-    T: WriteBytesExt,
-{
-    fn write_val(&mut self, v: &[u32; 8]) -> Result<(), io::Error> {
-        for elem in v {
-            self.write_u32::<LittleEndian>(*elem)?;
-        }
-        Ok(())
-    }
-}
-
-trait LittleEndianReader<T> {
-    fn read_val(&mut self) -> Result<T, io::Error>;
-}
-
-macro_rules! derive_reader {
-{ $( $m:ident: $t:ty ),*} => {
-    $(
-        impl<T: ReadBytesExt> LittleEndianReader<$t> for T {
-            fn read_val(&mut self) -> Result<$t, io::Error> {
-                self . $m ::<LittleEndian>()
-            }
-        }
-    )*
-
-}
-}
-
-derive_reader! { read_u32: u32, read_u16: u16, read_i16: i16, read_u64: u64, read_f32: f32 }
-
-impl<R: ReadBytesExt> LittleEndianReader<u8> for R {
-    fn read_val(&mut self) -> Result<u8, io::Error> {
-        self.read_u8()
-    }
-}
-
-impl<R: ReadBytesExt> LittleEndianReader<bool> for R {
-    fn read_val(&mut self) -> Result<bool, io::Error> {
-        Ok(self.read_u8()? > 0)
-    }
-}
-
-impl<R: ReadBytesExt> LittleEndianReader<LastHevCycleResult> for R {
-    fn read_val(&mut self) -> Result<LastHevCycleResult, io::Error> {
-        let val: u8 = self.read_val()?;
-        match val {
-            0 => Ok(LastHevCycleResult::Success),
-            1 => Ok(LastHevCycleResult::Busy),
-            2 => Ok(LastHevCycleResult::InterruptedByReset),
-            3 => Ok(LastHevCycleResult::InterruptedByHomekit),
-            4 => Ok(LastHevCycleResult::InterruptedByLan),
-            5 => Ok(LastHevCycleResult::InterruptedByCloud),
-            _ => Ok(LastHevCycleResult::None),
-        }
-    }
-}
-
-impl<R: ReadBytesExt> LittleEndianReader<MultiZoneEffectType> for R {
-    fn read_val(&mut self) -> Result<MultiZoneEffectType, io::Error> {
-        let val: u8 = self.read_val()?;
-        match val {
-            0 => Ok(MultiZoneEffectType::Off),
-            1 => Ok(MultiZoneEffectType::Move),
-            2 => Ok(MultiZoneEffectType::Reserved1),
-            _ => Ok(MultiZoneEffectType::Reserved2),
-        }
-    }
-}
-
-impl<R: ReadBytesExt> LittleEndianReader<[u8; 32]> for R {
-    fn read_val(&mut self) -> Result<[u8; 32], io::Error> {
-        let mut data = [0; 32];
-        self.read_exact(&mut data)?;
-        Ok(data)
-    }
-}
-
-impl<R: ReadBytesExt> LittleEndianReader<[u32; 8]> for R {
-    fn read_val(&mut self) -> Result<[u32; 8], io::Error> {
-        let mut data = [0; 8];
-        for x in &mut data {
-            *x = self.read_u32::<LittleEndian>()?;
-        }
-        Ok(data)
-    }
-}
-
-impl<R: ReadBytesExt> LittleEndianReader<[HSBK; 82]> for R {
-    fn read_val(&mut self) -> Result<[HSBK; 82], io::Error> {
-        let mut data = [HSBK {
-            hue: 0,
-            saturation: 0,
-            brightness: 0,
-            kelvin: 0,
-        }; 82];
-        for x in &mut data {
-            *x = self.read_val()?;
-        }
-
-        Ok(data)
-    }
-}
-
-impl<R: ReadBytesExt> LittleEndianReader<HSBK> for R {
-    fn read_val(&mut self) -> Result<HSBK, io::Error> {
-        let hue = self.read_val()?;
-        let sat = self.read_val()?;
-        let bri = self.read_val()?;
-        let kel = self.read_val()?;
-        Ok(HSBK {
-            hue,
-            saturation: sat,
-            brightness: bri,
-            kelvin: kel,
-        })
-    }
-}
-
-impl<R: ReadBytesExt> LittleEndianReader<LifxIdent> for R {
-    fn read_val(&mut self) -> Result<LifxIdent, io::Error> {
-        let mut val = [0; 16];
-        for v in &mut val {
-            *v = self.read_val()?;
-        }
-        Ok(LifxIdent(val))
-    }
-}
-
-impl<R: ReadBytesExt> LittleEndianReader<LifxString> for R {
-    fn read_val(&mut self) -> Result<LifxString, io::Error> {
-        let mut bytes = Vec::new();
-        for _ in 0..31 {
-            let c: u8 = self.read_val()?;
-            if let Some(b) = std::num::NonZeroU8::new(c) {
-                bytes.push(b);
-            }
-        }
-        // read the null terminator
-        self.read_u8()?;
-
-        Ok(LifxString(CString::from(bytes)))
-    }
-}
-
-impl<R: ReadBytesExt> LittleEndianReader<EchoPayload> for R {
-    fn read_val(&mut self) -> Result<EchoPayload, io::Error> {
-        let mut val = [0; 64];
-        for v in val.iter_mut() {
-            *v = self.read_val()?;
-        }
-        Ok(EchoPayload(val))
-    }
-}
-
-impl<R: ReadBytesExt> LittleEndianReader<PowerLevel> for R {
-    fn read_val(&mut self) -> Result<PowerLevel, io::Error> {
-        let val: u16 = self.read_val()?;
-        if val == 0 {
-            Ok(PowerLevel::Standby)
-        } else {
-            Ok(PowerLevel::Enabled)
-        }
-    }
-}
-
-impl<R: ReadBytesExt> LittleEndianReader<Waveform> for R {
-    fn read_val(&mut self) -> Result<Waveform, io::Error> {
-        let v = self.read_u8()?;
-        match v {
-            0 => Ok(Waveform::Saw),
-            1 => Ok(Waveform::Sine),
-            2 => Ok(Waveform::HalfSign),
-            3 => Ok(Waveform::Triangle),
-            4 => Ok(Waveform::Pulse),
-            _ => Ok(Waveform::Saw), // default
-        }
-    }
-}
-
-macro_rules! unpack {
-    ($msg:ident, $typ:ident, $( $n:ident: $t:ty ),*) => {
-        {
-        let mut c = Cursor::new(&$msg.payload);
-        $(
-            let $n: $t = c.read_val()?;
-        )*
-
-            Message::$typ {
-            $(
-                    $n: $n.try_into()?,
-            )*
-        }
-        }
-    };
-}
-
-//trace_macros!(true);
-//message_types! {
-//    /// GetService - 2
-//    ///
-//    /// Sent by a client to acquire responses from all devices on the local network.
-//    GetService(2, ),
-//    /// StateService - 3
-//    ///
-//    /// Response to GetService message.  Provides the device Service and Port.  If the Service
-//    /// is temporarily unavailable, then the port value will be zero
-//    StateService(3, {
-//        service: Service,
-//        port: u32
-//    })
-//}
-//trace_macros!(false);
-
-/// What services are exposed by the device.
-///
-/// LIFX only documents the UDP service, though bulbs may support other undocumented services.
-/// Since these other services are unsupported by the lifx-core library, a message with a non-UDP
-/// service cannot be constructed.
-#[repr(u8)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
-pub enum Service {
-    UDP = 1,
-    Reserved1 = 2,
-    Reserved2 = 3,
-    Reserved3 = 4,
-    Reserved4 = 5,
-}
-
-#[repr(u16)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
-pub enum PowerLevel {
-    Standby = 0,
-    Enabled = 65535,
-}
-
-/// Controls how/when multizone devices apply color changes
-///
-/// See also [Message::SetColorZones].
-#[repr(u8)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
-pub enum ApplicationRequest {
-    /// Don't apply the requested changes until a message with Apply or ApplyOnly is sent
-    NoApply = 0,
-    /// Apply the changes immediately and apply any pending changes
-    Apply = 1,
-    /// Ignore the requested changes in this message and only apply pending changes
-    ApplyOnly = 2,
-}
-
-#[repr(u8)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
-pub enum Waveform {
-    Saw = 0,
-    Sine = 1,
-    HalfSign = 2,
-    Triangle = 3,
-    Pulse = 4,
-}
-
-#[repr(u8)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
-pub enum LastHevCycleResult {
-    Success = 0,
-    Busy = 1,
-    InterruptedByReset = 2,
-    InterruptedByHomekit = 3,
-    InterruptedByLan = 4,
-    InterruptedByCloud = 5,
-    None = 255,
-}
-
-#[repr(u8)]
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
-pub enum MultiZoneEffectType {
-    Off = 0,
-    Move = 1,
-    Reserved1 = 2,
-    Reserved2 = 3,
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[non_exhaustive]
+pub enum TileEffectType {
+    Off,
+    Reserved1,
+    Morph,
+    Flame,
+    Reserved2,
+    /// A value without a variant of its own, kept so that it can be sent on unchanged
+    Unknown(u8),
 }
 
 /// Decoded LIFX Messages
@@ -685,7 +954,8 @@ pub enum Message {
 
     /// Response to [Message::GetHostInfo] message.
     ///
-    /// Provides host MCU information.
+    /// Provides host MCU information.  This message is no longer in the LIFX docs, but devices still
+    /// answer it.
     ///
     /// Message type 13
     StateHostInfo {
@@ -695,8 +965,10 @@ pub enum Message {
         #[cfg(fuzzing)]
         signal: ComparableFloat,
         /// Bytes transmitted since power on
+        #[deprecated(note = "no longer documented, and current firmware doesn't fill it in")]
         tx: u32,
         /// Bytes received since power on
+        #[deprecated(note = "no longer documented, and current firmware doesn't fill it in")]
         rx: u32,
         reserved: i16,
     },
@@ -880,6 +1152,14 @@ pub enum Message {
         downtime: u64,
     },
 
+    /// Reboot the device
+    ///
+    /// The device will reply with an [Message::Acknowledgement] (if one was requested) before
+    /// rebooting.  See the [maintenance] module.
+    ///
+    /// Message type 38
+    SetReboot,
+
     /// Response to any message sent with ack_required set to 1. See message header frame address.
     ///
     /// (Note that technically this message has no payload, but the frame sequence number is stored
@@ -1025,6 +1305,9 @@ pub enum Message {
     ///
     /// The duration is the power level transition time in milliseconds.
     ///
+    /// The level can only be 0 or 65535, and is sent as it is.  [Message::light_power] builds
+    /// this message from a bool.
+    ///
     /// If the Frame Address res_required field is set to one (1) then the device will transmit a
     /// StatePower message.
     ///
@@ -1232,6 +1515,51 @@ pub enum Message {
         colors: Box<[HSBK; 82]>,
     },
 
+    /// Asks a matrix device (such as the Tile) for its current effect
+    ///
+    /// Message type 718
+    GetTileEffect { reserved6: u8, reserved7: u8 },
+
+    /// Starts or stops an effect on a matrix device
+    ///
+    /// Message type 719
+    SetTileEffect {
+        reserved8: u8,
+        reserved9: u8,
+        /// The unique value identifying this effect
+        instance_id: u32,
+        typ: TileEffectType,
+        /// The time it takes for one cycle of the effect in milliseconds
+        speed: u32,
+        /// The time the effect will run for in nanoseconds, or 0 to run until it's stopped
+        duration: u64,
+        reserved6: u32,
+        reserved7: u32,
+        /// Effect-specific settings
+        parameters: [u8; 32],
+        /// The number of colors in `palette` that are used
+        palette_count: u8,
+        palette: Box<[HSBK; 16]>,
+    },
+
+    /// Message type 720
+    StateTileEffect {
+        reserved0: u8,
+        /// The unique value identifying this effect
+        instance_id: u32,
+        typ: TileEffectType,
+        /// The time it takes for one cycle of the effect in milliseconds
+        speed: u32,
+        /// The amount of time left in the current effect in nanoseconds
+        duration: u64,
+        reserved6: u32,
+        reserved7: u32,
+        /// The parameters that was used in the request.
+        parameters: [u8; 32],
+        palette_count: u8,
+        palette: Box<[HSBK; 16]>,
+    },
+
     /// Get the power state of a relay
     ///
     /// This requires the device has the `relays` capability.
@@ -1267,6 +1595,82 @@ pub enum Message {
     },
 }
 
+/// A group of related message types, following the sections of the LAN protocol docs
+///
+/// This makes it possible to pick out, say, all of the multizone traffic without listing every
+/// type number.  See [Message::category] and [MessageCategory::of].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum MessageCategory {
+    /// Finding devices on the network ([Message::GetService] and [Message::StateService])
+    Discovery,
+    /// Things every device has, like its label, power, firmware, and group
+    Device,
+    /// The color, power, infrared, and HEV settings of a light
+    Light,
+    /// Strips and beams with more than one zone
+    MultiZone,
+    /// Tiles, and other matrix devices
+    Tile,
+    /// The relays in a LIFX switch
+    Relay,
+}
+
+impl MessageCategory {
+    /// Every category, in the order of the docs
+    pub const ALL: [MessageCategory; 6] = [
+        MessageCategory::Discovery,
+        MessageCategory::Device,
+        MessageCategory::Light,
+        MessageCategory::MultiZone,
+        MessageCategory::Tile,
+        MessageCategory::Relay,
+    ];
+
+    /// The category of a message type number, or `None` if it isn't a type this crate knows
+    ///
+    /// ```
+    /// # use lifx_core::MessageCategory;
+    /// assert_eq!(MessageCategory::of(107), Some(MessageCategory::Light)); // LightState
+    /// assert_eq!(MessageCategory::of(9999), None);
+    /// ```
+    pub fn of(typ: u16) -> Option<MessageCategory> {
+        expected_payload_len(typ)?;
+        // the docs number each section's messages from their own block
+        match typ {
+            2 | 3 => Some(MessageCategory::Discovery),
+            0..=99 => Some(MessageCategory::Device),
+            100..=199 => Some(MessageCategory::Light),
+            500..=599 => Some(MessageCategory::MultiZone),
+            700..=799 => Some(MessageCategory::Tile),
+            800..=899 => Some(MessageCategory::Relay),
+            _ => None,
+        }
+    }
+
+    /// Every message type number in this category that this crate knows
+    pub fn types(self) -> impl Iterator<Item = u16> {
+        (0..1000).filter(move |&typ| MessageCategory::of(typ) == Some(self))
+    }
+
+    /// The name of the section of the docs that this category follows, like `"MultiZone"`
+    pub fn name(self) -> &'static str {
+        match self {
+            MessageCategory::Discovery => "Discovery",
+            MessageCategory::Device => "Device",
+            MessageCategory::Light => "Light",
+            MessageCategory::MultiZone => "MultiZone",
+            MessageCategory::Tile => "Tile",
+            MessageCategory::Relay => "Relay",
+        }
+    }
+}
+
+impl std::fmt::Display for MessageCategory {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        f.write_str(self.name())
+    }
+}
+
 impl Message {
     /// Get the message type
     ///
@@ -1293,6 +1697,7 @@ impl Message {
             Message::StateVersion { .. } => 33,
             Message::GetInfo => 34,
             Message::StateInfo { .. } => 35,
+            Message::SetReboot => 38,
             Message::Acknowledgement { .. } => 45,
             Message::GetLocation => 48,
             Message::SetLocation { .. } => 49,
@@ -1331,662 +1736,56 @@ impl Message {
             Message::SetExtendedColorZones { .. } => 510,
             Message::GetExtendedColorZone => 511,
             Message::StateExtendedColorZones { .. } => 512,
+            Message::GetTileEffect { .. } => 718,
+            Message::SetTileEffect { .. } => 719,
+            Message::StateTileEffect { .. } => 720,
             Message::RelayGetPower { .. } => 816,
             Message::RelaySetPower { .. } => 817,
             Message::RelayStatePower { .. } => 818,
         }
     }
 
-    /// Tries to parse the payload in a [RawMessage], based on its message type.
-    pub fn from_raw(msg: &RawMessage) -> Result<Message, Error> {
-        match msg.protocol_header.typ {
-            2 => Ok(Message::GetService),
-            3 => Ok(unpack!(msg, StateService, service: u8, port: u32)),
-            12 => Ok(Message::GetHostInfo),
-            13 => Ok(unpack!(
-                msg,
-                StateHostInfo,
-                signal: f32,
-                tx: u32,
-                rx: u32,
-                reserved: i16
-            )),
-            14 => Ok(Message::GetHostFirmware),
-            15 => Ok(unpack!(
-                msg,
-                StateHostFirmware,
-                build: u64,
-                reserved: u64,
-                version_minor: u16,
-                version_major: u16
-            )),
-            16 => Ok(Message::GetWifiInfo),
-            17 => Ok(unpack!(
-                msg,
-                StateWifiInfo,
-                signal: f32,
-                reserved6: u32,
-                reserved7: u32,
-                reserved: i16
-            )),
-            18 => Ok(Message::GetWifiFirmware),
-            19 => Ok(unpack!(
-                msg,
-                StateWifiFirmware,
-                build: u64,
-                reserved: u64,
-                version_minor: u16,
-                version_major: u16
-            )),
-            20 => Ok(Message::GetPower),
-            21 => Ok(unpack!(msg, SetPower, level: PowerLevel)),
-            22 => Ok(unpack!(msg, StatePower, level: u16)),
-            23 => Ok(Message::GetLabel),
-            24 => Ok(unpack!(msg, SetLabel, label: LifxString)),
-            25 => Ok(unpack!(msg, StateLabel, label: LifxString)),
-            32 => Ok(Message::GetVersion),
-            33 => Ok(unpack!(
-                msg,
-                StateVersion,
-                vendor: u32,
-                product: u32,
-                reserved: u32
-            )),
-            34 => Ok(Message::GetInfo),
-            35 => Ok(unpack!(
-                msg,
-                StateInfo,
-                time: u64,
-                uptime: u64,
-                downtime: u64
-            )),
-            45 => Ok(Message::Acknowledgement {
-                seq: msg.frame_addr.sequence,
-            }),
-            48 => Ok(Message::GetLocation),
-            49 => Ok(unpack!(
-                msg,
-                SetLocation,
-                location: LifxIdent,
-                label: LifxString,
-                updated_at: u64
-            )),
-            50 => Ok(unpack!(
-                msg,
-                StateLocation,
-                location: LifxIdent,
-                label: LifxString,
-                updated_at: u64
-            )),
-            51 => Ok(Message::GetGroup),
-            52 => Ok(unpack!(
-                msg,
-                SetGroup,
-                group: LifxIdent,
-                label: LifxString,
-                updated_at: u64
-            )),
-            53 => Ok(unpack!(
-                msg,
-                StateGroup,
-                group: LifxIdent,
-                label: LifxString,
-                updated_at: u64
-            )),
-            58 => Ok(unpack!(msg, EchoRequest, payload: EchoPayload)),
-            59 => Ok(unpack!(msg, EchoResponse, payload: EchoPayload)),
-            101 => Ok(Message::LightGet),
-            102 => Ok(unpack!(
-                msg,
-                LightSetColor,
-                reserved: u8,
-                color: HSBK,
-                duration: u32
-            )),
-            103 => Ok(unpack!(
-                msg,
-                SetWaveform,
-                reserved: u8,
-                transient: bool,
-                color: HSBK,
-                period: u32,
-                cycles: f32,
-                skew_ratio: i16,
-                waveform: Waveform
-            )),
-            107 => Ok(unpack!(
-                msg,
-                LightState,
-                color: HSBK,
-                reserved: i16,
-                power: u16,
-                label: LifxString,
-                reserved2: u64
-            )),
-            116 => Ok(Message::LightGetPower),
-            117 => Ok(unpack!(msg, LightSetPower, level: u16, duration: u32)),
-            118 => {
-                let mut c = Cursor::new(&msg.payload);
-                Ok(Message::LightStatePower {
-                    level: c.read_val()?,
-                })
-            }
-            119 => Ok(unpack!(
-                msg,
-                SetWaveformOptional,
-                reserved: u8,
-                transient: bool,
-                color: HSBK,
-                period: u32,
-                cycles: f32,
-                skew_ratio: i16,
-                waveform: Waveform,
-                set_hue: bool,
-                set_saturation: bool,
-                set_brightness: bool,
-                set_kelvin: bool
-            )),
-            120 => Ok(Message::LightGetInfrared),
-            122 => Ok(unpack!(msg, LightSetInfrared, brightness: u16)),
-            142 => Ok(Message::LightGetHevCycle),
-            143 => Ok(unpack!(msg, LightSetHevCycle, enable: bool, duration: u32)),
-            144 => Ok(unpack!(
-                msg,
-                LightStateHevCycle,
-                duration: u32,
-                remaining: u32,
-                last_power: bool
-            )),
-            145 => Ok(Message::LightGetHevCycleConfiguration),
-            146 => Ok(unpack!(
-                msg,
-                LightSetHevCycleConfiguration,
-                indication: bool,
-                duration: u32
-            )),
-            147 => Ok(unpack!(
-                msg,
-                LightStateHevCycleConfiguration,
-                indication: bool,
-                duration: u32
-            )),
-            148 => Ok(Message::LightGetLastHevCycleResult),
-            149 => Ok(unpack!(
-                msg,
-                LightStateLastHevCycleResult,
-                result: LastHevCycleResult
-            )),
-            121 => Ok(unpack!(msg, LightStateInfrared, brightness: u16)),
-            501 => Ok(unpack!(
-                msg,
-                SetColorZones,
-                start_index: u8,
-                end_index: u8,
-                color: HSBK,
-                duration: u32,
-                apply: u8
-            )),
-            502 => Ok(unpack!(msg, GetColorZones, start_index: u8, end_index: u8)),
-            503 => Ok(unpack!(msg, StateZone, count: u8, index: u8, color: HSBK)),
-            506 => Ok(unpack!(
-                msg,
-                StateMultiZone,
-                count: u8,
-                index: u8,
-                color0: HSBK,
-                color1: HSBK,
-                color2: HSBK,
-                color3: HSBK,
-                color4: HSBK,
-                color5: HSBK,
-                color6: HSBK,
-                color7: HSBK
-            )),
-            507 => Ok(Message::GetMultiZoneEffect),
-            508 => Ok(unpack!(
-                msg,
-                SetMultiZoneEffect,
-                instance_id: u32,
-                typ: MultiZoneEffectType,
-                reserved: u16,
-                speed: u32,
-                duration: u64,
-                reserved7: u32,
-                reserved8: u32,
-                parameters: [u32; 8]
-            )),
-            509 => Ok(unpack!(
-                msg,
-                StateMultiZoneEffect,
-                instance_id: u32,
-                typ: MultiZoneEffectType,
-                reserved: u16,
-                speed: u32,
-                duration: u64,
-                reserved7: u32,
-                reserved8: u32,
-                parameters: [u32; 8]
-            )),
-            510 => Ok(unpack!(
-                msg,
-                SetExtendedColorZones,
-                duration: u32,
-                apply: u8,
-                zone_index: u16,
-                colors_count: u8,
-                colors: [HSBK; 82]
-            )),
-            511 => Ok(Message::GetExtendedColorZone),
-            512 => Ok(unpack!(
-                msg,
-                StateExtendedColorZones,
-                zones_count: u16,
-                zone_index: u16,
-                colors_count: u8,
-                colors: [HSBK; 82]
-            )),
-            816 => Ok(unpack!(msg, RelayGetPower, relay_index: u8)),
-            817 => Ok(unpack!(msg, RelaySetPower, relay_index: u8, level: u16)),
-            818 => Ok(unpack!(msg, RelayStatePower, relay_index: u8, level: u16)),
-            _ => Err(Error::UnknownMessageType(msg.protocol_header.typ)),
-        }
+    /// The name that the LIFX LAN docs give to this message (see [message_type_name])
+    ///
+    /// ```
+    /// # use lifx_core::Message;
+    /// assert_eq!(Message::LightGetPower.type_name(), "GetLightPower");
+    /// ```
+    pub fn type_name(&self) -> &'static str {
+        // every message has a name
+        message_type_name(self.get_num()).unwrap()
     }
-}
 
-/// Bulb color (Hue-Saturation-Brightness-Kelvin)
-///
-/// # Notes:
-///
-/// Colors are represented as Hue-Saturation-Brightness-Kelvin, or HSBK
-///
-/// When a light is displaying whites, saturation will be zero, hue will be ignored, and only
-/// brightness and kelvin will matter.
-///
-/// Normal values for "kelvin" are from 2500 (warm/yellow) to 9000 (cool/blue)
-///
-/// When a light is displaying colors, kelvin is ignored.
-///
-/// To display "pure" colors, set saturation to full (65535).
-#[derive(Debug, Copy, Clone, PartialEq, Eq)]
-#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
-pub struct HSBK {
-    pub hue: u16,
-    pub saturation: u16,
-    pub brightness: u16,
-    pub kelvin: u16,
-}
+    /// A link to the section of the LIFX LAN docs that describes this message (see
+    /// [message_doc_url])
+    pub fn doc_url(&self) -> String {
+        message_doc_url(self.get_num()).unwrap()
+    }
 
-impl HSBK {
-    pub fn describe(&self, short: bool) -> String {
-        match short {
-            true if self.saturation == 0 => format!("{}K", self.kelvin),
-            true => format!(
-                "{:.0}/{:.0}",
-                (self.hue as f32 / 65535.0) * 360.0,
-                self.saturation as f32 / 655.35
-            ),
-            false if self.saturation == 0 => format!(
-                "{:.0}% White ({})",
-                self.brightness as f32 / 655.35,
-                describe_kelvin(self.kelvin)
-            ),
-            false => format!(
-                "{}% hue: {} sat: {}",
-                self.brightness as f32 / 655.35,
-                self.hue,
-                self.saturation
-            ),
-        }
-    }
-}
-
-/// Describe (in english words) the color temperature as given in kelvin.
-///
-/// These descriptions match the values shown in the LIFX mobile app.
-pub fn describe_kelvin(k: u16) -> &'static str {
-    if k <= 2500 {
-        "Ultra Warm"
-    } else if k > 2500 && k <= 2700 {
-        "Incandescent"
-    } else if k > 2700 && k <= 3000 {
-        "Warm"
-    } else if k > 300 && k <= 3200 {
-        "Neutral Warm"
-    } else if k > 3200 && k <= 3500 {
-        "Neutral"
-    } else if k > 3500 && k <= 4000 {
-        "Cool"
-    } else if k > 400 && k <= 4500 {
-        "Cool Daylight"
-    } else if k > 4500 && k <= 5000 {
-        "Soft Daylight"
-    } else if k > 5000 && k <= 5500 {
-        "Daylight"
-    } else if k > 5500 && k <= 6000 {
-        "Noon Daylight"
-    } else if k > 6000 && k <= 6500 {
-        "Bright Daylight"
-    } else if k > 6500 && k <= 7000 {
-        "Cloudy Daylight"
-    } else if k > 7000 && k <= 7500 {
-        "Blue Daylight"
-    } else if k > 7500 && k <= 8000 {
-        "Blue Overcast"
-    } else if k > 8000 && k <= 8500 {
-        "Blue Water"
-    } else {
-        "Blue Ice"
-    }
-}
-
-impl HSBK {}
-
-/// The raw message structure
-///
-/// Contains a low-level protocol info.  This is what is sent and received via UDP packets.
-///
-/// To parse the payload, use [Message::from_raw].
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct RawMessage {
-    pub frame: Frame,
-    pub frame_addr: FrameAddress,
-    pub protocol_header: ProtocolHeader,
-    pub payload: Vec<u8>,
-}
-
-/// The Frame section contains information about the following:
-///
-/// * Size of the entire message
-/// * LIFX Protocol number: must be 1024 (decimal)
-/// * Use of the Frame Address target field
-/// * Source identifier
-///
-/// The `tagged` field is a boolean that indicates whether the Frame Address target field is
-/// being used to address an individual device or all devices.  If `tagged` is true, then the
-/// `target` field should be all zeros.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct Frame {
-    /// 16 bits: Size of entire message in bytes including this field
-    pub size: u16,
-
-    /// 2 bits: Message origin indicator: must be zero (0)
-    pub origin: u8,
-
-    /// 1 bit: Determines usage of the Frame Address target field
-    pub tagged: bool,
-
-    /// 1 bit: Message includes a target address: must be one (1)
-    pub addressable: bool,
-
-    /// 12 bits: Protocol number: must be 1024 (decimal)
-    pub protocol: u16,
-
-    /// 32 bits: Source identifier: unique value set by the client, used by responses.
-    ///
-    /// If the source identifier is zero, then the LIFX device may send a broadcast message that can
-    /// be received by all clients on the same subnet.
-    ///
-    /// If this packet is a reply, then this source field will be set to the same value as the client-
-    /// sent request packet.
-    pub source: u32,
-}
-
-/// The Frame Address section contains the following routing information:
-///
-/// * Target device address
-/// * Acknowledgement message is required flag
-/// * State response message is required flag
-/// * Message sequence number
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct FrameAddress {
-    /// 64 bits: 6 byte device address (MAC address) or zero (0) means all devices
-    pub target: u64,
-
-    /// 48 bits: Must all be zero (0)
-    pub reserved: [u8; 6],
-
-    /// 6 bits: Reserved
-    pub reserved2: u8,
-
-    /// 1 bit: Acknowledgement message required
-    pub ack_required: bool,
-
-    /// 1 bit: Response message required
-    pub res_required: bool,
-
-    /// 8 bits: Wrap around message sequence number
-    pub sequence: u8,
-}
-
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub struct ProtocolHeader {
-    /// 64 bits: Reserved
-    pub reserved: u64,
-
-    /// 16 bits: Message type determines the payload being used
+    /// The group of related messages that this message belongs to
     ///
-    /// See also [Message::get_num]
-    pub typ: u16,
-
-    /// 16 bits: Reserved
-    pub reserved2: u16,
-}
-
-impl Frame {
-    /// packed sized, in bytes
-    fn packed_size() -> usize {
-        8
-    }
-
-    fn validate(&self) {
-        assert!(self.origin < 4);
-        assert!(self.addressable);
-        assert_eq!(self.protocol, 1024);
-    }
-
-    fn pack(&self) -> Result<Vec<u8>, Error> {
-        let mut v = Vec::with_capacity(Self::packed_size());
-
-        v.write_u16::<LittleEndian>(self.size)?;
-
-        // pack origin + tagged + addressable +  protocol as a u16
-        let mut d: u16 = (<u16 as From<u8>>::from(self.origin) & 0b11) << 14;
-        d += if self.tagged { 1 } else { 0 } << 13;
-        d += if self.addressable { 1 } else { 0 } << 12;
-        d += (self.protocol & 0b1111_1111_1111) as u16;
-
-        v.write_u16::<LittleEndian>(d)?;
-
-        v.write_u32::<LittleEndian>(self.source)?;
-
-        Ok(v)
-    }
-
-    fn unpack(v: &[u8]) -> Result<Frame, Error> {
-        let mut c = Cursor::new(v);
-
-        let size = c.read_val()?;
-
-        // origin + tagged + addressable + protocol
-        let d: u16 = c.read_val()?;
-
-        let origin: u8 = ((d & 0b1100_0000_0000_0000) >> 14) as u8;
-        let tagged: bool = (d & 0b0010_0000_0000_0000) > 0;
-        let addressable = (d & 0b0001_0000_0000_0000) > 0;
-        let protocol: u16 = d & 0b0000_1111_1111_1111;
-
-        if protocol != 1024 {
-            return Err(Error::ProtocolError(format!(
-                "Unpacked frame had protocol version {}",
-                protocol
-            )));
-        }
-
-        let source = c.read_val()?;
-
-        let frame = Frame {
-            size,
-            origin,
-            tagged,
-            addressable,
-            protocol,
-            source,
-        };
-        Ok(frame)
-    }
-}
-
-impl FrameAddress {
-    fn packed_size() -> usize {
-        16
-    }
-    fn validate(&self) {
-        //assert_eq!(self.reserved, [0;6]);
-        //assert_eq!(self.reserved2, 0);
-    }
-    fn pack(&self) -> Result<Vec<u8>, Error> {
-        let mut v = Vec::with_capacity(Self::packed_size());
-        v.write_u64::<LittleEndian>(self.target)?;
-        for idx in 0..6 {
-            v.write_u8(self.reserved[idx])?;
-        }
-
-        let b: u8 = (self.reserved2 << 2)
-            + if self.ack_required { 2 } else { 0 }
-            + if self.res_required { 1 } else { 0 };
-        v.write_u8(b)?;
-        v.write_u8(self.sequence)?;
-        Ok(v)
-    }
-
-    fn unpack(v: &[u8]) -> Result<FrameAddress, Error> {
-        let mut c = Cursor::new(v);
-
-        let target = c.read_val()?;
-
-        let mut reserved: [u8; 6] = [0; 6];
-        for slot in &mut reserved {
-            *slot = c.read_val()?;
-        }
-
-        let b: u8 = c.read_val()?;
-        let reserved2: u8 = (b & 0b1111_1100) >> 2;
-        let ack_required = (b & 0b10) > 0;
-        let res_required = (b & 0b01) > 0;
-
-        let sequence = c.read_val()?;
-
-        let f = FrameAddress {
-            target,
-            reserved,
-            reserved2,
-            ack_required,
-            res_required,
-            sequence,
-        };
-        f.validate();
-        Ok(f)
-    }
-}
-
-impl ProtocolHeader {
-    fn packed_size() -> usize {
-        12
-    }
-    fn validate(&self) {
-        //assert_eq!(self.reserved, 0);
-        //assert_eq!(self.reserved2, 0);
-    }
-
-    /// Packs this part of the packet into some bytes
-    pub fn pack(&self) -> Result<Vec<u8>, Error> {
-        let mut v = Vec::with_capacity(Self::packed_size());
-        v.write_u64::<LittleEndian>(self.reserved)?;
-        v.write_u16::<LittleEndian>(self.typ)?;
-        v.write_u16::<LittleEndian>(self.reserved2)?;
-        Ok(v)
+    /// ```
+    /// # use lifx_core::{Message, MessageCategory};
+    /// assert_eq!(Message::GetLabel.category(), MessageCategory::Device);
+    /// ```
+    pub fn category(&self) -> MessageCategory {
+        // every message is in a category
+        MessageCategory::of(self.get_num()).unwrap()
     }
-    fn unpack(v: &[u8]) -> Result<ProtocolHeader, Error> {
-        let mut c = Cursor::new(v);
 
-        let reserved = c.read_val()?;
-        let typ = c.read_val()?;
-        let reserved2 = c.read_val()?;
-
-        let f = ProtocolHeader {
-            reserved,
-            typ,
-            reserved2,
-        };
-        f.validate();
-        Ok(f)
+    /// The size (in bytes) of this message's payload, once packed
+    ///
+    /// Every message type has a fixed size payload, so this doesn't need to actually pack the
+    /// message.  The full datagram is this plus [HEADER_SIZE].
+    pub fn payload_size(&self) -> usize {
+        // every message has an entry in the table
+        expected_payload_len(self.get_num()).unwrap().size()
     }
-}
 
-/// Options used to construct a [RawMessage].
-///
-/// See also [RawMessage::build].
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
-pub struct BuildOptions {
-    /// If not `None`, this is the ID of the device you want to address.
-    ///
-    /// To look up the ID of a device, extract it from the [FrameAddress::target] field when a
-    /// device sends a [Message::StateService] message.
-    pub target: Option<u64>,
-    /// Acknowledgement message required.
-    ///
-    /// Causes the light to send an [Message::Acknowledgement] message.
-    pub ack_required: bool,
-    /// Response message required.
-    ///
-    /// Some message types are sent by clients to get data from a light.  These should always have
-    /// `res_required` set to true.
-    pub res_required: bool,
-    /// A wrap around sequence number.  Optional (can be zero).
-    ///
-    /// By providing a unique sequence value, the response message will also contain the same
-    /// sequence number, allowing a client to distinguish between different messages sent with the
-    /// same `source` identifier.
-    pub sequence: u8,
-    /// A unique client identifier. Optional (can be zero).
-    ///
-    /// If the source is non-zero, then the LIFX device with send a unicast message to the IP
-    /// address/port of the client that sent the originating message.  If zero, then the LIFX
-    /// device may send a broadcast message that can be received by all clients on the same sub-net.
-    pub source: u32,
-}
-
-impl RawMessage {
-    /// Build a RawMessage (which is suitable for sending on the network) from a given Message
-    /// type.
-    ///
-    /// If [BuildOptions::target] is None, then the message is addressed to all devices.  Else it should be a
-    /// bulb UID (MAC address)
-    pub fn build(options: &BuildOptions, typ: Message) -> Result<RawMessage, Error> {
-        let frame = Frame {
-            size: 0,
-            origin: 0,
-            tagged: options.target.is_none(),
-            addressable: true,
-            protocol: 1024,
-            source: options.source,
-        };
-        let addr = FrameAddress {
-            target: options.target.unwrap_or(0),
-            reserved: [0; 6],
-            reserved2: 0,
-            ack_required: options.ack_required,
-            res_required: options.res_required,
-            sequence: options.sequence,
-        };
-        let phead = ProtocolHeader {
-            reserved: 0,
-            reserved2: 0,
-            typ: typ.get_num(),
-        };
-
-        let mut v = Vec::new();
-        match typ {
+    /// Appends the packed payload of this message to `v`
+    fn write_payload(&self, v: &mut Vec<u8>) -> Result<(), Error> {
+        let mut w = PayloadWriter::new(v);
+        match *self {
             Message::GetService
             | Message::GetHostInfo
             | Message::GetHostFirmware
@@ -1996,6 +1795,7 @@ impl RawMessage {
             | Message::GetLabel
             | Message::GetVersion
             | Message::GetInfo
+            | Message::SetReboot
             | Message::Acknowledgement { .. }
             | Message::GetLocation
             | Message::GetGroup
@@ -2016,11 +1816,11 @@ impl RawMessage {
                 duration,
                 apply,
             } => {
-                v.write_val(start_index)?;
-                v.write_val(end_index)?;
-                v.write_val(color)?;
-                v.write_val(duration)?;
-                v.write_val(apply)?;
+                w.put_u8(start_index);
+                w.put_u8(end_index);
+                w.put_hsbk(color);
+                w.put_u32(duration);
+                w.put_u8(apply as u8);
             }
             Message::SetWaveform {
                 reserved,
@@ -2031,13 +1831,13 @@ impl RawMessage {
                 skew_ratio,
                 waveform,
             } => {
-                v.write_val(reserved)?;
-                v.write_val(transient)?;
-                v.write_val(color)?;
-                v.write_val(period)?;
-                v.write_val(cycles)?;
-                v.write_val(skew_ratio)?;
-                v.write_val(waveform)?;
+                w.put_u8(reserved);
+                w.put_bool(transient);
+                w.put_hsbk(color);
+                w.put_u32(period);
+                w.put_f32(cycles);
+                w.put_i16(skew_ratio);
+                w.put_u8(waveform.into());
             }
             Message::SetWaveformOptional {
                 reserved,
@@ -2052,33 +1852,33 @@ impl RawMessage {
                 set_brightness,
                 set_kelvin,
             } => {
-                v.write_val(reserved)?;
-                v.write_val(transient)?;
-                v.write_val(color)?;
-                v.write_val(period)?;
-                v.write_val(cycles)?;
-                v.write_val(skew_ratio)?;
-                v.write_val(waveform)?;
-                v.write_val(set_hue)?;
-                v.write_val(set_saturation)?;
-                v.write_val(set_brightness)?;
-                v.write_val(set_kelvin)?;
+                w.put_u8(reserved);
+                w.put_bool(transient);
+                w.put_hsbk(color);
+                w.put_u32(period);
+                w.put_f32(cycles);
+                w.put_i16(skew_ratio);
+                w.put_u8(waveform.into());
+                w.put_bool(set_hue);
+                w.put_bool(set_saturation);
+                w.put_bool(set_brightness);
+                w.put_bool(set_kelvin);
             }
             Message::GetColorZones {
                 start_index,
                 end_index,
             } => {
-                v.write_val(start_index)?;
-                v.write_val(end_index)?;
+                w.put_u8(start_index);
+                w.put_u8(end_index);
             }
             Message::StateZone {
                 count,
                 index,
                 color,
             } => {
-                v.write_val(count)?;
-                v.write_val(index)?;
-                v.write_val(color)?;
+                w.put_u8(count);
+                w.put_u8(index);
+                w.put_hsbk(color);
             }
             Message::StateMultiZone {
                 count,
@@ -2092,51 +1892,52 @@ impl RawMessage {
                 color6,
                 color7,
             } => {
-                v.write_val(count)?;
-                v.write_val(index)?;
-                v.write_val(color0)?;
-                v.write_val(color1)?;
-                v.write_val(color2)?;
-                v.write_val(color3)?;
-                v.write_val(color4)?;
-                v.write_val(color5)?;
-                v.write_val(color6)?;
-                v.write_val(color7)?;
-            }
-            Message::LightStateInfrared { brightness } => v.write_val(brightness)?,
-            Message::LightSetInfrared { brightness } => v.write_val(brightness)?,
+                w.put_u8(count);
+                w.put_u8(index);
+                w.put_hsbk(color0);
+                w.put_hsbk(color1);
+                w.put_hsbk(color2);
+                w.put_hsbk(color3);
+                w.put_hsbk(color4);
+                w.put_hsbk(color5);
+                w.put_hsbk(color6);
+                w.put_hsbk(color7);
+            }
+            Message::LightStateInfrared { brightness } => w.put_u16(brightness),
+            Message::LightSetInfrared { brightness } => w.put_u16(brightness),
             Message::SetLocation {
                 location,
-                label,
+                ref label,
                 updated_at,
             } => {
-                v.write_val(location)?;
-                v.write_val(label)?;
-                v.write_val(updated_at)?;
+                w.put_ident(&location);
+                w.put_string(label);
+                w.put_u64(updated_at);
             }
             Message::SetGroup {
                 group,
-                label,
+                ref label,
                 updated_at,
             } => {
-                v.write_val(group)?;
-                v.write_val(label)?;
-                v.write_val(updated_at)?;
+                w.put_ident(&group);
+                w.put_string(label);
+                w.put_u64(updated_at);
             }
             Message::StateService { port, service } => {
-                v.write_val(service as u8)?;
-                v.write_val(port)?;
+                w.put_u8(service.into());
+                w.put_u32(port);
             }
+            #[allow(deprecated)]
             Message::StateHostInfo {
                 signal,
                 tx,
                 rx,
                 reserved,
             } => {
-                v.write_val(signal)?;
-                v.write_val(tx)?;
-                v.write_val(rx)?;
-                v.write_val(reserved)?;
+                w.put_f32(signal);
+                w.put_u32(tx);
+                w.put_u32(rx);
+                w.put_i16(reserved);
             }
             Message::StateHostFirmware {
                 build,
@@ -2144,10 +1945,10 @@ impl RawMessage {
                 version_minor,
                 version_major,
             } => {
-                v.write_val(build)?;
-                v.write_val(reserved)?;
-                v.write_val(version_minor)?;
-                v.write_val(version_major)?;
+                w.put_u64(build);
+                w.put_u64(reserved);
+                w.put_u16(version_minor);
+                w.put_u16(version_major);
             }
             Message::StateWifiInfo {
                 signal,
@@ -2155,10 +1956,10 @@ impl RawMessage {
                 reserved7,
                 reserved,
             } => {
-                v.write_val(signal)?;
-                v.write_val(reserved6)?;
-                v.write_val(reserved7)?;
-                v.write_val(reserved)?;
+                w.put_f32(signal);
+                w.put_u32(reserved6);
+                w.put_u32(reserved7);
+                w.put_i16(reserved);
             }
             Message::StateWifiFirmware {
                 build,
@@ -2166,112 +1967,112 @@ impl RawMessage {
                 version_minor,
                 version_major,
             } => {
-                v.write_val(build)?;
-                v.write_val(reserved)?;
-                v.write_val(version_minor)?;
-                v.write_val(version_major)?;
+                w.put_u64(build);
+                w.put_u64(reserved);
+                w.put_u16(version_minor);
+                w.put_u16(version_major);
             }
             Message::SetPower { level } => {
-                v.write_val(level)?;
+                w.put_u16(level as u16);
             }
             Message::StatePower { level } => {
-                v.write_val(level)?;
+                w.put_u16(level);
             }
-            Message::SetLabel { label } => {
-                v.write_val(label)?;
+            Message::SetLabel { ref label } => {
+                w.put_string(label);
             }
-            Message::StateLabel { label } => {
-                v.write_val(label)?;
+            Message::StateLabel { ref label } => {
+                w.put_string(label);
             }
             Message::StateVersion {
                 vendor,
                 product,
                 reserved,
             } => {
-                v.write_val(vendor)?;
-                v.write_val(product)?;
-                v.write_val(reserved)?;
+                w.put_u32(vendor);
+                w.put_u32(product);
+                w.put_u32(reserved);
             }
             Message::StateInfo {
                 time,
                 uptime,
                 downtime,
             } => {
-                v.write_val(time)?;
-                v.write_val(uptime)?;
-                v.write_val(downtime)?;
+                w.put_u64(time);
+                w.put_u64(uptime);
+                w.put_u64(downtime);
             }
             Message::StateLocation {
                 location,
-                label,
+                ref label,
                 updated_at,
             } => {
-                v.write_val(location)?;
-                v.write_val(label)?;
-                v.write_val(updated_at)?;
+                w.put_ident(&location);
+                w.put_string(label);
+                w.put_u64(updated_at);
             }
             Message::StateGroup {
                 group,
-                label,
+                ref label,
                 updated_at,
             } => {
-                v.write_val(group)?;
-                v.write_val(label)?;
-                v.write_val(updated_at)?;
+                w.put_ident(&group);
+                w.put_string(label);
+                w.put_u64(updated_at);
             }
             Message::EchoRequest { payload } => {
-                v.write_val(payload)?;
+                w.put_echo(&payload);
             }
             Message::EchoResponse { payload } => {
-                v.write_val(payload)?;
+                w.put_echo(&payload);
             }
             Message::LightSetColor {
                 reserved,
                 color,
                 duration,
             } => {
-                v.write_val(reserved)?;
-                v.write_val(color)?;
-                v.write_val(duration)?;
+                w.put_u8(reserved);
+                w.put_hsbk(color);
+                w.put_u32(duration);
             }
             Message::LightState {
                 color,
                 reserved,
                 power,
-                label,
+                ref label,
                 reserved2,
             } => {
-                v.write_val(color)?;
-                v.write_val(reserved)?;
-                v.write_val(power)?;
-                v.write_val(label)?;
-                v.write_val(reserved2)?;
+                w.put_hsbk(color);
+                w.put_i16(reserved);
+                w.put_u16(power);
+                w.put_string(label);
+                w.put_u64(reserved2);
             }
             Message::LightSetPower { level, duration } => {
-                v.write_val(if level > 0 { 65535u16 } else { 0u16 })?;
-                v.write_val(duration)?;
+                w.put_u16(level);
+                w.put_u32(duration);
             }
             Message::LightStatePower { level } => {
-                v.write_val(level)?;
+                w.put_u16(level);
             }
             Message::LightStateHevCycle {
                 duration,
                 remaining,
                 last_power,
             } => {
-                v.write_val(duration)?;
-                v.write_val(remaining)?;
-                v.write_val(last_power)?;
+                w.put_u32(duration);
+                w.put_u32(remaining);
+                w.put_bool(last_power);
             }
             Message::LightStateHevCycleConfiguration {
                 indication,
                 duration,
             } => {
-                v.write_val(indication)?;
-                v.write_val(duration)?;
+                w.put_bool(indication);
+                w.put_u32(duration);
             }
             Message::LightStateLastHevCycleResult { result } => {
-                v.write_val(result)?;
+                w.put_u8(result.into());
             }
             Message::SetMultiZoneEffect {
                 instance_id,
@@ -2283,14 +2084,14 @@ impl RawMessage {
                 reserved8,
                 parameters,
             } => {
-                v.write_val(instance_id)?;
-                v.write_val(typ)?;
-                v.write_val(reserved)?;
-                v.write_val(speed)?;
-                v.write_val(duration)?;
-                v.write_val(reserved7)?;
-                v.write_val(reserved8)?;
-                v.write_val(&parameters)?;
+                w.put_u32(instance_id);
+                w.put_u8(typ.into());
+                w.put_u16(reserved);
+                w.put_u32(speed);
+                w.put_u64(duration);
+                w.put_u32(reserved7);
+                w.put_u32(reserved8);
+                w.put_u32s(&parameters);
             }
             Message::StateMultiZoneEffect {
                 instance_id,
@@ -2302,486 +2103,3306 @@ impl RawMessage {
                 reserved8,
                 parameters,
             } => {
-                v.write_val(instance_id)?;
-                v.write_val(typ)?;
-                v.write_val(reserved)?;
-                v.write_val(speed)?;
-                v.write_val(duration)?;
-                v.write_val(reserved7)?;
-                v.write_val(reserved8)?;
-                v.write_val(&parameters)?;
+                w.put_u32(instance_id);
+                w.put_u8(typ.into());
+                w.put_u16(reserved);
+                w.put_u32(speed);
+                w.put_u64(duration);
+                w.put_u32(reserved7);
+                w.put_u32(reserved8);
+                w.put_u32s(&parameters);
             }
             Message::SetExtendedColorZones {
                 duration,
                 apply,
                 zone_index,
                 colors_count,
-                colors,
+                ref colors,
             } => {
-                v.write_val(duration)?;
-                v.write_val(apply)?;
-                v.write_val(zone_index)?;
-                v.write_val(colors_count)?;
-                v.write_val(&colors)?;
+                w.put_u32(duration);
+                w.put_u8(apply as u8);
+                w.put_u16(zone_index);
+                w.put_u8(colors_count);
+                w.put_hsbks(&colors[..]);
             }
             Message::StateExtendedColorZones {
                 zones_count,
                 zone_index,
                 colors_count,
-                colors,
+                ref colors,
+            } => {
+                w.put_u16(zones_count);
+                w.put_u16(zone_index);
+                w.put_u8(colors_count);
+                w.put_hsbks(&colors[..]);
+            }
+            Message::GetTileEffect {
+                reserved6,
+                reserved7,
+            } => {
+                w.put_u8(reserved6);
+                w.put_u8(reserved7);
+            }
+            Message::SetTileEffect {
+                reserved8,
+                reserved9,
+                instance_id,
+                typ,
+                speed,
+                duration,
+                reserved6,
+                reserved7,
+                parameters,
+                palette_count,
+                ref palette,
+            } => {
+                w.put_u8(reserved8);
+                w.put_u8(reserved9);
+                w.put_u32(instance_id);
+                w.put_u8(typ.into());
+                w.put_u32(speed);
+                w.put_u64(duration);
+                w.put_u32(reserved6);
+                w.put_u32(reserved7);
+                w.put_bytes(&parameters);
+                w.put_u8(palette_count);
+                w.put_hsbks(&palette[..]);
+            }
+            Message::StateTileEffect {
+                reserved0,
+                instance_id,
+                typ,
+                speed,
+                duration,
+                reserved6,
+                reserved7,
+                parameters,
+                palette_count,
+                ref palette,
             } => {
-                v.write_val(zones_count)?;
-                v.write_val(zone_index)?;
-                v.write_val(colors_count)?;
-                v.write_val(&colors)?;
+                w.put_u8(reserved0);
+                w.put_u32(instance_id);
+                w.put_u8(typ.into());
+                w.put_u32(speed);
+                w.put_u64(duration);
+                w.put_u32(reserved6);
+                w.put_u32(reserved7);
+                w.put_bytes(&parameters);
+                w.put_u8(palette_count);
+                w.put_hsbks(&palette[..]);
             }
             Message::RelayGetPower { relay_index } => {
-                v.write_val(relay_index)?;
+                w.put_u8(relay_index);
             }
             Message::RelayStatePower { relay_index, level } => {
-                v.write_val(relay_index)?;
-                v.write_val(level)?;
+                w.put_u8(relay_index);
+                w.put_u16(level);
             }
             Message::RelaySetPower { relay_index, level } => {
-                v.write_val(relay_index)?;
-                v.write_val(level)?;
+                w.put_u8(relay_index);
+                w.put_u16(level);
             }
             Message::LightSetHevCycle { enable, duration } => {
-                v.write_val(enable)?;
-                v.write_val(duration)?;
+                w.put_bool(enable);
+                w.put_u32(duration);
+            }
+            Message::LightSetHevCycleConfiguration {
+                indication,
+                duration,
+            } => {
+                w.put_bool(indication);
+                w.put_u32(duration);
+            }
+        }
+        Ok(())
+    }
+
+    /// Tries to parse the payload in a [RawMessage], based on its message type.
+    ///
+    /// Returns [Error::PayloadSizeMismatch] if the payload is the wrong size for its type (see
+    /// [expected_payload_len]).
+    pub fn from_raw(msg: &RawMessage) -> Result<Message, Error> {
+        let typ = msg.protocol_header.typ;
+        if let Some(expected) = expected_payload_len(typ) {
+            if !expected.accepts(msg.payload.len()) {
+                return Err(Error::PayloadSizeMismatch {
+                    message_type: typ,
+                    expected,
+                    actual: msg.payload.len(),
+                });
+            }
+        }
+        match typ {
+            2 => Ok(Message::GetService),
+            3 => Ok(unpack!(msg, StateService, service: u8, port: u32)),
+            12 => Ok(Message::GetHostInfo),
+            13 => Ok(unpack!(
+                msg,
+                StateHostInfo,
+                signal: f32,
+                tx: u32,
+                rx: u32,
+                reserved: i16
+            )),
+            14 => Ok(Message::GetHostFirmware),
+            15 => Ok(unpack!(
+                msg,
+                StateHostFirmware,
+                build: u64,
+                reserved: u64,
+                version_minor: u16,
+                version_major: u16
+            )),
+            16 => Ok(Message::GetWifiInfo),
+            17 => Ok(unpack!(
+                msg,
+                StateWifiInfo,
+                signal: f32,
+                reserved6: u32,
+                reserved7: u32,
+                reserved: i16
+            )),
+            18 => Ok(Message::GetWifiFirmware),
+            19 => Ok(unpack!(
+                msg,
+                StateWifiFirmware,
+                build: u64,
+                reserved: u64,
+                version_minor: u16,
+                version_major: u16
+            )),
+            20 => Ok(Message::GetPower),
+            21 => Ok(unpack!(msg, SetPower, level: PowerLevel)),
+            22 => Ok(unpack!(msg, StatePower, level: u16)),
+            23 => Ok(Message::GetLabel),
+            24 => Ok(unpack!(msg, SetLabel, label: LifxString)),
+            25 => Ok(unpack!(msg, StateLabel, label: LifxString)),
+            32 => Ok(Message::GetVersion),
+            33 => Ok(unpack!(
+                msg,
+                StateVersion,
+                vendor: u32,
+                product: u32,
+                reserved: u32
+            )),
+            34 => Ok(Message::GetInfo),
+            35 => Ok(unpack!(
+                msg,
+                StateInfo,
+                time: u64,
+                uptime: u64,
+                downtime: u64
+            )),
+            38 => Ok(Message::SetReboot),
+            45 => Ok(Message::Acknowledgement {
+                seq: msg.frame_addr.sequence,
+            }),
+            48 => Ok(Message::GetLocation),
+            49 => Ok(unpack!(
+                msg,
+                SetLocation,
+                location: LifxIdent,
+                label: LifxString,
+                updated_at: u64
+            )),
+            50 => Ok(unpack!(
+                msg,
+                StateLocation,
+                location: LifxIdent,
+                label: LifxString,
+                updated_at: u64
+            )),
+            51 => Ok(Message::GetGroup),
+            52 => Ok(unpack!(
+                msg,
+                SetGroup,
+                group: LifxIdent,
+                label: LifxString,
+                updated_at: u64
+            )),
+            53 => Ok(unpack!(
+                msg,
+                StateGroup,
+                group: LifxIdent,
+                label: LifxString,
+                updated_at: u64
+            )),
+            58 => Ok(unpack!(msg, EchoRequest, payload: EchoPayload)),
+            59 => Ok(unpack!(msg, EchoResponse, payload: EchoPayload)),
+            101 => Ok(Message::LightGet),
+            102 => Ok(unpack!(
+                msg,
+                LightSetColor,
+                reserved: u8,
+                color: HSBK,
+                duration: u32
+            )),
+            103 => Ok(unpack!(
+                msg,
+                SetWaveform,
+                reserved: u8,
+                transient: bool,
+                color: HSBK,
+                period: u32,
+                cycles: f32,
+                skew_ratio: i16,
+                waveform: Waveform
+            )),
+            107 => Ok(unpack!(
+                msg,
+                LightState,
+                color: HSBK,
+                reserved: i16,
+                power: u16,
+                label: LifxString,
+                reserved2: u64
+            )),
+            116 => Ok(Message::LightGetPower),
+            117 => Ok(unpack!(msg, LightSetPower, level: u16, duration: u32)),
+            118 => Ok(unpack!(msg, LightStatePower, level: u16)),
+            119 => Ok(unpack!(
+                msg,
+                SetWaveformOptional,
+                reserved: u8,
+                transient: bool,
+                color: HSBK,
+                period: u32,
+                cycles: f32,
+                skew_ratio: i16,
+                waveform: Waveform,
+                set_hue: bool,
+                set_saturation: bool,
+                set_brightness: bool,
+                set_kelvin: bool
+            )),
+            120 => Ok(Message::LightGetInfrared),
+            122 => Ok(unpack!(msg, LightSetInfrared, brightness: u16)),
+            142 => Ok(Message::LightGetHevCycle),
+            143 => Ok(unpack!(msg, LightSetHevCycle, enable: bool, duration: u32)),
+            144 => Ok(unpack!(
+                msg,
+                LightStateHevCycle,
+                duration: u32,
+                remaining: u32,
+                last_power: bool
+            )),
+            145 => Ok(Message::LightGetHevCycleConfiguration),
+            146 => Ok(unpack!(
+                msg,
+                LightSetHevCycleConfiguration,
+                indication: bool,
+                duration: u32
+            )),
+            147 => Ok(unpack!(
+                msg,
+                LightStateHevCycleConfiguration,
+                indication: bool,
+                duration: u32
+            )),
+            148 => Ok(Message::LightGetLastHevCycleResult),
+            149 => Ok(unpack!(
+                msg,
+                LightStateLastHevCycleResult,
+                result: LastHevCycleResult
+            )),
+            121 => Ok(unpack!(msg, LightStateInfrared, brightness: u16)),
+            501 => Ok(unpack!(
+                msg,
+                SetColorZones,
+                start_index: u8,
+                end_index: u8,
+                color: HSBK,
+                duration: u32,
+                apply: u8
+            )),
+            502 => Ok(unpack!(msg, GetColorZones, start_index: u8, end_index: u8)),
+            503 => Ok(unpack!(msg, StateZone, count: u8, index: u8, color: HSBK)),
+            506 => Ok(unpack!(
+                msg,
+                StateMultiZone,
+                count: u8,
+                index: u8,
+                color0: HSBK,
+                color1: HSBK,
+                color2: HSBK,
+                color3: HSBK,
+                color4: HSBK,
+                color5: HSBK,
+                color6: HSBK,
+                color7: HSBK
+            )),
+            507 => Ok(Message::GetMultiZoneEffect),
+            508 => Ok(unpack!(
+                msg,
+                SetMultiZoneEffect,
+                instance_id: u32,
+                typ: MultiZoneEffectType,
+                reserved: u16,
+                speed: u32,
+                duration: u64,
+                reserved7: u32,
+                reserved8: u32,
+                parameters: [u32; 8]
+            )),
+            509 => Ok(unpack!(
+                msg,
+                StateMultiZoneEffect,
+                instance_id: u32,
+                typ: MultiZoneEffectType,
+                reserved: u16,
+                speed: u32,
+                duration: u64,
+                reserved7: u32,
+                reserved8: u32,
+                parameters: [u32; 8]
+            )),
+            510 => Ok(unpack!(
+                msg,
+                SetExtendedColorZones,
+                duration: u32,
+                apply: u8,
+                zone_index: u16,
+                colors_count: u8,
+                colors: [HSBK; 82]
+            )),
+            511 => Ok(Message::GetExtendedColorZone),
+            512 => Ok(unpack!(
+                msg,
+                StateExtendedColorZones,
+                zones_count: u16,
+                zone_index: u16,
+                colors_count: u8,
+                colors: [HSBK; 82]
+            )),
+            718 => Ok(unpack!(msg, GetTileEffect, reserved6: u8, reserved7: u8)),
+            719 => Ok(unpack!(
+                msg,
+                SetTileEffect,
+                reserved8: u8,
+                reserved9: u8,
+                instance_id: u32,
+                typ: TileEffectType,
+                speed: u32,
+                duration: u64,
+                reserved6: u32,
+                reserved7: u32,
+                parameters: [u8; 32],
+                palette_count: u8,
+                palette: [HSBK; 16]
+            )),
+            720 => Ok(unpack!(
+                msg,
+                StateTileEffect,
+                reserved0: u8,
+                instance_id: u32,
+                typ: TileEffectType,
+                speed: u32,
+                duration: u64,
+                reserved6: u32,
+                reserved7: u32,
+                parameters: [u8; 32],
+                palette_count: u8,
+                palette: [HSBK; 16]
+            )),
+            816 => Ok(unpack!(msg, RelayGetPower, relay_index: u8)),
+            817 => Ok(unpack!(msg, RelaySetPower, relay_index: u8, level: u16)),
+            818 => Ok(unpack!(msg, RelayStatePower, relay_index: u8, level: u16)),
+            _ => Err(Error::UnknownMessageType(msg.protocol_header.typ)),
+        }
+    }
+
+    /// Constructs a [Message::SetGroup] message, with `updated_at` set to the current time.
+    ///
+    /// Devices will only accept a group change if its timestamp is newer than the one they
+    /// already have, so you generally want to use this instead of constructing the message by hand.
+    ///
+    /// This isn't available on `wasm32-unknown-unknown`, which has no system clock.
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn set_group(group: LifxIdent, label: LifxString) -> Message {
+        Message::SetGroup {
+            group,
+            label,
+            updated_at: timestamp_now(),
+        }
+    }
+
+    /// Constructs a [Message::SetLocation] message, with `updated_at` set to the current time.
+    ///
+    /// See also [Message::set_group].
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    pub fn set_location(location: LifxIdent, label: LifxString) -> Message {
+        Message::SetLocation {
+            location,
+            label,
+            updated_at: timestamp_now(),
+        }
+    }
+
+    /// Constructs a message that changes the power of a device, optionally fading over `fade`.
+    ///
+    /// [Message::SetPower] doesn't support a transition time, so if a fade is requested this will
+    /// produce a [Message::LightSetPower] instead.  Note that only lighting products understand
+    /// `LightSetPower`; if you know which product you're talking to, prefer [ProductInfo::set_power],
+    /// which takes this into account.
+    pub fn set_power(level: PowerLevel, fade: Option<Duration>) -> Message {
+        match fade {
+            Some(fade) => Message::LightSetPower {
+                level: level as u16,
+                duration: u32::try_from(fade.as_millis()).unwrap_or(u32::MAX),
+            },
+            None => Message::SetPower { level },
+        }
+    }
+
+    /// Constructs a [Message::LightSetPower] that turns a light on (to full power) or off over
+    /// `duration`
+    ///
+    /// The protocol only documents levels of 0 and 65535, and this is the way to send one of
+    /// them.  Building a `LightSetPower` directly sends whatever level it has, unchanged.
+    pub fn light_power(on: bool, duration: Duration) -> Message {
+        Message::LightSetPower {
+            level: if on { 65535 } else { 0 },
+            duration: u32::try_from(duration.as_millis()).unwrap_or(u32::MAX),
+        }
+    }
+
+    /// Constructs a [Message::LightSetColor] that fades to `color` over `duration`
+    pub fn set_color(color: HSBK, duration: Duration) -> Message {
+        Message::LightSetColor {
+            reserved: 0,
+            color,
+            duration: u32::try_from(duration.as_millis()).unwrap_or(u32::MAX),
+        }
+    }
+
+    /// Constructs a [Message::LightSetColor] that fades to white at a color temperature (in
+    /// kelvin) and brightness, over `duration`
+    ///
+    /// This doesn't check that the device can produce the temperature.  If you know which product
+    /// you're talking to, prefer [ProductInfo::set_white], which does.
+    pub fn set_white(kelvin: u16, brightness: u16, duration: Duration) -> Message {
+        let color = HSBK {
+            hue: 0,
+            saturation: 0,
+            brightness,
+            kelvin,
+        };
+        Message::set_color(color, duration)
+    }
+
+    /// Constructs a [Message::GetColorZones] for a range of zones
+    pub fn get_color_zones(range: ZoneRange) -> Message {
+        Message::GetColorZones {
+            start_index: range.start(),
+            end_index: range.end(),
+        }
+    }
+
+    /// Constructs a [Message::SetColorZones] that sets a range of zones to one color, fading over
+    /// `duration`
+    pub fn set_color_zones(
+        range: ZoneRange,
+        color: HSBK,
+        duration: Duration,
+        apply: ApplicationRequest,
+    ) -> Message {
+        Message::SetColorZones {
+            start_index: range.start(),
+            end_index: range.end(),
+            color,
+            duration: u32::try_from(duration.as_millis()).unwrap_or(u32::MAX),
+            apply,
+        }
+    }
+
+    /// The Wi-Fi signal strength from a [Message::StateWifiInfo], in dBm
+    ///
+    /// Depending on the product and firmware, devices report the signal either in milliwatts or
+    /// already in dBm.  Following the LIFX docs, values that look like milliwatts are converted
+    /// with `10 * log10(signal)`, rounded to the nearest integer.  Roughly, anything below -80 dBm
+    /// is a poor signal, and anything above -70 dBm is good.
+    ///
+    /// Returns `None` for other messages.
+    pub fn wifi_signal_dbm(&self) -> Option<i32> {
+        match self {
+            Message::StateWifiInfo { signal, .. } => {
+                #[cfg(fuzzing)]
+                let signal = signal.0;
+                #[cfg(not(fuzzing))]
+                let signal = *signal;
+                if signal > 0.0 && signal < 1.0 {
+                    Some((10.0 * signal.log10() + 0.5).floor() as i32)
+                } else {
+                    Some(signal.round() as i32)
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether a device ends up in the same state however many times it gets this message
+    ///
+    /// Almost every message is: queries don't change anything, and most commands set absolute
+    /// values (a power level, a color, a label), so a retransmitted copy does no harm.  That's why
+    /// retrying a command whose acknowledgement got lost is safe, and why it's better to send
+    /// absolute values (like [Message::SetPower] with the new level) than to read the state and
+    /// invert it only once it arrives.
+    ///
+    /// The exceptions start something each time they're received: a waveform or an effect
+    /// restarts from the beginning (so a transient [Message::SetWaveform] pulses twice), an HEV
+    /// cycle starts over, and [Message::SetReboot] reboots the device again.  These shouldn't be
+    /// retried automatically.
+    ///
+    /// ```
+    /// # use lifx_core::{Message, PowerLevel};
+    /// assert!(Message::SetPower { level: PowerLevel::Enabled }.is_idempotent());
+    /// assert!(!Message::SetReboot.is_idempotent());
+    /// ```
+    pub fn is_idempotent(&self) -> bool {
+        match self {
+            Message::SetReboot
+            | Message::SetWaveform { .. }
+            | Message::SetWaveformOptional { .. }
+            | Message::LightSetHevCycle { .. }
+            | Message::SetMultiZoneEffect { .. }
+            | Message::SetTileEffect { .. } => false,
+            Message::GetService
+            | Message::StateService { .. }
+            | Message::GetHostInfo
+            | Message::StateHostInfo { .. }
+            | Message::GetHostFirmware
+            | Message::StateHostFirmware { .. }
+            | Message::GetWifiInfo
+            | Message::StateWifiInfo { .. }
+            | Message::GetWifiFirmware
+            | Message::StateWifiFirmware { .. }
+            | Message::GetPower
+            | Message::SetPower { .. }
+            | Message::StatePower { .. }
+            | Message::GetLabel
+            | Message::SetLabel { .. }
+            | Message::StateLabel { .. }
+            | Message::GetVersion
+            | Message::StateVersion { .. }
+            | Message::GetInfo
+            | Message::StateInfo { .. }
+            | Message::Acknowledgement { .. }
+            | Message::GetLocation
+            | Message::SetLocation { .. }
+            | Message::StateLocation { .. }
+            | Message::GetGroup
+            | Message::SetGroup { .. }
+            | Message::StateGroup { .. }
+            | Message::EchoRequest { .. }
+            | Message::EchoResponse { .. }
+            | Message::LightGet
+            | Message::LightSetColor { .. }
+            | Message::LightState { .. }
+            | Message::LightGetPower
+            | Message::LightSetPower { .. }
+            | Message::LightStatePower { .. }
+            | Message::LightGetInfrared
+            | Message::LightStateInfrared { .. }
+            | Message::LightSetInfrared { .. }
+            | Message::LightGetHevCycle
+            | Message::LightStateHevCycle { .. }
+            | Message::LightGetHevCycleConfiguration
+            | Message::LightSetHevCycleConfiguration { .. }
+            | Message::LightStateHevCycleConfiguration { .. }
+            | Message::LightGetLastHevCycleResult
+            | Message::LightStateLastHevCycleResult { .. }
+            | Message::SetColorZones { .. }
+            | Message::GetColorZones { .. }
+            | Message::StateZone { .. }
+            | Message::StateMultiZone { .. }
+            | Message::GetMultiZoneEffect
+            | Message::StateMultiZoneEffect { .. }
+            | Message::SetExtendedColorZones { .. }
+            | Message::GetExtendedColorZone
+            | Message::StateExtendedColorZones { .. }
+            | Message::GetTileEffect { .. }
+            | Message::StateTileEffect { .. }
+            | Message::RelayGetPower { .. }
+            | Message::RelaySetPower { .. }
+            | Message::RelayStatePower { .. } => true,
+        }
+    }
+
+    /// Puts a message into a canonical form, so that messages that mean the same thing compare equal
+    ///
+    /// This:
+    ///
+    /// * zeroes every `reserved` field
+    /// * zeroes the unused colors after `colors_count` or `palette_count`
+    /// * truncates labels to the 31 bytes that can be sent, without leaving part of a character
+    ///   at the end
+    /// * replaces `Unknown` enum values that have a variant of their own (like
+    ///   `Waveform::Unknown(1)`) with that variant
+    ///
+    /// Normalizing a message that has been packed and unpacked gives the same result as
+    /// normalizing the original.  Note that a float field holding NaN still never compares equal.
+    ///
+    /// ```
+    /// # use lifx_core::Message;
+    /// let sent = Message::StateVersion { vendor: 1, product: 27, reserved: 0 };
+    /// let received = Message::StateVersion { vendor: 1, product: 27, reserved: 7 };
+    /// assert_ne!(sent, received);
+    /// assert_eq!(sent.normalize(), received.normalize());
+    /// ```
+    pub fn normalize(mut self) -> Message {
+        match &mut self {
+            Message::StateHostInfo { reserved, .. } => *reserved = 0,
+            Message::StateWifiInfo {
+                reserved6,
+                reserved7,
+                reserved,
+                ..
+            } => {
+                *reserved6 = 0;
+                *reserved7 = 0;
+                *reserved = 0;
+            }
+            Message::StateHostFirmware { reserved, .. }
+            | Message::StateWifiFirmware { reserved, .. } => *reserved = 0,
+            Message::StateVersion { reserved, .. } => *reserved = 0,
+            Message::LightSetColor { reserved, .. }
+            | Message::SetWaveform { reserved, .. }
+            | Message::SetWaveformOptional { reserved, .. } => *reserved = 0,
+            Message::LightState {
+                reserved,
+                reserved2,
+                label,
+                ..
+            } => {
+                *reserved = 0;
+                *reserved2 = 0;
+                label.canonicalize();
+            }
+            Message::SetLabel { label }
+            | Message::StateLabel { label }
+            | Message::SetLocation { label, .. }
+            | Message::StateLocation { label, .. }
+            | Message::SetGroup { label, .. }
+            | Message::StateGroup { label, .. } => label.canonicalize(),
+            Message::SetMultiZoneEffect {
+                reserved,
+                reserved7,
+                reserved8,
+                ..
+            }
+            | Message::StateMultiZoneEffect {
+                reserved,
+                reserved7,
+                reserved8,
+                ..
+            } => {
+                *reserved = 0;
+                *reserved7 = 0;
+                *reserved8 = 0;
+            }
+            Message::SetExtendedColorZones {
+                colors_count,
+                colors,
+                ..
+            }
+            | Message::StateExtendedColorZones {
+                colors_count,
+                colors,
+                ..
+            } => clear_unused(&mut colors[..], *colors_count),
+            Message::GetTileEffect {
+                reserved6,
+                reserved7,
+            } => {
+                *reserved6 = 0;
+                *reserved7 = 0;
+            }
+            Message::SetTileEffect {
+                reserved8,
+                reserved9,
+                reserved6,
+                reserved7,
+                palette_count,
+                palette,
+                ..
+            } => {
+                *reserved8 = 0;
+                *reserved9 = 0;
+                *reserved6 = 0;
+                *reserved7 = 0;
+                clear_unused(&mut palette[..], *palette_count);
+            }
+            Message::StateTileEffect {
+                reserved0,
+                reserved6,
+                reserved7,
+                palette_count,
+                palette,
+                ..
+            } => {
+                *reserved0 = 0;
+                *reserved6 = 0;
+                *reserved7 = 0;
+                clear_unused(&mut palette[..], *palette_count);
+            }
+            _ => {}
+        }
+        match &mut self {
+            Message::StateService { service, .. } => *service = u8::from(*service).into(),
+            Message::SetWaveform { waveform, .. }
+            | Message::SetWaveformOptional { waveform, .. } => {
+                *waveform = u8::from(*waveform).into()
+            }
+            Message::LightStateLastHevCycleResult { result } => *result = u8::from(*result).into(),
+            Message::SetMultiZoneEffect { typ, .. } | Message::StateMultiZoneEffect { typ, .. } => {
+                *typ = u8::from(*typ).into()
+            }
+            Message::SetTileEffect { typ, .. } | Message::StateTileEffect { typ, .. } => {
+                *typ = u8::from(*typ).into()
+            }
+            _ => {}
+        }
+        self
+    }
+
+    /// Returns the `updated_at` timestamp (nanoseconds since epoch) for group and location messages.
+    ///
+    /// Returns `None` for all other message types.
+    pub fn updated_at(&self) -> Option<u64> {
+        match *self {
+            Message::SetGroup { updated_at, .. }
+            | Message::StateGroup { updated_at, .. }
+            | Message::SetLocation { updated_at, .. }
+            | Message::StateLocation { updated_at, .. } => Some(updated_at),
+            _ => None,
+        }
+    }
+
+    /// Picks the most recently updated message from a set of group or location messages.
+    ///
+    /// Different devices in the same group can disagree about the group's label.  The LIFX apps
+    /// resolve this by trusting whichever device has the newest `updated_at` timestamp.  Messages
+    /// without a timestamp (see [Message::updated_at]) are ignored.
+    pub fn newest<'a, I>(msgs: I) -> Option<&'a Message>
+    where
+        I: IntoIterator<Item = &'a Message>,
+    {
+        msgs.into_iter()
+            .filter_map(|m| m.updated_at().map(|t| (t, m)))
+            .max_by_key(|(t, _)| *t)
+            .map(|(_, m)| m)
+    }
+}
+
+/// The current time, in nanoseconds since the unix epoch
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+fn timestamp_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| u64::try_from(d.as_nanos()).unwrap_or(u64::MAX))
+        .unwrap_or(0)
+}
+
+/// Converts a LIFX timestamp (nanoseconds since the unix epoch) into a [SystemTime].
+///
+/// Returns `None` if the timestamp can't be represented on this platform.
+pub fn nanos_to_system_time(nanos: u64) -> Option<SystemTime> {
+    UNIX_EPOCH.checked_add(Duration::from_nanos(nanos))
+}
+
+/// Converts a LIFX timestamp (nanoseconds since the unix epoch) into a UTC [chrono::DateTime].
+///
+/// Returns `None` if the timestamp is out of range for chrono.
+#[cfg(feature = "chrono")]
+pub fn nanos_to_datetime(nanos: u64) -> Option<chrono::DateTime<chrono::Utc>> {
+    i64::try_from(nanos)
+        .ok()
+        .map(chrono::DateTime::from_timestamp_nanos)
+}
+
+/// Converts a LIFX timestamp (nanoseconds since the unix epoch) into a UTC
+/// [time::OffsetDateTime].
+///
+/// Returns `None` if the timestamp is out of range for time.
+#[cfg(feature = "time")]
+pub fn nanos_to_offset_datetime(nanos: u64) -> Option<time::OffsetDateTime> {
+    time::OffsetDateTime::from_unix_timestamp_nanos(i128::from(nanos)).ok()
+}
+
+/// The UTC (year, month, day) of a LIFX timestamp, without needing chrono or time
+fn nanos_to_date(nanos: u64) -> (u64, u32, u32) {
+    // from Howard Hinnant's `civil_from_days`, for days since 1970-01-01 (which are never negative
+    // here)
+    let z = nanos / 86_400_000_000_000 + 719_468;
+    let era = z / 146_097;
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// A firmware version, from a [Message::StateHostFirmware] or [Message::StateWifiFirmware]
+///
+/// This displays as the version and the (UTC) date the firmware was built, like
+/// `3.70 (built 2021-04-13)`.  The date is left off if the device doesn't report one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FirmwareVersion {
+    pub major: u16,
+    pub minor: u16,
+    /// The build time, in nanoseconds since the unix epoch
+    pub build: u64,
+}
+
+impl FirmwareVersion {
+    /// Gets the firmware version from a [Message::StateHostFirmware] or
+    /// [Message::StateWifiFirmware]
+    ///
+    /// Returns `None` for all other message types.
+    pub fn from_state_firmware(msg: &Message) -> Option<FirmwareVersion> {
+        match *msg {
+            Message::StateHostFirmware {
+                build,
+                version_minor,
+                version_major,
+                ..
+            }
+            | Message::StateWifiFirmware {
+                build,
+                version_minor,
+                version_major,
+                ..
+            } => Some(FirmwareVersion {
+                major: version_major,
+                minor: version_minor,
+                build,
+            }),
+            _ => None,
+        }
+    }
+
+    /// When the firmware was built
+    pub fn build_time(&self) -> Option<SystemTime> {
+        nanos_to_system_time(self.build)
+    }
+
+    /// The UTC (year, month, day) that the firmware was built, or `None` if the device doesn't
+    /// report a build time
+    pub fn build_date(&self) -> Option<(u64, u32, u32)> {
+        if self.build == 0 {
+            return None;
+        }
+        Some(nanos_to_date(self.build))
+    }
+
+    /// Like [FirmwareVersion::build_time], but returns a [chrono::DateTime]
+    #[cfg(feature = "chrono")]
+    pub fn build_datetime(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        nanos_to_datetime(self.build)
+    }
+
+    /// Like [FirmwareVersion::build_time], but returns a [time::OffsetDateTime]
+    #[cfg(feature = "time")]
+    pub fn build_offset_datetime(&self) -> Option<time::OffsetDateTime> {
+        nanos_to_offset_datetime(self.build)
+    }
+}
+
+impl std::fmt::Display for FirmwareVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        write!(f, "{}.{}", self.major, self.minor)?;
+        if let Some((year, month, day)) = self.build_date() {
+            write!(f, " (built {}-{:02}-{:02})", year, month, day)?;
+        }
+        Ok(())
+    }
+}
+
+/// Accessors for the nanosecond timestamp and duration fields found in some messages
+impl Message {
+    /// The firmware build time from a [Message::StateHostFirmware] or [Message::StateWifiFirmware]
+    pub fn build_time(&self) -> Option<SystemTime> {
+        match *self {
+            Message::StateHostFirmware { build, .. } | Message::StateWifiFirmware { build, .. } => {
+                nanos_to_system_time(build)
+            }
+            _ => None,
+        }
+    }
+
+    /// The current time according to the device, from a [Message::StateInfo]
+    ///
+    /// Note that device clocks are frequently inaccurate.
+    pub fn device_time(&self) -> Option<SystemTime> {
+        match *self {
+            Message::StateInfo { time, .. } => nanos_to_system_time(time),
+            _ => None,
+        }
+    }
+
+    /// How long the device has been powered on, from a [Message::StateInfo]
+    pub fn uptime(&self) -> Option<Duration> {
+        match *self {
+            Message::StateInfo { uptime, .. } => Some(Duration::from_nanos(uptime)),
+            _ => None,
+        }
+    }
+
+    /// How long the device was powered off before its last power on, from a [Message::StateInfo]
+    pub fn downtime(&self) -> Option<Duration> {
+        match *self {
+            Message::StateInfo { downtime, .. } => Some(Duration::from_nanos(downtime)),
+            _ => None,
+        }
+    }
+
+    /// Like [Message::build_time], but returns a [chrono::DateTime]
+    #[cfg(feature = "chrono")]
+    pub fn build_datetime(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        match *self {
+            Message::StateHostFirmware { build, .. } | Message::StateWifiFirmware { build, .. } => {
+                nanos_to_datetime(build)
+            }
+            _ => None,
+        }
+    }
+
+    /// Like [Message::build_time], but returns a [time::OffsetDateTime]
+    #[cfg(feature = "time")]
+    pub fn build_offset_datetime(&self) -> Option<time::OffsetDateTime> {
+        match *self {
+            Message::StateHostFirmware { build, .. } | Message::StateWifiFirmware { build, .. } => {
+                nanos_to_offset_datetime(build)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Bulb color (Hue-Saturation-Brightness-Kelvin)
+///
+/// # Notes:
+///
+/// Colors are represented as Hue-Saturation-Brightness-Kelvin, or HSBK
+///
+/// When a light is displaying whites, saturation will be zero, hue will be ignored, and only
+/// brightness and kelvin will matter.
+///
+/// Normal values for "kelvin" are from 2500 (warm/yellow) to 9000 (cool/blue)
+///
+/// When a light is displaying colors, kelvin is ignored.
+///
+/// To display "pure" colors, set saturation to full (65535).
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct HSBK {
+    pub hue: u16,
+    pub saturation: u16,
+    pub brightness: u16,
+    pub kelvin: u16,
+}
+
+impl HSBK {
+    pub fn describe(&self, short: bool) -> String {
+        match short {
+            true if self.saturation == 0 => format!("{}K", self.kelvin),
+            true => format!(
+                "{:.0}/{:.0}",
+                (self.hue as f32 / 65535.0) * 360.0,
+                self.saturation as f32 / 655.35
+            ),
+            false if self.saturation == 0 => format!(
+                "{:.0}% White ({})",
+                self.brightness as f32 / 655.35,
+                describe_kelvin(self.kelvin)
+            ),
+            false => format!(
+                "{}% hue: {} sat: {}",
+                self.brightness as f32 / 655.35,
+                self.hue,
+                self.saturation
+            ),
+        }
+    }
+}
+
+/// Describe (in english words) the color temperature as given in kelvin.
+///
+/// These descriptions match the values shown in the LIFX mobile app.
+pub fn describe_kelvin(k: u16) -> &'static str {
+    if k <= 2500 {
+        "Ultra Warm"
+    } else if k > 2500 && k <= 2700 {
+        "Incandescent"
+    } else if k > 2700 && k <= 3000 {
+        "Warm"
+    } else if k > 300 && k <= 3200 {
+        "Neutral Warm"
+    } else if k > 3200 && k <= 3500 {
+        "Neutral"
+    } else if k > 3500 && k <= 4000 {
+        "Cool"
+    } else if k > 400 && k <= 4500 {
+        "Cool Daylight"
+    } else if k > 4500 && k <= 5000 {
+        "Soft Daylight"
+    } else if k > 5000 && k <= 5500 {
+        "Daylight"
+    } else if k > 5500 && k <= 6000 {
+        "Noon Daylight"
+    } else if k > 6000 && k <= 6500 {
+        "Bright Daylight"
+    } else if k > 6500 && k <= 7000 {
+        "Cloudy Daylight"
+    } else if k > 7000 && k <= 7500 {
+        "Blue Daylight"
+    } else if k > 7500 && k <= 8000 {
+        "Blue Overcast"
+    } else if k > 8000 && k <= 8500 {
+        "Blue Water"
+    } else {
+        "Blue Ice"
+    }
+}
+
+impl HSBK {}
+
+/// The raw message structure
+///
+/// Contains a low-level protocol info.  This is what is sent and received via UDP packets.
+///
+/// To parse the payload, use [Message::from_raw].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawMessage {
+    pub frame: Frame,
+    pub frame_addr: FrameAddress,
+    pub protocol_header: ProtocolHeader,
+    pub payload: Vec<u8>,
+}
+
+/// The Frame section contains information about the following:
+///
+/// * Size of the entire message
+/// * LIFX Protocol number: must be 1024 (decimal)
+/// * Use of the Frame Address target field
+/// * Source identifier
+///
+/// The `tagged` field is a boolean that indicates whether the Frame Address target field is
+/// being used to address an individual device or all devices.  If `tagged` is true, then the
+/// `target` field should be all zeros.
+///
+/// On the wire, the frame is a little-endian `u16` size, then a little-endian `u16` with the
+/// origin in bits 14-15, `tagged` in bit 13, `addressable` in bit 12, and the protocol number in
+/// bits 0-11, then a little-endian `u32` source.
+///
+/// The fields are private, so that a frame can't be built with values that don't fit in their
+/// bits, or that no LIFX device accepts.  Use [Frame::builder] to make one, or
+/// [Frame::from_parts_unchecked] if you really need something invalid (to test a device, say).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    size: u16,
+    origin: u8,
+    tagged: bool,
+    addressable: bool,
+    protocol: u16,
+    source: u32,
+}
+
+/// Builds a valid [Frame], see [Frame::builder]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameBuilder {
+    frame: Frame,
+}
+
+impl FrameBuilder {
+    /// Sets the size of the entire message, in bytes
+    pub const fn size(mut self, size: u16) -> FrameBuilder {
+        self.frame.size = size;
+        self
+    }
+
+    /// Sets whether the message is for all devices (see [Frame::tagged])
+    pub const fn tagged(mut self, tagged: bool) -> FrameBuilder {
+        self.frame.tagged = tagged;
+        self
+    }
+
+    /// Sets the source identifier (see [Frame::source])
+    pub const fn source(mut self, source: u32) -> FrameBuilder {
+        self.frame.source = source;
+        self
+    }
+
+    pub const fn build(self) -> Frame {
+        self.frame
+    }
+}
+
+/// The Frame Address section contains the following routing information:
+///
+/// * Target device address
+/// * Acknowledgement message is required flag
+/// * State response message is required flag
+/// * Message sequence number
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameAddress {
+    /// 64 bits: 6 byte device address (MAC address) or zero (0) means all devices
+    pub target: u64,
+
+    /// 48 bits: Must all be zero (0)
+    pub reserved: [u8; 6],
+
+    /// 6 bits: Reserved
+    pub reserved2: u8,
+
+    /// 1 bit: Acknowledgement message required
+    pub ack_required: bool,
+
+    /// 1 bit: Response message required
+    pub res_required: bool,
+
+    /// 8 bits: Wrap around message sequence number
+    pub sequence: u8,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolHeader {
+    /// 64 bits: Reserved
+    pub reserved: u64,
+
+    /// 16 bits: Message type determines the payload being used
+    ///
+    /// See also [Message::get_num]
+    pub typ: u16,
+
+    /// 16 bits: Reserved
+    pub reserved2: u16,
+}
+
+/// Returns an error if `v` is too short to hold a `what`, which is `len` bytes long
+fn check_len(v: &[u8], len: usize, what: &str) -> Result<(), Error> {
+    if v.len() < len {
+        return Err(Error::ProtocolError(format!(
+            "{} needs {} bytes, but only {} were available",
+            what,
+            len,
+            v.len()
+        )));
+    }
+    Ok(())
+}
+
+// These read little endian integers directly out of a byte slice, which must already be known to
+// be long enough (see `check_len`)
+
+fn le_u16(v: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes(v[at..at + 2].try_into().unwrap())
+}
+
+fn le_u32(v: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes(v[at..at + 4].try_into().unwrap())
+}
+
+fn le_u64(v: &[u8], at: usize) -> u64 {
+    u64::from_le_bytes(v[at..at + 8].try_into().unwrap())
+}
+
+impl Frame {
+    /// Packed size, in bytes
+    pub const PACKED_SIZE: usize = 8;
+
+    /// Packed size, in bytes
+    pub const fn packed_size() -> usize {
+        Self::PACKED_SIZE
+    }
+
+    /// Starts building a frame, with origin 0, addressable set, and protocol [PROTOCOL_NUMBER]
+    ///
+    /// The size, `tagged`, and source all start at zero or false.
+    pub const fn builder() -> FrameBuilder {
+        FrameBuilder {
+            frame: Frame {
+                size: 0,
+                origin: 0,
+                tagged: false,
+                addressable: true,
+                protocol: PROTOCOL_NUMBER,
+                source: 0,
+            },
+        }
+    }
+
+    /// Makes a frame out of any values, valid or not
+    ///
+    /// Nothing is checked.  When packed, the origin is truncated to its low 2 bits, and the
+    /// protocol to its low 12 bits.
+    pub const fn from_parts_unchecked(
+        size: u16,
+        origin: u8,
+        tagged: bool,
+        addressable: bool,
+        protocol: u16,
+        source: u32,
+    ) -> Frame {
+        Frame {
+            size,
+            origin,
+            tagged,
+            addressable,
+            protocol,
+            source,
+        }
+    }
+
+    /// 16 bits (bytes 0-1): Size of entire message in bytes including this field
+    pub const fn size(&self) -> u16 {
+        self.size
+    }
+
+    /// 2 bits (bits 14-15 of bytes 2-3): Message origin indicator
+    ///
+    /// Clients must send zero (0), but devices have been seen replying with one (1).
+    pub const fn origin(&self) -> u8 {
+        self.origin
+    }
+
+    /// 1 bit (bit 13 of bytes 2-3): Determines usage of the Frame Address target field
+    pub const fn tagged(&self) -> bool {
+        self.tagged
+    }
+
+    /// 1 bit (bit 12 of bytes 2-3): Message includes a target address: must be one (1)
+    pub const fn addressable(&self) -> bool {
+        self.addressable
+    }
+
+    /// 12 bits (bits 0-11 of bytes 2-3): Protocol number: must be [PROTOCOL_NUMBER] (1024
+    /// decimal)
+    pub const fn protocol(&self) -> u16 {
+        self.protocol
+    }
+
+    /// 32 bits (bytes 4-7): Source identifier: unique value set by the client, used by responses.
+    ///
+    /// If the source identifier is zero, then the LIFX device may send a broadcast message that can
+    /// be received by all clients on the same subnet.
+    ///
+    /// If this packet is a reply, then this source field will be set to the same value as the client-
+    /// sent request packet.
+    pub const fn source(&self) -> u32 {
+        self.source
+    }
+
+    /// Sets the size of the entire message, in bytes
+    pub fn set_size(&mut self, size: u16) {
+        self.size = size;
+    }
+
+    /// Sets whether the message is for all devices
+    pub fn set_tagged(&mut self, tagged: bool) {
+        self.tagged = tagged;
+    }
+
+    /// Sets the source identifier
+    pub fn set_source(&mut self, source: u32) {
+        self.source = source;
+    }
+
+    fn validate(&self) {
+        assert!(self.origin < 4);
+        assert!(self.addressable);
+        assert_eq!(self.protocol, PROTOCOL_NUMBER);
+    }
+
+    /// Like `validate`, but returns an error instead of panicking, for frames that came from the
+    /// network
+    fn check(&self) -> Result<(), Error> {
+        if self.origin >= 4 || !self.addressable || self.protocol != PROTOCOL_NUMBER {
+            return Err(Error::ProtocolError(format!(
+                "Not a LIFX frame (protocol {}, addressable {})",
+                self.protocol, self.addressable
+            )));
+        }
+        Ok(())
+    }
+
+    fn pack(&self) -> Result<Vec<u8>, Error> {
+        let mut v = Vec::with_capacity(Self::packed_size());
+
+        v.extend_from_slice(&self.size.to_le_bytes());
+
+        // pack origin + tagged + addressable +  protocol as a u16
+        let mut d: u16 = (<u16 as From<u8>>::from(self.origin) & 0b11) << 14;
+        d += if self.tagged { 1 } else { 0 } << 13;
+        d += if self.addressable { 1 } else { 0 } << 12;
+        d += self.protocol & 0b1111_1111_1111;
+
+        v.extend_from_slice(&d.to_le_bytes());
+
+        v.extend_from_slice(&self.source.to_le_bytes());
+
+        Ok(v)
+    }
+
+    fn unpack(v: &[u8]) -> Result<Frame, Error> {
+        let frame = Frame::unpack_unchecked(v)?;
+        if frame.protocol != PROTOCOL_NUMBER {
+            return Err(Error::ProtocolError(format!(
+                "Unpacked frame had protocol version {}",
+                frame.protocol
+            )));
+        }
+        Ok(frame)
+    }
+
+    /// Like `unpack`, but accepts any protocol number, for [diagnose](crate::diagnose)
+    fn unpack_unchecked(v: &[u8]) -> Result<Frame, Error> {
+        check_len(v, Self::packed_size(), "Frame")?;
+
+        let size = le_u16(v, 0);
+
+        // origin + tagged + addressable + protocol
+        let d: u16 = le_u16(v, 2);
+
+        let origin: u8 = ((d & 0b1100_0000_0000_0000) >> 14) as u8;
+        let tagged: bool = (d & 0b0010_0000_0000_0000) > 0;
+        let addressable = (d & 0b0001_0000_0000_0000) > 0;
+        let protocol: u16 = d & 0b0000_1111_1111_1111;
+
+        let source = le_u32(v, 4);
+
+        let frame = Frame {
+            size,
+            origin,
+            tagged,
+            addressable,
+            protocol,
+            source,
+        };
+        Ok(frame)
+    }
+}
+
+impl FrameAddress {
+    /// Packed size, in bytes
+    pub const PACKED_SIZE: usize = 16;
+
+    /// Packed size, in bytes
+    pub const fn packed_size() -> usize {
+        Self::PACKED_SIZE
+    }
+
+    fn validate(&self) {
+        //assert_eq!(self.reserved, [0;6]);
+        //assert_eq!(self.reserved2, 0);
+    }
+    fn pack(&self) -> Result<Vec<u8>, Error> {
+        let mut v = Vec::with_capacity(Self::packed_size());
+        v.extend_from_slice(&self.target.to_le_bytes());
+        v.extend_from_slice(&self.reserved);
+
+        let b: u8 = (self.reserved2 << 2)
+            + if self.ack_required { 2 } else { 0 }
+            + if self.res_required { 1 } else { 0 };
+        v.push(b);
+        v.push(self.sequence);
+        Ok(v)
+    }
+
+    fn unpack(v: &[u8]) -> Result<FrameAddress, Error> {
+        check_len(v, Self::packed_size(), "FrameAddress")?;
+
+        let target = le_u64(v, 0);
+
+        let mut reserved: [u8; 6] = [0; 6];
+        reserved.copy_from_slice(&v[8..14]);
+
+        let b: u8 = v[14];
+        let reserved2: u8 = (b & 0b1111_1100) >> 2;
+        let ack_required = (b & 0b10) > 0;
+        let res_required = (b & 0b01) > 0;
+
+        let sequence = v[15];
+
+        let f = FrameAddress {
+            target,
+            reserved,
+            reserved2,
+            ack_required,
+            res_required,
+            sequence,
+        };
+        f.validate();
+        Ok(f)
+    }
+}
+
+impl ProtocolHeader {
+    /// Packed size, in bytes
+    pub const PACKED_SIZE: usize = 12;
+
+    /// Packed size, in bytes
+    pub const fn packed_size() -> usize {
+        Self::PACKED_SIZE
+    }
+
+    fn validate(&self) {
+        //assert_eq!(self.reserved, 0);
+        //assert_eq!(self.reserved2, 0);
+    }
+
+    /// Packs this part of the packet into some bytes
+    pub fn pack(&self) -> Result<Vec<u8>, Error> {
+        let mut v = Vec::with_capacity(Self::packed_size());
+        v.extend_from_slice(&self.reserved.to_le_bytes());
+        v.extend_from_slice(&self.typ.to_le_bytes());
+        v.extend_from_slice(&self.reserved2.to_le_bytes());
+        Ok(v)
+    }
+    fn unpack(v: &[u8]) -> Result<ProtocolHeader, Error> {
+        check_len(v, Self::packed_size(), "ProtocolHeader")?;
+
+        let reserved = le_u64(v, 0);
+        let typ = le_u16(v, 8);
+        let reserved2 = le_u16(v, 10);
+
+        let f = ProtocolHeader {
+            reserved,
+            typ,
+            reserved2,
+        };
+        f.validate();
+        Ok(f)
+    }
+}
+
+/// Options used to construct a [RawMessage].
+///
+/// See also [RawMessage::build].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct BuildOptions {
+    /// If not `None`, this is the ID of the device you want to address.
+    ///
+    /// To look up the ID of a device, extract it from the [FrameAddress::target] field when a
+    /// device sends a [Message::StateService] message.
+    pub target: Option<u64>,
+    /// Acknowledgement message required.
+    ///
+    /// Causes the light to send an [Message::Acknowledgement] message.
+    pub ack_required: bool,
+    /// Response message required.
+    ///
+    /// Some message types are sent by clients to get data from a light.  These should always have
+    /// `res_required` set to true.
+    pub res_required: bool,
+    /// A wrap around sequence number.  Optional (can be zero).
+    ///
+    /// By providing a unique sequence value, the response message will also contain the same
+    /// sequence number, allowing a client to distinguish between different messages sent with the
+    /// same `source` identifier.
+    pub sequence: u8,
+    /// A unique client identifier.
+    ///
+    /// The LIFX device will send its reply as a unicast message to the IP address/port of the
+    /// client that sent the originating message, with this same source.  This must be nonzero (see
+    /// the [source] module), and defaults to a random value that's shared by the whole process.
+    pub source: SourceId,
+}
+
+impl RawMessage {
+    /// Build a RawMessage (which is suitable for sending on the network) from a given Message
+    /// type.
+    ///
+    /// If [BuildOptions::target] is None, then the message is addressed to all devices.  Else it should be a
+    /// bulb UID (MAC address)
+    ///
+    /// Returns [Error::MessageTooLarge] if the packed message wouldn't fit in [MAX_DATAGRAM_SIZE].
+    pub fn build(options: &BuildOptions, typ: Message) -> Result<RawMessage, Error> {
+        let payload_size = typ.payload_size();
+        let message_type = typ.get_num();
+        let mut v = Vec::with_capacity(payload_size);
+        typ.write_payload(&mut v)?;
+        debug_assert_eq!(v.len(), payload_size);
+        RawMessage::with_payload(options, message_type, v)
+    }
+
+    /// Build a RawMessage from any [Payload], including ones defined outside this crate
+    ///
+    /// Like [RawMessage::build], this returns [Error::MessageTooLarge] if the packed message
+    /// wouldn't fit in [MAX_DATAGRAM_SIZE].
+    pub fn build_payload<P: Payload + ?Sized>(
+        options: &BuildOptions,
+        payload: &P,
+    ) -> Result<RawMessage, Error> {
+        let mut v = Vec::new();
+        payload.pack(&mut v)?;
+        RawMessage::with_payload(options, payload.type_num(), v)
+    }
+
+    /// Puts the headers described by `options` in front of an already packed payload
+    fn with_payload(
+        options: &BuildOptions,
+        message_type: u16,
+        payload: Vec<u8>,
+    ) -> Result<RawMessage, Error> {
+        let size = HEADER_SIZE + payload.len();
+        if size > MAX_DATAGRAM_SIZE {
+            return Err(Error::MessageTooLarge(size));
+        }
+
+        let frame = Frame::builder()
+            .size(size as u16)
+            .tagged(options.target.is_none())
+            .source(options.source.get())
+            .build();
+        let addr = FrameAddress {
+            target: options.target.unwrap_or(0),
+            reserved: [0; 6],
+            reserved2: 0,
+            ack_required: options.ack_required,
+            res_required: options.res_required,
+            sequence: options.sequence,
+        };
+        let phead = ProtocolHeader {
+            reserved: 0,
+            reserved2: 0,
+            typ: message_type,
+        };
+
+        Ok(RawMessage {
+            frame,
+            frame_addr: addr,
+            protocol_header: phead,
+            payload,
+        })
+    }
+
+    /// The total size (in bytes) of the packed version of this message.
+    pub fn packed_size(&self) -> usize {
+        HEADER_SIZE + self.payload.len()
+    }
+
+    /// Whether the sender asked for a [Message::Acknowledgement]
+    pub fn wants_ack(&self) -> bool {
+        self.frame_addr.ack_required
+    }
+
+    /// Whether the sender asked for a response (a `State` message, for most `Get` and `Set`
+    /// messages)
+    pub fn wants_response(&self) -> bool {
+        self.frame_addr.res_required
+    }
+
+    /// Whether the message is addressed to every device, rather than one target
+    pub fn is_broadcast(&self) -> bool {
+        self.frame.tagged() || self.frame_addr.target == 0
+    }
+
+    /// Whether this is a device's state that wasn't sent to any controller in particular
+    ///
+    /// When a request has a source of zero, the device broadcasts its reply (see
+    /// [Frame::source]), and some devices also broadcast [Message::StateService] and other state
+    /// on their own.  These have a source of zero, and come from a device (they have its target,
+    /// and are a type that devices send, see [is_device_message]), which tells them apart from
+    /// both replies to one controller and requests from other controllers.
+    pub fn is_unsolicited(&self) -> bool {
+        self.frame.source() == 0
+            && self.frame_addr.target != 0
+            && is_device_message(self.protocol_header.typ)
+    }
+
+    /// The options for building a reply to this message
+    ///
+    /// The reply has the same source, sequence number, and target as this message, and doesn't
+    /// ask for a reply itself.  A device answering a broadcast should put its own ID in
+    /// [BuildOptions::target], since the broadcast didn't have one.
+    ///
+    /// A source of zero can't be copied (see [SourceId]), so replies to such a message use the
+    /// default source instead.
+    ///
+    /// ```
+    /// # use lifx_core::{BuildOptions, Message, RawMessage};
+    /// let opts = BuildOptions { target: Some(0x1234), ack_required: true, sequence: 7, ..Default::default() };
+    /// let request = RawMessage::build(&opts, Message::GetPower).unwrap();
+    /// assert!(request.wants_ack() && !request.wants_response());
+    ///
+    /// let ack = Message::Acknowledgement { seq: request.frame_addr.sequence };
+    /// let reply = RawMessage::build(&request.reply_options(), ack).unwrap();
+    /// assert_eq!(reply.frame_addr.sequence, 7);
+    /// assert_eq!(reply.frame_addr.target, 0x1234);
+    /// assert_eq!(reply.frame.source(), request.frame.source());
+    /// ```
+    pub fn reply_options(&self) -> BuildOptions {
+        BuildOptions {
+            target: (!self.is_broadcast()).then_some(self.frame_addr.target),
+            ack_required: false,
+            res_required: false,
+            sequence: self.frame_addr.sequence,
+            source: SourceId::new(self.frame.source()).unwrap_or_default(),
+        }
+    }
+
+    /// Validates that this object was constructed correctly.  Panics if not.
+    pub fn validate(&self) {
+        self.frame.validate();
+        self.frame_addr.validate();
+        self.protocol_header.validate();
+    }
+
+    /// Packs this RawMessage into some bytes that can be send over the network.
+    ///
+    /// The length of the returned data will be [RawMessage::packed_size] in size.
+    pub fn pack(&self) -> Result<Vec<u8>, Error> {
+        let mut v = Vec::with_capacity(self.packed_size());
+        v.extend(self.frame.pack()?);
+        v.extend(self.frame_addr.pack()?);
+        v.extend(self.protocol_header.pack()?);
+        v.extend(&self.payload);
+        Ok(v)
+    }
+    /// Given some bytes (generally read from a network socket), unpack the data into a
+    /// `RawMessage` structure.
+    ///
+    /// Only the number of bytes given by the frame's size field are used, and anything after that
+    /// is ignored.  See [RawMessage::unpack_all] for buffers that might hold more than one message.
+    pub fn unpack(v: &[u8]) -> Result<RawMessage, Error> {
+        let mut start = 0;
+        let frame = Frame::unpack(v)?;
+        frame.check()?;
+        start += Frame::packed_size();
+        let addr = FrameAddress::unpack(v.get(start..).unwrap_or_default())?;
+        addr.validate();
+        start += FrameAddress::packed_size();
+        let proto = ProtocolHeader::unpack(v.get(start..).unwrap_or_default())?;
+        proto.validate();
+        start += ProtocolHeader::packed_size();
+
+        let size = frame.size as usize;
+        if size < start || size > v.len() {
+            return Err(Error::ProtocolError(format!(
+                "Frame size {} doesn't match datagram length {}",
+                size,
+                v.len()
+            )));
+        }
+        let body = Vec::from(&v[start..size]);
+
+        Ok(RawMessage {
+            frame,
+            frame_addr: addr,
+            protocol_header: proto,
+            payload: body,
+        })
+    }
+}
+
+impl RawMessage {
+    /// Unpacks every message in a buffer that may hold several messages back-to-back, or trailing
+    /// padding
+    ///
+    /// Each message is found using the size field of its frame.  Iteration stops at the end of the
+    /// buffer, at trailing padding (bytes that are all zero), or after the first error, since
+    /// there's no way to find where the next message starts after that.  Whatever wasn't
+    /// unpacked is available from [UnpackAll::remainder].
+    ///
+    /// ```
+    /// # use lifx_core::{BuildOptions, Message, RawMessage};
+    /// let mut buf = Vec::new();
+    /// for msg in [Message::GetService, Message::GetPower] {
+    ///     buf.extend(RawMessage::build(&BuildOptions::default(), msg).unwrap().pack().unwrap());
+    /// }
+    /// buf.extend([0; 4]);
+    ///
+    /// let mut messages = RawMessage::unpack_all(&buf);
+    /// assert_eq!(messages.by_ref().filter(|m| m.is_ok()).count(), 2);
+    /// assert_eq!(messages.remainder(), &[0; 4]);
+    /// ```
+    pub fn unpack_all(buf: &[u8]) -> UnpackAll<'_> {
+        UnpackAll {
+            rest: buf,
+            done: false,
+        }
+    }
+}
+
+/// An iterator over the messages in a buffer, created by [RawMessage::unpack_all]
+#[derive(Debug, Clone)]
+pub struct UnpackAll<'a> {
+    rest: &'a [u8],
+    done: bool,
+}
+
+impl<'a> UnpackAll<'a> {
+    /// The bytes that haven't been unpacked yet
+    ///
+    /// Once iteration has finished, this is the trailing padding, or everything from the start of
+    /// the message that failed to unpack.
+    pub fn remainder(&self) -> &'a [u8] {
+        self.rest
+    }
+}
+
+impl<'a> Iterator for UnpackAll<'a> {
+    type Item = Result<RawMessage, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.rest.iter().all(|b| *b == 0) {
+            self.done = true;
+            return None;
+        }
+        match RawMessage::unpack(self.rest) {
+            Ok(raw) => {
+                self.rest = &self.rest[raw.frame.size as usize..];
+                Some(Ok(raw))
             }
-            Message::LightSetHevCycleConfiguration {
-                indication,
-                duration,
-            } => {
-                v.write_val(indication)?;
-                v.write_val(duration)?;
+            Err(e) => {
+                self.done = true;
+                Some(Err(e))
             }
         }
+    }
+}
+
+impl std::iter::FusedIterator for UnpackAll<'_> {}
+
+impl RawMessage {
+    /// An annotated hex dump of the packed message, one line per header field, like the packet
+    /// layouts in the LIFX docs
+    ///
+    /// This is handy when comparing against packets captured from another implementation, since
+    /// it shows exactly which bytes hold which field.  The payload is dumped 16 bytes to a line.
+    ///
+    /// ```
+    /// # use lifx_core::{BuildOptions, Message, RawMessage};
+    /// let raw = RawMessage::build(&BuildOptions::default(), Message::GetService).unwrap();
+    /// let dump = raw.hexdump().to_string();
+    /// assert!(dump.contains("24 00                    size: 36"));
+    /// assert!(dump.contains("02 00                    type: 2"));
+    /// ```
+    pub fn hexdump(&self) -> Hexdump<'_> {
+        Hexdump { raw: self }
+    }
+}
+
+/// Formats a [RawMessage] as an annotated hex dump, created by [RawMessage::hexdump]
+#[derive(Debug, Clone, Copy)]
+pub struct Hexdump<'a> {
+    raw: &'a RawMessage,
+}
+
+impl<'a> std::fmt::Display for Hexdump<'a> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> Result<(), std::fmt::Error> {
+        fn line(
+            f: &mut std::fmt::Formatter,
+            bytes: &[u8],
+            what: std::fmt::Arguments,
+        ) -> Result<(), std::fmt::Error> {
+            let hex: Vec<_> = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+            writeln!(f, "  {:<24} {}", hex.join(" "), what)
+        }
+
+        let RawMessage {
+            frame,
+            frame_addr: addr,
+            protocol_header: header,
+            payload,
+        } = self.raw;
+        let frame_bytes = frame.pack().map_err(|_| std::fmt::Error)?;
+        let addr_bytes = addr.pack().map_err(|_| std::fmt::Error)?;
+        let header_bytes = header.pack().map_err(|_| std::fmt::Error)?;
+
+        writeln!(f, "frame header")?;
+        line(f, &frame_bytes[0..2], format_args!("size: {}", frame.size))?;
+        line(
+            f,
+            &frame_bytes[2..4],
+            format_args!(
+                "origin: {}, tagged: {}, addressable: {}, protocol: {}",
+                frame.origin, frame.tagged, frame.addressable, frame.protocol
+            ),
+        )?;
+        line(
+            f,
+            &frame_bytes[4..8],
+            format_args!("source: {:#010x}", frame.source),
+        )?;
+
+        writeln!(f, "frame address")?;
+        line(
+            f,
+            &addr_bytes[0..8],
+            format_args!("target: {:016x}", addr.target),
+        )?;
+        line(f, &addr_bytes[8..14], format_args!("reserved"))?;
+        line(
+            f,
+            &addr_bytes[14..15],
+            format_args!(
+                "ack_required: {}, res_required: {}",
+                addr.ack_required, addr.res_required
+            ),
+        )?;
+        line(
+            f,
+            &addr_bytes[15..16],
+            format_args!("sequence: {}", addr.sequence),
+        )?;
+
+        writeln!(f, "protocol header")?;
+        line(f, &header_bytes[0..8], format_args!("reserved"))?;
+        line(
+            f,
+            &header_bytes[8..10],
+            format_args!("type: {}", header.typ),
+        )?;
+        line(f, &header_bytes[10..12], format_args!("reserved"))?;
+
+        writeln!(f, "payload ({} bytes)", payload.len())?;
+        for (idx, chunk) in payload.chunks(16).enumerate() {
+            let hex: Vec<_> = chunk.iter().map(|b| format!("{:02x}", b)).collect();
+            writeln!(f, "  {:04x}  {}", idx * 16, hex.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TemperatureRange {
+    /// The device supports a range of temperatures
+    Variable { min: u16, max: u16 },
+    /// The device only supports 1 temperature
+    Fixed(u16),
+    /// For devices that aren't lighting products (the LIFX switch)
+    None,
+}
+
+impl TemperatureRange {
+    /// Whether a device with this range can produce a color temperature, in kelvin
+    pub const fn contains(&self, kelvin: u16) -> bool {
+        match *self {
+            TemperatureRange::Variable { min, max } => min <= kelvin && kelvin <= max,
+            TemperatureRange::Fixed(k) => k == kelvin,
+            TemperatureRange::None => false,
+        }
+    }
+}
+
+bitflags::bitflags! {
+    /// The features that a product supports
+    ///
+    /// These can be combined to ask about several features at once, for example
+    /// `caps.contains(Capabilities::MULTIZONE | Capabilities::EXTENDED_MULTIZONE)`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Capabilities: u32 {
+        /// The light changes physical appearance when the Hue value is changed
+        const COLOR = 1 << 0;
+        /// The light supports emitting infrared light
+        const INFRARED = 1 << 1;
+        /// The light supports a 1D linear array of LEDs (the Z and Beam)
+        const MULTIZONE = 1 << 2;
+        /// The light may be connected to physically separated hardware (currently only the LIFX Tile)
+        const CHAIN = 1 << 3;
+        /// The light supports a 2D matrix of LEDs (the Tile and Candle)
+        const MATRIX = 1 << 4;
+        /// The light supports emitted HEV light
+        const HEV = 1 << 5;
+        /// The device has relays for controlling physical power to something (the LIFX switch)
+        const RELAYS = 1 << 6;
+        /// The device has physical buttons to press (the LIFX switch)
+        const BUTTONS = 1 << 7;
+        /// The light understands [Message::SetExtendedColorZones] and
+        /// [Message::GetExtendedColorZone]
+        ///
+        /// Older Z and Beam products (product IDs 32 and 38) also gain this with firmware 2.77, but
+        /// since that depends on the firmware version, it isn't included in their product info.
+        const EXTENDED_MULTIZONE = 1 << 8;
+    }
+}
+
+#[derive(Clone, Debug, Copy, PartialEq, Eq)]
+pub struct ProductInfo {
+    pub name: &'static str,
+
+    /// What this product supports
+    pub capabilities: Capabilities,
+
+    /// The temperature range this device supports
+    pub temperature_range: TemperatureRange,
+
+    /// The most power the device draws, in milliwatts, if it's known
+    ///
+    /// LIFX's product data doesn't include this, so it's `None` in the built-in table unless it
+    /// was added by hand.  Register a product with the [products] registry to fill it in.
+    pub max_milliwatts: Option<u32>,
+}
+
+impl ProductInfo {
+    /// Looks up the product that sent a [Message::StateVersion]
+    ///
+    /// Returns `None` for any other message, or if the product is unknown (including products
+    /// from vendors other than LIFX).
+    ///
+    /// ```
+    /// # use lifx_core::{Message, ProductInfo};
+    /// let msg = Message::StateVersion { vendor: 1, product: 27, reserved: 0 };
+    /// assert_eq!(ProductInfo::from_state_version(&msg).unwrap().name, "LIFX A19");
+    /// ```
+    pub fn from_state_version(msg: &Message) -> Option<&'static ProductInfo> {
+        ProductId::from_state_version(msg)?.info()
+    }
+
+    /// [ProductInfo::max_milliwatts] in watts
+    pub fn max_watts(&self) -> Option<f32> {
+        self.max_milliwatts.map(|mw| mw as f32 / 1000.0)
+    }
+
+    /// Shorthand for `capabilities.contains(Capabilities::COLOR)`
+    pub const fn color(&self) -> bool {
+        self.capabilities.contains(Capabilities::COLOR)
+    }
+
+    /// Shorthand for `capabilities.contains(Capabilities::INFRARED)`
+    pub const fn infrared(&self) -> bool {
+        self.capabilities.contains(Capabilities::INFRARED)
+    }
+
+    /// Shorthand for `capabilities.contains(Capabilities::MULTIZONE)`
+    pub const fn multizone(&self) -> bool {
+        self.capabilities.contains(Capabilities::MULTIZONE)
+    }
+
+    /// Shorthand for `capabilities.contains(Capabilities::CHAIN)`
+    pub const fn chain(&self) -> bool {
+        self.capabilities.contains(Capabilities::CHAIN)
+    }
+
+    /// Shorthand for `capabilities.contains(Capabilities::HEV)`
+    pub const fn hev(&self) -> bool {
+        self.capabilities.contains(Capabilities::HEV)
+    }
+
+    /// Shorthand for `capabilities.contains(Capabilities::MATRIX)`
+    pub const fn matrix(&self) -> bool {
+        self.capabilities.contains(Capabilities::MATRIX)
+    }
+
+    /// Shorthand for `capabilities.contains(Capabilities::RELAYS)`
+    pub const fn relays(&self) -> bool {
+        self.capabilities.contains(Capabilities::RELAYS)
+    }
+
+    /// Shorthand for `capabilities.contains(Capabilities::BUTTONS)`
+    pub const fn buttons(&self) -> bool {
+        self.capabilities.contains(Capabilities::BUTTONS)
+    }
+
+    /// Shorthand for `capabilities.contains(Capabilities::EXTENDED_MULTIZONE)`
+    pub const fn extended_multizone(&self) -> bool {
+        self.capabilities.contains(Capabilities::EXTENDED_MULTIZONE)
+    }
+
+    /// Whether this product is a light, and so understands the `Light*` messages
+    ///
+    /// Every product is a light except for the ones with [relays](Capabilities::RELAYS), like the
+    /// LIFX Switch.
+    pub const fn is_light(&self) -> bool {
+        !self.relays()
+    }
+
+    /// Constructs a message that changes the power of this product, optionally fading over `fade`.
+    ///
+    /// Lighting products will get a [Message::LightSetPower] when a fade is requested.  Products
+    /// that aren't lights (like the LIFX Switch) don't understand that message, so they always get
+    /// a [Message::SetPower] and the fade is ignored.  See [ProductInfo::is_light].
+    pub fn set_power(&self, level: PowerLevel, fade: Option<Duration>) -> Message {
+        Message::set_power(level, fade.filter(|_| self.is_light()))
+    }
+
+    /// Constructs a message that fades this product to white at a color temperature (in kelvin)
+    /// and brightness, over `duration`
+    ///
+    /// This is all that White to Warm products (and other products without
+    /// [color](Capabilities::COLOR)) can do.  Returns [Error::InvalidColor] if the temperature is
+    /// outside the product's [temperature_range](ProductInfo::temperature_range).
+    ///
+    /// ```
+    /// # use lifx_core::get_product_info;
+    /// # use std::time::Duration;
+    /// let mini = get_product_info(1, 50).unwrap(); // LIFX Mini White to Warm
+    /// assert!(mini.set_white(2700, 65535, Duration::ZERO).is_ok());
+    /// assert!(mini.set_white(9000, 65535, Duration::ZERO).is_err());
+    /// ```
+    pub fn set_white(
+        &self,
+        kelvin: u16,
+        brightness: u16,
+        duration: Duration,
+    ) -> Result<Message, Error> {
+        if !self.temperature_range.contains(kelvin) {
+            return Err(Error::InvalidColor(format!(
+                "{}K is outside the temperature range of the {} ({:?})",
+                kelvin, self.name, self.temperature_range
+            )));
+        }
+        Ok(Message::set_white(kelvin, brightness, duration))
+    }
+}
+
+/// The vendor and product IDs that identify a product, as reported in a [Message::StateVersion]
+#[derive(Clone, Debug, Copy, PartialEq, Eq, Hash)]
+pub struct ProductId {
+    /// For LIFX products, this is `1`
+    pub vendor: u32,
+    pub product: u32,
+}
+
+impl ProductId {
+    /// Returns `None` if `msg` isn't a [Message::StateVersion]
+    pub fn from_state_version(msg: &Message) -> Option<ProductId> {
+        match *msg {
+            Message::StateVersion {
+                vendor, product, ..
+            } => Some(ProductId { vendor, product }),
+            _ => None,
+        }
+    }
+
+    /// Looks up info about this product, with [get_product_info]
+    pub fn info(&self) -> Option<&'static ProductInfo> {
+        get_product_info(self.vendor, self.product)
+    }
+}
+
+impl TryFrom<&Message> for ProductId {
+    type Error = Error;
+
+    fn try_from(msg: &Message) -> Result<ProductId, Error> {
+        ProductId::from_state_version(msg)
+            .ok_or_else(|| Error::ProtocolError(format!("expected StateVersion, got {:?}", msg)))
+    }
+}
+
+/// Builds a [Capabilities] constant, for use in the product table below
+macro_rules! caps {
+    ($($flag:ident),*) => {
+        const { Capabilities::empty()$(.union(Capabilities::$flag))* }
+    };
+}
+
+/// Look up info about what a LIFX product supports.
+///
+/// You can get the vendor and product IDs from a bulb by receiving a [Message::StateVersion] message
+///
+/// Data for LIFX products (vendor 1) is built in.  Products from other vendors return `None`
+/// unless they've been added with [products::register_vendor] or [products::register_product].
+pub fn get_product_info(vendor: u32, product: u32) -> Option<&'static ProductInfo> {
+    products::lookup(ProductId { vendor, product })
+        .unwrap_or_else(|| builtin_product_info(vendor, product))
+}
+
+/// The built-in product table
+///
+/// Data is taken from <https://github.com/LIFX/products/blob/master/products.json>
+#[rustfmt::skip]
+fn builtin_product_info(vendor: u32, product: u32) -> Option<&'static ProductInfo> {
+    match (vendor, product) {
+        (1, 1) => Some(&ProductInfo { name: "LIFX Original 1000", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 3) => Some(&ProductInfo { name: "LIFX Color 650", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 10) => Some(&ProductInfo { name: "LIFX White 800 (Low Voltage)", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2700, max: 6500 }, max_milliwatts: None }),
+        (1, 11) => Some(&ProductInfo { name: "LIFX White 800 (High Voltage)", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2700, max: 6500 }, max_milliwatts: None }),
+        (1, 15) => Some(&ProductInfo { name: "LIFX Color 1000", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 18) => Some(&ProductInfo { name: "LIFX White 900 BR30 (Low Voltage)", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 19) => Some(&ProductInfo { name: "LIFX White 900 BR30 (High Voltage)", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 20) => Some(&ProductInfo { name: "LIFX Color 1000 BR30", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 22) => Some(&ProductInfo { name: "LIFX Color 1000", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 27) => Some(&ProductInfo { name: "LIFX A19", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 28) => Some(&ProductInfo { name: "LIFX BR30", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 29) => Some(&ProductInfo { name: "LIFX A19 Night Vision", capabilities: caps!(COLOR, INFRARED), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 30) => Some(&ProductInfo { name: "LIFX BR30 Night Vision", capabilities: caps!(COLOR, INFRARED), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 31) => Some(&ProductInfo { name: "LIFX Z", capabilities: caps!(COLOR, MULTIZONE), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 32) => Some(&ProductInfo { name: "LIFX Z", capabilities: caps!(COLOR, MULTIZONE), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 36) => Some(&ProductInfo { name: "LIFX Downlight", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 37) => Some(&ProductInfo { name: "LIFX Downlight", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 38) => Some(&ProductInfo { name: "LIFX Beam", capabilities: caps!(COLOR, MULTIZONE), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 39) => Some(&ProductInfo { name: "LIFX Downlight White to Warm", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 40) => Some(&ProductInfo { name: "LIFX Downlight", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 43) => Some(&ProductInfo { name: "LIFX A19", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 44) => Some(&ProductInfo { name: "LIFX BR30", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 45) => Some(&ProductInfo { name: "LIFX A19 Night Vision", capabilities: caps!(COLOR, INFRARED), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 46) => Some(&ProductInfo { name: "LIFX BR30 Night Vision", capabilities: caps!(COLOR, INFRARED), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 49) => Some(&ProductInfo { name: "LIFX Mini Color", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 50) => Some(&ProductInfo { name: "LIFX Mini White to Warm", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 1500, max: 6500 }, max_milliwatts: None }),
+        (1, 51) => Some(&ProductInfo { name: "LIFX Mini White", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }, max_milliwatts: None }),
+        (1, 52) => Some(&ProductInfo { name: "LIFX GU10", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 53) => Some(&ProductInfo { name: "LIFX GU10", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 55) => Some(&ProductInfo { name: "LIFX Tile", capabilities: caps!(COLOR, CHAIN, MATRIX), temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }, max_milliwatts: None }),
+        (1, 57) => Some(&ProductInfo { name: "LIFX Candle", capabilities: caps!(COLOR, MATRIX), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 59) => Some(&ProductInfo { name: "LIFX Mini Color", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 60) => Some(&ProductInfo { name: "LIFX Mini White to Warm", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 1500, max: 6500 }, max_milliwatts: None }),
+        (1, 61) => Some(&ProductInfo { name: "LIFX Mini White", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }, max_milliwatts: None }),
+        (1, 62) => Some(&ProductInfo { name: "LIFX A19", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 63) => Some(&ProductInfo { name: "LIFX BR30", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 64) => Some(&ProductInfo { name: "LIFX A19 Night Vision", capabilities: caps!(COLOR, INFRARED), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 65) => Some(&ProductInfo { name: "LIFX BR30 Night Vision", capabilities: caps!(COLOR, INFRARED), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 66) => Some(&ProductInfo { name: "LIFX Mini White", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }, max_milliwatts: None }),
+        (1, 68) => Some(&ProductInfo { name: "LIFX Candle", capabilities: caps!(COLOR, MATRIX), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 70) => Some(&ProductInfo { name: "LIFX Switch", capabilities: caps!(RELAYS, BUTTONS), temperature_range: TemperatureRange::None, max_milliwatts: None }),
+        (1, 71) => Some(&ProductInfo { name: "LIFX Switch", capabilities: caps!(RELAYS, BUTTONS), temperature_range: TemperatureRange::None, max_milliwatts: None }),
+        (1, 81) => Some(&ProductInfo { name: "LIFX Candle White to Warm", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2200, max: 6500 }, max_milliwatts: None }),
+        (1, 82) => Some(&ProductInfo { name: "LIFX Filament Clear", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2100, max: 2100 }, max_milliwatts: None }),
+        (1, 85) => Some(&ProductInfo { name: "LIFX Filament Amber", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2000, max: 2000 }, max_milliwatts: None }),
+        (1, 87) => Some(&ProductInfo { name: "LIFX Mini White", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }, max_milliwatts: None }),
+        (1, 88) => Some(&ProductInfo { name: "LIFX Mini White", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }, max_milliwatts: None }),
+        (1, 89) => Some(&ProductInfo { name: "LIFX Switch", capabilities: caps!(RELAYS, BUTTONS), temperature_range: TemperatureRange::None, max_milliwatts: None }),
+        (1, 90) => Some(&ProductInfo { name: "LIFX Clean", capabilities: caps!(COLOR, HEV), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 91) => Some(&ProductInfo { name: "LIFX Color", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 92) => Some(&ProductInfo { name: "LIFX Color", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 93) => Some(&ProductInfo { name: "LIFX A19 US", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 94) => Some(&ProductInfo { name: "LIFX BR30", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 96) => Some(&ProductInfo { name: "LIFX Candle White to Warm", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2200, max: 6500 }, max_milliwatts: None }),
+        (1, 97) => Some(&ProductInfo { name: "LIFX A19", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 98) => Some(&ProductInfo { name: "LIFX BR30", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 99) => Some(&ProductInfo { name: "LIFX Clean", capabilities: caps!(COLOR, HEV), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 100) => Some(&ProductInfo { name: "LIFX Filament Clear", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2100, max: 2100 }, max_milliwatts: None }),
+        (1, 101) => Some(&ProductInfo { name: "LIFX Filament Amber", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2000, max: 2000 }, max_milliwatts: None }),
+        (1, 109) => Some(&ProductInfo { name: "LIFX A19 Night Vision", capabilities: caps!(COLOR, INFRARED), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 110) => Some(&ProductInfo { name: "LIFX BR30 Night Vision", capabilities: caps!(COLOR, INFRARED), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 111) => Some(&ProductInfo { name: "LIFX A19 Night Vision", capabilities: caps!(COLOR, INFRARED), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 112) => Some(&ProductInfo { name: "LIFX BR30 Night Vision Intl", capabilities: caps!(COLOR, INFRARED), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 113) => Some(&ProductInfo { name: "LIFX Mini WW US", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 114) => Some(&ProductInfo { name: "LIFX Mini WW Intl", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 115) => Some(&ProductInfo { name: "LIFX Switch", capabilities: caps!(RELAYS, BUTTONS), temperature_range: TemperatureRange::None, max_milliwatts: None }),
+        (1, 116) => Some(&ProductInfo { name: "LIFX Switch", capabilities: caps!(RELAYS, BUTTONS), temperature_range: TemperatureRange::None, max_milliwatts: None }),
+        (1, 117) => Some(&ProductInfo { name: "LIFX Z US", capabilities: caps!(COLOR, MULTIZONE, EXTENDED_MULTIZONE), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 118) => Some(&ProductInfo { name: "LIFX Z Intl", capabilities: caps!(COLOR, MULTIZONE, EXTENDED_MULTIZONE), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 119) => Some(&ProductInfo { name: "LIFX Beam US", capabilities: caps!(COLOR, MULTIZONE, EXTENDED_MULTIZONE), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 120) => Some(&ProductInfo { name: "LIFX Beam Intl", capabilities: caps!(COLOR, MULTIZONE, EXTENDED_MULTIZONE), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 123) => Some(&ProductInfo { name: "LIFX Color US", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 124) => Some(&ProductInfo { name: "LIFX Color Intl", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 125) => Some(&ProductInfo { name: "LIFX White to Warm US", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 126) => Some(&ProductInfo { name: "LIFX White to Warm Intl", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 127) => Some(&ProductInfo { name: "LIFX White US", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }, max_milliwatts: None }),
+        (1, 128) => Some(&ProductInfo { name: "LIFX White Intl", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }, max_milliwatts: None }),
+        (1, 129) => Some(&ProductInfo { name: "LIFX Color US", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 130) => Some(&ProductInfo { name: "LIFX Color Intl", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 131) => Some(&ProductInfo { name: "LIFX White To Warm US", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 132) => Some(&ProductInfo { name: "LIFX White To Warm Intl", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 133) => Some(&ProductInfo { name: "LIFX White US", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }, max_milliwatts: None }),
+        (1, 134) => Some(&ProductInfo { name: "LIFX White Intl", capabilities: caps!(), temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }, max_milliwatts: None }),
+        (1, 135) => Some(&ProductInfo { name: "LIFX GU10 Color US", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 136) => Some(&ProductInfo { name: "LIFX GU10 Color Intl", capabilities: caps!(COLOR), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 137) => Some(&ProductInfo { name: "LIFX Candle Color US", capabilities: caps!(COLOR, MATRIX), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (1, 138) => Some(&ProductInfo { name: "LIFX Candle Color Intl", capabilities: caps!(COLOR, MATRIX), temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }, max_milliwatts: None }),
+        (_, _) => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_frame() {
+        let frame = Frame::builder()
+            .size(0x1122)
+            .tagged(true)
+            .source(1234567)
+            .build();
+        frame.validate();
+
+        let v = frame.pack().unwrap();
+        println!("{:?}", v);
+        assert_eq!(v[0], 0x22);
+        assert_eq!(v[1], 0x11);
+
+        assert_eq!(v.len(), Frame::packed_size());
+
+        let unpacked = Frame::unpack(&v).unwrap();
+        assert_eq!(frame, unpacked);
+    }
+
+    #[test]
+    fn test_frame_unchecked() {
+        let mut frame = Frame::from_parts_unchecked(8, 7, false, false, 0x1fff, 0);
+        assert!(frame.check().is_err());
+        frame.set_tagged(true);
+        frame.set_source(42);
+
+        // the origin and protocol don't fit in their bits, so they're cut off
+        let unpacked = Frame::unpack_unchecked(&frame.pack().unwrap()).unwrap();
+        assert_eq!(
+            unpacked,
+            Frame::from_parts_unchecked(8, 3, true, false, 0xfff, 42)
+        );
+        assert!(Frame::unpack(&frame.pack().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_decode_frame() {
+        //             00    01    02    03    04    05    06    07
+        let v = vec![0x28, 0x00, 0x00, 0x54, 0x42, 0x52, 0x4b, 0x52];
+        let frame = Frame::unpack(&v).unwrap();
+        println!("{:?}", frame);
+
+        // manual decoding:
+        // size: 0x0028 ==> 40
+        // 0x00, 0x54 (origin, tagged, addressable, protocol)
+
+        //  /-Origin ==> 0
+        // || /- addressable=1
+        // || |
+        // 01010100 00000000
+        //   |
+        //   \- Tagged=0
+
+        assert_eq!(frame.size, 0x0028);
+        assert_eq!(frame.origin, 1);
+        assert!(frame.addressable);
+        assert!(!frame.tagged);
+        assert_eq!(frame.protocol, 1024);
+        assert_eq!(frame.source, 0x524b5242);
+    }
+
+    #[test]
+    fn test_decode_frame1() {
+        //             00    01    02    03    04    05    06    07
+        let v = vec![0x24, 0x00, 0x00, 0x14, 0xca, 0x41, 0x37, 0x05];
+        let frame = Frame::unpack(&v).unwrap();
+        println!("{:?}", frame);
+
+        // 00010100 00000000
+
+        assert_eq!(frame.size, 0x0024);
+        assert_eq!(frame.origin, 0);
+        assert!(!frame.tagged);
+        assert!(frame.addressable);
+        assert_eq!(frame.protocol, 1024);
+        assert_eq!(frame.source, 0x053741ca);
+    }
+
+    #[test]
+    fn test_frame_address() {
+        let frame = FrameAddress {
+            target: 0x11224488,
+            reserved: [0; 6],
+            reserved2: 0,
+            ack_required: true,
+            res_required: false,
+            sequence: 248,
+        };
+        frame.validate();
+
+        let v = frame.pack().unwrap();
+        assert_eq!(v.len(), FrameAddress::packed_size());
+        println!("Packed FrameAddress: {:?}", v);
+
+        let unpacked = FrameAddress::unpack(&v).unwrap();
+        assert_eq!(frame, unpacked);
+    }
+
+    #[test]
+    fn test_decode_frame_address() {
+        //   1  2  3  4  5  6  7  8  9  10 11 12 13 14 15 16
+        let v = vec![
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x01, 0x9c,
+        ];
+        assert_eq!(v.len(), FrameAddress::packed_size());
 
-        let mut msg = RawMessage {
-            frame,
-            frame_addr: addr,
-            protocol_header: phead,
-            payload: v,
+        let frame = FrameAddress::unpack(&v).unwrap();
+        frame.validate();
+        println!("FrameAddress: {:?}", frame);
+    }
+
+    #[test]
+    fn test_protocol_header() {
+        let frame = ProtocolHeader {
+            reserved: 0,
+            reserved2: 0,
+            typ: 0x4455,
         };
+        frame.validate();
 
-        msg.frame.size = msg.packed_size() as u16;
+        let v = frame.pack().unwrap();
+        assert_eq!(v.len(), ProtocolHeader::packed_size());
+        println!("Packed ProtocolHeader: {:?}", v);
 
-        Ok(msg)
+        let unpacked = ProtocolHeader::unpack(&v).unwrap();
+        assert_eq!(frame, unpacked);
     }
 
-    /// The total size (in bytes) of the packed version of this message.
-    pub fn packed_size(&self) -> usize {
-        Frame::packed_size()
-            + FrameAddress::packed_size()
-            + ProtocolHeader::packed_size()
-            + self.payload.len()
-    }
+    #[test]
+    fn test_decode_protocol_header() {
+        //   1  2  3  4  5  6  7  8  9  10 11 12 13 14 15 16
+        let v = vec![
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0e, 0x00, 0x00, 0x00,
+        ];
+        assert_eq!(v.len(), ProtocolHeader::packed_size());
 
-    /// Validates that this object was constructed correctly.  Panics if not.
-    pub fn validate(&self) {
-        self.frame.validate();
-        self.frame_addr.validate();
-        self.protocol_header.validate();
+        let frame = ProtocolHeader::unpack(&v).unwrap();
+        frame.validate();
+        println!("ProtocolHeader: {:?}", frame);
     }
 
-    /// Packs this RawMessage into some bytes that can be send over the network.
-    ///
-    /// The length of the returned data will be [RawMessage::packed_size] in size.
-    pub fn pack(&self) -> Result<Vec<u8>, Error> {
-        let mut v = Vec::with_capacity(self.packed_size());
-        v.extend(self.frame.pack()?);
-        v.extend(self.frame_addr.pack()?);
-        v.extend(self.protocol_header.pack()?);
-        v.extend(&self.payload);
-        Ok(v)
+    #[test]
+    fn test_decode_full() {
+        let v = vec![
+            0x24, 0x00, 0x00, 0x14, 0xca, 0x41, 0x37, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x98, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x33, 0x00, 0x00, 0x00,
+        ];
+
+        let msg = RawMessage::unpack(&v).unwrap();
+        msg.validate();
+        println!("{:#?}", msg);
     }
-    /// Given some bytes (generally read from a network socket), unpack the data into a
-    /// `RawMessage` structure.
-    pub fn unpack(v: &[u8]) -> Result<RawMessage, Error> {
-        let mut start = 0;
-        let frame = Frame::unpack(v)?;
-        frame.validate();
-        start += Frame::packed_size();
-        let addr = FrameAddress::unpack(&v[start..])?;
-        addr.validate();
-        start += FrameAddress::packed_size();
-        let proto = ProtocolHeader::unpack(&v[start..])?;
-        proto.validate();
-        start += ProtocolHeader::packed_size();
 
-        let body = Vec::from(&v[start..(frame.size as usize)]);
+    #[test]
+    fn test_decode_full_1() {
+        let v = vec![
+            0x58, 0x00, 0x00, 0x54, 0xca, 0x41, 0x37, 0x05, 0xd0, 0x73, 0xd5, 0x02, 0x97, 0xde,
+            0x00, 0x00, 0x4c, 0x49, 0x46, 0x58, 0x56, 0x32, 0x00, 0xc0, 0x44, 0x30, 0xeb, 0x47,
+            0xc4, 0x48, 0x18, 0x14, 0x6b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff,
+            0xb8, 0x0b, 0x00, 0x00, 0xff, 0xff, 0x4b, 0x69, 0x74, 0x63, 0x68, 0x65, 0x6e, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00,
+        ];
 
-        Ok(RawMessage {
-            frame,
-            frame_addr: addr,
-            protocol_header: proto,
-            payload: body,
-        })
+        let msg = RawMessage::unpack(&v).unwrap();
+        msg.validate();
+        println!("{:#?}", msg);
     }
-}
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum TemperatureRange {
-    /// The device supports a range of temperatures
-    Variable { min: u16, max: u16 },
-    /// The device only supports 1 temperature
-    Fixed(u16),
-    /// For devices that aren't lighting products (the LIFX switch)
-    None,
-}
+    #[test]
+    fn test_build_a_packet() {
+        // packet taken from https://lan.developer.lifx.com/docs/building-a-lifx-packet
 
-#[derive(Clone, Debug, Copy, PartialEq, Eq)]
-pub struct ProductInfo {
-    pub name: &'static str,
+        let msg = Message::LightSetColor {
+            reserved: 0,
+            color: HSBK {
+                hue: 21845,
+                saturation: 0xffff,
+                brightness: 0xffff,
+                kelvin: 3500,
+            },
+            duration: 1024,
+        };
+
+        let mut raw = RawMessage::build(
+            &BuildOptions {
+                target: None,
+                ack_required: false,
+                res_required: false,
+                sequence: 0,
+                ..Default::default()
+            },
+            msg,
+        )
+        .unwrap();
+        // this matches an example from the LIFX docs, which uses a source of zero
+        raw.frame.source = 0;
+
+        let bytes = raw.pack().unwrap();
+        println!("{:?}", bytes);
+        assert_eq!(bytes.len(), 49);
+        assert_eq!(
+            bytes,
+            vec![
+                0x31, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+                0x00, 0x00, 0x00, 0x00, 0x66, 0x00, 0x00, 0x00, 0x00, 0x55, 0x55, 0xFF, 0xFF, 0xFF,
+                0xFF, 0xAC, 0x0D, 0x00, 0x04, 0x00, 0x00
+            ]
+        );
+    }
 
-    /// The light changes physical appearance when the Hue value is changed
-    pub color: bool,
+    #[test]
+    fn test_lifx_string() {
+        let s = CStr::from_bytes_with_nul(b"hello\0").unwrap();
+        let ls = LifxString::new(s);
+        assert_eq!(ls.cstr(), s);
+        assert!(ls.cstr().to_bytes_with_nul().len() <= 32);
 
-    /// The light supports emitting infrared light
-    pub infrared: bool,
+        let s = CStr::from_bytes_with_nul(b"this is bigger than thirty two characters\0").unwrap();
+        let ls = LifxString::new(s);
+        assert_eq!(ls.cstr().to_bytes_with_nul().len(), 32);
+        assert_eq!(
+            ls.cstr(),
+            CStr::from_bytes_with_nul(b"this is bigger than thirty two \0").unwrap()
+        );
+    }
 
-    /// The light supports a 1D linear array of LEDs (the Z and Beam)
-    pub multizone: bool,
+    #[test]
+    fn test_echo_payload() {
+        let payload = EchoPayload::from(&b"hello"[..]);
+        assert_eq!(&payload[..5], b"hello");
+        assert!(payload[5..].iter().all(|b| *b == 0));
+        assert_eq!(payload, b"hello"[..]);
+        assert_ne!(payload, b"hello!"[..]);
+        assert!(format!("{}", payload).starts_with("68656c6c6f00"));
+        assert_eq!(format!("{}", payload).len(), 128);
+
+        // longer slices are truncated
+        let long = [0xab; 100];
+        let payload = EchoPayload::from(&long[..]);
+        assert_eq!(payload.0, [0xab; 64]);
+        assert_ne!(payload, long[..]);
 
-    /// The light may be connected to physically separated hardware (currently only the LIFX Tile)
-    pub chain: bool,
+        let raw = RawMessage::build(
+            &BuildOptions::default(),
+            Message::EchoRequest {
+                payload: EchoPayload::from(&b"ping"[..]),
+            },
+        )
+        .unwrap();
+        match Message::from_raw(&raw).unwrap() {
+            Message::EchoRequest { payload } => assert_eq!(payload, b"ping"[..]),
+            _ => panic!("Unexpected message"),
+        }
+    }
 
-    /// The light supports emitted HEV light
-    pub hev: bool,
+    #[test]
+    fn test_lifx_ident_display() {
+        let ident = LifxIdent([
+            0x67, 0xe5, 0x54, 0x3c, 0x9a, 0x1f, 0x4b, 0x2e, 0x8f, 0x06, 0x1d, 0x3a, 0x5b, 0x7c,
+            0x90, 0xe1,
+        ]);
+        assert_eq!(ident.to_string(), "67e5543c-9a1f-4b2e-8f06-1d3a5b7c90e1");
+
+        #[cfg(feature = "uuid")]
+        {
+            let u = ident.as_uuid();
+            assert_eq!(u.to_string(), ident.to_string());
+            assert_eq!(LifxIdent::from(u), ident);
+        }
+    }
 
-    /// The light supports a 2D matrix of LEDs (the Tile and Candle)
-    pub matrix: bool,
+    #[test]
+    fn test_newest_group() {
+        let label = LifxString::new(CStr::from_bytes_with_nul(b"Kitchen\0").unwrap());
+        let old = Message::StateGroup {
+            group: LifxIdent([1; 16]),
+            label: label.clone(),
+            updated_at: 1000,
+        };
+        let new = Message::set_group(LifxIdent([2; 16]), label);
+        assert!(new.updated_at().unwrap() > 1000);
 
-    /// The device has relays for controlling physical power to something (the LIFX switch)
-    pub relays: bool,
+        let msgs = vec![old.clone(), Message::GetGroup, new.clone()];
+        assert_eq!(Message::newest(&msgs), Some(&new));
+        assert_eq!(Message::newest(&[Message::GetGroup]), None);
+    }
 
-    /// The device has physical buttons to press (the LIFX switch)
-    pub buttons: bool,
+    #[test]
+    fn test_time_helpers() {
+        let msg = Message::StateHostFirmware {
+            build: 1_600_000_000_000_000_000,
+            reserved: 0,
+            version_minor: 70,
+            version_major: 3,
+        };
+        assert_eq!(
+            msg.build_time(),
+            Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000))
+        );
+        #[cfg(feature = "chrono")]
+        assert_eq!(msg.build_datetime().unwrap().timestamp(), 1_600_000_000);
+        #[cfg(feature = "time")]
+        assert_eq!(
+            msg.build_offset_datetime().unwrap().unix_timestamp(),
+            1_600_000_000
+        );
 
-    /// The temperature range this device supports
-    pub temperature_range: TemperatureRange,
-}
+        let firmware = FirmwareVersion::from_state_firmware(&msg).unwrap();
+        assert_eq!(firmware.to_string(), "3.70 (built 2020-09-13)");
+        assert_eq!(firmware.build_time(), msg.build_time());
+        let firmware = FirmwareVersion {
+            build: 0,
+            ..firmware
+        };
+        assert_eq!(firmware.to_string(), "3.70");
+        assert_eq!(firmware.build_date(), None);
+        assert_eq!(
+            FirmwareVersion::from_state_firmware(&Message::GetInfo),
+            None
+        );
+        // leap days, and the ends of months and years
+        for (secs, date) in [
+            (0, (1970, 1, 1)),
+            (951_782_400, (2000, 2, 29)),
+            (951_868_799, (2000, 2, 29)),
+            (1_704_067_199, (2023, 12, 31)),
+            (1_709_251_200, (2024, 3, 1)),
+        ] {
+            assert_eq!(nanos_to_date(secs * 1_000_000_000), date);
+        }
 
-/// Look up info about what a LIFX product supports.
-///
-/// You can get the vendor and product IDs from a bulb by receiving a [Message::StateVersion] message
-///
-/// Data is taken from <https://github.com/LIFX/products/blob/master/products.json>
-#[rustfmt::skip]
-pub fn get_product_info(vendor: u32, product: u32) -> Option<&'static ProductInfo> {
-    match (vendor, product) {
-        (1, 1) => Some(&ProductInfo { name: "LIFX Original 1000", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 3) => Some(&ProductInfo { name: "LIFX Color 650", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 10) => Some(&ProductInfo { name: "LIFX White 800 (Low Voltage)", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2700, max: 6500 }  }),
-        (1, 11) => Some(&ProductInfo { name: "LIFX White 800 (High Voltage)", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2700, max: 6500 }  }),
-        (1, 15) => Some(&ProductInfo { name: "LIFX Color 1000", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 18) => Some(&ProductInfo { name: "LIFX White 900 BR30 (Low Voltage)", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 19) => Some(&ProductInfo { name: "LIFX White 900 BR30 (High Voltage)", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 20) => Some(&ProductInfo { name: "LIFX Color 1000 BR30", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 22) => Some(&ProductInfo { name: "LIFX Color 1000", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 27) => Some(&ProductInfo { name: "LIFX A19", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 28) => Some(&ProductInfo { name: "LIFX BR30", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 29) => Some(&ProductInfo { name: "LIFX A19 Night Vision", color: true, infrared: true, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 30) => Some(&ProductInfo { name: "LIFX BR30 Night Vision", color: true, infrared: true, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 31) => Some(&ProductInfo { name: "LIFX Z", color: true, infrared: false, multizone: true, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 32) => Some(&ProductInfo { name: "LIFX Z", color: true, infrared: false, multizone: true, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 36) => Some(&ProductInfo { name: "LIFX Downlight", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 37) => Some(&ProductInfo { name: "LIFX Downlight", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 38) => Some(&ProductInfo { name: "LIFX Beam", color: true, infrared: false, multizone: true, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 39) => Some(&ProductInfo { name: "LIFX Downlight White to Warm", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 40) => Some(&ProductInfo { name: "LIFX Downlight", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 43) => Some(&ProductInfo { name: "LIFX A19", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 44) => Some(&ProductInfo { name: "LIFX BR30", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 45) => Some(&ProductInfo { name: "LIFX A19 Night Vision", color: true, infrared: true, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 46) => Some(&ProductInfo { name: "LIFX BR30 Night Vision", color: true, infrared: true, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 49) => Some(&ProductInfo { name: "LIFX Mini Color", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 50) => Some(&ProductInfo { name: "LIFX Mini White to Warm", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 6500 }  }),
-        (1, 51) => Some(&ProductInfo { name: "LIFX Mini White", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }  }),
-        (1, 52) => Some(&ProductInfo { name: "LIFX GU10", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 53) => Some(&ProductInfo { name: "LIFX GU10", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 55) => Some(&ProductInfo { name: "LIFX Tile", color: true, infrared: false, multizone: false, chain: true, hev: false, matrix: true, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2500, max: 9000 }  }),
-        (1, 57) => Some(&ProductInfo { name: "LIFX Candle", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: true, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 59) => Some(&ProductInfo { name: "LIFX Mini Color", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 60) => Some(&ProductInfo { name: "LIFX Mini White to Warm", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 6500 }  }),
-        (1, 61) => Some(&ProductInfo { name: "LIFX Mini White", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }  }),
-        (1, 62) => Some(&ProductInfo { name: "LIFX A19", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 63) => Some(&ProductInfo { name: "LIFX BR30", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 64) => Some(&ProductInfo { name: "LIFX A19 Night Vision", color: true, infrared: true, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 65) => Some(&ProductInfo { name: "LIFX BR30 Night Vision", color: true, infrared: true, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 66) => Some(&ProductInfo { name: "LIFX Mini White", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }  }),
-        (1, 68) => Some(&ProductInfo { name: "LIFX Candle", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: true, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 70) => Some(&ProductInfo { name: "LIFX Switch", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: true, buttons: true, temperature_range: TemperatureRange::None }),
-        (1, 71) => Some(&ProductInfo { name: "LIFX Switch", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: true, buttons: true, temperature_range: TemperatureRange::None }),
-        (1, 81) => Some(&ProductInfo { name: "LIFX Candle White to Warm", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2200, max: 6500 }  }),
-        (1, 82) => Some(&ProductInfo { name: "LIFX Filament Clear", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2100, max: 2100 }  }),
-        (1, 85) => Some(&ProductInfo { name: "LIFX Filament Amber", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2000, max: 2000 }  }),
-        (1, 87) => Some(&ProductInfo { name: "LIFX Mini White", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }  }),
-        (1, 88) => Some(&ProductInfo { name: "LIFX Mini White", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }  }),
-        (1, 89) => Some(&ProductInfo { name: "LIFX Switch", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: true, buttons: true, temperature_range: TemperatureRange::None }),
-        (1, 90) => Some(&ProductInfo { name: "LIFX Clean", color: true, infrared: false, multizone: false, chain: false, hev: true, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 91) => Some(&ProductInfo { name: "LIFX Color", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 92) => Some(&ProductInfo { name: "LIFX Color", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 93) => Some(&ProductInfo { name: "LIFX A19 US", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 94) => Some(&ProductInfo { name: "LIFX BR30", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 96) => Some(&ProductInfo { name: "LIFX Candle White to Warm", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2200, max: 6500 }  }),
-        (1, 97) => Some(&ProductInfo { name: "LIFX A19", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 98) => Some(&ProductInfo { name: "LIFX BR30", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 99) => Some(&ProductInfo { name: "LIFX Clean", color: true, infrared: false, multizone: false, chain: false, hev: true, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 100) => Some(&ProductInfo { name: "LIFX Filament Clear", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2100, max: 2100 }  }),
-        (1, 101) => Some(&ProductInfo { name: "LIFX Filament Amber", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2000, max: 2000 }  }),
-        (1, 109) => Some(&ProductInfo { name: "LIFX A19 Night Vision", color: true, infrared: true, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 110) => Some(&ProductInfo { name: "LIFX BR30 Night Vision", color: true, infrared: true, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 111) => Some(&ProductInfo { name: "LIFX A19 Night Vision", color: true, infrared: true, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 112) => Some(&ProductInfo { name: "LIFX BR30 Night Vision Intl", color: true, infrared: true, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 113) => Some(&ProductInfo { name: "LIFX Mini WW US", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 114) => Some(&ProductInfo { name: "LIFX Mini WW Intl", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 115) => Some(&ProductInfo { name: "LIFX Switch", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: true, buttons: true, temperature_range: TemperatureRange::None }),
-        (1, 116) => Some(&ProductInfo { name: "LIFX Switch", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: true, buttons: true, temperature_range: TemperatureRange::None }),
-        (1, 117) => Some(&ProductInfo { name: "LIFX Z US", color: true, infrared: false, multizone: true, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 118) => Some(&ProductInfo { name: "LIFX Z Intl", color: true, infrared: false, multizone: true, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 119) => Some(&ProductInfo { name: "LIFX Beam US", color: true, infrared: false, multizone: true, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 120) => Some(&ProductInfo { name: "LIFX Beam Intl", color: true, infrared: false, multizone: true, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 123) => Some(&ProductInfo { name: "LIFX Color US", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 124) => Some(&ProductInfo { name: "LIFX Color Intl", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 125) => Some(&ProductInfo { name: "LIFX White to Warm US", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 126) => Some(&ProductInfo { name: "LIFX White to Warm Intl", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 127) => Some(&ProductInfo { name: "LIFX White US", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }  }),
-        (1, 128) => Some(&ProductInfo { name: "LIFX White Intl", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }  }),
-        (1, 129) => Some(&ProductInfo { name: "LIFX Color US", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 130) => Some(&ProductInfo { name: "LIFX Color Intl", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 131) => Some(&ProductInfo { name: "LIFX White To Warm US", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 132) => Some(&ProductInfo { name: "LIFX White To Warm Intl", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 133) => Some(&ProductInfo { name: "LIFX White US", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }  }),
-        (1, 134) => Some(&ProductInfo { name: "LIFX White Intl", color: false, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 2700, max: 2700 }  }),
-        (1, 135) => Some(&ProductInfo { name: "LIFX GU10 Color US", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 136) => Some(&ProductInfo { name: "LIFX GU10 Color Intl", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: false, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 137) => Some(&ProductInfo { name: "LIFX Candle Color US", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: true, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (1, 138) => Some(&ProductInfo { name: "LIFX Candle Color Intl", color: true, infrared: false, multizone: false, chain: false, hev: false, matrix: true, relays: false, buttons: false, temperature_range: TemperatureRange::Variable { min: 1500, max: 9000 }  }),
-        (_, _) => None
+        let msg = Message::StateInfo {
+            time: u64::MAX,
+            uptime: 5_000_000_000,
+            downtime: 0,
+        };
+        assert!(msg.device_time().is_some());
+        assert_eq!(msg.uptime(), Some(Duration::from_secs(5)));
+        assert_eq!(msg.downtime(), Some(Duration::from_secs(0)));
+        assert_eq!(Message::GetInfo.uptime(), None);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+    #[test]
+    fn test_set_white() {
+        let white = Message::LightSetColor {
+            reserved: 0,
+            color: HSBK {
+                hue: 0,
+                saturation: 0,
+                brightness: 32768,
+                kelvin: 2700,
+            },
+            duration: 1000,
+        };
+        assert_eq!(
+            Message::set_white(2700, 32768, Duration::from_secs(1)),
+            white
+        );
+
+        let mini_white = get_product_info(1, 51).unwrap();
+        assert_eq!(
+            mini_white
+                .set_white(2700, 32768, Duration::from_secs(1))
+                .unwrap(),
+            white
+        );
+        assert!(matches!(
+            mini_white.set_white(3500, 32768, Duration::ZERO),
+            Err(Error::InvalidColor(_))
+        ));
+
+        let switch = get_product_info(1, 70).unwrap();
+        assert!(switch.set_white(2700, 32768, Duration::ZERO).is_err());
+    }
 
     #[test]
-    fn test_frame() {
-        let frame = Frame {
-            size: 0x1122,
-            origin: 0,
-            tagged: true,
-            addressable: true,
-            protocol: 1024,
-            source: 1234567,
+    fn test_set_power() {
+        assert_eq!(
+            Message::set_power(PowerLevel::Enabled, None),
+            Message::SetPower {
+                level: PowerLevel::Enabled
+            }
+        );
+
+        let bulb = get_product_info(1, 27).unwrap();
+        assert_eq!(
+            bulb.set_power(PowerLevel::Enabled, Some(Duration::from_millis(1500))),
+            Message::LightSetPower {
+                level: 65535,
+                duration: 1500
+            }
+        );
+
+        let switch = get_product_info(1, 70).unwrap();
+        assert!(!switch.is_light());
+        assert_eq!(
+            switch.set_power(PowerLevel::Standby, Some(Duration::from_secs(1))),
+            Message::SetPower {
+                level: PowerLevel::Standby
+            }
+        );
+
+        let white_to_warm = get_product_info(1, 50).unwrap();
+        assert!(!white_to_warm.capabilities.contains(Capabilities::COLOR));
+        let fade = white_to_warm.set_power(PowerLevel::Standby, Some(Duration::from_secs(2)));
+        assert_eq!(
+            fade,
+            Message::LightSetPower {
+                level: 0,
+                duration: 2000
+            }
+        );
+
+        // a light registered without a temperature range is still a light
+        let unknown_range = ProductInfo {
+            temperature_range: TemperatureRange::None,
+            ..*white_to_warm
         };
-        frame.validate();
+        assert_eq!(
+            unknown_range.set_power(PowerLevel::Standby, Some(Duration::from_secs(2))),
+            fade
+        );
+    }
 
-        let v = frame.pack().unwrap();
-        println!("{:?}", v);
-        assert_eq!(v[0], 0x22);
-        assert_eq!(v[1], 0x11);
+    #[test]
+    fn test_from_state_version() {
+        let version = |vendor| Message::StateVersion {
+            vendor,
+            product: 31,
+            reserved: 0,
+        };
+        assert_eq!(
+            ProductInfo::from_state_version(&version(1)).map(|p| p.name),
+            Some("LIFX Z")
+        );
+        assert_eq!(
+            ProductId::try_from(&version(1)).unwrap(),
+            ProductId {
+                vendor: 1,
+                product: 31
+            }
+        );
 
-        assert_eq!(v.len(), Frame::packed_size());
+        // unknown vendors and other messages aren't an error, there's just nothing to find
+        assert_eq!(ProductInfo::from_state_version(&version(99)), None);
+        assert_eq!(ProductInfo::from_state_version(&Message::GetVersion), None);
+        assert!(ProductId::try_from(&Message::GetVersion).is_err());
+    }
 
-        let unpacked = Frame::unpack(&v).unwrap();
-        assert_eq!(frame, unpacked);
+    #[test]
+    fn test_zone_range() {
+        assert_eq!(
+            Message::get_color_zones(ZoneRange::ALL),
+            Message::GetColorZones {
+                start_index: 0,
+                end_index: 255
+            }
+        );
+        assert_eq!(ZoneRange::ALL.len(), 256);
+        assert_eq!(ZoneRange::ALL.iter().count(), 256);
+        assert_eq!(
+            ZoneRange::single(7).into_iter().collect::<Vec<_>>(),
+            vec![7]
+        );
+        assert_eq!(ZoneRange::try_from(2..=3).unwrap().len(), 2);
+
+        let msg = Message::set_color_zones(
+            ZoneRange::new(4, 8).unwrap(),
+            HSBK {
+                hue: 0,
+                saturation: 0,
+                brightness: 65535,
+                kelvin: 3500,
+            },
+            Duration::from_secs(1),
+            ApplicationRequest::Apply,
+        );
+        assert_eq!(ZoneRange::from_message(&msg), ZoneRange::new(4, 8).ok());
+        assert_eq!(
+            ZoneRange::from_message(&Message::GetColorZones {
+                start_index: 9,
+                end_index: 1
+            }),
+            None
+        );
     }
 
     #[test]
-    fn test_decode_frame() {
-        //             00    01    02    03    04    05    06    07
-        let v = vec![0x28, 0x00, 0x00, 0x54, 0x42, 0x52, 0x4b, 0x52];
-        let frame = Frame::unpack(&v).unwrap();
-        println!("{:?}", frame);
+    fn test_plan_color_zones() {
+        use crate::zones::plan_color_zones;
 
-        // manual decoding:
-        // size: 0x0028 ==> 40
-        // 0x00, 0x54 (origin, tagged, addressable, protocol)
-
-        //  /-Origin ==> 0
-        // || /- addressable=1
-        // || |
-        // 01010100 00000000
-        //   |
-        //   \- Tagged=0
+        let color = |hue| HSBK {
+            hue,
+            saturation: 65535,
+            brightness: 65535,
+            kelvin: 3500,
+        };
+        let changes = [
+            (ZoneRange::new(0, 9).unwrap(), color(1)),
+            (ZoneRange::new(4, 5).unwrap(), color(2)),
+            (ZoneRange::new(8, 12).unwrap(), color(1)),
+            (ZoneRange::new(20, 21).unwrap(), color(1)),
+        ];
+        let planned: Vec<_> = plan_color_zones(&changes, Duration::from_millis(250))
+            .into_iter()
+            .map(|msg| match msg {
+                Message::SetColorZones {
+                    start_index,
+                    end_index,
+                    color,
+                    duration,
+                    apply,
+                } => {
+                    assert_eq!(duration, 250);
+                    (start_index, end_index, color.hue, apply)
+                }
+                msg => panic!("unexpected message {:?}", msg),
+            })
+            .collect();
+        use ApplicationRequest::{Apply, NoApply};
+        assert_eq!(
+            planned,
+            vec![
+                (0, 3, 1, NoApply),
+                (4, 5, 2, NoApply),
+                (6, 12, 1, NoApply),
+                (20, 21, 1, Apply),
+            ]
+        );
 
-        assert_eq!(frame.size, 0x0028);
-        assert_eq!(frame.origin, 1);
-        assert!(frame.addressable);
-        assert!(!frame.tagged);
-        assert_eq!(frame.protocol, 1024);
-        assert_eq!(frame.source, 0x524b5242);
+        assert!(plan_color_zones(&[], Duration::ZERO).is_empty());
+        let all = plan_color_zones(&[(ZoneRange::ALL, color(0))], Duration::ZERO);
+        assert_eq!(
+            all,
+            vec![Message::set_color_zones(
+                ZoneRange::ALL,
+                color(0),
+                Duration::ZERO,
+                Apply
+            )]
+        );
     }
 
     #[test]
-    fn test_decode_frame1() {
-        //             00    01    02    03    04    05    06    07
-        let v = vec![0x24, 0x00, 0x00, 0x14, 0xca, 0x41, 0x37, 0x05];
-        let frame = Frame::unpack(&v).unwrap();
-        println!("{:?}", frame);
-
-        // 00010100 00000000
+    fn test_extended_zone_pages() {
+        use crate::zones::{extended_zone_pages, ExtendedZones};
+
+        let strip: Vec<HSBK> = (0..200)
+            .map(|i| HSBK {
+                hue: i,
+                saturation: 65535,
+                brightness: 65535,
+                kelvin: 3500,
+            })
+            .collect();
+        let pages =
+            extended_zone_pages(&strip, Duration::from_secs(1), ApplicationRequest::Apply).unwrap();
+        assert_eq!(pages.len(), 3);
+
+        // replay the pages as a device would report them, out of order
+        let mut zones = ExtendedZones::new();
+        assert!(!zones.is_complete());
+        assert!(!zones.add(&Message::GetExtendedColorZone));
+        for page in pages.iter().rev() {
+            if let Message::SetExtendedColorZones {
+                duration,
+                apply,
+                zone_index,
+                colors_count,
+                colors,
+            } = page
+            {
+                assert_eq!(*duration, 1000);
+                let last = *zone_index == 164;
+                assert_eq!(*colors_count, if last { 36 } else { 82 });
+                assert_eq!(
+                    *apply,
+                    if last {
+                        ApplicationRequest::Apply
+                    } else {
+                        ApplicationRequest::NoApply
+                    }
+                );
+                assert!(zones.add(&Message::StateExtendedColorZones {
+                    zones_count: 200,
+                    zone_index: *zone_index,
+                    colors_count: *colors_count,
+                    colors: colors.clone(),
+                }));
+                if last {
+                    assert_eq!(zones.missing_pages(), vec![0, 82]);
+                }
+            }
+        }
+        assert!(zones.is_complete());
+        assert_eq!(zones.zones_count(), 200);
+        assert_eq!(zones.colors(), Some(strip.clone()));
+
+        // a different zone count starts over
+        zones.add(&Message::StateExtendedColorZones {
+            zones_count: 100,
+            zone_index: 82,
+            colors_count: 18,
+            colors: Box::new([strip[0]; 82]),
+        });
+        assert_eq!(zones.missing_pages(), vec![0]);
+        assert_eq!(zones.colors(), None);
 
-        assert_eq!(frame.size, 0x0024);
-        assert_eq!(frame.origin, 0);
-        assert!(!frame.tagged);
-        assert!(frame.addressable);
-        assert_eq!(frame.protocol, 1024);
-        assert_eq!(frame.source, 0x053741ca);
+        assert!(
+            extended_zone_pages(&[], Duration::ZERO, ApplicationRequest::Apply)
+                .unwrap()
+                .is_empty()
+        );
+        assert!(extended_zone_pages(
+            &vec![strip[0]; 70000],
+            Duration::ZERO,
+            ApplicationRequest::Apply
+        )
+        .is_err());
     }
 
     #[test]
-    fn test_frame_address() {
-        let frame = FrameAddress {
-            target: 0x11224488,
-            reserved: [0; 6],
-            reserved2: 0,
-            ack_required: true,
-            res_required: false,
-            sequence: 248,
+    fn test_wifi_signal_dbm() {
+        let info = |signal| Message::StateWifiInfo {
+            #[cfg(not(fuzzing))]
+            signal,
+            #[cfg(fuzzing)]
+            signal: ComparableFloat(signal),
+            reserved6: 0,
+            reserved7: 0,
+            reserved: 0,
         };
-        frame.validate();
-
-        let v = frame.pack().unwrap();
-        assert_eq!(v.len(), FrameAddress::packed_size());
-        println!("Packed FrameAddress: {:?}", v);
-
-        let unpacked = FrameAddress::unpack(&v).unwrap();
-        assert_eq!(frame, unpacked);
+        // 1e-7 mW is -70 dBm
+        assert_eq!(info(1e-7).wifi_signal_dbm(), Some(-70));
+        assert_eq!(info(-55.0).wifi_signal_dbm(), Some(-55));
+        assert_eq!(Message::GetWifiInfo.wifi_signal_dbm(), None);
     }
 
     #[test]
-    fn test_decode_frame_address() {
-        //   1  2  3  4  5  6  7  8  9  10 11 12 13 14 15 16
-        let v = vec![
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x01, 0x9c,
-        ];
-        assert_eq!(v.len(), FrameAddress::packed_size());
-
-        let frame = FrameAddress::unpack(&v).unwrap();
-        frame.validate();
-        println!("FrameAddress: {:?}", frame);
+    fn test_capabilities() {
+        let z = get_product_info(1, 117).unwrap();
+        assert!(z.multizone());
+        assert!(z
+            .capabilities
+            .contains(Capabilities::MULTIZONE | Capabilities::EXTENDED_MULTIZONE));
+        assert!(!z
+            .capabilities
+            .intersects(Capabilities::MATRIX | Capabilities::HEV));
+
+        let switch = get_product_info(1, 70).unwrap();
+        assert_eq!(
+            switch.capabilities,
+            Capabilities::RELAYS | Capabilities::BUTTONS
+        );
+        assert!(!switch.color());
     }
 
     #[test]
-    fn test_protocol_header() {
-        let frame = ProtocolHeader {
-            reserved: 0,
-            reserved2: 0,
-            typ: 0x4455,
+    fn test_reply_options() {
+        let source = SourceId::new(0x5678).unwrap();
+        let opts = BuildOptions {
+            res_required: true,
+            sequence: 3,
+            source,
+            ..Default::default()
         };
-        frame.validate();
+        let broadcast = RawMessage::build(&opts, Message::GetService).unwrap();
+        assert!(broadcast.is_broadcast() && broadcast.wants_response() && !broadcast.wants_ack());
+        let reply = broadcast.reply_options();
+        assert_eq!(
+            reply,
+            BuildOptions {
+                target: None,
+                ack_required: false,
+                res_required: false,
+                sequence: 3,
+                source,
+            }
+        );
 
-        let v = frame.pack().unwrap();
-        assert_eq!(v.len(), ProtocolHeader::packed_size());
-        println!("Packed ProtocolHeader: {:?}", v);
+        let mut unicast = RawMessage::build(
+            &BuildOptions {
+                target: Some(0x1234),
+                ..opts
+            },
+            Message::GetPower,
+        )
+        .unwrap();
+        assert!(!unicast.is_broadcast());
+        assert_eq!(unicast.reply_options().target, Some(0x1234));
 
-        let unpacked = ProtocolHeader::unpack(&v).unwrap();
-        assert_eq!(frame, unpacked);
+        // source 0 can't be mirrored
+        unicast.frame.set_source(0);
+        assert_eq!(unicast.reply_options().source, SourceId::default());
     }
 
     #[test]
-    fn test_decode_protocol_header() {
-        //   1  2  3  4  5  6  7  8  9  10 11 12 13 14 15 16
-        let v = vec![
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x0e, 0x00, 0x00, 0x00,
-        ];
-        assert_eq!(v.len(), ProtocolHeader::packed_size());
+    fn test_message_category() {
+        // categories follow the sections of the docs
+        for m in coverage::ProtocolCoverage::new().messages() {
+            let category = MessageCategory::of(m.type_num);
+            assert_eq!(
+                category.map(|c| c.name()),
+                m.is_supported().then_some(m.section)
+            );
+        }
+        let count: usize = MessageCategory::ALL.iter().map(|c| c.types().count()).sum();
+        assert_eq!(
+            count,
+            (0..=u16::MAX)
+                .filter(|&t| expected_payload_len(t).is_some())
+                .count()
+        );
+        let multizone: Vec<_> = MessageCategory::MultiZone.types().collect();
+        assert_eq!(
+            multizone,
+            vec![501, 502, 503, 506, 507, 508, 509, 510, 511, 512]
+        );
 
-        let frame = ProtocolHeader::unpack(&v).unwrap();
-        frame.validate();
-        println!("ProtocolHeader: {:?}", frame);
+        assert_eq!(Message::GetService.category(), MessageCategory::Discovery);
+        let relay = Message::RelayGetPower { relay_index: 0 };
+        assert_eq!(relay.category(), MessageCategory::Relay);
+        assert_eq!(MessageCategory::MultiZone.to_string(), "MultiZone");
     }
 
     #[test]
-    fn test_decode_full() {
-        let v = vec![
-            0x24, 0x00, 0x00, 0x14, 0xca, 0x41, 0x37, 0x05, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x98, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x33, 0x00, 0x00, 0x00,
+    fn test_payload_size() {
+        let msgs = vec![
+            Message::GetService,
+            Message::SetPower {
+                level: PowerLevel::Enabled,
+            },
+            Message::SetExtendedColorZones {
+                duration: 0,
+                apply: ApplicationRequest::Apply,
+                zone_index: 0,
+                colors_count: 82,
+                colors: Box::new(
+                    [HSBK {
+                        hue: 0,
+                        saturation: 0,
+                        brightness: 0,
+                        kelvin: 3500,
+                    }; 82],
+                ),
+            },
         ];
+        for msg in msgs {
+            let size = msg.payload_size();
+            let raw = RawMessage::build(&Default::default(), msg).unwrap();
+            assert_eq!(raw.payload.len(), size);
+            assert!(raw.packed_size() <= MAX_DATAGRAM_SIZE);
+        }
+    }
 
-        let msg = RawMessage::unpack(&v).unwrap();
-        msg.validate();
-        println!("{:#?}", msg);
+    #[test]
+    fn test_unpack_all() {
+        let get_power = RawMessage::build(&BuildOptions::default(), Message::GetPower)
+            .unwrap()
+            .pack()
+            .unwrap();
+
+        let mut buf = get_power.clone();
+        buf.extend(&get_power);
+        let mut messages = RawMessage::unpack_all(&buf);
+        assert!(messages.next().unwrap().is_ok());
+        assert!(messages.next().unwrap().is_ok());
+        assert!(messages.next().is_none());
+        assert!(messages.remainder().is_empty());
+
+        // garbage after the first message is reported, and stops iteration
+        let mut buf = get_power.clone();
+        buf.extend([0xff; 40]);
+        let mut messages = RawMessage::unpack_all(&buf);
+        assert!(messages.next().unwrap().is_ok());
+        assert!(messages.next().unwrap().is_err());
+        assert!(messages.next().is_none());
+        assert_eq!(messages.remainder(), &[0xff; 40][..]);
+
+        assert_eq!(RawMessage::unpack_all(&[]).count(), 0);
     }
 
     #[test]
-    fn test_decode_full_1() {
-        let v = vec![
-            0x58, 0x00, 0x00, 0x54, 0xca, 0x41, 0x37, 0x05, 0xd0, 0x73, 0xd5, 0x02, 0x97, 0xde,
-            0x00, 0x00, 0x4c, 0x49, 0x46, 0x58, 0x56, 0x32, 0x00, 0xc0, 0x44, 0x30, 0xeb, 0x47,
-            0xc4, 0x48, 0x18, 0x14, 0x6b, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0xff, 0xff,
-            0xb8, 0x0b, 0x00, 0x00, 0xff, 0xff, 0x4b, 0x69, 0x74, 0x63, 0x68, 0x65, 0x6e, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-            0x00, 0x00, 0x00, 0x00,
-        ];
+    fn test_normalize() {
+        let msg = Message::StateVersion {
+            vendor: 1,
+            product: 27,
+            reserved: 99,
+        };
+        assert_eq!(
+            msg.normalize(),
+            Message::StateVersion {
+                vendor: 1,
+                product: 27,
+                reserved: 0
+            }
+        );
 
-        let msg = RawMessage::unpack(&v).unwrap();
-        msg.validate();
-        println!("{:#?}", msg);
+        // a label cut off in the middle of a character loses the rest of the character
+        let label = "aKüche Küche Küche Küche Küche";
+        let msg = Message::SetLabel {
+            label: LifxString(CString::new(label).unwrap()),
+        };
+        match msg.normalize() {
+            Message::SetLabel { label } => {
+                assert_eq!(label.to_string(), "aKüche Küche Küche Küche K")
+            }
+            msg => panic!("unexpected message {:?}", msg),
+        }
+
+        // unused colors don't matter
+        let white = HSBK {
+            hue: 0,
+            saturation: 0,
+            brightness: 65535,
+            kelvin: 3500,
+        };
+        let zones = |colors| Message::StateExtendedColorZones {
+            zones_count: 1,
+            zone_index: 0,
+            colors_count: 1,
+            colors,
+        };
+        let mut colors = Box::new([white; 82]);
+        colors[1..].iter_mut().for_each(|c| c.brightness = 0);
+        assert_ne!(zones(colors.clone()), zones(Box::new([white; 82])));
+        assert_eq!(
+            zones(colors).normalize(),
+            zones(Box::new([white; 82])).normalize()
+        );
+
+        // normalizing is the same before and after a round trip
+        let msg = Message::StateVersion {
+            vendor: 1,
+            product: 27,
+            reserved: 7,
+        };
+        let raw = RawMessage::build(&BuildOptions::default(), msg.clone()).unwrap();
+        assert_eq!(
+            Message::from_raw(&raw).unwrap().normalize(),
+            msg.normalize()
+        );
     }
 
     #[test]
-    fn test_build_a_packet() {
-        // packet taken from https://lan.developer.lifx.com/docs/building-a-lifx-packet
+    fn test_unknown_enum_values() {
+        // values without a variant survive being decoded and packed again
+        let msg = Message::StateService {
+            service: Service::Unknown(9),
+            port: 56700,
+        };
+        let raw = RawMessage::build(&BuildOptions::default(), msg.clone()).unwrap();
+        assert_eq!(raw.payload[0], 9);
+        assert_eq!(Message::from_raw(&raw).unwrap(), msg);
 
-        let msg = Message::LightSetColor {
+        let msg = Message::LightStateLastHevCycleResult {
+            result: LastHevCycleResult::Unknown(6),
+        };
+        let raw = RawMessage::build(&BuildOptions::default(), msg.clone()).unwrap();
+        assert_eq!(Message::from_raw(&raw).unwrap(), msg);
+
+        // but ones that do have a variant come back as it
+        assert_eq!(Waveform::from(3), Waveform::Triangle);
+        assert_eq!(u8::from(LastHevCycleResult::None), 255);
+        let msg = Message::StateMultiZoneEffect {
+            instance_id: 1,
+            typ: MultiZoneEffectType::Unknown(1),
             reserved: 0,
-            color: HSBK {
-                hue: 21845,
-                saturation: 0xffff,
-                brightness: 0xffff,
-                kelvin: 3500,
-            },
-            duration: 1024,
+            speed: 1000,
+            duration: 0,
+            reserved7: 0,
+            reserved8: 0,
+            parameters: [0; 8],
         };
+        match msg.normalize() {
+            Message::StateMultiZoneEffect { typ, .. } => assert_eq!(typ, MultiZoneEffectType::Move),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+    }
 
-        let raw = RawMessage::build(
+    #[test]
+    fn test_light_power() {
+        // any level is sent as it is
+        let msg = Message::LightSetPower {
+            level: 7,
+            duration: 0,
+        };
+        let raw = RawMessage::build(&BuildOptions::default(), msg.clone()).unwrap();
+        assert_eq!(&raw.payload[..2], &[7, 0]);
+        assert_eq!(Message::from_raw(&raw).unwrap(), msg);
+
+        assert_eq!(
+            Message::light_power(true, Duration::from_millis(1500)),
+            Message::LightSetPower {
+                level: 65535,
+                duration: 1500
+            }
+        );
+        assert_eq!(
+            Message::light_power(false, Duration::ZERO),
+            Message::LightSetPower {
+                level: 0,
+                duration: 0
+            }
+        );
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let msg = Message::LightSetPower {
+            level: 65535,
+            duration: 0x0102_0304,
+        };
+        let mut raw = RawMessage::build(
             &BuildOptions {
-                target: None,
-                ack_required: false,
-                res_required: false,
-                sequence: 0,
-                source: 0,
+                target: Some(0xd073d5001337),
+                res_required: true,
+                sequence: 7,
+                ..Default::default()
             },
             msg,
         )
         .unwrap();
+        raw.frame.source = 0x1234;
+        let expected = "\
+frame header
+  2a 00                    size: 42
+  00 14                    origin: 0, tagged: false, addressable: true, protocol: 1024
+  34 12 00 00              source: 0x00001234
+frame address
+  37 13 00 d5 73 d0 00 00  target: 0000d073d5001337
+  00 00 00 00 00 00        reserved
+  01                       ack_required: false, res_required: true
+  07                       sequence: 7
+protocol header
+  00 00 00 00 00 00 00 00  reserved
+  75 00                    type: 117
+  00 00                    reserved
+payload (6 bytes)
+  0000  ff ff 04 03 02 01
+";
+        assert_eq!(raw.hexdump().to_string(), expected);
+    }
 
-        let bytes = raw.pack().unwrap();
-        println!("{:?}", bytes);
-        assert_eq!(bytes.len(), 49);
+    #[test]
+    fn test_payload_size_mismatch() {
+        let msg = Message::LightSetPower {
+            level: 65535,
+            duration: 1000,
+        };
+        let mut raw = RawMessage::build(&BuildOptions::default(), msg).unwrap();
+        raw.payload.truncate(4);
+        let err = Message::from_raw(&raw).unwrap_err();
+        assert!(matches!(
+            err,
+            Error::PayloadSizeMismatch {
+                message_type: 117,
+                expected: PayloadLen::Exact(6),
+                actual: 4
+            }
+        ));
         assert_eq!(
-            bytes,
-            vec![
-                0x31, 0x00, 0x00, 0x34, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
-                0x00, 0x00, 0x00, 0x00, 0x66, 0x00, 0x00, 0x00, 0x00, 0x55, 0x55, 0xFF, 0xFF, 0xFF,
-                0xFF, 0xAC, 0x0D, 0x00, 0x04, 0x00, 0x00
-            ]
+            err.to_string(),
+            "message type 117 has a 4-byte payload, but should have exactly 6 bytes"
+        );
+
+        // commands can't be too long either, but replies can
+        raw.payload.resize(8, 0);
+        assert!(Message::from_raw(&raw).is_err());
+        let mut raw = RawMessage::build(
+            &BuildOptions::default(),
+            Message::LightStatePower { level: 65535 },
+        )
+        .unwrap();
+        raw.payload.resize(8, 0);
+        assert_eq!(
+            Message::from_raw(&raw).unwrap(),
+            Message::LightStatePower { level: 65535 }
         );
     }
 
     #[test]
-    fn test_lifx_string() {
-        let s = CStr::from_bytes_with_nul(b"hello\0").unwrap();
-        let ls = LifxString::new(s);
-        assert_eq!(ls.cstr(), s);
-        assert!(ls.cstr().to_bytes_with_nul().len() <= 32);
+    fn test_expected_payload_len() {
+        // every known type can be read from a payload of its expected size (though zeros aren't
+        // valid for some fields), and packs back to that size
+        for typ in 0..1024 {
+            let expected = match expected_payload_len(typ) {
+                Some(expected) => expected,
+                None => continue,
+            };
+            let mut raw = RawMessage::build(&BuildOptions::default(), Message::GetService).unwrap();
+            raw.protocol_header.typ = typ;
+            raw.payload = vec![0; expected.size()];
+            match Message::from_raw(&raw) {
+                Ok(msg) => {
+                    assert_eq!(msg.get_num(), typ);
+                    assert_eq!(msg.payload_size(), expected.size());
+                }
+                Err(Error::ProtocolError(_)) => {}
+                Err(e) => panic!("type {}: {:?}", typ, e),
+            }
+        }
+    }
 
-        let s = CStr::from_bytes_with_nul(b"this is bigger than thirty two characters\0").unwrap();
-        let ls = LifxString::new(s);
-        assert_eq!(ls.cstr().to_bytes_with_nul().len(), 32);
+    #[test]
+    fn test_message_type_names() {
+        let mut names = Vec::new();
+        for typ in 0..1024 {
+            let name = message_type_name(typ);
+            assert_eq!(
+                name.is_some(),
+                expected_payload_len(typ).is_some(),
+                "{}",
+                typ
+            );
+            names.extend(name);
+        }
+        names.sort_unstable();
+        names.dedup();
+        assert_eq!(names.len(), (0..1024).filter_map(message_type_name).count());
         assert_eq!(
-            ls.cstr(),
-            CStr::from_bytes_with_nul(b"this is bigger than thirty two \0").unwrap()
+            Message::GetLabel.doc_url(),
+            "https://lan.developer.lifx.com/docs/querying-the-device-for-data#getlabel---packet-23"
+        );
+        assert_eq!(
+            Message::SetPower {
+                level: PowerLevel::Enabled
+            }
+            .doc_url(),
+            "https://lan.developer.lifx.com/docs/changing-a-device#setpower---packet-21"
         );
+        assert_eq!(message_doc_url(9999), None);
+    }
+
+    #[test]
+    fn test_is_unsolicited() {
+        let options = BuildOptions {
+            target: Some(0x1234),
+            ..Default::default()
+        };
+        let mut raw = RawMessage::build(&options, Message::StatePower { level: 0 }).unwrap();
+        assert!(!raw.is_unsolicited());
+        raw.frame.set_source(0);
+        assert!(raw.is_unsolicited());
+        raw.frame_addr.target = 0;
+        assert!(!raw.is_unsolicited());
+
+        // a request from a controller that wants its reply broadcast
+        let mut raw = RawMessage::build(&options, Message::GetPower).unwrap();
+        raw.frame.set_source(0);
+        assert!(!raw.is_unsolicited());
+
+        let replies: Vec<_> = (0..1024).filter(|&typ| is_device_message(typ)).collect();
+        assert_eq!(replies.len(), 25);
+        assert!(replies.contains(&107) && replies.contains(&45) && !replies.contains(&58));
+    }
+
+    #[test]
+    fn test_truncated_datagrams() {
+        let msg = RawMessage::build(&Default::default(), Message::GetLabel).unwrap();
+        let bytes = msg.pack().unwrap();
+        for len in 0..bytes.len() {
+            assert!(RawMessage::unpack(&bytes[..len]).is_err());
+        }
+        assert_eq!(RawMessage::unpack(&bytes).unwrap(), msg);
+    }
+
+    #[test]
+    fn test_frame_size_checked() {
+        let label = LifxString::new(&CString::new("Kitchen").unwrap());
+        let msg = RawMessage::build(&Default::default(), Message::SetLabel { label }).unwrap();
+        let bytes = msg.pack().unwrap();
+
+        // a frame that claims to be longer than the datagram was truncated in transit
+        let mut oversized = bytes.clone();
+        oversized[..2].copy_from_slice(&(bytes.len() as u16 + 1).to_le_bytes());
+        assert!(matches!(
+            RawMessage::unpack(&oversized),
+            Err(Error::ProtocolError(_))
+        ));
+
+        // one that claims to be shorter than its own headers makes no sense
+        let mut short = bytes.clone();
+        short[..2].copy_from_slice(&(HEADER_SIZE as u16 - 1).to_le_bytes());
+        assert!(matches!(
+            RawMessage::unpack(&short),
+            Err(Error::ProtocolError(_))
+        ));
+
+        // bytes past the end of the frame aren't part of the payload
+        let mut padded = bytes.clone();
+        padded.extend_from_slice(&[0; 8]);
+        assert_eq!(RawMessage::unpack(&padded).unwrap(), msg);
     }
 
     #[test]
@@ -0,0 +1,36 @@
+//! Device maintenance
+//!
+//! These messages act on the device itself, rather than on its light output, so they're kept
+//! separate from everything else to make them harder to send by accident.
+//!
+//! Currently the only documented maintenance message is [Message::SetReboot], which restarts the
+//! device.  This is useful for recovering a device that has stopped responding properly, without
+//! having to physically power-cycle it.  The device drops off the network for a few seconds while
+//! it restarts, and keeps all of its settings.
+//!
+//! ```
+//! use lifx_core::{maintenance, BuildOptions, RawMessage};
+//!
+//! let options = BuildOptions {
+//!     target: Some(0xd073d5001337),
+//!     // the device acknowledges the request before it goes away
+//!     ack_required: true,
+//!     ..Default::default()
+//! };
+//! let raw = RawMessage::build(&options, maintenance::reboot()).unwrap();
+//! # assert_eq!(raw.protocol_header.typ, 38);
+//! ```
+
+use crate::Message;
+
+/// Builds a message that reboots the device
+pub fn reboot() -> Message {
+    Message::SetReboot
+}
+
+/// Returns `true` for messages that act on the device itself (like [Message::SetReboot])
+///
+/// Tools that forward messages from untrusted sources can use this to refuse them.
+pub fn is_maintenance(msg: &Message) -> bool {
+    matches!(msg, Message::SetReboot)
+}
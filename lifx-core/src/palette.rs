@@ -0,0 +1,302 @@
+//! Named sets of colors, for offering presets in a UI
+//!
+//! A [Palette] is a name and a list of colors.  The [THEMES] are palettes modelled on the themes
+//! in the LIFX app ("Relaxing", "Energizing", and so on), and [WHITES] are the names that the app
+//! gives to color temperatures.  The app doesn't publish the exact colors in its themes, so these
+//! are close to them rather than identical.
+//!
+//! Palettes are just colors, so they work anywhere a list of colors does: as the palette of a
+//! [Morph](crate::effects::Effect::Morph) effect, as the colors of a chase, or spread across a
+//! group of lights with [Palette::spread].
+//!
+//! ```
+//! use lifx_core::palette::{self, Palette};
+//!
+//! let relaxing = Palette::find("relaxing").unwrap();
+//! assert_eq!(relaxing, &palette::RELAXING);
+//! let five_lights = relaxing.spread(5);
+//! assert_eq!(five_lights.len(), 5);
+//!
+//! let warm = palette::white("Warm").unwrap();
+//! assert_eq!(warm.kelvin, 2700);
+//! ```
+
+use crate::effects::MAX_PALETTE;
+use crate::HSBK;
+use std::borrow::Cow;
+use std::fmt;
+
+/// A color from degrees and percentages, at compile time
+const fn hsbk(hue: u32, saturation: u32, brightness: u32, kelvin: u16) -> HSBK {
+    HSBK {
+        hue: ((hue * 65535 + 180) / 360) as u16,
+        saturation: ((saturation * 65535 + 50) / 100) as u16,
+        brightness: ((brightness * 65535 + 50) / 100) as u16,
+        kelvin,
+    }
+}
+
+/// A named list of colors
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Palette {
+    name: Cow<'static, str>,
+    colors: Cow<'static, [HSBK]>,
+}
+
+impl Palette {
+    pub fn new(name: impl Into<String>, colors: Vec<HSBK>) -> Palette {
+        Palette {
+            name: Cow::Owned(name.into()),
+            colors: Cow::Owned(colors),
+        }
+    }
+
+    /// A palette that doesn't need allocating, for constants
+    pub const fn from_static(name: &'static str, colors: &'static [HSBK]) -> Palette {
+        Palette {
+            name: Cow::Borrowed(name),
+            colors: Cow::Borrowed(colors),
+        }
+    }
+
+    /// One of the [THEMES], by name (ignoring case)
+    pub fn find(name: &str) -> Option<&'static Palette> {
+        THEMES.iter().find(|p| p.name.eq_ignore_ascii_case(name))
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn colors(&self) -> &[HSBK] {
+        &self.colors
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    /// A color for each of `count` lights (or zones), going through the palette in order and
+    /// starting again at the beginning if there are more lights than colors
+    ///
+    /// An empty palette gives no colors.
+    pub fn spread(&self, count: usize) -> Vec<HSBK> {
+        self.colors.iter().copied().cycle().take(count).collect()
+    }
+
+    /// The same colors, at a different brightness (from 0 to 1)
+    pub fn with_brightness(&self, brightness: f32) -> Palette {
+        let brightness = (brightness.clamp(0.0, 1.0) * 65535.0).round() as u16;
+        let colors = self
+            .colors
+            .iter()
+            .map(|&color| HSBK {
+                brightness,
+                ..color
+            })
+            .collect();
+        Palette {
+            name: self.name.clone(),
+            colors: Cow::Owned(colors),
+        }
+    }
+
+    /// The colors, for a [Morph](crate::effects::Effect::Morph) effect
+    ///
+    /// Only the first [MAX_PALETTE] colors are used, since that's all the effect can take.
+    pub fn morph_palette(&self) -> Vec<HSBK> {
+        self.colors.iter().copied().take(MAX_PALETTE).collect()
+    }
+}
+
+impl fmt::Display for Palette {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.name)
+    }
+}
+
+impl From<Palette> for Vec<HSBK> {
+    fn from(palette: Palette) -> Vec<HSBK> {
+        palette.colors.into_owned()
+    }
+}
+
+pub const RELAXING: Palette = Palette::from_static(
+    "Relaxing",
+    &[
+        hsbk(30, 60, 60, 3500),
+        hsbk(40, 40, 70, 3500),
+        hsbk(340, 30, 50, 3500),
+        hsbk(20, 70, 40, 3500),
+    ],
+);
+
+pub const ENERGIZING: Palette = Palette::from_static(
+    "Energizing",
+    &[
+        hsbk(200, 100, 100, 3500),
+        hsbk(180, 80, 100, 3500),
+        hsbk(60, 100, 100, 3500),
+        hsbk(120, 70, 100, 3500),
+        hsbk(0, 0, 100, 6500),
+    ],
+);
+
+pub const CALMING: Palette = Palette::from_static(
+    "Calming",
+    &[
+        hsbk(200, 60, 60, 3500),
+        hsbk(170, 50, 60, 3500),
+        hsbk(230, 50, 50, 3500),
+        hsbk(260, 40, 50, 3500),
+    ],
+);
+
+pub const CHEERFUL: Palette = Palette::from_static(
+    "Cheerful",
+    &[
+        hsbk(50, 100, 100, 3500),
+        hsbk(330, 80, 100, 3500),
+        hsbk(20, 90, 100, 3500),
+        hsbk(120, 80, 90, 3500),
+        hsbk(190, 80, 90, 3500),
+    ],
+);
+
+pub const EXCITING: Palette = Palette::from_static(
+    "Exciting",
+    &[
+        hsbk(0, 100, 100, 3500),
+        hsbk(280, 100, 100, 3500),
+        hsbk(40, 100, 100, 3500),
+        hsbk(190, 100, 100, 3500),
+        hsbk(320, 100, 100, 3500),
+    ],
+);
+
+pub const PEACEFUL: Palette = Palette::from_static(
+    "Peaceful",
+    &[
+        hsbk(270, 40, 50, 3500),
+        hsbk(220, 40, 50, 3500),
+        hsbk(180, 30, 60, 3500),
+        hsbk(300, 30, 40, 3500),
+    ],
+);
+
+pub const ROMANTIC: Palette = Palette::from_static(
+    "Romantic",
+    &[
+        hsbk(340, 100, 60, 3500),
+        hsbk(320, 80, 50, 3500),
+        hsbk(0, 90, 50, 3500),
+        hsbk(10, 70, 60, 3500),
+    ],
+);
+
+pub const SPOOKY: Palette = Palette::from_static(
+    "Spooky",
+    &[
+        hsbk(30, 100, 100, 3500),
+        hsbk(270, 100, 60, 3500),
+        hsbk(100, 100, 60, 3500),
+    ],
+);
+
+pub const HOLLY: Palette = Palette::from_static(
+    "Holly",
+    &[
+        hsbk(0, 100, 100, 3500),
+        hsbk(120, 100, 80, 3500),
+        hsbk(0, 0, 100, 2700),
+    ],
+);
+
+/// Every built-in theme
+pub const THEMES: &[Palette] = &[
+    RELAXING, ENERGIZING, CALMING, CHEERFUL, EXCITING, PEACEFUL, ROMANTIC, SPOOKY, HOLLY,
+];
+
+/// The names that the LIFX app gives to color temperatures, from warmest to coolest
+pub const WHITES: &[(&str, u16)] = &[
+    ("Ultra Warm", 1500),
+    ("Incandescent", 2500),
+    ("Warm", 2700),
+    ("Neutral Warm", 3000),
+    ("Neutral", 3500),
+    ("Cool", 4000),
+    ("Cool Daylight", 4500),
+    ("Soft Daylight", 5000),
+    ("Daylight", 5600),
+    ("Noon Daylight", 6000),
+    ("Bright Daylight", 6500),
+    ("Cloudy Daylight", 7000),
+    ("Blue Daylight", 7500),
+    ("Blue Overcast", 8000),
+    ("Blue Water", 8500),
+    ("Blue Ice", 9000),
+];
+
+/// One of the [WHITES], by name (ignoring case), at full brightness
+pub fn white(name: &str) -> Option<HSBK> {
+    WHITES
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|&(_, kelvin)| hsbk(0, 0, 100, kelvin))
+}
+
+/// The [WHITES] as a palette, from warmest to coolest
+pub fn whites() -> Palette {
+    let colors = WHITES.iter().map(|&(_, k)| hsbk(0, 0, 100, k)).collect();
+    Palette::new("Whites", colors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hsbk() {
+        assert_eq!(
+            hsbk(120, 100, 50, 3500),
+            HSBK::new(120.0, 100.0, 50.0, 3500)
+        );
+        assert_eq!(hsbk(359, 1, 99, 9000), HSBK::new(359.0, 1.0, 99.0, 9000));
+    }
+
+    #[test]
+    fn test_presets() {
+        for (i, theme) in THEMES.iter().enumerate() {
+            assert!(!theme.is_empty() && theme.len() <= MAX_PALETTE, "{}", theme);
+            assert_eq!(Palette::find(&theme.name().to_uppercase()), Some(theme));
+            assert!(THEMES[..i].iter().all(|t| t.name() != theme.name()));
+        }
+        assert_eq!(Palette::find("Whites"), None);
+        assert!(WHITES.windows(2).all(|w| w[0].1 < w[1].1));
+        assert_eq!(white("blue ice"), Some(HSBK::new(0.0, 0.0, 100.0, 9000)));
+        assert_eq!(white("Green"), None);
+        assert_eq!(whites().len(), WHITES.len());
+    }
+
+    #[test]
+    fn test_palette() {
+        let red = HSBK::new(0.0, 100.0, 100.0, 3500);
+        let blue = HSBK::new(240.0, 100.0, 100.0, 3500);
+        let palette = Palette::new("Police", vec![red, blue]);
+        assert_eq!(palette.spread(3), vec![red, blue, red]);
+        assert!(Palette::new("Empty", vec![]).spread(3).is_empty());
+
+        let dim = palette.with_brightness(0.5);
+        assert_eq!(dim.name(), "Police");
+        assert!(dim.colors().iter().all(|c| c.brightness == 32768));
+        assert_eq!(Vec::from(palette.clone()), vec![red, blue]);
+        assert_eq!(palette.to_string(), "Police");
+
+        let long = Palette::new("Long", vec![red; MAX_PALETTE + 1]);
+        assert_eq!(long.morph_palette().len(), MAX_PALETTE);
+    }
+}
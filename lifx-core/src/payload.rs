@@ -0,0 +1,55 @@
+//! The [Payload] trait, for anything that can be sent as a message
+//!
+//! Anything that can be sent as the payload of a LIFX message implements [Payload]: it knows its
+//! message type number, and how to pack itself into bytes and unpack itself from a [RawMessage].
+//! [Message] is the main implementation, covering every message this crate knows about, and
+//! [RawMessage::build_payload] builds a datagram from any implementation, so that payloads for
+//! messages this crate doesn't know about can be defined elsewhere.
+//!
+//! The checked [family](crate::family) views of [Message] implement it too.
+//!
+//! ```
+//! use lifx_core::payload::Payload;
+//! use lifx_core::{BuildOptions, Message, RawMessage};
+//!
+//! let msg = Message::LightGet;
+//! assert_eq!(msg.type_num(), 101);
+//! let raw = RawMessage::build_payload(&BuildOptions::default(), &msg).unwrap();
+//! assert_eq!(Message::from_raw(&raw).unwrap(), Message::LightGet);
+//! ```
+
+use crate::{Error, Message, RawMessage};
+
+/// The payload of a LIFX message
+///
+/// The trait is object safe, so payloads of different types can be kept together as
+/// `Box<dyn Payload>`.
+pub trait Payload {
+    /// The message type number, sent in the [ProtocolHeader](crate::ProtocolHeader)
+    fn type_num(&self) -> u16;
+
+    /// Appends the packed payload to `buf`
+    fn pack(&self, buf: &mut Vec<u8>) -> Result<(), Error>;
+
+    /// Decodes the payload of a message
+    ///
+    /// This gets the whole [RawMessage], since a few messages (like
+    /// [Message::Acknowledgement]) carry their information in the headers.
+    fn unpack(raw: &RawMessage) -> Result<Self, Error>
+    where
+        Self: Sized;
+}
+
+impl Payload for Message {
+    fn type_num(&self) -> u16 {
+        self.get_num()
+    }
+
+    fn pack(&self, buf: &mut Vec<u8>) -> Result<(), Error> {
+        self.write_payload(buf)
+    }
+
+    fn unpack(raw: &RawMessage) -> Result<Message, Error> {
+        Message::from_raw(raw)
+    }
+}
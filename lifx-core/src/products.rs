@@ -0,0 +1,109 @@
+//! Product info for devices that aren't in the built-in LIFX product table
+//!
+//! [get_product_info](crate::get_product_info) knows about every product that LIFX has published
+//! (vendor ID 1).  Other devices that speak the LAN protocol report their own vendor IDs, and
+//! newer LIFX products may not be in this crate's table yet.  Either can be taught to
+//! `get_product_info` at runtime:
+//!
+//! ```
+//! use lifx_core::products::{register_product, register_vendor};
+//! use lifx_core::{get_product_info, Capabilities, ProductId, ProductInfo, TemperatureRange};
+//!
+//! static CLONE_BULB: ProductInfo = ProductInfo {
+//!     name: "Acme Color Bulb",
+//!     capabilities: Capabilities::COLOR,
+//!     temperature_range: TemperatureRange::Variable { min: 2700, max: 6500 },
+//!     max_milliwatts: Some(9_000),
+//! };
+//!
+//! // every product from vendor 42 is the same bulb
+//! register_vendor(42, |_product| Some(&CLONE_BULB));
+//! assert_eq!(get_product_info(42, 7).unwrap().name, "Acme Color Bulb");
+//! assert_eq!(get_product_info(42, 7).unwrap().max_watts(), Some(9.0));
+//!
+//! // or register products one at a time
+//! register_product(ProductId { vendor: 43, product: 1 }, &CLONE_BULB);
+//! assert!(get_product_info(43, 1).is_some());
+//! assert!(get_product_info(43, 2).is_none());
+//! ```
+//!
+//! Registrations apply to the whole process, and can't be undone.
+
+use crate::{ProductId, ProductInfo};
+use std::collections::HashMap;
+use std::sync::{OnceLock, RwLock};
+
+/// Looks up a product ID for a single vendor
+pub type VendorLookup = fn(product: u32) -> Option<&'static ProductInfo>;
+
+#[derive(Default)]
+struct Registry {
+    products: HashMap<ProductId, &'static ProductInfo>,
+    vendors: HashMap<u32, VendorLookup>,
+}
+
+fn registry() -> &'static RwLock<Registry> {
+    static REGISTRY: OnceLock<RwLock<Registry>> = OnceLock::new();
+    REGISTRY.get_or_init(Default::default)
+}
+
+/// Registers info for a single product
+///
+/// This takes priority over everything else, including the built-in table, so it can also be
+/// used to correct or add to the info for a LIFX product.  Registering the same ID twice replaces
+/// the earlier registration.
+pub fn register_product(id: ProductId, info: &'static ProductInfo) {
+    let mut registry = registry().write().unwrap_or_else(|e| e.into_inner());
+    registry.products.insert(id, info);
+}
+
+/// Registers a lookup function for every product from `vendor`
+///
+/// This is used for any product from this vendor that wasn't registered with
+/// [register_product].  Registering vendor 1 (LIFX) replaces the built-in table.
+pub fn register_vendor(vendor: u32, lookup: VendorLookup) {
+    let mut registry = registry().write().unwrap_or_else(|e| e.into_inner());
+    registry.vendors.insert(vendor, lookup);
+}
+
+/// Registers how much power a product draws, keeping everything else that's known about it
+///
+/// This is for filling in [ProductInfo::max_milliwatts], which the built-in table doesn't have,
+/// for products that are already known.  Returns `false` (and registers nothing) if the product
+/// isn't known.
+///
+/// ```
+/// use lifx_core::products::register_max_milliwatts;
+/// use lifx_core::{get_product_info, ProductId};
+///
+/// assert!(register_max_milliwatts(ProductId { vendor: 1, product: 27 }, 11_000));
+/// let a19 = get_product_info(1, 27).unwrap();
+/// assert_eq!((a19.name, a19.max_watts()), ("LIFX A19", Some(11.0)));
+/// ```
+pub fn register_max_milliwatts(id: ProductId, milliwatts: u32) -> bool {
+    let Some(info) = crate::get_product_info(id.vendor, id.product) else {
+        return false;
+    };
+    let info = ProductInfo {
+        max_milliwatts: Some(milliwatts),
+        ..*info
+    };
+    // registrations last for the life of the process anyway
+    register_product(id, Box::leak(Box::new(info)));
+    true
+}
+
+/// Looks up a product in the runtime registry
+///
+/// The outer `None` means that nothing was registered for this vendor or product, so the caller
+/// should fall back to the built-in table.
+pub(crate) fn lookup(id: ProductId) -> Option<Option<&'static ProductInfo>> {
+    let registry = registry().read().unwrap_or_else(|e| e.into_inner());
+    if let Some(info) = registry.products.get(&id) {
+        return Some(Some(*info));
+    }
+    registry
+        .vendors
+        .get(&id.vendor)
+        .map(|lookup| lookup(id.product))
+}
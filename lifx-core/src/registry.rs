@@ -0,0 +1,221 @@
+//! Decoding message types that this crate doesn't know about
+//!
+//! LIFX devices send some messages that aren't in the official docs (see
+//! [Error::UnknownMessageType]), and newer firmware adds messages before this crate catches up.
+//! Rather than forking `lifx-core`, a downstream crate can define a [Payload] for such a message,
+//! and register it with a [MessageRegistry] under its type number.  [MessageRegistry::decode]
+//! then decodes it alongside all of the built-in messages.
+//!
+//! Custom payloads are sent with [RawMessage::build_payload].
+//!
+//! ```
+//! use lifx_core::registry::{Decoded, MessageRegistry};
+//! use lifx_core::{BuildOptions, Error, Payload, RawMessage};
+//! use std::convert::TryInto;
+//!
+//! /// A message that some firmware sends, reverse-engineered from packet captures
+//! #[derive(Debug, PartialEq)]
+//! struct StateUptimeStats { boots: u32 }
+//!
+//! impl Payload for StateUptimeStats {
+//!     fn type_num(&self) -> u16 { 1234 }
+//!     fn pack(&self, buf: &mut Vec<u8>) -> Result<(), Error> {
+//!         buf.extend_from_slice(&self.boots.to_le_bytes());
+//!         Ok(())
+//!     }
+//!     fn unpack(raw: &RawMessage) -> Result<Self, Error> {
+//!         let bytes = raw.payload.get(..4).ok_or_else(|| Error::ProtocolError("too short".into()))?;
+//!         Ok(StateUptimeStats { boots: u32::from_le_bytes(bytes.try_into().unwrap()) })
+//!     }
+//! }
+//!
+//! let mut registry = MessageRegistry::new();
+//! registry.register::<StateUptimeStats>(1234, "StateUptimeStats");
+//!
+//! let raw = RawMessage::build_payload(&BuildOptions::default(), &StateUptimeStats { boots: 7 })?;
+//! match registry.decode(&raw)? {
+//!     Decoded::Custom(msg) => {
+//!         assert_eq!(msg.name(), "StateUptimeStats");
+//!         assert_eq!(msg.downcast_ref::<StateUptimeStats>(), Some(&StateUptimeStats { boots: 7 }));
+//!     }
+//!     Decoded::Known(msg) => panic!("unexpected {:?}", msg),
+//! }
+//! # Ok::<(), Error>(())
+//! ```
+
+use crate::{Error, Message, Payload, RawMessage};
+use std::any::Any;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A decoded custom payload, which can be any type
+trait AnyPayload: Any + fmt::Debug + Send + Sync {
+    fn as_any(&self) -> &dyn Any;
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<T: Any + fmt::Debug + Send + Sync> AnyPayload for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+/// A message decoded by a payload registered with a [MessageRegistry]
+#[derive(Debug)]
+pub struct CustomMessage {
+    type_num: u16,
+    name: &'static str,
+    payload: Box<dyn AnyPayload>,
+}
+
+impl CustomMessage {
+    /// The message type number
+    pub fn type_num(&self) -> u16 {
+        self.type_num
+    }
+
+    /// The name the payload was registered with
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The decoded payload, if it's a `P`
+    pub fn downcast_ref<P: Any>(&self) -> Option<&P> {
+        // deref first, since the box itself is also an AnyPayload
+        (*self.payload).as_any().downcast_ref()
+    }
+
+    /// Takes the decoded payload, if it's a `P`, or gives this message back if it isn't
+    pub fn downcast<P: Any>(self) -> Result<P, CustomMessage> {
+        if self.downcast_ref::<P>().is_none() {
+            return Err(self);
+        }
+        // just checked the type
+        Ok(*self.payload.into_any().downcast().unwrap())
+    }
+}
+
+/// The result of [MessageRegistry::decode]
+#[derive(Debug)]
+pub enum Decoded {
+    /// A message this crate knows about
+    Known(Message),
+    /// A message decoded by a registered payload
+    Custom(CustomMessage),
+}
+
+type Decoder = fn(&RawMessage) -> Result<Box<dyn AnyPayload>, Error>;
+
+fn decoder<P: Payload + fmt::Debug + Send + Sync + 'static>(
+    raw: &RawMessage,
+) -> Result<Box<dyn AnyPayload>, Error> {
+    Ok(Box::new(P::unpack(raw)?))
+}
+
+/// Custom payloads, keyed by message type number
+#[derive(Debug, Clone, Default)]
+pub struct MessageRegistry {
+    decoders: HashMap<u16, (&'static str, Decoder)>,
+}
+
+impl MessageRegistry {
+    pub fn new() -> MessageRegistry {
+        MessageRegistry::default()
+    }
+
+    /// Decodes messages of type `type_num` as a `P`
+    ///
+    /// This replaces anything already registered for the type.  Registered payloads take priority
+    /// over the built-in ones, so a built-in message can be replaced if a device doesn't match
+    /// the documented layout.
+    pub fn register<P>(&mut self, type_num: u16, name: &'static str)
+    where
+        P: Payload + fmt::Debug + Send + Sync + 'static,
+    {
+        self.decoders.insert(type_num, (name, decoder::<P>));
+    }
+
+    /// Forgets the payload registered for a type, returning whether there was one
+    pub fn unregister(&mut self, type_num: u16) -> bool {
+        self.decoders.remove(&type_num).is_some()
+    }
+
+    /// Whether a payload is registered for a type
+    pub fn is_registered(&self, type_num: u16) -> bool {
+        self.decoders.contains_key(&type_num)
+    }
+
+    /// Decodes a message, using the registered payload for its type if there is one, and
+    /// [Message::from_raw] otherwise
+    pub fn decode(&self, raw: &RawMessage) -> Result<Decoded, Error> {
+        let type_num = raw.protocol_header.typ;
+        match self.decoders.get(&type_num) {
+            Some((name, decode)) => Ok(Decoded::Custom(CustomMessage {
+                type_num,
+                name,
+                payload: decode(raw)?,
+            })),
+            None => Message::from_raw(raw).map(Decoded::Known),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BuildOptions;
+
+    /// A replacement for the built-in StatePower, which accepts (and keeps) any payload
+    #[derive(Debug, PartialEq)]
+    struct RawPower(Vec<u8>);
+
+    impl Payload for RawPower {
+        fn type_num(&self) -> u16 {
+            22
+        }
+
+        fn pack(&self, buf: &mut Vec<u8>) -> Result<(), Error> {
+            buf.extend_from_slice(&self.0);
+            Ok(())
+        }
+
+        fn unpack(raw: &RawMessage) -> Result<RawPower, Error> {
+            Ok(RawPower(raw.payload.clone()))
+        }
+    }
+
+    #[test]
+    fn test_registry() {
+        let opts = BuildOptions::default();
+        let mut registry = MessageRegistry::new();
+        let unknown = RawMessage::build_payload(&opts, &RawPower(vec![1])).unwrap();
+        assert!(matches!(
+            registry.decode(&unknown),
+            Err(Error::PayloadSizeMismatch { .. })
+        ));
+
+        registry.register::<RawPower>(22, "RawPower");
+        assert!(registry.is_registered(22));
+        let msg = match registry.decode(&unknown).unwrap() {
+            Decoded::Custom(msg) => msg,
+            decoded => panic!("unexpected {:?}", decoded),
+        };
+        assert_eq!(msg.type_num(), 22);
+        assert!(msg.downcast_ref::<Message>().is_none());
+        let msg = msg.downcast::<u32>().unwrap_err();
+        assert_eq!(msg.downcast::<RawPower>().unwrap(), RawPower(vec![1]));
+
+        // other types are decoded as usual
+        let known = RawMessage::build(&opts, Message::GetPower).unwrap();
+        assert!(matches!(
+            registry.decode(&known),
+            Ok(Decoded::Known(Message::GetPower))
+        ));
+        assert!(registry.unregister(22));
+        assert!(!registry.unregister(22));
+    }
+}
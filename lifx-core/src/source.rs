@@ -0,0 +1,106 @@
+//! Source identifiers
+//!
+//! Every message includes a 32-bit `source` value, which devices copy into their replies.  This
+//! is how a client knows which replies are meant for it, so every client on a network should use
+//! a different, nonzero source.
+//!
+//! A source of zero has special meaning: devices may broadcast their replies to it to the whole
+//! subnet, instead of sending them back to the client that asked.  That's almost never what you
+//! want, so [SourceId] can't be zero.
+
+use crate::Error;
+use std::collections::hash_map::RandomState;
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hasher};
+use std::num::NonZeroU32;
+use std::sync::OnceLock;
+
+/// A nonzero source identifier
+///
+/// The [Default] is a random source that is generated once per process, so all messages built
+/// with `BuildOptions::default()` share the same source (and their replies can be matched up).
+/// Use [generate] to get a new random source instead.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SourceId(NonZeroU32);
+
+impl SourceId {
+    /// Returns `None` if `source` is zero
+    pub const fn new(source: u32) -> Option<SourceId> {
+        match NonZeroU32::new(source) {
+            Some(s) => Some(SourceId(s)),
+            None => None,
+        }
+    }
+
+    pub const fn get(self) -> u32 {
+        self.0.get()
+    }
+}
+
+impl Default for SourceId {
+    fn default() -> SourceId {
+        static DEFAULT: OnceLock<SourceId> = OnceLock::new();
+        *DEFAULT.get_or_init(generate)
+    }
+}
+
+impl From<NonZeroU32> for SourceId {
+    fn from(source: NonZeroU32) -> SourceId {
+        SourceId(source)
+    }
+}
+
+impl From<SourceId> for u32 {
+    fn from(source: SourceId) -> u32 {
+        source.get()
+    }
+}
+
+impl TryFrom<u32> for SourceId {
+    type Error = Error;
+
+    fn try_from(source: u32) -> Result<SourceId, Error> {
+        SourceId::new(source).ok_or_else(|| Error::ProtocolError("source must be nonzero".into()))
+    }
+}
+
+impl std::fmt::Display for SourceId {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{:08x}", self.get())
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for SourceId {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(SourceId(NonZeroU32::arbitrary(u)?))
+    }
+}
+
+/// Generates a new random source
+///
+/// This uses the randomness that the standard library seeds its hash maps with, so it's not
+/// suitable for anything security related, but it's plenty to keep clients on the same network
+/// from colliding.
+pub fn generate() -> SourceId {
+    loop {
+        let random = RandomState::new().build_hasher().finish();
+        if let Some(source) = SourceId::new(random as u32) {
+            return source;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_source_id() {
+        assert_eq!(SourceId::new(0), None);
+        assert!(SourceId::try_from(0).is_err());
+        assert_eq!(SourceId::new(42).unwrap().get(), 42);
+        assert_eq!(SourceId::default(), SourceId::default());
+        assert_ne!(generate().get(), 0);
+    }
+}
@@ -0,0 +1,228 @@
+//! Keeping a [Message::SetWaveform] pulse in time with a beat
+//!
+//! A single `SetWaveform` makes a device pulse on its own for many cycles, which is much smoother
+//! than sending a color change on every beat.  The catch is that the device's clock and the music
+//! slowly drift apart, and the tempo may change.  [BeatSync] takes beat timestamps (from a tap
+//! button, or an audio beat detector), estimates the tempo, and works out when a new `SetWaveform`
+//! is needed to pull the pulse back into line.
+//!
+//! This doesn't do any I/O, so the caller sends the messages and supplies the timestamps.
+//!
+//! ```
+//! use lifx_core::waveform::BeatSync;
+//! use lifx_core::{Waveform, HSBK};
+//! use std::time::{Duration, Instant};
+//!
+//! let color = HSBK { hue: 0, saturation: 65535, brightness: 65535, kelvin: 3500 };
+//! let mut sync = BeatSync::new(color, Waveform::Saw);
+//!
+//! let start = Instant::now();
+//! let mut sent = Vec::new();
+//! for beat in 0..16 {
+//!     // 120 bpm
+//!     if let Some(msg) = sync.beat(start + Duration::from_millis(500) * beat) {
+//!         sent.push(msg);
+//!     }
+//! }
+//! // one message once the tempo is known, and nothing else while it stays in time
+//! assert_eq!(sent.len(), 1);
+//! assert_eq!(sync.period(), Some(Duration::from_millis(500)));
+//! ```
+
+use crate::{Message, Waveform, HSBK};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// The number of beat intervals averaged to estimate the tempo
+const HISTORY: usize = 8;
+
+/// A change in tempo triggers a resend if it would drift past the threshold within this many beats
+const LOOKAHEAD_BEATS: u32 = 4;
+
+/// Decides when to send a [Message::SetWaveform] to keep a device pulsing in time with a beat
+#[derive(Debug, Clone)]
+pub struct BeatSync {
+    color: HSBK,
+    waveform: Waveform,
+    skew_ratio: i16,
+    cycles: f32,
+    threshold: Duration,
+    beats: VecDeque<Instant>,
+    /// When the last message was sent, and the period in it
+    sent: Option<(Instant, Duration)>,
+}
+
+impl BeatSync {
+    /// Pulses to `color` and back once per beat, with the given waveform
+    ///
+    /// The defaults are a skew ratio of 0 (the midpoint), 64 cycles per message, and a drift
+    /// threshold of 40ms.
+    pub fn new(color: HSBK, waveform: Waveform) -> BeatSync {
+        BeatSync {
+            color,
+            waveform,
+            skew_ratio: 0,
+            cycles: 64.0,
+            threshold: Duration::from_millis(40),
+            beats: VecDeque::with_capacity(HISTORY + 1),
+            sent: None,
+        }
+    }
+
+    pub fn with_skew_ratio(mut self, skew_ratio: i16) -> BeatSync {
+        self.skew_ratio = skew_ratio;
+        self
+    }
+
+    /// Sets how many beats each message covers
+    ///
+    /// If no beats arrive, the device stops pulsing after this many.  The minimum is 1.
+    pub fn with_cycles(mut self, cycles: f32) -> BeatSync {
+        self.cycles = cycles.max(1.0);
+        self
+    }
+
+    /// Sets how far the pulse can drift from the beat before it's corrected
+    pub fn with_threshold(mut self, threshold: Duration) -> BeatSync {
+        self.threshold = threshold;
+        self
+    }
+
+    /// The current tempo estimate, as the time between beats
+    pub fn period(&self) -> Option<Duration> {
+        let first = self.beats.front()?;
+        let last = self.beats.back()?;
+        let intervals = self.beats.len() as u32 - 1;
+        if intervals == 0 {
+            return None;
+        }
+        Some(last.duration_since(*first) / intervals)
+    }
+
+    /// Records a beat, and returns a message to send if the pulse needs to be (re)started
+    ///
+    /// Timestamps must not go backwards.  Nothing is sent until there have been two beats, since
+    /// that's the least needed to estimate a tempo.
+    pub fn beat(&mut self, at: Instant) -> Option<Message> {
+        if self.beats.len() > HISTORY {
+            self.beats.pop_front();
+        }
+        self.beats.push_back(at);
+        let period = self.period()?;
+
+        let resend = match self.sent {
+            None => true,
+            Some((start, sent_period)) => {
+                let elapsed = at.duration_since(start);
+                let sent_ms = sent_period.as_millis().max(1);
+                // how far this beat is from the nearest pulse
+                let phase = elapsed.as_millis() % sent_ms;
+                let drift = Duration::from_millis(phase.min(sent_ms - phase) as u64);
+                let tempo_change = period.abs_diff(sent_period);
+                // restart before the device runs out of cycles
+                let remaining = sent_period.mul_f32(self.cycles).saturating_sub(elapsed);
+                drift > self.threshold
+                    || tempo_change * LOOKAHEAD_BEATS > self.threshold
+                    || remaining < sent_period * 2
+            }
+        };
+        if !resend {
+            return None;
+        }
+        // devices only work in whole milliseconds, so track the period that was actually sent
+        let period = Duration::from_millis(period.as_millis() as u64);
+        self.sent = Some((at, period));
+        Some(self.message(period))
+    }
+
+    /// Starts a pulse at a fixed tempo, with a beat at `at`
+    ///
+    /// Later calls to [BeatSync::beat] keep it in time, as usual.
+    pub fn set_bpm(&mut self, bpm: f32, at: Instant) -> Message {
+        let period = Duration::from_millis((60_000.0 / bpm.max(1.0)).round() as u64);
+        self.beats.clear();
+        self.beats.extend(at.checked_sub(period));
+        self.beats.push_back(at);
+        self.sent = Some((at, period));
+        self.message(period)
+    }
+
+    #[allow(clippy::useless_conversion)]
+    fn message(&self, period: Duration) -> Message {
+        Message::SetWaveform {
+            reserved: 0,
+            transient: true,
+            color: self.color,
+            period: period.as_millis() as u32,
+            cycles: self.cycles.into(),
+            skew_ratio: self.skew_ratio,
+            waveform: self.waveform,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sync() -> BeatSync {
+        let color = HSBK {
+            hue: 0,
+            saturation: 65535,
+            brightness: 65535,
+            kelvin: 3500,
+        };
+        BeatSync::new(color, Waveform::Sine)
+    }
+
+    fn period_of(msg: Option<Message>) -> Option<u32> {
+        match msg {
+            Some(Message::SetWaveform { period, .. }) => Some(period),
+            None => None,
+            Some(msg) => panic!("unexpected message {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn test_steady_tempo() {
+        let mut sync = sync();
+        let start = Instant::now();
+        assert_eq!(period_of(sync.beat(start)), None);
+        assert_eq!(
+            period_of(sync.beat(start + Duration::from_millis(500))),
+            Some(500)
+        );
+        // small amounts of jitter don't cause a resend
+        for (i, jitter) in [5, 0, 10, 0, 3, 0].iter().enumerate() {
+            let at = start + Duration::from_millis(500 * (i as u64 + 2) + jitter);
+            assert_eq!(period_of(sync.beat(at)), None);
+        }
+    }
+
+    #[test]
+    fn test_tempo_change() {
+        let mut sync = sync();
+        let start = Instant::now();
+        sync.set_bpm(120.0, start);
+        let mut at = start;
+        let mut sent = Vec::new();
+        for _ in 0..16 {
+            at += Duration::from_millis(400);
+            sent.extend(period_of(sync.beat(at)));
+        }
+        // the pulse speeds up to match
+        assert!(!sent.is_empty());
+        assert_eq!(sync.period(), Some(Duration::from_millis(400)));
+        assert_eq!(*sent.last().unwrap(), 400);
+    }
+
+    #[test]
+    fn test_runs_out_of_cycles() {
+        let mut sync = sync().with_cycles(4.0);
+        let start = Instant::now();
+        let sent: Vec<_> = (0..12)
+            .filter_map(|i| period_of(sync.beat(start + Duration::from_millis(500) * i)))
+            .collect();
+        assert!(sent.len() >= 3, "{:?}", sent);
+    }
+}
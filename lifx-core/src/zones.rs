@@ -0,0 +1,361 @@
+//! Ranges of zones on multizone devices
+//!
+//! [Message::GetColorZones] and [Message::SetColorZones] take a start and end zone index.  Both
+//! ends are inclusive, and a range where the end comes before the start doesn't make sense to the
+//! device.  [ZoneRange] checks that up front.
+//!
+//! Changing several ranges at once with separate `SetColorZones` messages makes the strip flicker
+//! through the in-between states.  [plan_color_zones] turns a set of changes into as few messages
+//! as it can, all but the last with [ApplicationRequest::NoApply], so they all show up together.
+//!
+//! Devices with extended multizone support can instead send and receive up to 82 zones per
+//! message, with [Message::SetExtendedColorZones] and [Message::StateExtendedColorZones].  Longer
+//! strips take several of these "pages", each with the index of its first zone.
+//! [ExtendedZones] puts the pages of a reply back together, and [extended_zone_pages] splits a
+//! whole strip into pages to send.
+
+use crate::{ApplicationRequest, Error, Message, HSBK};
+use std::convert::TryFrom;
+use std::ops::RangeInclusive;
+use std::time::Duration;
+
+/// The most zones that fit in one [Message::SetExtendedColorZones] or
+/// [Message::StateExtendedColorZones]
+pub const EXTENDED_ZONES_PER_PAGE: usize = 82;
+
+/// An inclusive range of zone indices, where the start is never after the end
+///
+/// ```
+/// use lifx_core::zones::ZoneRange;
+///
+/// let range = ZoneRange::new(2, 5).unwrap();
+/// assert_eq!(range.len(), 4);
+/// assert_eq!(range.into_iter().collect::<Vec<u8>>(), vec![2, 3, 4, 5]);
+/// assert!(ZoneRange::new(5, 2).is_err());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ZoneRange {
+    start: u8,
+    end: u8,
+}
+
+// a range is never empty, so there's no point in an is_empty()
+#[allow(clippy::len_without_is_empty)]
+impl ZoneRange {
+    /// Every zone (`0..=255`)
+    ///
+    /// Devices clamp the end of the range to their last zone, so this is the usual way to ask
+    /// about all of a device's zones.
+    pub const ALL: ZoneRange = ZoneRange {
+        start: 0,
+        end: u8::MAX,
+    };
+
+    /// Returns an error if `end` is before `start`
+    pub fn new(start: u8, end: u8) -> Result<ZoneRange, Error> {
+        if end < start {
+            return Err(Error::ProtocolError(format!(
+                "zone range end {} is before start {}",
+                end, start
+            )));
+        }
+        Ok(ZoneRange { start, end })
+    }
+
+    /// A range covering just one zone
+    pub const fn single(index: u8) -> ZoneRange {
+        ZoneRange {
+            start: index,
+            end: index,
+        }
+    }
+
+    pub const fn start(&self) -> u8 {
+        self.start
+    }
+
+    /// The last zone in the range (inclusive)
+    pub const fn end(&self) -> u8 {
+        self.end
+    }
+
+    /// The number of zones in the range (never zero)
+    pub const fn len(&self) -> usize {
+        (self.end - self.start) as usize + 1
+    }
+
+    pub const fn contains(&self, index: u8) -> bool {
+        self.start <= index && index <= self.end
+    }
+
+    /// Iterates over the zone indices in this range
+    pub fn iter(&self) -> RangeInclusive<u8> {
+        self.start..=self.end
+    }
+
+    /// How many replies a device with `zone_count` zones sends to a [Message::GetColorZones] for
+    /// this range
+    ///
+    /// A single zone is answered with one [Message::StateZone].  Anything else is answered with
+    /// [Message::StateMultiZone] messages of 8 zones each, starting at the start of the range, with
+    /// the end of the range clamped to the device's last zone.  Ranges that start past the last zone
+    /// get no reply at all.
+    ///
+    /// `zone_count` is in the `count` field of every reply, so a caller can work out how many more
+    /// replies to wait for as soon as the first one arrives, instead of waiting for a timeout.
+    ///
+    /// ```
+    /// use lifx_core::zones::ZoneRange;
+    ///
+    /// // a LIFX Z strip with 16 zones
+    /// assert_eq!(ZoneRange::ALL.expected_replies(16), 2);
+    /// assert_eq!(ZoneRange::new(4, 12).unwrap().expected_replies(16), 2);
+    /// assert_eq!(ZoneRange::single(3).expected_replies(16), 1);
+    /// ```
+    pub fn expected_replies(&self, zone_count: usize) -> usize {
+        let start = self.start as usize;
+        if start >= zone_count {
+            return 0;
+        }
+        if self.start == self.end {
+            return 1;
+        }
+        let last = (self.end as usize).min(zone_count - 1);
+        (last - start + 1).div_ceil(8)
+    }
+
+    /// The range of zones in a [Message::GetColorZones] or [Message::SetColorZones]
+    ///
+    /// Returns `None` for other messages, or if the message's range is backwards.
+    pub fn from_message(msg: &Message) -> Option<ZoneRange> {
+        match *msg {
+            Message::GetColorZones {
+                start_index,
+                end_index,
+            }
+            | Message::SetColorZones {
+                start_index,
+                end_index,
+                ..
+            } => ZoneRange::new(start_index, end_index).ok(),
+            _ => None,
+        }
+    }
+}
+
+impl Default for ZoneRange {
+    fn default() -> Self {
+        ZoneRange::ALL
+    }
+}
+
+impl IntoIterator for ZoneRange {
+    type Item = u8;
+    type IntoIter = RangeInclusive<u8>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl TryFrom<RangeInclusive<u8>> for ZoneRange {
+    type Error = Error;
+
+    fn try_from(range: RangeInclusive<u8>) -> Result<ZoneRange, Error> {
+        ZoneRange::new(*range.start(), *range.end())
+    }
+}
+
+impl From<ZoneRange> for RangeInclusive<u8> {
+    fn from(range: ZoneRange) -> RangeInclusive<u8> {
+        range.iter()
+    }
+}
+
+/// Collects the pages of [Message::StateExtendedColorZones] replies into one list of colors
+///
+/// Pages can arrive in any order.  If a page reports a different zone count from the earlier
+/// ones, the earlier ones are thrown away, since the device must have changed.
+///
+/// ```
+/// use lifx_core::zones::{extended_zone_pages, ExtendedZones};
+/// use lifx_core::{ApplicationRequest, Message, HSBK};
+/// use std::time::Duration;
+///
+/// let strip = vec![HSBK { hue: 0, saturation: 0, brightness: 65535, kelvin: 3500 }; 120];
+/// let mut zones = ExtendedZones::new();
+/// // the same layout that a device would reply with
+/// for page in extended_zone_pages(&strip, Duration::ZERO, ApplicationRequest::Apply).unwrap() {
+///     if let Message::SetExtendedColorZones { zone_index, colors_count, colors, .. } = page {
+///         zones.add(&Message::StateExtendedColorZones { zones_count: 120, zone_index, colors_count, colors });
+///     }
+/// }
+/// assert_eq!(zones.colors(), Some(strip));
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ExtendedZones {
+    zones: Vec<Option<HSBK>>,
+}
+
+impl ExtendedZones {
+    pub fn new() -> ExtendedZones {
+        ExtendedZones::default()
+    }
+
+    /// Adds the zones from a [Message::StateExtendedColorZones]
+    ///
+    /// Returns `false` (and does nothing) for any other message.
+    pub fn add(&mut self, msg: &Message) -> bool {
+        let (zones_count, zone_index, colors) = match msg {
+            Message::StateExtendedColorZones {
+                zones_count,
+                zone_index,
+                colors_count,
+                colors,
+            } => {
+                let len = (*colors_count as usize).min(colors.len());
+                (*zones_count as usize, *zone_index as usize, &colors[..len])
+            }
+            _ => return false,
+        };
+        if self.zones.len() != zones_count {
+            self.zones = vec![None; zones_count];
+        }
+        for (zone, color) in self.zones.iter_mut().skip(zone_index).zip(colors) {
+            *zone = Some(*color);
+        }
+        true
+    }
+
+    /// The number of zones the device has, or 0 if no pages have arrived yet
+    pub fn zones_count(&self) -> usize {
+        self.zones.len()
+    }
+
+    /// Returns `true` once every zone has been received
+    pub fn is_complete(&self) -> bool {
+        !self.zones.is_empty() && self.zones.iter().all(Option::is_some)
+    }
+
+    /// The index of the first zone of each page that's still missing
+    ///
+    /// This assumes pages start at multiples of [EXTENDED_ZONES_PER_PAGE], which is how devices
+    /// send them.
+    pub fn missing_pages(&self) -> Vec<u16> {
+        self.zones
+            .chunks(EXTENDED_ZONES_PER_PAGE)
+            .enumerate()
+            .filter(|(_, page)| page.iter().any(Option::is_none))
+            .map(|(i, _)| (i * EXTENDED_ZONES_PER_PAGE) as u16)
+            .collect()
+    }
+
+    /// The color of every zone, once they've all been received
+    pub fn colors(&self) -> Option<Vec<HSBK>> {
+        self.zones.iter().copied().collect()
+    }
+}
+
+/// Splits the colors for a whole strip into [Message::SetExtendedColorZones] pages
+///
+/// Every page but the last is sent with [ApplicationRequest::NoApply], and the last one with
+/// `apply`, so that with [ApplicationRequest::Apply] the whole strip changes at once.  Returns an
+/// error if there are too many colors to address with a `u16` zone index.
+pub fn extended_zone_pages(
+    colors: &[HSBK],
+    duration: Duration,
+    apply: ApplicationRequest,
+) -> Result<Vec<Message>, Error> {
+    if colors.len() > u16::MAX as usize + 1 {
+        return Err(Error::ProtocolError(format!(
+            "{} zones is more than can be addressed",
+            colors.len()
+        )));
+    }
+    let duration = u32::try_from(duration.as_millis()).unwrap_or(u32::MAX);
+    let pages = colors.chunks(EXTENDED_ZONES_PER_PAGE).count();
+    Ok(colors
+        .chunks(EXTENDED_ZONES_PER_PAGE)
+        .enumerate()
+        .map(|(i, chunk)| {
+            let mut page = Box::new(
+                [HSBK {
+                    hue: 0,
+                    saturation: 0,
+                    brightness: 0,
+                    kelvin: 0,
+                }; EXTENDED_ZONES_PER_PAGE],
+            );
+            page[..chunk.len()].copy_from_slice(chunk);
+            Message::SetExtendedColorZones {
+                duration,
+                apply: match i + 1 == pages {
+                    true => apply,
+                    false => ApplicationRequest::NoApply,
+                },
+                zone_index: (i * EXTENDED_ZONES_PER_PAGE) as u16,
+                colors_count: chunk.len() as u8,
+                colors: page,
+            }
+        })
+        .collect())
+}
+
+/// Plans the [Message::SetColorZones] messages that make several changes to a strip at once
+///
+/// `changes` are applied in order, so where ranges overlap, later changes win.  Neighbouring zones
+/// that end up the same color are merged into one message.  Every message but the last is sent
+/// with [ApplicationRequest::NoApply], and the last with [ApplicationRequest::Apply], so if they're
+/// sent in order, the device shows every change at the same moment.  Zones that aren't in any
+/// range are left alone.
+///
+/// ```
+/// use lifx_core::zones::{plan_color_zones, ZoneRange};
+/// use lifx_core::{ApplicationRequest, Message, HSBK};
+/// use std::time::Duration;
+///
+/// let red = HSBK { hue: 0, saturation: 65535, brightness: 65535, kelvin: 3500 };
+/// let off = HSBK { brightness: 0, ..red };
+/// let changes = [(ZoneRange::new(0, 15)?, off), (ZoneRange::single(3), red)];
+/// let messages = plan_color_zones(&changes, Duration::ZERO);
+/// assert_eq!(messages.len(), 3); // 0..=2, 3, 4..=15
+/// assert!(matches!(messages[2], Message::SetColorZones { apply: ApplicationRequest::Apply, .. }));
+/// # Ok::<(), lifx_core::Error>(())
+/// ```
+pub fn plan_color_zones(changes: &[(ZoneRange, HSBK)], duration: Duration) -> Vec<Message> {
+    let mut zones = [None; 256];
+    for (range, color) in changes {
+        for index in range.iter() {
+            zones[index as usize] = Some(*color);
+        }
+    }
+
+    let mut runs: Vec<(ZoneRange, HSBK)> = Vec::new();
+    for (index, color) in zones.iter().enumerate() {
+        let (index, color) = match color {
+            Some(color) => (index as u8, *color),
+            None => continue,
+        };
+        match runs.last_mut() {
+            Some((range, last)) if *last == color && range.end() + 1 == index => {
+                *range = ZoneRange {
+                    start: range.start(),
+                    end: index,
+                }
+            }
+            _ => runs.push((ZoneRange::single(index), color)),
+        }
+    }
+
+    let last = runs.len().saturating_sub(1);
+    runs.into_iter()
+        .enumerate()
+        .map(|(i, (range, color))| {
+            let apply = match i == last {
+                true => ApplicationRequest::Apply,
+                false => ApplicationRequest::NoApply,
+            };
+            Message::set_color_zones(range, color, duration, apply)
+        })
+        .collect()
+}
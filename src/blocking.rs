@@ -0,0 +1,752 @@
+//! A simple synchronous client, for scripts and tools that don't want an async runtime
+//!
+//! [SyncClient] uses a plain [std::net::UdpSocket], and blocks the calling thread until each reply
+//! arrives (or the timeout passes).  It handles one request at a time, which keeps it simple, but
+//! makes it slow for talking to many devices at once; use the async [Client](crate::Client) for
+//! that.
+//!
+//! Everything that devices report in their replies is kept in a [DeviceMap], which other threads
+//! can read, and [SyncClient::wait_for] blocks until a device gets into some state.
+//!
+//! ```no_run
+//! # fn example() -> Result<(), lifx::Error> {
+//! use lifx::SyncClient;
+//! use lifx_core::HSBK;
+//! use std::time::Duration;
+//!
+//! let client = SyncClient::new()?;
+//! for device in client.discover(Duration::from_secs(1))? {
+//!     let state = client.get_state(device.target)?;
+//!     println!("{:?} is {:?}", state.label, state.color);
+//!     let red: HSBK = "red".parse()?;
+//!     client.set_color(device.target, red, Duration::from_secs(1))?;
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::{ClientOptions, DiscoveredDevice, Response, ServiceReply, SERVICE_RETRY};
+use crate::devices::{DeviceMap, DeviceStateSnapshot};
+use crate::proto::{Connection, RequestId, Status};
+use crate::state::DeviceState;
+use crate::transport::{self, BlockingTransport, ErrorClass, RECV_BUFFER_SIZE};
+use crate::Error;
+use lifx_core::{default_broadcast_addr, Message, SourceId, HSBK};
+use std::collections::HashMap;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A blocking client, which sends one request at a time
+///
+/// Devices found by [SyncClient::discover] are remembered, so later calls only need their target
+/// ID.  Use [SyncClient::add_device] for devices whose address is already known.
+///
+/// Devices can move to a new address (when DHCP gives them a different one, say), so the
+/// remembered addresses are checked with [SyncClient::rediscover]:
+///
+/// * before using an address that's older than the TTL (see [SyncClient::with_address_ttl])
+/// * when the network reports that a device is unreachable, in which case its address is forgotten
+///   unless it's found again
+/// * when a device hasn't answered [MAX_MISSES] requests in a row
+///
+/// If the device turns up at a new address in the last two cases, the request is retried once
+/// there.
+///
+/// This normally uses a UDP socket, but can use any [BlockingTransport] (see
+/// [SyncClient::with_transport]).  Matching up replies is left to a [Connection], and this just
+/// moves datagrams between it and the transport.
+pub struct SyncClient<T = UdpSocket> {
+    transport: T,
+    connection: Mutex<Connection>,
+    timeout: Duration,
+    address_ttl: Duration,
+    devices: Mutex<HashMap<u64, KnownDevice>>,
+    states: Arc<DeviceMap>,
+}
+
+/// How long a [SyncClient] trusts a device's address by default, before looking it up again
+pub const DEFAULT_ADDRESS_TTL: Duration = Duration::from_secs(10 * 60);
+
+/// How many requests in a row a device can fail to answer before [SyncClient] looks for it at a
+/// new address
+pub const MAX_MISSES: u32 = 3;
+
+/// How often [SyncClient::wait_for] asks a device for its state
+pub const WAIT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// A device whose address is known
+struct KnownDevice {
+    addr: SocketAddr,
+    /// When the address was last confirmed by discovery
+    resolved: Instant,
+    /// How many requests in a row have timed out
+    misses: u32,
+}
+
+impl SyncClient {
+    /// Creates a new client using the default [ClientOptions]
+    pub fn new() -> Result<SyncClient, Error> {
+        SyncClient::with_options(ClientOptions::default())
+    }
+
+    /// Creates a new client
+    ///
+    /// Only `bind_addr`, `source`, and `timeout` are used from the options.
+    pub fn with_options(options: ClientOptions) -> Result<SyncClient, Error> {
+        let socket = UdpSocket::bind(options.bind_addr)?;
+        socket.set_broadcast(true)?;
+        Ok(SyncClient::with_transport(socket, options))
+    }
+}
+
+impl<T: BlockingTransport> SyncClient<T> {
+    /// Creates a new client that sends and receives through the given transport
+    ///
+    /// Only `source` and `timeout` are used from the options.
+    pub fn with_transport(transport: T, options: ClientOptions) -> SyncClient<T> {
+        SyncClient {
+            transport,
+            connection: Mutex::new(Connection::new(options.source)),
+            timeout: options.timeout,
+            address_ttl: DEFAULT_ADDRESS_TTL,
+            devices: Mutex::new(HashMap::new()),
+            states: Arc::new(DeviceMap::new()),
+        }
+    }
+
+    /// Sets how long a device's address is trusted before it's looked up again (the default is
+    /// [DEFAULT_ADDRESS_TTL])
+    ///
+    /// Devices can get a new address from DHCP at any time, and messages sent to the old address
+    /// just disappear.
+    pub fn with_address_ttl(mut self, address_ttl: Duration) -> SyncClient<T> {
+        self.address_ttl = address_ttl;
+        self
+    }
+
+    /// Keeps device state in the given map, instead of one of its own
+    ///
+    /// This lets several clients (or a client and a [Receiver](crate::receiver::Receiver)) keep
+    /// one map up to date.
+    pub fn with_device_map(mut self, states: Arc<DeviceMap>) -> SyncClient<T> {
+        self.states = states;
+        self
+    }
+
+    /// The state of every device, as of the replies this client has received and the commands
+    /// that have been acknowledged
+    pub fn device_map(&self) -> &Arc<DeviceMap> {
+        &self.states
+    }
+
+    /// The source ID used by this client
+    pub fn source(&self) -> SourceId {
+        self.connection.lock().unwrap().source()
+    }
+
+    /// The local address that this client is bound to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.transport.local_addr()
+    }
+
+    /// Remembers the address of a device, so it can be used without discovering it first
+    pub fn add_device(&self, device: DiscoveredDevice) {
+        let known = KnownDevice {
+            addr: device.addr,
+            resolved: Instant::now(),
+            misses: 0,
+        };
+        self.devices.lock().unwrap().insert(device.target, known);
+    }
+
+    /// The address of a device, if it has been discovered or added
+    pub fn addr(&self, target: u64) -> Option<SocketAddr> {
+        self.devices.lock().unwrap().get(&target).map(|d| d.addr)
+    }
+
+    /// Broadcasts a discovery request, and collects replies for `wait`
+    pub fn discover(&self, wait: Duration) -> Result<Vec<DiscoveredDevice>, Error> {
+        self.discover_on(default_broadcast_addr(), wait)
+    }
+
+    /// Sends a discovery request to a specific address, and collects replies for `wait`
+    ///
+    /// Every device found is remembered for later calls.  Like
+    /// [Client::discover_on](crate::Client::discover_on), devices that advertise port 0 are asked
+    /// again until they advertise a real one.
+    pub fn discover_on(
+        &self,
+        addr: SocketAddr,
+        wait: Duration,
+    ) -> Result<Vec<DiscoveredDevice>, Error> {
+        let deadline = Instant::now() + wait;
+        let request = self.start(addr, None, Message::GetService, false, true, deadline)?;
+        let mut devices: Vec<DiscoveredDevice> = Vec::new();
+        let mut unavailable: HashMap<u64, SocketAddr> = HashMap::new();
+        let mut retry_at = None;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            if retry_at.is_some_and(|at| now >= at) {
+                for &addr in unavailable.values() {
+                    self.resend(request.id, addr)?;
+                }
+                retry_at = None;
+            }
+            if retry_at.is_none() && !unavailable.is_empty() {
+                retry_at = Some(now + SERVICE_RETRY);
+            }
+            let until = retry_at.map_or(deadline, |at| at.min(deadline));
+            let Some(reply) = self.next_reply(request.id, until)? else {
+                continue;
+            };
+            let Ok(msg) = reply.message() else {
+                continue;
+            };
+            match ServiceReply::new(reply.target(), reply.addr, &msg) {
+                ServiceReply::Available(device) => {
+                    unavailable.remove(&device.target);
+                    if !devices.iter().any(|d| d.target == device.target) {
+                        self.add_device(device);
+                        devices.push(device);
+                    }
+                }
+                ServiceReply::Unavailable => {
+                    unavailable.insert(reply.target(), reply.addr);
+                }
+                ServiceReply::Other => (),
+            }
+        }
+        Ok(devices)
+    }
+
+    /// Broadcasts a discovery request, and waits for one particular device to reply
+    ///
+    /// If the device replies before the timeout, its address is remembered and returned.  Replies
+    /// from any other device are ignored.
+    pub fn rediscover(&self, target: u64) -> Result<Option<SocketAddr>, Error> {
+        let broadcast = default_broadcast_addr();
+        let deadline = Instant::now() + self.timeout;
+        let request = self.start(broadcast, None, Message::GetService, false, true, deadline)?;
+        while let Some(reply) = self.next_reply(request.id, deadline)? {
+            if reply.target() != target {
+                continue;
+            }
+            let Ok(msg) = reply.message() else {
+                continue;
+            };
+            if let ServiceReply::Available(device) = ServiceReply::new(target, reply.addr, &msg) {
+                self.add_device(device);
+                return Ok(Some(device.addr));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Sends a message to a device and waits for its reply
+    ///
+    /// Any acknowledgements are skipped, so this returns the first "real" reply.
+    pub fn request(&self, target: u64, msg: Message) -> Result<Message, Error> {
+        self.with_addr(target, |addr| {
+            let deadline = Instant::now() + self.timeout;
+            let request = self.start(addr, Some(target), msg.clone(), false, true, deadline)?;
+            while let Some(reply) = self.next_reply(request.id, deadline)? {
+                match reply.message()? {
+                    Message::Acknowledgement { .. } => continue,
+                    msg => return Ok(msg),
+                }
+            }
+            Err(Error::Timeout)
+        })
+    }
+
+    /// Sends a message to a device and waits for it to be acknowledged
+    ///
+    /// Once it is, the command is assumed to have worked, and the [device map](Self::device_map)
+    /// is updated to match (see [DeviceState::apply]).
+    pub fn send_acked(&self, target: u64, msg: Message) -> Result<(), Error> {
+        self.with_addr(target, |addr| {
+            let deadline = Instant::now() + self.timeout;
+            let request = self.start(addr, Some(target), msg.clone(), true, false, deadline)?;
+            while let Some(reply) = self.next_reply(request.id, deadline)? {
+                if let Message::Acknowledgement { .. } = reply.message()? {
+                    return Ok(());
+                }
+            }
+            Err(Error::Timeout)
+        })?;
+        self.states.apply(target, &msg);
+        Ok(())
+    }
+
+    /// Waits until a device's state satisfies `predicate`, and returns that state
+    ///
+    /// The device is asked for its color, power, and label (with a [Message::LightGet]) every
+    /// [WAIT_POLL_INTERVAL], and in between, anything else that changes it in the
+    /// [device map](Self::device_map) (such as replies to other threads' requests) is checked too.
+    /// A device that doesn't answer is just asked again.  Returns [Error::Timeout] if it doesn't
+    /// get into the state in time.
+    ///
+    /// ```no_run
+    /// # fn example(client: lifx::SyncClient) -> Result<(), lifx::Error> {
+    /// use std::time::Duration;
+    ///
+    /// // wait for someone to turn the light on
+    /// let state = client.wait_for(0xd073d5001234, |state| state.power > Some(0), Duration::from_secs(60))?;
+    /// println!("the light is on: {:?}", state.color);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn wait_for(
+        &self,
+        target: u64,
+        mut predicate: impl FnMut(&DeviceState) -> bool,
+        timeout: Duration,
+    ) -> Result<DeviceStateSnapshot, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            match self.request(target, Message::LightGet) {
+                Ok(_) | Err(Error::Timeout) => (),
+                Err(e) => return Err(e),
+            }
+            let wait = deadline
+                .saturating_duration_since(Instant::now())
+                .min(WAIT_POLL_INTERVAL);
+            let found = self
+                .states
+                .wait_for(|state| state.target == target && predicate(state), wait);
+            match found {
+                Err(Error::Timeout) if Instant::now() < deadline => continue,
+                res => return res,
+            }
+        }
+    }
+
+    /// Calls `f` with the address of a device, checking the address as described in [SyncClient]
+    fn with_addr<R>(
+        &self,
+        target: u64,
+        f: impl Fn(SocketAddr) -> Result<R, Error>,
+    ) -> Result<R, Error> {
+        let (mut addr, stale) = {
+            let devices = self.devices.lock().unwrap();
+            let known = devices.get(&target).ok_or(Error::UnknownAddress(target))?;
+            (known.addr, known.resolved.elapsed() >= self.address_ttl)
+        };
+        if stale {
+            match self.rediscover(target)? {
+                Some(found) => addr = found,
+                // it might just be turned off, so keep the old address until it stops working
+                None => self.update(target, |known| known.resolved = Instant::now()),
+            }
+        }
+        match f(addr) {
+            Err(Error::Unreachable(_)) => {
+                self.devices.lock().unwrap().remove(&target);
+                f(self.rediscover(target)?.ok_or(Error::Unreachable(addr))?)
+            }
+            Err(Error::Timeout) => {
+                let mut misses = 0;
+                self.update(target, |known| {
+                    known.misses += 1;
+                    misses = known.misses;
+                });
+                if misses >= MAX_MISSES {
+                    self.update(target, |known| known.misses = 0);
+                    if let Some(found) = self.rediscover(target)?.filter(|&a| a != addr) {
+                        return f(found);
+                    }
+                }
+                Err(Error::Timeout)
+            }
+            res => {
+                self.update(target, |known| known.misses = 0);
+                res
+            }
+        }
+    }
+
+    /// Changes what's known about a device, if it's still known
+    fn update(&self, target: u64, f: impl FnOnce(&mut KnownDevice)) {
+        if let Some(known) = self.devices.lock().unwrap().get_mut(&target) {
+            f(known);
+        }
+    }
+
+    /// Asks a device for its color, power, label, version, group, and location
+    pub fn get_state(&self, target: u64) -> Result<DeviceState, Error> {
+        let mut state = DeviceState::new(target);
+        state.addr = self.addr(target);
+        for msg in [
+            Message::LightGet,
+            Message::GetVersion,
+            Message::GetGroup,
+            Message::GetLocation,
+        ] {
+            state.update(&self.request(target, msg)?);
+        }
+        Ok(state)
+    }
+
+    /// Changes the color of a device, and waits for it to be acknowledged
+    pub fn set_color(&self, target: u64, color: HSBK, duration: Duration) -> Result<(), Error> {
+        self.send_acked(target, Message::set_color(color, duration))
+    }
+
+    /// Starts a request that waits for replies until `deadline`, and sends it
+    fn start(
+        &self,
+        addr: SocketAddr,
+        target: Option<u64>,
+        msg: Message,
+        ack_required: bool,
+        res_required: bool,
+        deadline: Instant,
+    ) -> Result<Pending<'_, T>, Error> {
+        let id = self.connection.lock().unwrap().send_request(
+            addr,
+            target,
+            msg,
+            ack_required,
+            res_required,
+            deadline,
+        )?;
+        let pending = Pending { client: self, id };
+        self.flush()?;
+        Ok(pending)
+    }
+
+    /// Sends a request again, with the same sequence number
+    fn resend(&self, id: RequestId, addr: SocketAddr) -> Result<(), Error> {
+        self.connection.lock().unwrap().resend(id, addr);
+        self.flush()
+    }
+
+    /// Sends everything the connection has waiting to be sent
+    fn flush(&self) -> Result<(), Error> {
+        loop {
+            let Some(transmit) = self.connection.lock().unwrap().poll_transmit() else {
+                return Ok(());
+            };
+            self.send_datagram(&transmit.contents, transmit.destination)?;
+        }
+    }
+
+    fn send_datagram(&self, bytes: &[u8], addr: SocketAddr) -> Result<(), Error> {
+        let mut delay = transport::SEND_RETRY_DELAY;
+        for _ in 0..transport::SEND_RETRIES {
+            match self.transport.send_to(bytes, addr) {
+                Err(e) if transport::classify(&e) == ErrorClass::Transient => {
+                    thread::sleep(delay);
+                    delay *= 2;
+                }
+                res => return res.map(|_| ()).map_err(|e| transport::send_error(e, addr)),
+            }
+        }
+        self.transport
+            .send_to(bytes, addr)
+            .map(|_| ())
+            .map_err(|e| transport::send_error(e, addr))
+    }
+
+    /// Waits for the next reply to a request
+    ///
+    /// Returns `None` once `until` has passed, or once the request has stopped waiting and every
+    /// reply to it has been returned.  Datagrams that arrive in the meantime are handed to the
+    /// connection, so replies to other threads' requests aren't lost.
+    fn next_reply(&self, id: RequestId, until: Instant) -> Result<Option<Response>, Error> {
+        let mut buf = vec![0; RECV_BUFFER_SIZE];
+        loop {
+            let now = Instant::now();
+            let deadline = {
+                let mut connection = self.connection.lock().unwrap();
+                connection.handle_timeout(now);
+                if let Some(reply) = connection.poll_reply(id) {
+                    // anything that can't be decoded is still returned, for the caller to report
+                    let _ = self.states.update_raw(&reply.raw, reply.addr);
+                    return Ok(Some(reply));
+                }
+                if connection.status(id) != Some(Status::Waiting) {
+                    return Ok(None);
+                }
+                connection.deadline(id).map_or(until, |d| d.min(until))
+            };
+            let remaining = deadline.saturating_duration_since(now);
+            if remaining.is_zero() {
+                return Ok(None);
+            }
+            match self.transport.recv_from(&mut buf, Some(remaining)) {
+                Ok((nbytes, from)) => {
+                    let mut connection = self.connection.lock().unwrap();
+                    connection.handle_datagram(from, &buf[..nbytes]);
+                }
+                Err(e) => match transport::classify(&e) {
+                    // this includes the timeout running out, which is checked at the top
+                    ErrorClass::Transient => continue,
+                    // ICMP errors from previous sends can show up here
+                    ErrorClass::Unreachable => continue,
+                    // like anything else that can't be decoded, this is dropped
+                    ErrorClass::Truncated => continue,
+                    ErrorClass::NetworkDown => return Err(Error::NetworkDown),
+                    ErrorClass::Fatal => return Err(e.into()),
+                },
+            }
+        }
+    }
+}
+
+/// A request that's been started, which is forgotten when this is dropped
+struct Pending<'a, T: BlockingTransport> {
+    client: &'a SyncClient<T>,
+    id: RequestId,
+}
+
+impl<T: BlockingTransport> Drop for Pending<'_, T> {
+    fn drop(&mut self) {
+        self.client.connection.lock().unwrap().finish(self.id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client;
+
+    /// Runs a [fake bulb](crate::client::tests::fake_bulb) on its own thread, so that it keeps
+    /// going while the test blocks
+    fn fake_bulb(target: u64) -> SocketAddr {
+        let (tx, rx) = std::sync::mpsc::channel();
+        thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread()
+                .enable_all()
+                .build()
+                .unwrap();
+            runtime.block_on(async {
+                tx.send(client::tests::fake_bulb(target, "Desk").await)
+                    .unwrap();
+                std::future::pending::<()>().await
+            })
+        });
+        rx.recv().unwrap()
+    }
+
+    fn client() -> SyncClient {
+        SyncClient::with_options(ClientOptions {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_sync_client() {
+        let addr = fake_bulb(0x1234);
+        let client = client();
+        assert!(matches!(
+            client.get_state(0x1234),
+            Err(Error::UnknownAddress(0x1234))
+        ));
+
+        let devices = client
+            .discover_on(addr, Duration::from_millis(100))
+            .unwrap();
+        assert_eq!(
+            devices,
+            vec![DiscoveredDevice {
+                target: 0x1234,
+                addr
+            }]
+        );
+
+        let red = "red".parse().unwrap();
+        client.set_color(0x1234, red, Duration::ZERO).unwrap();
+        let state = client.get_state(0x1234).unwrap();
+        assert_eq!(state.color, Some(red));
+        assert_eq!(state.label.as_deref(), Some("Desk"));
+        assert_eq!(state.version, Some((1, 29)));
+        assert_eq!(state.addr, Some(addr));
+        assert!(state.group.is_some() && state.location.is_some());
+    }
+
+    #[test]
+    fn test_wait_for() {
+        let addr = fake_bulb(0x1234);
+        let waiter = client();
+        let device = DiscoveredDevice {
+            target: 0x1234,
+            addr,
+        };
+        waiter.add_device(device);
+        let red: HSBK = "red".parse().unwrap();
+        let is_red = |state: &DeviceState| state.color == Some(red);
+        let res = waiter.wait_for(0x1234, is_red, Duration::from_millis(50));
+        assert!(matches!(res, Err(Error::Timeout)));
+        assert!(waiter.device_map().get(0x1234).unwrap().color.is_some());
+
+        // someone else changes it, which shows up the next time it's asked
+        let other = client();
+        other.add_device(device);
+        let changer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            other.set_color(0x1234, red, Duration::ZERO).unwrap();
+        });
+        let state = waiter
+            .wait_for(0x1234, is_red, Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(state.color, Some(red));
+        changer.join().unwrap();
+
+        // acknowledged commands are applied straight away
+        let blue = "blue".parse().unwrap();
+        waiter.set_color(0x1234, blue, Duration::ZERO).unwrap();
+        assert_eq!(waiter.device_map().get(0x1234).unwrap().color, Some(blue));
+    }
+
+    #[test]
+    fn test_timeout() {
+        // nothing is listening here
+        let sock = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = client();
+        client.add_device(DiscoveredDevice {
+            target: 1,
+            addr: sock.local_addr().unwrap(),
+        });
+        assert!(matches!(
+            client.request(1, Message::GetLabel),
+            Err(Error::Timeout)
+        ));
+    }
+
+    /// A socket that can't reach `dead`, and sends broadcasts to `bulb`
+    struct Moved {
+        sock: UdpSocket,
+        dead: SocketAddr,
+        bulb: SocketAddr,
+    }
+
+    impl BlockingTransport for Moved {
+        fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+            if addr == self.dead {
+                return Err(io::ErrorKind::HostUnreachable.into());
+            }
+            let addr = if addr == default_broadcast_addr() {
+                self.bulb
+            } else {
+                addr
+            };
+            self.sock.send_to(buf, addr)
+        }
+
+        fn recv_from(
+            &self,
+            buf: &mut [u8],
+            timeout: Option<Duration>,
+        ) -> io::Result<(usize, SocketAddr)> {
+            BlockingTransport::recv_from(&self.sock, buf, timeout)
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.sock.local_addr()
+        }
+    }
+
+    #[test]
+    fn test_unreachable() {
+        let bulb = fake_bulb(0x1234);
+        let dead = "127.0.0.1:9".parse().unwrap();
+        let moved = Moved {
+            sock: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            dead,
+            bulb,
+        };
+        let options = ClientOptions {
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let client = SyncClient::with_transport(moved, options);
+
+        // the bulb is found again at its new address
+        client.add_device(DiscoveredDevice {
+            target: 0x1234,
+            addr: dead,
+        });
+        let red = "red".parse().unwrap();
+        client.set_color(0x1234, red, Duration::ZERO).unwrap();
+        assert_eq!(client.addr(0x1234), Some(bulb));
+
+        // but this one isn't anywhere
+        client.add_device(DiscoveredDevice {
+            target: 1,
+            addr: dead,
+        });
+        let res = client.request(1, Message::GetLabel);
+        assert!(matches!(res, Err(Error::Unreachable(addr)) if addr == dead));
+        assert_eq!(client.addr(1), None);
+    }
+
+    fn moved_client(bulb: SocketAddr, address_ttl: Duration) -> SyncClient<Moved> {
+        let moved = Moved {
+            sock: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            dead: "127.0.0.1:9".parse().unwrap(),
+            bulb,
+        };
+        let options = ClientOptions {
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        SyncClient::with_transport(moved, options).with_address_ttl(address_ttl)
+    }
+
+    #[test]
+    fn test_moved_silently() {
+        let bulb = fake_bulb(0x1234);
+        // nothing answers at the old address, and nothing reports an error either
+        let old = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let old = old.local_addr().unwrap();
+        let client = moved_client(bulb, Duration::from_secs(60));
+        client.add_device(DiscoveredDevice {
+            target: 0x1234,
+            addr: old,
+        });
+        for _ in 1..MAX_MISSES {
+            let res = client.request(0x1234, Message::LightGet);
+            assert!(matches!(res, Err(Error::Timeout)));
+            assert_eq!(client.addr(0x1234), Some(old));
+        }
+        // the last miss looks for the bulb, and retries at its new address
+        client.request(0x1234, Message::LightGet).unwrap();
+        assert_eq!(client.addr(0x1234), Some(bulb));
+
+        // a missing device keeps its address
+        client.add_device(DiscoveredDevice {
+            target: 1,
+            addr: old,
+        });
+        for _ in 0..MAX_MISSES {
+            let res = client.request(1, Message::LightGet);
+            assert!(matches!(res, Err(Error::Timeout)));
+        }
+        assert_eq!(client.addr(1), Some(old));
+    }
+
+    #[test]
+    fn test_address_ttl() {
+        let bulb = fake_bulb(0x1234);
+        let old = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let old = old.local_addr().unwrap();
+        // every address is out of date straight away, so it's checked before every request
+        let client = moved_client(bulb, Duration::ZERO);
+        client.add_device(DiscoveredDevice {
+            target: 0x1234,
+            addr: old,
+        });
+        client.request(0x1234, Message::LightGet).unwrap();
+        assert_eq!(client.addr(0x1234), Some(bulb));
+    }
+}
@@ -0,0 +1,1736 @@
+//! An async client that shares a single UDP socket between many concurrent requests
+//!
+//! A [Client] is a cheap handle (it's just an [Arc] internally), so it can be cloned into as many
+//! tasks as needed.  Every clone shares the same socket and the same background task, which reads
+//! all incoming datagrams and routes each reply to the request that it belongs to.
+//!
+//! Sequence numbers, and matching replies to requests, are left to a [Connection]: a reply must
+//! have this client's source ID, and its (target, sequence) pair must match an outstanding
+//! request.  Requests that were broadcast (with no target) will match replies from any target.
+//! Sequence numbers aren't reused until the request that was using them is finished.  The
+//! client's background tasks just move datagrams between the connection and the transport, and
+//! wake up whoever is waiting for each reply.
+//!
+//! All of the request methods are cancellation-safe: dropping one of their futures (because it lost
+//! a `select!`, or was wrapped in a timeout) frees its sequence number straight away, and if the
+//! message hasn't been sent yet, it's taken out of the send queue instead of going out later, when
+//! nothing is listening for the reply.
+
+use crate::collision::CollisionDetector;
+use crate::dedup::DedupFilter;
+use crate::filter::{self, FilterSlot, MessageFilter};
+use crate::proto::{Connection, RequestId, Transmit};
+use crate::queue::{Outgoing, Priority, QueueStats, SharedQueue};
+use crate::record::{self, Direction, Recorder, RecorderSlot};
+use crate::telemetry;
+use crate::transport::{self, Backoff, ErrorClass, Transport, RECV_BUFFER_SIZE};
+use crate::unsolicited::{Unsolicited, UNSOLICITED_CAPACITY};
+use crate::Error;
+use lifx_core::zones::ExtendedZones;
+use lifx_core::{
+    get_product_info, FirmwareVersion, Message, ProductInfo, RawMessage, Service, SourceId,
+    ZoneRange, HSBK,
+};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tokio::sync::{broadcast, oneshot, Notify, Semaphore};
+use tokio::task::JoinHandle;
+
+/// A message received from a device in reply to one of our requests
+#[derive(Debug, Clone)]
+pub struct Response {
+    /// The address that the reply came from
+    pub addr: SocketAddr,
+    pub raw: RawMessage,
+}
+
+impl Response {
+    /// The ID of the device that sent this reply
+    pub fn target(&self) -> u64 {
+        self.raw.frame_addr.target
+    }
+
+    /// Decodes the payload of this reply
+    pub fn message(&self) -> Result<Message, lifx_core::Error> {
+        let msg = Message::from_raw(&self.raw);
+        if msg.is_err() {
+            telemetry::decode_error();
+        }
+        msg
+    }
+}
+
+/// Outstanding requests, shared by a [Client] and every [Responses]
+struct Requests {
+    connection: Connection,
+    /// What to wake when each request gets a reply
+    waiting: HashMap<RequestId, Arc<Notify>>,
+    collisions: CollisionDetector,
+}
+
+impl Requests {
+    /// Takes everything the connection has waiting to be sent
+    fn transmits(&mut self) -> Vec<Transmit> {
+        std::iter::from_fn(|| self.connection.poll_transmit()).collect()
+    }
+}
+
+type SharedRequests = Arc<Mutex<Requests>>;
+
+/// A stream of replies to a single request
+///
+/// Replies will keep being collected for as long as this object is alive.  Dropping it
+/// unregisters the request and frees up its sequence number, and any further replies to it will be
+/// discarded.
+pub struct Responses {
+    id: RequestId,
+    sequence: u8,
+    /// The message type of the request, for telemetry when it's sent again
+    typ: u16,
+    notify: Arc<Notify>,
+    requests: SharedRequests,
+}
+
+impl Responses {
+    /// The sequence number that was used to send the request
+    pub fn sequence(&self) -> u8 {
+        self.sequence
+    }
+
+    /// Waits for the next reply
+    ///
+    /// Note that this will wait forever if the device never replies, so you probably want
+    /// [Responses::recv_timeout] instead.
+    pub async fn recv(&mut self) -> Option<Response> {
+        loop {
+            {
+                let mut requests = self.requests.lock().unwrap();
+                if let Some(resp) = requests.connection.poll_reply(self.id) {
+                    return Some(resp);
+                }
+                requests.connection.status(self.id)?;
+            }
+            // a reply that arrives before this starts waiting leaves a permit, so it isn't missed
+            self.notify.notified().await;
+        }
+    }
+
+    /// Waits for the next reply, returning [Error::Timeout] if one doesn't arrive in time
+    pub async fn recv_timeout(&mut self, timeout: Duration) -> Result<Response, Error> {
+        match tokio::time::timeout(timeout, self.recv()).await {
+            Ok(Some(resp)) => Ok(resp),
+            Ok(None) | Err(_) => Err(Error::Timeout),
+        }
+    }
+}
+
+impl Drop for Responses {
+    fn drop(&mut self) {
+        if let Ok(mut requests) = self.requests.lock() {
+            requests.connection.finish(self.id);
+            requests.waiting.remove(&self.id);
+        }
+    }
+}
+
+/// How long to wait before asking a device whose service is temporarily unavailable again
+pub const SERVICE_RETRY: Duration = Duration::from_millis(250);
+
+/// A device that replied to a discovery request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DiscoveredDevice {
+    /// The ID of the device, used as the [BuildOptions::target](lifx_core::BuildOptions::target) when talking to it
+    pub target: u64,
+    /// The address to send messages to
+    ///
+    /// This uses the port advertised in the [Message::StateService] reply, which isn't always
+    /// [LIFX_PORT](lifx_core::LIFX_PORT).
+    pub addr: SocketAddr,
+}
+
+/// A discovered device, along with the details that tools usually want to show straight away
+///
+/// See [Client::snapshot].  Anything that the device didn't report in time is `None`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceSnapshot {
+    pub device: DiscoveredDevice,
+    pub label: Option<String>,
+    /// The (vendor, product) IDs from [Message::StateVersion]
+    pub version: Option<(u32, u32)>,
+    /// The firmware version from [Message::StateHostFirmware]
+    pub firmware: Option<FirmwareVersion>,
+    /// The Wi-Fi signal strength, in dBm (see [Message::wifi_signal_dbm])
+    pub signal: Option<i32>,
+}
+
+impl DeviceSnapshot {
+    /// The messages that are sent to fill in a snapshot
+    const QUERIES: [Message; 4] = [
+        Message::GetLabel,
+        Message::GetVersion,
+        Message::GetHostFirmware,
+        Message::GetWifiInfo,
+    ];
+
+    fn new(device: DiscoveredDevice) -> DeviceSnapshot {
+        DeviceSnapshot {
+            device,
+            label: None,
+            version: None,
+            firmware: None,
+            signal: None,
+        }
+    }
+
+    fn update(&mut self, msg: &Message) {
+        match msg {
+            Message::StateLabel { label } => self.label = Some(label.to_string()),
+            Message::StateVersion {
+                vendor, product, ..
+            } => self.version = Some((*vendor, *product)),
+            Message::StateHostFirmware { .. } => {
+                self.firmware = FirmwareVersion::from_state_firmware(msg)
+            }
+            Message::StateWifiInfo { .. } => self.signal = msg.wifi_signal_dbm(),
+            _ => (),
+        }
+    }
+
+    /// Product details for this device, if we know its version and it's a known product
+    pub fn product_info(&self) -> Option<&'static ProductInfo> {
+        let (vendor, product) = self.version?;
+        get_product_info(vendor, product)
+    }
+}
+
+/// What a device said in reply to a [Message::GetService]
+pub(crate) enum ServiceReply {
+    /// The device can be reached on this address
+    Available(DiscoveredDevice),
+    /// The device advertised port 0, which means that its service is temporarily unavailable,
+    /// and it should be asked again later (at the address the reply came from)
+    Unavailable,
+    /// Not a UDP [Message::StateService] (or a port that doesn't fit in a `u16`)
+    Other,
+}
+
+impl ServiceReply {
+    pub(crate) fn new(target: u64, from: SocketAddr, msg: &Message) -> ServiceReply {
+        match *msg {
+            Message::StateService {
+                service: Service::UDP,
+                port: 0,
+            } => ServiceReply::Unavailable,
+            Message::StateService {
+                service: Service::UDP,
+                port,
+            } => match u16::try_from(port) {
+                Ok(port) => ServiceReply::Available(DiscoveredDevice {
+                    target,
+                    addr: SocketAddr::new(from.ip(), port),
+                }),
+                Err(_) => ServiceReply::Other,
+            },
+            _ => ServiceReply::Other,
+        }
+    }
+}
+
+/// Options used to construct a [Client]
+#[derive(Debug, Clone, Copy)]
+pub struct ClientOptions {
+    /// The local address to bind the socket to
+    pub bind_addr: SocketAddr,
+    /// The source ID included in all messages sent by this client
+    ///
+    /// Devices include this in their replies, which is how we know that a reply was meant for us.
+    /// This should be different from the source ID used by any other LIFX controllers on your
+    /// network.  The default is a new random source for every client.
+    pub source: SourceId,
+    /// How long to wait for a reply in [Client::request] and [Client::send_acked]
+    pub timeout: Duration,
+    /// The maximum number of messages of each [Priority] that can be waiting in the send queue
+    ///
+    /// When this is exceeded, the oldest waiting message of that priority is dropped.
+    pub queue_capacity: usize,
+    /// Replies that are identical to one received within this long are dropped
+    ///
+    /// See [DedupFilter].  Set this to zero to receive every copy.
+    pub dedup_window: Duration,
+    /// How many replies to requests we never sent it takes to decide that another controller is
+    /// using our source ID
+    ///
+    /// When that happens, the client switches to a new random source ID, and logs a warning.
+    /// Requests that are waiting for a reply when it switches will probably time out.  See
+    /// [collision](crate::collision).  Set this to zero to never switch.
+    pub collision_threshold: usize,
+}
+
+impl Default for ClientOptions {
+    fn default() -> Self {
+        ClientOptions {
+            bind_addr: SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+            source: lifx_core::source::generate(),
+            timeout: Duration::from_secs(1),
+            queue_capacity: 256,
+            dedup_window: Duration::from_secs(1),
+            collision_threshold: 5,
+        }
+    }
+}
+
+/// A handle for sending messages to LIFX devices and receiving their replies
+///
+/// This is cheap to clone, and all clones share the same socket.
+#[derive(Clone)]
+pub struct Client {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    transport: Arc<dyn Transport>,
+    timeout: Duration,
+    requests: SharedRequests,
+    queue: Arc<SharedQueue>,
+    recorder: RecorderSlot,
+    filter: FilterSlot,
+    unsolicited: broadcast::Sender<Unsolicited>,
+    recv_task: JoinHandle<()>,
+    send_task: JoinHandle<()>,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.recv_task.abort();
+        self.send_task.abort();
+    }
+}
+
+impl Client {
+    /// Creates a new client using the default [ClientOptions]
+    ///
+    /// This must be called from within a tokio runtime.
+    pub async fn new() -> Result<Client, Error> {
+        Client::with_options(ClientOptions::default()).await
+    }
+
+    /// Creates a new client
+    ///
+    /// This must be called from within a tokio runtime.
+    pub async fn with_options(options: ClientOptions) -> Result<Client, Error> {
+        let socket = UdpSocket::bind(options.bind_addr).await?;
+        socket.set_broadcast(true)?;
+        Ok(Client::with_transport(socket, options))
+    }
+
+    /// Creates a new client that sends and receives through the given transport
+    ///
+    /// [ClientOptions::bind_addr] isn't used, since the transport is already bound.  This must be
+    /// called from within a tokio runtime.
+    pub fn with_transport(transport: impl Transport, options: ClientOptions) -> Client {
+        let transport: Arc<dyn Transport> = Arc::new(transport);
+        let requests = Arc::new(Mutex::new(Requests {
+            connection: Connection::new(options.source),
+            waiting: HashMap::new(),
+            collisions: CollisionDetector::new(options.collision_threshold),
+        }));
+        let queue = Arc::new(SharedQueue::new(options.queue_capacity));
+        let recorder = RecorderSlot::default();
+        let filter = FilterSlot::default();
+        let (unsolicited, _) = broadcast::channel(UNSOLICITED_CAPACITY);
+
+        let recv_task = tokio::spawn(recv_loop(
+            transport.clone(),
+            DedupFilter::new(options.dedup_window),
+            requests.clone(),
+            recorder.clone(),
+            filter.clone(),
+            unsolicited.clone(),
+        ));
+        let send_task = tokio::spawn(send_loop(
+            transport.clone(),
+            queue.clone(),
+            recorder.clone(),
+        ));
+
+        Client {
+            inner: Arc::new(Inner {
+                transport,
+                timeout: options.timeout,
+                requests,
+                queue,
+                recorder,
+                filter,
+                unsolicited,
+                recv_task,
+                send_task,
+            }),
+        }
+    }
+
+    /// The source ID currently used by this client
+    ///
+    /// This starts as [ClientOptions::source], but changes if another controller seems to be
+    /// using the same one (see [ClientOptions::collision_threshold]).
+    pub fn source(&self) -> SourceId {
+        self.inner.requests.lock().unwrap().connection.source()
+    }
+
+    /// Switches to a new random source ID, and returns it
+    ///
+    /// Replies to requests sent with the old source ID will be ignored.
+    pub fn renew_source(&self) -> SourceId {
+        let source = lifx_core::source::generate();
+        self.inner
+            .requests
+            .lock()
+            .unwrap()
+            .connection
+            .set_source(source);
+        source
+    }
+
+    /// The local address that this client is bound to
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.transport.local_addr()
+    }
+
+    /// How long to wait for a reply, from [ClientOptions::timeout]
+    pub fn timeout(&self) -> Duration {
+        self.inner.timeout
+    }
+
+    /// A snapshot of the current state of the send queue
+    pub fn queue_stats(&self) -> QueueStats {
+        self.inner.queue.stats()
+    }
+
+    /// Starts (or with `None`, stops) recording every datagram sent and received by this client
+    ///
+    /// This replaces any recorder that was already attached.  Received datagrams are recorded
+    /// before any filtering, so replies meant for other clients are included.  See
+    /// [record](crate::record).
+    pub fn set_recorder(&self, recorder: Option<Recorder>) {
+        *self.inner.recorder.lock().unwrap() = recorder;
+    }
+
+    /// Only accepts the message types allowed by a filter (or with `None`, accepts everything)
+    ///
+    /// Messages that are dropped by the filter are never delivered to any request, so a request
+    /// whose reply type is dropped will time out.  See [filter](crate::filter).
+    pub fn set_filter(&self, filter: Option<MessageFilter>) {
+        *self.inner.filter.lock().unwrap() = filter;
+    }
+
+    /// Subscribes to the state that devices broadcast to this client's socket without being asked
+    /// (see [unsolicited](crate::unsolicited))
+    ///
+    /// These are otherwise ignored, since they aren't replies to any of this client's requests.
+    /// Only messages that arrive after subscribing are received.  A subscriber that falls more
+    /// than [UNSOLICITED_CAPACITY] messages behind misses the oldest ones, and its next `recv`
+    /// returns [RecvError::Lagged](broadcast::error::RecvError::Lagged).
+    pub fn unsolicited(&self) -> broadcast::Receiver<Unsolicited> {
+        self.inner.unsolicited.subscribe()
+    }
+
+    /// Sends a message without asking for any kind of reply
+    ///
+    /// This is sent with [Priority::User].
+    pub async fn send(
+        &self,
+        addr: SocketAddr,
+        target: Option<u64>,
+        msg: Message,
+    ) -> Result<(), Error> {
+        let typ = msg.get_num();
+        let transmits = {
+            let mut requests = self.inner.requests.lock().unwrap();
+            let id = requests
+                .connection
+                .open_request(addr, target, msg, false, false)?;
+            let sequence = requests.connection.sequence(id).unwrap();
+            // nothing will reply to this, so the sequence number can be released straight away
+            requests.connection.finish(id);
+            requests.collisions.sent(target.unwrap_or(0), sequence);
+            requests.transmits()
+        };
+        self.transmit(transmits, typ, Priority::User).await
+    }
+
+    /// Queues datagrams from the connection, and waits for them to be sent
+    async fn transmit(
+        &self,
+        transmits: Vec<Transmit>,
+        typ: u16,
+        priority: Priority,
+    ) -> Result<(), Error> {
+        for transmit in transmits {
+            let (done, rx) = oneshot::channel();
+            let out = Outgoing {
+                addr: transmit.destination,
+                bytes: transmit.contents,
+                done,
+            };
+            self.inner.queue.push(priority, out);
+            // if the send task has gone away, the message is never going to be sent
+            rx.await.unwrap_or(Err(Error::Dropped))?;
+            telemetry::message_sent(typ);
+        }
+        Ok(())
+    }
+
+    /// Sends a message, and returns a [Responses] object that will receive all replies to it
+    ///
+    /// This is the most flexible way to send a request.  For example, it can be used to collect
+    /// all of the [Message::StateMultiZone] replies to a [Message::GetColorZones] request.
+    pub async fn send_request(
+        &self,
+        addr: SocketAddr,
+        target: Option<u64>,
+        msg: Message,
+        ack_required: bool,
+        res_required: bool,
+        priority: Priority,
+    ) -> Result<Responses, Error> {
+        let typ = msg.get_num();
+        let (responses, transmits) = {
+            let mut requests = self.inner.requests.lock().unwrap();
+            let id =
+                requests
+                    .connection
+                    .open_request(addr, target, msg, ack_required, res_required)?;
+            let sequence = requests.connection.sequence(id).unwrap();
+            requests.collisions.sent(target.unwrap_or(0), sequence);
+            let notify = Arc::new(Notify::new());
+            requests.waiting.insert(id, notify.clone());
+            let responses = Responses {
+                id,
+                sequence,
+                typ,
+                notify,
+                requests: self.inner.requests.clone(),
+            };
+            (responses, requests.transmits())
+        };
+        // if this fails, dropping the responses cancels the request
+        self.transmit(transmits, typ, priority).await?;
+        Ok(responses)
+    }
+
+    /// Sends a request again, to the given address, with the same sequence number
+    ///
+    /// Replies to either copy arrive at `responses`.
+    pub(crate) async fn resend(
+        &self,
+        responses: &Responses,
+        addr: SocketAddr,
+        priority: Priority,
+    ) -> Result<(), Error> {
+        let transmits = {
+            let mut requests = self.inner.requests.lock().unwrap();
+            requests.connection.resend(responses.id, addr);
+            requests.transmits()
+        };
+        self.transmit(transmits, responses.typ, priority).await
+    }
+
+    /// Sends a message to a device and waits for its reply
+    ///
+    /// Any acknowledgements are skipped, so this returns the first "real" reply.
+    ///
+    /// This is sent with [Priority::Refresh], since it's usually used to query a device's state.
+    /// Use [Client::send_request] to pick a different priority.
+    pub async fn request(
+        &self,
+        addr: SocketAddr,
+        target: u64,
+        msg: Message,
+    ) -> Result<Message, Error> {
+        let mut responses = self
+            .send_request(addr, Some(target), msg, false, true, Priority::Refresh)
+            .await?;
+        let sent = tokio::time::Instant::now();
+        let deadline = sent + self.inner.timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            match responses.recv_timeout(remaining).await?.message()? {
+                Message::Acknowledgement { .. } => continue,
+                msg => {
+                    telemetry::round_trip(sent.elapsed());
+                    return Ok(msg);
+                }
+            }
+        }
+    }
+
+    /// Sends a message to a device and waits for it to be acknowledged
+    ///
+    /// This is sent with [Priority::User].
+    pub async fn send_acked(
+        &self,
+        addr: SocketAddr,
+        target: u64,
+        msg: Message,
+    ) -> Result<(), Error> {
+        let mut responses = self
+            .send_request(addr, Some(target), msg, true, false, Priority::User)
+            .await?;
+        let sent = tokio::time::Instant::now();
+        let deadline = sent + self.inner.timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            if let Message::Acknowledgement { .. } =
+                responses.recv_timeout(remaining).await?.message()?
+            {
+                telemetry::round_trip(sent.elapsed());
+                return Ok(());
+            }
+        }
+    }
+
+    /// Asks a multizone device for the colors of a range of zones, and waits for all of the replies
+    ///
+    /// This returns the [Message::StateZone] or [Message::StateMultiZone] replies, which can be fed
+    /// into [DeviceState::update](crate::DeviceState::update).  Rather than waiting for the timeout,
+    /// this returns as soon as the expected number of replies has arrived, which is worked out from
+    /// the zone count in the first reply (see [ZoneRange::expected_replies]).
+    ///
+    /// This is sent with [Priority::Refresh].
+    pub async fn get_color_zones(
+        &self,
+        addr: SocketAddr,
+        target: u64,
+        range: ZoneRange,
+    ) -> Result<Vec<Message>, Error> {
+        let msg = Message::get_color_zones(range);
+        let mut responses = self
+            .send_request(addr, Some(target), msg, false, true, Priority::Refresh)
+            .await?;
+        let sent = tokio::time::Instant::now();
+        let deadline = sent + self.inner.timeout;
+        let mut replies = Vec::new();
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            let msg = responses.recv_timeout(remaining).await?.message()?;
+            let count = match msg {
+                Message::StateZone { count, .. } | Message::StateMultiZone { count, .. } => count,
+                _ => continue,
+            };
+            replies.push(msg);
+            if replies.len() >= range.expected_replies(count as usize) {
+                telemetry::round_trip(sent.elapsed());
+                return Ok(replies);
+            }
+        }
+    }
+
+    /// Asks a device with extended multizone support for the colors of all of its zones
+    ///
+    /// Strips with more than 82 zones reply with several [Message::StateExtendedColorZones] pages,
+    /// which are put back together with [ExtendedZones].  This returns as soon as every zone has
+    /// arrived.
+    ///
+    /// This is sent with [Priority::Refresh].
+    pub async fn get_extended_color_zones(
+        &self,
+        addr: SocketAddr,
+        target: u64,
+    ) -> Result<Vec<HSBK>, Error> {
+        let mut responses = self
+            .send_request(
+                addr,
+                Some(target),
+                Message::GetExtendedColorZone,
+                false,
+                true,
+                Priority::Refresh,
+            )
+            .await?;
+        let sent = tokio::time::Instant::now();
+        let deadline = sent + self.inner.timeout;
+        let mut zones = ExtendedZones::new();
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            zones.add(&responses.recv_timeout(remaining).await?.message()?);
+            if let Some(colors) = zones.colors().filter(|_| zones.is_complete()) {
+                telemetry::round_trip(sent.elapsed());
+                return Ok(colors);
+            }
+        }
+    }
+
+    /// Switches a Night Vision light to infrared only, for security cameras at night
+    ///
+    /// This turns the infrared LEDs up to full, and then the visible light down to nothing
+    /// (keeping its hue, saturation, and temperature), so that the area is never left unlit.
+    /// Fails without changing anything if the device doesn't have
+    /// [infrared](lifx_core::Capabilities::INFRARED).  See also [Client::day_mode].
+    pub async fn night_mode(&self, addr: SocketAddr, target: u64) -> Result<(), Error> {
+        self.set_night_vision(addr, target, true).await
+    }
+
+    /// Switches a Night Vision light back to visible light, undoing [Client::night_mode]
+    ///
+    /// This turns the visible light up to full brightness, and then the infrared LEDs off.
+    pub async fn day_mode(&self, addr: SocketAddr, target: u64) -> Result<(), Error> {
+        self.set_night_vision(addr, target, false).await
+    }
+
+    async fn set_night_vision(
+        &self,
+        addr: SocketAddr,
+        target: u64,
+        night: bool,
+    ) -> Result<(), Error> {
+        let version = self.request(addr, target, Message::GetVersion).await?;
+        let info = ProductInfo::from_state_version(&version);
+        if !info.is_some_and(|info| info.infrared()) {
+            return Err(Error::Protocol(lifx_core::Error::ProtocolError(format!(
+                "{} doesn't have infrared",
+                info.map_or("this device", |info| info.name)
+            ))));
+        }
+        let color = match self.request(addr, target, Message::LightGet).await? {
+            Message::LightState { color, .. } => color,
+            msg => {
+                return Err(Error::Protocol(lifx_core::Error::ProtocolError(format!(
+                    "unexpected reply to LightGet: {:?}",
+                    msg
+                ))))
+            }
+        };
+
+        let (brightness, infrared) = if night { (0, 65535) } else { (65535, 0) };
+        let visible = Message::set_color(
+            HSBK {
+                brightness,
+                ..color
+            },
+            Duration::ZERO,
+        );
+        let infrared = Message::LightSetInfrared {
+            brightness: infrared,
+        };
+        // whichever light is being turned on goes first
+        let order = if night {
+            [infrared, visible]
+        } else {
+            [visible, infrared]
+        };
+        for msg in order {
+            self.send_acked(addr, target, msg).await?;
+        }
+        Ok(())
+    }
+
+    /// Broadcasts a [Message::GetService] to the local network, and collects all the devices that
+    /// reply within the given amount of time.
+    pub async fn discover(&self, wait: Duration) -> Result<Vec<DiscoveredDevice>, Error> {
+        self.discover_on(lifx_core::default_broadcast_addr(), wait)
+            .await
+    }
+
+    /// Like [Client::discover], but sends the [Message::GetService] to a specific address
+    ///
+    /// This can be a subnet-specific broadcast address, or the address of a single device.
+    ///
+    /// A device that advertises port 0 (meaning that its service is temporarily unavailable) is
+    /// asked again every [SERVICE_RETRY], and is only included if it advertises a real port before
+    /// the time is up.
+    pub async fn discover_on(
+        &self,
+        addr: SocketAddr,
+        wait: Duration,
+    ) -> Result<Vec<DiscoveredDevice>, Error> {
+        // every retry uses the same sequence number, so all the replies come back here
+        let mut responses = self
+            .send_request(
+                addr,
+                None,
+                Message::GetService,
+                false,
+                true,
+                Priority::Discovery,
+            )
+            .await?;
+        let deadline = tokio::time::Instant::now() + wait;
+
+        let mut devices: Vec<DiscoveredDevice> = Vec::new();
+        let mut unavailable: HashMap<u64, SocketAddr> = HashMap::new();
+        let mut retry_at = None;
+        loop {
+            let now = tokio::time::Instant::now();
+            if now >= deadline {
+                break;
+            }
+            if retry_at.is_some_and(|at| now >= at) {
+                for &addr in unavailable.values() {
+                    self.resend(&responses, addr, Priority::Discovery).await?;
+                }
+                retry_at = None;
+            }
+            if retry_at.is_none() && !unavailable.is_empty() {
+                retry_at = Some(now + SERVICE_RETRY);
+            }
+            let until = retry_at.map_or(deadline, |at| at.min(deadline));
+            let resp = match responses.recv_timeout(until - now).await {
+                Ok(resp) => resp,
+                Err(Error::Timeout) => continue,
+                Err(e) => return Err(e),
+            };
+            let Ok(msg) = resp.message() else {
+                continue;
+            };
+            match ServiceReply::new(resp.target(), resp.addr, &msg) {
+                ServiceReply::Available(device) => {
+                    unavailable.remove(&device.target);
+                    if !devices.iter().any(|d| d.target == device.target) {
+                        devices.push(device);
+                    }
+                }
+                ServiceReply::Unavailable => {
+                    unavailable.insert(resp.target(), resp.addr);
+                }
+                ServiceReply::Other => (),
+            }
+        }
+        telemetry::devices_online(devices.len());
+        Ok(devices)
+    }
+
+    /// Like [Client::discover], but also asks every device for its label, version, host firmware,
+    /// and Wi-Fi signal strength (see [Client::snapshots])
+    pub async fn discover_snapshots(
+        &self,
+        wait: Duration,
+        concurrency: usize,
+    ) -> Result<Vec<DeviceSnapshot>, Error> {
+        let devices = self.discover(wait).await?;
+        Ok(self.snapshots(&devices, concurrency).await)
+    }
+
+    /// Asks a device for its label, version, host firmware, and Wi-Fi signal strength
+    ///
+    /// All of the questions are sent at once, rather than waiting for each answer before asking
+    /// the next, so this takes one round trip.  Answers that don't arrive before the timeout are
+    /// left out of the snapshot.
+    pub async fn snapshot(&self, device: DiscoveredDevice) -> DeviceSnapshot {
+        let mut snapshot = DeviceSnapshot::new(device);
+        let mut pending = Vec::new();
+        for msg in DeviceSnapshot::QUERIES {
+            let target = Some(device.target);
+            match self
+                .send_request(device.addr, target, msg, false, true, Priority::Discovery)
+                .await
+            {
+                Ok(responses) => pending.push(responses),
+                Err(e) => log::debug!("couldn't query {:016X}: {}", device.target, e),
+            }
+        }
+        let deadline = tokio::time::Instant::now() + self.inner.timeout;
+        for mut responses in pending {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            if let Ok(msg) = responses
+                .recv_timeout(remaining)
+                .await
+                .and_then(|r| r.message().map_err(Error::from))
+            {
+                snapshot.update(&msg);
+            }
+        }
+        snapshot
+    }
+
+    /// Takes a [Client::snapshot] of each device, with up to `concurrency` at a time
+    ///
+    /// The snapshots are in the same order as the devices.
+    pub async fn snapshots(
+        &self,
+        devices: &[DiscoveredDevice],
+        concurrency: usize,
+    ) -> Vec<DeviceSnapshot> {
+        let permits = Arc::new(Semaphore::new(concurrency.max(1)));
+        let tasks: Vec<_> = devices
+            .iter()
+            .map(|&device| {
+                let (client, permits) = (self.clone(), permits.clone());
+                tokio::spawn(async move {
+                    let _permit = permits.acquire().await;
+                    client.snapshot(device).await
+                })
+            })
+            .collect();
+        let mut snapshots = Vec::with_capacity(tasks.len());
+        for task in tasks {
+            match task.await {
+                Ok(snapshot) => snapshots.push(snapshot),
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            }
+        }
+        snapshots
+    }
+}
+
+/// Sends everything that gets put into the queue
+async fn send_loop(transport: Arc<dyn Transport>, queue: Arc<SharedQueue>, recorder: RecorderSlot) {
+    let mut backoff = Backoff::default();
+    loop {
+        let out = queue.pop().await;
+        if out.done.is_closed() {
+            // whoever queued this has given up on it, and released its sequence number
+            continue;
+        }
+        // while the network is down, hold off between attempts instead of failing everything
+        // in the queue as fast as possible
+        if let Some(delay) = backoff.current() {
+            tokio::time::sleep(delay).await;
+        }
+        let res = match send_datagram(&*transport, &out.bytes, out.addr).await {
+            Ok(_) => {
+                backoff.reset();
+                record::record(&recorder, Direction::Sent, out.addr, &out.bytes);
+                Ok(())
+            }
+            Err(e) => {
+                if transport::classify(&e) == ErrorClass::NetworkDown {
+                    let delay = backoff.next_delay();
+                    log::warn!("network is down ({}); backing off for {:?}", e, delay);
+                }
+                Err(transport::send_error(e, out.addr))
+            }
+        };
+        let _ = out.done.send(res);
+    }
+}
+
+/// Sends a datagram, retrying a few times if it fails with a transient error
+async fn send_datagram(transport: &dyn Transport, buf: &[u8], addr: SocketAddr) -> io::Result<()> {
+    let mut delay = transport::SEND_RETRY_DELAY;
+    for _ in 0..transport::SEND_RETRIES {
+        match transport.send_to(buf, addr).await {
+            Err(e) if transport::classify(&e) == ErrorClass::Transient => {
+                tokio::time::sleep(delay).await;
+                delay *= 2;
+            }
+            res => return res.map(|_| ()),
+        }
+    }
+    transport.send_to(buf, addr).await.map(|_| ())
+}
+
+/// Reads every datagram that arrives on the transport, and routes replies to the request they belong to
+async fn recv_loop(
+    transport: Arc<dyn Transport>,
+    mut dedup: DedupFilter,
+    requests: SharedRequests,
+    recorder: RecorderSlot,
+    filter: FilterSlot,
+    unsolicited: broadcast::Sender<Unsolicited>,
+) {
+    let mut buf = vec![0; RECV_BUFFER_SIZE];
+    let mut backoff = Backoff::default();
+    loop {
+        let (nbytes, addr) = match transport.recv_from(&mut buf).await {
+            Ok(x) => {
+                backoff.reset();
+                x
+            }
+            Err(e) => match transport::classify(&e) {
+                // Some platforms report ICMP errors from previous sends here; these don't affect
+                // the socket itself, so just keep going.
+                ErrorClass::Transient | ErrorClass::Unreachable => continue,
+                // this can't be a valid message, so it's treated like one that can't be decoded
+                ErrorClass::Truncated => {
+                    log::debug!("{}", e);
+                    telemetry::decode_error();
+                    continue;
+                }
+                // but anything else is likely to fail again straight away, so don't spin on it
+                ErrorClass::NetworkDown | ErrorClass::Fatal => {
+                    let delay = backoff.next_delay();
+                    log::warn!("failed to receive ({}); backing off for {:?}", e, delay);
+                    tokio::time::sleep(delay).await;
+                    continue;
+                }
+            },
+        };
+        record::record(&recorder, Direction::Received, addr, &buf[..nbytes]);
+        let raw = match RawMessage::unpack(&buf[..nbytes]) {
+            Ok(raw) => raw,
+            Err(_) => {
+                telemetry::decode_error();
+                continue;
+            }
+        };
+        if !filter::check(&filter, raw.protocol_header.typ) {
+            continue;
+        }
+        // nobody is listening most of the time, so don't bother decoding these
+        if raw.is_unsolicited() && unsolicited.receiver_count() > 0 {
+            telemetry::message_received(raw.protocol_header.typ);
+            if let Some(msg) = Unsolicited::from_raw(&raw, addr) {
+                let _ = unsolicited.send(msg);
+            }
+            continue;
+        }
+        let mut requests = requests.lock().unwrap();
+        let current = requests.connection.source().get();
+        if raw.frame.source() != current {
+            continue;
+        }
+        telemetry::message_received(raw.protocol_header.typ);
+        if dedup.is_duplicate(&raw) {
+            continue;
+        }
+
+        let (target, sequence) = (raw.frame_addr.target, raw.frame_addr.sequence);
+        if let Some(id) = requests.connection.handle_raw(addr, raw) {
+            if let Some(notify) = requests.waiting.get(&id) {
+                notify.notify_one();
+            }
+        } else if requests.collisions.unmatched(target, sequence) {
+            let new = lifx_core::source::generate();
+            requests.connection.set_source(new);
+            telemetry::source_collision();
+            log::warn!(
+                "getting replies to requests we never sent with source ID {:08x}; another \
+                 controller may be using it, so switching to {:08x}",
+                current,
+                new.get()
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use lifx_core::zones::extended_zone_pages;
+    use lifx_core::{ApplicationRequest, BuildOptions, LifxIdent, LifxString};
+    use std::ffi::CString;
+    use tokio::sync::mpsc;
+
+    /// The number of zones that [fake_bulb] pretends to have
+    pub(crate) const FAKE_ZONES: u8 = 20;
+
+    /// Ways for a [fake_bulb_with] to misbehave, to exercise a client's retries and dedup
+    #[derive(Debug, Clone, Copy, Default)]
+    pub(crate) struct Faults {
+        /// Ignores this percentage of the datagrams that arrive, spread out evenly (so with 50,
+        /// every second one), which keeps tests repeatable
+        pub(crate) drop_percent: u32,
+        /// Ignores this many datagrams before anything else
+        pub(crate) drop_first: usize,
+        /// Acknowledges this many commands (anything that changes the bulb) before obeying any
+        pub(crate) ignore_commands: usize,
+        /// Waits this long before replying
+        pub(crate) delay: Duration,
+        /// Sends every reply apart from acks twice
+        pub(crate) duplicate: bool,
+        /// Answers everything apart from GetService with a StateUnhandled, like a device that
+        /// doesn't support the message
+        pub(crate) unhandled: bool,
+        /// Flips the bits of the first payload byte of every reply
+        pub(crate) garble: bool,
+    }
+
+    impl Faults {
+        /// Whether to drop the `nth` datagram received (counting from 1)
+        fn drops(&self, nth: u32) -> bool {
+            nth as usize <= self.drop_first
+                || nth * self.drop_percent / 100 > (nth - 1) * self.drop_percent / 100
+        }
+    }
+
+    /// A StateUnhandled (type 223), which lifx-core doesn't support yet
+    struct StateUnhandled {
+        unhandled_type: u16,
+    }
+
+    impl lifx_core::Payload for StateUnhandled {
+        fn type_num(&self) -> u16 {
+            223
+        }
+
+        fn pack(&self, buf: &mut Vec<u8>) -> Result<(), lifx_core::Error> {
+            buf.extend_from_slice(&self.unhandled_type.to_le_bytes());
+            Ok(())
+        }
+
+        fn unpack(raw: &RawMessage) -> Result<Self, lifx_core::Error> {
+            match *raw.payload {
+                [lo, hi] => Ok(StateUnhandled {
+                    unhandled_type: u16::from_le_bytes([lo, hi]),
+                }),
+                _ => Err(lifx_core::Error::ProtocolError(
+                    "StateUnhandled should have a 2 byte payload".to_owned(),
+                )),
+            }
+        }
+    }
+
+    /// Spawns a very simple fake Night Vision bulb, which replies to GetService, GetLabel,
+    /// GetVersion, GetHostFirmware, GetWifiInfo, GetPower, GetGroup, GetLocation, LightGet,
+    /// LightGetInfrared, RelayGetPower, GetColorZones, and GetExtendedColorZone, obeys SetPower,
+    /// LightSetColor, LightSetInfrared, and RelaySetPower, and acknowledges anything that asks
+    /// for it
+    pub(crate) async fn fake_bulb(target: u64, label: &str) -> SocketAddr {
+        fake_bulb_with(target, label, Faults::default()).await
+    }
+
+    /// Like [fake_bulb], but misbehaving in the given ways
+    pub(crate) async fn fake_bulb_with(target: u64, label: &str, faults: Faults) -> SocketAddr {
+        let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = sock.local_addr().unwrap();
+        spawn_fake_bulb(sock, target, label, faults);
+        addr
+    }
+
+    /// Runs a [fake_bulb_with] on any transport
+    ///
+    /// Every message that arrives is also sent to the returned channel, including the ones that
+    /// are dropped.
+    pub(crate) fn spawn_fake_bulb(
+        sock: impl Transport,
+        target: u64,
+        label: &str,
+        faults: Faults,
+    ) -> mpsc::UnboundedReceiver<RawMessage> {
+        let addr = sock.local_addr().unwrap();
+        let label = LifxString::new(&CString::new(label).unwrap());
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut buf = [0; RECV_BUFFER_SIZE];
+            let mut color = lifx_core::HSBK {
+                hue: 120,
+                saturation: 65535,
+                brightness: 65535,
+                kelvin: 3500,
+            };
+            let mut infrared = 0;
+            let mut power = 65535;
+            let mut relay = 0;
+            let mut commands = 0;
+            for received in 1.. {
+                let (n, from) = sock.recv_from(&mut buf).await.unwrap();
+                let raw = RawMessage::unpack(&buf[..n]).unwrap();
+                let _ = tx.send(raw.clone());
+                if faults.drops(received) {
+                    continue;
+                }
+                let msg = Message::from_raw(&raw).unwrap();
+                let obey = match msg {
+                    Message::SetPower { .. }
+                    | Message::LightSetColor { .. }
+                    | Message::LightSetInfrared { .. }
+                    | Message::RelaySetPower { .. } => {
+                        commands += 1;
+                        commands > faults.ignore_commands
+                    }
+                    _ => true,
+                };
+                let states = match msg {
+                    Message::GetVersion => vec![Message::StateVersion {
+                        vendor: 1,
+                        product: 29,
+                        reserved: 0,
+                    }],
+                    Message::GetHostFirmware => vec![Message::StateHostFirmware {
+                        build: 0,
+                        reserved: 0,
+                        version_minor: 70,
+                        version_major: 3,
+                    }],
+                    Message::GetPower => vec![Message::StatePower { level: power }],
+                    Message::SetPower { level } => {
+                        if obey {
+                            power = level as u16;
+                        }
+                        vec![]
+                    }
+                    Message::LightGet => vec![Message::LightState {
+                        color,
+                        reserved: 0,
+                        power,
+                        label: label.clone(),
+                        reserved2: 0,
+                    }],
+                    Message::LightGetInfrared => {
+                        vec![Message::LightStateInfrared {
+                            brightness: infrared,
+                        }]
+                    }
+                    Message::LightSetColor { color: new, .. } => {
+                        if obey {
+                            color = new;
+                        }
+                        vec![]
+                    }
+                    Message::LightSetInfrared { brightness } => {
+                        if obey {
+                            infrared = brightness;
+                        }
+                        vec![]
+                    }
+                    Message::RelayGetPower { relay_index } => vec![Message::RelayStatePower {
+                        relay_index,
+                        level: relay,
+                    }],
+                    Message::RelaySetPower { level, .. } => {
+                        if obey {
+                            relay = level;
+                        }
+                        vec![]
+                    }
+                    Message::GetService => vec![Message::StateService {
+                        service: Service::UDP,
+                        port: addr.port() as u32,
+                    }],
+                    Message::GetLabel => vec![Message::StateLabel {
+                        label: label.clone(),
+                    }],
+                    Message::GetGroup => vec![Message::StateGroup {
+                        group: LifxIdent([1; 16]),
+                        label: label.clone(),
+                        updated_at: 0,
+                    }],
+                    Message::GetLocation => vec![Message::StateLocation {
+                        location: LifxIdent([2; 16]),
+                        label: label.clone(),
+                        updated_at: 0,
+                    }],
+                    Message::GetWifiInfo => vec![Message::StateWifiInfo {
+                        signal: -52.0,
+                        reserved6: 0,
+                        reserved7: 0,
+                        reserved: 0,
+                    }],
+                    Message::GetColorZones {
+                        start_index,
+                        end_index,
+                    } => {
+                        let color = |index: u8| lifx_core::HSBK {
+                            hue: index as u16,
+                            saturation: 0,
+                            brightness: 65535,
+                            kelvin: 3500,
+                        };
+                        let end = end_index.min(FAKE_ZONES - 1);
+                        (start_index..=end)
+                            .step_by(8)
+                            .map(|index| Message::StateMultiZone {
+                                count: FAKE_ZONES,
+                                index,
+                                color0: color(index),
+                                color1: color(index + 1),
+                                color2: color(index + 2),
+                                color3: color(index + 3),
+                                color4: color(index + 4),
+                                color5: color(index + 5),
+                                color6: color(index + 6),
+                                color7: color(index + 7),
+                            })
+                            .collect()
+                    }
+                    Message::GetExtendedColorZone => {
+                        let colors: Vec<_> = (0..FAKE_ZONES)
+                            .map(|index| lifx_core::HSBK {
+                                hue: index as u16,
+                                saturation: 0,
+                                brightness: 65535,
+                                kelvin: 3500,
+                            })
+                            .collect();
+                        let pages =
+                            extended_zone_pages(&colors, Duration::ZERO, ApplicationRequest::Apply)
+                                .unwrap();
+                        pages
+                            .into_iter()
+                            .filter_map(|page| match page {
+                                Message::SetExtendedColorZones {
+                                    zone_index,
+                                    colors_count,
+                                    colors,
+                                    ..
+                                } => Some(Message::StateExtendedColorZones {
+                                    zones_count: FAKE_ZONES as u16,
+                                    zone_index,
+                                    colors_count,
+                                    colors,
+                                }),
+                                _ => None,
+                            })
+                            .collect()
+                    }
+                    _ => vec![],
+                };
+                let opts = BuildOptions {
+                    target: Some(target),
+                    ..raw.reply_options()
+                };
+                let mut replies = Vec::new();
+                if raw.wants_ack() {
+                    let ack = Message::Acknowledgement {
+                        seq: raw.frame_addr.sequence,
+                    };
+                    replies.push(RawMessage::build(&opts, ack).unwrap());
+                }
+                let states: Vec<_> = if faults.unhandled && raw.protocol_header.typ != 2 {
+                    let unhandled = StateUnhandled {
+                        unhandled_type: raw.protocol_header.typ,
+                    };
+                    vec![RawMessage::build_payload(&opts, &unhandled).unwrap()]
+                } else {
+                    let build = |state| RawMessage::build(&opts, state).unwrap();
+                    states.into_iter().map(build).collect()
+                };
+                for state in states {
+                    if faults.duplicate {
+                        replies.push(state.clone());
+                    }
+                    replies.push(state);
+                }
+                if !faults.delay.is_zero() {
+                    tokio::time::sleep(faults.delay).await;
+                }
+                for mut reply in replies {
+                    if let Some(byte) = reply.payload.first_mut().filter(|_| faults.garble) {
+                        *byte ^= 0xff;
+                    }
+                    sock.send_to(&reply.pack().unwrap(), from).await.unwrap();
+                }
+            }
+        });
+        rx
+    }
+
+    pub(crate) fn localhost_options() -> ClientOptions {
+        ClientOptions {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_unsolicited() {
+        use crate::unsolicited::tests::from_device;
+
+        let client = Client::with_options(localhost_options()).await.unwrap();
+        let addr = client.local_addr().unwrap();
+        let mut unsolicited = client.unsolicited();
+        let device = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let power = Message::StatePower { level: 65535 };
+        device
+            .send_to(&from_device(0, power.clone()), addr)
+            .await
+            .unwrap();
+        let received = unsolicited.recv().await.unwrap();
+        assert_eq!((received.target, received.message), (0x1234, power));
+
+        // replies still go to their requests
+        let bulb = fake_bulb(0x5678, "Desk").await;
+        client
+            .request(bulb, 0x5678, Message::GetPower)
+            .await
+            .unwrap();
+        assert!(unsolicited.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_faults() {
+        let options = ClientOptions {
+            timeout: Duration::from_millis(100),
+            ..localhost_options()
+        };
+        let client = Client::with_options(options).await.unwrap();
+        let faulty = |faults| fake_bulb_with(1, "Faulty", faults);
+
+        // every second request is lost, so it takes a retry
+        let addr = faulty(Faults {
+            drop_percent: 50,
+            ..Default::default()
+        })
+        .await;
+        client.send_acked(addr, 1, Message::GetPower).await.unwrap();
+        let res = client.send_acked(addr, 1, Message::GetPower).await;
+        assert!(matches!(res, Err(Error::Timeout)));
+        let sender = crate::ReliableSender::new(client.clone(), 2);
+        for _ in 0..4 {
+            sender.send_acked(addr, 1, Message::GetPower).await.unwrap();
+        }
+
+        let addr = faulty(Faults {
+            delay: Duration::from_millis(200),
+            ..Default::default()
+        })
+        .await;
+        let res = client.request(addr, 1, Message::GetPower).await;
+        assert!(matches!(res, Err(Error::Timeout)));
+
+        // the second copy of the reply is filtered out
+        let addr = faulty(Faults {
+            duplicate: true,
+            ..Default::default()
+        })
+        .await;
+        let mut responses = client
+            .send_request(
+                addr,
+                Some(1),
+                Message::GetPower,
+                false,
+                true,
+                Priority::User,
+            )
+            .await
+            .unwrap();
+        let timeout = Duration::from_millis(100);
+        assert!(responses.recv_timeout(timeout).await.is_ok());
+        let res = responses.recv_timeout(timeout).await;
+        assert!(matches!(res, Err(Error::Timeout)));
+
+        let addr = faulty(Faults {
+            unhandled: true,
+            ..Default::default()
+        })
+        .await;
+        let res = client.request(addr, 1, Message::GetPower).await;
+        assert!(matches!(
+            res,
+            Err(Error::Protocol(lifx_core::Error::UnknownMessageType(223)))
+        ));
+
+        // a garbled StateService has an invalid service, so the device isn't found
+        let addr = faulty(Faults {
+            garble: true,
+            ..Default::default()
+        })
+        .await;
+        let devices = client.discover_on(addr, timeout).await.unwrap();
+        assert!(devices.is_empty());
+        let power = client.request(addr, 1, Message::GetPower).await.unwrap();
+        assert_eq!(power, Message::StatePower { level: 0xff00 });
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests() {
+        let addr = fake_bulb(0x1234, "Kitchen").await;
+        let client = Client::with_options(localhost_options()).await.unwrap();
+
+        let tasks: Vec<_> = (0..50)
+            .map(|_| {
+                let client = client.clone();
+                tokio::spawn(async move { client.request(addr, 0x1234, Message::GetLabel).await })
+            })
+            .collect();
+        for task in tasks {
+            match task.await.unwrap().unwrap() {
+                Message::StateLabel { label } => assert_eq!(label.to_string(), "Kitchen"),
+                msg => panic!("Unexpected reply {:?}", msg),
+            }
+        }
+        let requests = client.inner.requests.lock().unwrap();
+        assert!(requests.connection.is_empty());
+        assert!(requests.waiting.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_discover() {
+        let addr = fake_bulb(0x5678, "Office").await;
+        let client = Client::with_options(localhost_options()).await.unwrap();
+
+        let devices = client
+            .discover_on(addr, Duration::from_millis(200))
+            .await
+            .unwrap();
+        assert_eq!(
+            devices,
+            vec![DiscoveredDevice {
+                target: 0x5678,
+                addr
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_snapshots() {
+        let office = fake_bulb(0x5678, "Office").await;
+        let desk = fake_bulb(0x1234, "Desk").await;
+        // nothing answers here
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let options = ClientOptions {
+            timeout: Duration::from_millis(200),
+            ..localhost_options()
+        };
+        let client = Client::with_options(options).await.unwrap();
+
+        let devices = [
+            DiscoveredDevice {
+                target: 0x5678,
+                addr: office,
+            },
+            DiscoveredDevice {
+                target: 0x9999,
+                addr: silent.local_addr().unwrap(),
+            },
+            DiscoveredDevice {
+                target: 0x1234,
+                addr: desk,
+            },
+        ];
+        let snapshots = client.snapshots(&devices, 2).await;
+        let labels: Vec<_> = snapshots.iter().map(|s| s.label.as_deref()).collect();
+        assert_eq!(labels, vec![Some("Office"), None, Some("Desk")]);
+
+        let office = &snapshots[0];
+        assert_eq!(office.device, devices[0]);
+        assert_eq!(office.version, Some((1, 29)));
+        assert_eq!(office.product_info().unwrap().name, "LIFX A19 Night Vision");
+        assert_eq!(office.firmware.unwrap().to_string(), "3.70");
+        assert_eq!(office.signal, Some(-52));
+        assert_eq!(snapshots[1], DeviceSnapshot::new(devices[1]));
+    }
+
+    #[tokio::test]
+    async fn test_discover_unavailable() {
+        // a device whose service is unavailable at first, and then comes up on another port
+        let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = sock.local_addr().unwrap();
+        let service = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let service_addr = service.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0; 1024];
+            for asked in 0.. {
+                let (n, from) = sock.recv_from(&mut buf).await.unwrap();
+                let raw = RawMessage::unpack(&buf[..n]).unwrap();
+                let port = if asked < 2 { 0 } else { service_addr.port() };
+                let opts = BuildOptions {
+                    target: Some(0x5678),
+                    ..raw.reply_options()
+                };
+                let reply = Message::StateService {
+                    service: Service::UDP,
+                    port: port as u32,
+                };
+                let reply = RawMessage::build(&opts, reply).unwrap();
+                sock.send_to(&reply.pack().unwrap(), from).await.unwrap();
+            }
+        });
+        let client = Client::with_options(localhost_options()).await.unwrap();
+
+        // not long enough to ask again
+        let devices = client.discover_on(addr, SERVICE_RETRY / 2).await.unwrap();
+        assert!(devices.is_empty());
+        // unavailable the first time, and then asked again
+        let devices = client.discover_on(addr, SERVICE_RETRY * 2).await.unwrap();
+        assert_eq!(
+            devices,
+            vec![DiscoveredDevice {
+                target: 0x5678,
+                addr: service_addr
+            }]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_color_zones() {
+        let addr = fake_bulb(0x1234, "Strip").await;
+        // a long timeout, to make sure we're not just waiting for it
+        let client = Client::with_options(ClientOptions {
+            timeout: Duration::from_secs(30),
+            ..localhost_options()
+        })
+        .await
+        .unwrap();
+
+        let replies = tokio::time::timeout(
+            Duration::from_secs(5),
+            client.get_color_zones(addr, 0x1234, ZoneRange::ALL),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(replies.len(), 3);
+
+        let mut state = crate::DeviceState::new(0x1234);
+        for reply in &replies {
+            state.update(reply);
+        }
+        let zones = state.zones.unwrap();
+        assert_eq!(zones.len(), FAKE_ZONES as usize);
+        assert!(zones.iter().all(Option::is_some));
+    }
+
+    #[tokio::test]
+    async fn test_get_extended_color_zones() {
+        let addr = fake_bulb(0x1234, "Strip").await;
+        let client = Client::with_options(ClientOptions {
+            timeout: Duration::from_secs(30),
+            ..localhost_options()
+        })
+        .await
+        .unwrap();
+
+        let colors = tokio::time::timeout(
+            Duration::from_secs(5),
+            client.get_extended_color_zones(addr, 0x1234),
+        )
+        .await
+        .unwrap()
+        .unwrap();
+        assert_eq!(colors.len(), FAKE_ZONES as usize);
+        assert_eq!(colors[7].hue, 7);
+    }
+
+    #[tokio::test]
+    async fn test_night_mode() {
+        let addr = fake_bulb(0x1234, "Porch").await;
+        let client = Client::with_options(localhost_options()).await.unwrap();
+        let state = || async {
+            let color = match client.request(addr, 0x1234, Message::LightGet).await {
+                Ok(Message::LightState { color, .. }) => color,
+                reply => panic!("Unexpected reply {:?}", reply),
+            };
+            match client
+                .request(addr, 0x1234, Message::LightGetInfrared)
+                .await
+            {
+                Ok(Message::LightStateInfrared { brightness }) => (color, brightness),
+                reply => panic!("Unexpected reply {:?}", reply),
+            }
+        };
+
+        client.night_mode(addr, 0x1234).await.unwrap();
+        let (color, infrared) = state().await;
+        assert_eq!((color.hue, color.brightness, infrared), (120, 0, 65535));
+
+        client.day_mode(addr, 0x1234).await.unwrap();
+        let (color, infrared) = state().await;
+        assert_eq!((color.hue, color.brightness, infrared), (120, 65535, 0));
+    }
+
+    #[tokio::test]
+    async fn test_duplicate_replies() {
+        let bulb = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::with_options(localhost_options()).await.unwrap();
+
+        let mut responses = client
+            .send_request(
+                bulb.local_addr().unwrap(),
+                Some(1),
+                Message::GetPower,
+                false,
+                true,
+                Priority::User,
+            )
+            .await
+            .unwrap();
+        let mut buf = [0; 1024];
+        let (n, from) = bulb.recv_from(&mut buf).await.unwrap();
+        let raw = RawMessage::unpack(&buf[..n]).unwrap();
+        let opts = BuildOptions {
+            target: Some(1),
+            ..raw.reply_options()
+        };
+        let reply = RawMessage::build(&opts, Message::StatePower { level: 0 }).unwrap();
+        for _ in 0..2 {
+            bulb.send_to(&reply.pack().unwrap(), from).await.unwrap();
+        }
+
+        let timeout = Duration::from_millis(100);
+        assert!(responses.recv_timeout(timeout).await.is_ok());
+        assert!(matches!(
+            responses.recv_timeout(timeout).await,
+            Err(Error::Timeout)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_timeout() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::with_options(ClientOptions {
+            timeout: Duration::from_millis(50),
+            ..localhost_options()
+        })
+        .await
+        .unwrap();
+
+        let res = client
+            .request(silent.local_addr().unwrap(), 1, Message::GetLabel)
+            .await;
+        assert!(matches!(res, Err(Error::Timeout)));
+        let requests = client.inner.requests.lock().unwrap();
+        assert!(requests.connection.is_empty());
+        assert!(requests.waiting.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_cancel() {
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = silent.local_addr().unwrap();
+        let client = Client::with_options(localhost_options()).await.unwrap();
+
+        // given up on while it's still in the send queue (after being polled once), so it's never
+        // sent
+        tokio::select! {
+            biased;
+            _ = client.send(addr, Some(1), Message::GetLabel) => panic!("sent"),
+            _ = std::future::ready(()) => {}
+        }
+        // given up on while waiting for the reply
+        let res = tokio::time::timeout(
+            Duration::from_millis(20),
+            client.request(addr, 1, Message::GetPower),
+        )
+        .await;
+        assert!(res.is_err());
+        {
+            let requests = client.inner.requests.lock().unwrap();
+            assert!(requests.connection.is_empty());
+            assert!(requests.waiting.is_empty());
+        }
+
+        let mut buf = [0; 128];
+        let (n, _) = silent.recv_from(&mut buf).await.unwrap();
+        let raw = RawMessage::unpack(&buf[..n]).unwrap();
+        assert_eq!(Message::from_raw(&raw).unwrap(), Message::GetPower);
+    }
+
+    #[tokio::test]
+    async fn test_source_collision() {
+        let client = Client::with_options(ClientOptions {
+            collision_threshold: 3,
+            ..localhost_options()
+        })
+        .await
+        .unwrap();
+        let original = client.source();
+
+        // another controller with the same source, whose replies are coming to us
+        let other = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        for sequence in 100..103 {
+            let opts = BuildOptions {
+                target: Some(1),
+                source: original,
+                sequence,
+                ..Default::default()
+            };
+            let reply = RawMessage::build(&opts, Message::StatePower { level: 0 }).unwrap();
+            let to = client.local_addr().unwrap();
+            other.send_to(&reply.pack().unwrap(), to).await.unwrap();
+        }
+
+        for _ in 0..100 {
+            if client.source() != original {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("source wasn't changed");
+    }
+}
@@ -0,0 +1,72 @@
+//! Where time-dependent components get the current time from
+//!
+//! Components that make decisions based on elapsed time, such as [DedupFilter](crate::DedupFilter),
+//! ask a [Clock] for the time instead of calling [Instant::now] themselves.  In normal use that's
+//! the [SystemClock], and tests can swap in a [MockClock], which only moves when it's told to.
+//!
+//! Async components (timeouts and retransmits in [Client](crate::Client) and
+//! [ReliableSender](crate::ReliableSender)) use tokio's timers instead, which can be controlled in
+//! tests with [tokio::time::pause] and [tokio::time::advance].
+//!
+//! ```
+//! use lifx::clock::{Clock, MockClock};
+//! use std::time::Duration;
+//!
+//! let clock = MockClock::new();
+//! let start = clock.now();
+//! clock.advance(Duration::from_secs(5));
+//! assert_eq!(clock.now() - start, Duration::from_secs(5));
+//! ```
+
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A source of the current time
+pub trait Clock: Debug + Send + Sync + 'static {
+    fn now(&self) -> Instant;
+}
+
+/// The real time, from [Instant::now]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that stands still until it's advanced, for tests
+///
+/// This is cheap to clone, and all clones show the same time.
+#[derive(Debug, Clone)]
+pub struct MockClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl MockClock {
+    /// Creates a clock, stopped at the current time
+    pub fn new() -> MockClock {
+        MockClock {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Moves the clock forward
+    pub fn advance(&self, by: Duration) {
+        *self.now.lock().unwrap() += by;
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> Self {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+}
@@ -0,0 +1,135 @@
+//! Noticing when another controller is using the same source ID
+//!
+//! Devices send replies to whoever asked, tagged with the source ID from the request.  If another
+//! app on the network happens to use the same source ID as us, and is listening on the same
+//! address (for example because both broadcast from port 56700), its replies look like ours.
+//! Most of them will be for sequence numbers that we never used, which is what a
+//! [CollisionDetector] watches for.
+//!
+//! A late reply to a request that has already timed out looks the same, so one stray reply isn't
+//! enough: the detector only reports a collision once several arrive within a short window.
+//! [Client](crate::Client) uses this to switch to a new random source ID when it sees a collision
+//! (see [ClientOptions::collision_threshold](crate::ClientOptions::collision_threshold)).
+
+use crate::clock::{Clock, SystemClock};
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How long a sequence number counts as "ours" after it's used, and how long foreign replies are
+/// counted for
+pub const COLLISION_WINDOW: Duration = Duration::from_secs(10);
+
+/// Watches for replies to requests that we never sent
+#[derive(Debug)]
+pub struct CollisionDetector {
+    threshold: usize,
+    /// When each (target, sequence) was last used
+    sent: HashMap<(u64, u8), Instant>,
+    /// When each recent foreign reply arrived
+    foreign: VecDeque<Instant>,
+    clock: Arc<dyn Clock>,
+}
+
+impl CollisionDetector {
+    /// Creates a detector that reports a collision after `threshold` foreign replies within
+    /// [COLLISION_WINDOW]
+    ///
+    /// A threshold of 0 disables detection.
+    pub fn new(threshold: usize) -> CollisionDetector {
+        CollisionDetector::with_clock(threshold, SystemClock)
+    }
+
+    /// Like [CollisionDetector::new], but timing replies with the given clock
+    pub fn with_clock(threshold: usize, clock: impl Clock) -> CollisionDetector {
+        CollisionDetector {
+            threshold,
+            sent: HashMap::new(),
+            foreign: VecDeque::new(),
+            clock: Arc::new(clock),
+        }
+    }
+
+    /// Records that we sent a message with this sequence number
+    ///
+    /// Use a target of `0` for broadcasts.
+    pub fn sent(&mut self, target: u64, sequence: u8) {
+        if self.threshold == 0 {
+            return;
+        }
+        let now = self.clock.now();
+        self.sent
+            .retain(|_, at| now.duration_since(*at) < COLLISION_WINDOW);
+        self.sent.insert((target, sequence), now);
+    }
+
+    /// Checks a reply with our source ID that didn't match any outstanding request
+    ///
+    /// Returns `true` if this makes enough foreign replies to report a collision.  The count then
+    /// starts over, along with the record of what was sent, since that was for the old source ID.
+    pub fn unmatched(&mut self, target: u64, sequence: u8) -> bool {
+        if self.threshold == 0 {
+            return false;
+        }
+        let now = self.clock.now();
+        let recent = |at: &Instant| now.duration_since(*at) < COLLISION_WINDOW;
+        let ours = [(target, sequence), (0, sequence)]
+            .iter()
+            .any(|key| self.sent.get(key).is_some_and(recent));
+        if ours {
+            return false;
+        }
+
+        while self.foreign.front().is_some_and(|at| !recent(at)) {
+            self.foreign.pop_front();
+        }
+        self.foreign.push_back(now);
+        if self.foreign.len() < self.threshold {
+            return false;
+        }
+        self.foreign.clear();
+        self.sent.clear();
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    #[test]
+    fn test_detect() {
+        let clock = MockClock::new();
+        let mut detector = CollisionDetector::with_clock(3, clock.clone());
+        detector.sent(1, 5);
+        detector.sent(0, 9);
+
+        // late replies to things we sent aren't suspicious
+        assert!(!detector.unmatched(1, 5));
+        assert!(!detector.unmatched(2, 9));
+        for _ in 0..5 {
+            assert!(!detector.unmatched(1, 5));
+        }
+
+        // but replies to things we didn't send are
+        assert!(!detector.unmatched(1, 6));
+        assert!(!detector.unmatched(1, 7));
+        // unless they're spread out
+        clock.advance(COLLISION_WINDOW);
+        assert!(!detector.unmatched(1, 8));
+        assert!(!detector.unmatched(1, 8));
+        assert!(detector.unmatched(1, 8));
+
+        // and then it all starts over
+        assert!(!detector.unmatched(1, 8));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut detector = CollisionDetector::new(0);
+        for sequence in 0..10 {
+            assert!(!detector.unmatched(1, sequence));
+        }
+    }
+}
@@ -0,0 +1,288 @@
+//! Sending messages that have to arrive in order
+//!
+//! Messages sent one after another over UDP can arrive in a different order, and some changes
+//! only work in the right order: [Message::SetColorZones] with
+//! [ApplicationRequest::NoApply] has to reach the device before the message that applies it, and
+//! a light that's turned on before its color is set will flash its old color first.
+//!
+//! A [CommandSequence] is a list of steps for one device.  Every message in a step is sent at
+//! once, and every one has to be acknowledged before the next step is sent.  If anything in a step
+//! isn't acknowledged in time, nothing after it is sent, and [Error::StepFailed] says which step
+//! that was.
+//!
+//! ```no_run
+//! # async fn example(client: lifx::Client, addr: std::net::SocketAddr) -> Result<(), lifx::Error> {
+//! use lifx::command::CommandSequence;
+//! use lifx_core::{Message, PowerLevel, HSBK};
+//! use std::time::Duration;
+//!
+//! let red = HSBK::new(0.0, 100.0, 100.0, 3500);
+//! CommandSequence::new()
+//!     .then(Message::set_color(red, Duration::ZERO))
+//!     .then(Message::SetPower { level: PowerLevel::Enabled })
+//!     .send(&client, addr, 0xd073d5001337)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::Client;
+use crate::queue::Priority;
+use crate::Error;
+use lifx_core::{ApplicationRequest, Message};
+use std::net::SocketAddr;
+
+/// Whether a message changes zones without showing the change yet
+fn is_no_apply(msg: &Message) -> bool {
+    matches!(
+        msg,
+        Message::SetColorZones {
+            apply: ApplicationRequest::NoApply,
+            ..
+        } | Message::SetExtendedColorZones {
+            apply: ApplicationRequest::NoApply,
+            ..
+        }
+    )
+}
+
+/// Steps of messages for one device, each acknowledged before the next is sent
+#[derive(Debug, Clone, Default)]
+pub struct CommandSequence {
+    steps: Vec<Vec<Message>>,
+}
+
+impl CommandSequence {
+    pub fn new() -> CommandSequence {
+        CommandSequence::default()
+    }
+
+    /// Adds a step, which is sent once everything before it has been acknowledged
+    pub fn then(mut self, msg: Message) -> CommandSequence {
+        self.steps.push(vec![msg]);
+        self
+    }
+
+    /// Adds a message to the last step, to be sent along with the rest of it, in no particular
+    /// order
+    ///
+    /// If there are no steps yet, this starts the first one.
+    pub fn and(mut self, msg: Message) -> CommandSequence {
+        match self.steps.last_mut() {
+            Some(step) => step.push(msg),
+            None => self.steps.push(vec![msg]),
+        }
+        self
+    }
+
+    /// The steps for a set of zone changes, like those from
+    /// [plan_color_zones](lifx_core::zones::plan_color_zones) or
+    /// [extended_zone_pages](lifx_core::zones::extended_zone_pages)
+    ///
+    /// Messages with [ApplicationRequest::NoApply] that come one after another are sent together
+    /// in one step, since it doesn't matter which order they arrive in.  Every other message gets
+    /// a step of its own, so a message that applies the changes is only sent once they have all
+    /// arrived.
+    pub fn zones(messages: impl IntoIterator<Item = Message>) -> CommandSequence {
+        let mut sequence = CommandSequence::new();
+        for msg in messages {
+            let batch = is_no_apply(&msg)
+                && sequence
+                    .steps
+                    .last()
+                    .is_some_and(|step| step.iter().all(is_no_apply));
+            sequence = match batch {
+                true => sequence.and(msg),
+                false => sequence.then(msg),
+            };
+        }
+        sequence
+    }
+
+    pub fn steps(&self) -> &[Vec<Message>] {
+        &self.steps
+    }
+
+    /// The number of messages in all of the steps
+    pub fn len(&self) -> usize {
+        self.steps.iter().map(Vec::len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Sends each step to a device in turn, waiting for everything in a step to be acknowledged
+    /// before sending the next one
+    ///
+    /// Each step waits up to the client's [timeout](Client::timeout) for its acknowledgements.
+    /// When a step fails, the steps after it aren't sent, and this returns [Error::StepFailed]
+    /// with the index of the step (counting from 0).  The steps before it have already taken
+    /// effect, and some of the failed step may have too.
+    ///
+    /// Messages are sent with [Priority::User].
+    pub async fn send(&self, client: &Client, addr: SocketAddr, target: u64) -> Result<(), Error> {
+        for (index, step) in self.steps.iter().enumerate() {
+            send_step(client, addr, target, step)
+                .await
+                .map_err(|e| Error::StepFailed {
+                    step: index,
+                    source: Box::new(e),
+                })?;
+        }
+        Ok(())
+    }
+}
+
+impl Extend<Message> for CommandSequence {
+    /// Adds each message as a step of its own
+    fn extend<I: IntoIterator<Item = Message>>(&mut self, messages: I) {
+        self.steps.extend(messages.into_iter().map(|msg| vec![msg]));
+    }
+}
+
+/// Sends every message in a step, and then waits for all of the acknowledgements
+async fn send_step(
+    client: &Client,
+    addr: SocketAddr,
+    target: u64,
+    step: &[Message],
+) -> Result<(), Error> {
+    let mut pending = Vec::with_capacity(step.len());
+    for msg in step {
+        let responses = client
+            .send_request(addr, Some(target), msg.clone(), true, false, Priority::User)
+            .await?;
+        pending.push(responses);
+    }
+    let deadline = tokio::time::Instant::now() + client.timeout();
+    for mut responses in pending {
+        loop {
+            let remaining = deadline.saturating_duration_since(tokio::time::Instant::now());
+            if let Message::Acknowledgement { .. } =
+                responses.recv_timeout(remaining).await?.message()?
+            {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::localhost_options;
+    use crate::ClientOptions;
+    use lifx_core::zones::{plan_color_zones, ZoneRange};
+    use lifx_core::{BuildOptions, RawMessage, HSBK};
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+    use tokio::time::Instant;
+
+    const RED: HSBK = HSBK {
+        hue: 0,
+        saturation: 65535,
+        brightness: 65535,
+        kelvin: 3500,
+    };
+
+    /// A device that acks everything (apart from messages of type `ignore`) after a delay, and
+    /// reports each message's type and when it arrived
+    async fn slow_device(
+        ignore: u16,
+    ) -> (
+        SocketAddr,
+        tokio::sync::mpsc::UnboundedReceiver<(u16, Instant)>,
+    ) {
+        let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = sock.local_addr().unwrap();
+        let (tx, rx) = tokio::sync::mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut buf = [0; 1024];
+            loop {
+                let (n, from) = sock.recv_from(&mut buf).await.unwrap();
+                let raw = RawMessage::unpack(&buf[..n]).unwrap();
+                let typ = raw.protocol_header.typ;
+                let _ = tx.send((typ, Instant::now()));
+                if typ == ignore {
+                    continue;
+                }
+                let options = BuildOptions {
+                    target: Some(raw.frame_addr.target),
+                    ..raw.reply_options()
+                };
+                let ack = Message::Acknowledgement {
+                    seq: raw.frame_addr.sequence,
+                };
+                let bytes = RawMessage::build(&options, ack).unwrap().pack().unwrap();
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                sock.send_to(&bytes, from).await.unwrap();
+            }
+        });
+        (addr, rx)
+    }
+
+    #[test]
+    fn test_zones() {
+        let changes = [
+            (ZoneRange::new(0, 3).unwrap(), RED),
+            (ZoneRange::single(5), RED),
+            (ZoneRange::single(7), RED),
+        ];
+        let sequence = CommandSequence::zones(plan_color_zones(&changes, Duration::ZERO));
+        let sizes: Vec<_> = sequence.steps().iter().map(Vec::len).collect();
+        assert_eq!(sizes, vec![2, 1]);
+        assert_eq!(sequence.len(), 3);
+
+        let mut sequence = CommandSequence::new().and(Message::GetPower);
+        sequence.extend(vec![Message::GetLabel, Message::GetPower]);
+        assert_eq!(sequence.steps().len(), 3);
+    }
+
+    #[tokio::test]
+    async fn test_waits_between_steps() {
+        let (addr, mut received) = slow_device(0).await;
+        let client = Client::with_options(localhost_options()).await.unwrap();
+        CommandSequence::new()
+            .then(Message::GetPower)
+            .and(Message::GetLabel)
+            .then(Message::GetVersion)
+            .send(&client, addr, 0x1234)
+            .await
+            .unwrap();
+
+        let (first, sent) = received.recv().await.unwrap();
+        let (second, _) = received.recv().await.unwrap();
+        let (third, last) = received.recv().await.unwrap();
+        assert_eq!((first, second, third), (20, 23, 32));
+        assert!(last - sent >= Duration::from_millis(50));
+    }
+
+    #[tokio::test]
+    async fn test_stops_at_failed_step() {
+        // GetLabel is never acknowledged
+        let (addr, mut received) = slow_device(23).await;
+        let options = ClientOptions {
+            timeout: Duration::from_millis(200),
+            ..localhost_options()
+        };
+        let client = Client::with_options(options).await.unwrap();
+        let res = CommandSequence::new()
+            .then(Message::GetPower)
+            .then(Message::GetLabel)
+            .then(Message::GetVersion)
+            .send(&client, addr, 0x1234)
+            .await;
+        match res {
+            Err(Error::StepFailed { step: 1, source }) => {
+                assert!(matches!(*source, Error::Timeout))
+            }
+            res => panic!("unexpected result {:?}", res),
+        }
+        let types: Vec<_> = std::iter::from_fn(|| received.try_recv().ok())
+            .map(|(typ, _)| typ)
+            .collect();
+        assert_eq!(types, vec![20, 23]);
+    }
+}
@@ -0,0 +1,380 @@
+//! Bringing devices in line with a configuration file
+//!
+//! A [Config] describes how devices should be set up: their labels, groups and locations, and
+//! their color and power, either fixed or on a daily schedule.  Devices are picked out by serial
+//! number, or by their current label.  [Config::plan] compares that with what's actually on the
+//! network (as a list of [DeviceState]s) and reports the [Drift], and [Config::reconcile] sends the
+//! messages to fix it.
+//!
+//! Configs can be written in TOML or JSON.  Colors use the same strings as
+//! [HSBK::from_str](lifx_core::HSBK), and times are `"HH:MM"`, local time:
+//!
+//! ```toml
+//! [[device]]
+//! serial = "d073d5001337"
+//! label = "Kitchen"
+//! group = "Downstairs"
+//! location = "Home"
+//! color = "3500K 80%"
+//! power = true
+//!
+//! # matched by its current label, since there's no serial
+//! [[device]]
+//! label = "Porch"
+//! on = "19:00"
+//! off = "23:30"
+//! ```
+//!
+//! This needs the `config` feature.
+
+use crate::client::Client;
+use crate::diff::diff;
+use crate::provision::{ident_for, lifx_string};
+use crate::reliable::ReliableSender;
+use crate::state::DeviceState;
+use crate::Error;
+use lifx_core::{Message, HSBK};
+use serde::Deserialize;
+use std::time::Duration;
+
+/// The desired setup for one device, as written in the config file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// The device's serial number (see [DeviceState::serial])
+    pub serial: Option<String>,
+    /// The label the device should have
+    ///
+    /// When there's no serial, this is also how the device is found.
+    pub label: Option<String>,
+    /// The name of the group the device should be in
+    pub group: Option<String>,
+    /// The name of the location the device should be in
+    pub location: Option<String>,
+    /// The color the device should be, as a color string
+    pub color: Option<String>,
+    /// Whether the device should be on
+    pub power: Option<bool>,
+    /// The time of day (`"HH:MM"`) that the device should turn on
+    ///
+    /// This needs `off` as well, and can't be combined with `power`.
+    pub on: Option<String>,
+    /// The time of day (`"HH:MM"`) that the device should turn off
+    pub off: Option<String>,
+}
+
+impl DeviceConfig {
+    /// Whether this entry is for the given device
+    pub fn matches(&self, device: &DeviceState) -> bool {
+        match (&self.serial, &self.label) {
+            (Some(serial), _) => serial.eq_ignore_ascii_case(&device.serial()),
+            (None, Some(label)) => device.label.as_ref() == Some(label),
+            (None, None) => false,
+        }
+    }
+
+    fn describe(&self) -> String {
+        match (&self.serial, &self.label) {
+            (Some(serial), _) => serial.clone(),
+            (None, Some(label)) => format!("{:?}", label),
+            (None, None) => "(unnamed)".to_owned(),
+        }
+    }
+
+    fn invalid(&self, reason: String) -> Error {
+        Error::Config(format!("{}: {}", self.describe(), reason))
+    }
+
+    fn color(&self) -> Result<Option<HSBK>, Error> {
+        self.color
+            .as_ref()
+            .map(|c| {
+                c.parse()
+                    .map_err(|e: lifx_core::Error| self.invalid(e.to_string()))
+            })
+            .transpose()
+    }
+
+    /// Whether the device should be on at the given time since midnight
+    fn power_at(&self, time_of_day: Duration) -> Result<Option<bool>, Error> {
+        match (&self.on, &self.off) {
+            (Some(on), Some(off)) => {
+                let on = parse_time(on).map_err(|e| self.invalid(e))?;
+                let off = parse_time(off).map_err(|e| self.invalid(e))?;
+                if on <= off {
+                    Ok(Some(on <= time_of_day && time_of_day < off))
+                } else {
+                    Ok(Some(on <= time_of_day || time_of_day < off))
+                }
+            }
+            (None, None) => Ok(self.power),
+            _ => Err(self.invalid("`on` and `off` must be used together".to_owned())),
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        if self.serial.is_none() && self.label.is_none() {
+            return Err(self.invalid("needs a serial or a label".to_owned()));
+        }
+        self.color()?;
+        self.power_at(Duration::ZERO)?;
+        if self.on.is_some() && self.power.is_some() {
+            return Err(self.invalid("`power` can't be combined with `on`/`off`".to_owned()));
+        }
+        Ok(())
+    }
+}
+
+/// A parsed configuration file
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default, rename = "device")]
+    pub devices: Vec<DeviceConfig>,
+}
+
+/// The differences between one device and its configuration
+#[derive(Debug, Clone, PartialEq)]
+pub struct Drift {
+    pub target: u64,
+    /// The messages that will bring the device in line, in the order they should be sent
+    pub messages: Vec<Message>,
+}
+
+/// The result of comparing a [Config] with the devices on the network
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// Devices that don't match their configuration
+    pub drift: Vec<Drift>,
+    /// Config entries that didn't match any device
+    pub missing: Vec<String>,
+}
+
+impl Report {
+    /// Returns `true` if every configured device was found, and matches its configuration
+    pub fn is_clean(&self) -> bool {
+        self.drift.is_empty() && self.missing.is_empty()
+    }
+}
+
+impl Config {
+    pub fn from_toml(s: &str) -> Result<Config, Error> {
+        let config: Config = toml::from_str(s).map_err(|e| Error::Config(e.to_string()))?;
+        config.validate()
+    }
+
+    pub fn from_json(s: &str) -> Result<Config, Error> {
+        let config: Config = serde_json::from_str(s).map_err(|e| Error::Config(e.to_string()))?;
+        config.validate()
+    }
+
+    fn validate(self) -> Result<Config, Error> {
+        for device in &self.devices {
+            device.validate()?;
+        }
+        Ok(self)
+    }
+
+    /// Compares the config with the current state of each device
+    ///
+    /// `time_of_day` is the local time since midnight (see
+    /// [schedule::time_of_day](crate::schedule::time_of_day)), for devices with a power schedule.
+    /// The first config entry that matches a device is used.  Returns an error if any entry is
+    /// invalid, which can only happen if the config wasn't loaded with [Config::from_toml] or
+    /// [Config::from_json].
+    pub fn plan(&self, devices: &[DeviceState], time_of_day: Duration) -> Result<Report, Error> {
+        let mut report = Report::default();
+        for entry in &self.devices {
+            entry.validate()?;
+            if !devices.iter().any(|d| entry.matches(d)) {
+                report.missing.push(entry.describe());
+            }
+        }
+        for device in devices {
+            let entry = match self.devices.iter().find(|e| e.matches(device)) {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let messages = device_messages(entry, device, devices, time_of_day)?;
+            if !messages.is_empty() {
+                report.drift.push(Drift {
+                    target: device.target,
+                    messages,
+                });
+            }
+        }
+        Ok(report)
+    }
+
+    /// Sends the messages needed to bring every device in line with the config
+    ///
+    /// Each message is retried until it's acknowledged, up to 3 times.  Devices whose address
+    /// isn't known are skipped.  Returns the report from before anything was changed.
+    pub async fn reconcile(
+        &self,
+        client: &Client,
+        devices: &[DeviceState],
+        time_of_day: Duration,
+    ) -> Result<Report, Error> {
+        let report = self.plan(devices, time_of_day)?;
+        let sender = ReliableSender::new(client.clone(), 3);
+        for drift in &report.drift {
+            let addr = match devices
+                .iter()
+                .find(|d| d.target == drift.target)
+                .and_then(|d| d.addr)
+            {
+                Some(addr) => addr,
+                None => continue,
+            };
+            for msg in &drift.messages {
+                sender.send_acked(addr, drift.target, msg.clone()).await?;
+            }
+        }
+        Ok(report)
+    }
+}
+
+fn device_messages(
+    entry: &DeviceConfig,
+    device: &DeviceState,
+    devices: &[DeviceState],
+    time_of_day: Duration,
+) -> Result<Vec<Message>, Error> {
+    let mut messages = Vec::new();
+    if let (Some(_), Some(label)) = (&entry.serial, &entry.label) {
+        if device.label.as_ref() != Some(label) {
+            messages.push(Message::SetLabel {
+                label: lifx_string(label),
+            });
+        }
+    }
+    if let Some(name) = &entry.group {
+        if device.group.as_ref().map(|(_, n)| n) != Some(name) {
+            let id = ident_for(devices.iter().filter_map(|d| d.group.as_ref()), name);
+            messages.push(Message::set_group(id, lifx_string(name)));
+        }
+    }
+    if let Some(name) = &entry.location {
+        if device.location.as_ref().map(|(_, n)| n) != Some(name) {
+            let id = ident_for(devices.iter().filter_map(|d| d.location.as_ref()), name);
+            messages.push(Message::set_location(id, lifx_string(name)));
+        }
+    }
+
+    let mut desired = DeviceState::new(device.target);
+    desired.color = entry.color()?;
+    desired.power = entry
+        .power_at(time_of_day)?
+        .map(|on| if on { 65535 } else { 0 });
+    messages.extend(diff(device, &desired));
+    Ok(messages)
+}
+
+/// Parses `"HH:MM"` into a time since midnight
+fn parse_time(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid time {:?}, expected HH:MM", s);
+    let (hours, minutes) = s.split_once(':').ok_or_else(invalid)?;
+    let hours: u64 = hours.trim().parse().map_err(|_| invalid())?;
+    let minutes: u64 = minutes.trim().parse().map_err(|_| invalid())?;
+    if hours > 23 || minutes > 59 {
+        return Err(invalid());
+    }
+    Ok(Duration::from_secs((hours * 60 + minutes) * 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lifx_core::LifxIdent;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    const CONFIG: &str = r#"
+        [[device]]
+        serial = "D073D5001337"
+        label = "Kitchen"
+        group = "Downstairs"
+        color = "red"
+        power = true
+
+        [[device]]
+        label = "Porch"
+        on = "19:00"
+        off = "01:00"
+
+        [[device]]
+        label = "Attic"
+    "#;
+
+    fn kitchen() -> DeviceState {
+        let mut state = DeviceState::new(0x3713_00d5_73d0);
+        state.label = Some("Old label".to_owned());
+        state.power = Some(0);
+        state
+    }
+
+    fn porch() -> DeviceState {
+        let mut state = DeviceState::new(2);
+        state.label = Some("Porch".to_owned());
+        state.power = Some(65535);
+        state.group = Some((LifxIdent([7; 16]), "Downstairs".to_owned()));
+        state
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = Config::from_toml(CONFIG).unwrap();
+        assert_eq!(config.devices.len(), 3);
+        assert_eq!(config.devices[1].on.as_deref(), Some("19:00"));
+
+        let json = Config::from_json(r#"{"device": [{"label": "Porch", "power": false}]}"#);
+        assert_eq!(json.unwrap().devices[0].power, Some(false));
+
+        for bad in &[
+            "[[device]]\ncolor = \"red\"",
+            "[[device]]\nlabel = \"x\"\ncolor = \"mauve\"",
+            "[[device]]\nlabel = \"x\"\non = \"25:00\"\noff = \"01:00\"",
+            "[[device]]\nlabel = \"x\"\non = \"01:00\"",
+            "[[device]]\nlabel = \"x\"\non = \"01:00\"\noff = \"02:00\"\npower = true",
+            "[[device]]\nlabel = \"x\"\nbrightness = 1",
+        ] {
+            assert!(Config::from_toml(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let config = Config::from_toml(CONFIG).unwrap();
+        let devices = [kitchen(), porch()];
+
+        let report = config.plan(&devices, 12 * HOUR).unwrap();
+        assert_eq!(report.missing, vec!["\"Attic\"".to_owned()]);
+        assert_eq!(report.drift.len(), 2);
+
+        // label, group (joining the existing one), color, then power on
+        let kitchen = &report.drift[0].messages;
+        let types: Vec<_> = kitchen.iter().map(|m| m.get_num()).collect();
+        assert_eq!(types, vec![24, 52, 102, 21]);
+        match kitchen[1] {
+            Message::SetGroup { group, .. } => assert_eq!(group, LifxIdent([7; 16])),
+            ref msg => panic!("unexpected message {:?}", msg),
+        }
+
+        // the porch light should be off at midday, and on at midnight
+        assert_eq!(report.drift[1].messages.len(), 1);
+        let report = config.plan(&devices, Duration::ZERO).unwrap();
+        assert_eq!(report.drift.len(), 1);
+        assert!(!report.is_clean());
+
+        // hand-built configs aren't checked until they're used
+        let bad = Config {
+            devices: vec![DeviceConfig {
+                label: Some("Porch".to_owned()),
+                color: Some("mauve".to_owned()),
+                ..Default::default()
+            }],
+        };
+        assert!(matches!(bad.plan(&devices, HOUR), Err(Error::Config(_))));
+    }
+}
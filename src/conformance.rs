@@ -0,0 +1,251 @@
+//! Checking that a device (or an emulator) speaks the protocol correctly
+//!
+//! A conformance run sends a scripted list of [Step]s to a device, one at a time, and checks
+//! each reply against what the step expects.  The result is a [Report] listing which steps
+//! passed and why the others failed.  [standard_script] covers the messages that every LIFX
+//! light should support, and custom scripts can be built from [Step::new].
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), lifx::Error> {
+//! use lifx::conformance::{run, standard_script};
+//! use lifx::Client;
+//! use std::time::Duration;
+//!
+//! let client = Client::new().await?;
+//! for device in client.discover(Duration::from_secs(1)).await? {
+//!     let report = run(&client, device.addr, device.target, &standard_script()).await;
+//!     print!("{}", report);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Steps that set state (such as power and color) aren't undone afterwards.
+
+use crate::client::Client;
+use lifx_core::{EchoPayload, Message, PowerLevel, HSBK};
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// The color set by [standard_script]
+const TEST_COLOR: HSBK = HSBK {
+    hue: 21845,
+    saturation: 65535,
+    brightness: 32768,
+    kelvin: 3500,
+};
+
+/// What a [Step] expects the device to send back
+#[derive(Debug, Clone)]
+pub enum Expect {
+    /// An acknowledgement
+    ///
+    /// The request is sent with `ack_required` set, and without `res_required`.
+    Ack,
+    /// A reply with this message type, with any contents
+    Type(u16),
+    /// Exactly this reply
+    Reply(Message),
+    /// A reply that passes a custom check, which returns the reason if it fails
+    Check(fn(&Message) -> Result<(), String>),
+}
+
+/// One request in a conformance script, and what should come back
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub name: String,
+    pub request: Message,
+    pub expect: Expect,
+}
+
+impl Step {
+    pub fn new(name: impl Into<String>, request: Message, expect: Expect) -> Step {
+        Step {
+            name: name.into(),
+            request,
+            expect,
+        }
+    }
+
+    /// Checks a reply against this step's expectations
+    fn check(&self, reply: &Message) -> Result<(), String> {
+        match &self.expect {
+            Expect::Ack => unreachable!("acks aren't requested as replies"),
+            Expect::Type(typ) if reply.get_num() == *typ => Ok(()),
+            Expect::Type(typ) => Err(format!("expected a reply of type {}, got {:?}", typ, reply)),
+            Expect::Reply(expected) if reply == expected => Ok(()),
+            Expect::Reply(expected) => Err(format!("expected {:?}, got {:?}", expected, reply)),
+            Expect::Check(check) => check(reply),
+        }
+    }
+}
+
+/// The result of a single [Step]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepResult {
+    pub name: String,
+    /// `Ok` if the step passed, or the reason it failed
+    pub outcome: Result<(), String>,
+}
+
+/// The results of every step in a conformance run, in order
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub results: Vec<StepResult>,
+}
+
+impl Report {
+    /// Returns `true` if every step passed
+    pub fn is_success(&self) -> bool {
+        self.results.iter().all(|r| r.outcome.is_ok())
+    }
+
+    /// The number of steps that passed
+    pub fn passed(&self) -> usize {
+        self.results.iter().filter(|r| r.outcome.is_ok()).count()
+    }
+
+    /// The steps that failed
+    pub fn failures(&self) -> impl Iterator<Item = &StepResult> {
+        self.results.iter().filter(|r| r.outcome.is_err())
+    }
+}
+
+impl fmt::Display for Report {
+    /// One line per step, followed by a summary
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in &self.results {
+            match &result.outcome {
+                Ok(()) => writeln!(f, "PASS {}", result.name)?,
+                Err(reason) => writeln!(f, "FAIL {}: {}", result.name, reason)?,
+            }
+        }
+        writeln!(f, "{}/{} passed", self.passed(), self.results.len())
+    }
+}
+
+/// Runs each step of a script against a device, and reports the results
+///
+/// Each step gets a single attempt, waiting up to [Client::timeout] for the reply, so lost
+/// packets show up as failures.  A failed step doesn't stop the run.
+pub async fn run(client: &Client, addr: SocketAddr, target: u64, script: &[Step]) -> Report {
+    let mut report = Report::default();
+    for step in script {
+        let outcome = match step.expect {
+            Expect::Ack => client
+                .send_acked(addr, target, step.request.clone())
+                .await
+                .map_err(|e| e.to_string()),
+            _ => match client.request(addr, target, step.request.clone()).await {
+                Ok(reply) => step.check(&reply),
+                Err(e) => Err(e.to_string()),
+            },
+        };
+        report.results.push(StepResult {
+            name: step.name.clone(),
+            outcome,
+        });
+    }
+    report
+}
+
+/// A script that covers the messages every LIFX light should support
+///
+/// This queries the device's details, checks that echo requests are echoed, and turns the
+/// light on and sets its color, checking that each change is reported back.
+pub fn standard_script() -> Vec<Step> {
+    let payload = EchoPayload::from(&b"lifx conformance"[..]);
+    fn check_color(reply: &Message) -> Result<(), String> {
+        match reply {
+            Message::LightState { color, .. } if *color == TEST_COLOR => Ok(()),
+            Message::LightState { color, .. } => Err(format!("color wasn't set, got {:?}", color)),
+            reply => Err(format!("expected LightState, got {:?}", reply)),
+        }
+    }
+
+    vec![
+        Step::new("GetService", Message::GetService, Expect::Type(3)),
+        Step::new(
+            "GetHostFirmware",
+            Message::GetHostFirmware,
+            Expect::Type(15),
+        ),
+        Step::new(
+            "GetWifiFirmware",
+            Message::GetWifiFirmware,
+            Expect::Type(19),
+        ),
+        Step::new("GetVersion", Message::GetVersion, Expect::Type(33)),
+        Step::new("GetLabel", Message::GetLabel, Expect::Type(25)),
+        Step::new("GetLocation", Message::GetLocation, Expect::Type(50)),
+        Step::new("GetGroup", Message::GetGroup, Expect::Type(53)),
+        Step::new(
+            "EchoRequest",
+            Message::EchoRequest { payload },
+            Expect::Reply(Message::EchoResponse { payload }),
+        ),
+        Step::new(
+            "SetPower",
+            Message::SetPower {
+                level: PowerLevel::Enabled,
+            },
+            Expect::Ack,
+        ),
+        Step::new(
+            "GetPower after SetPower",
+            Message::GetPower,
+            Expect::Reply(Message::StatePower { level: 65535 }),
+        ),
+        Step::new(
+            "LightSetColor",
+            Message::set_color(TEST_COLOR, Duration::ZERO),
+            Expect::Ack,
+        ),
+        Step::new(
+            "LightGet after LightSetColor",
+            Message::LightGet,
+            Expect::Check(check_color),
+        ),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::fake_bulb;
+    use crate::ClientOptions;
+    use lifx_core::LifxString;
+    use std::ffi::CString;
+
+    #[tokio::test]
+    async fn test_run() {
+        let addr = fake_bulb(0x1234, "Kitchen").await;
+        let client = Client::with_options(ClientOptions {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let label = LifxString::new(&CString::new("Kitchen").unwrap());
+        let script = [
+            Step::new("GetService", Message::GetService, Expect::Type(3)),
+            Step::new(
+                "GetLabel",
+                Message::GetLabel,
+                Expect::Reply(Message::StateLabel { label }),
+            ),
+            Step::new("wrong type", Message::GetLabel, Expect::Type(22)),
+            // the fake bulb doesn't answer this at all
+            Step::new("GetHostInfo", Message::GetHostInfo, Expect::Type(13)),
+        ];
+        let report = run(&client, addr, 0x1234, &script).await;
+        assert!(!report.is_success());
+        assert_eq!(report.passed(), 2);
+        let failed: Vec<_> = report.failures().map(|r| r.name.as_str()).collect();
+        assert_eq!(failed, vec!["wrong type", "GetHostInfo"]);
+        assert!(report.to_string().ends_with("2/4 passed\n"));
+    }
+}
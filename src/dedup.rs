@@ -0,0 +1,163 @@
+//! Filtering out duplicate replies
+//!
+//! Devices sometimes send the same reply more than once: for example when a request was
+//! retransmitted and both copies got through, or when a device answers a broadcast on more than
+//! one interface.  A [DedupFilter] remembers the replies it has seen recently, so that anything
+//! watching for changes doesn't see the same state twice.
+//!
+//! Two messages are considered the same if they have the same target, message type, sequence
+//! number, and payload.  Payloads are compared after [Message::normalize], so copies that only
+//! differ in reserved fields still count as duplicates.
+
+use crate::clock::{Clock, SystemClock};
+use lifx_core::{BuildOptions, Message, RawMessage};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// (target, type, sequence, payload hash)
+type Key = (u64, u16, u8, u64);
+
+/// Remembers recently seen messages, to detect duplicates
+#[derive(Debug)]
+pub struct DedupFilter {
+    window: Duration,
+    seen: HashMap<Key, Instant>,
+    last_pruned: Option<Instant>,
+    clock: Arc<dyn Clock>,
+}
+
+impl DedupFilter {
+    /// Creates a filter that treats identical messages as duplicates if they arrive within
+    /// `window` of each other
+    ///
+    /// A zero window disables the filter.
+    pub fn new(window: Duration) -> DedupFilter {
+        DedupFilter::with_clock(window, SystemClock)
+    }
+
+    /// Like [DedupFilter::new], but timing arrivals with the given clock
+    pub fn with_clock(window: Duration, clock: impl Clock) -> DedupFilter {
+        DedupFilter {
+            window,
+            seen: HashMap::new(),
+            last_pruned: None,
+            clock: Arc::new(clock),
+        }
+    }
+
+    /// Returns `true` if this message is a duplicate of one seen recently
+    ///
+    /// Every call counts as seeing the message, so only the first copy returns `false`.
+    pub fn is_duplicate(&mut self, raw: &RawMessage) -> bool {
+        if self.window.is_zero() {
+            return false;
+        }
+        let now = self.clock.now();
+        self.prune(now);
+
+        let key = (
+            raw.frame_addr.target,
+            raw.protocol_header.typ,
+            raw.frame_addr.sequence,
+            payload_hash(raw),
+        );
+        match self.seen.insert(key, now) {
+            Some(previous) => now.duration_since(previous) < self.window,
+            None => false,
+        }
+    }
+
+    /// Forgets messages that are too old to matter, at most once per window
+    fn prune(&mut self, now: Instant) {
+        match self.last_pruned {
+            Some(last) if now.duration_since(last) < self.window => {}
+            _ => {
+                let window = self.window;
+                self.seen
+                    .retain(|_, seen| now.duration_since(*seen) < window);
+                self.last_pruned = Some(now);
+            }
+        }
+    }
+
+    /// The number of messages currently remembered
+    pub fn len(&self) -> usize {
+        self.seen.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.seen.is_empty()
+    }
+}
+
+/// Hashes the normalized payload, or the payload as it is if it can't be decoded
+fn payload_hash(raw: &RawMessage) -> u64 {
+    let normalized = Message::from_raw(raw)
+        .and_then(|msg| RawMessage::build(&BuildOptions::default(), msg.normalize()));
+    let mut hasher = DefaultHasher::new();
+    match normalized {
+        Ok(normalized) => normalized.payload.hash(&mut hasher),
+        Err(_) => raw.payload.hash(&mut hasher),
+    }
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::MockClock;
+
+    fn state_power(target: u64, sequence: u8, level: u16) -> RawMessage {
+        let options = BuildOptions {
+            target: Some(target),
+            sequence,
+            ..Default::default()
+        };
+        RawMessage::build(&options, Message::StatePower { level }).unwrap()
+    }
+
+    #[test]
+    fn test_dedup() {
+        let clock = MockClock::new();
+        let mut filter = DedupFilter::with_clock(Duration::from_secs(1), clock.clone());
+
+        assert!(!filter.is_duplicate(&state_power(1, 5, 0)));
+        assert!(filter.is_duplicate(&state_power(1, 5, 0)));
+
+        // anything different isn't a duplicate
+        assert!(!filter.is_duplicate(&state_power(2, 5, 0)));
+        assert!(!filter.is_duplicate(&state_power(1, 6, 0)));
+        assert!(!filter.is_duplicate(&state_power(1, 5, 65535)));
+
+        // and neither is the same message after the window has passed
+        clock.advance(Duration::from_secs(2));
+        assert!(!filter.is_duplicate(&state_power(1, 5, 0)));
+        assert_eq!(filter.len(), 1);
+    }
+
+    #[test]
+    fn test_reserved_fields() {
+        let mut filter = DedupFilter::new(Duration::from_secs(1));
+        let version = |reserved| {
+            let msg = Message::StateVersion {
+                vendor: 1,
+                product: 27,
+                reserved,
+            };
+            RawMessage::build(&BuildOptions::default(), msg).unwrap()
+        };
+        assert!(!filter.is_duplicate(&version(0)));
+        assert!(filter.is_duplicate(&version(7)));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut filter = DedupFilter::new(Duration::ZERO);
+        assert!(!filter.is_duplicate(&state_power(1, 5, 0)));
+        assert!(!filter.is_duplicate(&state_power(1, 5, 0)));
+        assert!(filter.is_empty());
+    }
+}
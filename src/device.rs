@@ -0,0 +1,469 @@
+//! Typed wrappers for each kind of device
+//!
+//! Every message can be sent to every device, but most of them only mean something to some
+//! products: a plain bulb ignores [Message::SetColorZones], and a Switch ignores
+//! [Message::LightSetColor].  [AnyDevice::connect] asks a device what product it is, and wraps
+//! it in a [Bulb], [MultizoneStrip], [TileChain], or [Switch], each of which only has methods for
+//! what its hardware can do.  Operations that every device supports (labels, power, and firmware)
+//! are in the [Device] trait, and the ones that every light supports are in [Light].
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), lifx::Error> {
+//! use lifx::device::{AnyDevice, Device, Light};
+//! use lifx::lifx_core::{ZoneRange, HSBK};
+//! use lifx::Client;
+//! use std::time::Duration;
+//!
+//! let client = Client::new().await?;
+//! let red = HSBK::new(0.0, 100.0, 100.0, 3500);
+//! for found in client.discover(Duration::from_secs(1)).await? {
+//!     match AnyDevice::connect(client.clone(), &found).await? {
+//!         AnyDevice::Strip(strip) => {
+//!             // only strips can set a range of zones
+//!             strip.set_zones(ZoneRange::new(0, 7)?, red, Duration::ZERO).await?
+//!         }
+//!         AnyDevice::Switch(switch) => switch.relays().set(0, true).await?,
+//!         device => {
+//!             println!("{} is a {}", device.get_label().await?, device.product().name);
+//!         }
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::{Client, DiscoveredDevice};
+use crate::provision::lifx_string;
+use crate::relay::RelaySwitch;
+use crate::state::DeviceState;
+use crate::Error;
+use lifx_core::effects::{self, Effect, MoveDirection};
+use lifx_core::{
+    ApplicationRequest, FirmwareVersion, Message, PowerLevel, ProductInfo, ZoneRange, HSBK,
+};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::time::Duration;
+
+/// The future returned by [Device] and [Light] methods
+pub type DeviceFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Error>> + Send + 'a>>;
+
+fn unexpected(request: &str, msg: Message) -> Error {
+    Error::Protocol(lifx_core::Error::ProtocolError(format!(
+        "unexpected reply to {}: {:?}",
+        request, msg
+    )))
+}
+
+/// A device's address and product, and the client used to talk to it
+#[derive(Clone)]
+pub struct DeviceHandle {
+    client: Client,
+    addr: SocketAddr,
+    target: u64,
+    product: &'static ProductInfo,
+}
+
+impl DeviceHandle {
+    pub fn new(
+        client: Client,
+        addr: SocketAddr,
+        target: u64,
+        product: &'static ProductInfo,
+    ) -> DeviceHandle {
+        DeviceHandle {
+            client,
+            addr,
+            target,
+            product,
+        }
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn target(&self) -> u64 {
+        self.target
+    }
+
+    pub fn product(&self) -> &'static ProductInfo {
+        self.product
+    }
+
+    /// Sends a message, and waits for the reply
+    pub async fn request(&self, msg: Message) -> Result<Message, Error> {
+        self.client.request(self.addr, self.target, msg).await
+    }
+
+    /// Sends a message, and waits for it to be acknowledged
+    pub async fn send_acked(&self, msg: Message) -> Result<(), Error> {
+        self.client.send_acked(self.addr, self.target, msg).await
+    }
+}
+
+/// What every device can do
+pub trait Device: Send + Sync {
+    fn handle(&self) -> &DeviceHandle;
+
+    fn target(&self) -> u64 {
+        self.handle().target()
+    }
+
+    fn addr(&self) -> SocketAddr {
+        self.handle().addr()
+    }
+
+    fn product(&self) -> &'static ProductInfo {
+        self.handle().product()
+    }
+
+    fn get_label(&self) -> DeviceFuture<'_, String> {
+        Box::pin(async move {
+            match self.handle().request(Message::GetLabel).await? {
+                Message::StateLabel { label } => Ok(label.to_string()),
+                msg => Err(unexpected("GetLabel", msg)),
+            }
+        })
+    }
+
+    /// Renames the device (only the first 31 bytes of the label are sent)
+    fn set_label<'a>(&'a self, label: &'a str) -> DeviceFuture<'a, ()> {
+        let label = lifx_string(label);
+        Box::pin(self.handle().send_acked(Message::SetLabel { label }))
+    }
+
+    /// Whether the device is on
+    fn get_power(&self) -> DeviceFuture<'_, bool> {
+        Box::pin(async move {
+            match self.handle().request(Message::GetPower).await? {
+                Message::StatePower { level } => Ok(level != 0),
+                msg => Err(unexpected("GetPower", msg)),
+            }
+        })
+    }
+
+    fn set_power(&self, on: bool) -> DeviceFuture<'_, ()> {
+        let level = if on {
+            PowerLevel::Enabled
+        } else {
+            PowerLevel::Standby
+        };
+        Box::pin(self.handle().send_acked(Message::SetPower { level }))
+    }
+
+    /// The version of the device's main firmware
+    fn get_firmware(&self) -> DeviceFuture<'_, FirmwareVersion> {
+        Box::pin(async move {
+            let msg = self.handle().request(Message::GetHostFirmware).await?;
+            FirmwareVersion::from_state_firmware(&msg)
+                .ok_or_else(|| unexpected("GetHostFirmware", msg))
+        })
+    }
+}
+
+/// What every light can do
+pub trait Light: Device {
+    /// The color of the light (or of its first zone, for lights with more than one)
+    fn get_color(&self) -> DeviceFuture<'_, HSBK> {
+        Box::pin(async move {
+            match self.handle().request(Message::LightGet).await? {
+                Message::LightState { color, .. } => Ok(color),
+                msg => Err(unexpected("LightGet", msg)),
+            }
+        })
+    }
+
+    /// Fades the whole light to a color
+    fn set_color(&self, color: HSBK, duration: Duration) -> DeviceFuture<'_, ()> {
+        Box::pin(
+            self.handle()
+                .send_acked(Message::set_color(color, duration)),
+        )
+    }
+
+    /// Turns the light on or off, fading over `duration`
+    fn set_light_power(&self, on: bool, duration: Duration) -> DeviceFuture<'_, ()> {
+        Box::pin(self.handle().send_acked(Message::light_power(on, duration)))
+    }
+}
+
+/// A light with a single color
+#[derive(Clone)]
+pub struct Bulb {
+    handle: DeviceHandle,
+}
+
+impl Device for Bulb {
+    fn handle(&self) -> &DeviceHandle {
+        &self.handle
+    }
+}
+
+impl Light for Bulb {}
+
+/// A light with a row of zones, like the LIFX Z or Beam
+#[derive(Clone)]
+pub struct MultizoneStrip {
+    handle: DeviceHandle,
+}
+
+impl Device for MultizoneStrip {
+    fn handle(&self) -> &DeviceHandle {
+        &self.handle
+    }
+}
+
+impl Light for MultizoneStrip {}
+
+impl MultizoneStrip {
+    /// The colors of every zone
+    ///
+    /// This uses the extended multizone messages if the product supports them.
+    pub async fn get_zones(&self) -> Result<Vec<HSBK>, Error> {
+        let handle = &self.handle;
+        if handle.product.extended_multizone() {
+            return handle
+                .client
+                .get_extended_color_zones(handle.addr, handle.target)
+                .await;
+        }
+        let replies = handle
+            .client
+            .get_color_zones(handle.addr, handle.target, ZoneRange::ALL)
+            .await?;
+        let mut state = DeviceState::new(handle.target);
+        for reply in &replies {
+            state.update(reply);
+        }
+        state
+            .zones
+            .and_then(|zones| zones.into_iter().collect())
+            .ok_or_else(|| {
+                Error::Protocol(lifx_core::Error::ProtocolError(
+                    "some zones weren't reported".to_owned(),
+                ))
+            })
+    }
+
+    /// Fades a range of zones to a color
+    pub async fn set_zones(
+        &self,
+        range: ZoneRange,
+        color: HSBK,
+        duration: Duration,
+    ) -> Result<(), Error> {
+        let msg = Message::set_color_zones(range, color, duration, ApplicationRequest::Apply);
+        self.handle.send_acked(msg).await
+    }
+
+    /// Starts moving the zone colors along the strip, taking `speed` to move the whole length
+    pub async fn start_move(&self, speed: Duration, direction: MoveDirection) -> Result<(), Error> {
+        self.start_effect(&Effect::Move { speed, direction }).await
+    }
+
+    /// Stops any effect running on the strip
+    pub async fn stop_effect(&self) -> Result<(), Error> {
+        stop_effects(&self.handle).await
+    }
+
+    async fn start_effect(&self, effect: &Effect) -> Result<(), Error> {
+        let msg = effects::start_effect(self.handle.product, effect)?;
+        self.handle.send_acked(msg).await
+    }
+}
+
+/// A light made of a chain of tiles, each with a matrix of zones, like the LIFX Tile or Candle
+#[derive(Clone)]
+pub struct TileChain {
+    handle: DeviceHandle,
+}
+
+impl Device for TileChain {
+    fn handle(&self) -> &DeviceHandle {
+        &self.handle
+    }
+}
+
+impl Light for TileChain {}
+
+impl TileChain {
+    /// Starts slowly blending between the colors in a palette (or the device's default palette,
+    /// if it's empty)
+    pub async fn start_morph(&self, speed: Duration, palette: Vec<HSBK>) -> Result<(), Error> {
+        self.start_effect(&Effect::Morph { speed, palette }).await
+    }
+
+    /// Starts a flickering fire
+    pub async fn start_flame(&self, speed: Duration) -> Result<(), Error> {
+        self.start_effect(&Effect::Flame { speed }).await
+    }
+
+    /// Stops any effect running on the tiles
+    pub async fn stop_effect(&self) -> Result<(), Error> {
+        stop_effects(&self.handle).await
+    }
+
+    async fn start_effect(&self, effect: &Effect) -> Result<(), Error> {
+        let msg = effects::start_effect(self.handle.product, effect)?;
+        self.handle.send_acked(msg).await
+    }
+}
+
+async fn stop_effects(handle: &DeviceHandle) -> Result<(), Error> {
+    for msg in effects::stop_effect(handle.product) {
+        handle.send_acked(msg).await?;
+    }
+    Ok(())
+}
+
+/// A device with relays instead of lights, like the LIFX Switch
+pub struct Switch {
+    handle: DeviceHandle,
+    relays: RelaySwitch,
+}
+
+impl Device for Switch {
+    fn handle(&self) -> &DeviceHandle {
+        &self.handle
+    }
+}
+
+impl Switch {
+    /// Controls the relays, with the defaults described in [RelaySwitch::new]
+    pub fn relays(&self) -> &RelaySwitch {
+        &self.relays
+    }
+}
+
+/// Any kind of device, as picked by [AnyDevice::new]
+pub enum AnyDevice {
+    Bulb(Bulb),
+    Strip(MultizoneStrip),
+    Tile(TileChain),
+    Switch(Switch),
+}
+
+impl AnyDevice {
+    /// Wraps a device in the right type for its product
+    ///
+    /// Products with relays are a [Switch], matrix products are a [TileChain], multizone products
+    /// are a [MultizoneStrip], and everything else is a [Bulb].
+    pub fn new(handle: DeviceHandle) -> AnyDevice {
+        let product = handle.product;
+        if product.relays() {
+            let relays = RelaySwitch::new(handle.client.clone(), handle.addr, handle.target);
+            AnyDevice::Switch(Switch { handle, relays })
+        } else if product.matrix() {
+            AnyDevice::Tile(TileChain { handle })
+        } else if product.multizone() {
+            AnyDevice::Strip(MultizoneStrip { handle })
+        } else {
+            AnyDevice::Bulb(Bulb { handle })
+        }
+    }
+
+    /// Asks a discovered device what product it is, and wraps it in the right type
+    ///
+    /// Fails if the product isn't known (see [lifx_core::products] for teaching this crate about
+    /// more products).
+    pub async fn connect(client: Client, device: &DiscoveredDevice) -> Result<AnyDevice, Error> {
+        let version = client
+            .request(device.addr, device.target, Message::GetVersion)
+            .await?;
+        let product = ProductInfo::from_state_version(&version).ok_or_else(|| {
+            Error::Protocol(lifx_core::Error::ProtocolError(format!(
+                "unknown product: {:?}",
+                version
+            )))
+        })?;
+        Ok(AnyDevice::new(DeviceHandle::new(
+            client,
+            device.addr,
+            device.target,
+            product,
+        )))
+    }
+
+    /// The device as a [Light], unless it's a [Switch]
+    pub fn as_light(&self) -> Option<&dyn Light> {
+        match self {
+            AnyDevice::Bulb(bulb) => Some(bulb),
+            AnyDevice::Strip(strip) => Some(strip),
+            AnyDevice::Tile(tile) => Some(tile),
+            AnyDevice::Switch(_) => None,
+        }
+    }
+}
+
+impl Device for AnyDevice {
+    fn handle(&self) -> &DeviceHandle {
+        match self {
+            AnyDevice::Bulb(bulb) => bulb.handle(),
+            AnyDevice::Strip(strip) => strip.handle(),
+            AnyDevice::Tile(tile) => tile.handle(),
+            AnyDevice::Switch(switch) => switch.handle(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::fake_bulb;
+    use lifx_core::get_product_info;
+
+    #[tokio::test]
+    async fn test_bulb() {
+        let addr = fake_bulb(0x1234, "Porch").await;
+        let client = Client::new().await.unwrap();
+        let found = DiscoveredDevice {
+            target: 0x1234,
+            addr,
+        };
+        let device = AnyDevice::connect(client, &found).await.unwrap();
+        assert!(device.as_light().is_some());
+        let AnyDevice::Bulb(bulb) = device else {
+            panic!("not a bulb");
+        };
+        assert_eq!(bulb.get_label().await.unwrap(), "Porch");
+        assert_eq!(bulb.get_firmware().await.unwrap().to_string(), "3.70");
+
+        bulb.set_power(false).await.unwrap();
+        assert!(!bulb.get_power().await.unwrap());
+        let color = HSBK::new(240.0, 100.0, 50.0, 3500);
+        bulb.set_color(color, Duration::ZERO).await.unwrap();
+        assert_eq!(bulb.get_color().await.unwrap(), color);
+    }
+
+    #[tokio::test]
+    async fn test_product_types() {
+        let addr = fake_bulb(0x1234, "Strip").await;
+        let client = Client::new().await.unwrap();
+        let handle = |product| {
+            let info = get_product_info(1, product).unwrap();
+            AnyDevice::new(DeviceHandle::new(client.clone(), addr, 0x1234, info))
+        };
+        assert!(matches!(handle(55), AnyDevice::Tile(_)));
+        let switch = handle(70);
+        assert!(matches!(switch, AnyDevice::Switch(_)));
+        assert!(switch.as_light().is_none());
+
+        // the fake bulb answers zone requests like a strip
+        let AnyDevice::Strip(strip) = handle(32) else {
+            panic!("not a strip");
+        };
+        let zones = strip.get_zones().await.unwrap();
+        assert_eq!(zones.len(), crate::client::tests::FAKE_ZONES as usize);
+        assert_eq!(zones[5].hue, 5);
+        let AnyDevice::Strip(strip) = handle(117) else {
+            panic!("not a strip");
+        };
+        assert_eq!(strip.get_zones().await.unwrap(), zones);
+    }
+}
@@ -0,0 +1,384 @@
+//! Device state that one thread keeps up to date while others read it
+//!
+//! A common design is one thread receiving messages and updating a map of device state, and other
+//! threads (a UI, say) reading it.  Putting the whole map behind one `Mutex` makes every reader
+//! wait for every update and vice versa, which adds up with many devices.
+//!
+//! A [DeviceMap] splits the devices across several independently locked shards, and keeps each
+//! device's state behind an [Arc].  Updating a device only locks its own shard, and reading takes
+//! a [DeviceStateSnapshot], which is just another reference to the state: it never changes, and
+//! holding onto it doesn't hold up updates.  An update to a device that someone holds a snapshot
+//! of copies the state first, so the snapshot stays as it was.
+//!
+//! A thread can also block until some device gets into a particular state, with
+//! [DeviceMap::wait_for].
+//!
+//! ```no_run
+//! # fn example() -> Result<(), lifx::Error> {
+//! use lifx::devices::DeviceMap;
+//! use lifx::receiver::Receiver;
+//! use std::net::UdpSocket;
+//! use std::sync::Arc;
+//!
+//! let devices = Arc::new(DeviceMap::new());
+//! let socket = UdpSocket::bind("0.0.0.0:56700")?;
+//! let updater = devices.clone();
+//! let _receiver = Receiver::new(socket).spawn(move |raw, addr| {
+//!     let _ = updater.update_raw(&raw, addr);
+//! });
+//!
+//! // on another thread
+//! for device in devices.snapshot() {
+//!     println!("{}: {:?}", device.serial(), device.label);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::shard::jump_hash;
+use crate::state::DeviceState;
+use crate::Error;
+use lifx_core::{Message, RawMessage};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::ops::Deref;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+/// The number of shards in a [DeviceMap::new]
+pub const DEFAULT_SHARDS: usize = 16;
+
+/// The state of a device at some moment, which doesn't change
+///
+/// This dereferences to the [DeviceState], and is cheap to clone.
+#[derive(Debug, Clone)]
+pub struct DeviceStateSnapshot {
+    state: Arc<DeviceState>,
+    last_seen: Instant,
+}
+
+impl DeviceStateSnapshot {
+    /// When the last message from the device was received, as of this snapshot
+    pub fn last_seen(&self) -> Instant {
+        self.last_seen
+    }
+}
+
+impl Deref for DeviceStateSnapshot {
+    type Target = DeviceState;
+
+    fn deref(&self) -> &DeviceState {
+        &self.state
+    }
+}
+
+type Shard = RwLock<HashMap<u64, DeviceStateSnapshot>>;
+
+/// The state of many devices, shared between threads
+///
+/// See the [module documentation](self).
+#[derive(Debug)]
+pub struct DeviceMap {
+    shards: Box<[Shard]>,
+    generation: AtomicU64,
+    /// Held while the generation goes up, so that [DeviceMap::wait_for] can't miss a change
+    waiters: Mutex<()>,
+    changed: Condvar,
+}
+
+impl Default for DeviceMap {
+    fn default() -> DeviceMap {
+        DeviceMap::new()
+    }
+}
+
+impl DeviceMap {
+    /// Creates an empty map with [DEFAULT_SHARDS] shards
+    pub fn new() -> DeviceMap {
+        DeviceMap::with_shards(DEFAULT_SHARDS)
+    }
+
+    /// Creates an empty map with the given number of shards (at least one)
+    ///
+    /// More shards mean fewer threads waiting on the same lock, at the cost of a little memory,
+    /// and a little more work for [DeviceMap::snapshot].
+    pub fn with_shards(shards: usize) -> DeviceMap {
+        DeviceMap {
+            shards: (0..shards.max(1)).map(|_| Shard::default()).collect(),
+            generation: AtomicU64::new(0),
+            waiters: Mutex::new(()),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn shard(&self, target: u64) -> &Shard {
+        &self.shards[jump_hash(target, self.shards.len())]
+    }
+
+    /// Changes a device's state with `f` (adding the device if it's new), and returns what `f`
+    /// returned
+    fn modify(&self, target: u64, f: impl FnOnce(&mut DeviceState) -> bool) -> bool {
+        let mut shard = self.shard(target).write().unwrap();
+        let entry = shard.entry(target).or_insert_with(|| DeviceStateSnapshot {
+            state: Arc::new(DeviceState::new(target)),
+            last_seen: Instant::now(),
+        });
+        entry.last_seen = Instant::now();
+        let changed = f(Arc::make_mut(&mut entry.state));
+        if changed {
+            self.bump();
+        }
+        changed
+    }
+
+    /// Updates a device's state from a message it sent (see [DeviceState::update])
+    ///
+    /// The device is added if it's new.  Returns `true` if this changed the state.
+    pub fn update(&self, target: u64, addr: SocketAddr, msg: &Message) -> bool {
+        self.modify(target, |state| {
+            let moved = state.addr.replace(addr) != Some(addr);
+            state.update(msg) || moved
+        })
+    }
+
+    /// Decodes a message received from `addr`, and updates the state of the device that sent it
+    ///
+    /// Messages that aren't from a particular device (those with a target of `0`) are ignored.
+    pub fn update_raw(&self, raw: &RawMessage, addr: SocketAddr) -> Result<bool, lifx_core::Error> {
+        let target = raw.frame_addr.target;
+        if target == 0 {
+            return Ok(false);
+        }
+        let msg = Message::from_raw(raw)?;
+        Ok(self.update(target, addr, &msg))
+    }
+
+    /// Updates a device's state to reflect a command sent to it (see [DeviceState::apply])
+    ///
+    /// Returns `true` if this changed the state.  Devices that aren't in the map are ignored,
+    /// since there's nowhere to send a command to them.
+    pub fn apply(&self, target: u64, msg: &Message) -> bool {
+        let mut shard = self.shard(target).write().unwrap();
+        let Some(entry) = shard.get_mut(&target) else {
+            return false;
+        };
+        let changed = Arc::make_mut(&mut entry.state).apply(msg);
+        if changed {
+            self.bump();
+        }
+        changed
+    }
+
+    /// The current state of one device
+    pub fn get(&self, target: u64) -> Option<DeviceStateSnapshot> {
+        self.shard(target).read().unwrap().get(&target).cloned()
+    }
+
+    /// The current state of every device, ordered by target
+    ///
+    /// Each shard is locked only long enough to copy its references, so this doesn't hold up
+    /// updates for long.  Devices in different shards can be from slightly different moments,
+    /// if they're being updated at the same time.
+    pub fn snapshot(&self) -> Vec<DeviceStateSnapshot> {
+        let mut devices = Vec::with_capacity(self.len());
+        for shard in self.shards.iter() {
+            devices.extend(shard.read().unwrap().values().cloned());
+        }
+        devices.sort_by_key(|device| device.target);
+        devices
+    }
+
+    /// Forgets a device, returning its last state
+    pub fn remove(&self, target: u64) -> Option<DeviceStateSnapshot> {
+        let removed = self.shard(target).write().unwrap().remove(&target);
+        if removed.is_some() {
+            self.bump();
+        }
+        removed
+    }
+
+    /// The number of devices
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|s| s.read().unwrap().len()).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// A number that goes up every time any device's state changes (or a device is removed)
+    ///
+    /// A UI can compare this with the value from its last redraw, to skip taking a snapshot when
+    /// nothing has changed.
+    pub fn generation(&self) -> u64 {
+        self.generation.load(Ordering::Acquire)
+    }
+
+    /// Records that something changed, and wakes up anything waiting for a change
+    fn bump(&self) {
+        let _waiters = self.waiters.lock().unwrap();
+        self.generation.fetch_add(1, Ordering::Release);
+        self.changed.notify_all();
+    }
+
+    /// Blocks until a device's state satisfies `predicate`, and returns that state
+    ///
+    /// The devices are checked straight away, and then again every time any of them changes.
+    /// Returns [Error::Timeout] if none of them does in time.  This only sees the changes that
+    /// something else puts in the map, such as a [Receiver](crate::receiver::Receiver) thread or
+    /// a [SyncClient](crate::SyncClient) (see [SyncClient::wait_for](crate::SyncClient::wait_for)).
+    ///
+    /// ```no_run
+    /// # fn example(devices: &lifx::devices::DeviceMap) -> Result<(), lifx::Error> {
+    /// use std::time::Duration;
+    ///
+    /// let kitchen = devices.wait_for(
+    ///     |state| state.label.as_deref() == Some("Kitchen") && state.power > Some(0),
+    ///     Duration::from_secs(10),
+    /// )?;
+    /// println!("the kitchen light is on: {:?}", kitchen.color);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn wait_for(
+        &self,
+        mut predicate: impl FnMut(&DeviceState) -> bool,
+        timeout: Duration,
+    ) -> Result<DeviceStateSnapshot, Error> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let seen = self.generation();
+            if let Some(found) = self.snapshot().into_iter().find(|d| predicate(d)) {
+                return Ok(found);
+            }
+            // the shards are never locked while holding this, since updates lock them the other
+            // way round
+            let mut waiters = self.waiters.lock().unwrap();
+            while self.generation() == seen {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(Error::Timeout);
+                }
+                waiters = self.changed.wait_timeout(waiters, remaining).unwrap().0;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lifx_core::{LifxString, PowerLevel, HSBK};
+    use std::ffi::CString;
+    use std::thread;
+    use std::time::Duration;
+
+    fn addr() -> SocketAddr {
+        "127.0.0.1:56700".parse().unwrap()
+    }
+
+    #[test]
+    fn test_device_map() {
+        let devices = DeviceMap::with_shards(4);
+        assert!(devices.is_empty());
+        assert!(devices.update(2, addr(), &Message::StatePower { level: 65535 }));
+        assert!(!devices.update(2, addr(), &Message::StatePower { level: 65535 }));
+        assert!(devices.update(1, addr(), &Message::StatePower { level: 0 }));
+        assert_eq!(devices.generation(), 2);
+
+        // snapshots don't change when the device does
+        let before = devices.get(2).unwrap();
+        assert!(devices.apply(
+            2,
+            &Message::SetPower {
+                level: PowerLevel::Standby
+            }
+        ));
+        assert_eq!(before.power, Some(65535));
+        assert_eq!(devices.get(2).unwrap().power, Some(0));
+        assert!(!devices.apply(
+            3,
+            &Message::SetPower {
+                level: PowerLevel::Standby
+            }
+        ));
+
+        let targets: Vec<_> = devices.snapshot().iter().map(|d| d.target).collect();
+        assert_eq!(targets, vec![1, 2]);
+        assert!(devices.remove(1).is_some());
+        assert_eq!(devices.len(), 1);
+        assert_eq!(devices.generation(), 4);
+    }
+
+    #[test]
+    fn test_concurrent_snapshots() {
+        let devices = Arc::new(DeviceMap::new());
+        let writers: Vec<_> = (0..4u64)
+            .map(|writer| {
+                let devices = devices.clone();
+                thread::spawn(move || {
+                    for step in 0..500u16 {
+                        for target in (writer * 25)..(writer + 1) * 25 {
+                            let color = HSBK {
+                                hue: step,
+                                saturation: 0,
+                                brightness: 65535,
+                                kelvin: 3500,
+                            };
+                            let msg = Message::LightState {
+                                color,
+                                reserved: 0,
+                                power: 65535,
+                                label: LifxString::new(&CString::new("Light").unwrap()),
+                                reserved2: 0,
+                            };
+                            devices.update(target + 1, addr(), &msg);
+                        }
+                    }
+                })
+            })
+            .collect();
+
+        // readers never see a half-updated device, or the same device twice
+        while writers.iter().any(|w| !w.is_finished()) {
+            let snapshot = devices.snapshot();
+            assert!(snapshot.windows(2).all(|w| w[0].target < w[1].target));
+            assert!(snapshot
+                .iter()
+                .all(|d| d.color.is_some() && d.power.is_some()));
+            thread::sleep(Duration::from_millis(1));
+        }
+        for writer in writers {
+            writer.join().unwrap();
+        }
+        let snapshot = devices.snapshot();
+        assert_eq!(snapshot.len(), 100);
+        assert!(snapshot.iter().all(|d| d.color.unwrap().hue == 499));
+    }
+
+    #[test]
+    fn test_wait_for() {
+        let devices = Arc::new(DeviceMap::new());
+        devices.update(1, addr(), &Message::StatePower { level: 0 });
+        let is_on = |state: &DeviceState| state.power > Some(0);
+        assert!(matches!(
+            devices.wait_for(is_on, Duration::from_millis(20)),
+            Err(Error::Timeout)
+        ));
+
+        let updater = devices.clone();
+        let writer = thread::spawn(move || {
+            for target in 2..=3 {
+                thread::sleep(Duration::from_millis(20));
+                updater.update(target, addr(), &Message::StatePower { level: 0 });
+            }
+            updater.update(3, addr(), &Message::StatePower { level: 65535 });
+        });
+        let found = devices.wait_for(is_on, Duration::from_secs(5)).unwrap();
+        assert_eq!((found.target, found.power), (3, Some(65535)));
+        writer.join().unwrap();
+        // something that's already true is returned straight away
+        assert_eq!(devices.wait_for(is_on, Duration::ZERO).unwrap().target, 3);
+    }
+}
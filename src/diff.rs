@@ -0,0 +1,209 @@
+//! Working out which messages will move a device from one state to another
+//!
+//! [diff] compares the state a device is in (as cached in a [DeviceState]) with the state it
+//! should be in, and returns only the messages needed to get there.  Sending the result twice is
+//! harmless, so this is the building block for declarative control: describe what the lights
+//! should look like, and let `diff` decide what to send.
+//!
+//! ```
+//! use lifx::diff::diff;
+//! use lifx::DeviceState;
+//! use lifx_core::{Message, PowerLevel};
+//!
+//! let mut current = DeviceState::new(0xd073d5001337);
+//! current.update(&Message::StatePower { level: 0 });
+//!
+//! let mut desired = current.clone();
+//! desired.power = Some(65535);
+//! assert_eq!(diff(&current, &desired), vec![Message::SetPower { level: PowerLevel::Enabled }]);
+//! assert!(diff(&desired, &desired).is_empty());
+//! ```
+
+use crate::state::DeviceState;
+use lifx_core::{ApplicationRequest, Message, PowerLevel, ZoneRange, HSBK};
+use std::time::Duration;
+
+/// The messages needed to change a device from `current` to `desired`
+///
+/// Only power, color, zones, and infrared are compared.  Fields that are `None` in `desired` are
+/// left alone, and fields that are `None` in `current` are assumed to be different.  Zones that
+/// are `None` in `desired` are also left alone.
+///
+/// Messages are ordered so that nothing looks wrong part of the way through: a device that's
+/// being turned off is turned off first, and a device that's being turned on is only turned on
+/// once it has its new colors.  Zone changes are batched into as few [Message::SetColorZones] as
+/// possible, and only the last one applies them, so they all change at once.
+pub fn diff(current: &DeviceState, desired: &DeviceState) -> Vec<Message> {
+    let mut messages = Vec::new();
+
+    let power = match (current.power, desired.power) {
+        (_, None) => None,
+        (Some(have), Some(want)) if (have == 0) == (want == 0) => None,
+        (_, Some(0)) => Some(Message::SetPower {
+            level: PowerLevel::Standby,
+        }),
+        (_, Some(_)) => Some(Message::SetPower {
+            level: PowerLevel::Enabled,
+        }),
+    };
+    let turning_on = desired.power.is_some_and(|p| p != 0);
+
+    if !turning_on {
+        messages.extend(power.clone());
+    }
+    if let Some(color) = desired.color {
+        if current.color != Some(color) {
+            messages.push(Message::set_color(color, Duration::ZERO));
+        }
+    }
+    if let Some(zones) = &desired.zones {
+        messages.extend(zone_messages(current.zones.as_deref(), zones));
+    }
+    if let Some(brightness) = desired.infrared {
+        if current.infrared != Some(brightness) {
+            messages.push(Message::LightSetInfrared { brightness });
+        }
+    }
+    if turning_on {
+        messages.extend(power);
+    }
+    messages
+}
+
+/// [Message::SetColorZones] for each run of changed zones that have the same desired color
+fn zone_messages(current: Option<&[Option<HSBK>]>, desired: &[Option<HSBK>]) -> Vec<Message> {
+    // (start, end, color) for each run
+    let mut runs: Vec<(u8, u8, HSBK)> = Vec::new();
+    for (index, want) in desired.iter().enumerate().take(256) {
+        let want = match want {
+            Some(want) => *want,
+            None => continue,
+        };
+        let have = current
+            .and_then(|zones| zones.get(index))
+            .copied()
+            .flatten();
+        if have == Some(want) {
+            continue;
+        }
+        let index = index as u8;
+        match runs.last_mut() {
+            Some((_, end, color)) if *end + 1 == index && *color == want => *end = index,
+            _ => runs.push((index, index, want)),
+        }
+    }
+
+    let last = runs.len().saturating_sub(1);
+    runs.into_iter()
+        .enumerate()
+        .map(|(i, (start, end, color))| {
+            let apply = match i == last {
+                true => ApplicationRequest::Apply,
+                false => ApplicationRequest::NoApply,
+            };
+            // start is never after end, so this can't fail
+            let range = ZoneRange::new(start, end).unwrap();
+            Message::set_color_zones(range, color, Duration::ZERO, apply)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn color(hue: u16) -> HSBK {
+        HSBK {
+            hue,
+            saturation: 65535,
+            brightness: 65535,
+            kelvin: 3500,
+        }
+    }
+
+    fn names(messages: &[Message]) -> Vec<u16> {
+        messages.iter().map(|m| m.get_num()).collect()
+    }
+
+    #[test]
+    fn test_order() {
+        let mut off = DeviceState::new(1);
+        off.power = Some(0);
+        off.color = Some(color(0));
+        let mut on = DeviceState::new(1);
+        on.power = Some(65535);
+        on.color = Some(color(100));
+
+        // color first, then power on
+        assert_eq!(names(&diff(&off, &on)), vec![102, 21]);
+        // power off first, then color
+        assert_eq!(names(&diff(&on, &off)), vec![21, 102]);
+
+        // unknown state gets everything
+        assert_eq!(diff(&DeviceState::new(1), &on).len(), 2);
+        // and nothing desired means nothing to do
+        assert!(diff(&on, &DeviceState::new(1)).is_empty());
+    }
+
+    #[test]
+    fn test_zones() {
+        let mut current = DeviceState::new(1);
+        current.zones = Some(vec![Some(color(0)); 6]);
+        let mut desired = DeviceState::new(1);
+        desired.zones = Some(vec![
+            Some(color(0)),
+            Some(color(5)),
+            Some(color(5)),
+            None,
+            Some(color(5)),
+            Some(color(7)),
+        ]);
+
+        let messages = diff(&current, &desired);
+        let runs: Vec<_> = messages
+            .iter()
+            .map(|m| match *m {
+                Message::SetColorZones {
+                    start_index,
+                    end_index,
+                    color,
+                    apply,
+                    ..
+                } => (start_index, end_index, color.hue, apply),
+                ref m => panic!("unexpected message {:?}", m),
+            })
+            .collect();
+        assert_eq!(
+            runs,
+            vec![
+                (1, 2, 5, ApplicationRequest::NoApply),
+                (4, 4, 5, ApplicationRequest::NoApply),
+                (5, 5, 7, ApplicationRequest::Apply),
+            ]
+        );
+
+        // applying the changes leaves nothing else to do
+        for (zone, want) in current
+            .zones
+            .as_mut()
+            .unwrap()
+            .iter_mut()
+            .zip(&desired.zones.clone().unwrap())
+        {
+            if want.is_some() {
+                *zone = *want;
+            }
+        }
+        assert!(diff(&current, &desired).is_empty());
+    }
+
+    #[test]
+    fn test_infrared() {
+        let mut desired = DeviceState::new(1);
+        desired.infrared = Some(1000);
+        assert_eq!(
+            diff(&DeviceState::new(1), &desired),
+            vec![Message::LightSetInfrared { brightness: 1000 }]
+        );
+    }
+}
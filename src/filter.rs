@@ -0,0 +1,153 @@
+//! Only accepting certain types of message from the network
+//!
+//! A bridge that exposes LIFX devices to something else should only react to the messages it
+//! expects.  A [MessageFilter] is an allow-list or deny-list of message types, checked as soon as
+//! a datagram's header has been read, before the payload is decoded or anything acts on it.
+//! [Client::set_filter](crate::Client::set_filter) and
+//! [PassiveObserver::set_filter](crate::observer::PassiveObserver::set_filter) both take one.
+//!
+//! Filters can also be built from whole [MessageCategory]s, such as all of the multizone traffic,
+//! with [MessageFilter::allow_categories] and [MessageFilter::deny_categories].
+//!
+//! Every message that's dropped is counted, both by the filter itself (see
+//! [MessageFilter::dropped]) and in [telemetry](crate::telemetry::MESSAGES_FILTERED).
+//!
+//! ```
+//! use lifx::filter::MessageFilter;
+//! use lifx_core::{Message, PowerLevel};
+//!
+//! // a listener that should only ever see devices reporting their power and color
+//! let filter = MessageFilter::allow([22, 107]); // StatePower, LightState
+//! assert!(filter.check(22));
+//! let set_power = Message::SetPower { level: PowerLevel::Enabled };
+//! assert!(!filter.check(set_power.get_num()));
+//! assert_eq!(filter.dropped(), 1);
+//! ```
+
+use crate::telemetry;
+use lifx_core::MessageCategory;
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Rule {
+    Allow(HashSet<u16>),
+    Deny(HashSet<u16>),
+}
+
+/// Decides which message types to accept
+///
+/// This is cheap to clone, and all clones share the same count of dropped messages.
+#[derive(Debug, Clone)]
+pub struct MessageFilter {
+    rule: Rule,
+    dropped: Arc<AtomicU64>,
+}
+
+impl MessageFilter {
+    fn new(rule: Rule) -> MessageFilter {
+        MessageFilter {
+            rule,
+            dropped: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Accepts only the given message types
+    pub fn allow(types: impl IntoIterator<Item = u16>) -> MessageFilter {
+        MessageFilter::new(Rule::Allow(types.into_iter().collect()))
+    }
+
+    /// Accepts every message type except the given ones
+    pub fn deny(types: impl IntoIterator<Item = u16>) -> MessageFilter {
+        MessageFilter::new(Rule::Deny(types.into_iter().collect()))
+    }
+
+    /// Accepts only the message types in the given categories
+    ///
+    /// ```
+    /// use lifx::filter::MessageFilter;
+    /// use lifx_core::MessageCategory;
+    ///
+    /// let filter = MessageFilter::allow_categories([MessageCategory::MultiZone]);
+    /// assert!(filter.permits(506)); // StateMultiZone
+    /// assert!(!filter.permits(107)); // LightState
+    /// ```
+    pub fn allow_categories(
+        categories: impl IntoIterator<Item = MessageCategory>,
+    ) -> MessageFilter {
+        MessageFilter::allow(categories.into_iter().flat_map(MessageCategory::types))
+    }
+
+    /// Accepts every message type except those in the given categories
+    ///
+    /// Unknown message types aren't in any category, so they're still accepted.
+    pub fn deny_categories(categories: impl IntoIterator<Item = MessageCategory>) -> MessageFilter {
+        MessageFilter::deny(categories.into_iter().flat_map(MessageCategory::types))
+    }
+
+    /// Whether a message type would be accepted, without counting anything
+    pub fn permits(&self, typ: u16) -> bool {
+        match &self.rule {
+            Rule::Allow(types) => types.contains(&typ),
+            Rule::Deny(types) => !types.contains(&typ),
+        }
+    }
+
+    /// Checks a received message type, counting it as dropped if it isn't accepted
+    pub fn check(&self, typ: u16) -> bool {
+        let permitted = self.permits(typ);
+        if !permitted {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            telemetry::message_filtered(typ);
+        }
+        permitted
+    }
+
+    /// The number of messages dropped by this filter (and its clones) so far
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+/// The filter attached to a client, used by its receive task
+pub(crate) type FilterSlot = Arc<Mutex<Option<MessageFilter>>>;
+
+/// Checks a message type against the filter in a slot, if there is one
+pub(crate) fn check(slot: &FilterSlot, typ: u16) -> bool {
+    match &*slot.lock().unwrap() {
+        Some(filter) => filter.check(typ),
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deny() {
+        let filter = MessageFilter::deny([21, 117]);
+        let clone = filter.clone();
+        assert!(filter.check(22));
+        assert!(!filter.check(21));
+        assert!(!clone.check(117));
+        assert!(!filter.permits(117));
+        assert_eq!(filter.dropped(), 2);
+    }
+
+    #[test]
+    fn test_categories() {
+        let filter =
+            MessageFilter::deny_categories([MessageCategory::Light, MessageCategory::Tile]);
+        assert!(!filter.permits(107));
+        assert!(!filter.permits(720));
+        assert!(filter.permits(22));
+        assert!(filter.permits(9999));
+
+        let filter = MessageFilter::allow_categories([MessageCategory::Relay]);
+        assert!((0..=u16::MAX)
+            .filter(|&t| filter.permits(t))
+            .eq([816, 817, 818]));
+    }
+}
@@ -0,0 +1,345 @@
+//! Wrapping datagrams in an extra layer, for talking to devices through a relay
+//!
+//! LIFX devices only speak plain UDP on their own network, so controlling lights at another site
+//! means going through something there that passes datagrams on: a relay at the far end of a VPN,
+//! for instance.  Traffic between the controller and the relay can be wrapped in whatever the
+//! relay expects: a header that says which site or device it's for, a MAC that proves where it
+//! came from, or encryption.
+//!
+//! A [Framing] does the wrapping and unwrapping, and [Framed] puts it around any [Transport] or
+//! [BlockingTransport], so that [Client] and [SyncClient](crate::SyncClient) work through it
+//! without knowing it's there.  Datagrams that the framing rejects (because the MAC is wrong,
+//! say) are dropped as if they never arrived.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), lifx::Error> {
+//! use lifx::framing::{Framed, Prefix};
+//! use lifx::{Client, ClientOptions};
+//! use tokio::net::UdpSocket;
+//!
+//! let socket = UdpSocket::bind("0.0.0.0:0").await?;
+//! let transport = Framed::new(socket, Prefix::new(b"site-7:".to_vec()));
+//! let client = Client::with_transport(transport, ClientOptions::default());
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::transport::{BlockingTransport, Transport, TransportFuture, Truncated};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// The largest framed datagram that a [Framed] transport can receive, which is the largest that
+/// UDP can carry
+pub const MAX_FRAMED_SIZE: usize = 65536;
+
+/// A way of wrapping datagrams before they're sent, and unwrapping them when they arrive
+///
+/// Both methods may be called from several threads at once.
+pub trait Framing: Send + Sync + 'static {
+    /// Wraps a datagram that's about to be sent to `to`
+    ///
+    /// An error fails the send.
+    fn wrap(&self, datagram: &[u8], to: SocketAddr) -> io::Result<Vec<u8>>;
+
+    /// Unwraps a datagram that arrived from `from`, or returns `None` to drop it
+    fn unwrap(&self, datagram: &[u8], from: SocketAddr) -> Option<Vec<u8>>;
+}
+
+/// A [Framing] made of a pair of functions
+///
+/// See [framing_fn].
+pub struct FramingFn<W, U> {
+    wrap: W,
+    unwrap: U,
+}
+
+/// Makes a [Framing] out of a function that wraps datagrams, and one that unwraps them
+pub fn framing_fn<W, U>(wrap: W, unwrap: U) -> FramingFn<W, U>
+where
+    W: Fn(&[u8], SocketAddr) -> io::Result<Vec<u8>> + Send + Sync + 'static,
+    U: Fn(&[u8], SocketAddr) -> Option<Vec<u8>> + Send + Sync + 'static,
+{
+    FramingFn { wrap, unwrap }
+}
+
+impl<W, U> Framing for FramingFn<W, U>
+where
+    W: Fn(&[u8], SocketAddr) -> io::Result<Vec<u8>> + Send + Sync + 'static,
+    U: Fn(&[u8], SocketAddr) -> Option<Vec<u8>> + Send + Sync + 'static,
+{
+    fn wrap(&self, datagram: &[u8], to: SocketAddr) -> io::Result<Vec<u8>> {
+        (self.wrap)(datagram, to)
+    }
+
+    fn unwrap(&self, datagram: &[u8], from: SocketAddr) -> Option<Vec<u8>> {
+        (self.unwrap)(datagram, from)
+    }
+}
+
+/// Puts the same bytes in front of every datagram, and drops any datagram that doesn't start
+/// with them
+///
+/// This doesn't make anything secure, since anyone can copy the prefix, but it's enough for a
+/// relay that serves several sites to tell them apart.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prefix {
+    prefix: Vec<u8>,
+}
+
+impl Prefix {
+    pub fn new(prefix: Vec<u8>) -> Prefix {
+        Prefix { prefix }
+    }
+}
+
+impl Framing for Prefix {
+    fn wrap(&self, datagram: &[u8], _: SocketAddr) -> io::Result<Vec<u8>> {
+        Ok([&self.prefix[..], datagram].concat())
+    }
+
+    fn unwrap(&self, datagram: &[u8], _: SocketAddr) -> Option<Vec<u8>> {
+        datagram.strip_prefix(&self.prefix[..]).map(<[u8]>::to_vec)
+    }
+}
+
+/// A transport that wraps everything it sends with a [Framing], and unwraps everything it
+/// receives
+pub struct Framed<T, F> {
+    inner: T,
+    framing: F,
+    /// Receive buffers that aren't in use, so that receiving doesn't allocate a new one each time
+    ///
+    /// This ends up holding as many buffers as there have ever been receives running at once.
+    spare: Mutex<Vec<Vec<u8>>>,
+}
+
+impl<T, F: Framing> Framed<T, F> {
+    pub fn new(inner: T, framing: F) -> Framed<T, F> {
+        Framed {
+            inner,
+            framing,
+            spare: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// The transport underneath
+    pub fn get_ref(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// A buffer big enough for any framed datagram
+    fn take_buffer(&self) -> Vec<u8> {
+        let spare = self.spare.lock().unwrap().pop();
+        spare.unwrap_or_else(|| vec![0; MAX_FRAMED_SIZE])
+    }
+
+    /// Keeps a buffer from [Framed::take_buffer] for the next receive
+    fn put_buffer(&self, buf: Vec<u8>) {
+        self.spare.lock().unwrap().push(buf);
+    }
+
+    /// Unwraps a datagram that arrived, and copies it into `buf`
+    ///
+    /// Returns `None` if the framing rejected it.
+    fn unwrap_into(
+        &self,
+        framed: &[u8],
+        from: SocketAddr,
+        buf: &mut [u8],
+    ) -> Option<io::Result<(usize, SocketAddr)>> {
+        let Some(datagram) = self.framing.unwrap(framed, from) else {
+            log::debug!(
+                "dropping a datagram from {} that couldn't be unwrapped",
+                from
+            );
+            return None;
+        };
+        // like a socket, a datagram that fills the buffer counts as truncated
+        if datagram.len() >= buf.len() {
+            return Some(Err(Truncated { from: Some(from) }.into()));
+        }
+        buf[..datagram.len()].copy_from_slice(&datagram);
+        Some(Ok((datagram.len(), from)))
+    }
+}
+
+impl<T: Transport, F: Framing> Transport for Framed<T, F> {
+    fn send_to<'a>(&'a self, buf: &'a [u8], addr: SocketAddr) -> TransportFuture<'a, usize> {
+        Box::pin(async move {
+            let framed = self.framing.wrap(buf, addr)?;
+            self.inner.send_to(&framed, addr).await?;
+            Ok(buf.len())
+        })
+    }
+
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> TransportFuture<'a, (usize, SocketAddr)> {
+        Box::pin(async move {
+            let mut framed = self.take_buffer();
+            let res = loop {
+                let (len, from) = match self.inner.recv_from(&mut framed).await {
+                    Ok(received) => received,
+                    Err(e) => break Err(e),
+                };
+                if let Some(res) = self.unwrap_into(&framed[..len], from, buf) {
+                    break res;
+                }
+            };
+            self.put_buffer(framed);
+            res
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+impl<T: BlockingTransport, F: Framing> BlockingTransport for Framed<T, F> {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        let framed = self.framing.wrap(buf, addr)?;
+        self.inner.send_to(&framed, addr)?;
+        Ok(buf.len())
+    }
+
+    fn recv_from(
+        &self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<(usize, SocketAddr)> {
+        let deadline = timeout.map(|timeout| Instant::now() + timeout);
+        let mut framed = self.take_buffer();
+        let res = loop {
+            // rejected datagrams don't get any more time to wait
+            let remaining = match deadline {
+                Some(deadline) => match deadline.checked_duration_since(Instant::now()) {
+                    Some(remaining) if !remaining.is_zero() => Some(remaining),
+                    _ => break Err(io::ErrorKind::TimedOut.into()),
+                },
+                None => None,
+            };
+            let (len, from) = match self.inner.recv_from(&mut framed, remaining) {
+                Ok(received) => received,
+                Err(e) => break Err(e),
+            };
+            if let Some(res) = self.unwrap_into(&framed[..len], from, buf) {
+                break res;
+            }
+        };
+        self.put_buffer(framed);
+        res
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.inner.local_addr()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::{spawn_fake_bulb, Faults};
+    use crate::transport::{self, LoopbackNetwork, RECV_BUFFER_SIZE};
+    use crate::{Client, ClientOptions, Error};
+    use lifx_core::Message;
+
+    /// A stand-in for a MAC: a one-byte sum of the key and the datagram, at the end
+    fn checksum(key: u8) -> impl Framing {
+        let sum = move |datagram: &[u8]| {
+            datagram
+                .iter()
+                .fold(key, |sum: u8, &byte| sum.wrapping_add(byte))
+        };
+        framing_fn(
+            move |datagram, _| Ok([datagram, &[sum(datagram)]].concat()),
+            move |datagram, _| {
+                let (&mac, datagram) = datagram.split_last()?;
+                (mac == sum(datagram)).then(|| datagram.to_vec())
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn test_framed() {
+        let network = LoopbackNetwork::new();
+        let relay = network.bind("10.0.0.2:56700".parse().unwrap()).unwrap();
+        let relay_addr = relay.local_addr().unwrap();
+        spawn_fake_bulb(
+            Framed::new(relay, checksum(7)),
+            0x1234,
+            "Relay",
+            Faults::default(),
+        );
+        let wrong = network.bind("10.0.0.3:56700".parse().unwrap()).unwrap();
+        let wrong_addr = wrong.local_addr().unwrap();
+        spawn_fake_bulb(
+            Framed::new(wrong, checksum(8)),
+            0x1234,
+            "Wrong",
+            Faults::default(),
+        );
+        let local = Framed::new(
+            network.bind("10.0.0.1:0".parse().unwrap()).unwrap(),
+            checksum(7),
+        );
+        let local_addr = local.local_addr().unwrap();
+        let options = ClientOptions {
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        };
+        let client = Client::with_transport(local, options);
+
+        client
+            .send_acked(relay_addr, 0x1234, Message::GetPower)
+            .await
+            .unwrap();
+
+        // a relay with the wrong key drops everything from the client
+        let res = client
+            .send_acked(wrong_addr, 0x1234, Message::GetPower)
+            .await;
+        assert!(matches!(res, Err(Error::Timeout)), "{:?}", res);
+        assert_eq!(
+            checksum(7).unwrap(&[1, 2, 10], local_addr),
+            Some(vec![1, 2])
+        );
+    }
+
+    #[test]
+    fn test_blocking_framed() {
+        let relay = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let relay_addr = relay.local_addr().unwrap();
+        let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+        let framed = Framed::new(socket, Prefix::new(b"site-7:".to_vec()));
+        let local_addr = BlockingTransport::local_addr(&framed).unwrap();
+
+        // datagrams are prefixed on the way out
+        assert_eq!(
+            BlockingTransport::send_to(&framed, b"ping", relay_addr).unwrap(),
+            4
+        );
+        let mut buf = [0; RECV_BUFFER_SIZE];
+        let (n, _) = relay.recv_from(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"site-7:ping");
+
+        // and anything that arrives without the prefix is dropped
+        relay.send_to(b"pong", local_addr).unwrap();
+        relay.send_to(b"site-7:pong", local_addr).unwrap();
+        let timeout = Some(Duration::from_secs(1));
+        let (n, from) = BlockingTransport::recv_from(&framed, &mut buf, timeout).unwrap();
+        assert_eq!((&buf[..n], from), (&b"pong"[..], relay_addr));
+
+        relay.send_to(b"pong", local_addr).unwrap();
+        let timeout = Some(Duration::from_millis(50));
+        let err = BlockingTransport::recv_from(&framed, &mut buf, timeout).unwrap_err();
+        assert_eq!(transport::classify(&err), transport::ErrorClass::Transient);
+
+        // one receive at a time only ever needs one buffer
+        assert_eq!(framed.spare.lock().unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,190 @@
+//! Talking to devices on several network interfaces
+//!
+//! A [Client] bound to the unspecified address (the default) leaves it up to the OS to pick which
+//! interface broadcasts go out of, which on a host with more than one network (or in a container)
+//! usually means that only one of them gets discovered.  A [MultiHomeClient] has a client bound to
+//! each [Interface], discovers devices on all of them at once, and says which interface each
+//! device was found on.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), lifx::Error> {
+//! use lifx::interface::{Interface, MultiHomeClient};
+//! use lifx::ClientOptions;
+//! use std::time::Duration;
+//!
+//! let interfaces = vec![
+//!     Interface::new("eth0", [192, 168, 1, 10].into(), [255, 255, 255, 0].into()),
+//!     Interface::new("wlan0", [10, 0, 0, 5].into(), [255, 255, 0, 0].into()),
+//! ];
+//! let client = MultiHomeClient::new(interfaces, ClientOptions::default()).await?;
+//! for found in client.discover(Duration::from_secs(1)).await {
+//!     println!("{:016X} on {}", found.device.target, found.interface);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::{Client, ClientOptions, DiscoveredDevice};
+use crate::Error;
+use lifx_core::LIFX_PORT;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+/// A local network interface
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interface {
+    /// A name for the interface (like `eth0`), used to label the devices found on it
+    pub name: String,
+    /// The interface's own address, which its client is bound to
+    pub addr: Ipv4Addr,
+    /// Where discovery requests are sent: usually the broadcast address of the interface's subnet
+    pub broadcast: SocketAddr,
+}
+
+impl Interface {
+    /// Describes an interface by its address and netmask, broadcasting to its subnet
+    pub fn new(name: impl Into<String>, addr: Ipv4Addr, netmask: Ipv4Addr) -> Interface {
+        let broadcast = Ipv4Addr::from(u32::from(addr) | !u32::from(netmask));
+        Interface {
+            name: name.into(),
+            addr,
+            broadcast: SocketAddr::from((broadcast, LIFX_PORT)),
+        }
+    }
+
+    /// `options`, but bound to this interface's address (with any free port)
+    pub fn client_options(&self, options: ClientOptions) -> ClientOptions {
+        ClientOptions {
+            bind_addr: SocketAddr::from((self.addr, 0)),
+            ..options
+        }
+    }
+}
+
+/// A device found by [MultiHomeClient::discover]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceDevice {
+    /// The name of the interface that the device was found on
+    pub interface: String,
+    pub device: DiscoveredDevice,
+}
+
+/// A [Client] for each of several network interfaces
+///
+/// This is cheap to clone, and all clones share the same clients.
+#[derive(Clone)]
+pub struct MultiHomeClient {
+    clients: Vec<(Interface, Client)>,
+}
+
+impl MultiHomeClient {
+    /// Creates a client bound to each interface
+    ///
+    /// Every client uses the same [ClientOptions] (apart from the bind address), including the
+    /// source ID.  This must be called from within a tokio runtime.
+    pub async fn new(
+        interfaces: Vec<Interface>,
+        options: ClientOptions,
+    ) -> Result<MultiHomeClient, Error> {
+        let mut clients = Vec::with_capacity(interfaces.len());
+        for interface in interfaces {
+            let client = Client::with_options(interface.client_options(options)).await?;
+            clients.push((interface, client));
+        }
+        Ok(MultiHomeClient { clients })
+    }
+
+    /// Every interface, with its client
+    pub fn clients(&self) -> &[(Interface, Client)] {
+        &self.clients
+    }
+
+    /// The client for an interface, by name
+    pub fn client(&self, interface: &str) -> Option<&Client> {
+        self.clients
+            .iter()
+            .find(|(i, _)| i.name == interface)
+            .map(|(_, client)| client)
+    }
+
+    /// Discovers devices on every interface at once, waiting `wait` for replies
+    ///
+    /// Each device is only listed once.  If it was found on more than one interface, it's listed
+    /// with the first of them, in the order the interfaces were given.  Interfaces where discovery
+    /// fails (because the interface has gone down, say) are logged and skipped.
+    pub async fn discover(&self, wait: Duration) -> Vec<InterfaceDevice> {
+        let tasks: Vec<_> = self
+            .clients
+            .iter()
+            .map(|(interface, client)| {
+                let (client, broadcast) = (client.clone(), interface.broadcast);
+                tokio::spawn(async move { client.discover_on(broadcast, wait).await })
+            })
+            .collect();
+
+        let mut found: Vec<InterfaceDevice> = Vec::new();
+        for ((interface, _), task) in self.clients.iter().zip(tasks) {
+            let devices = match task.await {
+                Ok(Ok(devices)) => devices,
+                Ok(Err(e)) => {
+                    log::warn!("discovery on {} failed: {}", interface.name, e);
+                    continue;
+                }
+                Err(e) => std::panic::resume_unwind(e.into_panic()),
+            };
+            for device in devices {
+                if !found.iter().any(|f| f.device.target == device.target) {
+                    found.push(InterfaceDevice {
+                        interface: interface.name.clone(),
+                        device,
+                    });
+                }
+            }
+        }
+        found
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::fake_bulb;
+
+    #[test]
+    fn test_interface() {
+        let interface = Interface::new("eth0", [192, 168, 1, 10].into(), [255, 255, 254, 0].into());
+        assert_eq!(interface.broadcast, "192.168.1.255:56700".parse().unwrap());
+        let options = interface.client_options(ClientOptions::default());
+        assert_eq!(options.bind_addr, "192.168.1.10:0".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_discover() {
+        let kitchen = fake_bulb(1, "Kitchen").await;
+        let garage = fake_bulb(2, "Garage").await;
+        let interface = |name: &str, broadcast| Interface {
+            name: name.to_owned(),
+            addr: Ipv4Addr::LOCALHOST,
+            broadcast,
+        };
+        let client = MultiHomeClient::new(
+            vec![
+                interface("lan", kitchen),
+                interface("iot", garage),
+                interface("vpn", kitchen),
+            ],
+            ClientOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert!(client.client("iot").is_some());
+        assert!(client.client("wan").is_none());
+
+        let found = client.discover(Duration::from_millis(200)).await;
+        let found: Vec<_> = found
+            .iter()
+            .map(|f| (f.interface.as_str(), f.device.target))
+            .collect();
+        assert_eq!(found, vec![("lan", 1), ("iot", 2)]);
+    }
+}
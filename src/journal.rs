@@ -0,0 +1,220 @@
+//! Keeping a record of every change to every device, for working out what changed what
+//!
+//! In a household with several apps, hubs, and automations all controlling the same lights, it
+//! can be hard to tell why a light did something.  A [Journal] attached to a
+//! [PassiveObserver](crate::observer::PassiveObserver) gets a [JournalEntry] for every state
+//! change the observer sees: which device changed, when, what it changed from and to, and the
+//! message and controller (by address and source ID) responsible.  Entries go to a
+//! [JournalSink], which can be anything from a log file to a database.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), lifx::Error> {
+//! use lifx::journal::{Journal, WriteSink};
+//! use lifx::observer::PassiveObserver;
+//! use std::fs::File;
+//!
+//! let mut observer = PassiveObserver::new().await?;
+//! let log = File::create("lights.log")?;
+//! observer.set_journal(Some(Journal::new(WriteSink::new(log))));
+//! loop {
+//!     observer.recv().await?;
+//! }
+//! # }
+//! ```
+
+use crate::state::DeviceState;
+use lifx_core::Message;
+use std::fmt;
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One change to the state of one device
+#[derive(Debug, Clone, PartialEq)]
+pub struct JournalEntry {
+    /// When the change was seen
+    pub at: SystemTime,
+    /// Where the message that caused the change came from
+    ///
+    /// For a command, this is the controller that sent it.  For a state report, it's the device.
+    pub addr: SocketAddr,
+    /// The source ID of the controller that sent the command, or that asked for the state report
+    ///
+    /// This is `None` when the source ID is 0, which devices use for reports that nobody asked
+    /// for.
+    pub source: Option<u32>,
+    /// The message that caused the change
+    pub message: Message,
+    /// The state of the device before the change
+    pub before: DeviceState,
+    /// The state of the device after the change
+    pub after: DeviceState,
+}
+
+impl JournalEntry {
+    /// The device that changed
+    pub fn target(&self) -> u64 {
+        self.after.target
+    }
+
+    /// The names of the [DeviceState] fields that changed
+    pub fn changed_fields(&self) -> Vec<&'static str> {
+        let (a, b) = (&self.before, &self.after);
+        let fields = [
+            ("label", a.label != b.label),
+            ("power", a.power != b.power),
+            ("color", a.color != b.color),
+            ("zones", a.zones != b.zones),
+            ("infrared", a.infrared != b.infrared),
+            ("group", a.group != b.group),
+            ("location", a.location != b.location),
+            ("version", a.version != b.version),
+            ("firmware", a.firmware != b.firmware),
+        ];
+        fields
+            .iter()
+            .filter(|(_, changed)| *changed)
+            .map(|(name, _)| *name)
+            .collect()
+    }
+}
+
+/// Formats an entry as a single line, for example
+/// `1718000000.123 0000D073D5001337 "Kitchen" color changed by 192.168.1.20:56700 (source 0000ABCD):
+/// LightSetColor { .. }`
+impl fmt::Display for JournalEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let at = self.at.duration_since(UNIX_EPOCH).unwrap_or_default();
+        write!(
+            f,
+            "{}.{:03} {:016X}",
+            at.as_secs(),
+            at.subsec_millis(),
+            self.target()
+        )?;
+        if let Some(label) = &self.after.label {
+            write!(f, " {:?}", label)?;
+        }
+        write!(
+            f,
+            " {} changed by {}",
+            self.changed_fields().join(", "),
+            self.addr
+        )?;
+        if let Some(source) = self.source {
+            write!(f, " (source {:08X})", source)?;
+        }
+        write!(f, ": {:?}", self.message)
+    }
+}
+
+/// Somewhere to keep journal entries
+///
+/// This is implemented for closures, so a sink can be as simple as
+/// `|entry: &JournalEntry| { entries.push(entry.clone()); Ok(()) }`.
+pub trait JournalSink: Send + 'static {
+    fn record(&mut self, entry: &JournalEntry) -> io::Result<()>;
+}
+
+impl<F> JournalSink for F
+where
+    F: FnMut(&JournalEntry) -> io::Result<()> + Send + 'static,
+{
+    fn record(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        self(entry)
+    }
+}
+
+/// Writes each entry as a line of text, in the format of its [Display](fmt::Display) impl
+#[derive(Debug)]
+pub struct WriteSink<W> {
+    writer: W,
+}
+
+impl<W: Write + Send + 'static> WriteSink<W> {
+    pub fn new(writer: W) -> WriteSink<W> {
+        WriteSink { writer }
+    }
+}
+
+impl<W: Write + Send + 'static> JournalSink for WriteSink<W> {
+    fn record(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        writeln!(self.writer, "{}", entry)?;
+        self.writer.flush()
+    }
+}
+
+/// Sends journal entries to a [JournalSink]
+pub struct Journal {
+    sink: Box<dyn JournalSink>,
+}
+
+impl Journal {
+    pub fn new(sink: impl JournalSink) -> Journal {
+        Journal {
+            sink: Box::new(sink),
+        }
+    }
+
+    /// Records a change, unless nothing actually changed
+    pub fn record(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        if entry.before == entry.after {
+            return Ok(());
+        }
+        self.sink.record(entry)
+    }
+}
+
+impl fmt::Debug for Journal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Journal").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration;
+
+    #[test]
+    fn test_entry() {
+        let mut before = DeviceState::new(0xd073d5001337);
+        before.update(&Message::StatePower { level: 0 });
+        let mut after = before.clone();
+        let message = Message::StatePower { level: 65535 };
+        after.update(&message);
+
+        let entry = JournalEntry {
+            at: UNIX_EPOCH + Duration::from_millis(1_500),
+            addr: "10.0.0.2:56700".parse().unwrap(),
+            source: Some(0xabcd),
+            message,
+            before: before.clone(),
+            after,
+        };
+        assert_eq!(entry.changed_fields(), vec!["power"]);
+        assert_eq!(
+            entry.to_string(),
+            "1.500 0000D073D5001337 power changed by 10.0.0.2:56700 (source 0000ABCD): \
+             StatePower { level: 65535 }"
+        );
+
+        // entries go to the sink, but only if something changed
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let sink = entries.clone();
+        let mut journal = Journal::new(move |entry: &JournalEntry| {
+            sink.lock().unwrap().push(entry.clone());
+            Ok(())
+        });
+        journal.record(&entry).unwrap();
+        journal
+            .record(&JournalEntry {
+                after: before.clone(),
+                before,
+                ..entry.clone()
+            })
+            .unwrap();
+        assert_eq!(*entries.lock().unwrap(), vec![entry]);
+    }
+}
@@ -1 +1,125 @@
-//! TODO:  This eventually will be a library of higher-level utils that sit on top of `lifx-core`
+//! Higher-level utilities for controlling LIFX devices, built on top of [lifx_core].
+//!
+//! Where `lifx-core` only deals with encoding and decoding messages, this crate takes care of
+//! talking to the network: sending requests, matching up replies, and discovering devices.
+//!
+//! The main entry point is the async [Client], which requires a [tokio](https://tokio.rs) runtime.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), lifx::Error> {
+//! use lifx::Client;
+//! use lifx_core::Message;
+//! use std::time::Duration;
+//!
+//! let client = Client::new().await?;
+//! for device in client.discover(Duration::from_secs(1)).await? {
+//!     let label = client.request(device.addr, device.target, Message::GetLabel).await?;
+//!     println!("{:016X} at {}: {:?}", device.target, device.addr, label);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::io;
+use std::net::SocketAddr;
+use thiserror::Error;
+
+pub mod blocking;
+pub mod client;
+pub mod clock;
+pub mod collision;
+pub mod command;
+#[cfg(feature = "config")]
+pub mod config;
+pub mod conformance;
+pub mod dedup;
+pub mod device;
+pub mod devices;
+pub mod diff;
+pub mod filter;
+pub mod framing;
+pub mod interface;
+pub mod journal;
+pub mod observer;
+pub mod poller;
+pub mod proto;
+pub mod provision;
+pub mod queue;
+pub mod receiver;
+pub mod record;
+pub mod relay;
+pub mod reliable;
+pub mod scene;
+pub mod schedule;
+pub mod sequence;
+pub mod shard;
+pub mod state;
+pub mod stream;
+pub mod telemetry;
+pub mod transition;
+pub mod transport;
+pub mod unsolicited;
+
+pub use blocking::SyncClient;
+pub use client::{Client, ClientOptions, DeviceSnapshot, DiscoveredDevice, Response, Responses};
+pub use dedup::DedupFilter;
+pub use lifx_core;
+pub use queue::{Priority, QueueStats};
+pub use reliable::{CommandLatency, ReliableSender, RetryPolicy};
+pub use sequence::SequenceAllocator;
+pub use state::DeviceState;
+
+/// Errors that can happen while talking to LIFX devices
+#[derive(Error, Debug)]
+pub enum Error {
+    /// A message couldn't be encoded or decoded
+    #[error("protocol error: {0}")]
+    Protocol(#[from] lifx_core::Error),
+
+    #[error("i/o error")]
+    Io(#[from] io::Error),
+
+    /// No reply was received before the configured timeout
+    #[error("timed out waiting for a reply")]
+    Timeout,
+
+    /// Every sequence number for this target is in use by an outstanding request
+    #[error("too many outstanding requests to target {0:016X}")]
+    SequenceExhausted(u64),
+
+    /// The address of this device (given as its target ID) isn't known
+    #[error("no known address for target {0:016X}")]
+    UnknownAddress(u64),
+
+    /// A device (given as its target ID) acknowledged a change, but didn't report the new state
+    /// when asked
+    #[error("target {0:016X} didn't confirm the change")]
+    Unconfirmed(u64),
+
+    /// A configuration file couldn't be parsed, or has invalid values in it
+    #[error("invalid configuration: {0}")]
+    Config(String),
+
+    /// The message was dropped from the send queue before it could be sent, because too many
+    /// newer messages of the same priority were queued behind it
+    #[error("message dropped from the send queue")]
+    Dropped,
+
+    /// The device at this address couldn't be reached (the network reported that the host is
+    /// unreachable, or refused the datagram)
+    #[error("{0} is unreachable")]
+    Unreachable(SocketAddr),
+
+    /// The local network is down, so nothing could be sent
+    #[error("the network is down")]
+    NetworkDown,
+
+    /// A step of a [CommandSequence](command::CommandSequence) (counting from 0) failed, so the
+    /// steps after it weren't sent
+    #[error("step {step} of a command sequence failed")]
+    StepFailed {
+        step: usize,
+        #[source]
+        source: Box<Error>,
+    },
+}
@@ -0,0 +1,439 @@
+//! Watching LIFX traffic without sending anything
+//!
+//! A [PassiveObserver] listens on the LIFX port and decodes every message it sees, including
+//! commands sent by other controllers, and keeps a [DeviceState] for each device up to date from
+//! them.  It has no way to send anything, so it can't interfere with the apps and hubs that are
+//! actually controlling the lights.  This makes it a good fit for dashboards and logging.
+//!
+//! Only traffic that reaches this host can be seen.  That's everything broadcast on the local
+//! network (which includes discovery, and commands from the official apps, which broadcast most
+//! things), plus anything sent to this host directly.  Replies that devices send to other
+//! controllers usually aren't broadcast, so state often comes from the commands instead: a
+//! [Message::SetPower] is assumed to have worked, and so on (see [DeviceState::apply]).
+//!
+//! To keep a record of every change, attach a [Journal] with [PassiveObserver::set_journal].  To
+//! wait until a device gets into some state (say, until someone turns the kitchen light on), use
+//! [PassiveObserver::wait_for].  That only sees what reaches the LIFX port, so it never sees the
+//! replies to this program's own requests, which are sent to the client's socket instead; to wait
+//! on those, use [SyncClient::wait_for](crate::SyncClient::wait_for), or
+//! [DeviceMap::wait_for](crate::devices::DeviceMap::wait_for) from another thread.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), lifx::Error> {
+//! use lifx::observer::PassiveObserver;
+//!
+//! let mut observer = PassiveObserver::new().await?;
+//! loop {
+//!     let seen = observer.recv().await?;
+//!     if seen.changed {
+//!         println!("{:016X} is now {:?}", seen.target, observer.device(seen.target));
+//!     }
+//! }
+//! # }
+//! ```
+
+use crate::filter::MessageFilter;
+use crate::journal::{Journal, JournalEntry};
+use crate::state::DeviceState;
+use crate::telemetry;
+use crate::transport::{self, ErrorClass, Transport, RECV_BUFFER_SIZE};
+use crate::Error;
+use lifx_core::{Message, RawMessage, LIFX_PORT};
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::{Duration, SystemTime};
+use tokio::net::UdpSocket;
+use tokio::time::Instant;
+
+/// A message seen by a [PassiveObserver]
+#[derive(Debug, Clone)]
+pub struct Observation {
+    /// Where the message was sent from
+    pub addr: SocketAddr,
+    /// The source ID of the controller that the message was sent by or to
+    pub source: u32,
+    /// The device that the message was sent by or to, or `0` for a broadcast
+    pub target: u64,
+    pub sequence: u8,
+    pub message: Message,
+    /// Whether this changed the state of any device
+    pub changed: bool,
+}
+
+/// Listens to LIFX traffic, and keeps track of device state, without ever sending anything
+#[derive(Debug)]
+pub struct PassiveObserver {
+    socket: UdpSocket,
+    devices: HashMap<u64, DeviceState>,
+    journal: Option<Journal>,
+    filter: Option<MessageFilter>,
+    buf: Vec<u8>,
+}
+
+impl PassiveObserver {
+    /// Listens on the LIFX port on all interfaces
+    ///
+    /// This fails if something else on this host is already using the port.  This must be called
+    /// from within a tokio runtime.
+    pub async fn new() -> Result<PassiveObserver, Error> {
+        PassiveObserver::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), LIFX_PORT)).await
+    }
+
+    /// Listens on the given address
+    pub async fn bind(addr: SocketAddr) -> Result<PassiveObserver, Error> {
+        Ok(PassiveObserver {
+            socket: UdpSocket::bind(addr).await?,
+            devices: HashMap::new(),
+            journal: None,
+            filter: None,
+            buf: vec![0; RECV_BUFFER_SIZE],
+        })
+    }
+
+    /// Starts (or with `None`, stops) recording every state change to a journal
+    ///
+    /// If the journal fails to record a change, [PassiveObserver::recv] returns the error instead
+    /// of the message that caused the change.  The device state is still updated.
+    pub fn set_journal(&mut self, journal: Option<Journal>) {
+        self.journal = journal;
+    }
+
+    /// Only accepts the message types allowed by a filter (or with `None`, accepts everything)
+    ///
+    /// Messages that are dropped by the filter are skipped without being decoded, and don't
+    /// change any device state.  See [filter](crate::filter).
+    pub fn set_filter(&mut self, filter: Option<MessageFilter>) {
+        self.filter = filter;
+    }
+
+    /// The local address that this observer is listening on
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Waits for the next message, and updates the state of the device(s) it's about
+    ///
+    /// Datagrams that can't be decoded (or are too large to be LIFX messages) are skipped.  Messages from devices update their state
+    /// with [DeviceState::update], and commands sent to devices with [DeviceState::apply].  A
+    /// command that's broadcast applies to every device seen so far.
+    pub async fn recv(&mut self) -> Result<Observation, Error> {
+        loop {
+            let (nbytes, addr) = match Transport::recv_from(&self.socket, &mut self.buf).await {
+                Ok(x) => x,
+                Err(e) if transport::classify(&e) == ErrorClass::Truncated => {
+                    telemetry::decode_error();
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let raw = match RawMessage::unpack(&self.buf[..nbytes]) {
+                Ok(raw) => raw,
+                Err(_) => {
+                    telemetry::decode_error();
+                    continue;
+                }
+            };
+            if let Some(filter) = &self.filter {
+                if !filter.check(raw.protocol_header.typ) {
+                    continue;
+                }
+            }
+            let message = match Message::from_raw(&raw) {
+                Ok(message) => message,
+                Err(_) => {
+                    telemetry::decode_error();
+                    continue;
+                }
+            };
+            let target = raw.frame_addr.target;
+            let source = raw.frame.source();
+            let changed = self.observe(addr, source, target, &message)?;
+            return Ok(Observation {
+                addr,
+                source,
+                target,
+                sequence: raw.frame_addr.sequence,
+                message,
+                changed,
+            });
+        }
+    }
+
+    fn observe(
+        &mut self,
+        addr: SocketAddr,
+        source: u32,
+        target: u64,
+        message: &Message,
+    ) -> io::Result<bool> {
+        let mut journal = JournalWriter {
+            journal: self.journal.as_mut(),
+            addr,
+            source,
+            message,
+            result: Ok(()),
+        };
+        if target == 0 {
+            let mut changed = false;
+            for state in self.devices.values_mut() {
+                let before = journal.snapshot(state);
+                if state.apply(message) {
+                    changed = true;
+                    journal.record(before, state);
+                }
+            }
+            return journal.result.map(|()| changed);
+        }
+
+        let mut state = self
+            .devices
+            .remove(&target)
+            .unwrap_or_else(|| DeviceState::new(target));
+        let before = journal.snapshot(&state);
+        let changed = if state.update(message) {
+            // only devices send state, so this is where the device is
+            state.addr = Some(addr);
+            true
+        } else {
+            state.apply(message)
+        };
+        if changed {
+            journal.record(before, &state);
+        }
+        // don't start tracking a device just because someone asked it something
+        if changed || state != DeviceState::new(target) {
+            self.devices.insert(target, state);
+        }
+        journal.result.map(|()| changed)
+    }
+
+    /// The state of one device, if anything has been seen from or sent to it
+    pub fn device(&self, target: u64) -> Option<&DeviceState> {
+        self.devices.get(&target)
+    }
+
+    /// The state of every device seen so far, in no particular order
+    pub fn devices(&self) -> impl Iterator<Item = &DeviceState> {
+        self.devices.values()
+    }
+
+    /// Waits until a device's state satisfies `predicate`, and returns that state
+    ///
+    /// The devices seen so far are checked first.  After that, messages are received (just like
+    /// [PassiveObserver::recv]) until one of them changes a device so that it does.  Returns
+    /// [Error::Timeout] if that doesn't happen in time.
+    ///
+    /// ```no_run
+    /// # async fn example(mut observer: lifx::observer::PassiveObserver) -> Result<(), lifx::Error> {
+    /// use std::time::Duration;
+    ///
+    /// let kitchen = observer
+    ///     .wait_for(
+    ///         |state| state.label.as_deref() == Some("Kitchen") && state.power > Some(0),
+    ///         Duration::from_secs(10),
+    ///     )
+    ///     .await?;
+    /// println!("the kitchen light is on: {:?}", kitchen.color);
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn wait_for(
+        &mut self,
+        mut predicate: impl FnMut(&DeviceState) -> bool,
+        timeout: Duration,
+    ) -> Result<DeviceState, Error> {
+        let deadline = Instant::now() + timeout;
+        let mut changed = true;
+        loop {
+            if changed {
+                if let Some(state) = self.devices.values().find(|state| predicate(state)) {
+                    return Ok(state.clone());
+                }
+            }
+            // recv is cancellation-safe, so nothing is lost if this times out
+            changed = tokio::time::timeout_at(deadline, self.recv())
+                .await
+                .map_err(|_| Error::Timeout)??
+                .changed;
+        }
+    }
+}
+
+/// Records the changes caused by one message, keeping the first error
+struct JournalWriter<'a> {
+    journal: Option<&'a mut Journal>,
+    addr: SocketAddr,
+    source: u32,
+    message: &'a Message,
+    result: io::Result<()>,
+}
+
+impl JournalWriter<'_> {
+    /// A copy of the state before a change, if there's a journal to record it in
+    fn snapshot(&self, state: &DeviceState) -> Option<DeviceState> {
+        self.journal.as_ref().map(|_| state.clone())
+    }
+
+    fn record(&mut self, before: Option<DeviceState>, after: &DeviceState) {
+        let (journal, before) = match (self.journal.as_deref_mut(), before) {
+            (Some(journal), Some(before)) => (journal, before),
+            _ => return,
+        };
+        let entry = JournalEntry {
+            at: SystemTime::now(),
+            addr: self.addr,
+            source: Some(self.source).filter(|&source| source != 0),
+            message: self.message.clone(),
+            before,
+            after: after.clone(),
+        };
+        let result = journal.record(&entry);
+        if self.result.is_ok() {
+            self.result = result;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lifx_core::{BuildOptions, LifxString, PowerLevel, SourceId};
+    use std::ffi::CString;
+    use std::sync::{Arc, Mutex};
+
+    async fn send(from: &UdpSocket, to: SocketAddr, target: u64, msg: Message) {
+        let opts = BuildOptions {
+            target: if target == 0 { None } else { Some(target) },
+            source: SourceId::new(1234).unwrap(),
+            ..Default::default()
+        };
+        let raw = RawMessage::build(&opts, msg).unwrap();
+        from.send_to(&raw.pack().unwrap(), to).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_observe() {
+        let mut observer = PassiveObserver::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let addr = observer.local_addr().unwrap();
+        let other = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        // nobody's heard of a device that's only been asked a question
+        send(&other, addr, 1, Message::GetLabel).await;
+        assert!(!observer.recv().await.unwrap().changed);
+        assert!(observer.device(1).is_none());
+
+        let label = LifxString::new(&CString::new("Kitchen").unwrap());
+        send(&other, addr, 1, Message::StateLabel { label }).await;
+        let seen = observer.recv().await.unwrap();
+        assert!(seen.changed);
+        assert_eq!(seen.source, 1234);
+        let state = observer.device(1).unwrap();
+        assert_eq!(state.label.as_deref(), Some("Kitchen"));
+        assert_eq!(state.addr, Some(other.local_addr().unwrap()));
+
+        // garbage is skipped, and a broadcast command applies to everything
+        other.send_to(b"not lifx", addr).await.unwrap();
+        let on = Message::SetPower {
+            level: PowerLevel::Enabled,
+        };
+        send(&other, addr, 0, on).await;
+        let seen = observer.recv().await.unwrap();
+        assert_eq!(seen.target, 0);
+        assert!(seen.changed);
+        assert_eq!(observer.device(1).unwrap().power, Some(65535));
+        assert_eq!(observer.devices().count(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_wait_for() {
+        let mut observer = PassiveObserver::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let addr = observer.local_addr().unwrap();
+        let other = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let kitchen_on = |state: &DeviceState| {
+            state.label.as_deref() == Some("Kitchen") && state.power > Some(0)
+        };
+
+        let label = LifxString::new(&CString::new("Kitchen").unwrap());
+        send(&other, addr, 1, Message::StateLabel { label }).await;
+        send(&other, addr, 1, Message::StatePower { level: 0 }).await;
+        send(&other, addr, 2, Message::StatePower { level: 65535 }).await;
+        let res = observer
+            .wait_for(kitchen_on, Duration::from_millis(50))
+            .await;
+        assert!(matches!(res, Err(Error::Timeout)));
+        assert_eq!(observer.devices().count(), 2);
+
+        send(&other, addr, 1, Message::StatePower { level: 65535 }).await;
+        let state = observer
+            .wait_for(kitchen_on, Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(state.target, 1);
+        // already true, so this doesn't wait for anything
+        let state = observer.wait_for(kitchen_on, Duration::ZERO).await.unwrap();
+        assert_eq!(state.power, Some(65535));
+    }
+
+    #[tokio::test]
+    async fn test_filter() {
+        let mut observer = PassiveObserver::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let addr = observer.local_addr().unwrap();
+        let other = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        // only state reports, no commands
+        let filter = MessageFilter::allow([22]);
+        observer.set_filter(Some(filter.clone()));
+
+        let on = Message::SetPower {
+            level: PowerLevel::Enabled,
+        };
+        send(&other, addr, 1, on).await;
+        send(&other, addr, 1, Message::StatePower { level: 0 }).await;
+        let seen = observer.recv().await.unwrap();
+        assert_eq!(seen.message, Message::StatePower { level: 0 });
+        assert_eq!(observer.device(1).unwrap().power, Some(0));
+        assert_eq!(filter.dropped(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_journal() {
+        let mut observer = PassiveObserver::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let addr = observer.local_addr().unwrap();
+        let other = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let entries = Arc::new(Mutex::new(Vec::new()));
+        let sink = entries.clone();
+        observer.set_journal(Some(Journal::new(move |entry: &JournalEntry| {
+            sink.lock().unwrap().push(entry.clone());
+            Ok(())
+        })));
+
+        send(&other, addr, 1, Message::StatePower { level: 0 }).await;
+        let on = Message::SetPower {
+            level: PowerLevel::Enabled,
+        };
+        send(&other, addr, 1, on.clone()).await;
+        // nothing to record the second time
+        send(&other, addr, 1, on.clone()).await;
+        for _ in 0..3 {
+            observer.recv().await.unwrap();
+        }
+
+        let entries = entries.lock().unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[1].target(), 1);
+        assert_eq!(entries[1].source, Some(1234));
+        assert_eq!(entries[1].addr, other.local_addr().unwrap());
+        assert_eq!(entries[1].message, on);
+        assert_eq!(entries[1].changed_fields(), vec!["power"]);
+        assert_eq!(entries[1].before.power, Some(0));
+        assert_eq!(entries[1].after.power, Some(65535));
+    }
+}
@@ -0,0 +1,330 @@
+//! Keeping device state fresh by polling, more often while things are changing
+//!
+//! The LAN protocol has no way for a device to tell anyone when it changes, so the only way to
+//! keep up with changes made by other apps (or by a light switch) is to keep asking.  Asking
+//! often keeps the state fresh, but eats into the [MAX_MESSAGES_PER_SECOND] that each device can
+//! handle, and most of the time nothing has changed.
+//!
+//! A [PollSchedule] polls each device at its own interval, which starts short and doubles after
+//! every poll that finds nothing new, up to a maximum.  When a poll finds a change, or something
+//! else suggests that one is coming (like a command that was just sent, or a change seen by a
+//! [PassiveObserver](crate::observer::PassiveObserver)), the interval drops back to the minimum,
+//! because changes tend to come in bursts.  [AdaptivePoller] runs the schedule with a [Client].
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), lifx::Error> {
+//! use lifx::poller::AdaptivePoller;
+//! use lifx::Client;
+//! use lifx_core::Message;
+//! use std::time::Duration;
+//!
+//! let client = Client::new().await?;
+//! let devices = client.discover(Duration::from_secs(1)).await?;
+//! let queries = vec![Message::LightGet];
+//! let mut poller = AdaptivePoller::new(client, queries, Duration::from_secs(1), Duration::from_secs(30));
+//! for device in devices {
+//!     poller.add(device);
+//! }
+//! while let Some(polled) = poller.poll().await {
+//!     if let Ok(true) = polled.result {
+//!         println!("{:?}", poller.device(polled.target));
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::{Client, DiscoveredDevice};
+use crate::clock::{Clock, SystemClock};
+use crate::state::DeviceState;
+use crate::stream::MAX_MESSAGES_PER_SECOND;
+use crate::Error;
+use lifx_core::Message;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy)]
+struct Slot {
+    interval: Duration,
+    due: Instant,
+}
+
+/// When to next poll each of a set of devices
+///
+/// This only keeps time; it doesn't send anything.  See [AdaptivePoller] for something that does.
+#[derive(Debug)]
+pub struct PollSchedule {
+    min: Duration,
+    max: Duration,
+    devices: HashMap<u64, Slot>,
+    clock: Arc<dyn Clock>,
+}
+
+impl PollSchedule {
+    /// Creates a schedule whose intervals are between `min` and `max` (`max` is raised to `min`
+    /// if it's smaller)
+    pub fn new(min: Duration, max: Duration) -> PollSchedule {
+        PollSchedule::with_clock(min, max, SystemClock)
+    }
+
+    /// Like [PollSchedule::new], but keeping time with the given clock
+    pub fn with_clock(min: Duration, max: Duration, clock: impl Clock) -> PollSchedule {
+        PollSchedule {
+            min,
+            max: max.max(min),
+            devices: HashMap::new(),
+            clock: Arc::new(clock),
+        }
+    }
+
+    /// Adds a device, which is due to be polled straight away
+    ///
+    /// Adding a device that's already in the schedule is the same as [poking](PollSchedule::poke)
+    /// it.
+    pub fn add(&mut self, target: u64) {
+        let slot = Slot {
+            interval: self.min,
+            due: self.clock.now(),
+        };
+        self.devices.entry(target).or_insert(slot);
+        self.poke(target);
+    }
+
+    pub fn remove(&mut self, target: u64) {
+        self.devices.remove(&target);
+    }
+
+    /// Notes that a device has probably changed (or is about to), so it's polled again within the
+    /// minimum interval, and keeps being polled often for a while
+    pub fn poke(&mut self, target: u64) {
+        let now = self.clock.now();
+        if let Some(slot) = self.devices.get_mut(&target) {
+            slot.interval = self.min;
+            slot.due = slot.due.min(now + self.min);
+        }
+    }
+
+    /// Notes that a device was just polled, and whether anything had changed
+    ///
+    /// A change resets the device's interval to the minimum, and otherwise it doubles.
+    pub fn polled(&mut self, target: u64, changed: bool) {
+        let now = self.clock.now();
+        if let Some(slot) = self.devices.get_mut(&target) {
+            slot.interval = match changed {
+                true => self.min,
+                false => (slot.interval * 2).min(self.max),
+            };
+            slot.due = now + slot.interval;
+        }
+    }
+
+    /// The device that's due to be polled first, and when
+    ///
+    /// The time may be in the past, if the device is overdue.  When two devices are due at the
+    /// same time, the one with the lower target comes first.
+    pub fn next(&self) -> Option<(u64, Instant)> {
+        self.devices
+            .iter()
+            .map(|(&target, slot)| (target, slot.due))
+            .min_by_key(|&(target, due)| (due, target))
+    }
+
+    /// How long a device will wait between polls if nothing changes in the meantime
+    pub fn interval(&self, target: u64) -> Option<Duration> {
+        self.devices.get(&target).map(|slot| slot.interval)
+    }
+
+    /// The number of devices in the schedule
+    pub fn len(&self) -> usize {
+        self.devices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.devices.is_empty()
+    }
+}
+
+/// The outcome of one poll by an [AdaptivePoller]
+#[derive(Debug)]
+pub struct Polled {
+    pub target: u64,
+    /// Whether any of the replies changed the device's state, or why they couldn't be collected
+    pub result: Result<bool, Error>,
+}
+
+/// Polls devices with a [PollSchedule], and keeps track of their state
+pub struct AdaptivePoller {
+    client: Client,
+    queries: Vec<Message>,
+    schedule: PollSchedule,
+    devices: HashMap<u64, DeviceState>,
+}
+
+impl AdaptivePoller {
+    /// Creates a poller that sends each of `queries` to a device every time it's polled, with
+    /// intervals between `min` and `max`
+    ///
+    /// The minimum interval is raised if needed so that polling never uses more than half of
+    /// [MAX_MESSAGES_PER_SECOND], leaving the rest for commands.
+    pub fn new(client: Client, queries: Vec<Message>, min: Duration, max: Duration) -> Self {
+        let budget = Duration::from_secs(2) / MAX_MESSAGES_PER_SECOND;
+        let min = min.max(budget * queries.len() as u32);
+        AdaptivePoller {
+            client,
+            queries,
+            schedule: PollSchedule::new(min, max),
+            devices: HashMap::new(),
+        }
+    }
+
+    /// Starts polling a device
+    pub fn add(&mut self, device: DiscoveredDevice) {
+        let state = self
+            .devices
+            .entry(device.target)
+            .or_insert_with(|| DeviceState::new(device.target));
+        state.addr = Some(device.addr);
+        self.schedule.add(device.target);
+    }
+
+    pub fn remove(&mut self, target: u64) {
+        self.devices.remove(&target);
+        self.schedule.remove(target);
+    }
+
+    /// Polls a device soon, and often for a while (see [PollSchedule::poke])
+    pub fn poke(&mut self, target: u64) {
+        self.schedule.poke(target);
+    }
+
+    /// Sends a command to a device, waits for it to be acknowledged, and pokes the device so its
+    /// new state is picked up soon
+    ///
+    /// The command is also applied to the cached state straight away, with [DeviceState::apply].
+    pub async fn send_acked(&mut self, target: u64, msg: Message) -> Result<(), Error> {
+        let state = self
+            .devices
+            .get_mut(&target)
+            .ok_or(Error::UnknownAddress(target))?;
+        let addr = state.addr.ok_or(Error::UnknownAddress(target))?;
+        self.client.send_acked(addr, target, msg.clone()).await?;
+        state.apply(&msg);
+        self.schedule.poke(target);
+        Ok(())
+    }
+
+    /// The last known state of a device
+    pub fn device(&self, target: u64) -> Option<&DeviceState> {
+        self.devices.get(&target)
+    }
+
+    pub fn schedule(&self) -> &PollSchedule {
+        &self.schedule
+    }
+
+    /// Waits until the next device is due, and polls it
+    ///
+    /// Returns `None` if there are no devices to poll.  A device that doesn't answer is treated
+    /// like one that hasn't changed, so it's polled less and less often.
+    pub async fn poll(&mut self) -> Option<Polled> {
+        let (target, due) = self.schedule.next()?;
+        tokio::time::sleep_until(due.into()).await;
+
+        let state = self.devices.get_mut(&target)?;
+        let addr = state.addr?;
+        let mut result = Ok(false);
+        for query in &self.queries {
+            match self.client.request(addr, target, query.clone()).await {
+                Ok(reply) => {
+                    if state.update(&reply) {
+                        result = Ok(true);
+                    }
+                }
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        self.schedule.polled(target, matches!(result, Ok(true)));
+        Some(Polled { target, result })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::{fake_bulb, localhost_options};
+    use crate::clock::MockClock;
+    use lifx_core::HSBK;
+
+    #[test]
+    fn test_schedule() {
+        let clock = MockClock::new();
+        let start = clock.now();
+        let second = Duration::from_secs(1);
+        let mut schedule = PollSchedule::with_clock(second, second * 5, clock.clone());
+        assert_eq!(schedule.next(), None);
+
+        schedule.add(2);
+        schedule.add(1);
+        assert_eq!(schedule.next(), Some((1, start)));
+
+        // nothing changing backs off, up to the maximum
+        for expected in [2, 4, 5, 5] {
+            schedule.polled(1, false);
+            assert_eq!(schedule.interval(1), Some(second * expected));
+        }
+        assert_eq!(schedule.next(), Some((2, start)));
+        schedule.polled(2, true);
+        assert_eq!(schedule.interval(2), Some(second));
+        assert_eq!(schedule.next(), Some((2, start + second)));
+
+        // a poke brings the next poll forward, and resets the interval
+        clock.advance(second * 3);
+        schedule.poke(1);
+        assert_eq!(schedule.interval(1), Some(second));
+        assert_eq!(schedule.next(), Some((2, start + second)));
+        schedule.polled(2, false);
+        assert_eq!(schedule.next(), Some((1, start + second * 4)));
+
+        schedule.remove(1);
+        schedule.poke(1);
+        assert_eq!(schedule.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_poller() {
+        let addr = fake_bulb(0x1234, "Desk").await;
+        let client = Client::with_options(localhost_options()).await.unwrap();
+        let min = Duration::from_millis(100);
+        let mut poller = AdaptivePoller::new(client, vec![Message::LightGet], min, min * 4);
+        assert!(poller.poll().await.is_none());
+        poller.add(DiscoveredDevice {
+            target: 0x1234,
+            addr,
+        });
+
+        // the first poll fills in the state, and then nothing changes
+        let polled = poller.poll().await.unwrap();
+        assert_eq!(polled.target, 0x1234);
+        assert!(polled.result.unwrap());
+        assert_eq!(
+            poller.device(0x1234).unwrap().label.as_deref(),
+            Some("Desk")
+        );
+        assert!(!poller.poll().await.unwrap().result.unwrap());
+        assert_eq!(poller.schedule().interval(0x1234), Some(min * 2));
+
+        // a command speeds polling up again
+        let color = HSBK::new(120.0, 100.0, 100.0, 3500);
+        poller
+            .send_acked(0x1234, Message::set_color(color, Duration::ZERO))
+            .await
+            .unwrap();
+        assert_eq!(poller.device(0x1234).unwrap().color, Some(color));
+        assert_eq!(poller.schedule().interval(0x1234), Some(min));
+        assert!(poller.send_acked(1, Message::GetPower).await.is_err());
+    }
+}
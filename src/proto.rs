@@ -0,0 +1,653 @@
+//! The request/response logic, without any I/O
+//!
+//! A [Connection] keeps track of requests: which sequence numbers are in use, which replies belong
+//! to which request, and when each request runs out of time.  It never touches a socket or reads
+//! the clock.  Instead, the code driving it:
+//!
+//! * sends whatever [Connection::poll_transmit] hands out,
+//! * passes every datagram that arrives to [Connection::handle_datagram],
+//! * calls [Connection::handle_timeout] once the time from [Connection::poll_timeout] has passed,
+//! * and collects replies with [Connection::poll_reply].
+//!
+//! This is the same split as `quinn-proto` and `quinn`: the state machine can be driven by a
+//! blocking socket (as [SyncClient](crate::SyncClient) does), by an async runtime (as
+//! [Client](crate::Client) does), or by whatever a microcontroller's network stack offers, and it
+//! can be tested by feeding it bytes and instants by hand.
+//!
+//! Requests started with [Connection::open_request] have no deadline, and collect every reply
+//! until they're finished.  They're for drivers that keep time themselves, like
+//! [Client](crate::Client), whose timeouts run on the async runtime's clock.
+//!
+//! ```
+//! use lifx::proto::{Connection, Status};
+//! use lifx_core::{Message, SourceId};
+//! use std::time::{Duration, Instant};
+//!
+//! let mut conn = Connection::new(SourceId::new(0x1234).unwrap());
+//! let now = Instant::now();
+//! let addr = "192.168.1.10:56700".parse().unwrap();
+//! let deadline = now + Duration::from_secs(1);
+//! let id = conn
+//!     .send_request(addr, Some(0xd073d5001337), Message::GetPower, false, true, deadline)
+//!     .unwrap();
+//! let transmit = conn.poll_transmit().unwrap();
+//! assert_eq!(transmit.destination, addr);
+//!
+//! // no reply arrives
+//! conn.handle_timeout(deadline);
+//! assert_eq!(conn.status(id), Some(Status::Expired));
+//! ```
+
+use crate::client::Response;
+use crate::sequence::SequenceAllocator;
+use crate::Error;
+use lifx_core::{BuildOptions, Message, RawMessage, SourceId};
+use std::collections::{HashMap, VecDeque};
+use std::net::SocketAddr;
+use std::time::Instant;
+
+/// Identifies a request made through a [Connection]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RequestId(u64);
+
+/// A datagram that a [Connection] wants sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Transmit {
+    pub destination: SocketAddr,
+    pub contents: Vec<u8>,
+}
+
+/// Where a request is up to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// Still waiting for replies
+    Waiting,
+    /// Got the reply it was waiting for: the first acknowledgement, if only an acknowledgement was
+    /// asked for, or otherwise the first reply that isn't one
+    Answered,
+    /// The deadline passed
+    ///
+    /// This is how broadcasts and requests that don't ask for anything end, since there's no
+    /// telling how many replies they'll get.  For any other request, it means the device didn't
+    /// answer in time.
+    Expired,
+}
+
+#[derive(Debug)]
+struct Request {
+    /// The target the sequence number was allocated for (0 for broadcasts)
+    target: u64,
+    sequence: u8,
+    ack_required: bool,
+    res_required: bool,
+    /// `None` for an [open](Connection::open_request) request
+    deadline: Option<Instant>,
+    /// The encoded request, for [Connection::resend]
+    datagram: Vec<u8>,
+    status: Status,
+    replies: VecDeque<Response>,
+}
+
+impl Request {
+    /// Whether this reply is the one the request was waiting for
+    fn is_answered_by(&self, raw: &RawMessage) -> bool {
+        if self.target == 0 || self.deadline.is_none() {
+            return false;
+        }
+        if let Ok(Message::Acknowledgement { .. }) = Message::from_raw(raw) {
+            self.ack_required && !self.res_required
+        } else {
+            self.res_required
+        }
+    }
+}
+
+/// The state of every request made with one source ID
+///
+/// See the [module documentation](self).
+#[derive(Debug)]
+pub struct Connection {
+    source: SourceId,
+    sequences: SequenceAllocator,
+    requests: HashMap<RequestId, Request>,
+    /// Requests that are still waiting, keyed by (target, sequence)
+    routes: HashMap<(u64, u8), RequestId>,
+    transmits: VecDeque<Transmit>,
+    next_id: u64,
+}
+
+impl Connection {
+    pub fn new(source: SourceId) -> Connection {
+        Connection {
+            source,
+            sequences: SequenceAllocator::new(),
+            requests: HashMap::new(),
+            routes: HashMap::new(),
+            transmits: VecDeque::new(),
+            next_id: 0,
+        }
+    }
+
+    /// The source ID that requests are sent with
+    pub fn source(&self) -> SourceId {
+        self.source
+    }
+
+    /// Switches to a different source ID for new requests
+    ///
+    /// Replies come back with the source ID of the request they answer, so requests that are
+    /// still waiting won't get any more replies.
+    pub fn set_source(&mut self, source: SourceId) {
+        self.source = source;
+    }
+
+    /// Starts a request, which waits for replies until `deadline`
+    ///
+    /// With no target, this is a broadcast, and it collects replies from every device until the
+    /// deadline.  The request is sent by the next [Connection::poll_transmit].
+    pub fn send_request(
+        &mut self,
+        addr: SocketAddr,
+        target: Option<u64>,
+        msg: Message,
+        ack_required: bool,
+        res_required: bool,
+        deadline: Instant,
+    ) -> Result<RequestId, Error> {
+        let deadline = Some(deadline);
+        self.start(addr, target, msg, ack_required, res_required, deadline)
+    }
+
+    /// Starts a request that collects every reply until it's [finished](Connection::finish)
+    ///
+    /// Unlike [Connection::send_request], this never stops waiting by itself: it isn't answered
+    /// by any reply, and [Connection::handle_timeout] leaves it alone.  It's for requests that
+    /// get several replies (like [Message::GetColorZones]), or whose caller times them out some
+    /// other way.
+    pub fn open_request(
+        &mut self,
+        addr: SocketAddr,
+        target: Option<u64>,
+        msg: Message,
+        ack_required: bool,
+        res_required: bool,
+    ) -> Result<RequestId, Error> {
+        self.start(addr, target, msg, ack_required, res_required, None)
+    }
+
+    fn start(
+        &mut self,
+        addr: SocketAddr,
+        target: Option<u64>,
+        msg: Message,
+        ack_required: bool,
+        res_required: bool,
+        deadline: Option<Instant>,
+    ) -> Result<RequestId, Error> {
+        let key = target.unwrap_or(0);
+        let sequence = self
+            .sequences
+            .allocate(key)
+            .ok_or(Error::SequenceExhausted(key))?;
+        let options = BuildOptions {
+            target,
+            ack_required,
+            res_required,
+            sequence,
+            source: self.source,
+        };
+        let datagram = match RawMessage::build(&options, msg).and_then(|raw| raw.pack()) {
+            Ok(datagram) => datagram,
+            Err(e) => {
+                self.sequences.release(key, sequence);
+                return Err(e.into());
+            }
+        };
+        let id = RequestId(self.next_id);
+        self.next_id += 1;
+        self.transmits.push_back(Transmit {
+            destination: addr,
+            contents: datagram.clone(),
+        });
+        self.routes.insert((key, sequence), id);
+        self.requests.insert(
+            id,
+            Request {
+                target: key,
+                sequence,
+                ack_required,
+                res_required,
+                deadline,
+                datagram,
+                status: Status::Waiting,
+                replies: VecDeque::new(),
+            },
+        );
+        Ok(id)
+    }
+
+    /// Sends a request again, to the given address, with the same sequence number
+    ///
+    /// Replies to either copy count as replies to the request.  Does nothing if the request has
+    /// stopped waiting.
+    pub fn resend(&mut self, id: RequestId, addr: SocketAddr) {
+        if let Some(request) = self.requests.get(&id) {
+            if request.status == Status::Waiting {
+                self.transmits.push_back(Transmit {
+                    destination: addr,
+                    contents: request.datagram.clone(),
+                });
+            }
+        }
+    }
+
+    /// The next datagram to send, if there is one
+    pub fn poll_transmit(&mut self) -> Option<Transmit> {
+        self.transmits.pop_front()
+    }
+
+    /// Handles a datagram that arrived from `from`, and returns the request it's a reply to
+    ///
+    /// Anything that isn't a reply to one of this connection's requests is dropped, including
+    /// datagrams that can't be decoded.
+    pub fn handle_datagram(&mut self, from: SocketAddr, datagram: &[u8]) -> Option<RequestId> {
+        let raw = RawMessage::unpack(datagram).ok()?;
+        self.handle_raw(from, raw)
+    }
+
+    /// Like [Connection::handle_datagram], for a datagram that's already been decoded
+    pub fn handle_raw(&mut self, from: SocketAddr, raw: RawMessage) -> Option<RequestId> {
+        if raw.frame.source() != self.source.get() {
+            return None;
+        }
+        // The allocator never gives a targeted request a sequence number that a broadcast is
+        // using (or the other way around), but broadcasts are checked first anyway, so that a
+        // reply to a broadcast can never answer a targeted request by mistake
+        let (target, sequence) = (raw.frame_addr.target, raw.frame_addr.sequence);
+        let &id = self
+            .routes
+            .get(&(0, sequence))
+            .or_else(|| self.routes.get(&(target, sequence)))?;
+        let request = self
+            .requests
+            .get_mut(&id)
+            .expect("routes only hold live requests");
+        let answered = request.is_answered_by(&raw);
+        request.replies.push_back(Response { addr: from, raw });
+        if answered {
+            self.stop(id, Status::Answered);
+        }
+        Some(id)
+    }
+
+    /// Expires every request whose deadline is at or before `now`
+    pub fn handle_timeout(&mut self, now: Instant) {
+        let expired: Vec<_> = self
+            .requests
+            .iter()
+            .filter(|(_, r)| r.status == Status::Waiting && r.deadline.is_some_and(|d| d <= now))
+            .map(|(&id, _)| id)
+            .collect();
+        for id in expired {
+            self.stop(id, Status::Expired);
+        }
+    }
+
+    /// The earliest deadline of any request that's still waiting
+    pub fn poll_timeout(&self) -> Option<Instant> {
+        self.requests
+            .values()
+            .filter(|r| r.status == Status::Waiting)
+            .filter_map(|r| r.deadline)
+            .min()
+    }
+
+    /// The next reply to a request, in the order they arrived
+    pub fn poll_reply(&mut self, id: RequestId) -> Option<Response> {
+        self.requests.get_mut(&id)?.replies.pop_front()
+    }
+
+    /// Where a request is up to, or `None` if it's been [finished](Connection::finish)
+    pub fn status(&self, id: RequestId) -> Option<Status> {
+        self.requests.get(&id).map(|r| r.status)
+    }
+
+    /// When a request stops waiting for replies (never, for an
+    /// [open](Connection::open_request) request)
+    pub fn deadline(&self, id: RequestId) -> Option<Instant> {
+        self.requests.get(&id)?.deadline
+    }
+
+    /// The sequence number that a request was sent with
+    pub fn sequence(&self, id: RequestId) -> Option<u8> {
+        self.requests.get(&id).map(|r| r.sequence)
+    }
+
+    /// Forgets a request, along with any replies that haven't been collected
+    ///
+    /// A request that's still waiting is cancelled, and later replies to it are dropped.
+    pub fn finish(&mut self, id: RequestId) {
+        self.stop(id, Status::Expired);
+        self.requests.remove(&id);
+    }
+
+    /// The number of requests that haven't been finished
+    pub fn len(&self) -> usize {
+        self.requests.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_empty()
+    }
+
+    /// Stops a request from waiting, so that its sequence number can be used again
+    fn stop(&mut self, id: RequestId, status: Status) {
+        let Some(request) = self.requests.get_mut(&id) else {
+            return;
+        };
+        if request.status != Status::Waiting {
+            return;
+        }
+        request.status = status;
+        self.routes.remove(&(request.target, request.sequence));
+        self.sequences.release(request.target, request.sequence);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const TARGET: u64 = 0xd073d5001337;
+
+    fn addr() -> SocketAddr {
+        "10.0.0.2:56700".parse().unwrap()
+    }
+
+    /// A device's reply to a transmitted request
+    fn reply(transmit: &Transmit, target: u64, msg: Message) -> Vec<u8> {
+        let raw = RawMessage::unpack(&transmit.contents).unwrap();
+        let options = BuildOptions {
+            target: Some(target),
+            ..raw.reply_options()
+        };
+        RawMessage::build(&options, msg).unwrap().pack().unwrap()
+    }
+
+    fn connection() -> Connection {
+        Connection::new(SourceId::new(0x1234).unwrap())
+    }
+
+    #[test]
+    fn test_request() {
+        let mut conn = connection();
+        let now = Instant::now();
+        let deadline = now + Duration::from_secs(1);
+        let id = conn
+            .send_request(
+                addr(),
+                Some(TARGET),
+                Message::GetPower,
+                true,
+                true,
+                deadline,
+            )
+            .unwrap();
+        let transmit = conn.poll_transmit().unwrap();
+        assert_eq!(transmit.destination, addr());
+        assert_eq!(conn.poll_transmit(), None);
+        assert_eq!(conn.poll_timeout(), Some(deadline));
+
+        // the ack doesn't answer it, since a reply was asked for
+        let seq = RawMessage::unpack(&transmit.contents)
+            .unwrap()
+            .frame_addr
+            .sequence;
+        let ack = Message::Acknowledgement { seq };
+        conn.handle_datagram(addr(), &reply(&transmit, TARGET, ack.clone()));
+        assert_eq!(conn.status(id), Some(Status::Waiting));
+        let power = Message::StatePower { level: 65535 };
+        conn.handle_datagram(addr(), &reply(&transmit, TARGET, power.clone()));
+        assert_eq!(conn.status(id), Some(Status::Answered));
+        assert_eq!(conn.poll_timeout(), None);
+
+        let first = conn.poll_reply(id).unwrap();
+        assert_eq!(first.message().unwrap(), ack);
+        assert_eq!(conn.poll_reply(id).unwrap().message().unwrap(), power);
+        assert!(conn.poll_reply(id).is_none());
+
+        // once it's answered, more replies are dropped
+        conn.handle_datagram(addr(), &reply(&transmit, TARGET, power));
+        assert!(conn.poll_reply(id).is_none());
+        conn.finish(id);
+        assert!(conn.is_empty());
+    }
+
+    #[test]
+    fn test_ignores_other_replies() {
+        let mut conn = connection();
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let id = conn
+            .send_request(
+                addr(),
+                Some(TARGET),
+                Message::GetPower,
+                false,
+                true,
+                deadline,
+            )
+            .unwrap();
+        let transmit = conn.poll_transmit().unwrap();
+        let power = Message::StatePower { level: 0 };
+
+        // from another device, for another source, and not a LIFX message at all
+        conn.handle_datagram(addr(), &reply(&transmit, TARGET + 1, power.clone()));
+        let mut raw = RawMessage::unpack(&reply(&transmit, TARGET, power)).unwrap();
+        raw.frame.set_source(0x4321);
+        conn.handle_datagram(addr(), &raw.pack().unwrap());
+        conn.handle_datagram(addr(), b"hello");
+        assert!(conn.poll_reply(id).is_none());
+        assert_eq!(conn.status(id), Some(Status::Waiting));
+    }
+
+    #[test]
+    fn test_broadcast() {
+        let mut conn = connection();
+        let now = Instant::now();
+        let broadcast = "10.0.0.255:56700".parse().unwrap();
+        let id = conn
+            .send_request(
+                broadcast,
+                None,
+                Message::GetService,
+                false,
+                true,
+                now + Duration::from_millis(500),
+            )
+            .unwrap();
+        let transmit = conn.poll_transmit().unwrap();
+
+        // every device can answer, so only the deadline ends it
+        for target in [1, 2] {
+            let msg = Message::StatePower { level: 0 };
+            conn.handle_datagram(addr(), &reply(&transmit, target, msg));
+        }
+        conn.handle_timeout(now + Duration::from_millis(499));
+        assert_eq!(conn.status(id), Some(Status::Waiting));
+        conn.resend(id, addr());
+        assert_eq!(conn.poll_transmit().unwrap().contents, transmit.contents);
+
+        conn.handle_timeout(now + Duration::from_millis(500));
+        assert_eq!(conn.status(id), Some(Status::Expired));
+        let targets: Vec<_> = std::iter::from_fn(|| conn.poll_reply(id))
+            .map(|r| r.target())
+            .collect();
+        assert_eq!(targets, vec![1, 2]);
+
+        // a request that's stopped waiting isn't sent again
+        conn.resend(id, addr());
+        assert_eq!(conn.poll_transmit(), None);
+    }
+
+    #[test]
+    fn test_broadcast_and_targeted() {
+        let mut conn = connection();
+        let deadline = Instant::now() + Duration::from_secs(1);
+        let mut start = |target| {
+            let id = conn
+                .send_request(addr(), target, Message::GetPower, false, true, deadline)
+                .unwrap();
+            let transmit = conn.poll_transmit().unwrap();
+            let seq = RawMessage::unpack(&transmit.contents)
+                .unwrap()
+                .frame_addr
+                .sequence;
+            (id, transmit, seq)
+        };
+        let (before, _, before_seq) = start(Some(TARGET));
+        let (broadcast, broadcast_transmit, broadcast_seq) = start(None);
+        let (after, after_transmit, after_seq) = start(Some(TARGET));
+
+        // the broadcast's sequence number isn't shared with either targeted request
+        assert_ne!(broadcast_seq, before_seq);
+        assert_ne!(broadcast_seq, after_seq);
+
+        // so the target's reply to the broadcast only goes to the broadcast
+        let power = Message::StatePower { level: 0 };
+        conn.handle_datagram(addr(), &reply(&broadcast_transmit, TARGET, power.clone()));
+        assert_eq!(conn.status(before), Some(Status::Waiting));
+        assert_eq!(conn.status(after), Some(Status::Waiting));
+        assert_eq!(conn.poll_reply(broadcast).unwrap().target(), TARGET);
+
+        conn.handle_datagram(addr(), &reply(&after_transmit, TARGET, power));
+        assert_eq!(conn.status(after), Some(Status::Answered));
+        assert_eq!(conn.status(before), Some(Status::Waiting));
+        assert_eq!(conn.status(broadcast), Some(Status::Waiting));
+    }
+
+    #[test]
+    fn test_open_request() {
+        let mut conn = connection();
+        let now = Instant::now();
+        let id = conn
+            .open_request(
+                addr(),
+                Some(TARGET),
+                Message::GetColorZones {
+                    start_index: 0,
+                    end_index: 15,
+                },
+                true,
+                true,
+            )
+            .unwrap();
+        let transmit = conn.poll_transmit().unwrap();
+        assert_eq!(conn.poll_timeout(), None);
+        assert_eq!(conn.deadline(id), None);
+
+        // no reply answers it, and it never expires
+        let zones = |index| Message::StateZone {
+            count: 16,
+            index,
+            color: lifx_core::HSBK {
+                hue: 0,
+                saturation: 0,
+                brightness: 0,
+                kelvin: 3500,
+            },
+        };
+        for index in [0, 8] {
+            let routed = conn.handle_datagram(addr(), &reply(&transmit, TARGET, zones(index)));
+            assert_eq!(routed, Some(id));
+        }
+        conn.handle_timeout(now + Duration::from_secs(3600));
+        assert_eq!(conn.status(id), Some(Status::Waiting));
+        assert_eq!(std::iter::from_fn(|| conn.poll_reply(id)).count(), 2);
+
+        // until it's finished
+        conn.finish(id);
+        assert!(conn.is_empty());
+        let routed = conn.handle_datagram(addr(), &reply(&transmit, TARGET, zones(0)));
+        assert_eq!(routed, None);
+    }
+
+    #[test]
+    fn test_set_source() {
+        let mut conn = connection();
+        let id = conn
+            .open_request(addr(), Some(TARGET), Message::GetPower, false, true)
+            .unwrap();
+        let transmit = conn.poll_transmit().unwrap();
+        conn.set_source(SourceId::new(0x5678).unwrap());
+
+        // replies to the old source are no longer ours
+        let power = Message::StatePower { level: 0 };
+        assert_eq!(
+            conn.handle_datagram(addr(), &reply(&transmit, TARGET, power)),
+            None
+        );
+        conn.finish(id);
+        conn.open_request(addr(), Some(TARGET), Message::GetPower, false, true)
+            .unwrap();
+        let raw = RawMessage::unpack(&conn.poll_transmit().unwrap().contents).unwrap();
+        assert_eq!(raw.frame.source(), 0x5678);
+    }
+
+    #[test]
+    fn test_sequences_are_reused() {
+        let mut conn = connection();
+        let deadline = Instant::now();
+        for _ in 0..256 {
+            let id = conn
+                .send_request(
+                    addr(),
+                    Some(TARGET),
+                    Message::GetPower,
+                    false,
+                    true,
+                    deadline,
+                )
+                .unwrap();
+            conn.finish(id);
+        }
+        let ids: Vec<_> = (0..256)
+            .map(|_| {
+                conn.send_request(
+                    addr(),
+                    Some(TARGET),
+                    Message::GetPower,
+                    false,
+                    true,
+                    deadline,
+                )
+                .unwrap()
+            })
+            .collect();
+        assert!(matches!(
+            conn.send_request(
+                addr(),
+                Some(TARGET),
+                Message::GetPower,
+                false,
+                true,
+                deadline
+            ),
+            Err(Error::SequenceExhausted(TARGET))
+        ));
+        conn.handle_timeout(deadline);
+        assert!(ids
+            .iter()
+            .all(|&id| conn.status(id) == Some(Status::Expired)));
+        assert!(conn
+            .send_request(
+                addr(),
+                Some(TARGET),
+                Message::GetPower,
+                false,
+                true,
+                deadline
+            )
+            .is_ok());
+    }
+}
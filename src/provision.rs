@@ -0,0 +1,399 @@
+//! Renaming devices, and sorting out their groups and locations
+//!
+//! Groups and locations aren't stored centrally: each device keeps its own copy of the ID and
+//! name of the group (and location) it's in.  Apps are supposed to keep these consistent, but in
+//! practice they drift apart: a group gets renamed while one of its devices is unplugged, or two
+//! apps each create a group with the same name.  The result is devices that share a group ID but
+//! disagree about its name, or groups with the same name but different IDs, which apps then show
+//! as separate groups.
+//!
+//! [find_conflicts] reports both problems, and [canonicalize] works out the messages to fix them.
+//! [assign] and [rename] build the messages to provision devices in bulk, and [send_all] sends
+//! them.
+//!
+//! ```
+//! use lifx::provision::{canonicalize, find_conflicts, Kind};
+//! use lifx::DeviceState;
+//! use lifx_core::LifxIdent;
+//!
+//! let mut devices = vec![DeviceState::new(1), DeviceState::new(2), DeviceState::new(3)];
+//! let id = LifxIdent([1; 16]);
+//! devices[0].group = Some((id, "Kitchen".to_owned()));
+//! devices[1].group = Some((id, "Kitchen".to_owned()));
+//! devices[2].group = Some((id, "Kitchen (old)".to_owned()));
+//!
+//! assert_eq!(find_conflicts(&devices, Kind::Group).len(), 1);
+//! // the odd one out gets renamed to match
+//! let fixes = canonicalize(&devices, Kind::Group);
+//! assert_eq!(fixes.len(), 1);
+//! assert_eq!(fixes[0].0, 3);
+//! ```
+
+use crate::client::Client;
+use crate::reliable::ReliableSender;
+use crate::state::DeviceState;
+use crate::Error;
+use lifx_core::{LifxIdent, LifxString, Message};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+
+/// Whether to look at groups or locations (which work the same way)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Kind {
+    Group,
+    Location,
+}
+
+impl Kind {
+    fn of(self, device: &DeviceState) -> Option<&(LifxIdent, String)> {
+        match self {
+            Kind::Group => device.group.as_ref(),
+            Kind::Location => device.location.as_ref(),
+        }
+    }
+
+    fn message(self, id: LifxIdent, name: &str) -> Message {
+        match self {
+            Kind::Group => Message::set_group(id, lifx_string(name)),
+            Kind::Location => Message::set_location(id, lifx_string(name)),
+        }
+    }
+}
+
+/// Devices that disagree about a group or location
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Conflict {
+    /// Devices with the same ID, but different names for it
+    ///
+    /// Each name is listed with the devices that use it.
+    Names {
+        id: LifxIdent,
+        names: Vec<(String, Vec<u64>)>,
+    },
+    /// Devices with the same name, but different IDs
+    ///
+    /// Each ID is listed with the devices that use it.
+    Ids {
+        name: String,
+        ids: Vec<(LifxIdent, Vec<u64>)>,
+    },
+}
+
+/// Finds groups (or locations) that devices disagree about
+pub fn find_conflicts(devices: &[DeviceState], kind: Kind) -> Vec<Conflict> {
+    let mut by_id: BTreeMap<[u8; 16], BTreeMap<&str, Vec<u64>>> = BTreeMap::new();
+    let mut by_name: BTreeMap<&str, BTreeMap<[u8; 16], Vec<u64>>> = BTreeMap::new();
+    for device in devices {
+        if let Some((id, name)) = kind.of(device) {
+            by_id
+                .entry(id.0)
+                .or_default()
+                .entry(name)
+                .or_default()
+                .push(device.target);
+            by_name
+                .entry(name)
+                .or_default()
+                .entry(id.0)
+                .or_default()
+                .push(device.target);
+        }
+    }
+
+    let mut conflicts = Vec::new();
+    for (id, names) in by_id {
+        if names.len() > 1 {
+            conflicts.push(Conflict::Names {
+                id: LifxIdent(id),
+                names: names
+                    .into_iter()
+                    .map(|(name, targets)| (name.to_owned(), targets))
+                    .collect(),
+            });
+        }
+    }
+    for (name, ids) in by_name {
+        if ids.len() > 1 {
+            conflicts.push(Conflict::Ids {
+                name: name.to_owned(),
+                ids: ids
+                    .into_iter()
+                    .map(|(id, targets)| (LifxIdent(id), targets))
+                    .collect(),
+            });
+        }
+    }
+    conflicts
+}
+
+/// The messages that resolve every conflict found by [find_conflicts], as `(target, message)`
+///
+/// Where devices disagree, the majority wins: devices with the same ID all get the name that most
+/// of them already have, and then devices with the same name all get the ID that most of them
+/// already have.  Ties go to the name or ID that sorts first, so the result doesn't depend on the
+/// order of `devices`.  Each device gets at most one message.
+pub fn canonicalize(devices: &[DeviceState], kind: Kind) -> Vec<(u64, Message)> {
+    // first settle on one name for each ID
+    let mut resolved: BTreeMap<u64, (LifxIdent, String)> = devices
+        .iter()
+        .filter_map(|d| kind.of(d).map(|g| (d.target, g.clone())))
+        .collect();
+    for conflict in find_conflicts(devices, kind) {
+        if let Conflict::Names { id, names } = conflict {
+            let (name, _) = majority(names);
+            for (_, group) in resolved.iter_mut().filter(|(_, g)| g.0 == id) {
+                group.1 = name.clone();
+            }
+        }
+    }
+
+    // then on one ID for each name, using the names from above
+    let mut by_name: BTreeMap<String, BTreeMap<[u8; 16], Vec<u64>>> = BTreeMap::new();
+    for (target, (id, name)) in &resolved {
+        by_name
+            .entry(name.clone())
+            .or_default()
+            .entry(id.0)
+            .or_default()
+            .push(*target);
+    }
+    for ids in by_name.into_values() {
+        if ids.len() > 1 {
+            let targets: Vec<u64> = ids.values().flatten().copied().collect();
+            let (id, _) = majority(ids.into_iter().collect());
+            for target in targets {
+                if let Some(group) = resolved.get_mut(&target) {
+                    group.0 = LifxIdent(id);
+                }
+            }
+        }
+    }
+
+    let mut messages = Vec::new();
+    for device in devices {
+        if let (Some(current), Some(wanted)) = (kind.of(device), resolved.get(&device.target)) {
+            if current != wanted {
+                messages.push((device.target, kind.message(wanted.0, &wanted.1)));
+            }
+        }
+    }
+    messages
+}
+
+/// The entry with the most devices, preferring the first on a tie
+fn majority<T>(entries: Vec<(T, Vec<u64>)>) -> (T, Vec<u64>) {
+    let mut best: Option<(T, Vec<u64>)> = None;
+    for entry in entries {
+        if best.as_ref().is_none_or(|b| entry.1.len() > b.1.len()) {
+            best = Some(entry);
+        }
+    }
+    best.expect("conflicts always have at least two entries")
+}
+
+/// The messages that put each of `targets` into the group (or location) called `name`
+///
+/// If any device is already in a group with that name, its ID is reused, so the devices join
+/// that group rather than creating a second one with the same name.  Devices that are already in
+/// it are skipped.
+pub fn assign(
+    devices: &[DeviceState],
+    kind: Kind,
+    targets: &[u64],
+    name: &str,
+) -> Vec<(u64, Message)> {
+    let id = ident_for(devices.iter().filter_map(|d| kind.of(d)), name);
+    targets
+        .iter()
+        .filter(|target| {
+            let current = devices
+                .iter()
+                .find(|d| d.target == **target)
+                .and_then(|d| kind.of(d));
+            current != Some(&(id, name.to_owned()))
+        })
+        .map(|target| (*target, kind.message(id, name)))
+        .collect()
+}
+
+/// The messages that give devices new labels, skipping any that already have them
+///
+/// Labels longer than 31 bytes are truncated.
+pub fn rename(devices: &[DeviceState], labels: &[(u64, &str)]) -> Vec<(u64, Message)> {
+    labels
+        .iter()
+        .filter(|(target, label)| {
+            let current = devices.iter().find(|d| d.target == *target);
+            current.and_then(|d| d.label.as_deref()) != Some(*label)
+        })
+        .map(|(target, label)| {
+            (
+                *target,
+                Message::SetLabel {
+                    label: lifx_string(label),
+                },
+            )
+        })
+        .collect()
+}
+
+/// Sends each message to its device, retrying until it's acknowledged (up to 3 attempts)
+///
+/// The device's address comes from `devices`.  Returns an error if a device's address isn't
+/// known, or if any device doesn't acknowledge its message, in which case the messages after it
+/// aren't sent.
+pub async fn send_all(
+    client: &Client,
+    devices: &[DeviceState],
+    messages: &[(u64, Message)],
+) -> Result<(), Error> {
+    let sender = ReliableSender::new(client.clone(), 3);
+    for (target, msg) in messages {
+        let addr = devices
+            .iter()
+            .find(|d| d.target == *target)
+            .and_then(|d| d.addr)
+            .ok_or(Error::UnknownAddress(*target))?;
+        sender.send_acked(addr, *target, msg.clone()).await?;
+    }
+    Ok(())
+}
+
+/// The ID of the group (or location) with this name, so that devices with the same group name
+/// end up in the same group
+///
+/// If nothing is in it yet, the ID is derived from the name, so that provisioning the same
+/// devices twice doesn't create two different groups.  The derived ID has to be the same from one
+/// build to the next, so it's two 64-bit FNV-1a hashes rather than anything from `std::hash`.
+///
+/// ```
+/// use lifx::provision::ident_for;
+///
+/// let id = ident_for(std::iter::empty(), "Downstairs");
+/// assert_eq!(ident_for(std::iter::empty(), "Downstairs"), id);
+/// assert_eq!(ident_for([(id, "Downstairs".to_owned())].iter(), "Downstairs"), id);
+/// ```
+pub fn ident_for<'a>(
+    mut existing: impl Iterator<Item = &'a (LifxIdent, String)>,
+    name: &str,
+) -> LifxIdent {
+    if let Some((id, _)) = existing.find(|(_, n)| n == name) {
+        return *id;
+    }
+    let mut id = [0; 16];
+    for (seed, chunk) in id.chunks_mut(8).enumerate() {
+        chunk.copy_from_slice(&fnv1a(seed as u8, name.as_bytes()).to_le_bytes());
+    }
+    LifxIdent(id)
+}
+
+/// The 64-bit FNV-1a hash of `seed` followed by `bytes`
+fn fnv1a(seed: u8, bytes: &[u8]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const PRIME: u64 = 0x0100_0000_01b3;
+    std::iter::once(&seed)
+        .chain(bytes)
+        .fold(OFFSET_BASIS, |hash, &b| {
+            (hash ^ b as u64).wrapping_mul(PRIME)
+        })
+}
+
+pub(crate) fn lifx_string(s: &str) -> LifxString {
+    // anything after a nul can't be sent anyway
+    let s = s.split('\0').next().unwrap_or_default();
+    LifxString::new(&CString::new(s).unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(target: u64, group: Option<(u8, &str)>) -> DeviceState {
+        let mut state = DeviceState::new(target);
+        state.group = group.map(|(id, name)| (LifxIdent([id; 16]), name.to_owned()));
+        state
+    }
+
+    fn group_of(msg: &Message) -> (LifxIdent, String) {
+        match msg {
+            Message::SetGroup { group, label, .. } => (*group, label.to_string()),
+            msg => panic!("unexpected message {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let devices = [
+            device(1, Some((1, "Kitchen"))),
+            device(2, Some((1, "Kitchen"))),
+            device(3, Some((1, "Kitchen (old)"))),
+            device(4, Some((2, "Kitchen"))),
+            device(5, Some((3, "Lounge"))),
+            device(6, None),
+        ];
+        let conflicts = find_conflicts(&devices, Kind::Group);
+        assert_eq!(
+            conflicts,
+            vec![
+                Conflict::Names {
+                    id: LifxIdent([1; 16]),
+                    names: vec![
+                        ("Kitchen".to_owned(), vec![1, 2]),
+                        ("Kitchen (old)".to_owned(), vec![3])
+                    ],
+                },
+                Conflict::Ids {
+                    name: "Kitchen".to_owned(),
+                    ids: vec![
+                        (LifxIdent([1; 16]), vec![1, 2]),
+                        (LifxIdent([2; 16]), vec![4])
+                    ],
+                },
+            ]
+        );
+        assert!(find_conflicts(&devices, Kind::Location).is_empty());
+
+        // 3 is renamed, and 4 joins the bigger Kitchen group
+        let fixes = canonicalize(&devices, Kind::Group);
+        let fixes: Vec<_> = fixes.iter().map(|(t, m)| (*t, group_of(m))).collect();
+        let kitchen = (LifxIdent([1; 16]), "Kitchen".to_owned());
+        assert_eq!(fixes, vec![(3, kitchen.clone()), (4, kitchen)]);
+    }
+
+    #[test]
+    fn test_assign() {
+        let devices = [device(1, Some((1, "Kitchen"))), device(2, None)];
+        let messages = assign(&devices, Kind::Group, &[1, 2, 3], "Kitchen");
+        let targets: Vec<_> = messages.iter().map(|(t, _)| *t).collect();
+        assert_eq!(targets, vec![2, 3]);
+        assert_eq!(group_of(&messages[0].1).0, LifxIdent([1; 16]));
+
+        // a new group gets the same ID every time
+        let a = assign(&devices, Kind::Group, &[2], "Lounge");
+        let b = assign(&devices, Kind::Group, &[2], "Lounge");
+        assert_eq!(group_of(&a[0].1), group_of(&b[0].1));
+        assert_ne!(group_of(&a[0].1).0, LifxIdent([1; 16]));
+    }
+
+    #[test]
+    fn test_rename() {
+        let mut devices = [DeviceState::new(1), DeviceState::new(2)];
+        devices[0].label = Some("Porch".to_owned());
+        let messages = rename(&devices, &[(1, "Porch"), (2, "Hall")]);
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].0, 2);
+    }
+
+    #[test]
+    fn test_ident_for() {
+        // derived IDs are part of what's stored on devices, so they must never change
+        let id = ident_for(std::iter::empty(), "Downstairs");
+        assert_eq!(
+            id.0,
+            [9, 110, 129, 66, 185, 163, 100, 119, 182, 171, 50, 74, 174, 39, 205, 55]
+        );
+        let existing = [(LifxIdent([7; 16]), "Downstairs".to_owned())];
+        assert_eq!(ident_for(existing.iter(), "Downstairs"), LifxIdent([7; 16]));
+        assert_ne!(ident_for(existing.iter(), "Upstairs"), id);
+    }
+}
@@ -0,0 +1,219 @@
+//! The outgoing message queue used by [Client](crate::Client)
+//!
+//! Every datagram sent by a client passes through a single queue, which is drained by a background
+//! task.  Messages are split into [Priority] classes, and the queue is drained with a weighted
+//! round-robin schedule, so higher priority messages go out first but no class is ever starved:
+//! a burst of animation frames can delay discovery, but can't stop it from happening.
+//!
+//! Each class has a bounded capacity.  When a class is full, the oldest message in that class is
+//! dropped to make room (since for things like animation frames, only the newest one matters),
+//! and whoever sent the dropped message is told so with [Error::Dropped](crate::Error::Dropped).
+
+use crate::telemetry;
+use crate::Error;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use tokio::sync::{oneshot, Notify};
+
+/// How urgently a message should be sent
+///
+/// The ordering of the variants reflects their priority, from lowest to highest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    /// Discovering new devices on the network
+    Discovery,
+    /// Periodically querying the state of known devices
+    Refresh,
+    /// Commands that a user is waiting on, like turning a light on or changing its color
+    User,
+}
+
+impl Priority {
+    pub const ALL: [Priority; 3] = [Priority::Discovery, Priority::Refresh, Priority::User];
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// The order in which classes are served.  Empty classes are skipped, so when only one class has
+/// anything queued, it gets the whole send rate.
+const SCHEDULE: [Priority; 7] = [
+    Priority::User,
+    Priority::User,
+    Priority::Refresh,
+    Priority::User,
+    Priority::User,
+    Priority::Refresh,
+    Priority::Discovery,
+];
+
+/// A datagram waiting to be sent
+pub(crate) struct Outgoing {
+    pub addr: SocketAddr,
+    pub bytes: Vec<u8>,
+    pub done: oneshot::Sender<Result<(), Error>>,
+}
+
+/// A snapshot of the state of a client's send queue
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct QueueStats {
+    depth: [usize; 3],
+    dropped: [u64; 3],
+}
+
+impl QueueStats {
+    /// The number of messages of the given priority currently waiting to be sent
+    pub fn depth(&self, priority: Priority) -> usize {
+        self.depth[priority.index()]
+    }
+
+    /// The total number of messages of the given priority that have been dropped because the queue
+    /// was full
+    pub fn dropped(&self, priority: Priority) -> u64 {
+        self.dropped[priority.index()]
+    }
+}
+
+pub(crate) struct SendQueue {
+    queues: [VecDeque<Outgoing>; 3],
+    capacity: usize,
+    dropped: [u64; 3],
+    cursor: usize,
+}
+
+impl SendQueue {
+    pub fn new(capacity: usize) -> SendQueue {
+        SendQueue {
+            queues: Default::default(),
+            capacity: capacity.max(1),
+            dropped: [0; 3],
+            cursor: 0,
+        }
+    }
+
+    /// Adds a message to the queue, dropping the oldest message of the same priority if needed
+    pub fn push(&mut self, priority: Priority, item: Outgoing) {
+        let queue = &mut self.queues[priority.index()];
+        if queue.len() >= self.capacity {
+            if let Some(old) = queue.pop_front() {
+                self.dropped[priority.index()] += 1;
+                telemetry::queue_dropped(priority);
+                let _ = old.done.send(Err(Error::Dropped));
+            }
+        }
+        queue.push_back(item);
+        telemetry::queue_depth(priority, queue.len());
+    }
+
+    /// Takes the next message to send, if there is one
+    pub fn pop(&mut self) -> Option<Outgoing> {
+        for _ in 0..SCHEDULE.len() {
+            let priority = SCHEDULE[self.cursor];
+            self.cursor = (self.cursor + 1) % SCHEDULE.len();
+            let queue = &mut self.queues[priority.index()];
+            if let Some(item) = queue.pop_front() {
+                telemetry::queue_depth(priority, queue.len());
+                return Some(item);
+            }
+        }
+        None
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        let mut depth = [0; 3];
+        for (d, q) in depth.iter_mut().zip(&self.queues) {
+            *d = q.len();
+        }
+        QueueStats {
+            depth,
+            dropped: self.dropped,
+        }
+    }
+}
+
+/// A [SendQueue] that can be shared between the client and its send task
+pub(crate) struct SharedQueue {
+    queue: Mutex<SendQueue>,
+    notify: Notify,
+}
+
+impl SharedQueue {
+    pub fn new(capacity: usize) -> SharedQueue {
+        SharedQueue {
+            queue: Mutex::new(SendQueue::new(capacity)),
+            notify: Notify::new(),
+        }
+    }
+
+    pub fn push(&self, priority: Priority, item: Outgoing) {
+        self.queue.lock().unwrap().push(priority, item);
+        self.notify.notify_one();
+    }
+
+    /// Waits for the next message to send
+    pub async fn pop(&self) -> Outgoing {
+        loop {
+            if let Some(item) = self.queue.lock().unwrap().pop() {
+                return item;
+            }
+            self.notify.notified().await;
+        }
+    }
+
+    pub fn stats(&self) -> QueueStats {
+        self.queue.lock().unwrap().stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn item(port: u16) -> (Outgoing, oneshot::Receiver<Result<(), Error>>) {
+        let (done, rx) = oneshot::channel();
+        let out = Outgoing {
+            addr: SocketAddr::from(([127, 0, 0, 1], port)),
+            bytes: Vec::new(),
+            done,
+        };
+        (out, rx)
+    }
+
+    #[test]
+    fn test_no_starvation() {
+        let mut queue = SendQueue::new(100);
+        for _ in 0..50 {
+            queue.push(Priority::User, item(3).0);
+        }
+        queue.push(Priority::Discovery, item(1).0);
+        queue.push(Priority::Refresh, item(2).0);
+
+        let order: Vec<u16> = std::iter::from_fn(|| queue.pop())
+            .map(|o| o.addr.port())
+            .collect();
+        assert_eq!(order.len(), 52);
+        // user messages go first, but the others get a turn long before the burst is done
+        assert_eq!(order[0], 3);
+        assert!(order[..SCHEDULE.len()].contains(&1));
+        assert!(order[..SCHEDULE.len()].contains(&2));
+    }
+
+    #[test]
+    fn test_drop_oldest() {
+        let mut queue = SendQueue::new(2);
+        let (a, mut a_rx) = item(1);
+        queue.push(Priority::Refresh, a);
+        queue.push(Priority::Refresh, item(2).0);
+        queue.push(Priority::Refresh, item(3).0);
+        queue.push(Priority::User, item(4).0);
+
+        assert!(matches!(a_rx.try_recv(), Ok(Err(Error::Dropped))));
+        let stats = queue.stats();
+        assert_eq!(stats.depth(Priority::Refresh), 2);
+        assert_eq!(stats.depth(Priority::User), 1);
+        assert_eq!(stats.dropped(Priority::Refresh), 1);
+        assert_eq!(stats.dropped(Priority::User), 0);
+    }
+}
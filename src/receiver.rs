@@ -0,0 +1,288 @@
+//! A receive loop on its own thread, which can be stopped
+//!
+//! Programs that don't use the async [Client](crate::Client) often start a thread that receives
+//! LIFX messages in a loop and updates some shared state.  A [Receiver] is that loop: it decodes
+//! each datagram and hands it to a callback, waits out network problems instead of giving up, and
+//! reports anything that goes wrong to an error callback.
+//!
+//! The loop checks a [Shutdown] token between reads, so it stops within one read timeout of the
+//! token being triggered.  [ReceiverHandle::stop] does that and hands back the transport, so the
+//! receiver can be started again later with the same socket.
+//!
+//! ```no_run
+//! # fn example() -> Result<(), lifx::Error> {
+//! use lifx::receiver::Receiver;
+//! use std::net::UdpSocket;
+//! use std::time::Duration;
+//!
+//! let socket = UdpSocket::bind("0.0.0.0:56700")?;
+//! let handle = Receiver::new(socket)
+//!     .with_read_timeout(Duration::from_millis(250))
+//!     .on_error(|e| eprintln!("receive error: {}", e))
+//!     .spawn(|raw, from| println!("type {} from {}", raw.protocol_header.typ, from));
+//!
+//! // ... later
+//! let socket = handle.stop();
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::transport::{self, Backoff, BlockingTransport, ErrorClass, RECV_BUFFER_SIZE};
+use crate::Error;
+use lifx_core::RawMessage;
+use std::net::SocketAddr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+/// How long a [Receiver] waits for each read by default, which is how quickly it notices a
+/// [Shutdown]
+pub const DEFAULT_READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// A flag for asking loops to stop
+///
+/// This is cheap to clone, and all clones share the same flag.  Once triggered, it stays
+/// triggered.
+#[derive(Debug, Clone, Default)]
+pub struct Shutdown {
+    inner: Arc<(Mutex<bool>, Condvar)>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        Shutdown::default()
+    }
+
+    /// Asks everything watching this token to stop
+    pub fn trigger(&self) {
+        let (flag, cvar) = &*self.inner;
+        *flag.lock().unwrap() = true;
+        cvar.notify_all();
+    }
+
+    pub fn is_triggered(&self) -> bool {
+        *self.inner.0.lock().unwrap()
+    }
+
+    /// Sleeps for `timeout`, or until the token is triggered, and returns whether it was
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let (flag, cvar) = &*self.inner;
+        let guard = flag.lock().unwrap();
+        let (guard, _) = cvar
+            .wait_timeout_while(guard, timeout, |triggered| !*triggered)
+            .unwrap();
+        *guard
+    }
+}
+
+type ErrorCallback = Box<dyn FnMut(Error) + Send>;
+
+/// A loop that receives and decodes LIFX messages from a [BlockingTransport]
+pub struct Receiver<T> {
+    transport: T,
+    read_timeout: Duration,
+    on_error: Option<ErrorCallback>,
+}
+
+impl<T: BlockingTransport> Receiver<T> {
+    pub fn new(transport: T) -> Receiver<T> {
+        Receiver {
+            transport,
+            read_timeout: DEFAULT_READ_TIMEOUT,
+            on_error: None,
+        }
+    }
+
+    /// Sets how long each read waits (the default is [DEFAULT_READ_TIMEOUT])
+    ///
+    /// A shorter timeout stops the loop sooner after a [Shutdown], at the cost of waking up more
+    /// often when nothing is arriving.
+    pub fn with_read_timeout(mut self, read_timeout: Duration) -> Receiver<T> {
+        self.read_timeout = read_timeout;
+        self
+    }
+
+    /// Calls `f` with every error the loop runs into
+    ///
+    /// This includes datagrams that can't be decoded ([Error::Protocol]), datagrams that are too
+    /// large to be LIFX messages, and receive errors other than timeouts.  None of them stop the
+    /// loop: when the network is down, it waits a while (longer each time) and tries again.
+    pub fn on_error(mut self, f: impl FnMut(Error) + Send + 'static) -> Receiver<T> {
+        self.on_error = Some(Box::new(f));
+        self
+    }
+
+    /// Receives messages on the current thread until `shutdown` is triggered, calling
+    /// `on_message` with each one and where it came from
+    ///
+    /// Returns the transport, so it can be used again.
+    pub fn run(self, shutdown: &Shutdown, mut on_message: impl FnMut(RawMessage, SocketAddr)) -> T {
+        let mut buf = vec![0; RECV_BUFFER_SIZE];
+        let mut backoff = Backoff::default();
+        let Receiver {
+            transport,
+            read_timeout,
+            mut on_error,
+        } = self;
+        let mut report = |e| {
+            if let Some(on_error) = &mut on_error {
+                on_error(e);
+            }
+        };
+        while !shutdown.is_triggered() {
+            match transport.recv_from(&mut buf, Some(read_timeout)) {
+                Ok((nbytes, from)) => {
+                    backoff.reset();
+                    match RawMessage::unpack(&buf[..nbytes]) {
+                        Ok(raw) => on_message(raw, from),
+                        Err(e) => report(e.into()),
+                    }
+                }
+                Err(e) => match transport::classify(&e) {
+                    // this includes the read timeout running out
+                    ErrorClass::Transient => continue,
+                    // ICMP errors from earlier sends can show up here
+                    ErrorClass::Unreachable => continue,
+                    ErrorClass::Truncated => report(e.into()),
+                    ErrorClass::NetworkDown | ErrorClass::Fatal => {
+                        let delay = backoff.next_delay();
+                        report(e.into());
+                        shutdown.wait_timeout(delay);
+                    }
+                },
+            }
+        }
+        transport
+    }
+}
+
+impl<T: BlockingTransport + Send + 'static> Receiver<T> {
+    /// Starts the loop on a new thread
+    pub fn spawn(
+        self,
+        on_message: impl FnMut(RawMessage, SocketAddr) + Send + 'static,
+    ) -> ReceiverHandle<T> {
+        let shutdown = Shutdown::new();
+        let token = shutdown.clone();
+        let thread = thread::spawn(move || self.run(&token, on_message));
+        ReceiverHandle { shutdown, thread }
+    }
+}
+
+/// A [Receiver] running on its own thread
+pub struct ReceiverHandle<T> {
+    shutdown: Shutdown,
+    thread: JoinHandle<T>,
+}
+
+impl<T> ReceiverHandle<T> {
+    /// The token that stops the loop, for sharing with other loops that should stop at the same
+    /// time
+    pub fn shutdown_token(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    /// Whether the loop has stopped (because it was told to, or because a callback panicked)
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Stops the loop, waits for the thread to finish, and returns the transport
+    ///
+    /// # Panics
+    ///
+    /// If one of the callbacks panicked, this panics with the same payload.
+    pub fn stop(self) -> T {
+        self.shutdown.trigger();
+        match self.thread.join() {
+            Ok(transport) => transport,
+            Err(payload) => std::panic::resume_unwind(payload),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lifx_core::{BuildOptions, Message};
+    use std::io;
+    use std::net::UdpSocket;
+    use std::sync::mpsc;
+    use std::time::Instant;
+
+    #[test]
+    fn test_receiver() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let addr = socket.local_addr().unwrap();
+        let (messages, received) = mpsc::channel();
+        let (errors, reported) = mpsc::channel();
+        let handle = Receiver::new(socket)
+            .with_read_timeout(Duration::from_millis(20))
+            .on_error(move |e| errors.send(e).unwrap())
+            .spawn(move |raw, _| messages.send(raw).unwrap());
+
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        sender.send_to(b"not a LIFX message", addr).unwrap();
+        let raw = RawMessage::build(&BuildOptions::default(), Message::GetLabel).unwrap();
+        sender.send_to(&raw.pack().unwrap(), addr).unwrap();
+
+        let wait = Duration::from_secs(1);
+        assert_eq!(received.recv_timeout(wait).unwrap(), raw);
+        assert!(matches!(
+            reported.recv_timeout(wait).unwrap(),
+            Error::Protocol(_)
+        ));
+
+        // stopping hands back the socket, which can be used again
+        let start = Instant::now();
+        let socket = handle.stop();
+        assert!(start.elapsed() < Duration::from_millis(500));
+        assert_eq!(socket.local_addr().unwrap(), addr);
+    }
+
+    /// A transport whose network is always down
+    struct Down;
+
+    impl BlockingTransport for Down {
+        fn send_to(&self, _: &[u8], _: SocketAddr) -> io::Result<usize> {
+            Err(io::ErrorKind::NetworkDown.into())
+        }
+
+        fn recv_from(&self, _: &mut [u8], _: Option<Duration>) -> io::Result<(usize, SocketAddr)> {
+            Err(io::ErrorKind::NetworkDown.into())
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            Err(io::ErrorKind::NetworkDown.into())
+        }
+    }
+
+    #[test]
+    fn test_shutdown_while_backing_off() {
+        let (errors, reported) = mpsc::channel();
+        let handle = Receiver::new(Down)
+            .on_error(move |e| errors.send(e).unwrap())
+            .spawn(|_, _| panic!("nothing should be received"));
+        assert!(matches!(
+            reported.recv_timeout(Duration::from_secs(1)).unwrap(),
+            Error::Io(_)
+        ));
+        // backing off doubles the wait each time, so after a second there have only been a few
+        thread::sleep(Duration::from_secs(1));
+        assert!(reported.try_iter().count() < 5);
+
+        let start = Instant::now();
+        handle.stop();
+        assert!(start.elapsed() < Duration::from_millis(500));
+    }
+
+    #[test]
+    fn test_shutdown_token() {
+        let shutdown = Shutdown::new();
+        assert!(!shutdown.wait_timeout(Duration::from_millis(1)));
+        let clone = shutdown.clone();
+        thread::spawn(move || clone.trigger());
+        assert!(shutdown.wait_timeout(Duration::from_secs(5)));
+        assert!(shutdown.is_triggered());
+    }
+}
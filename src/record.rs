@@ -0,0 +1,395 @@
+//! Recording every message a client sends and receives, and replaying them later
+//!
+//! A [Recorder] attached to a [Client](crate::Client) (with
+//! [Client::set_recorder](crate::Client::set_recorder)) writes every datagram the
+//! client sends or receives to a file, with a timestamp.  The recording can then be read back
+//! with [RecordReader], and the sent half replayed with [replay], with the original timing, to
+//! reproduce a problem against a test device.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), Box<dyn std::error::Error>> {
+//! use lifx::record::{replay, RecordReader, Recorder};
+//! use lifx::Client;
+//! use std::fs::File;
+//! use std::time::Duration;
+//!
+//! let client = Client::new().await?;
+//! let recorder = Recorder::new(File::create("session.lifxrec")?);
+//! client.set_recorder(Some(recorder.clone()));
+//! // ... talk to some devices ...
+//! client.set_recorder(None);
+//! recorder.flush()?;
+//!
+//! let records: Vec<_> = RecordReader::new(File::open("session.lifxrec")?).collect::<Result<_, _>>()?;
+//! let test_device = "127.0.0.1:56700".parse()?;
+//! let replies = replay(&records, test_device, Duration::from_secs(1)).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! # File format
+//!
+//! A recording is a sequence of records, each of which is (all integers little endian):
+//!
+//! * the time since recording started, in microseconds, as a `u64`
+//! * the direction: `0` for sent, `1` for received
+//! * the IP version of the remote address: `4` or `6`
+//! * the remote IP address (4 or 16 bytes) and port (`u16`)
+//! * the length of the datagram, as a `u16`, followed by the datagram itself
+
+use crate::clock::{Clock, SystemClock};
+use crate::Error;
+use lifx_core::RawMessage;
+use std::convert::TryFrom;
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::net::UdpSocket;
+
+/// Size of the buffer used to receive datagrams.
+const RECV_BUFFER_SIZE: usize = 4096;
+
+/// Whether a recorded datagram was sent or received
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+/// One recorded datagram
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    /// The time since the recording started
+    pub at: Duration,
+    pub direction: Direction,
+    /// Where the datagram was sent to, or received from
+    pub addr: SocketAddr,
+    /// The datagram, exactly as it was on the wire
+    pub bytes: Vec<u8>,
+}
+
+impl Record {
+    /// Decodes the datagram
+    pub fn raw(&self) -> Result<RawMessage, lifx_core::Error> {
+        RawMessage::unpack(&self.bytes)
+    }
+
+    /// Writes this record in the format described in the [module docs](self)
+    pub fn write_to(&self, w: &mut impl Write) -> io::Result<()> {
+        let micros = self.at.as_micros().min(u64::MAX as u128) as u64;
+        w.write_all(&micros.to_le_bytes())?;
+        w.write_all(&[match self.direction {
+            Direction::Sent => 0,
+            Direction::Received => 1,
+        }])?;
+        match self.addr.ip() {
+            IpAddr::V4(ip) => {
+                w.write_all(&[4])?;
+                w.write_all(&ip.octets())?;
+            }
+            IpAddr::V6(ip) => {
+                w.write_all(&[6])?;
+                w.write_all(&ip.octets())?;
+            }
+        }
+        w.write_all(&self.addr.port().to_le_bytes())?;
+        let len = u16::try_from(self.bytes.len())
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "datagram too large"))?;
+        w.write_all(&len.to_le_bytes())?;
+        w.write_all(&self.bytes)
+    }
+
+    /// Reads a record, or returns `None` at the end of the input
+    fn read_from(r: &mut impl Read) -> io::Result<Option<Record>> {
+        let mut micros = [0; 8];
+        // only a clean end of file is allowed here; anywhere else it's a truncated record
+        let mut filled = 0;
+        while filled < micros.len() {
+            match r.read(&mut micros[filled..])? {
+                0 if filled == 0 => return Ok(None),
+                0 => return Err(io::ErrorKind::UnexpectedEof.into()),
+                n => filled += n,
+            }
+        }
+        let invalid = |what: &str| io::Error::new(io::ErrorKind::InvalidData, what.to_owned());
+
+        let mut byte = [0; 1];
+        r.read_exact(&mut byte)?;
+        let direction = match byte[0] {
+            0 => Direction::Sent,
+            1 => Direction::Received,
+            _ => return Err(invalid("invalid direction")),
+        };
+        r.read_exact(&mut byte)?;
+        let ip: IpAddr = match byte[0] {
+            4 => {
+                let mut octets = [0; 4];
+                r.read_exact(&mut octets)?;
+                octets.into()
+            }
+            6 => {
+                let mut octets = [0; 16];
+                r.read_exact(&mut octets)?;
+                octets.into()
+            }
+            _ => return Err(invalid("invalid IP version")),
+        };
+        let mut short = [0; 2];
+        r.read_exact(&mut short)?;
+        let port = u16::from_le_bytes(short);
+        r.read_exact(&mut short)?;
+        let mut bytes = vec![0; u16::from_le_bytes(short) as usize];
+        r.read_exact(&mut bytes)?;
+
+        Ok(Some(Record {
+            at: Duration::from_micros(u64::from_le_bytes(micros)),
+            direction,
+            addr: SocketAddr::new(ip, port),
+            bytes,
+        }))
+    }
+}
+
+struct RecorderInner {
+    clock: Arc<dyn Clock>,
+    start: Instant,
+    writer: Box<dyn Write + Send>,
+    /// The first write error, which is reported by [Recorder::flush]
+    error: Option<io::Error>,
+}
+
+/// Writes datagrams to a recording as they're sent and received
+///
+/// This is cheap to clone, and all clones write to the same recording.
+#[derive(Clone)]
+pub struct Recorder {
+    inner: Arc<Mutex<RecorderInner>>,
+}
+
+impl Recorder {
+    /// Starts a new recording, with timestamps relative to now
+    ///
+    /// Each record is written with a separate call, so wrap files in a [BufWriter](io::BufWriter).
+    pub fn new(writer: impl Write + Send + 'static) -> Recorder {
+        Recorder::with_clock(writer, SystemClock)
+    }
+
+    /// Like [Recorder::new], but timestamping records with the given clock
+    pub fn with_clock(writer: impl Write + Send + 'static, clock: impl Clock) -> Recorder {
+        Recorder {
+            inner: Arc::new(Mutex::new(RecorderInner {
+                start: clock.now(),
+                clock: Arc::new(clock),
+                writer: Box::new(writer),
+                error: None,
+            })),
+        }
+    }
+
+    /// Adds a datagram to the recording
+    ///
+    /// Once a write fails, nothing else is recorded, and the error is returned by
+    /// [Recorder::flush].
+    pub fn record(&self, direction: Direction, addr: SocketAddr, bytes: &[u8]) {
+        let mut inner = self.inner.lock().unwrap();
+        if inner.error.is_some() {
+            return;
+        }
+        let record = Record {
+            at: inner.clock.now() - inner.start,
+            direction,
+            addr,
+            bytes: bytes.to_vec(),
+        };
+        if let Err(e) = record.write_to(&mut inner.writer) {
+            inner.error = Some(e);
+        }
+    }
+
+    /// Flushes the recording, returning the first error that happened while writing it
+    pub fn flush(&self) -> io::Result<()> {
+        let mut inner = self.inner.lock().unwrap();
+        match inner.error.take() {
+            Some(e) => Err(e),
+            None => inner.writer.flush(),
+        }
+    }
+}
+
+/// The recorder attached to a client, shared by its send and receive tasks
+pub(crate) type RecorderSlot = Arc<Mutex<Option<Recorder>>>;
+
+pub(crate) fn record(slot: &RecorderSlot, direction: Direction, addr: SocketAddr, bytes: &[u8]) {
+    if let Some(recorder) = &*slot.lock().unwrap() {
+        recorder.record(direction, addr, bytes);
+    }
+}
+
+/// Reads the records from a recording, in order
+pub struct RecordReader<R> {
+    reader: R,
+    done: bool,
+}
+
+impl<R: Read> RecordReader<R> {
+    pub fn new(reader: R) -> RecordReader<R> {
+        RecordReader {
+            reader,
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for RecordReader<R> {
+    type Item = io::Result<Record>;
+
+    /// The next record, stopping after the first error
+    fn next(&mut self) -> Option<io::Result<Record>> {
+        if self.done {
+            return None;
+        }
+        let record = Record::read_from(&mut self.reader).transpose();
+        self.done = !matches!(record, Some(Ok(_)));
+        record
+    }
+}
+
+/// Sends the sent half of a recording to `addr`, with the original timing, and records what
+/// comes back
+///
+/// Every [Direction::Sent] record is sent to `addr` (whatever address it was originally sent
+/// to), from a new socket, at the same time relative to the start as it was originally sent.
+/// Replies are collected until `settle` after the last message is sent, and returned as
+/// [Direction::Received] records, so they can be compared against the originals.
+pub async fn replay(
+    records: &[Record],
+    addr: SocketAddr,
+    settle: Duration,
+) -> Result<Vec<Record>, Error> {
+    let local = match addr {
+        SocketAddr::V4(_) => SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), 0),
+        SocketAddr::V6(_) => "[::]:0".parse().unwrap(),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    let start = tokio::time::Instant::now();
+    let mut replies = Vec::new();
+    let mut buf = vec![0; RECV_BUFFER_SIZE];
+
+    let mut to_send = records.iter().filter(|r| r.direction == Direction::Sent);
+    let mut next = to_send.next();
+    let mut deadline = start + settle;
+    loop {
+        let wake = match next {
+            Some(record) => start + record.at,
+            None => deadline,
+        };
+        tokio::select! {
+            res = socket.recv_from(&mut buf) => {
+                let (nbytes, from) = res?;
+                replies.push(Record {
+                    at: start.elapsed(),
+                    direction: Direction::Received,
+                    addr: from,
+                    bytes: buf[..nbytes].to_vec(),
+                });
+            }
+            _ = tokio::time::sleep_until(wake) => match next {
+                Some(record) => {
+                    socket.send_to(&record.bytes, addr).await?;
+                    deadline = tokio::time::Instant::now() + settle;
+                    next = to_send.next();
+                }
+                None => return Ok(replies),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::fake_bulb;
+    use crate::{Client, ClientOptions};
+    use lifx_core::Message;
+
+    /// A writer that can be read back while the recorder still has it
+    #[derive(Clone, Default)]
+    struct SharedBuf(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuf {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        let records = vec![
+            Record {
+                at: Duration::from_micros(5),
+                direction: Direction::Sent,
+                addr: "10.0.0.1:56700".parse().unwrap(),
+                bytes: vec![1, 2, 3],
+            },
+            Record {
+                at: Duration::from_secs(3),
+                direction: Direction::Received,
+                addr: "[fe80::1]:1234".parse().unwrap(),
+                bytes: vec![],
+            },
+        ];
+        let mut buf = Vec::new();
+        for record in &records {
+            record.write_to(&mut buf).unwrap();
+        }
+        let read: Vec<_> = RecordReader::new(&buf[..])
+            .collect::<Result<_, _>>()
+            .unwrap();
+        assert_eq!(read, records);
+
+        // a truncated recording is an error, not a short one
+        let mut reader = RecordReader::new(&buf[..buf.len() - 1]);
+        assert!(reader.next().unwrap().is_ok());
+        assert!(reader.next().unwrap().is_err());
+        assert!(reader.next().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_record_and_replay() {
+        let addr = fake_bulb(0x1234, "Kitchen").await;
+        let client = Client::with_options(ClientOptions {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let buf = SharedBuf::default();
+        client.set_recorder(Some(Recorder::new(buf.clone())));
+        client
+            .request(addr, 0x1234, Message::GetLabel)
+            .await
+            .unwrap();
+        client.set_recorder(None);
+
+        let bytes = buf.0.lock().unwrap().clone();
+        let records: Vec<_> = RecordReader::new(&bytes[..])
+            .collect::<Result<_, _>>()
+            .unwrap();
+        let directions: Vec<_> = records.iter().map(|r| r.direction).collect();
+        assert_eq!(directions, vec![Direction::Sent, Direction::Received]);
+        assert!(records.iter().all(|r| r.addr == addr));
+
+        // replaying gets the same reply again
+        let replies = replay(&records, addr, Duration::from_millis(200))
+            .await
+            .unwrap();
+        assert_eq!(replies.len(), 1);
+        assert_eq!(replies[0].bytes, records[1].bytes);
+    }
+}
@@ -0,0 +1,208 @@
+//! Switching the relays on a LIFX Switch safely
+//!
+//! The relays on a Switch can control real loads, like a ceiling fan, so a lost
+//! [Message::RelaySetPower] (or a burst of them from a bouncy button) matters more than a lost
+//! color change.  A [RelaySwitch] confirms every change by reading the relay back with
+//! [Message::RelayGetPower], and tries again if the relay didn't change.  It also debounces
+//! changes, so that a relay is never switched more often than a minimum interval.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), lifx::Error> {
+//! use lifx::relay::RelaySwitch;
+//! use lifx::Client;
+//! use std::time::Duration;
+//!
+//! let client = Client::new().await?;
+//! let switch = &client.discover(Duration::from_secs(1)).await?[0];
+//! let relays = RelaySwitch::new(client.clone(), switch.addr, switch.target);
+//! relays.set(0, true).await?;
+//! assert!(relays.get(0).await?);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::Client;
+use crate::reliable::ReliableSender;
+use crate::Error;
+use lifx_core::Message;
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// The level that a relay reports when it's on
+///
+/// Current Switches can't dim, so a relay is either 0 (off) or this.
+pub const RELAY_ON: u16 = 65535;
+
+/// Switches the relays on one device, confirming and debouncing every change
+pub struct RelaySwitch {
+    sender: ReliableSender,
+    addr: SocketAddr,
+    target: u64,
+    debounce: Duration,
+    attempts: usize,
+    /// When each relay was last changed, and what it was changed to
+    changed: Mutex<HashMap<u8, (Instant, bool)>>,
+}
+
+impl RelaySwitch {
+    /// Controls the relays on the device at `addr`
+    ///
+    /// Each message is retried up to 3 times if it isn't answered (see [ReliableSender]), each
+    /// change is tried up to 3 times if the relay doesn't report the new state, and relays are
+    /// switched at most once a second.
+    pub fn new(client: Client, addr: SocketAddr, target: u64) -> RelaySwitch {
+        RelaySwitch {
+            sender: ReliableSender::new(client, 3),
+            addr,
+            target,
+            debounce: Duration::from_secs(1),
+            attempts: 3,
+            changed: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Sets the minimum time between changes to the same relay
+    pub fn with_debounce(mut self, debounce: Duration) -> RelaySwitch {
+        self.debounce = debounce;
+        self
+    }
+
+    /// Sets how many times a change is tried before giving up (at least once)
+    pub fn with_attempts(mut self, attempts: usize) -> RelaySwitch {
+        self.attempts = attempts.max(1);
+        self
+    }
+
+    /// Asks the device whether a relay is on
+    pub async fn get(&self, relay_index: u8) -> Result<bool, Error> {
+        let msg = Message::RelayGetPower { relay_index };
+        match self.sender.request(self.addr, self.target, msg).await? {
+            Message::RelayStatePower { level, .. } => Ok(level != 0),
+            msg => Err(Error::Protocol(lifx_core::Error::ProtocolError(format!(
+                "unexpected reply to RelayGetPower: {:?}",
+                msg
+            )))),
+        }
+    }
+
+    /// Turns a relay on or off, and waits until the device confirms it
+    ///
+    /// If the relay was changed less than the debounce interval ago, this first waits for the rest
+    /// of the interval, unless the relay was changed to the same state, in which case there's
+    /// nothing to do.  Fails with [Error::Unconfirmed] if the relay still isn't in the requested
+    /// state after the last attempt.
+    pub async fn set(&self, relay_index: u8, on: bool) -> Result<(), Error> {
+        let last = self.changed.lock().unwrap().get(&relay_index).copied();
+        if let Some((at, state)) = last {
+            let ready = at + self.debounce;
+            if Instant::now() < ready {
+                if state == on {
+                    return Ok(());
+                }
+                tokio::time::sleep_until(ready).await;
+            }
+        }
+
+        let level = if on { RELAY_ON } else { 0 };
+        for _ in 0..self.attempts {
+            let msg = Message::RelaySetPower { relay_index, level };
+            match self.sender.send_acked(self.addr, self.target, msg).await {
+                // the change may have got through even if the ack didn't
+                Ok(()) | Err(Error::Timeout) => {}
+                Err(e) => return Err(e),
+            }
+            if self.get(relay_index).await? == on {
+                self.changed
+                    .lock()
+                    .unwrap()
+                    .insert(relay_index, (Instant::now(), on));
+                return Ok(());
+            }
+        }
+        Err(Error::Unconfirmed(self.target))
+    }
+
+    /// Switches a relay to the opposite of its current state, returning the new state
+    pub async fn toggle(&self, relay_index: u8) -> Result<bool, Error> {
+        let on = !self.get(relay_index).await?;
+        self.set(relay_index, on).await?;
+        Ok(on)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::{spawn_fake_bulb, Faults};
+    use crate::client::ClientOptions;
+    use crate::transport::{LoopbackNetwork, Transport};
+    use lifx_core::RawMessage;
+    use tokio::sync::mpsc;
+
+    /// A switch whose first `ignore` RelaySetPowers are acknowledged but not obeyed, and
+    /// everything it receives
+    fn relays(ignore: usize) -> (RelaySwitch, mpsc::UnboundedReceiver<RawMessage>) {
+        let network = LoopbackNetwork::new();
+        let device = network.bind("10.0.0.2:56700".parse().unwrap()).unwrap();
+        let addr = device.local_addr().unwrap();
+        let faults = Faults {
+            ignore_commands: ignore,
+            ..Default::default()
+        };
+        let received = spawn_fake_bulb(device, 0x1234, "Switch", faults);
+        let local = network.bind("10.0.0.1:0".parse().unwrap()).unwrap();
+        let options = ClientOptions {
+            timeout: Duration::from_millis(100),
+            ..Default::default()
+        };
+        let client = Client::with_transport(local, options);
+        (RelaySwitch::new(client, addr, 0x1234), received)
+    }
+
+    /// The levels of the RelaySetPowers that the switch has received so far
+    fn sets(received: &mut mpsc::UnboundedReceiver<RawMessage>) -> Vec<u16> {
+        std::iter::from_fn(|| received.try_recv().ok())
+            .filter_map(|raw| match Message::from_raw(&raw) {
+                Ok(Message::RelaySetPower { level, .. }) => Some(level),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_set_and_confirm() {
+        let (switch, mut received) = relays(1);
+
+        // the first change is ignored, so it's sent again
+        switch.set(0, true).await.unwrap();
+        assert!(switch.get(0).await.unwrap());
+        assert_eq!(sets(&mut received), vec![RELAY_ON, RELAY_ON]);
+
+        // a relay that never changes is reported
+        let switch = relays(usize::MAX).0.with_attempts(2);
+        assert!(matches!(
+            switch.set(0, true).await,
+            Err(Error::Unconfirmed(0x1234))
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_debounce() {
+        let (relays, mut received) = relays(0);
+        let relays = relays.with_debounce(Duration::from_secs(2));
+
+        let start = Instant::now();
+        relays.set(0, true).await.unwrap();
+        // repeating a change within the interval does nothing
+        relays.set(0, true).await.unwrap();
+        // but a different one waits for the interval to pass
+        assert!(!relays.toggle(0).await.unwrap());
+        assert!(start.elapsed() >= Duration::from_secs(2));
+        assert!(!relays.get(0).await.unwrap());
+
+        assert_eq!(sets(&mut received), vec![RELAY_ON, 0]);
+    }
+}
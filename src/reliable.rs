@@ -0,0 +1,496 @@
+//! Retransmitting messages that don't get a reply
+//!
+//! LIFX devices talk UDP, so requests and replies occasionally get lost.  A [ReliableSender] sends
+//! a message, and if nothing comes back in time, sends it again, following a [RetryPolicy] for how
+//! many attempts to make and how long to wait for each one.
+//!
+//! Only messages that are safe to apply twice ([Message::is_idempotent]) are retried, since a lost
+//! acknowledgement doesn't mean that the message itself was lost: retrying a
+//! [Message::SetWaveform] whose ack went missing would play the waveform twice.  Other messages
+//! are sent once, and [Error::Timeout] means that it's unknown whether they arrived.
+//!
+//! Every attempt uses the same sequence number, so a late reply to an earlier attempt still counts.
+//! That number stays reserved (see [SequenceAllocator](crate::SequenceAllocator)) from the first
+//! attempt until the message is answered, the last attempt times out, or the future is dropped, so
+//! no other request to the same target can be mistaken for it in the meantime.
+//!
+//! When lights feel laggy, the `_timed` methods say where the time went: waiting in the client's
+//! send queue (because of rate limiting, or a burst of other messages), on the network (including
+//! any retransmits, which mean packets were lost), or waiting for the device to confirm the change.
+//! See [CommandLatency].
+
+use crate::client::Client;
+use crate::queue::Priority;
+use crate::telemetry;
+use crate::Error;
+use lifx_core::Message;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// How many times to send a message, and how long to wait for a reply to each attempt
+///
+/// Each attempt waits `backoff` times longer than the one before, up to `max_timeout`, so a device
+/// that's busy (or a network that's congested) gets some breathing room.  Each wait is then moved
+/// up or down by a random fraction of up to `jitter` of itself, so that many senders that lost
+/// messages at the same moment don't all retry at the same moment too.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    /// The most times to send a message (it's always sent at least once)
+    pub attempts: usize,
+    /// How long to wait for a reply to the first attempt
+    pub timeout: Duration,
+    /// How much longer to wait for each attempt than for the one before
+    pub backoff: f64,
+    /// The longest to wait for any one attempt
+    pub max_timeout: Duration,
+    /// How much each wait is randomly moved by, as a fraction of it, between 0 and 1
+    pub jitter: f64,
+}
+
+impl RetryPolicy {
+    /// A policy that waits the same time for every attempt, with no jitter
+    pub fn fixed(attempts: usize, timeout: Duration) -> RetryPolicy {
+        RetryPolicy {
+            attempts,
+            timeout,
+            backoff: 1.0,
+            max_timeout: timeout,
+            jitter: 0.0,
+        }
+    }
+
+    /// How long to wait for attempt number `attempt` (starting from 0), before any jitter
+    pub fn timeout_for(&self, attempt: usize) -> Duration {
+        let factor = self
+            .backoff
+            .max(1.0)
+            .powi(attempt.min(i32::MAX as usize) as i32);
+        Duration::try_from_secs_f64(self.timeout.as_secs_f64() * factor)
+            .unwrap_or(self.max_timeout)
+            .min(self.max_timeout)
+    }
+
+    /// [RetryPolicy::timeout_for], with jitter
+    fn jittered_timeout_for(&self, attempt: usize) -> Duration {
+        let timeout = self.timeout_for(attempt);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        if jitter == 0.0 {
+            return timeout;
+        }
+        // a random number between -1 and 1
+        let random =
+            RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64 * 2.0 - 1.0;
+        timeout.mul_f64(1.0 + jitter * random)
+    }
+}
+
+/// Three attempts, waiting 1, 2, and then 4 seconds, with 10% jitter
+impl Default for RetryPolicy {
+    fn default() -> RetryPolicy {
+        RetryPolicy {
+            attempts: 3,
+            timeout: Duration::from_secs(1),
+            backoff: 2.0,
+            max_timeout: Duration::from_secs(4),
+            jitter: 0.1,
+        }
+    }
+}
+
+/// Where the time went while sending one command
+///
+/// The stages are queued → sent → acknowledged (or replied to) → confirmed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CommandLatency {
+    /// The type of the message that was sent
+    pub message_type: u16,
+    /// How many times the message was sent; more than 1 means something was lost
+    pub attempts: usize,
+    /// Time spent waiting in the client's send queue, over all attempts
+    pub queued: Duration,
+    /// Time spent waiting for the acknowledgement or reply, over all attempts
+    pub network: Duration,
+    /// Time from the acknowledgement to the device reporting the new state, for commands sent
+    /// with [ReliableSender::send_confirmed]
+    pub confirmed: Option<Duration>,
+}
+
+impl CommandLatency {
+    /// The time from the command being queued to it being finished
+    pub fn total(&self) -> Duration {
+        self.queued + self.network + self.confirmed.unwrap_or_default()
+    }
+}
+
+/// Sends messages to devices, retrying until they're answered
+#[derive(Clone)]
+pub struct ReliableSender {
+    client: Client,
+    policy: RetryPolicy,
+}
+
+impl ReliableSender {
+    /// Creates a sender that tries each message up to `attempts` times (and always at least once)
+    ///
+    /// Each attempt waits for [Client::timeout] before trying again.
+    pub fn new(client: Client, attempts: usize) -> ReliableSender {
+        let policy = RetryPolicy::fixed(attempts, client.timeout());
+        ReliableSender::with_policy(client, policy)
+    }
+
+    /// Creates a sender that retries messages according to `policy`
+    ///
+    /// [Client::timeout] isn't used.
+    pub fn with_policy(client: Client, policy: RetryPolicy) -> ReliableSender {
+        ReliableSender { client, policy }
+    }
+
+    /// How messages are retried
+    pub fn policy(&self) -> &RetryPolicy {
+        &self.policy
+    }
+
+    /// The client that messages are sent with
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Sends a message to a device, retrying until it's acknowledged
+    ///
+    /// This is sent with [Priority::User].
+    pub async fn send_acked(
+        &self,
+        addr: SocketAddr,
+        target: u64,
+        msg: Message,
+    ) -> Result<(), Error> {
+        self.send_acked_timed(addr, target, msg).await.map(|_| ())
+    }
+
+    /// Like [ReliableSender::send_acked], but also returns how long each stage took
+    pub async fn send_acked_timed(
+        &self,
+        addr: SocketAddr,
+        target: u64,
+        msg: Message,
+    ) -> Result<CommandLatency, Error> {
+        self.exchange(addr, target, msg, true, false, Priority::User)
+            .await
+            .map(|(_, latency)| latency)
+    }
+
+    /// Sends a message to a device, and then asks for its state until it confirms the change
+    ///
+    /// Once `msg` is acknowledged, `query` is sent (with retries, like [ReliableSender::request]),
+    /// and its reply is passed to `confirmed`.  Fails with [Error::Unconfirmed] if that returns
+    /// false.  For example, a [Message::SetPower] could be confirmed by a [Message::GetPower]
+    /// whose [Message::StatePower] reply has the new level.
+    pub async fn send_confirmed(
+        &self,
+        addr: SocketAddr,
+        target: u64,
+        msg: Message,
+        query: Message,
+        confirmed: impl FnOnce(&Message) -> bool,
+    ) -> Result<CommandLatency, Error> {
+        let mut latency = self.send_acked_timed(addr, target, msg).await?;
+        let acked = Instant::now();
+        let reply = self.request(addr, target, query).await?;
+        if !confirmed(&reply) {
+            return Err(Error::Unconfirmed(target));
+        }
+        latency.confirmed = Some(acked.elapsed());
+        Ok(latency)
+    }
+
+    /// Sends a message to a device, retrying until it replies
+    ///
+    /// Like [Client::request], any acknowledgements are skipped.  This is sent with
+    /// [Priority::Refresh].
+    pub async fn request(
+        &self,
+        addr: SocketAddr,
+        target: u64,
+        msg: Message,
+    ) -> Result<Message, Error> {
+        self.request_timed(addr, target, msg)
+            .await
+            .map(|(reply, _)| reply)
+    }
+
+    /// Like [ReliableSender::request], but also returns how long each stage took
+    pub async fn request_timed(
+        &self,
+        addr: SocketAddr,
+        target: u64,
+        msg: Message,
+    ) -> Result<(Message, CommandLatency), Error> {
+        self.exchange(addr, target, msg, false, true, Priority::Refresh)
+            .await
+    }
+
+    async fn exchange(
+        &self,
+        addr: SocketAddr,
+        target: u64,
+        msg: Message,
+        ack_required: bool,
+        res_required: bool,
+        priority: Priority,
+    ) -> Result<(Message, CommandLatency), Error> {
+        let mut latency = CommandLatency {
+            message_type: msg.get_num(),
+            attempts: 0,
+            queued: Duration::ZERO,
+            network: Duration::ZERO,
+            confirmed: None,
+        };
+        let attempts = match msg.is_idempotent() {
+            true => self.policy.attempts.max(1),
+            false => 1,
+        };
+
+        let mut queued = Instant::now();
+        // this holds on to the sequence number until we return, so every attempt uses the same one
+        let mut responses = self
+            .client
+            .send_request(
+                addr,
+                Some(target),
+                msg,
+                ack_required,
+                res_required,
+                priority,
+            )
+            .await?;
+        for attempt in 0..attempts {
+            if attempt > 0 {
+                telemetry::retransmit();
+                queued = Instant::now();
+                self.client.resend(&responses, addr, priority).await?;
+            }
+            let sent = Instant::now();
+            latency.attempts += 1;
+            latency.queued += sent - queued;
+            telemetry::queue_wait(sent - queued);
+            let deadline = sent + self.policy.jittered_timeout_for(attempt);
+            loop {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                let reply = match responses.recv_timeout(remaining).await {
+                    Ok(resp) => resp.message()?,
+                    Err(Error::Timeout) => break,
+                    Err(e) => return Err(e),
+                };
+                let is_ack = matches!(reply, Message::Acknowledgement { .. });
+                if is_ack != res_required {
+                    telemetry::round_trip(sent.elapsed());
+                    latency.network += sent.elapsed();
+                    return Ok((reply, latency));
+                }
+            }
+            latency.network += sent.elapsed();
+        }
+        Err(Error::Timeout)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::{spawn_fake_bulb, Faults};
+    use crate::client::ClientOptions;
+    use crate::transport::{LoopbackNetwork, Transport};
+    use lifx_core::RawMessage;
+    use std::time::Duration;
+    use tokio::net::UdpSocket;
+    use tokio::sync::mpsc;
+
+    /// Spawns a fake bulb that ignores the first `drop` messages it receives, and acknowledges
+    /// everything after that.  Every message it receives is sent to the returned channel.
+    async fn lossy_bulb(drop: usize) -> (SocketAddr, mpsc::UnboundedReceiver<RawMessage>) {
+        let sock = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = sock.local_addr().unwrap();
+        let faults = Faults {
+            drop_first: drop,
+            ..Default::default()
+        };
+        (addr, spawn_fake_bulb(sock, 0x1234, "Lossy", faults))
+    }
+
+    async fn sender(attempts: usize) -> ReliableSender {
+        let client = Client::with_options(ClientOptions {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            timeout: Duration::from_millis(50),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        ReliableSender::new(client, attempts)
+    }
+
+    #[tokio::test]
+    async fn test_retransmit() {
+        let (addr, mut received) = lossy_bulb(2).await;
+        let sender = sender(3).await;
+
+        sender
+            .send_acked(addr, 0x1234, Message::GetLabel)
+            .await
+            .unwrap();
+        let first = received.recv().await.unwrap().frame_addr.sequence;
+        for _ in 0..2 {
+            assert_eq!(received.recv().await.unwrap().frame_addr.sequence, first);
+        }
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_retransmit_timing() {
+        let network = LoopbackNetwork::new();
+        let device = network.bind("10.0.0.2:56700".parse().unwrap()).unwrap();
+        let local = network.bind("10.0.0.1:0".parse().unwrap()).unwrap();
+        let options = ClientOptions {
+            timeout: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let sender = ReliableSender::new(Client::with_transport(local, options), 3);
+
+        // with the clock paused, time only moves when everything is waiting on a timer
+        let start = Instant::now();
+        let res = sender
+            .send_acked(device.local_addr().unwrap(), 0x1234, Message::GetLabel)
+            .await;
+        assert!(matches!(res, Err(Error::Timeout)));
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= Duration::from_secs(3) && elapsed < Duration::from_millis(3010),
+            "{:?}",
+            elapsed
+        );
+
+        let mut buf = [0; 128];
+        for _ in 0..3 {
+            device.recv_from(&mut buf).await.unwrap();
+        }
+    }
+
+    #[test]
+    fn test_retry_policy() {
+        let policy = RetryPolicy {
+            max_timeout: Duration::from_secs(3),
+            ..Default::default()
+        };
+        let timeouts: Vec<_> = (0..4).map(|i| policy.timeout_for(i).as_secs()).collect();
+        assert_eq!(timeouts, vec![1, 2, 3, 3]);
+        assert_eq!(policy.timeout_for(usize::MAX), Duration::from_secs(3));
+        for _ in 0..100 {
+            let timeout = policy.jittered_timeout_for(0).as_millis();
+            assert!((900..=1100).contains(&timeout), "{}", timeout);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_backoff() {
+        let network = LoopbackNetwork::new();
+        let device = network.bind("10.0.0.2:56700".parse().unwrap()).unwrap();
+        let local = network.bind("10.0.0.1:0".parse().unwrap()).unwrap();
+        let client = Client::with_transport(local, ClientOptions::default());
+        let policy = RetryPolicy {
+            attempts: 3,
+            jitter: 0.0,
+            ..Default::default()
+        };
+        let sender = ReliableSender::with_policy(client, policy);
+
+        let start = Instant::now();
+        let res = sender
+            .request(device.local_addr().unwrap(), 0x1234, Message::GetLabel)
+            .await;
+        assert!(matches!(res, Err(Error::Timeout)));
+        let elapsed = start.elapsed();
+        assert!(
+            elapsed >= Duration::from_secs(7) && elapsed < Duration::from_millis(7010),
+            "{:?}",
+            elapsed
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_latency() {
+        let network = LoopbackNetwork::new();
+        let device = network.bind("10.0.0.2:56700".parse().unwrap()).unwrap();
+        let addr = device.local_addr().unwrap();
+        let local = network.bind("10.0.0.1:0".parse().unwrap()).unwrap();
+        let options = ClientOptions {
+            timeout: Duration::from_secs(1),
+            ..Default::default()
+        };
+        let sender = ReliableSender::new(Client::with_transport(local, options), 3);
+
+        // a bulb that loses the first SetPower, takes 100ms to acknowledge the second, and then
+        // reports that it's on
+        tokio::spawn(async move {
+            let mut buf = [0; 1024];
+            for received in 0.. {
+                let (n, from) = device.recv_from(&mut buf).await.unwrap();
+                let raw = RawMessage::unpack(&buf[..n]).unwrap();
+                let reply = match Message::from_raw(&raw).unwrap() {
+                    Message::SetPower { .. } if received == 0 => continue,
+                    Message::SetPower { .. } => {
+                        tokio::time::sleep(Duration::from_millis(100)).await;
+                        Message::Acknowledgement {
+                            seq: raw.frame_addr.sequence,
+                        }
+                    }
+                    _ => Message::StatePower { level: 65535 },
+                };
+                let opts = raw.reply_options();
+                let reply = RawMessage::build(&opts, reply).unwrap().pack().unwrap();
+                device.send_to(&reply, from).await.unwrap();
+            }
+        });
+
+        let set_power = Message::SetPower {
+            level: lifx_core::PowerLevel::Enabled,
+        };
+        let latency = sender
+            .send_confirmed(addr, 0x1234, set_power, Message::GetPower, |reply| {
+                *reply == Message::StatePower { level: 65535 }
+            })
+            .await
+            .unwrap();
+        assert_eq!(latency.message_type, 21);
+        assert_eq!(latency.attempts, 2);
+        assert!(latency.queued < Duration::from_millis(10));
+        let network = latency.network.as_millis();
+        assert!((1100..1110).contains(&network), "{}", network);
+        assert!(latency.confirmed.unwrap() < Duration::from_millis(10));
+        assert!(latency.total() >= latency.network);
+    }
+
+    #[tokio::test]
+    async fn test_not_idempotent() {
+        let (addr, mut received) = lossy_bulb(usize::MAX).await;
+        let sender = sender(3).await;
+
+        let reboot = sender.send_acked(addr, 0x1234, Message::SetReboot).await;
+        assert!(matches!(reboot, Err(Error::Timeout)));
+        assert!(received.recv().await.is_some());
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_give_up() {
+        let (addr, mut received) = lossy_bulb(usize::MAX).await;
+        let sender = sender(2).await;
+
+        let res = sender.send_acked(addr, 0x1234, Message::GetLabel).await;
+        assert!(matches!(res, Err(Error::Timeout)));
+        assert!(received.recv().await.is_some());
+        assert!(received.recv().await.is_some());
+        assert!(received.try_recv().is_err());
+    }
+}
@@ -0,0 +1,343 @@
+//! Coordinated color changes across a group of devices
+//!
+//! A [Scene] is a color for each device in a group.  A [Chase] cycles a list of colors across a
+//! group, with each device one step behind the last, so the colors appear to move around the room.
+//! Both are turned into a list of [ScheduledMessage]s, which [play] then sends at the right times.
+//!
+//! Building the schedule up front means it can be inspected, combined with other schedules, or
+//! played more than once.  A scene can also be [captured](Scene::capture) from the devices'
+//! current colors, to put them back afterwards, which is how [identify] flashes a device without
+//! losing its color.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), lifx::Error> {
+//! use lifx::scene::{play, Chase};
+//! use lifx::Client;
+//! use std::time::Duration;
+//!
+//! let client = Client::new().await?;
+//! let devices = client.discover(Duration::from_secs(1)).await?;
+//!
+//! let colors = vec!["red".parse()?, "green".parse()?, "blue".parse()?];
+//! let chase = Chase::new(colors, Duration::from_millis(500)).with_rounds(20);
+//! play(&client, &chase.messages(&devices)).await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::{Client, DiscoveredDevice};
+use crate::Error;
+use lifx_core::palette::Palette;
+use lifx_core::{Message, PowerLevel, Waveform, HSBK};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// A message to send to one device, at some time after the start of a schedule
+#[derive(Debug, Clone, PartialEq)]
+pub struct ScheduledMessage {
+    pub at: Duration,
+    pub device: DiscoveredDevice,
+    pub message: Message,
+}
+
+/// A color for each device in a group
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scene {
+    colors: Vec<(DiscoveredDevice, HSBK)>,
+}
+
+impl Scene {
+    pub fn new() -> Scene {
+        Scene::default()
+    }
+
+    /// Gives each device a color from a palette, going through the palette in order (see
+    /// [Palette::spread])
+    pub fn from_palette(devices: &[DiscoveredDevice], palette: &Palette) -> Scene {
+        let mut scene = Scene::new();
+        for (device, color) in devices.iter().zip(palette.spread(devices.len())) {
+            scene.set(*device, color);
+        }
+        scene
+    }
+
+    /// Asks each device for its current color
+    pub async fn capture(client: &Client, devices: &[DiscoveredDevice]) -> Result<Scene, Error> {
+        let mut scene = Scene::new();
+        for device in devices {
+            match client
+                .request(device.addr, device.target, Message::LightGet)
+                .await?
+            {
+                Message::LightState { color, .. } => scene.set(*device, color),
+                msg => {
+                    return Err(Error::Protocol(lifx_core::Error::ProtocolError(format!(
+                        "unexpected reply to LightGet: {:?}",
+                        msg
+                    ))))
+                }
+            }
+        }
+        Ok(scene)
+    }
+
+    /// Sets the color for a device, replacing any color it already had in this scene
+    pub fn set(&mut self, device: DiscoveredDevice, color: HSBK) {
+        match self.colors.iter_mut().find(|(d, _)| *d == device) {
+            Some(entry) => entry.1 = color,
+            None => self.colors.push((device, color)),
+        }
+    }
+
+    /// The devices in this scene, with their colors, in the order they were added
+    pub fn colors(&self) -> &[(DiscoveredDevice, HSBK)] {
+        &self.colors
+    }
+
+    /// Messages that fade every device to its color over `fade`
+    ///
+    /// Each device starts `stagger` after the one before it, so a nonzero stagger makes the scene
+    /// sweep across the group.
+    pub fn messages(&self, fade: Duration, stagger: Duration) -> Vec<ScheduledMessage> {
+        self.colors
+            .iter()
+            .enumerate()
+            .map(|(i, (device, color))| ScheduledMessage {
+                at: stagger * i as u32,
+                device: *device,
+                message: Message::set_color(*color, fade),
+            })
+            .collect()
+    }
+}
+
+/// A list of colors, rotated across a group of devices
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chase {
+    colors: Vec<HSBK>,
+    step: Duration,
+    rounds: usize,
+    offset: Duration,
+}
+
+impl Chase {
+    /// Every `step`, each device fades to the next color in `colors`, one color ahead of the
+    /// device before it
+    pub fn new(colors: Vec<HSBK>, step: Duration) -> Chase {
+        let rounds = colors.len();
+        Chase {
+            colors,
+            step,
+            rounds,
+            offset: Duration::ZERO,
+        }
+    }
+
+    /// Sets the number of steps to run for (the default is one full rotation)
+    pub fn with_rounds(mut self, rounds: usize) -> Chase {
+        self.rounds = rounds;
+        self
+    }
+
+    /// Delays each device's steps by `offset` more than the device before it
+    pub fn with_offset(mut self, offset: Duration) -> Chase {
+        self.offset = offset;
+        self
+    }
+
+    /// The schedule for running this chase across `devices`, in order
+    pub fn messages(&self, devices: &[DiscoveredDevice]) -> Vec<ScheduledMessage> {
+        if self.colors.is_empty() {
+            return Vec::new();
+        }
+        let mut messages = Vec::with_capacity(self.rounds * devices.len());
+        for round in 0..self.rounds {
+            for (i, device) in devices.iter().enumerate() {
+                let color = self.colors[(round + i) % self.colors.len()];
+                messages.push(ScheduledMessage {
+                    at: self.step * round as u32 + self.offset * i as u32,
+                    device: *device,
+                    message: Message::set_color(color, self.step),
+                });
+            }
+        }
+        messages
+    }
+}
+
+/// Sends each message at its scheduled time, counting from now
+///
+/// Messages can be in any order.  If sending falls behind, late messages are sent straight away.
+pub async fn play(client: &Client, messages: &[ScheduledMessage]) -> Result<(), Error> {
+    let mut messages: Vec<_> = messages.iter().collect();
+    messages.sort_by_key(|m| m.at);
+    let start = Instant::now();
+    for m in messages {
+        tokio::time::sleep_until(start + m.at).await;
+        client
+            .send(m.device.addr, Some(m.device.target), m.message.clone())
+            .await?;
+    }
+    Ok(())
+}
+
+/// How long each flash of [identify] takes
+pub const IDENTIFY_PERIOD: Duration = Duration::from_millis(500);
+
+/// Flashes a device `times` times, so that it can be found in the room
+///
+/// The device pulses between its current color and a fully bright, fully saturated color on the
+/// other side of the color wheel, once every [IDENTIFY_PERIOD].  A device that's off is turned on
+/// for the flashes.  Afterwards, its color (see [Scene::capture]) and power are put back the way
+/// they were.
+pub async fn identify(client: &Client, device: DiscoveredDevice, times: u32) -> Result<(), Error> {
+    let scene = Scene::capture(client, &[device]).await?;
+    let was_on = match client
+        .request(device.addr, device.target, Message::GetPower)
+        .await?
+    {
+        Message::StatePower { level } => level != 0,
+        msg => {
+            return Err(Error::Protocol(lifx_core::Error::ProtocolError(format!(
+                "unexpected reply to GetPower: {:?}",
+                msg
+            ))))
+        }
+    };
+    let color = scene.colors()[0].1;
+
+    if !was_on {
+        let msg = Message::SetPower {
+            level: PowerLevel::Enabled,
+        };
+        client.send_acked(device.addr, device.target, msg).await?;
+    }
+    let flash = Message::SetWaveform {
+        reserved: 0,
+        transient: true,
+        color: HSBK {
+            hue: color.hue.wrapping_add(32768),
+            saturation: 65535,
+            brightness: 65535,
+            kelvin: color.kelvin,
+        },
+        period: IDENTIFY_PERIOD.as_millis() as u32,
+        cycles: times as f32,
+        skew_ratio: 0,
+        waveform: Waveform::Pulse,
+    };
+    client.send_acked(device.addr, device.target, flash).await?;
+    tokio::time::sleep(IDENTIFY_PERIOD * times).await;
+
+    play(client, &scene.messages(Duration::ZERO, Duration::ZERO)).await?;
+    if !was_on {
+        let msg = Message::SetPower {
+            level: PowerLevel::Standby,
+        };
+        client.send_acked(device.addr, device.target, msg).await?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::fake_bulb;
+    use crate::ClientOptions;
+
+    fn device(target: u64) -> DiscoveredDevice {
+        DiscoveredDevice {
+            target,
+            addr: "127.0.0.1:56700".parse().unwrap(),
+        }
+    }
+
+    fn hue(hue: u16) -> HSBK {
+        HSBK {
+            hue,
+            saturation: 65535,
+            brightness: 65535,
+            kelvin: 3500,
+        }
+    }
+
+    fn color_of(m: &ScheduledMessage) -> HSBK {
+        match m.message {
+            Message::LightSetColor { color, .. } => color,
+            ref msg => panic!("unexpected message {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn test_scene() {
+        let mut scene = Scene::new();
+        scene.set(device(1), hue(1));
+        scene.set(device(2), hue(2));
+        scene.set(device(1), hue(3));
+        assert_eq!(scene.colors().len(), 2);
+
+        let messages = scene.messages(Duration::from_secs(1), Duration::from_millis(100));
+        assert_eq!(messages[0].at, Duration::ZERO);
+        assert_eq!(color_of(&messages[0]), hue(3));
+        assert_eq!(messages[1].at, Duration::from_millis(100));
+        assert_eq!(messages[1].device, device(2));
+    }
+
+    #[test]
+    fn test_from_palette() {
+        let palette = Palette::new("Two", vec![hue(1), hue(2)]);
+        let devices = [device(1), device(2), device(3)];
+        let scene = Scene::from_palette(&devices, &palette);
+        let hues: Vec<_> = scene.colors().iter().map(|(_, c)| c.hue).collect();
+        assert_eq!(hues, vec![1, 2, 1]);
+    }
+
+    #[test]
+    fn test_chase() {
+        let devices = [device(1), device(2), device(3)];
+        let chase = Chase::new(vec![hue(0), hue(1)], Duration::from_secs(1))
+            .with_offset(Duration::from_millis(10));
+        let messages = chase.messages(&devices);
+        assert_eq!(messages.len(), 6);
+
+        // each device is one color ahead of the one before it
+        let colors: Vec<_> = messages.iter().map(|m| color_of(m).hue).collect();
+        assert_eq!(colors, vec![0, 1, 0, 1, 0, 1]);
+        let times: Vec<_> = messages.iter().map(|m| m.at.as_millis()).collect();
+        assert_eq!(times, vec![0, 10, 20, 1000, 1010, 1020]);
+
+        assert!(Chase::new(Vec::new(), Duration::from_secs(1))
+            .messages(&devices)
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_identify() {
+        let device = DiscoveredDevice {
+            target: 0x1234,
+            addr: fake_bulb(0x1234, "Lamp").await,
+        };
+        let client = Client::with_options(ClientOptions {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+        let off = Message::SetPower {
+            level: PowerLevel::Standby,
+        };
+        client
+            .send_acked(device.addr, device.target, off)
+            .await
+            .unwrap();
+        let before = Scene::capture(&client, &[device]).await.unwrap();
+
+        identify(&client, device, 1).await.unwrap();
+        assert_eq!(Scene::capture(&client, &[device]).await.unwrap(), before);
+        let power = client
+            .request(device.addr, device.target, Message::GetPower)
+            .await
+            .unwrap();
+        assert_eq!(power, Message::StatePower { level: 0 });
+    }
+}
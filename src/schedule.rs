@@ -0,0 +1,253 @@
+//! Changing color temperature and brightness over the course of a day
+//!
+//! A [DaySchedule] is a list of [Waypoint]s, each giving a brightness and color temperature for a
+//! time of day.  Between waypoints, both are interpolated, and the schedule wraps around midnight.
+//! [DaySchedule::run] then keeps a device following the schedule, by sending it a
+//! [Message::LightSetColor] at regular intervals that fades smoothly to the next point.
+//!
+//! Brightness is interpolated along a [BrightnessCurve], so a slow fade from off to full
+//! brightness looks even to the eye, rather than jumping up in the first few minutes.
+//!
+//! For example, a "fake dawn" alarm clock that brightens over half an hour before 7am, and turns
+//! itself down again at 9am:
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), lifx::Error> {
+//! use lifx::schedule::{DaySchedule, Waypoint};
+//! use lifx::Client;
+//! use std::time::Duration;
+//!
+//! let dawn = DaySchedule::new(vec![
+//!     Waypoint::at(6, 30, 0.0, 2000),
+//!     Waypoint::at(7, 0, 1.0, 4000),
+//!     Waypoint::at(9, 0, 1.0, 4000),
+//!     Waypoint::at(9, 1, 0.0, 2000),
+//! ])?;
+//!
+//! let client = Client::new().await?;
+//! let bulb = &client.discover(Duration::from_secs(1)).await?[0];
+//! // UTC+1
+//! dawn.run(&client, bulb.addr, bulb.target, Duration::from_secs(60), 3600)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::Client;
+use crate::Error;
+use lifx_core::color::BrightnessCurve;
+use lifx_core::{Message, HSBK};
+use std::net::SocketAddr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// The brightness and color temperature for one time of day
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Waypoint {
+    /// Time since midnight (local time, see [time_of_day])
+    pub time: Duration,
+    /// Between 0 and 1, as a position along the schedule's [BrightnessCurve]
+    pub brightness: f32,
+    pub kelvin: u16,
+}
+
+impl Waypoint {
+    /// A waypoint at the given hour (0-23) and minute
+    pub fn at(hour: u32, minute: u32, brightness: f32, kelvin: u16) -> Waypoint {
+        Waypoint {
+            time: Duration::from_secs(u64::from(hour * 60 + minute) * 60),
+            brightness,
+            kelvin,
+        }
+    }
+}
+
+/// Brightness and color temperature over a day
+#[derive(Debug, Clone, PartialEq)]
+pub struct DaySchedule {
+    waypoints: Vec<Waypoint>,
+    curve: BrightnessCurve,
+}
+
+impl DaySchedule {
+    /// Creates a schedule from a list of waypoints, in any order
+    ///
+    /// Times of 24 hours or more wrap around to the next day.  Returns an error if there are no
+    /// waypoints.
+    pub fn new(mut waypoints: Vec<Waypoint>) -> Result<DaySchedule, Error> {
+        if waypoints.is_empty() {
+            return Err(Error::Protocol(lifx_core::Error::ProtocolError(
+                "a schedule needs at least one waypoint".to_owned(),
+            )));
+        }
+        for waypoint in &mut waypoints {
+            waypoint.time =
+                Duration::from_nanos((waypoint.time.as_nanos() % DAY.as_nanos()) as u64);
+        }
+        waypoints.sort_by_key(|w| w.time);
+        Ok(DaySchedule {
+            waypoints,
+            curve: BrightnessCurve::default(),
+        })
+    }
+
+    /// A schedule that roughly follows daylight: dim and warm at night, bright and cool around
+    /// midday
+    pub fn circadian() -> DaySchedule {
+        DaySchedule::new(vec![
+            Waypoint::at(5, 0, 0.1, 2000),
+            Waypoint::at(7, 0, 0.5, 2700),
+            Waypoint::at(9, 0, 0.9, 4000),
+            Waypoint::at(13, 0, 1.0, 5500),
+            Waypoint::at(17, 0, 0.9, 4000),
+            Waypoint::at(20, 0, 0.6, 2700),
+            Waypoint::at(22, 0, 0.3, 2200),
+            Waypoint::at(23, 30, 0.1, 2000),
+        ])
+        .unwrap()
+    }
+
+    /// Sets how waypoint brightnesses map to device brightness (the default is
+    /// [BrightnessCurve::CieLightness])
+    pub fn with_curve(mut self, curve: BrightnessCurve) -> DaySchedule {
+        self.curve = curve;
+        self
+    }
+
+    pub fn waypoints(&self) -> &[Waypoint] {
+        &self.waypoints
+    }
+
+    /// The color at the given time since midnight
+    ///
+    /// This is always a white (zero saturation).
+    pub fn color_at(&self, time_of_day: Duration) -> HSBK {
+        let t = Duration::from_nanos((time_of_day.as_nanos() % DAY.as_nanos()) as u64);
+        // the waypoints either side of t, wrapping around midnight
+        let next = self.waypoints.iter().position(|w| w.time > t);
+        let (prev, next) = match next {
+            Some(0) | None => (self.waypoints[self.waypoints.len() - 1], self.waypoints[0]),
+            Some(i) => (self.waypoints[i - 1], self.waypoints[i]),
+        };
+
+        let span = (next.time + DAY - prev.time).as_secs_f64() % DAY.as_secs_f64();
+        let fraction = if span == 0.0 {
+            0.0
+        } else {
+            ((t + DAY - prev.time).as_secs_f64() % DAY.as_secs_f64()) / span
+        };
+        let lerp = |a: f64, b: f64| a + (b - a) * fraction;
+
+        HSBK {
+            hue: 0,
+            saturation: 0,
+            brightness: self
+                .curve
+                .to_brightness(lerp(f64::from(prev.brightness), f64::from(next.brightness)) as f32),
+            kelvin: lerp(f64::from(prev.kelvin), f64::from(next.kelvin)).round() as u16,
+        }
+    }
+
+    /// A [Message::LightSetColor] that fades to the color at `time_of_day + step`, over `step`
+    pub fn message_at(&self, time_of_day: Duration, step: Duration) -> Message {
+        Message::set_color(self.color_at(time_of_day + step), step)
+    }
+
+    /// Keeps a device following this schedule
+    ///
+    /// Every `step`, this sends a [Message::LightSetColor] that fades to where the schedule will be
+    /// at the end of the step.  `utc_offset` is the local time zone, in seconds east of UTC.
+    ///
+    /// This only changes the color: the device needs to be powered on separately.  It runs until
+    /// a message can't be sent.
+    pub async fn run(
+        &self,
+        client: &Client,
+        addr: SocketAddr,
+        target: u64,
+        step: Duration,
+        utc_offset: i32,
+    ) -> Result<(), Error> {
+        let mut interval = tokio::time::interval(step);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let now = time_of_day(SystemTime::now(), utc_offset);
+            client
+                .send(addr, Some(target), self.message_at(now, step))
+                .await?;
+        }
+    }
+}
+
+/// The time since midnight, in a time zone `utc_offset` seconds east of UTC
+pub fn time_of_day(now: SystemTime, utc_offset: i32) -> Duration {
+    let since_epoch = now.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let day = DAY.as_secs() as i64;
+    let secs = (since_epoch.as_secs() as i64 + i64::from(utc_offset)).rem_euclid(day);
+    Duration::new(secs as u64, since_epoch.subsec_nanos())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOUR: Duration = Duration::from_secs(60 * 60);
+
+    #[test]
+    fn test_interpolation() {
+        let schedule = DaySchedule::new(vec![
+            Waypoint::at(20, 0, 0.0, 2000),
+            Waypoint::at(8, 0, 1.0, 4000),
+        ])
+        .unwrap()
+        .with_curve(BrightnessCurve::Linear);
+        assert_eq!(schedule.waypoints()[0].time, 8 * HOUR);
+
+        let c = schedule.color_at(8 * HOUR);
+        assert_eq!((c.brightness, c.kelvin), (65535, 4000));
+        let c = schedule.color_at(14 * HOUR);
+        assert_eq!((c.brightness, c.kelvin), (32768, 3000));
+
+        // across midnight, from 20:00 back to 08:00
+        let c = schedule.color_at(2 * HOUR);
+        assert_eq!((c.brightness, c.kelvin), (32768, 3000));
+        assert_eq!(schedule.color_at(26 * HOUR), c);
+        let c = schedule.color_at(20 * HOUR);
+        assert_eq!((c.brightness, c.kelvin), (0, 2000));
+    }
+
+    #[test]
+    fn test_single_waypoint() {
+        let schedule = DaySchedule::new(vec![Waypoint::at(12, 0, 1.0, 3500)]).unwrap();
+        for hour in 0..24 {
+            let c = schedule.color_at(hour * HOUR);
+            assert_eq!((c.brightness, c.kelvin), (65535, 3500));
+        }
+        assert!(DaySchedule::new(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_message_at() {
+        let schedule = DaySchedule::circadian();
+        match schedule.message_at(13 * HOUR - Duration::from_secs(60), Duration::from_secs(60)) {
+            Message::LightSetColor {
+                color, duration, ..
+            } => {
+                assert_eq!(color.kelvin, 5500);
+                assert_eq!(color.brightness, 65535);
+                assert_eq!(duration, 60_000);
+            }
+            msg => panic!("unexpected message {:?}", msg),
+        }
+    }
+
+    #[test]
+    fn test_time_of_day() {
+        let now = UNIX_EPOCH + 3 * DAY + 6 * HOUR;
+        assert_eq!(time_of_day(now, 0), 6 * HOUR);
+        assert_eq!(time_of_day(now, 2 * 3600), 8 * HOUR);
+        assert_eq!(time_of_day(now, -8 * 3600), 22 * HOUR);
+    }
+}
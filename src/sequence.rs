@@ -0,0 +1,169 @@
+//! Per-target sequence numbers
+//!
+//! The sequence number in the frame header is only a `u8`, so a single counter shared by every
+//! request wraps around after 256 messages.  With enough requests in flight (for example when
+//! polling a large installation), a number can get reused while a reply to its previous use is
+//! still on the way, and that reply gets matched to the wrong request.
+//!
+//! Replies are matched on the (target, sequence) pair, so sequence numbers only need to be unique
+//! per target.  A [SequenceAllocator] hands them out per target, and a number only becomes
+//! available again once the request that was using it has been released, which the [Client]
+//! does once the request has been acknowledged or has timed out.
+//!
+//! Broadcast requests (target 0) are answered by every device, so their sequence numbers are
+//! reserved across all targets.
+//!
+//! [Client]: crate::Client
+
+use std::collections::HashMap;
+
+/// A set of sequence numbers
+#[derive(Debug, Default, Clone, Copy)]
+struct SequenceSet([u64; 4]);
+
+impl SequenceSet {
+    fn contains(&self, seq: u8) -> bool {
+        self.0[seq as usize / 64] & (1 << (seq % 64)) != 0
+    }
+
+    fn insert(&mut self, seq: u8) {
+        self.0[seq as usize / 64] |= 1 << (seq % 64);
+    }
+
+    fn remove(&mut self, seq: u8) -> bool {
+        let was_present = self.contains(seq);
+        self.0[seq as usize / 64] &= !(1 << (seq % 64));
+        was_present
+    }
+
+    fn len(&self) -> usize {
+        self.0.iter().map(|w| w.count_ones() as usize).sum()
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct TargetSequences {
+    /// Where to start looking for a free number, so that numbers are handed out round-robin
+    /// instead of immediately reusing one that was just released
+    next: u8,
+    in_use: SequenceSet,
+}
+
+/// Hands out sequence numbers per target, without reusing any that are still in flight
+#[derive(Debug)]
+pub struct SequenceAllocator {
+    targets: HashMap<u64, TargetSequences>,
+    /// How many targets currently hold each sequence number
+    holders: [u32; 256],
+}
+
+impl Default for SequenceAllocator {
+    fn default() -> Self {
+        SequenceAllocator {
+            targets: HashMap::new(),
+            holders: [0; 256],
+        }
+    }
+}
+
+impl SequenceAllocator {
+    pub fn new() -> SequenceAllocator {
+        SequenceAllocator::default()
+    }
+
+    /// Reserves a sequence number for a request to `target`
+    ///
+    /// Use a target of 0 for broadcasts.  Returns `None` if every number is already in use.
+    pub fn allocate(&mut self, target: u64) -> Option<u8> {
+        let broadcast = self.targets.get(&0).map(|t| t.in_use).unwrap_or_default();
+        let entry = self.targets.entry(target).or_default();
+        for offset in 0..=u8::MAX {
+            let seq = entry.next.wrapping_add(offset);
+            let free = if target == 0 {
+                self.holders[seq as usize] == 0
+            } else {
+                !entry.in_use.contains(seq) && !broadcast.contains(seq)
+            };
+            if free {
+                entry.in_use.insert(seq);
+                entry.next = seq.wrapping_add(1);
+                self.holders[seq as usize] += 1;
+                return Some(seq);
+            }
+        }
+        None
+    }
+
+    /// Makes a sequence number available again
+    ///
+    /// Releasing a number that isn't in use does nothing.
+    pub fn release(&mut self, target: u64, seq: u8) {
+        if let Some(entry) = self.targets.get_mut(&target) {
+            if entry.in_use.remove(seq) {
+                self.holders[seq as usize] -= 1;
+            }
+        }
+    }
+
+    /// Whether `seq` is currently reserved for `target`
+    pub fn is_in_use(&self, target: u64, seq: u8) -> bool {
+        self.targets
+            .get(&target)
+            .is_some_and(|t| t.in_use.contains(seq))
+    }
+
+    /// The number of sequence numbers currently reserved for `target`
+    pub fn in_flight(&self, target: u64) -> usize {
+        self.targets.get(&target).map_or(0, |t| t.in_use.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_per_target() {
+        let mut seqs = SequenceAllocator::new();
+        for _ in 0..256 {
+            assert!(seqs.allocate(1).is_some());
+        }
+        assert_eq!(seqs.allocate(1), None);
+        assert_eq!(seqs.in_flight(1), 256);
+
+        // other targets have their own numbers
+        assert_eq!(seqs.allocate(2), Some(0));
+
+        // numbers are only reused after they're released
+        seqs.release(1, 42);
+        assert!(!seqs.is_in_use(1, 42));
+        assert_eq!(seqs.allocate(1), Some(42));
+        assert_eq!(seqs.allocate(1), None);
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let mut seqs = SequenceAllocator::new();
+        let first = seqs.allocate(1).unwrap();
+        seqs.release(1, first);
+        assert_ne!(seqs.allocate(1), Some(first));
+    }
+
+    #[test]
+    fn test_broadcast() {
+        let mut seqs = SequenceAllocator::new();
+        assert_eq!(seqs.allocate(1), Some(0));
+
+        // a broadcast can't use a number held by any target...
+        assert_eq!(seqs.allocate(0), Some(1));
+        // ...and no target can use a number held by a broadcast
+        assert_eq!(seqs.allocate(2), Some(0));
+        assert_eq!(seqs.allocate(2), Some(2));
+
+        seqs.release(1, 0);
+        seqs.release(2, 0);
+        seqs.release(0, 1);
+        assert_eq!(seqs.in_flight(0), 0);
+        assert_eq!(seqs.holders.iter().sum::<u32>(), 1);
+    }
+}
@@ -0,0 +1,199 @@
+//! Spreading a very large fleet of devices across several sockets
+//!
+//! A [Client] has one socket, one receive task, and 256 sequence numbers per device, which is
+//! plenty for a house, but can become the bottleneck when polling hundreds of devices.  A
+//! [ShardedClient] is a group of clients, each with its own socket, tasks, and sequence numbers.
+//! Each device is always handled by the same shard, picked by a consistent hash of its target ID,
+//! so adding a shard only moves the devices that the new shard takes over.
+//!
+//! Discovery is shared: it's done once, from the first shard, since any shard can talk to any
+//! device.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), lifx::Error> {
+//! use lifx::shard::ShardedClient;
+//! use lifx::ClientOptions;
+//! use lifx_core::Message;
+//! use std::time::Duration;
+//!
+//! let client = ShardedClient::with_options(4, ClientOptions::default()).await?;
+//! for device in client.discover(Duration::from_secs(1)).await? {
+//!     let label = client.request(device.addr, device.target, Message::GetLabel).await?;
+//!     println!("{:016X}: {:?}", device.target, label);
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::{Client, ClientOptions, DiscoveredDevice};
+use crate::Error;
+use lifx_core::Message;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// Picks one of `buckets` buckets for a key, using Lamping and Veach's "jump" consistent hash
+///
+/// When the number of buckets goes from `n` to `n + 1`, about `1 / (n + 1)` of the keys move, all
+/// of them to the new bucket.
+pub(crate) fn jump_hash(mut key: u64, buckets: usize) -> usize {
+    let (mut b, mut j) = (-1i64, 0i64);
+    while j < buckets as i64 {
+        b = j;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        j = ((b + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    b as usize
+}
+
+/// Several [Client]s, with each device always handled by the same one
+///
+/// This is cheap to clone, and all clones share the same shards.
+#[derive(Clone)]
+pub struct ShardedClient {
+    shards: Vec<Client>,
+}
+
+impl ShardedClient {
+    /// Creates `shards` clients, each with its own socket
+    ///
+    /// Every shard uses the same [ClientOptions], including the source ID, so that devices see a
+    /// single controller.  [ClientOptions::bind_addr] should have port 0, since each shard binds
+    /// its own socket.  This must be called from within a tokio runtime.
+    pub async fn with_options(
+        shards: usize,
+        options: ClientOptions,
+    ) -> Result<ShardedClient, Error> {
+        let mut clients = Vec::with_capacity(shards.max(1));
+        for _ in 0..shards.max(1) {
+            clients.push(Client::with_options(options).await?);
+        }
+        Ok(ShardedClient::from_clients(clients))
+    }
+
+    /// Uses existing clients as the shards, in order
+    ///
+    /// # Panics
+    ///
+    /// Panics if `clients` is empty.
+    pub fn from_clients(clients: Vec<Client>) -> ShardedClient {
+        assert!(
+            !clients.is_empty(),
+            "a ShardedClient needs at least one shard"
+        );
+        ShardedClient { shards: clients }
+    }
+
+    /// All of the shards
+    pub fn shards(&self) -> &[Client] {
+        &self.shards
+    }
+
+    /// The index of the shard that handles a device
+    pub fn shard_index(&self, target: u64) -> usize {
+        jump_hash(target, self.shards.len())
+    }
+
+    /// The shard that handles a device
+    pub fn shard(&self, target: u64) -> &Client {
+        &self.shards[self.shard_index(target)]
+    }
+
+    /// Discovers devices from the first shard (see [Client::discover])
+    pub async fn discover(&self, wait: Duration) -> Result<Vec<DiscoveredDevice>, Error> {
+        self.shards[0].discover(wait).await
+    }
+
+    /// Sends a message through the device's shard, without asking for a reply (see [Client::send])
+    ///
+    /// Broadcasts (with no target) go through the first shard.
+    pub async fn send(
+        &self,
+        addr: SocketAddr,
+        target: Option<u64>,
+        msg: Message,
+    ) -> Result<(), Error> {
+        let shard = match target {
+            Some(target) => self.shard(target),
+            None => &self.shards[0],
+        };
+        shard.send(addr, target, msg).await
+    }
+
+    /// Sends a message through the device's shard, and waits for the reply (see [Client::request])
+    pub async fn request(
+        &self,
+        addr: SocketAddr,
+        target: u64,
+        msg: Message,
+    ) -> Result<Message, Error> {
+        self.shard(target).request(addr, target, msg).await
+    }
+
+    /// Sends a message through the device's shard, and waits for it to be acknowledged (see
+    /// [Client::send_acked])
+    pub async fn send_acked(
+        &self,
+        addr: SocketAddr,
+        target: u64,
+        msg: Message,
+    ) -> Result<(), Error> {
+        self.shard(target).send_acked(addr, target, msg).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::fake_bulb;
+
+    #[test]
+    fn test_jump_hash() {
+        let targets: Vec<u64> = (0..1000).map(|i| 0xd073d5000000 + i).collect();
+
+        let mut counts = [0; 4];
+        for &target in &targets {
+            counts[jump_hash(target, 4)] += 1;
+        }
+        assert!(counts.iter().all(|&n| n > 200), "{:?}", counts);
+
+        // adding a shard only moves devices to the new shard
+        let mut moved = 0;
+        for &target in &targets {
+            let (before, after) = (jump_hash(target, 4), jump_hash(target, 5));
+            if before != after {
+                assert_eq!(after, 4);
+                moved += 1;
+            }
+        }
+        assert!((150..250).contains(&moved), "{}", moved);
+    }
+
+    #[tokio::test]
+    async fn test_request() {
+        let client = ShardedClient::with_options(
+            3,
+            ClientOptions {
+                bind_addr: "127.0.0.1:0".parse().unwrap(),
+                ..Default::default()
+            },
+        )
+        .await
+        .unwrap();
+        let sockets: Vec<_> = client
+            .shards()
+            .iter()
+            .map(|shard| shard.local_addr().unwrap())
+            .collect();
+        assert!(sockets[0] != sockets[1] && sockets[1] != sockets[2]);
+
+        // every device can be reached, whichever shard it's on
+        let mut used = std::collections::HashSet::new();
+        for target in 0x1000..0x1008 {
+            let addr = fake_bulb(target, "Warehouse").await;
+            let reply = client.request(addr, target, Message::GetLabel).await;
+            assert!(matches!(reply, Ok(Message::StateLabel { .. })));
+            used.insert(client.shard_index(target));
+        }
+        assert!(used.len() > 1);
+    }
+}
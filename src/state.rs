@@ -0,0 +1,628 @@
+//! A cached view of a device's state, built up from the messages it sends us
+
+use lifx_core::{
+    get_product_info, ApplicationRequest, FirmwareVersion, LifxIdent, Message, PowerLevel,
+    ProductInfo, TemperatureRange, HSBK,
+};
+use std::net::SocketAddr;
+
+/// Everything we know about a single device
+///
+/// Start with [DeviceState::new], and feed every message received from the device into
+/// [DeviceState::update].  Fields are `None` until the corresponding `State*` message has been
+/// seen.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeviceState {
+    pub target: u64,
+    pub addr: Option<SocketAddr>,
+    pub label: Option<String>,
+    /// The current power level (`0` is off, anything else is on)
+    pub power: Option<u16>,
+    pub color: Option<HSBK>,
+    /// The color of each zone, for multizone devices
+    ///
+    /// Zones that haven't been reported yet are `None`.
+    pub zones: Option<Vec<Option<HSBK>>>,
+    /// The maximum brightness of the infrared LEDs, for devices that have them
+    pub infrared: Option<u16>,
+    pub group: Option<(LifxIdent, String)>,
+    pub location: Option<(LifxIdent, String)>,
+    /// The (vendor, product) IDs from [Message::StateVersion]
+    pub version: Option<(u32, u32)>,
+    /// The firmware version from [Message::StateHostFirmware]
+    pub firmware: Option<FirmwareVersion>,
+    /// Zone colors from commands sent with [ApplicationRequest::NoApply], which the device holds
+    /// on to until a command tells it to apply them
+    pending_zones: Vec<(usize, HSBK)>,
+}
+
+impl DeviceState {
+    pub fn new(target: u64) -> DeviceState {
+        DeviceState {
+            target,
+            addr: None,
+            label: None,
+            power: None,
+            color: None,
+            zones: None,
+            infrared: None,
+            group: None,
+            location: None,
+            version: None,
+            firmware: None,
+            pending_zones: Vec::new(),
+        }
+    }
+
+    /// Updates the cached state from a message sent by the device
+    ///
+    /// Returns `true` if this changed the cached state.  Applying the same message twice is
+    /// harmless, and the second time returns `false`, as does a message that doesn't contain any
+    /// state that we track.
+    pub fn update(&mut self, msg: &Message) -> bool {
+        let before = self.clone();
+        match msg {
+            Message::StateLabel { label } => self.label = Some(label.to_string()),
+            Message::StatePower { level } | Message::LightStatePower { level } => {
+                self.power = Some(*level)
+            }
+            Message::LightState {
+                color,
+                power,
+                label,
+                ..
+            } => {
+                self.color = Some(*color);
+                self.power = Some(*power);
+                self.label = Some(label.to_string());
+            }
+            Message::LightStateInfrared { brightness } => self.infrared = Some(*brightness),
+            Message::StateGroup { group, label, .. } => {
+                self.group = Some((*group, label.to_string()))
+            }
+            Message::StateLocation {
+                location, label, ..
+            } => self.location = Some((*location, label.to_string())),
+            Message::StateVersion {
+                vendor, product, ..
+            } => self.version = Some((*vendor, *product)),
+            Message::StateHostFirmware { .. } => {
+                self.firmware = FirmwareVersion::from_state_firmware(msg)
+            }
+            Message::StateZone {
+                count,
+                index,
+                color,
+            } => self.update_zones(*count as usize, *index as usize, &[*color]),
+            Message::StateMultiZone {
+                count,
+                index,
+                color0,
+                color1,
+                color2,
+                color3,
+                color4,
+                color5,
+                color6,
+                color7,
+            } => {
+                let colors = [
+                    *color0, *color1, *color2, *color3, *color4, *color5, *color6, *color7,
+                ];
+                self.update_zones(*count as usize, *index as usize, &colors);
+            }
+            Message::StateExtendedColorZones {
+                zones_count,
+                zone_index,
+                colors_count,
+                colors,
+            } => {
+                let len = (*colors_count as usize).min(colors.len());
+                self.update_zones(*zones_count as usize, *zone_index as usize, &colors[..len]);
+            }
+            _ => return false,
+        }
+        *self != before
+    }
+
+    /// Updates the cached state from a command sent to the device, assuming that it worked
+    ///
+    /// This is for keeping track of commands sent by someone else (see
+    /// [PassiveObserver](crate::observer::PassiveObserver)), where the device's reply isn't
+    /// visible.  Zone commands only update zones we already know about, and zone commands sent
+    /// with [ApplicationRequest::NoApply] are held back until a later zone command applies them,
+    /// just as the device does.  Returns `true` if this changed the cached state.
+    pub fn apply(&mut self, msg: &Message) -> bool {
+        let before = self.clone();
+        match msg {
+            Message::SetLabel { label } => self.label = Some(label.to_string()),
+            Message::SetPower { level } => {
+                self.power = Some(match level {
+                    PowerLevel::Standby => 0,
+                    PowerLevel::Enabled => 65535,
+                })
+            }
+            Message::LightSetPower { level, .. } => self.power = Some(*level),
+            Message::LightSetColor { color, .. } => self.color = Some(*color),
+            Message::LightSetInfrared { brightness } => self.infrared = Some(*brightness),
+            Message::SetGroup { group, label, .. } => {
+                self.group = Some((*group, label.to_string()))
+            }
+            Message::SetLocation {
+                location, label, ..
+            } => self.location = Some((*location, label.to_string())),
+            Message::SetColorZones {
+                start_index,
+                end_index,
+                color,
+                apply,
+                ..
+            } => {
+                let (start, end) = (*start_index as usize, *end_index as usize);
+                self.apply_zones((start..=end).map(|i| (i, *color)), *apply);
+            }
+            Message::SetExtendedColorZones {
+                zone_index,
+                colors_count,
+                colors,
+                apply,
+                ..
+            } => {
+                let len = (*colors_count as usize).min(colors.len());
+                let start = *zone_index as usize;
+                let colors = colors[..len].iter().enumerate();
+                self.apply_zones(colors.map(|(i, c)| (start + i, *c)), *apply);
+            }
+            _ => return false,
+        }
+        // zone colors that are being held back aren't showing yet, so they aren't a change
+        let before = DeviceState {
+            pending_zones: self.pending_zones.clone(),
+            ..before
+        };
+        *self != before
+    }
+
+    /// Stores zone colors from a command, holding them back unless the command applies them
+    fn apply_zones(
+        &mut self,
+        colors: impl Iterator<Item = (usize, HSBK)>,
+        apply: ApplicationRequest,
+    ) {
+        match apply {
+            ApplicationRequest::NoApply => {
+                self.pending_zones.extend(colors);
+                return;
+            }
+            ApplicationRequest::Apply => self.pending_zones.extend(colors),
+            // the colors in this message are ignored
+            ApplicationRequest::ApplyOnly => (),
+        }
+        let pending = std::mem::take(&mut self.pending_zones);
+        if let Some(zones) = &mut self.zones {
+            for (index, color) in pending {
+                if let Some(zone) = zones.get_mut(index) {
+                    *zone = Some(color);
+                }
+            }
+        }
+    }
+
+    /// Stores a run of zone colors, starting over if the device's zone count has changed
+    fn update_zones(&mut self, count: usize, index: usize, colors: &[HSBK]) {
+        let zones = self.zones.get_or_insert_with(Vec::new);
+        if zones.len() != count {
+            *zones = vec![None; count];
+        }
+        for (zone, color) in zones.iter_mut().skip(index).zip(colors) {
+            *zone = Some(*color);
+        }
+    }
+
+    /// Product details for this device, if we know its version and it's a known product
+    pub fn product_info(&self) -> Option<&'static ProductInfo> {
+        let (vendor, product) = self.version?;
+        get_product_info(vendor, product)
+    }
+
+    /// A rough estimate of how much power the device is drawing, in watts
+    ///
+    /// This needs the device's power, its color (or the color of every zone, for multizone
+    /// devices), and a product with a known [ProductInfo::max_milliwatts].  A device that's off
+    /// counts as drawing nothing, since standby power isn't known.
+    ///
+    /// The estimate assumes that LEDs draw power in proportion to their brightness.  A white in
+    /// the middle of the product's temperature range counts as the full maximum, and whites
+    /// towards either end of the range as up to a fifth less, since fewer of the LEDs are lit.  A
+    /// fully saturated color counts as 60% of the maximum, and partly saturated colors somewhere
+    /// in between.  Don't use it for billing.
+    pub fn estimated_power_w(&self) -> Option<f32> {
+        let info = self.product_info()?;
+        let max = info.max_watts()?;
+        if self.power? == 0 {
+            return Some(0.0);
+        }
+        let load = |color: &HSBK| {
+            let brightness = color.brightness as f32 / 65535.0;
+            let saturation = color.saturation as f32 / 65535.0;
+            let white = match info.temperature_range {
+                TemperatureRange::Variable { min, max } if max > min => {
+                    let middle = (min as f32 + max as f32) / 2.0;
+                    let off_middle = (color.kelvin as f32 - middle).abs() / (max - min) as f32;
+                    1.0 - 0.4 * off_middle.min(0.5)
+                }
+                _ => 1.0,
+            };
+            brightness * (white * (1.0 - saturation) + 0.6 * saturation)
+        };
+        let load = match self.zones.as_deref() {
+            Some(zones) if !zones.is_empty() => {
+                let loads: Vec<f32> = zones
+                    .iter()
+                    .map(|z| z.as_ref().map(load))
+                    .collect::<Option<_>>()?;
+                loads.iter().sum::<f32>() / loads.len() as f32
+            }
+            _ => load(self.color.as_ref()?),
+        };
+        Some(max * load)
+    }
+
+    /// The serial number of the device, as printed on its label (and used as its ID by the LIFX
+    /// HTTP API): the 6 byte MAC address, in lowercase hex
+    pub fn serial(&self) -> String {
+        self.target.to_le_bytes()[..6]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+}
+
+#[cfg(feature = "json")]
+mod json {
+    use super::*;
+    use lifx_core::TemperatureRange;
+    use serde_json::{json, Value};
+
+    fn hue_degrees(hue: u16) -> f64 {
+        hue as f64 / 65535.0 * 360.0
+    }
+
+    fn fraction(val: u16) -> f64 {
+        val as f64 / 65535.0
+    }
+
+    fn color_json(color: &HSBK) -> Value {
+        json!({
+            "hue": hue_degrees(color.hue),
+            "saturation": fraction(color.saturation),
+            "brightness": fraction(color.brightness),
+            "kelvin": color.kelvin,
+        })
+    }
+
+    fn group_json(group: &Option<(LifxIdent, String)>) -> Value {
+        match group {
+            Some((id, name)) => json!({ "id": id.to_string(), "name": name }),
+            None => Value::Null,
+        }
+    }
+
+    impl DeviceState {
+        /// Exports this state as JSON
+        ///
+        /// The schema is stable, and uses the same field names as the LIFX HTTP API where
+        /// there's an equivalent field.  Every key is always present; anything we don't know
+        /// yet is `null`.  Hue is in degrees (0-360), saturation and brightness are between 0
+        /// and 1, and kelvin is unscaled:
+        ///
+        /// ```json
+        /// {
+        ///   "id": "d073d5001337",
+        ///   "label": "Kitchen",
+        ///   "power": "on",
+        ///   "color": { "hue": 120.0, "saturation": 1.0, "kelvin": 3500 },
+        ///   "brightness": 0.5,
+        ///   "zones": null,
+        ///   "group": { "id": "...", "name": "Downstairs" },
+        ///   "location": { "id": "...", "name": "Home" },
+        ///   "product": {
+        ///     "name": "LIFX A19", "vendor_id": 1, "product_id": 27,
+        ///     "capabilities": {
+        ///       "has_color": true, "has_variable_color_temp": true, "has_ir": false,
+        ///       "has_hev": false, "has_chain": false, "has_matrix": false,
+        ///       "has_multizone": false, "min_kelvin": 2500, "max_kelvin": 9000
+        ///     }
+        ///   },
+        ///   "firmware": { "version": "3.70", "major": 3, "minor": 70, "built": "2021-04-13" }
+        /// }
+        /// ```
+        ///
+        /// `zones` is a list of `{hue, saturation, brightness, kelvin}` objects (or `null` for
+        /// zones that haven't been reported yet).  The firmware's `built` date is UTC, and is
+        /// `null` if the device doesn't report one.
+        pub fn to_json(&self) -> Value {
+            let power = self.power.map(|p| if p == 0 { "off" } else { "on" });
+            let color = self.color.as_ref().map(|c| {
+                json!({
+                    "hue": hue_degrees(c.hue),
+                    "saturation": fraction(c.saturation),
+                    "kelvin": c.kelvin,
+                })
+            });
+            let brightness = self.color.as_ref().map(|c| fraction(c.brightness));
+            let zones = self.zones.as_ref().map(|zones| {
+                zones
+                    .iter()
+                    .map(|z| z.as_ref().map(color_json).unwrap_or(Value::Null))
+                    .collect::<Vec<_>>()
+            });
+            let product = self.version.map(|(vendor, product)| {
+                let info = get_product_info(vendor, product);
+                let capabilities = info.map(|info| {
+                    let (min_kelvin, max_kelvin) = match info.temperature_range {
+                        TemperatureRange::Variable { min, max } => (Some(min), Some(max)),
+                        TemperatureRange::Fixed(k) => (Some(k), Some(k)),
+                        TemperatureRange::None => (None, None),
+                    };
+                    json!({
+                        "has_color": info.color(),
+                        "has_variable_color_temp":
+                            matches!(info.temperature_range, TemperatureRange::Variable { .. }),
+                        "has_ir": info.infrared(),
+                        "has_hev": info.hev(),
+                        "has_chain": info.chain(),
+                        "has_matrix": info.matrix(),
+                        "has_multizone": info.multizone(),
+                        "min_kelvin": min_kelvin,
+                        "max_kelvin": max_kelvin,
+                    })
+                });
+                json!({
+                    "name": info.map(|i| i.name),
+                    "vendor_id": vendor,
+                    "product_id": product,
+                    "capabilities": capabilities,
+                })
+            });
+            let firmware = self.firmware.map(|firmware| {
+                let built = firmware
+                    .build_date()
+                    .map(|(year, month, day)| format!("{}-{:02}-{:02}", year, month, day));
+                json!({
+                    "version": format!("{}.{}", firmware.major, firmware.minor),
+                    "major": firmware.major,
+                    "minor": firmware.minor,
+                    "built": built,
+                })
+            });
+
+            json!({
+                "id": self.serial(),
+                "label": self.label,
+                "power": power,
+                "color": color,
+                "brightness": brightness,
+                "zones": zones,
+                "group": group_json(&self.group),
+                "location": group_json(&self.location),
+                "product": product,
+                "firmware": firmware,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    fn color(hue: u16) -> HSBK {
+        HSBK {
+            hue,
+            saturation: 65535,
+            brightness: 32768,
+            kelvin: 3500,
+        }
+    }
+
+    #[test]
+    fn test_estimated_power() {
+        // a made-up product, so that registering it doesn't change the real LIFX Z (product 32)
+        // for other tests in this process
+        let lifx_z = lifx_core::get_product_info(1, 32).unwrap();
+        let id = lifx_core::ProductId {
+            vendor: 0xfff0,
+            product: 32,
+        };
+        let mut state = DeviceState::new(1);
+        state.version = Some((id.vendor, id.product));
+        state.power = Some(65535);
+        state.color = Some(HSBK::new(0.0, 0.0, 100.0, 5750));
+        lifx_core::products::register_product(id, lifx_z);
+        assert_eq!(state.estimated_power_w(), None);
+
+        lifx_core::products::register_max_milliwatts(id, 20_000);
+        assert_eq!(state.estimated_power_w(), Some(20.0));
+        // the warmest white it can do, at half brightness
+        state.color = Some(HSBK::new(0.0, 0.0, 50.0, 2500));
+        assert!((state.estimated_power_w().unwrap() - 8.0).abs() < 0.01);
+        state.color = Some(HSBK::new(120.0, 100.0, 100.0, 3500));
+        assert!((state.estimated_power_w().unwrap() - 12.0).abs() < 0.01);
+
+        // zones are averaged, once they're all known
+        state.zones = Some(vec![Some(HSBK::new(0.0, 0.0, 0.0, 5750)), None]);
+        assert_eq!(state.estimated_power_w(), None);
+        state.zones = Some(vec![
+            Some(HSBK::new(0.0, 0.0, 0.0, 5750)),
+            Some(HSBK::new(0.0, 0.0, 100.0, 5750)),
+        ]);
+        assert_eq!(state.estimated_power_w(), Some(10.0));
+
+        state.power = Some(0);
+        assert_eq!(state.estimated_power_w(), Some(0.0));
+    }
+
+    #[test]
+    fn test_update_zones() {
+        let mut state = DeviceState::new(1);
+        let mut colors = Box::new([color(0); 82]);
+        colors[1] = color(100);
+        assert!(state.update(&Message::StateExtendedColorZones {
+            zones_count: 3,
+            zone_index: 0,
+            colors_count: 2,
+            colors,
+        }));
+        assert_eq!(
+            state.zones,
+            Some(vec![Some(color(0)), Some(color(100)), None])
+        );
+
+        // a different zone count throws away what we had
+        state.update(&Message::StateMultiZone {
+            count: 2,
+            index: 1,
+            color0: color(5),
+            color1: color(6),
+            color2: color(7),
+            color3: color(8),
+            color4: color(9),
+            color5: color(10),
+            color6: color(11),
+            color7: color(12),
+        });
+        assert_eq!(state.zones, Some(vec![None, Some(color(5))]));
+
+        // single zones, from a StateZone reply
+        assert!(state.update(&Message::StateZone {
+            count: 2,
+            index: 0,
+            color: color(1),
+        }));
+        assert_eq!(state.zones, Some(vec![Some(color(1)), Some(color(5))]));
+
+        assert!(!state.update(&Message::GetPower));
+    }
+
+    #[test]
+    fn test_update_idempotent() {
+        let mut state = DeviceState::new(1);
+        let msg = Message::StatePower { level: 65535 };
+        assert!(state.update(&msg));
+        assert!(!state.update(&msg));
+        assert!(state.update(&Message::StatePower { level: 0 }));
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut state = DeviceState::new(1);
+        assert!(state.apply(&Message::SetPower {
+            level: PowerLevel::Enabled
+        }));
+        assert_eq!(state.power, Some(65535));
+        assert!(state.apply(&Message::set_color(color(7), Duration::ZERO)));
+        assert_eq!(state.color, Some(color(7)));
+
+        // zones are only updated once we know how many there are
+        let set_zones = Message::SetColorZones {
+            start_index: 1,
+            end_index: 5,
+            color: color(9),
+            duration: 0,
+            apply: lifx_core::ApplicationRequest::Apply,
+        };
+        assert!(!state.apply(&set_zones));
+        state.zones = Some(vec![None; 3]);
+        assert!(state.apply(&set_zones));
+        assert_eq!(
+            state.zones,
+            Some(vec![None, Some(color(9)), Some(color(9))])
+        );
+
+        // replies aren't commands
+        assert!(!state.apply(&Message::StatePower { level: 0 }));
+    }
+
+    #[test]
+    fn test_apply_buffered_zones() {
+        let mut state = DeviceState::new(1);
+        state.zones = Some(vec![None; 4]);
+        let set_zones = |start_index, end_index, color, apply| Message::SetColorZones {
+            start_index,
+            end_index,
+            color,
+            duration: 0,
+            apply,
+        };
+
+        // the device doesn't show these until they're applied
+        assert!(!state.apply(&set_zones(0, 1, color(1), ApplicationRequest::NoApply)));
+        assert!(!state.apply(&set_zones(2, 2, color(2), ApplicationRequest::NoApply)));
+        assert_eq!(state.zones, Some(vec![None; 4]));
+
+        // and then shows them along with the applying message's own colors
+        assert!(state.apply(&set_zones(3, 3, color(3), ApplicationRequest::Apply)));
+        let applied = vec![
+            Some(color(1)),
+            Some(color(1)),
+            Some(color(2)),
+            Some(color(3)),
+        ];
+        assert_eq!(state.zones, Some(applied.clone()));
+
+        // ApplyOnly applies what's held back, and ignores its own colors
+        assert!(!state.apply(&set_zones(0, 3, color(4), ApplicationRequest::ApplyOnly)));
+        assert_eq!(state.zones, Some(applied));
+        assert!(!state.apply(&set_zones(0, 0, color(5), ApplicationRequest::NoApply)));
+        assert!(state.apply(&set_zones(1, 3, color(6), ApplicationRequest::ApplyOnly)));
+        assert_eq!(
+            state.zones.as_ref().unwrap()[..2],
+            [Some(color(5)), Some(color(1))]
+        );
+    }
+
+    #[cfg(feature = "json")]
+    #[test]
+    fn test_to_json() {
+        use lifx_core::LifxString;
+        use std::ffi::CString;
+
+        let mut state = DeviceState::new(0x3713_00d5_73d0);
+        assert_eq!(state.serial(), "d073d5001337");
+        assert_eq!(state.to_json()["power"], serde_json::Value::Null);
+
+        state.update(&Message::LightState {
+            color: color(32768),
+            reserved: 0,
+            power: 65535,
+            label: LifxString::new(&CString::new("Kitchen").unwrap()),
+            reserved2: 0,
+        });
+        state.update(&Message::StateVersion {
+            vendor: 1,
+            product: 27,
+            reserved: 0,
+        });
+        state.update(&Message::StateHostFirmware {
+            build: 1_600_000_000_000_000_000,
+            reserved: 0,
+            version_minor: 70,
+            version_major: 3,
+        });
+
+        let json = state.to_json();
+        assert_eq!(json["id"], "d073d5001337");
+        assert_eq!(json["label"], "Kitchen");
+        assert_eq!(json["power"], "on");
+        assert_eq!(json["color"]["kelvin"], 3500);
+        assert!((json["color"]["hue"].as_f64().unwrap() - 180.0).abs() < 0.01);
+        assert!((json["brightness"].as_f64().unwrap() - 0.5).abs() < 0.01);
+        assert_eq!(json["product"]["product_id"], 27);
+        assert_eq!(json["product"]["capabilities"]["has_color"], true);
+        assert_eq!(json["firmware"]["version"], "3.70");
+        assert_eq!(json["firmware"]["built"], "2020-09-13");
+        assert_eq!(json["group"], serde_json::Value::Null);
+    }
+}
@@ -0,0 +1,278 @@
+//! Streaming frames of colors to a device at a steady rate
+//!
+//! Animations on strips and tiles send a whole frame of zone colors at a time, often as several
+//! messages (a long strip takes more than one [Message::SetExtendedColorZones], for instance).
+//! Devices can only keep up with about [MAX_MESSAGES_PER_SECOND] messages, so a [FrameStream]
+//! paces frames to a target frame rate, slowing down further if the frames are too big to send
+//! that often.
+//!
+//! Frames are rendered by the caller and handed to [FrameStream::submit], which never waits.  Only
+//! the newest frame is kept: if a frame is submitted before the previous one was sent, the
+//! previous one is dropped, so a renderer that runs faster than the device never builds up a
+//! backlog.  [FrameStream::stats] reports how many frames were sent and dropped, and the frame
+//! rate that was actually achieved.
+//!
+//! ```no_run
+//! # async fn example(client: lifx::Client, addr: std::net::SocketAddr) -> Result<(), lifx::Error> {
+//! use lifx::stream::FrameStream;
+//! use lifx_core::zones::extended_zone_pages;
+//! use lifx_core::{ApplicationRequest, HSBK};
+//! use std::time::Duration;
+//!
+//! let stream = FrameStream::start(client, addr, 0xd073d5001337, 15);
+//! for step in 0..300u16 {
+//!     let zones: Vec<HSBK> = (0..120u16)
+//!         .map(|zone| HSBK::new(f32::from((zone + step) % 360), 100.0, 100.0, 3500))
+//!         .collect();
+//!     stream.submit(extended_zone_pages(&zones, Duration::ZERO, ApplicationRequest::Apply)?);
+//!     tokio::time::sleep(Duration::from_millis(50)).await;
+//! }
+//! let stats = stream.finish().await;
+//! println!("{:.1} fps, {} frames dropped", stats.fps(), stats.dropped);
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::Client;
+use lifx_core::Message;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+use tokio::time::Instant;
+
+/// The most messages per second that a [FrameStream] sends to its device
+///
+/// LIFX recommends sending no more than 20 messages per second to a single device.
+pub const MAX_MESSAGES_PER_SECOND: u32 = 20;
+
+/// How a [FrameStream] is doing
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStats {
+    /// Frames handed to [FrameStream::submit]
+    pub submitted: u64,
+    /// Frames that were sent in full
+    pub sent: u64,
+    /// Frames that were replaced by a newer frame before they could be sent
+    pub dropped: u64,
+    /// Frames where at least one message couldn't be sent
+    pub failed: u64,
+    /// The time from the start of the first frame that was sent to the start of the last one
+    pub elapsed: Duration,
+}
+
+impl StreamStats {
+    /// The average number of frames sent per second, over [StreamStats::elapsed]
+    pub fn fps(&self) -> f64 {
+        let frames = self.sent + self.failed;
+        if frames < 2 || self.elapsed.is_zero() {
+            return 0.0;
+        }
+        (frames - 1) as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+#[derive(Default)]
+struct Pending {
+    frame: Option<Vec<Message>>,
+    stats: StreamStats,
+    finishing: bool,
+}
+
+struct Shared {
+    pending: Mutex<Pending>,
+    notify: Notify,
+}
+
+/// Sends frames to one device, no faster than a target frame rate
+///
+/// Dropping the stream stops it straight away, without sending any frame that's waiting; use
+/// [FrameStream::finish] to send it first.
+pub struct FrameStream {
+    shared: Arc<Shared>,
+    task: Option<JoinHandle<()>>,
+}
+
+impl FrameStream {
+    /// Starts a stream to a device, sending up to `frames_per_second` frames per second
+    ///
+    /// The frame rate is clamped to between 1 and [MAX_MESSAGES_PER_SECOND].  Frames with more
+    /// than one message are spaced out further, so that the device never gets more than
+    /// [MAX_MESSAGES_PER_SECOND] messages per second.
+    pub fn start(client: Client, addr: SocketAddr, target: u64, frames_per_second: u32) -> Self {
+        let shared = Arc::new(Shared {
+            pending: Mutex::new(Pending::default()),
+            notify: Notify::new(),
+        });
+        let frame = Duration::from_secs(1) / frames_per_second.clamp(1, MAX_MESSAGES_PER_SECOND);
+        let task = tokio::spawn(send_frames(client, addr, target, frame, shared.clone()));
+        FrameStream {
+            shared,
+            task: Some(task),
+        }
+    }
+
+    /// Queues a frame to be sent as soon as the frame rate allows, replacing any frame that's
+    /// still waiting
+    ///
+    /// The messages in a frame are sent one after another, to the stream's device.  An empty frame
+    /// still takes up a frame's worth of time, which can be used to pause the animation.
+    pub fn submit(&self, frame: Vec<Message>) {
+        let mut pending = self.shared.pending.lock().unwrap();
+        pending.stats.submitted += 1;
+        if pending.frame.replace(frame).is_some() {
+            pending.stats.dropped += 1;
+        }
+        drop(pending);
+        self.shared.notify.notify_one();
+    }
+
+    pub fn stats(&self) -> StreamStats {
+        self.shared.pending.lock().unwrap().stats
+    }
+
+    /// Sends the frame that's waiting (if there is one), then stops the stream
+    pub async fn finish(mut self) -> StreamStats {
+        self.shared.pending.lock().unwrap().finishing = true;
+        self.shared.notify.notify_one();
+        if let Some(task) = self.task.take() {
+            let _ = task.await;
+        }
+        self.stats()
+    }
+}
+
+impl Drop for FrameStream {
+    fn drop(&mut self) {
+        if let Some(task) = &self.task {
+            task.abort();
+        }
+    }
+}
+
+async fn send_frames(
+    client: Client,
+    addr: SocketAddr,
+    target: u64,
+    frame_time: Duration,
+    shared: Arc<Shared>,
+) {
+    let per_message = Duration::from_secs(1) / MAX_MESSAGES_PER_SECOND;
+    let mut first_frame = None;
+    let mut next_frame = Instant::now();
+    loop {
+        tokio::time::sleep_until(next_frame).await;
+        let frame = loop {
+            let notified = shared.notify.notified();
+            {
+                let mut pending = shared.pending.lock().unwrap();
+                if let Some(frame) = pending.frame.take() {
+                    break frame;
+                }
+                if pending.finishing {
+                    return;
+                }
+            }
+            notified.await;
+        };
+
+        let start = Instant::now();
+        let first = *first_frame.get_or_insert(start);
+        next_frame = start + frame_time.max(per_message * frame.len() as u32);
+        let mut ok = true;
+        for msg in frame {
+            if let Err(e) = client.send(addr, Some(target), msg).await {
+                log::debug!("couldn't send a frame to {:016X}: {}", target, e);
+                ok = false;
+                break;
+            }
+        }
+
+        let mut pending = shared.pending.lock().unwrap();
+        if ok {
+            pending.stats.sent += 1;
+        } else {
+            pending.stats.failed += 1;
+        }
+        pending.stats.elapsed = start - first;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::localhost_options;
+    use lifx_core::{RawMessage, HSBK};
+    use tokio::net::UdpSocket;
+
+    fn frame(brightness: u16, messages: usize) -> Vec<Message> {
+        let color = HSBK {
+            hue: 0,
+            saturation: 0,
+            brightness,
+            kelvin: 3500,
+        };
+        vec![Message::set_color(color, Duration::ZERO); messages]
+    }
+
+    /// Receives colors until nothing arrives for a while, with the time each one arrived
+    async fn received(device: &UdpSocket) -> Vec<(Instant, u16)> {
+        let mut colors = Vec::new();
+        let mut buf = [0; 1024];
+        while let Ok(Ok(n)) =
+            tokio::time::timeout(Duration::from_millis(300), device.recv(&mut buf)).await
+        {
+            let raw = RawMessage::unpack(&buf[..n]).unwrap();
+            match Message::from_raw(&raw).unwrap() {
+                Message::LightSetColor { color, .. } => {
+                    colors.push((Instant::now(), color.brightness))
+                }
+                msg => panic!("unexpected message {:?}", msg),
+            }
+        }
+        colors
+    }
+
+    #[tokio::test]
+    async fn test_drops_stale_frames() {
+        let device = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::with_options(localhost_options()).await.unwrap();
+        let stream = FrameStream::start(client, device.local_addr().unwrap(), 0x1234, 5);
+
+        // the first frame goes straight out, and the rest pile up behind it
+        for brightness in 0..10 {
+            stream.submit(frame(brightness, 1));
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        let stats = stream.finish().await;
+        let colors: Vec<_> = received(&device).await.into_iter().map(|c| c.1).collect();
+        assert_eq!(colors, vec![0, 9]);
+        assert_eq!(stats.submitted, 10);
+        assert_eq!(stats.sent, 2);
+        assert_eq!(stats.dropped, 8);
+        assert_eq!(stats.failed, 0);
+        assert!(stats.elapsed >= Duration::from_millis(200), "{:?}", stats);
+        assert!(stats.fps() <= 5.0, "{}", stats.fps());
+    }
+
+    #[tokio::test]
+    async fn test_message_budget() {
+        let device = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::with_options(localhost_options()).await.unwrap();
+        let stream = FrameStream::start(client, device.local_addr().unwrap(), 0x1234, 100);
+        let colors = tokio::spawn(async move { received(&device).await });
+
+        // four messages a frame only leaves room for five frames a second
+        stream.submit(frame(1, 4));
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        stream.submit(frame(2, 4));
+        let stats = stream.finish().await;
+        let colors = colors.await.unwrap();
+        assert_eq!(colors.len(), 8);
+        let gap = colors[4].0 - colors[3].0;
+        assert!(gap >= Duration::from_millis(150), "{:?}", gap);
+        assert_eq!(stats.sent, 2);
+        assert_eq!(stats.dropped, 0);
+    }
+}
@@ -0,0 +1,116 @@
+//! Protocol health metrics
+//!
+//! When the `metrics` feature is enabled, the client reports the metrics below through the
+//! [metrics](https://docs.rs/metrics) facade.  Nothing is recorded until you install a recorder
+//! (for example from `metrics-exporter-prometheus`) in your application.
+//!
+//! Without the feature, all of this compiles down to nothing.
+//!
+//! Labels:
+//!
+//! * `type`: the numeric message type, as found in the protocol header
+//! * `priority`: one of `discovery`, `refresh`, or `user` (see [Priority](crate::Priority))
+
+/// Counter of messages sent, labelled by `type`
+pub const MESSAGES_SENT: &str = "lifx_messages_sent_total";
+/// Counter of messages received in reply to our requests, labelled by `type`
+pub const MESSAGES_RECEIVED: &str = "lifx_messages_received_total";
+/// Counter of datagrams or payloads that couldn't be decoded
+pub const DECODE_ERRORS: &str = "lifx_decode_errors_total";
+/// Counter of received messages dropped by a [MessageFilter](crate::filter::MessageFilter),
+/// labelled by `type`
+pub const MESSAGES_FILTERED: &str = "lifx_messages_filtered_total";
+/// Counter of messages that were sent again because no reply arrived in time
+pub const RETRANSMITS: &str = "lifx_retransmits_total";
+/// Gauge of the number of devices that replied to the most recent discovery
+pub const DEVICES_ONLINE: &str = "lifx_devices_online";
+/// Histogram of the time between sending a request and receiving its reply, in seconds
+pub const ROUND_TRIP_SECONDS: &str = "lifx_round_trip_seconds";
+/// Histogram of the time messages sent by a [ReliableSender](crate::ReliableSender) spent waiting
+/// in the send queue, in seconds
+pub const QUEUE_WAIT_SECONDS: &str = "lifx_send_queue_wait_seconds";
+/// Gauge of the number of messages waiting in the send queue, labelled by `priority`
+pub const QUEUE_DEPTH: &str = "lifx_send_queue_depth";
+/// Counter of messages dropped from a full send queue, labelled by `priority`
+pub const QUEUE_DROPPED: &str = "lifx_send_queue_dropped_total";
+/// Counter of times the client switched source IDs because another controller was using its one
+pub const SOURCE_COLLISIONS: &str = "lifx_source_collisions_total";
+
+#[cfg(feature = "metrics")]
+mod imp {
+    use super::*;
+    use crate::queue::Priority;
+    use std::time::Duration;
+
+    fn priority_label(priority: Priority) -> &'static str {
+        match priority {
+            Priority::Discovery => "discovery",
+            Priority::Refresh => "refresh",
+            Priority::User => "user",
+        }
+    }
+
+    pub fn message_sent(typ: u16) {
+        metrics::counter!(MESSAGES_SENT, "type" => typ.to_string()).increment(1);
+    }
+
+    pub fn message_received(typ: u16) {
+        metrics::counter!(MESSAGES_RECEIVED, "type" => typ.to_string()).increment(1);
+    }
+
+    pub fn decode_error() {
+        metrics::counter!(DECODE_ERRORS).increment(1);
+    }
+
+    pub fn message_filtered(typ: u16) {
+        metrics::counter!(MESSAGES_FILTERED, "type" => typ.to_string()).increment(1);
+    }
+
+    pub fn retransmit() {
+        metrics::counter!(RETRANSMITS).increment(1);
+    }
+
+    pub fn devices_online(count: usize) {
+        metrics::gauge!(DEVICES_ONLINE).set(count as f64);
+    }
+
+    pub fn round_trip(rtt: Duration) {
+        metrics::histogram!(ROUND_TRIP_SECONDS).record(rtt.as_secs_f64());
+    }
+
+    pub fn queue_wait(wait: Duration) {
+        metrics::histogram!(QUEUE_WAIT_SECONDS).record(wait.as_secs_f64());
+    }
+
+    pub fn queue_depth(priority: Priority, depth: usize) {
+        metrics::gauge!(QUEUE_DEPTH, "priority" => priority_label(priority)).set(depth as f64);
+    }
+
+    pub fn queue_dropped(priority: Priority) {
+        metrics::counter!(QUEUE_DROPPED, "priority" => priority_label(priority)).increment(1);
+    }
+
+    pub fn source_collision() {
+        metrics::counter!(SOURCE_COLLISIONS).increment(1);
+    }
+}
+
+#[cfg(not(feature = "metrics"))]
+mod imp {
+    use crate::queue::Priority;
+    use std::time::Duration;
+
+    pub fn message_sent(_typ: u16) {}
+    pub fn message_received(_typ: u16) {}
+    pub fn decode_error() {}
+    pub fn message_filtered(_typ: u16) {}
+    pub fn retransmit() {}
+    pub fn devices_online(_count: usize) {}
+    pub fn round_trip(_rtt: Duration) {}
+    pub fn queue_wait(_wait: Duration) {}
+    pub fn queue_depth(_priority: Priority, _depth: usize) {}
+    pub fn queue_dropped(_priority: Priority) {}
+    pub fn source_collision() {}
+}
+
+pub(crate) use imp::*;
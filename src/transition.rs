@@ -0,0 +1,263 @@
+//! Client-side fades between two colors
+//!
+//! Devices can fade between colors on their own (that's what the `duration` in
+//! [Message::LightSetColor] is for), but only linearly, and long fades are quantized into visible
+//! steps by some firmware.  A [Transition] instead works out the color at each point in time on
+//! the client, with an [Easing] curve, and sends a stream of short fades to the device.
+//!
+//! Hue takes the shortest way around the color wheel, and brightness is interpolated along a
+//! [BrightnessCurve] so that fades to and from off look even.
+//!
+//! ```no_run
+//! # async fn example(client: lifx::Client, addr: std::net::SocketAddr) -> Result<(), lifx::Error> {
+//! use lifx::transition::{Easing, Transition};
+//! use lifx_core::HSBK;
+//! use std::time::Duration;
+//!
+//! let from: HSBK = "3500K 10%".parse()?;
+//! let to: HSBK = "orange".parse()?;
+//! Transition::new(from, to, Duration::from_secs(30 * 60))
+//!     .with_easing(Easing::EaseInOut)
+//!     .run(&client, addr, 0xd073d5001337, 10)
+//!     .await?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::client::Client;
+use crate::Error;
+use lifx_core::color::BrightnessCurve;
+use lifx_core::{Message, HSBK};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::time::{Instant, MissedTickBehavior};
+
+/// The most frames per second that [Transition::run] will send
+///
+/// Each frame is a single message, so this is the same as
+/// [MAX_MESSAGES_PER_SECOND](crate::stream::MAX_MESSAGES_PER_SECOND).
+pub const MAX_FRAMES_PER_SECOND: u32 = crate::stream::MAX_MESSAGES_PER_SECOND;
+
+/// How progress through a transition is spread out over time
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Easing {
+    /// Constant speed
+    #[default]
+    Linear,
+    /// Starts slowly, and speeds up
+    EaseIn,
+    /// Starts quickly, and slows down
+    EaseOut,
+    /// Starts and ends slowly
+    EaseInOut,
+}
+
+impl Easing {
+    /// Maps a fraction of the elapsed time (0 to 1) to a fraction of the way to the end color
+    pub fn apply(&self, t: f32) -> f32 {
+        let t = t.clamp(0.0, 1.0);
+        match self {
+            Easing::Linear => t,
+            Easing::EaseIn => t * t * t,
+            Easing::EaseOut => 1.0 - (1.0 - t).powi(3),
+            Easing::EaseInOut if t < 0.5 => 4.0 * t * t * t,
+            Easing::EaseInOut => 1.0 - (-2.0 * t + 2.0).powi(3) / 2.0,
+        }
+    }
+}
+
+/// A fade from one color to another
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transition {
+    from: HSBK,
+    to: HSBK,
+    duration: Duration,
+    easing: Easing,
+    curve: BrightnessCurve,
+}
+
+impl Transition {
+    pub fn new(from: HSBK, to: HSBK, duration: Duration) -> Transition {
+        Transition {
+            from,
+            to,
+            duration,
+            easing: Easing::default(),
+            curve: BrightnessCurve::default(),
+        }
+    }
+
+    pub fn with_easing(mut self, easing: Easing) -> Transition {
+        self.easing = easing;
+        self
+    }
+
+    /// Sets the curve that brightness is interpolated along (the default is
+    /// [BrightnessCurve::CieLightness])
+    pub fn with_curve(mut self, curve: BrightnessCurve) -> Transition {
+        self.curve = curve;
+        self
+    }
+
+    pub fn duration(&self) -> Duration {
+        self.duration
+    }
+
+    /// The color at `elapsed` into the transition
+    ///
+    /// Anything past the end of the transition is the end color.
+    pub fn color_at(&self, elapsed: Duration) -> HSBK {
+        if elapsed >= self.duration {
+            return self.to;
+        }
+        let t = self
+            .easing
+            .apply((elapsed.as_secs_f64() / self.duration.as_secs_f64()) as f32);
+        let lerp = |a: u16, b: u16| (a as f32 + (b as f32 - a as f32) * t).round() as u16;
+
+        // a white has no meaningful hue, so don't spin through the color wheel on the way to or
+        // from one
+        let from_hue = match self.from.saturation {
+            0 => self.to.hue,
+            _ => self.from.hue,
+        };
+        let to_hue = match self.to.saturation {
+            0 => from_hue,
+            _ => self.to.hue,
+        };
+        let hue_delta = to_hue.wrapping_sub(from_hue) as i16;
+        let hue = from_hue.wrapping_add((hue_delta as f32 * t).round() as i16 as u16);
+
+        let from_b = self.curve.from_brightness(self.from.brightness);
+        let to_b = self.curve.from_brightness(self.to.brightness);
+
+        HSBK {
+            hue,
+            saturation: lerp(self.from.saturation, self.to.saturation),
+            brightness: self.curve.to_brightness(from_b + (to_b - from_b) * t),
+            kelvin: lerp(self.from.kelvin, self.to.kelvin),
+        }
+    }
+
+    /// Plays the transition on a device, sending `frames_per_second` color changes per second
+    ///
+    /// The frame rate is clamped to between 1 and [MAX_FRAMES_PER_SECOND].  Each frame fades to
+    /// the color at the start of the next one, so the device's own fading smooths out the steps.
+    /// If the client's send queue backs up, frames are skipped rather than sent late, and the last
+    /// frame is always exactly the end color.
+    pub async fn run(
+        &self,
+        client: &Client,
+        addr: SocketAddr,
+        target: u64,
+        frames_per_second: u32,
+    ) -> Result<(), Error> {
+        let frame = Duration::from_secs(1) / frames_per_second.clamp(1, MAX_FRAMES_PER_SECOND);
+        let start = Instant::now();
+        let mut interval = tokio::time::interval_at(start, frame);
+        interval.set_missed_tick_behavior(MissedTickBehavior::Skip);
+        loop {
+            let elapsed = interval.tick().await.duration_since(start);
+            if elapsed + frame >= self.duration {
+                let remaining = self.duration.saturating_sub(elapsed);
+                return client
+                    .send(addr, Some(target), Message::set_color(self.to, remaining))
+                    .await;
+            }
+            let msg = Message::set_color(self.color_at(elapsed + frame), frame);
+            client.send(addr, Some(target), msg).await?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ClientOptions;
+    use lifx_core::RawMessage;
+    use tokio::net::UdpSocket;
+
+    fn color(hue: u16, saturation: u16, brightness: u16) -> HSBK {
+        HSBK {
+            hue,
+            saturation,
+            brightness,
+            kelvin: 3500,
+        }
+    }
+
+    #[test]
+    fn test_easing() {
+        for easing in &[
+            Easing::Linear,
+            Easing::EaseIn,
+            Easing::EaseOut,
+            Easing::EaseInOut,
+        ] {
+            assert_eq!(easing.apply(0.0), 0.0);
+            assert_eq!(easing.apply(1.0), 1.0);
+            assert!((easing.apply(0.5) - 0.5).abs() < 0.5);
+        }
+        assert!(Easing::EaseIn.apply(0.25) < 0.25);
+        assert!(Easing::EaseOut.apply(0.25) > 0.25);
+        assert_eq!(Easing::EaseInOut.apply(0.5), 0.5);
+    }
+
+    #[test]
+    fn test_color_at() {
+        let from = color(65000, 65535, 0);
+        let to = color(1000, 0, 65535);
+        let t =
+            Transition::new(from, to, Duration::from_secs(10)).with_curve(BrightnessCurve::Linear);
+
+        assert_eq!(t.color_at(Duration::ZERO), from);
+        assert_eq!(t.color_at(Duration::from_secs(10)), to);
+        assert_eq!(t.color_at(Duration::from_secs(60)), to);
+
+        // fading to a white keeps the hue
+        let mid = t.color_at(Duration::from_secs(5));
+        assert_eq!(mid.hue, 65000);
+        assert_eq!(mid.saturation, 32768);
+        assert_eq!(mid.brightness, 32768);
+
+        // hue goes the short way around, through 0
+        let t = Transition::new(from, color(1000, 65535, 0), Duration::from_secs(10));
+        let mid = t.color_at(Duration::from_secs(5));
+        assert!(mid.hue > 65000 || mid.hue < 1000, "{}", mid.hue);
+    }
+
+    #[tokio::test]
+    async fn test_run() {
+        let bulb = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::with_options(ClientOptions {
+            bind_addr: "127.0.0.1:0".parse().unwrap(),
+            ..Default::default()
+        })
+        .await
+        .unwrap();
+
+        let from = color(0, 65535, 0);
+        let to = color(0, 65535, 65535);
+        Transition::new(from, to, Duration::from_millis(250))
+            .run(&client, bulb.local_addr().unwrap(), 0x1234, 20)
+            .await
+            .unwrap();
+
+        let mut frames = Vec::new();
+        let mut buf = [0; 1024];
+        while let Ok(Ok(n)) =
+            tokio::time::timeout(Duration::from_millis(100), bulb.recv(&mut buf)).await
+        {
+            let raw = RawMessage::unpack(&buf[..n]).unwrap();
+            match Message::from_raw(&raw).unwrap() {
+                Message::LightSetColor { color, .. } => frames.push(color),
+                msg => panic!("unexpected message {:?}", msg),
+            }
+        }
+        assert!(frames.len() > 1, "{:?}", frames);
+        assert_eq!(*frames.last().unwrap(), to);
+        assert!(frames
+            .windows(2)
+            .all(|w| w[0].brightness <= w[1].brightness));
+    }
+}
@@ -0,0 +1,521 @@
+//! The sockets that clients send and receive datagrams with
+//!
+//! [Client] talks to the network through a [Transport], and [SyncClient] through a
+//! [BlockingTransport].  Normally these are UDP sockets, but anything that can send and receive
+//! datagrams will do.  In particular, a [LoopbackNetwork] connects transports to each other in
+//! memory, so that tests can run a client against fake devices without any real networking, and
+//! can drop or delay datagrams on purpose.
+//!
+//! ```
+//! # async fn example() -> Result<(), lifx::Error> {
+//! use lifx::transport::LoopbackNetwork;
+//! use lifx::{Client, ClientOptions};
+//!
+//! let network = LoopbackNetwork::new();
+//! let device = network.bind("10.0.0.2:56700".parse().unwrap())?;
+//! let local = network.bind("10.0.0.1:0".parse().unwrap())?;
+//! let client = Client::with_transport(local, ClientOptions::default());
+//! // ... answer requests by reading from `device` ...
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! Datagrams should be received into a buffer of [RECV_BUFFER_SIZE] bytes, which has room for any
+//! LIFX message.  Anything that doesn't fit fails with a [Truncated] error, rather than being cut
+//! short and decoded as garbage.
+//!
+//! Errors from a transport are sorted with [classify], so that the clients can react sensibly to
+//! them: transient errors are retried, a device that's unreachable is reported as
+//! [Error::Unreachable], and the clients back off for a while when the network is down, rather
+//! than failing every request as fast as they can.
+//!
+//! [Client]: crate::Client
+//! [SyncClient]: crate::SyncClient
+
+use crate::Error;
+use lifx_core::MAX_DATAGRAM_SIZE;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc;
+
+/// How large a buffer to receive datagrams into
+///
+/// This is one byte more than [MAX_DATAGRAM_SIZE], the largest datagram a LIFX device should send,
+/// so that a datagram that's too large can be told apart from one that fits exactly: a datagram
+/// that fills the whole buffer is treated as [Truncated].
+pub const RECV_BUFFER_SIZE: usize = MAX_DATAGRAM_SIZE + 1;
+
+/// The error returned when a received datagram didn't fit in the buffer
+///
+/// This is wrapped in an [io::Error] (of kind [io::ErrorKind::InvalidData]), since that's what
+/// transports return; [classify] sorts it as [ErrorClass::Truncated].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Truncated {
+    /// Where the datagram came from, if the OS said
+    pub from: Option<SocketAddr>,
+}
+
+impl fmt::Display for Truncated {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.from {
+            Some(from) => write!(f, "datagram from {} was too large, and was truncated", from),
+            None => write!(f, "datagram was too large, and was truncated"),
+        }
+    }
+}
+
+impl std::error::Error for Truncated {}
+
+impl From<Truncated> for io::Error {
+    fn from(err: Truncated) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// Fails with a [Truncated] error if a datagram of `len` bytes filled the whole buffer
+fn check_len(len: usize, buf: &[u8], from: SocketAddr) -> io::Result<(usize, SocketAddr)> {
+    if len >= buf.len() {
+        return Err(Truncated { from: Some(from) }.into());
+    }
+    Ok((len, from))
+}
+
+/// The future returned by [Transport] methods
+pub type TransportFuture<'a, T> = Pin<Box<dyn Future<Output = io::Result<T>> + Send + 'a>>;
+
+/// Something that can send and receive datagrams asynchronously, like a UDP socket
+///
+/// A single transport is shared between the tasks that send and receive, so both methods take
+/// `&self`, and may be called concurrently.
+pub trait Transport: Send + Sync + 'static {
+    /// Sends a datagram to the given address, returning the number of bytes sent
+    fn send_to<'a>(&'a self, buf: &'a [u8], addr: SocketAddr) -> TransportFuture<'a, usize>;
+
+    /// Waits for a datagram, returning its length and where it came from
+    ///
+    /// A datagram that doesn't fit in `buf` fails with a [Truncated] error.  Since sockets can't
+    /// always tell a truncated datagram from one that exactly fills the buffer, one that fills it
+    /// counts as truncated too, so `buf` should be at least [RECV_BUFFER_SIZE] bytes.
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> TransportFuture<'a, (usize, SocketAddr)>;
+
+    /// The local address that this transport receives datagrams on
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl Transport for tokio::net::UdpSocket {
+    fn send_to<'a>(&'a self, buf: &'a [u8], addr: SocketAddr) -> TransportFuture<'a, usize> {
+        Box::pin(tokio::net::UdpSocket::send_to(self, buf, addr))
+    }
+
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> TransportFuture<'a, (usize, SocketAddr)> {
+        Box::pin(async move {
+            let (len, from) = tokio::net::UdpSocket::recv_from(self, buf).await?;
+            check_len(len, buf, from)
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        tokio::net::UdpSocket::local_addr(self)
+    }
+}
+
+/// Something that can send and receive datagrams, blocking the calling thread
+pub trait BlockingTransport {
+    /// Sends a datagram to the given address, returning the number of bytes sent
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize>;
+
+    /// Waits for a datagram, returning its length and where it came from
+    ///
+    /// Like [Transport::recv_from], a datagram that fills `buf` fails with a [Truncated] error.
+    /// If nothing arrives within `timeout`, this fails with [io::ErrorKind::WouldBlock] or
+    /// [io::ErrorKind::TimedOut].  With no timeout, it waits forever.
+    fn recv_from(
+        &self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<(usize, SocketAddr)>;
+
+    /// The local address that this transport receives datagrams on
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+}
+
+impl BlockingTransport for std::net::UdpSocket {
+    fn send_to(&self, buf: &[u8], addr: SocketAddr) -> io::Result<usize> {
+        std::net::UdpSocket::send_to(self, buf, addr)
+    }
+
+    fn recv_from(
+        &self,
+        buf: &mut [u8],
+        timeout: Option<Duration>,
+    ) -> io::Result<(usize, SocketAddr)> {
+        self.set_read_timeout(timeout)?;
+        let (len, from) = std::net::UdpSocket::recv_from(self, buf)?;
+        check_len(len, buf, from)
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        std::net::UdpSocket::local_addr(self)
+    }
+}
+
+/// What kind of problem an I/O error from a transport is, which decides what to do about it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorClass {
+    /// The operation might work if it's tried again straight away (the socket's buffer was full,
+    /// or a signal interrupted it)
+    Transient,
+    /// The host that a datagram was sent to can't be reached, usually reported through ICMP
+    ///
+    /// Since UDP is connectionless, these can also show up when receiving, long after the send
+    /// that caused them.
+    Unreachable,
+    /// The local network is down (or the local address has gone away), so nothing can be sent
+    /// until it comes back
+    NetworkDown,
+    /// A datagram didn't fit in the buffer it was received into (see [Truncated])
+    ///
+    /// Only that datagram was lost, so receiving can carry on.
+    Truncated,
+    /// Anything else, which probably won't go away by itself
+    Fatal,
+}
+
+/// Works out what kind of problem an I/O error from a transport is
+pub fn classify(err: &io::Error) -> ErrorClass {
+    use io::ErrorKind::*;
+    // Windows reports a truncated datagram as WSAEMSGSIZE
+    if err.get_ref().is_some_and(|e| e.is::<Truncated>())
+        || (cfg!(windows) && err.raw_os_error() == Some(10040))
+    {
+        return ErrorClass::Truncated;
+    }
+    match err.kind() {
+        WouldBlock | Interrupted | TimedOut => ErrorClass::Transient,
+        HostUnreachable | ConnectionRefused | ConnectionReset => ErrorClass::Unreachable,
+        NetworkUnreachable | NetworkDown | AddrNotAvailable => ErrorClass::NetworkDown,
+        _ => ErrorClass::Fatal,
+    }
+}
+
+/// How many times a send that fails with a [transient](ErrorClass::Transient) error is retried
+pub(crate) const SEND_RETRIES: u32 = 3;
+
+/// How long to wait before the first retry of a transient send error (this doubles each time)
+pub(crate) const SEND_RETRY_DELAY: Duration = Duration::from_millis(5);
+
+/// Turns an I/O error from sending to `addr` into the error reported to the caller
+pub(crate) fn send_error(err: io::Error, addr: SocketAddr) -> Error {
+    match classify(&err) {
+        ErrorClass::Unreachable => Error::Unreachable(addr),
+        ErrorClass::NetworkDown => Error::NetworkDown,
+        ErrorClass::Transient | ErrorClass::Truncated | ErrorClass::Fatal => Error::Io(err),
+    }
+}
+
+/// An exponential backoff, for waiting out a network that's down
+#[derive(Debug, Default)]
+pub(crate) struct Backoff {
+    delay: Option<Duration>,
+}
+
+impl Backoff {
+    const MIN: Duration = Duration::from_millis(100);
+    const MAX: Duration = Duration::from_secs(5);
+
+    /// How long to wait before trying again, which doubles each time until [Backoff::reset]
+    pub(crate) fn next_delay(&mut self) -> Duration {
+        let delay = self
+            .delay
+            .map_or(Backoff::MIN, |d| (d * 2).min(Backoff::MAX));
+        self.delay = Some(delay);
+        delay
+    }
+
+    /// The delay to wait before the next attempt, if the last one failed
+    pub(crate) fn current(&self) -> Option<Duration> {
+        self.delay
+    }
+
+    /// Notes that things are working again
+    pub(crate) fn reset(&mut self) {
+        self.delay = None;
+    }
+}
+
+type Datagram = (Vec<u8>, SocketAddr);
+
+#[derive(Default)]
+struct NetworkInner {
+    endpoints: HashMap<SocketAddr, mpsc::UnboundedSender<Datagram>>,
+    next_port: u16,
+}
+
+/// An in-memory network, which delivers datagrams between the [LoopbackSocket]s bound to it
+///
+/// Addresses are only labels here, so any address can be bound.  Datagrams sent to the
+/// broadcast address (`255.255.255.255`) are delivered to every other socket with the same port,
+/// and datagrams sent to an address that nothing is bound to are dropped, as with UDP.
+///
+/// This is cheap to clone, and all clones are the same network.
+#[derive(Clone, Default)]
+pub struct LoopbackNetwork {
+    inner: Arc<Mutex<NetworkInner>>,
+}
+
+impl LoopbackNetwork {
+    pub fn new() -> LoopbackNetwork {
+        LoopbackNetwork::default()
+    }
+
+    /// Binds a new socket to the network
+    ///
+    /// A port of `0` picks an unused port in the dynamic range (49152 and up).  Fails with
+    /// [io::ErrorKind::AddrInUse] if another socket is already bound to the address.
+    pub fn bind(&self, addr: SocketAddr) -> io::Result<LoopbackSocket> {
+        let mut inner = self.inner.lock().unwrap();
+        let mut addr = addr;
+        if addr.port() == 0 {
+            loop {
+                inner.next_port = inner.next_port.wrapping_add(1);
+                addr.set_port(49152 | inner.next_port);
+                if !inner.endpoints.contains_key(&addr) {
+                    break;
+                }
+            }
+        }
+        if inner.endpoints.contains_key(&addr) {
+            return Err(io::ErrorKind::AddrInUse.into());
+        }
+        let (tx, rx) = mpsc::unbounded_channel();
+        inner.endpoints.insert(addr, tx);
+        Ok(LoopbackSocket {
+            addr,
+            network: self.clone(),
+            rx: tokio::sync::Mutex::new(rx),
+        })
+    }
+
+    fn deliver(&self, from: SocketAddr, buf: &[u8], to: SocketAddr) {
+        let inner = self.inner.lock().unwrap();
+        let broadcast = matches!(to.ip(), IpAddr::V4(ip) if ip.is_broadcast());
+        for (addr, tx) in &inner.endpoints {
+            let matches = match broadcast {
+                true => addr.port() == to.port() && *addr != from,
+                false => *addr == to,
+            };
+            if matches {
+                let _ = tx.send((buf.to_vec(), from));
+            }
+        }
+    }
+}
+
+/// A socket on a [LoopbackNetwork]
+pub struct LoopbackSocket {
+    addr: SocketAddr,
+    network: LoopbackNetwork,
+    rx: tokio::sync::Mutex<mpsc::UnboundedReceiver<Datagram>>,
+}
+
+impl Transport for LoopbackSocket {
+    fn send_to<'a>(&'a self, buf: &'a [u8], addr: SocketAddr) -> TransportFuture<'a, usize> {
+        self.network.deliver(self.addr, buf, addr);
+        Box::pin(std::future::ready(Ok(buf.len())))
+    }
+
+    fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> TransportFuture<'a, (usize, SocketAddr)> {
+        Box::pin(async move {
+            // the network keeps a sender for as long as this socket exists
+            let (datagram, from) = self.rx.lock().await.recv().await.unwrap();
+            let len = datagram.len().min(buf.len());
+            buf[..len].copy_from_slice(&datagram[..len]);
+            check_len(datagram.len(), buf, from)
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.addr)
+    }
+}
+
+impl Drop for LoopbackSocket {
+    fn drop(&mut self) {
+        self.network
+            .inner
+            .lock()
+            .unwrap()
+            .endpoints
+            .remove(&self.addr);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::tests::{spawn_fake_bulb, Faults};
+    use crate::{Client, ClientOptions, Error, ReliableSender};
+    use lifx_core::Message;
+
+    /// Acknowledges everything, after ignoring the first `drop` datagrams
+    fn spawn_device(socket: LoopbackSocket, drop: usize) {
+        let faults = Faults {
+            drop_first: drop,
+            ..Default::default()
+        };
+        spawn_fake_bulb(socket, 0x1234, "Device", faults);
+    }
+
+    /// A transport that fails sends with the given errors, in order, before working normally
+    struct Flaky {
+        socket: LoopbackSocket,
+        errors: Mutex<Vec<io::ErrorKind>>,
+    }
+
+    impl Transport for Flaky {
+        fn send_to<'a>(&'a self, buf: &'a [u8], addr: SocketAddr) -> TransportFuture<'a, usize> {
+            let mut errors = self.errors.lock().unwrap();
+            if !errors.is_empty() {
+                let err = errors.remove(0);
+                return Box::pin(std::future::ready(Err(err.into())));
+            }
+            self.socket.send_to(buf, addr)
+        }
+
+        fn recv_from<'a>(&'a self, buf: &'a mut [u8]) -> TransportFuture<'a, (usize, SocketAddr)> {
+            self.socket.recv_from(buf)
+        }
+
+        fn local_addr(&self) -> io::Result<SocketAddr> {
+            self.socket.local_addr()
+        }
+    }
+
+    #[test]
+    fn test_classify() {
+        let class = |kind: io::ErrorKind| classify(&kind.into());
+        assert_eq!(class(io::ErrorKind::WouldBlock), ErrorClass::Transient);
+        assert_eq!(
+            class(io::ErrorKind::HostUnreachable),
+            ErrorClass::Unreachable
+        );
+        assert_eq!(
+            class(io::ErrorKind::ConnectionReset),
+            ErrorClass::Unreachable
+        );
+        assert_eq!(class(io::ErrorKind::NetworkDown), ErrorClass::NetworkDown);
+        assert_eq!(class(io::ErrorKind::PermissionDenied), ErrorClass::Fatal);
+
+        let addr = "10.0.0.2:56700".parse().unwrap();
+        let err = send_error(io::ErrorKind::HostUnreachable.into(), addr);
+        assert!(matches!(err, Error::Unreachable(a) if a == addr));
+
+        let mut backoff = Backoff::default();
+        assert_eq!(backoff.current(), None);
+        assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+        assert_eq!(backoff.next_delay(), Duration::from_millis(200));
+        for _ in 0..10 {
+            backoff.next_delay();
+        }
+        assert_eq!(backoff.current(), Some(Duration::from_secs(5)));
+        backoff.reset();
+        assert_eq!(backoff.current(), None);
+    }
+
+    #[tokio::test]
+    async fn test_send_errors() {
+        use io::ErrorKind::*;
+        let network = LoopbackNetwork::new();
+        let device_addr: SocketAddr = "10.0.0.2:56700".parse().unwrap();
+        spawn_device(network.bind(device_addr).unwrap(), 0);
+
+        let flaky = Flaky {
+            socket: network.bind("10.0.0.1:0".parse().unwrap()).unwrap(),
+            errors: Mutex::new(vec![WouldBlock, WouldBlock, HostUnreachable, NetworkDown]),
+        };
+        let options = ClientOptions {
+            timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let client = Client::with_transport(flaky, options);
+
+        // transient errors are retried, but an unreachable host isn't
+        let res = client
+            .send_acked(device_addr, 0x1234, Message::GetPower)
+            .await;
+        assert!(matches!(res, Err(Error::Unreachable(addr)) if addr == device_addr));
+        let res = client
+            .send_acked(device_addr, 0x1234, Message::GetPower)
+            .await;
+        assert!(matches!(res, Err(Error::NetworkDown)));
+        // the next send waits out the backoff, and then works
+        client
+            .send_acked(device_addr, 0x1234, Message::GetPower)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_loopback_client() {
+        let network = LoopbackNetwork::new();
+        let device_addr: SocketAddr = "10.0.0.2:56700".parse().unwrap();
+        spawn_device(network.bind(device_addr).unwrap(), 1);
+
+        let options = ClientOptions {
+            timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let local = network.bind("10.0.0.1:0".parse().unwrap()).unwrap();
+        let client = Client::with_transport(local, options);
+        assert_eq!(client.local_addr().unwrap().ip().to_string(), "10.0.0.1");
+
+        // the first attempt is always lost, so this needs a retry
+        let res = client
+            .send_acked(device_addr, 0x1234, Message::GetPower)
+            .await;
+        assert!(matches!(res, Err(Error::Timeout)));
+        ReliableSender::new(client, 2)
+            .send_acked(device_addr, 0x1234, Message::GetPower)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_loopback_network() {
+        let network = LoopbackNetwork::new();
+        let a = network.bind("10.0.0.1:56700".parse().unwrap()).unwrap();
+        let b = network.bind("10.0.0.2:56700".parse().unwrap()).unwrap();
+        let c = network.bind("10.0.0.3:0".parse().unwrap()).unwrap();
+        assert!(network.bind(a.local_addr().unwrap()).is_err());
+
+        // broadcasts reach everything on the port, except the sender
+        c.send_to(b"hello", "255.255.255.255:56700".parse().unwrap())
+            .await
+            .unwrap();
+        let mut buf = [0; 8];
+        for socket in &[&a, &b] {
+            let (n, from) = socket.recv_from(&mut buf).await.unwrap();
+            assert_eq!((&buf[..n], from), (&b"hello"[..], c.local_addr().unwrap()));
+        }
+
+        // datagrams that don't fit (or only just fit) are reported as truncated
+        c.send_to(b"too long", a.local_addr().unwrap())
+            .await
+            .unwrap();
+        let err = a.recv_from(&mut buf).await.unwrap_err();
+        assert_eq!(classify(&err), ErrorClass::Truncated);
+        let truncated = err.get_ref().unwrap().downcast_ref::<Truncated>();
+        assert_eq!(truncated.unwrap().from, c.local_addr().ok());
+
+        // a socket's address is free again once it's dropped
+        let addr = b.local_addr().unwrap();
+        drop(b);
+        assert!(network.bind(addr).is_ok());
+    }
+}
@@ -0,0 +1,184 @@
+//! Picking up state that devices broadcast without being asked
+//!
+//! When a controller sends a request with a source of zero, the device broadcasts its reply to
+//! the whole subnet, and some devices broadcast [Message::StateService] and other state on their
+//! own.  Anything listening on the LIFX port can hear these, and they're as good as an answer to a
+//! poll, for free.  [RawMessage::is_unsolicited] tells them apart from replies to a particular
+//! controller, and from other controllers' requests.
+//!
+//! There are two ways to hear them:
+//!
+//! * [Client::unsolicited](crate::Client::unsolicited) hands over the ones that arrive at a
+//!   client's own socket.  A client normally ignores every message that isn't a reply to one of
+//!   its own requests.
+//! * An [UnsolicitedListener] binds the LIFX port itself, since that's where devices send their
+//!   broadcasts, and only passes on unsolicited messages.
+//!
+//! Either way, [Unsolicited::update] feeds them into a [DeviceMap], so its state stays fresh
+//! between polls.
+//!
+//! ```no_run
+//! # async fn example() -> Result<(), lifx::Error> {
+//! use lifx::devices::DeviceMap;
+//! use lifx::unsolicited::UnsolicitedListener;
+//!
+//! let devices = DeviceMap::new();
+//! let mut listener = UnsolicitedListener::new().await?;
+//! loop {
+//!     let unsolicited = listener.recv().await?;
+//!     if unsolicited.update(&devices) {
+//!         println!("{:016X} changed: {:?}", unsolicited.target, unsolicited.message);
+//!     }
+//! }
+//! # }
+//! ```
+
+use crate::devices::DeviceMap;
+use crate::telemetry;
+use crate::transport::{self, ErrorClass, Transport, RECV_BUFFER_SIZE};
+use crate::Error;
+use lifx_core::{Message, RawMessage, LIFX_PORT};
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use tokio::net::UdpSocket;
+
+/// How many unsolicited messages a [Client::unsolicited](crate::Client::unsolicited) subscriber
+/// can fall behind by before it starts missing them
+pub const UNSOLICITED_CAPACITY: usize = 64;
+
+/// A message that a device broadcast without it being a reply to us
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unsolicited {
+    /// Where the message was sent from
+    pub addr: SocketAddr,
+    /// The device that sent it
+    pub target: u64,
+    pub message: Message,
+}
+
+impl Unsolicited {
+    /// Decodes a message, if it's unsolicited (see [RawMessage::is_unsolicited])
+    ///
+    /// Returns `None` for anything else, including unsolicited messages that can't be decoded.
+    pub fn from_raw(raw: &RawMessage, addr: SocketAddr) -> Option<Unsolicited> {
+        if !raw.is_unsolicited() {
+            return None;
+        }
+        let message = Message::from_raw(raw).ok()?;
+        Some(Unsolicited {
+            addr,
+            target: raw.frame_addr.target,
+            message,
+        })
+    }
+
+    /// Updates the state of the device that sent this, and returns whether it changed (see
+    /// [DeviceMap::update])
+    pub fn update(&self, devices: &DeviceMap) -> bool {
+        devices.update(self.target, self.addr, &self.message)
+    }
+}
+
+/// Listens for state that devices broadcast, ignoring everything else
+#[derive(Debug)]
+pub struct UnsolicitedListener {
+    socket: UdpSocket,
+    buf: Vec<u8>,
+}
+
+impl UnsolicitedListener {
+    /// Listens on the LIFX port on all interfaces
+    ///
+    /// This fails if something else on this host is already using the port.  This must be called
+    /// from within a tokio runtime.
+    pub async fn new() -> Result<UnsolicitedListener, Error> {
+        UnsolicitedListener::bind(SocketAddr::new(Ipv4Addr::UNSPECIFIED.into(), LIFX_PORT)).await
+    }
+
+    /// Listens on the given address
+    pub async fn bind(addr: SocketAddr) -> Result<UnsolicitedListener, Error> {
+        Ok(UnsolicitedListener {
+            socket: UdpSocket::bind(addr).await?,
+            buf: vec![0; RECV_BUFFER_SIZE],
+        })
+    }
+
+    /// The local address that this listener is listening on
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Waits for the next unsolicited message
+    ///
+    /// Datagrams that can't be decoded, replies to particular controllers, and requests are all
+    /// skipped.
+    pub async fn recv(&mut self) -> Result<Unsolicited, Error> {
+        loop {
+            let (nbytes, addr) = match Transport::recv_from(&self.socket, &mut self.buf).await {
+                Ok(x) => x,
+                Err(e) if transport::classify(&e) == ErrorClass::Truncated => {
+                    telemetry::decode_error();
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            let raw = match RawMessage::unpack(&self.buf[..nbytes]) {
+                Ok(raw) => raw,
+                Err(_) => {
+                    telemetry::decode_error();
+                    continue;
+                }
+            };
+            if let Some(unsolicited) = Unsolicited::from_raw(&raw, addr) {
+                return Ok(unsolicited);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use lifx_core::BuildOptions;
+
+    /// A message from a device, with the given source
+    pub(crate) fn from_device(source: u32, msg: Message) -> Vec<u8> {
+        let options = BuildOptions {
+            target: Some(0x1234),
+            ..Default::default()
+        };
+        let mut raw = RawMessage::build(&options, msg).unwrap();
+        raw.frame.set_source(source);
+        raw.pack().unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_listener() {
+        let mut listener = UnsolicitedListener::bind("127.0.0.1:0".parse().unwrap())
+            .await
+            .unwrap();
+        let addr = listener.local_addr().unwrap();
+        let device = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+
+        // a reply to one controller, a request, and then a broadcast reply
+        let label = Message::StateLabel {
+            label: lifx_core::LifxString::new(&std::ffi::CString::new("Desk").unwrap()),
+        };
+        for bytes in [
+            from_device(0x4321, label.clone()),
+            from_device(0, Message::GetLabel),
+            from_device(0, label.clone()),
+        ] {
+            device.send_to(&bytes, addr).await.unwrap();
+        }
+
+        let unsolicited = listener.recv().await.unwrap();
+        assert_eq!(unsolicited.target, 0x1234);
+        assert_eq!(unsolicited.addr, device.local_addr().unwrap());
+        assert_eq!(unsolicited.message, label);
+
+        let devices = DeviceMap::new();
+        assert!(unsolicited.update(&devices));
+        assert_eq!(devices.get(0x1234).unwrap().label.as_deref(), Some("Desk"));
+    }
+}
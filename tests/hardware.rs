@@ -0,0 +1,171 @@
+//! A read-only test suite for a real LIFX device
+//!
+//! This only runs when `LIFX_TEST_TARGET` is set to the address of a device, either as an IP
+//! address (`192.168.1.20`) or with a port (`192.168.1.20:56700`):
+//!
+//! ```text
+//! LIFX_TEST_TARGET=192.168.1.20 cargo test --test hardware -- --nocapture
+//! ```
+//!
+//! Only `Get*` messages (and an echo) are sent, so it's safe to point at a light that someone is
+//! using.  Every reply is checked to be the right type, and to survive being packed and unpacked
+//! again, which catches decoding bugs that only show up with what real firmware sends.
+
+use lifx::{Client, ClientOptions, ReliableSender};
+use lifx_core::{
+    BuildOptions, EchoPayload, Message, ProductInfo, RawMessage, ZoneRange, LIFX_PORT,
+};
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+/// The address of the device to test, if there is one
+fn test_target() -> Option<SocketAddr> {
+    let target = std::env::var("LIFX_TEST_TARGET").ok()?;
+    let addr = match target.parse::<SocketAddr>() {
+        Ok(addr) => addr,
+        Err(_) => SocketAddr::new(
+            target
+                .parse::<IpAddr>()
+                .expect("LIFX_TEST_TARGET should be an IP address, with or without a port"),
+            LIFX_PORT,
+        ),
+    };
+    Some(addr)
+}
+
+/// Sends a request, and checks that the reply has the expected type and round-trips
+async fn check(sender: &ReliableSender, addr: SocketAddr, target: u64, msg: Message, reply: u16) {
+    let name = format!("{:?}", msg);
+    let got = sender
+        .request(addr, target, msg)
+        .await
+        .unwrap_or_else(|e| panic!("{}: {}", name, e));
+    println!("{} -> {:?}", name, got);
+    assert_eq!(got.get_num(), reply, "{} got the wrong reply", name);
+
+    let raw = RawMessage::build(&BuildOptions::default(), got.clone()).unwrap();
+    let again = Message::from_raw(&RawMessage::unpack(&raw.pack().unwrap()).unwrap()).unwrap();
+    assert_eq!(
+        again.normalize(),
+        got.normalize(),
+        "{} didn't round-trip",
+        name
+    );
+}
+
+#[tokio::test]
+async fn read_only_suite() {
+    let Some(addr) = test_target() else {
+        eprintln!("LIFX_TEST_TARGET isn't set, so not testing against a real device");
+        return;
+    };
+    let client = Client::with_options(ClientOptions {
+        timeout: Duration::from_secs(2),
+        ..Default::default()
+    })
+    .await
+    .unwrap();
+    let devices = client
+        .discover_on(addr, Duration::from_secs(2))
+        .await
+        .unwrap();
+    let device = devices
+        .first()
+        .expect("the device didn't answer GetService");
+    println!("testing {:016X} at {}", device.target, device.addr);
+    let (addr, target) = (device.addr, device.target);
+    let sender = ReliableSender::new(client, 3);
+
+    for (msg, reply) in [
+        (Message::GetService, 3),
+        (Message::GetHostFirmware, 15),
+        (Message::GetWifiInfo, 17),
+        (Message::GetWifiFirmware, 19),
+        (Message::GetPower, 22),
+        (Message::GetLabel, 25),
+        (Message::GetVersion, 33),
+        (Message::GetInfo, 35),
+        (Message::GetLocation, 50),
+        (Message::GetGroup, 53),
+    ] {
+        check(&sender, addr, target, msg, reply).await;
+    }
+
+    let payload = EchoPayload(std::array::from_fn(|i| i as u8));
+    let echo = sender
+        .request(addr, target, Message::EchoRequest { payload })
+        .await
+        .unwrap();
+    assert_eq!(echo, Message::EchoResponse { payload });
+
+    let version = sender
+        .request(addr, target, Message::GetVersion)
+        .await
+        .unwrap();
+    let Some(info) = ProductInfo::from_state_version(&version) else {
+        println!(
+            "unknown product {:?}, so only the device messages were tested",
+            version
+        );
+        return;
+    };
+    println!("product: {}", info.name);
+    if info.relays() {
+        check(
+            &sender,
+            addr,
+            target,
+            Message::RelayGetPower { relay_index: 0 },
+            818,
+        )
+        .await;
+        return;
+    }
+
+    check(&sender, addr, target, Message::LightGet, 107).await;
+    check(&sender, addr, target, Message::LightGetPower, 118).await;
+    if info.infrared() {
+        check(&sender, addr, target, Message::LightGetInfrared, 121).await;
+    }
+    if info.hev() {
+        check(&sender, addr, target, Message::LightGetHevCycle, 144).await;
+        check(
+            &sender,
+            addr,
+            target,
+            Message::LightGetHevCycleConfiguration,
+            147,
+        )
+        .await;
+        check(
+            &sender,
+            addr,
+            target,
+            Message::LightGetLastHevCycleResult,
+            149,
+        )
+        .await;
+    }
+    if info.multizone() {
+        check(&sender, addr, target, Message::GetMultiZoneEffect, 509).await;
+        let zones = sender
+            .client()
+            .get_color_zones(addr, target, ZoneRange::ALL)
+            .await
+            .unwrap();
+        assert!(!zones.is_empty());
+    }
+    if info.matrix() {
+        check(
+            &sender,
+            addr,
+            target,
+            Message::GetTileEffect {
+                reserved6: 0,
+                reserved7: 0,
+            },
+            720,
+        )
+        .await;
+    }
+}
@@ -0,0 +1,633 @@
+//! Runs a fleet of emulated LIFX devices, for load-testing controllers
+//!
+//! ```text
+//! cargo run -p emulator -- utils/emulator/example.toml
+//! ```
+//!
+//! The config file lists the devices to run (see `example.toml`).  Every device has its own
+//! socket, and so its own address, and they're numbered in order with serial numbers from
+//! `d073d5000001` up, so they keep the same identities from one run to the next.  One extra socket
+//! listens for discovery broadcasts, and every device answers them from its own socket.
+//!
+//! Each device answers the basic device and light queries, and obeys the commands that change
+//! them, so a controller reads back what it set.  Multizone products also have a strip of zones
+//! (16 unless the config says otherwise), which follow the zone commands and answer the zone
+//! queries.  Anything else is only acknowledged (if asked).
+//!
+//! With `state` set in the config file, each device's label, power, color, infrared level, group,
+//! location and zones are saved to that JSON file whenever a command changes them, and restored
+//! from it on the next run, so the fleet looks like the same real hardware after a restart.
+
+use lifx::provision::ident_for;
+use lifx::DeviceState;
+use lifx_core::zones::{extended_zone_pages, ZoneRange};
+use lifx_core::{
+    get_product_info, ApplicationRequest, BuildOptions, LifxIdent, LifxString, Message, RawMessage,
+    Service, HSBK, LIFX_PORT, MAX_DATAGRAM_SIZE,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::ffi::CString;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, UdpSocket};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::{sleep, spawn};
+use std::time::Duration;
+
+/// The first three bytes of every LIFX serial number
+const OUI: [u8; 3] = [0xd0, 0x73, 0xd5];
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Config {
+    /// Where to listen for discovery broadcasts
+    #[serde(default = "default_discovery")]
+    discovery: SocketAddr,
+    /// A JSON file to save the state of every device to, and restore it from on startup
+    state: Option<PathBuf>,
+    #[serde(default, rename = "device")]
+    devices: Vec<DeviceConfig>,
+}
+
+/// One kind of device in the config file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DeviceConfig {
+    /// How many devices like this to run
+    #[serde(default = "one")]
+    count: u32,
+    /// The product ID that the device reports (the vendor is always LIFX)
+    product: u32,
+    /// The label, which gets a number on the end when there's more than one device
+    label: Option<String>,
+    group: Option<String>,
+    location: Option<String>,
+    /// How many zones each device has (ignored unless the product is a multizone one)
+    #[serde(default = "default_zones")]
+    zones: u16,
+    /// The address to bind each device's socket to; with port 0, every device gets its own port
+    #[serde(default = "any_addr")]
+    bind: SocketAddr,
+}
+
+fn default_discovery() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::UNSPECIFIED, LIFX_PORT))
+}
+
+fn any_addr() -> SocketAddr {
+    SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0))
+}
+
+fn one() -> u32 {
+    1
+}
+
+fn default_zones() -> u16 {
+    16
+}
+
+fn lifx_string(s: &str) -> LifxString {
+    // anything after a nul can't be sent anyway
+    let s = s.split('\0').next().unwrap_or_default();
+    LifxString::new(&CString::new(s).unwrap())
+}
+
+/// A color, as saved in the state file
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+struct SavedColor {
+    hue: u16,
+    saturation: u16,
+    brightness: u16,
+    kelvin: u16,
+}
+
+impl From<HSBK> for SavedColor {
+    fn from(c: HSBK) -> SavedColor {
+        SavedColor {
+            hue: c.hue,
+            saturation: c.saturation,
+            brightness: c.brightness,
+            kelvin: c.kelvin,
+        }
+    }
+}
+
+impl From<SavedColor> for HSBK {
+    fn from(c: SavedColor) -> HSBK {
+        HSBK {
+            hue: c.hue,
+            saturation: c.saturation,
+            brightness: c.brightness,
+            kelvin: c.kelvin,
+        }
+    }
+}
+
+/// A group or location, as saved in the state file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SavedGroup {
+    /// The ID, in the same UUID format that it's displayed in
+    id: String,
+    name: String,
+}
+
+impl SavedGroup {
+    fn of(named: &Option<(LifxIdent, String)>) -> Option<SavedGroup> {
+        named.as_ref().map(|(id, name)| SavedGroup {
+            id: id.to_string(),
+            name: name.clone(),
+        })
+    }
+
+    /// The group's ID and name, or `None` if the ID can't be parsed
+    fn get(&self) -> Option<(LifxIdent, String)> {
+        let hex: Vec<u8> = self.id.bytes().filter(|&b| b != b'-').collect();
+        if hex.len() != 32 {
+            return None;
+        }
+        let mut id = [0; 16];
+        for (byte, pair) in id.iter_mut().zip(hex.chunks(2)) {
+            *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+        }
+        Some((LifxIdent(id), self.name.clone()))
+    }
+}
+
+/// The part of a device's state that's kept between runs
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct SavedState {
+    label: Option<String>,
+    power: Option<u16>,
+    color: Option<SavedColor>,
+    infrared: Option<u16>,
+    group: Option<SavedGroup>,
+    location: Option<SavedGroup>,
+    zones: Option<Vec<Option<SavedColor>>>,
+}
+
+impl SavedState {
+    fn of(state: &DeviceState) -> SavedState {
+        SavedState {
+            label: state.label.clone(),
+            power: state.power,
+            color: state.color.map(SavedColor::from),
+            infrared: state.infrared,
+            group: SavedGroup::of(&state.group),
+            location: SavedGroup::of(&state.location),
+            zones: state
+                .zones
+                .as_ref()
+                .map(|zones| zones.iter().map(|z| z.map(SavedColor::from)).collect()),
+        }
+    }
+
+    /// Copies everything that was saved into `state`, leaving the rest alone
+    fn restore(&self, state: &mut DeviceState) {
+        if self.label.is_some() {
+            state.label = self.label.clone();
+        }
+        state.power = self.power.or(state.power);
+        state.color = self.color.map(HSBK::from).or(state.color);
+        state.infrared = self.infrared.or(state.infrared);
+        if let Some(group) = self.group.as_ref().and_then(SavedGroup::get) {
+            state.group = Some(group);
+        }
+        if let Some(location) = self.location.as_ref().and_then(SavedGroup::get) {
+            state.location = Some(location);
+        }
+        if let Some(zones) = &self.zones {
+            state.zones = Some(zones.iter().map(|z| z.map(HSBK::from)).collect());
+        }
+    }
+}
+
+/// The JSON file that device state is saved to, keyed by serial number
+struct StateFile {
+    path: PathBuf,
+    saved: Mutex<BTreeMap<String, SavedState>>,
+}
+
+impl StateFile {
+    /// Reads the saved state, if the file exists yet
+    fn open(path: &Path) -> Result<StateFile, Box<dyn std::error::Error>> {
+        let saved = match std::fs::read_to_string(path) {
+            Ok(json) => serde_json::from_str(&json)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(StateFile {
+            path: path.to_owned(),
+            saved: Mutex::new(saved),
+        })
+    }
+
+    fn restore(&self, state: &mut DeviceState) {
+        if let Some(saved) = self.saved.lock().unwrap().get(&state.serial()) {
+            saved.restore(state);
+        }
+    }
+
+    /// Saves a device's state, rewriting the whole file
+    fn save(&self, state: &DeviceState) {
+        let mut saved = self.saved.lock().unwrap();
+        saved.insert(state.serial(), SavedState::of(state));
+        // written to the side and renamed, so a crash can't leave half a file behind
+        let tmp = self.path.with_extension("tmp");
+        let res = serde_json::to_vec_pretty(&*saved)
+            .map_err(io::Error::from)
+            .and_then(|json| std::fs::write(&tmp, json))
+            .and_then(|()| std::fs::rename(&tmp, &self.path));
+        if let Err(e) = res {
+            println!("Couldn't save state to {}: {}", self.path.display(), e);
+        }
+    }
+}
+
+/// Whether an error means that an earlier reply couldn't be delivered (which is reported through
+/// ICMP, so it shows up when receiving)
+fn is_unreachable(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::HostUnreachable
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+struct Device {
+    target: u64,
+    product: u32,
+    sock: UdpSocket,
+    state: Mutex<DeviceState>,
+    state_file: Option<Arc<StateFile>>,
+}
+
+impl Device {
+    /// Creates the `number`th device (counting from 1) described by `config`
+    ///
+    /// Anything saved in `state_file` for this device replaces the state from the config.
+    fn new(
+        number: u32,
+        config: &DeviceConfig,
+        label: String,
+        state_file: Option<Arc<StateFile>>,
+    ) -> io::Result<Device> {
+        let [low, mid, high, _] = number.to_le_bytes();
+        let serial = [OUI[0], OUI[1], OUI[2], high, mid, low, 0, 0];
+        let target = u64::from_le_bytes(serial);
+
+        let sock = UdpSocket::bind(config.bind)?;
+        let mut state = DeviceState::new(target);
+        state.addr = Some(sock.local_addr()?);
+        state.label = Some(label);
+        state.power = Some(0);
+        state.color = Some(HSBK {
+            hue: 0,
+            saturation: 0,
+            brightness: 65535,
+            kelvin: 3500,
+        });
+        state.infrared = Some(0);
+        // the same IDs that provisioning would give groups with these names
+        let group = config.group.as_deref().unwrap_or("Emulated");
+        state.group = Some((ident_for(std::iter::empty(), group), group.to_owned()));
+        let location = config.location.as_deref().unwrap_or("Emulated");
+        state.location = Some((ident_for(std::iter::empty(), location), location.to_owned()));
+        state.version = Some((1, config.product));
+        if state.product_info().is_some_and(|p| p.multizone()) {
+            state.zones = Some(vec![state.color; config.zones as usize]);
+        }
+        if let Some(file) = &state_file {
+            file.restore(&mut state);
+        }
+
+        Ok(Device {
+            target,
+            product: config.product,
+            sock,
+            state: Mutex::new(state),
+            state_file,
+        })
+    }
+
+    /// The replies to a query, based on the current state
+    fn answer(&self, query: &Message) -> Vec<Message> {
+        match query {
+            Message::GetColorZones { .. } | Message::GetExtendedColorZone => {
+                self.answer_zones(query)
+            }
+            _ => self.answer_one(query).into_iter().collect(),
+        }
+    }
+
+    /// The reply to a query that's answered with a single message
+    fn answer_one(&self, query: &Message) -> Option<Message> {
+        let state = self.state.lock().unwrap();
+        let label = lifx_string(state.label.as_deref().unwrap_or_default());
+        let power = state.power.unwrap_or_default();
+        Some(match query {
+            Message::GetService => Message::StateService {
+                service: Service::UDP,
+                port: self.sock.local_addr().ok()?.port() as u32,
+            },
+            Message::GetHostFirmware => Message::StateHostFirmware {
+                build: 0,
+                reserved: 0,
+                version_minor: 70,
+                version_major: 3,
+            },
+            Message::GetVersion => Message::StateVersion {
+                vendor: 1,
+                product: self.product,
+                reserved: 0,
+            },
+            Message::GetLabel => Message::StateLabel { label },
+            Message::GetPower => Message::StatePower { level: power },
+            Message::LightGetPower => Message::LightStatePower { level: power },
+            Message::LightGet => Message::LightState {
+                color: state.color?,
+                reserved: 0,
+                power,
+                label,
+                reserved2: 0,
+            },
+            Message::LightGetInfrared if state.product_info()?.infrared() => {
+                Message::LightStateInfrared {
+                    brightness: state.infrared?,
+                }
+            }
+            Message::GetGroup => {
+                let (group, label) = state.group.as_ref()?;
+                Message::StateGroup {
+                    group: *group,
+                    label: lifx_string(label),
+                    updated_at: 0,
+                }
+            }
+            Message::GetLocation => {
+                let (location, label) = state.location.as_ref()?;
+                Message::StateLocation {
+                    location: *location,
+                    label: lifx_string(label),
+                    updated_at: 0,
+                }
+            }
+            Message::EchoRequest { payload } => Message::EchoResponse { payload: *payload },
+            _ => return None,
+        })
+    }
+
+    fn extended_multizone(&self) -> bool {
+        get_product_info(1, self.product).is_some_and(|p| p.extended_multizone())
+    }
+
+    /// The replies to a zone query, the same way a real strip splits them up
+    ///
+    /// Devices without zones don't answer at all.
+    fn answer_zones(&self, query: &Message) -> Vec<Message> {
+        let state = self.state.lock().unwrap();
+        let Some(zones) = state.zones.as_deref() else {
+            return Vec::new();
+        };
+        let black = HSBK {
+            hue: 0,
+            saturation: 0,
+            brightness: 0,
+            kelvin: 3500,
+        };
+        let colors: Vec<HSBK> = zones.iter().map(|z| z.unwrap_or(black)).collect();
+        // the older zone messages can only count up to 255 zones
+        let count = colors.len().min(u8::MAX as usize + 1);
+        match *query {
+            Message::GetColorZones {
+                start_index,
+                end_index,
+            } => {
+                let Ok(range) = ZoneRange::new(start_index, end_index) else {
+                    return Vec::new();
+                };
+                let start = range.start() as usize;
+                if start >= count {
+                    return Vec::new();
+                }
+                if range.len() == 1 {
+                    return vec![Message::StateZone {
+                        count: count as u8,
+                        index: range.start(),
+                        color: colors[start],
+                    }];
+                }
+                let end = (range.end() as usize).min(count - 1);
+                (start..=end)
+                    .step_by(8)
+                    .map(|index| {
+                        let zone = |i: usize| colors.get(index + i).copied().unwrap_or(black);
+                        Message::StateMultiZone {
+                            count: count as u8,
+                            index: index as u8,
+                            color0: zone(0),
+                            color1: zone(1),
+                            color2: zone(2),
+                            color3: zone(3),
+                            color4: zone(4),
+                            color5: zone(5),
+                            color6: zone(6),
+                            color7: zone(7),
+                        }
+                    })
+                    .collect()
+            }
+            Message::GetExtendedColorZone if self.extended_multizone() => {
+                // the pages of a strip-wide set are laid out the same as the replies
+                let pages = extended_zone_pages(&colors, Duration::ZERO, ApplicationRequest::Apply)
+                    .unwrap_or_default();
+                pages
+                    .into_iter()
+                    .filter_map(|page| match page {
+                        Message::SetExtendedColorZones {
+                            zone_index,
+                            colors_count,
+                            colors,
+                            ..
+                        } => Some(Message::StateExtendedColorZones {
+                            zones_count: zones.len() as u16,
+                            zone_index,
+                            colors_count,
+                            colors,
+                        }),
+                        _ => None,
+                    })
+                    .collect()
+            }
+            _ => Vec::new(),
+        }
+    }
+
+    /// Handles a message sent to this device, and returns the replies
+    fn handle(&self, raw: &RawMessage) -> Vec<Message> {
+        let mut replies = Vec::new();
+        if raw.wants_ack() {
+            replies.push(Message::Acknowledgement {
+                seq: raw.frame_addr.sequence,
+            });
+        }
+        let Ok(msg) = Message::from_raw(raw) else {
+            return replies;
+        };
+        // commands are answered with the new state, but only if the sender asked for it
+        let query = match msg {
+            Message::SetLabel { .. } => Some(Message::GetLabel),
+            Message::SetPower { .. } => Some(Message::GetPower),
+            Message::LightSetPower { .. } => Some(Message::LightGetPower),
+            Message::LightSetColor { .. } => Some(Message::LightGet),
+            Message::LightSetInfrared { .. } => Some(Message::LightGetInfrared),
+            Message::SetGroup { .. } => Some(Message::GetGroup),
+            Message::SetLocation { .. } => Some(Message::GetLocation),
+            Message::SetColorZones {
+                start_index,
+                end_index,
+                ..
+            } => Some(Message::GetColorZones {
+                start_index,
+                end_index,
+            }),
+            // products without extended multizone support ignore it, like real ones
+            Message::SetExtendedColorZones { .. } if self.extended_multizone() => {
+                Some(Message::GetExtendedColorZone)
+            }
+            _ => None,
+        };
+        match query {
+            Some(query) => {
+                let mut state = self.state.lock().unwrap();
+                if state.apply(&msg) {
+                    if let Some(file) = &self.state_file {
+                        file.save(&state);
+                    }
+                }
+                drop(state);
+                if raw.wants_response() {
+                    replies.extend(self.answer(&query));
+                }
+            }
+            None => replies.extend(self.answer(&msg)),
+        }
+        replies
+    }
+
+    /// Sends replies to a message from this device's socket
+    fn reply(&self, raw: &RawMessage, replies: Vec<Message>, to: SocketAddr) {
+        let opts = BuildOptions {
+            target: Some(self.target),
+            ..raw.reply_options()
+        };
+        for reply in replies {
+            let bytes = RawMessage::build(&opts, reply).and_then(|raw| raw.pack());
+            let res = match bytes {
+                Ok(bytes) => self.sock.send_to(&bytes, to).map(|_| ()),
+                Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+            };
+            if let Err(e) = res {
+                println!("{:016X} couldn't reply to {}: {}", self.target, to, e);
+            }
+        }
+    }
+
+    /// Whether a message is meant for this device
+    fn is_for(&self, raw: &RawMessage) -> bool {
+        raw.is_broadcast() || raw.frame_addr.target == self.target
+    }
+
+    /// Answers messages sent to this device, forever
+    fn run(&self) {
+        serve(&self.sock, |raw, from| {
+            if self.is_for(&raw) {
+                self.reply(&raw, self.handle(&raw), from);
+            }
+        })
+    }
+}
+
+/// Receives messages on a socket forever, calling `handle` with each one
+fn serve(sock: &UdpSocket, mut handle: impl FnMut(RawMessage, SocketAddr)) {
+    // one byte larger than any LIFX message, so that anything larger can be spotted
+    let mut buf = [0; MAX_DATAGRAM_SIZE + 1];
+    loop {
+        match sock.recv_from(&mut buf) {
+            Ok((nbytes, _)) if nbytes == buf.len() => continue,
+            Ok((nbytes, from)) => {
+                if let Ok(raw) = RawMessage::unpack(&buf[..nbytes]) {
+                    handle(raw, from);
+                }
+            }
+            Err(e) if is_unreachable(&e) => continue,
+            Err(e) => {
+                println!("Error receiving: {}", e);
+                sleep(Duration::from_millis(100));
+            }
+        }
+    }
+}
+
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let Some(path) = std::env::args().nth(1) else {
+        eprintln!("usage: emulator <config.toml>");
+        std::process::exit(2);
+    };
+    let config: Config = toml::from_str(&std::fs::read_to_string(&path)?)?;
+    let state_file = match &config.state {
+        Some(path) => Some(Arc::new(StateFile::open(path)?)),
+        None => None,
+    };
+
+    let mut devices = Vec::new();
+    for device in &config.devices {
+        let product = get_product_info(1, device.product);
+        if product.is_none() {
+            println!("Warning: {} isn't a known product ID", device.product);
+        }
+        let name = product.map_or("Emulated", |p| p.name);
+        let label = device.label.as_deref().unwrap_or(name);
+        for n in 1..=device.count {
+            let label = match device.count {
+                1 => label.to_owned(),
+                _ => format!("{} {}", label, n),
+            };
+            let number = devices.len() as u32 + 1;
+            devices.push(Device::new(number, device, label, state_file.clone())?);
+        }
+    }
+    let devices = Arc::new(devices);
+
+    let discovery = UdpSocket::bind(config.discovery)?;
+    println!(
+        "Emulating {} devices, with discovery on {}",
+        devices.len(),
+        config.discovery
+    );
+    for device in devices.iter() {
+        let state = device.state.lock().unwrap();
+        println!(
+            "  {} {:?} on {}",
+            state.serial(),
+            state.label.as_deref().unwrap_or_default(),
+            device.sock.local_addr()?
+        );
+    }
+
+    for i in 0..devices.len() {
+        let devices = devices.clone();
+        spawn(move || devices[i].run());
+    }
+    serve(&discovery, |raw, from| {
+        if raw.protocol_header.typ != Message::GetService.get_num() {
+            return;
+        }
+        for device in devices.iter().filter(|device| device.is_for(&raw)) {
+            device.reply(&raw, device.handle(&raw), from);
+        }
+    });
+    Ok(())
+}
@@ -1,10 +1,16 @@
 use get_if_addrs::{get_if_addrs, IfAddr, Ifv4Addr};
-use lifx_core::{get_product_info, BuildOptions, Message, RawMessage, Service, HSBK};
+use lifx::receiver::{self, ReceiverHandle};
+use lifx_core::{
+    get_product_info, BuildOptions, FirmwareVersion, Message, RawMessage, Service, SourceId,
+    ZoneRange, HSBK, LIFX_PORT,
+};
 use std::collections::HashMap;
 use std::ffi::CString;
+use std::io;
 use std::net::{IpAddr, SocketAddr, UdpSocket};
-use std::sync::{Arc, Mutex};
-use std::thread::{sleep, spawn};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::sleep;
 use std::time::{Duration, Instant};
 
 const HOUR: Duration = Duration::from_secs(60 * 60);
@@ -40,18 +46,49 @@ impl<T> RefreshableData<T> {
 
 struct BulbInfo {
     last_seen: Instant,
-    source: u32,
+    source: SourceId,
     target: u64,
+    /// Where to send messages, using the port from the bulb's StateService
     addr: SocketAddr,
+    /// Whether the bulb's service is up; it advertises port 0 while it's temporarily unavailable
+    available: bool,
     name: RefreshableData<CString>,
     model: RefreshableData<(u32, u32)>,
     location: RefreshableData<CString>,
-    host_firmware: RefreshableData<(u16, u16)>,
-    wifi_firmware: RefreshableData<(u16, u16)>,
+    host_firmware: RefreshableData<FirmwareVersion>,
+    wifi_firmware: RefreshableData<FirmwareVersion>,
     power_level: RefreshableData<u16>,
     color: Color,
 }
 
+/// Things that happened to a bulb that are worth telling the user about
+#[derive(Debug)]
+enum Event {
+    /// The number of zones reported by a multizone device changed (for example, because an
+    /// extension was added to a Beam), so all of its cached zone colors were thrown away.
+    ZonesChanged {
+        target: u64,
+        old_count: usize,
+        new_count: usize,
+    },
+}
+
+impl std::fmt::Display for Event {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Event::ZonesChanged {
+                target,
+                old_count,
+                new_count,
+            } => write!(
+                f,
+                "{:0>16X} zone count changed from {} to {}",
+                target, old_count, new_count
+            ),
+        }
+    }
+}
+
 #[derive(Debug)]
 enum Color {
     Unknown,
@@ -59,13 +96,44 @@ enum Color {
     Multi(RefreshableData<Vec<Option<HSBK>>>),
 }
 
+/// Stores zone colors from a StateZone or StateMultiZone message into the zone cache.
+///
+/// If the device reports a different number of zones than we have cached, then the cache is reset
+/// (and a [Event::ZonesChanged] event is sent), since the cached indices can no longer be trusted.
+/// Zones beyond `count` are ignored, which happens for the last StateMultiZone message when the
+/// number of zones isn't a multiple of 8.
+fn update_zones(
+    target: u64,
+    data: &mut RefreshableData<Vec<Option<HSBK>>>,
+    count: u8,
+    index: u8,
+    colors: &[HSBK],
+    events: &Sender<Event>,
+) {
+    let count = count as usize;
+    let zones = data.data.get_or_insert_with(|| vec![None; count]);
+    if zones.len() != count {
+        let _ = events.send(Event::ZonesChanged {
+            target,
+            old_count: zones.len(),
+            new_count: count,
+        });
+        *zones = vec![None; count];
+    }
+
+    for (slot, color) in zones.iter_mut().skip(index as usize).zip(colors) {
+        *slot = Some(*color);
+    }
+}
+
 impl BulbInfo {
-    fn new(source: u32, target: u64, addr: SocketAddr) -> BulbInfo {
+    fn new(source: SourceId, target: u64, addr: SocketAddr) -> BulbInfo {
         BulbInfo {
             last_seen: Instant::now(),
             source,
             target,
             addr,
+            available: true,
             name: RefreshableData::empty(HOUR, Message::GetLabel),
             model: RefreshableData::empty(HOUR, Message::GetVersion),
             location: RefreshableData::empty(HOUR, Message::GetLocation),
@@ -76,9 +144,25 @@ impl BulbInfo {
         }
     }
 
+    /// Notes that a message arrived from the bulb, from `addr`
+    ///
+    /// The bulb might have moved to a new IP address, but the port it sends from isn't necessarily
+    /// the one it listens on, so that comes from StateService instead.
     fn update(&mut self, addr: SocketAddr) {
         self.last_seen = Instant::now();
-        self.addr = addr;
+        self.addr.set_ip(addr.ip());
+    }
+
+    fn send(&self, sock: &UdpSocket, msg: Message) -> Result<(), failure::Error> {
+        let options = BuildOptions {
+            target: Some(self.target),
+            res_required: true,
+            source: self.source,
+            ..Default::default()
+        };
+        let message = RawMessage::build(&options, msg)?;
+        sock.send_to(&message.pack()?, self.addr)?;
+        Ok(())
     }
 
     fn refresh_if_needed<T>(
@@ -87,19 +171,16 @@ impl BulbInfo {
         data: &RefreshableData<T>,
     ) -> Result<(), failure::Error> {
         if data.needs_refresh() {
-            let options = BuildOptions {
-                target: Some(self.target),
-                res_required: true,
-                source: self.source,
-                ..Default::default()
-            };
-            let message = RawMessage::build(&options, data.refresh_msg.clone())?;
-            sock.send_to(&message.pack()?, self.addr)?;
+            self.send(sock, data.refresh_msg.clone())?;
         }
         Ok(())
     }
 
     fn query_for_missing_info(&self, sock: &UdpSocket) -> Result<(), failure::Error> {
+        if !self.available {
+            // hold off on everything else until the service is back
+            return self.send(sock, Message::GetService);
+        }
         self.refresh_if_needed(sock, &self.name)?;
         self.refresh_if_needed(sock, &self.model)?;
         self.refresh_if_needed(sock, &self.location)?;
@@ -137,11 +218,11 @@ impl std::fmt::Debug for BulbInfo {
                 )?;
             }
         }
-        if let Some((major, minor)) = self.host_firmware.as_ref() {
-            write!(f, " McuFW:{}.{}", major, minor)?;
+        if let Some(firmware) = self.host_firmware.as_ref() {
+            write!(f, " McuFW:{}", firmware)?;
         }
-        if let Some((major, minor)) = self.wifi_firmware.as_ref() {
-            write!(f, " WifiFW:{}.{}", major, minor)?;
+        if let Some(firmware) = self.wifi_firmware.as_ref() {
+            write!(f, " WifiFW:{}", firmware)?;
         }
         if let Some(level) = self.power_level.as_ref() {
             if *level > 0 {
@@ -180,14 +261,18 @@ impl std::fmt::Debug for BulbInfo {
 
 struct Manager {
     bulbs: Arc<Mutex<HashMap<u64, BulbInfo>>>,
+    /// Notified whenever the worker has handled a message
+    updated: Arc<Condvar>,
+    events: Receiver<Event>,
     last_discovery: Instant,
     sock: UdpSocket,
-    source: u32,
+    source: SourceId,
+    receiver: Option<ReceiverHandle<UdpSocket>>,
 }
 
 impl Manager {
     fn new() -> Result<Manager, failure::Error> {
-        let sock = UdpSocket::bind("0.0.0.0:56700")?;
+        let sock = UdpSocket::bind(("0.0.0.0", LIFX_PORT))?;
         sock.set_broadcast(true)?;
 
         // spawn a thread that can send to our socket
@@ -195,27 +280,52 @@ impl Manager {
 
         let bulbs = Arc::new(Mutex::new(HashMap::new()));
         let receiver_bulbs = bulbs.clone();
-        let source = 0x72757374;
+        let updated = Arc::new(Condvar::new());
+        let receiver_updated = updated.clone();
+        let source = SourceId::new(0x72757374).unwrap();
+        let (event_tx, events) = channel();
 
-        // spawn a thread that will receive data from our socket and update our internal data structures
-        spawn(move || Self::worker(recv_sock, source, receiver_bulbs));
+        let receiver = Self::start_receiver(
+            recv_sock,
+            source,
+            receiver_bulbs,
+            receiver_updated,
+            event_tx,
+        );
 
         let mut mgr = Manager {
             bulbs,
+            updated,
+            events,
             last_discovery: Instant::now(),
             sock,
             source,
+            receiver: Some(receiver),
         };
         mgr.discover()?;
         Ok(mgr)
     }
 
-    fn handle_message(raw: RawMessage, bulb: &mut BulbInfo) -> Result<(), lifx_core::Error> {
-        match Message::from_raw(&raw)? {
+    fn handle_message(
+        raw: RawMessage,
+        bulb: &mut BulbInfo,
+        events: &Sender<Event>,
+    ) -> Result<(), lifx_core::Error> {
+        let msg = Message::from_raw(&raw)?;
+        match msg {
+            Message::StateService {
+                service: Service::UDP,
+                port: 0,
+            } => bulb.available = false,
+            Message::StateService {
+                service: Service::UDP,
+                port,
+            } if port <= u16::MAX as u32 => {
+                bulb.addr.set_port(port as u16);
+                bulb.available = true;
+            }
             Message::StateService { port, service } => {
-                if port != bulb.addr.port() as u32 || service != Service::UDP {
-                    println!("Unsupported service: {:?}/{}", service, port);
-                }
+                println!("Unsupported service: {:?}/{}", service, port);
             }
             Message::StateLabel { label } => bulb.name.update(label.cstr().to_owned()),
             Message::StateLocation { label, .. } => bulb.location.update(label.cstr().to_owned()),
@@ -224,13 +334,10 @@ impl Manager {
             } => {
                 bulb.model.update((vendor, product));
                 if let Some(info) = get_product_info(vendor, product) {
-                    if info.multizone {
+                    if info.multizone() {
                         bulb.color = Color::Multi(RefreshableData::empty(
                             Duration::from_secs(15),
-                            Message::GetColorZones {
-                                start_index: 0,
-                                end_index: 255,
-                            },
+                            Message::get_color_zones(ZoneRange::ALL),
                         ))
                     } else {
                         bulb.color = Color::Single(RefreshableData::empty(
@@ -241,16 +348,12 @@ impl Manager {
                 }
             }
             Message::StatePower { level } => bulb.power_level.update(level),
-            Message::StateHostFirmware {
-                version_minor,
-                version_major,
-                ..
-            } => bulb.host_firmware.update((version_major, version_minor)),
-            Message::StateWifiFirmware {
-                version_minor,
-                version_major,
-                ..
-            } => bulb.wifi_firmware.update((version_major, version_minor)),
+            Message::StateHostFirmware { .. } => bulb
+                .host_firmware
+                .update(FirmwareVersion::from_state_firmware(&msg).unwrap()),
+            Message::StateWifiFirmware { .. } => bulb
+                .wifi_firmware
+                .update(FirmwareVersion::from_state_firmware(&msg).unwrap()),
             Message::LightState {
                 color,
                 power,
@@ -269,12 +372,7 @@ impl Manager {
                 color,
             } => {
                 if let Color::Multi(ref mut d) = bulb.color {
-                    d.data.get_or_insert_with(|| {
-                        let mut v = Vec::with_capacity(count as usize);
-                        v.resize(count as usize, None);
-                        assert!(index <= count);
-                        v
-                    })[index as usize] = Some(color);
+                    update_zones(bulb.target, d, count, index, &[color], events);
                 }
             }
             Message::StateMultiZone {
@@ -290,21 +388,10 @@ impl Manager {
                 color7,
             } => {
                 if let Color::Multi(ref mut d) = bulb.color {
-                    let v = d.data.get_or_insert_with(|| {
-                        let mut v = Vec::with_capacity(count as usize);
-                        v.resize(count as usize, None);
-                        assert!(index + 7 <= count);
-                        v
-                    });
-
-                    v[index as usize] = Some(color0);
-                    v[index as usize + 1] = Some(color1);
-                    v[index as usize + 2] = Some(color2);
-                    v[index as usize + 3] = Some(color3);
-                    v[index as usize + 4] = Some(color4);
-                    v[index as usize + 5] = Some(color5);
-                    v[index as usize + 6] = Some(color6);
-                    v[index as usize + 7] = Some(color7);
+                    let colors = [
+                        color0, color1, color2, color3, color4, color5, color6, color7,
+                    ];
+                    update_zones(bulb.target, d, count, index, &colors, events);
                 }
             }
             unknown => {
@@ -314,37 +401,32 @@ impl Manager {
         Ok(())
     }
 
-    fn worker(
+    /// Starts a thread that receives data from our socket and updates our internal data
+    /// structures
+    fn start_receiver(
         recv_sock: UdpSocket,
-        source: u32,
-        receiver_bulbs: Arc<Mutex<HashMap<u64, BulbInfo>>>,
-    ) {
-        let mut buf = [0; 1024];
-        loop {
-            match recv_sock.recv_from(&mut buf) {
-                Ok((0, addr)) => println!("Received a zero-byte datagram from {:?}", addr),
-                Ok((nbytes, addr)) => match RawMessage::unpack(&buf[0..nbytes]) {
-                    Ok(raw) => {
-                        if raw.frame_addr.target == 0 {
-                            continue;
-                        }
-                        if let Ok(mut bulbs) = receiver_bulbs.lock() {
-                            let bulb = bulbs
-                                .entry(raw.frame_addr.target)
-                                .and_modify(|bulb| bulb.update(addr))
-                                .or_insert_with(|| {
-                                    BulbInfo::new(source, raw.frame_addr.target, addr)
-                                });
-                            if let Err(e) = Self::handle_message(raw, bulb) {
-                                println!("Error handling message from {}: {}", addr, e)
-                            }
-                        }
+        source: SourceId,
+        bulbs: Arc<Mutex<HashMap<u64, BulbInfo>>>,
+        updated: Arc<Condvar>,
+        events: Sender<Event>,
+    ) -> ReceiverHandle<UdpSocket> {
+        receiver::Receiver::new(recv_sock)
+            .on_error(|e| println!("Error receiving: {}", e))
+            .spawn(move |raw, addr| {
+                if raw.frame_addr.target == 0 {
+                    return;
+                }
+                if let Ok(mut bulbs) = bulbs.lock() {
+                    let bulb = bulbs
+                        .entry(raw.frame_addr.target)
+                        .and_modify(|bulb| bulb.update(addr))
+                        .or_insert_with(|| BulbInfo::new(source, raw.frame_addr.target, addr));
+                    if let Err(e) = Self::handle_message(raw, bulb, &events) {
+                        println!("Error handling message from {}: {}", addr, e)
                     }
-                    Err(e) => println!("Error unpacking raw message from {}: {}", addr, e),
-                },
-                Err(e) => panic!("recv_from err {:?}", e),
-            }
-        }
+                    updated.notify_all();
+                }
+            })
     }
 
     fn discover(&mut self) -> Result<(), failure::Error> {
@@ -359,15 +441,16 @@ impl Manager {
 
         for addr in get_if_addrs().unwrap() {
             if let IfAddr::V4(Ifv4Addr {
-                                broadcast: Some(bcast),
-                                ..
-                            }) = addr.addr {
+                broadcast: Some(bcast),
+                ..
+            }) = addr.addr
+            {
                 if addr.ip().is_loopback() {
                     continue;
                 }
-                let addr = SocketAddr::new(IpAddr::V4(bcast), 56700);
+                let addr = SocketAddr::new(IpAddr::V4(bcast), LIFX_PORT);
                 println!("Discovering bulbs on LAN {:?}", addr);
-                self.sock.send_to(&bytes, &addr)?;
+                self.sock.send_to(&bytes, addr)?;
             }
         }
 
@@ -376,26 +459,87 @@ impl Manager {
         Ok(())
     }
 
-    fn refresh(&self) {
-        if let Ok(bulbs) = self.bulbs.lock() {
-            let bulbs = bulbs.values();
-            for bulb in bulbs {
-                bulb.query_for_missing_info(&self.sock).unwrap();
+    /// Blocks until some bulb satisfies `predicate`, and returns its target, or `None` if none
+    /// does before the timeout
+    ///
+    /// This is checked again every time a message arrives, so it returns as soon as the bulb tells
+    /// us about the state we're waiting for.
+    fn wait_for(&self, predicate: impl Fn(&BulbInfo) -> bool, timeout: Duration) -> Option<u64> {
+        let bulbs = self.bulbs.lock().unwrap();
+        let (bulbs, _) = self
+            .updated
+            .wait_timeout_while(bulbs, timeout, |bulbs| !bulbs.values().any(&predicate))
+            .unwrap();
+        bulbs
+            .values()
+            .find(|bulb| predicate(bulb))
+            .map(|bulb| bulb.target)
+    }
+
+    /// Asks every bulb for whatever info is out of date
+    ///
+    /// Bulbs that can't be reached are treated as unavailable until they answer a GetService
+    /// again, and this returns true so that they can be looked for again with discovery, in case
+    /// they've moved to a new address.
+    fn refresh(&self) -> bool {
+        let mut unreachable = false;
+        if let Ok(mut bulbs) = self.bulbs.lock() {
+            for bulb in bulbs.values_mut() {
+                if let Err(e) = bulb.query_for_missing_info(&self.sock) {
+                    println!("Error refreshing {:0>16X}: {}", bulb.target, e);
+                    if e.downcast_ref().is_some_and(is_unreachable) {
+                        bulb.available = false;
+                        unreachable = true;
+                    }
+                }
             }
         }
+        unreachable
     }
 }
 
+/// Whether an error means that a bulb couldn't be reached (which is reported through ICMP, so it
+/// can also show up when receiving, after a later send)
+impl Drop for Manager {
+    fn drop(&mut self) {
+        if let Some(receiver) = self.receiver.take() {
+            receiver.stop();
+        }
+    }
+}
+
+fn is_unreachable(e: &io::Error) -> bool {
+    matches!(
+        e.kind(),
+        io::ErrorKind::HostUnreachable
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
 fn main() {
     let mut mgr = Manager::new().unwrap();
+    // give the bulbs a moment to answer discovery, so that the first report isn't empty
+    if mgr.wait_for(|_| true, Duration::from_secs(2)).is_none() {
+        println!("No bulbs have answered yet");
+    }
 
     loop {
         if Instant::now() - mgr.last_discovery > Duration::from_secs(300) {
-            mgr.discover().unwrap();
+            if let Err(e) = mgr.discover() {
+                println!("Discovery failed: {}", e);
+            }
+        }
+        if mgr.refresh() {
+            if let Err(e) = mgr.discover() {
+                println!("Discovery failed: {}", e);
+            }
         }
-        mgr.refresh();
 
         println!("\n\n\n\n");
+        for event in mgr.events.try_iter() {
+            println!("{}", event);
+        }
         if let Ok(bulbs) = mgr.bulbs.lock() {
             let bulbs = bulbs.values();
             for bulb in bulbs {
@@ -1,4 +1,4 @@
-use std::{borrow::Cow, fs::File};
+use std::{borrow::Cow, collections::HashMap, fs::File};
 
 use serde::Deserialize;
 
@@ -29,6 +29,8 @@ pub struct LifxFeatures {
     #[serde(default)]
     multizone: bool,
     #[serde(default)]
+    extended_multizone: bool,
+    #[serde(default)]
     temperature_range: Option<Vec<u16>>,
 }
 
@@ -61,10 +63,10 @@ impl From<Option<&[u16]>> for TemperatureRange {
 }
 
 impl TemperatureRange {
-    fn fmt(&self) -> Cow<str> {
+    fn fmt(&self) -> Cow<'_, str> {
         match self {
             TemperatureRange::Variable { min, max } => Cow::from(format!(
-                "TemperatureRange::Variable {{ min: {}, max: {} }} ",
+                "TemperatureRange::Variable {{ min: {}, max: {} }}",
                 min, max
             )),
             TemperatureRange::Fixed(x) => Cow::from(format!("TemperatureRange::Fixed({})", x)),
@@ -78,25 +80,316 @@ pub fn update_products() -> anyhow::Result<()> {
     let products: Vec<LifxProducts> = serde_json::from_reader(file)?;
     assert_eq!(products.len(), 1);
 
-    // We want to produce a string like the following, which we can copy/paste into lifx-core/src/lib.rs
-    // (1, 1) => Some(&ProductInfo { name: "Original 1000", color: true, infrared: false, multizone: false, chain: false}),
+    // products.json doesn't say how much power anything draws, so that comes from an optional
+    // wattage.json alongside it, mapping product IDs to watts: {"27": 11.0, ...}
+    let watts: HashMap<i32, f32> = match File::open("wattage.json") {
+        Ok(file) => serde_json::from_reader(file)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+        Err(e) => return Err(e.into()),
+    };
+
+    // We want to produce a string like the following, which we can copy/paste into builtin_product_info in lifx-core/src/lib.rs
+    // (1, 1) => Some(&ProductInfo { name: "Original 1000", capabilities: caps!(COLOR), temperature_range: ..., max_milliwatts: None }),
 
     for prd in &products[0].products {
         let t = TemperatureRange::from(prd.features.temperature_range.as_deref());
+        let features = &prd.features;
+        let caps: Vec<&str> = [
+            (features.color, "COLOR"),
+            (features.infrared, "INFRARED"),
+            (features.multizone, "MULTIZONE"),
+            (features.chain, "CHAIN"),
+            (features.hev, "HEV"),
+            (features.matrix, "MATRIX"),
+            (features.relays, "RELAYS"),
+            (features.buttons, "BUTTONS"),
+            (features.extended_multizone, "EXTENDED_MULTIZONE"),
+        ]
+        .iter()
+        .filter(|(supported, _)| *supported)
+        .map(|(_, name)| *name)
+        .collect();
+        let max_milliwatts = match watts.get(&prd.pid) {
+            Some(w) => format!("Some({})", (w * 1000.0).round() as u32),
+            None => "None".to_owned(),
+        };
         println!(
-            r#"(1, {pid}) => Some(&ProductInfo {{ name: "{name}", color: {color}, infrared: {ir}, multizone: {mz}, chain: {chain}, hev: {hev}, matrix: {matrix}, relays: {relay}, buttons: {buttons}, temperature_range: {temp} }}),"#,
+            r#"(1, {pid}) => Some(&ProductInfo {{ name: "{name}", capabilities: caps!({caps}), temperature_range: {temp}, max_milliwatts: {max_milliwatts} }}),"#,
             pid = prd.pid,
             name = prd.name,
-            color = prd.features.color,
-            ir = prd.features.infrared,
-            mz = prd.features.multizone,
-            chain = prd.features.chain,
-            hev = prd.features.hev,
-            matrix = prd.features.matrix,
-            relay = prd.features.relays,
-            buttons = prd.features.buttons,
-            temp = t.fmt()
+            caps = caps.join(", "),
+            temp = t.fmt(),
+            max_milliwatts = max_milliwatts
         );
     }
     Ok(())
 }
+
+/// Prints which of the documented LAN messages lifx-core supports, as a markdown table
+pub fn coverage() -> anyhow::Result<()> {
+    let coverage = lifx_core::coverage::ProtocolCoverage::new();
+    print!("{}", coverage);
+    Ok(())
+}
+
+/// The packed size of a field type, for the types that lifx-core knows how to pack
+fn field_size(ty: &str) -> Option<usize> {
+    if let Some(inner) = ty.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        let (elem, count) = inner.split_once(';')?;
+        return Some(field_size(elem.trim())? * count.trim().parse::<usize>().ok()?);
+    }
+    Some(match ty {
+        "u8"
+        | "bool"
+        | "Service"
+        | "ApplicationRequest"
+        | "Waveform"
+        | "LastHevCycleResult"
+        | "MultiZoneEffectType"
+        | "TileEffectType" => 1,
+        "u16" | "i16" | "PowerLevel" => 2,
+        "u32" | "f32" => 4,
+        "u64" | "HSBK" => 8,
+        "LifxIdent" => 16,
+        "LifxString" => 32,
+        "EchoPayload" => 64,
+        _ => return None,
+    })
+}
+
+/// The type that `unpack!` reads a field as, before converting it with `try_into`
+fn unpack_type(ty: &str) -> &str {
+    match ty {
+        "Service" | "ApplicationRequest" => "u8",
+        _ => ty,
+    }
+}
+
+/// Whether a field has to be bound with `ref` in `write_payload`, which matches on `*self`
+fn binds_by_ref(ty: &str) -> bool {
+    ty == "LifxString" || (ty.starts_with("[HSBK") && ty.ends_with(']'))
+}
+
+/// The `PayloadWriter` call that writes a field in `write_payload`
+fn put_call(name: &str, ty: &str) -> String {
+    if let Some(inner) = ty.strip_prefix('[').and_then(|t| t.strip_suffix(']')) {
+        let elem = inner.split(';').next().unwrap_or_default().trim();
+        return match elem {
+            "u8" => format!("w.put_bytes(&{});", name),
+            "u32" => format!("w.put_u32s(&{});", name),
+            _ => format!("w.put_hsbks(&{}[..]);", name),
+        };
+    }
+    match ty {
+        "Service"
+        | "Waveform"
+        | "LastHevCycleResult"
+        | "MultiZoneEffectType"
+        | "TileEffectType" => format!("w.put_u8({}.into());", name),
+        "ApplicationRequest" => format!("w.put_u8({} as u8);", name),
+        "PowerLevel" => format!("w.put_u16({} as u16);", name),
+        "HSBK" => format!("w.put_hsbk({});", name),
+        "LifxIdent" => format!("w.put_ident(&{});", name),
+        "LifxString" => format!("w.put_string({});", name),
+        "EchoPayload" => format!("w.put_echo(&{});", name),
+        _ => format!("w.put_{}({});", ty, name),
+    }
+}
+
+/// Parses `--fields "duration:u32,apply:ApplicationRequest"` into (name, type) pairs
+fn parse_fields(fields: &str) -> anyhow::Result<Vec<(String, String)>> {
+    fields
+        .split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(|field| {
+            let (name, ty) = field
+                .split_once(':')
+                .ok_or_else(|| anyhow::anyhow!("expected name:type, got {:?}", field))?;
+            let (name, ty) = (name.trim(), ty.trim());
+            if field_size(ty).is_none() {
+                anyhow::bail!("don't know how to pack a {} (for field {})", ty, name);
+            }
+            Ok((name.to_owned(), ty.to_owned()))
+        })
+        .collect()
+}
+
+/// The code needed to add a message to lifx-core, in the order it's printed by [new_message]
+fn generate(num: u16, name: &str, fields: &[(String, String)]) -> String {
+    let mut out = String::new();
+    macro_rules! out {
+        ($($arg:tt)*) => {{
+            out.push_str(&format!($($arg)*));
+            out.push('\n');
+        }};
+    }
+
+    let size: usize = fields.iter().map(|(_, ty)| field_size(ty).unwrap()).sum();
+    let len = if name.starts_with("State") {
+        "AtLeast"
+    } else {
+        "Exact"
+    };
+    let bindings: Vec<String> = fields
+        .iter()
+        .map(|(n, ty)| {
+            if binds_by_ref(ty) {
+                format!("ref {}", n)
+            } else {
+                n.clone()
+            }
+        })
+        .collect();
+
+    out!("// Message enum");
+    out!("    /// TODO: describe this message, from the LAN protocol docs");
+    out!("    ///");
+    out!("    /// Message type {}", num);
+    if fields.is_empty() {
+        out!("    {},", name);
+    } else {
+        out!("    {} {{", name);
+        for (n, ty) in fields {
+            out!("        {}: {},", n, ty);
+        }
+        out!("    }},");
+    }
+
+    out!("");
+    out!("// expected_payload_len");
+    out!("        {} => {}({}), // {}", num, len, size, name);
+
+    out!("");
+    out!("// Message::get_num");
+    if fields.is_empty() {
+        out!("            Message::{} => {},", name, num);
+    } else {
+        out!("            Message::{} {{ .. }} => {},", name, num);
+    }
+
+    out!("");
+    out!("// Message::write_payload");
+    if fields.is_empty() {
+        out!("            | Message::{}", name);
+    } else {
+        out!(
+            "            Message::{} {{ {} }} => {{",
+            name,
+            bindings.join(", ")
+        );
+        for (n, ty) in fields {
+            out!("                {}", put_call(n, ty));
+        }
+        out!("            }}");
+    }
+
+    out!("");
+    out!("// Message::from_raw");
+    if fields.is_empty() {
+        out!("            {} => Ok(Message::{}),", num, name);
+    } else {
+        let unpacked: Vec<String> = fields
+            .iter()
+            .map(|(n, ty)| format!("{}: {}", n, unpack_type(ty)))
+            .collect();
+        out!(
+            "            {} => Ok(unpack!(msg, {}, {})),",
+            num,
+            name,
+            unpacked.join(", ")
+        );
+    }
+
+    out!("");
+    out!("// coverage::DOCUMENTED, if it isn't there already");
+    out!("    ({}, \"TODO: section\", \"{}\"),", num, name);
+    out
+}
+
+/// Prints the code needed to add a message to lifx-core, for pasting into lifx-core/src/lib.rs
+///
+/// ```text
+/// cargo xtask new-message --num 122 --name LightSetInfrared --fields "brightness:u16"
+/// ```
+///
+/// Messages named `State*` are replies, so their payloads may be longer than documented.
+pub fn new_message(mut args: impl Iterator<Item = String>) -> anyhow::Result<()> {
+    let (mut num, mut name, mut fields) = (None, None, Vec::new());
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| anyhow::anyhow!("{} needs a value", arg))
+        };
+        match arg.as_str() {
+            "--num" => num = Some(value()?.parse::<u16>()?),
+            "--name" => name = Some(value()?),
+            "--fields" => fields = parse_fields(&value()?)?,
+            _ => anyhow::bail!("unexpected argument {:?}", arg),
+        }
+    }
+    let usage =
+        "usage: cargo xtask new-message --num <type> --name <Name> [--fields name:type,...]";
+    let num = num.ok_or_else(|| anyhow::anyhow!(usage))?;
+    let name = name.ok_or_else(|| anyhow::anyhow!(usage))?;
+
+    print!("{}", generate(num, &name, &fields));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The part of the generated code that goes in `Message::write_payload`
+    fn write_payload_arm(num: u16, name: &str, fields: &str) -> String {
+        let code = generate(num, name, &parse_fields(fields).unwrap());
+        let start = code.find("// Message::write_payload\n").unwrap();
+        let end = code.find("// Message::from_raw").unwrap();
+        code[start..end]
+            .lines()
+            .skip(1)
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    // these have to match lifx-core's PayloadWriter, or the generated code won't compile
+    #[test]
+    fn test_write_payload() {
+        assert_eq!(
+            write_payload_arm(122, "LightSetInfrared", "brightness:u16"),
+            "Message::LightSetInfrared { brightness } => {\nw.put_u16(brightness);\n}"
+        );
+        assert_eq!(
+            write_payload_arm(
+                49,
+                "SetLocation",
+                "location:LifxIdent,label:LifxString,updated_at:u64"
+            ),
+            "Message::SetLocation { location, ref label, updated_at } => {\n\
+             w.put_ident(&location);\n\
+             w.put_string(label);\n\
+             w.put_u64(updated_at);\n\
+             }"
+        );
+        assert_eq!(
+            write_payload_arm(
+                103,
+                "SetWaveform",
+                "transient:bool,color:HSBK,waveform:Waveform,colors:[HSBK;8]"
+            ),
+            "Message::SetWaveform { transient, color, waveform, ref colors } => {\n\
+             w.put_bool(transient);\n\
+             w.put_hsbk(color);\n\
+             w.put_u8(waveform.into());\n\
+             w.put_hsbks(&colors[..]);\n\
+             }"
+        );
+        assert_eq!(
+            write_payload_arm(101, "LightGet", ""),
+            "| Message::LightGet"
+        );
+    }
+}
@@ -4,6 +4,8 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     let task_name = args.next();
     match task_name.as_deref() {
         Some("update-products") => Ok(xtask::update_products()?),
+        Some("coverage") => Ok(xtask::coverage()?),
+        Some("new-message") => Ok(xtask::new_message(args)?),
         _ => Ok(()),
     }
 }